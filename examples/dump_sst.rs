@@ -0,0 +1,55 @@
+//!Prints the entries of one SST run of a database, without opening the database.
+//! Usage: cargo run --example dump_sst -- <database directory> <level> <run> [--raw]
+//! Entries are printed as "key,value" lines, or as little endian key and value bytes with --raw
+
+use std::{io, path::Path, process};
+
+use key_value::{
+    db::Database,
+    export::{self, ExportFormat},
+    util::types::{DatabaseName, Level, Run},
+    Storage,
+};
+
+const USAGE: &str = "usage: dump_sst <database directory> <level> <run> [--raw]";
+
+fn parse_args(args: &[String]) -> Result<(&Path, Level, Run, ExportFormat), String> {
+    let (positional, flags): (Vec<&String>, Vec<&String>) =
+        args.iter().partition(|arg| !arg.starts_with("--"));
+    let [db_name, level, run] = positional[..] else {
+        return Err(USAGE.to_string());
+    };
+    let level = level
+        .parse::<Level>()
+        .map_err(|why| format!("invalid level \"{level}\", {why}"))?;
+    let run = run
+        .parse::<Run>()
+        .map_err(|why| format!("invalid run \"{run}\", {why}"))?;
+    let format = match flags[..] {
+        [] => ExportFormat::Csv,
+        [flag] if flag == "--raw" => ExportFormat::Raw,
+        _ => return Err(USAGE.to_string()),
+    };
+    Ok((Path::new(db_name), level, run, format))
+}
+
+fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let dump = || -> Result<(), String> {
+        let (db_name, level, run, format) = parse_args(&args)?;
+        let key_codec = Database::stored_key_codec(db_name)
+            .map_err(|why| format!("unable to read the config of {}, {why}", db_name.display()))?;
+        export::export_run_to_writer(
+            &(&DatabaseName::new(db_name, Storage::disk()), level, run),
+            key_codec,
+            format,
+            &mut io::stdout().lock(),
+        )
+        .map_err(|why| format!("unable to read level {level}, run {run}, {why}"))?;
+        Ok(())
+    };
+    if let Err(why) = dump() {
+        eprintln!("{why}");
+        process::exit(2);
+    }
+}
@@ -0,0 +1,70 @@
+//!Runs one command against a database, creating it if it doesn't exist.
+//! Usage: cargo run --example kvcli -- <database directory> <command> [<args>]
+//! Commands: get <key>, put <key> <value>, delete <key>, scan <low key> <high key>, explain <key>.
+//! explain prints each step a get of the key takes (see Database::explain_get)
+
+use std::{path::Path, process};
+
+use key_value::{
+    db::Database,
+    util::types::{Key, Value},
+};
+
+const USAGE: &str = "usage: kvcli <database directory> <command> [<args>], commands: get <key>, \
+    put <key> <value>, delete <key>, scan <low key> <high key>, explain <key>";
+
+fn parse_number(name: &str, arg: &str) -> Result<i64, String> {
+    arg.parse::<i64>()
+        .map_err(|why| format!("invalid {name} \"{arg}\", {why}"))
+}
+
+fn run(args: &[String]) -> Result<(), String> {
+    let [db_name, command, command_args @ ..] = args else {
+        return Err(USAGE.to_string());
+    };
+    let mut db = Database::try_open(Path::new(db_name))
+        .map_err(|why| format!("unable to open {db_name}, {why}"))?;
+    let result = match (command.as_str(), command_args) {
+        ("get", [key]) => {
+            let key: Key = parse_number("key", key)?;
+            match db.get(key) {
+                Some(value) => println!("{value}"),
+                None => println!("not found"),
+            }
+            Ok(())
+        }
+        ("put", [key, value]) => {
+            let key: Key = parse_number("key", key)?;
+            let value: Value = parse_number("value", value)?;
+            db.put(key, value).map_err(|why| why.to_string())
+        }
+        ("delete", [key]) => {
+            let key: Key = parse_number("key", key)?;
+            db.delete(key).map_err(|why| why.to_string())
+        }
+        ("scan", [low, high]) => {
+            let low: Key = parse_number("low key", low)?;
+            let high: Key = parse_number("high key", high)?;
+            for (key, value) in db.scan(low, high) {
+                println!("{key},{value}");
+            }
+            Ok(())
+        }
+        ("explain", [key]) => {
+            let key: Key = parse_number("key", key)?;
+            print!("{}", db.explain_get(key));
+            Ok(())
+        }
+        _ => Err(USAGE.to_string()),
+    };
+    db.close();
+    result
+}
+
+fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if let Err(why) = run(&args) {
+        eprintln!("{why}");
+        process::exit(2);
+    }
+}
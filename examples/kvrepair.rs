@@ -0,0 +1,155 @@
+//!Inspects and repairs a damaged database.
+//! Usage: cargo run --example kvrepair -- <command> <database directory> [<args>]
+//! Commands:
+//!  inspect: prints the database's layout, runs and health checks (with a read of the newest run's first page)
+//!  repair --dry-run: prints the health checks and the maintenance task a repair would start with
+//!  repair --apply: runs every pending maintenance task (level compactions, tombstone collection, missing bloom filters,
+//!   then verifying every run), see Database::maintain
+//!  rebuild-indexes: rewrites every run with its B-tree, bloom filter, fence index and sidecars rebuilt
+//!  salvage <out directory>: for a database that can't be opened, copies every entry that can still be read into a new
+//!   database, the youngest entry of each key wins. Prints the pages it had to skip (see Database::salvage)
+//!  verify-audits <audit directory>: checks the audits of flushes written under Database::set_flush_audit_dir
+//!   against the runs they wrote, see Database::verify_flush_audits
+//! Exits with 1 if the database is unhealthy, something had to be skipped or an audit doesn't match, 2 on usage or
+//! I/O errors
+
+use std::{path::Path, process, time::Duration};
+
+use key_value::{db::Database, flush_audit::AuditCheck};
+
+const USAGE: &str = "usage: kvrepair <command> <database directory> [<args>], commands: inspect, \
+    repair --dry-run, repair --apply, rebuild-indexes, salvage <out directory>, verify-audits <audit directory>";
+const UNHEALTHY_EXIT_CODE: i32 = 1;
+const USAGE_EXIT_CODE: i32 = 2;
+
+fn open(db_name: &str) -> Result<Database, String> {
+    if !Path::new(db_name).exists() {
+        return Err(format!("{db_name} doesn't exist"));
+    }
+    Database::try_open(Path::new(db_name)).map_err(|why| {
+        format!("unable to open {db_name}, {why}. kvrepair salvage can still recover its entries")
+    })
+}
+
+///Prints the health checks, returns whether they all passed
+fn print_health(db: &Database) -> bool {
+    let report = db.health_check(true);
+    print!("{report}");
+    report.is_healthy()
+}
+
+fn inspect(db_name: &str) -> Result<bool, String> {
+    let mut db = open(db_name)?;
+    println!("{}", db.describe_layout());
+    for run in db.list_runs() {
+        if !run.has_sst {
+            println!(
+                "level {}, run {}: {} entries, SST missing",
+                run.level, run.run, run.entries
+            );
+            continue;
+        }
+        println!(
+            "level {}, run {}: {} entries, {} bytes, b-tree: {}, bloom filter: {}",
+            run.level, run.run, run.entries, run.bytes, run.has_btree, run.has_bloom
+        );
+    }
+    let is_healthy = print_health(&db);
+    db.close();
+    Ok(is_healthy)
+}
+
+fn repair(db_name: &str, apply: bool) -> Result<bool, String> {
+    let mut db = open(db_name)?;
+    let is_healthy = print_health(&db);
+    if !apply {
+        match db.next_maintenance() {
+            Some(task) => println!("repair would start with {task:?}"),
+            None => println!("nothing to repair"),
+        }
+        db.close();
+        return Ok(is_healthy);
+    }
+    let done = db.maintain(Duration::MAX);
+    let is_repaired = match done {
+        Ok(tasks) => {
+            tasks.iter().for_each(|task| println!("done: {task:?}"));
+            print_health(&db)
+        }
+        Err(why) => {
+            println!(
+                "repair stopped, {why}. kvrepair salvage can still recover the readable entries"
+            );
+            false
+        }
+    };
+    db.close();
+    Ok(is_repaired)
+}
+
+fn rebuild_indexes(db_name: &str) -> Result<bool, String> {
+    let mut db = open(db_name)?;
+    for run in db.list_runs() {
+        db.rewrite_run(run.level, run.run).map_err(|why| {
+            format!(
+                "unable to rebuild level {}, run {}, {why}",
+                run.level, run.run
+            )
+        })?;
+        println!("rebuilt level {}, run {}", run.level, run.run);
+    }
+    let is_healthy = print_health(&db);
+    db.close();
+    Ok(is_healthy)
+}
+
+fn salvage(db_name: &str, out_dir: &str) -> Result<bool, String> {
+    let report = Database::salvage(Path::new(db_name), Path::new(out_dir))
+        .map_err(|why| format!("unable to salvage {db_name} into {out_dir}, {why}"))?;
+    print!("{report}");
+    Ok(report.is_complete())
+}
+
+fn verify_audits(db_name: &str, audit_dir: &str) -> Result<bool, String> {
+    let mut db = open(db_name)?;
+    let checks = db
+        .verify_flush_audits(Path::new(audit_dir))
+        .map_err(|why| format!("unable to check the audits in {audit_dir}, {why}"));
+    db.close();
+    let mut all_match = true;
+    for (audit, check) in checks? {
+        println!("{}: {check}", audit.path.display());
+        all_match &= !matches!(check, AuditCheck::Mismatch { .. });
+    }
+    Ok(all_match)
+}
+
+fn run(args: &[String]) -> Result<bool, String> {
+    match args {
+        [command, db_name] if command == "inspect" => inspect(db_name),
+        [command, db_name, flag] if command == "repair" && flag == "--dry-run" => {
+            repair(db_name, false)
+        }
+        [command, db_name, flag] if command == "repair" && flag == "--apply" => {
+            repair(db_name, true)
+        }
+        [command, db_name] if command == "rebuild-indexes" => rebuild_indexes(db_name),
+        [command, db_name, out_dir] if command == "salvage" => salvage(db_name, out_dir),
+        [command, db_name, audit_dir] if command == "verify-audits" => {
+            verify_audits(db_name, audit_dir)
+        }
+        _ => Err(USAGE.to_string()),
+    }
+}
+
+fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    match run(&args) {
+        Ok(true) => {}
+        Ok(false) => process::exit(UNHEALTHY_EXIT_CODE),
+        Err(why) => {
+            eprintln!("{why}");
+            process::exit(USAGE_EXIT_CODE);
+        }
+    }
+}
@@ -9,52 +9,79 @@ use key_value::{
 use rand::{seq::SliceRandom, thread_rng, Rng};
 use std::{hint::black_box, time::Instant};
 
+use crate::distribution::{KeyDistribution, KeyGenerator};
+
 const NS_PER_SEC: u128 = 1_000_000_000; //For conversions from sec to nanosec
 
-///Inserts num_bytes worth of entries, returns entries added (in order that they were added)
-fn fill_db_with_size(db: &mut Database, num_bytes: Size) -> Vec<Entry> {
+///Result of a throughput benchmark
+#[derive(Debug, Clone, Default)]
+pub struct Measurement {
+    pub ops_per_sec: f64,
+    pub pages_per_op: f64, //pages read and written by the benchmark thread, see Database::io_stats
+    pub trial_ops_per_sec: Vec<f64>, //raw ops/sec of each trial, ops_per_sec is their average
+    pub space_amplification: f64, //bytes on disk per logical byte after the benchmark, see DiskUsage
+}
+
+///Inserts num_bytes worth of entries with keys in 0..(number of entries), returns entries added (in order that they
+/// were added). Uniform puts every key once in random order, sequential every key once in order, skewed
+/// distributions draw every key so hot keys are overwritten and fewer distinct keys are stored
+fn fill_db_with_size(
+    db: &mut Database,
+    num_bytes: Size,
+    distribution: KeyDistribution,
+) -> Vec<Entry> {
     let num_entries = ceil_div!(num_bytes, ENTRY_SIZE); //ceil divison
-    let range = 0..num_entries;
+    let key_range = (0, num_entries as Key);
 
     let mut entries = Vec::<Entry>::new();
 
     let mut rng = thread_rng();
 
     //generate keys and random values
-    for i in range.into_iter() {
-        entries.push((i as Key, rng.gen()))
+    let mut generator = KeyGenerator::new(distribution, &key_range);
+    for i in 0..num_entries {
+        let key = match distribution {
+            KeyDistribution::Uniform => i as Key,
+            _ => generator.next_key(&mut rng),
+        };
+        entries.push((key, rng.gen_range(Value::MIN + 1..=Value::MAX)))
     }
 
     //random insertion order to prevent insertion bias
-    entries.shuffle(&mut rng);
+    if distribution == KeyDistribution::Uniform {
+        entries.shuffle(&mut rng);
+    }
 
     for (key, value) in entries.iter() {
-        db.put(*key, *value);
+        db.put(*key, *value).unwrap();
     }
 
     entries
 }
 
 ///Runs num_trials number of iterations, each iteration we count how many operations (with a random input) can be done within the window_duration.
-/// Returns Average number of operations per second
+/// Input keys are drawn from <experiment_key_range> following <distribution>, restarting every trial.
+/// Returns Average number of operations per second and pages of I/O per operation
 #[allow(clippy::unit_arg)] //Get rid of black box warning
 fn bench_throughput(
     db: &mut Database,
     experiment: &mut dyn FnMut(&mut Database, &Key, &Value),
     experiment_key_range: &(Key, Key),
+    distribution: KeyDistribution,
     window_duration_sec: u128,
     num_trials: usize,
-) -> f64 {
-    let (lower, upper) = *experiment_key_range;
+) -> Measurement {
     let window_nano_sec = window_duration_sec * NS_PER_SEC;
     let mut opcount_each_trial = vec![0; num_trials];
+    db.reset_io_stats(); //don't count filling the db
 
     for op_count in opcount_each_trial.iter_mut() {
         let mut total_duration = 0;
         let mut rng = rand::thread_rng();
+        let mut keys = KeyGenerator::new(distribution, experiment_key_range);
         while total_duration < window_nano_sec {
             //select random input for experiment
-            let input_key = rng.gen_range(lower..upper);
+            let input_key = keys.next_key(&mut rng);
             let input_value = rng.gen_range(Value::MIN + 1..=Value::MAX); //NOTE: We do MIN + 1, because MIN is not a valid value or key to enter into the DB
 
             let start = Instant::now();
@@ -67,65 +94,15 @@ fn bench_throughput(
     }
     let total_opcount: u128 = opcount_each_trial.iter().sum();
     let avg_opcount = total_opcount as f64 / num_trials as f64;
-    avg_opcount / window_duration_sec as f64
-}
-
-///Creates a db and runs experiment as many times as possible within window_duration, repeats num_trials times and
-/// return the avg number of operations per second
-fn bench_throughput_on_db_size(
-    database_size_bytes: Size,
-    database_alterations: &mut dyn FnMut(Database) -> Database,
-    experiment: &mut dyn FnMut(&mut Database, &Key, &Value),
-    experiment_key_range: &(Key, Key),
-    window_duration_sec: u128,
-    num_trials: usize,
-) -> f64 {
-    let experiment_dir = "experiment_database_bandwidth_temp_directory";
-    let db_name = format!("{experiment_dir}/test");
-    if std::path::Path::new(experiment_dir).exists() {
-        std::fs::remove_dir_all(experiment_dir).unwrap(); //remove previous directory if panicked during tests and didn't clean up
+    Measurement {
+        ops_per_sec: avg_opcount / window_duration_sec as f64,
+        pages_per_op: db.io_stats().pages() as f64 / total_opcount as f64,
+        trial_ops_per_sec: opcount_each_trial
+            .iter()
+            .map(|op_count| *op_count as f64 / window_duration_sec as f64)
+            .collect(),
+        space_amplification: 0.0, //filled in by the caller, which owns the database's files
     }
-    std::fs::create_dir_all(experiment_dir).unwrap();
-
-    let mut db = database_alterations(Database::open(&db_name));
-
-    fill_db_with_size(&mut db, database_size_bytes);
-    let ops_per_sec = bench_throughput(
-        &mut db,
-        experiment,
-        experiment_key_range,
-        window_duration_sec,
-        num_trials,
-    );
-
-    //cleanup
-    db.close();
-    std::fs::remove_dir_all(experiment_dir).unwrap();
-
-    ops_per_sec
-}
-
-///creates a new db instance each trial, returns avg ops/sec
-fn bench_throughput_on_db_size_reset_each(
-    database_size_bytes: Size,
-    database_alterations: &mut dyn FnMut(Database) -> Database,
-    experiment: &mut dyn FnMut(&mut Database, &Key, &Value),
-    experiment_key_range: &(Key, Key),
-    window_duration_sec: u128,
-    num_trials: usize,
-) -> f64 {
-    let mut total_ops_per_sec = 0.0;
-    for _ in 0..num_trials {
-        total_ops_per_sec += bench_throughput_on_db_size(
-            database_size_bytes,
-            database_alterations,
-            experiment,
-            experiment_key_range,
-            window_duration_sec,
-            1,
-        )
-    }
-    total_ops_per_sec / num_trials as f64
 }
 
 pub struct Benchmarker {
@@ -133,6 +110,7 @@ pub struct Benchmarker {
     pub db_byte_sizes: Vec<usize>,
     pub window_duration_sec: u128, //metric to measure bandwidth, (ops/(sec*window_size))
     pub num_trials: usize,
+    pub fill_distribution: KeyDistribution, //keys the db is filled with, independent of the experiment's keys
 }
 
 impl Benchmarker {
@@ -147,46 +125,118 @@ impl Benchmarker {
             db_byte_sizes,
             window_duration_sec,
             num_trials,
+            fill_distribution: KeyDistribution::Uniform,
         }
     }
-    ///Runs experiment, returns a vector of avg ops/sec
+    ///Runs experiment with input keys drawn from <experiment_key_range> following <distribution>, returns a vector of
+    /// avg ops/sec and pages/op
     pub fn run_experiment(
         &mut self,
         experiment: &mut dyn FnMut(&mut Database, &Key, &Value),
         experiment_key_range: &(Key, Key),
-    ) -> Vec<f64> {
-        let mut results = Vec::<f64>::new();
-        for database_size_bytes in &self.db_byte_sizes {
-            let data = bench_throughput_on_db_size(
-                *database_size_bytes,
-                &mut self.database_alterations,
+        distribution: KeyDistribution,
+    ) -> Vec<Measurement> {
+        let mut results = Vec::<Measurement>::new();
+        for database_size_bytes in self.db_byte_sizes.clone() {
+            let data = self.bench_throughput_on_db_size(
+                database_size_bytes,
                 experiment,
                 experiment_key_range,
-                self.window_duration_sec,
+                distribution,
                 self.num_trials,
             );
             results.push(data);
         }
         results
     }
-    ///Runs experiment, remaking the database each time, returns a vector of avg ops/sec
+    ///Runs experiment like run_experiment, remaking the database each time, returns a vector of avg ops/sec and
+    /// pages/op
     pub fn run_reset_experiment(
         &mut self,
         experiment: &mut dyn FnMut(&mut Database, &Key, &Value),
         experiment_key_range: &(Key, Key),
-    ) -> Vec<f64> {
-        let mut results = Vec::<f64>::new();
-        for database_size_bytes in &self.db_byte_sizes {
-            let data = bench_throughput_on_db_size_reset_each(
-                *database_size_bytes,
-                &mut self.database_alterations,
+        distribution: KeyDistribution,
+    ) -> Vec<Measurement> {
+        let mut results = Vec::<Measurement>::new();
+        for database_size_bytes in self.db_byte_sizes.clone() {
+            let data = self.bench_throughput_on_db_size_reset_each(
+                database_size_bytes,
                 experiment,
                 experiment_key_range,
-                self.window_duration_sec,
-                self.num_trials,
+                distribution,
             );
             results.push(data);
         }
         results
     }
+    ///Creates a db and runs experiment as many times as possible within window_duration, repeats num_trials times and
+    /// return the avg number of operations per second
+    fn bench_throughput_on_db_size(
+        &mut self,
+        database_size_bytes: Size,
+        experiment: &mut dyn FnMut(&mut Database, &Key, &Value),
+        experiment_key_range: &(Key, Key),
+        distribution: KeyDistribution,
+        num_trials: usize,
+    ) -> Measurement {
+        let experiment_dir = "experiment_database_bandwidth_temp_directory";
+        let db_name = format!("{experiment_dir}/test");
+        if std::path::Path::new(experiment_dir).exists() {
+            std::fs::remove_dir_all(experiment_dir).unwrap(); //remove previous directory if panicked during tests and didn't clean up
+        }
+        std::fs::create_dir_all(experiment_dir).unwrap();
+
+        let mut db = (self.database_alterations)(Database::open(&db_name));
+
+        fill_db_with_size(&mut db, database_size_bytes, self.fill_distribution);
+        let measurement = bench_throughput(
+            &mut db,
+            experiment,
+            experiment_key_range,
+            distribution,
+            self.window_duration_sec,
+            num_trials,
+        );
+        let measurement = Measurement {
+            space_amplification: db.approximate_size_bytes().space_amplification(),
+            ..measurement
+        };
+
+        //cleanup
+        db.close();
+        std::fs::remove_dir_all(experiment_dir).unwrap();
+
+        measurement
+    }
+    ///creates a new db instance each trial, returns avg ops/sec and pages/op
+    fn bench_throughput_on_db_size_reset_each(
+        &mut self,
+        database_size_bytes: Size,
+        experiment: &mut dyn FnMut(&mut Database, &Key, &Value),
+        experiment_key_range: &(Key, Key),
+        distribution: KeyDistribution,
+    ) -> Measurement {
+        let mut total = Measurement::default();
+        for _ in 0..self.num_trials {
+            let measurement = self.bench_throughput_on_db_size(
+                database_size_bytes,
+                experiment,
+                experiment_key_range,
+                distribution,
+                1,
+            );
+            total.ops_per_sec += measurement.ops_per_sec;
+            total.pages_per_op += measurement.pages_per_op;
+            total.space_amplification += measurement.space_amplification;
+            total
+                .trial_ops_per_sec
+                .extend(measurement.trial_ops_per_sec);
+        }
+        Measurement {
+            ops_per_sec: total.ops_per_sec / self.num_trials as f64,
+            pages_per_op: total.pages_per_op / self.num_trials as f64,
+            trial_ops_per_sec: total.trial_ops_per_sec,
+            space_amplification: total.space_amplification / self.num_trials as f64,
+        }
+    }
 }
@@ -0,0 +1,342 @@
+//!bench-compare: compares the raw trials of two experiment runs (see results.rs), e.g. before and after a change.
+//! Usage: experiments bench-compare <baseline trials file> <candidate trials file> [--threshold <fraction>] [--min-t <t>]
+//! Exits with 1 if any experiment regressed, so it can fail CI
+
+use std::fmt::Write;
+
+use crate::results::{self, Trials};
+
+///Exit code when a regression was found
+pub const REGRESSION_EXIT_CODE: i32 = 1;
+///Exit code for bad arguments or unreadable files
+pub const USAGE_EXIT_CODE: i32 = 2;
+
+///When a change in throughput counts as a regression or improvement
+#[derive(Debug, Clone, Copy)]
+pub struct Thresholds {
+    pub min_change: f64, //fraction of the baseline throughput, changes smaller than this are noise
+    pub min_t: f64, //Welch's t statistic the change needs to reach, i.e. how many standard errors it spans
+}
+
+impl Default for Thresholds {
+    fn default() -> Self {
+        Self {
+            min_change: 0.05,
+            min_t: 2.0,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Verdict {
+    Regression,
+    Improvement,
+    Unchanged,
+}
+
+///Comparison of one experiment at one db size
+#[derive(Debug, Clone, PartialEq)]
+pub struct Comparison {
+    pub experiment: String,
+    pub size: usize,
+    pub baseline_mean: f64,
+    pub candidate_mean: f64,
+    pub ratio: f64, //candidate / baseline throughput
+    pub t: f64,
+    pub verdict: Verdict,
+}
+
+#[derive(Debug, Default)]
+pub struct Report {
+    pub comparisons: Vec<Comparison>,
+    pub missing: Vec<(String, usize)>, //experiments and sizes found in only one of the files
+}
+
+impl Report {
+    pub fn has_regression(&self) -> bool {
+        self.comparisons
+            .iter()
+            .any(|comparison| comparison.verdict == Verdict::Regression)
+    }
+}
+
+///Mean and sample variance, the variance of a single trial is 0
+fn mean_and_variance(trials: &[f64]) -> (f64, f64) {
+    let n = trials.len() as f64;
+    let mean = trials.iter().sum::<f64>() / n;
+    if trials.len() < 2 {
+        return (mean, 0.0);
+    }
+    let variance = trials.iter().map(|x| (x - mean).powi(2)).sum::<f64>() / (n - 1.0);
+    (mean, variance)
+}
+
+///Welch's t statistic of the difference in means, infinite if both sides have no variance but differ
+fn welch_t(baseline: &[f64], candidate: &[f64]) -> f64 {
+    let (baseline_mean, baseline_variance) = mean_and_variance(baseline);
+    let (candidate_mean, candidate_variance) = mean_and_variance(candidate);
+    let difference = candidate_mean - baseline_mean;
+    let standard_error = (baseline_variance / baseline.len() as f64
+        + candidate_variance / candidate.len() as f64)
+        .sqrt();
+    if standard_error == 0.0 {
+        return match difference {
+            d if d > 0.0 => f64::INFINITY,
+            d if d < 0.0 => f64::NEG_INFINITY,
+            _ => 0.0,
+        };
+    }
+    difference / standard_error
+}
+
+///Align both runs by experiment and size and classify each change in throughput (higher is better)
+pub fn compare(baseline: &Trials, candidate: &Trials, thresholds: &Thresholds) -> Report {
+    let mut report = Report::default();
+    for (key, baseline_trials) in baseline {
+        let Some(candidate_trials) = candidate.get(key).filter(|trials| !trials.is_empty()) else {
+            report.missing.push(key.clone());
+            continue;
+        };
+        if baseline_trials.is_empty() {
+            report.missing.push(key.clone());
+            continue;
+        }
+        let (baseline_mean, _) = mean_and_variance(baseline_trials);
+        let (candidate_mean, _) = mean_and_variance(candidate_trials);
+        let ratio = candidate_mean / baseline_mean;
+        let t = welch_t(baseline_trials, candidate_trials);
+        let is_significant = t.abs() >= thresholds.min_t;
+        let verdict = if is_significant && ratio <= 1.0 - thresholds.min_change {
+            Verdict::Regression
+        } else if is_significant && ratio >= 1.0 + thresholds.min_change {
+            Verdict::Improvement
+        } else {
+            Verdict::Unchanged
+        };
+        let (experiment, size) = key.clone();
+        report.comparisons.push(Comparison {
+            experiment,
+            size,
+            baseline_mean,
+            candidate_mean,
+            ratio,
+            t,
+            verdict,
+        });
+    }
+    report.missing.extend(
+        candidate
+            .keys()
+            .filter(|key| !baseline.contains_key(*key))
+            .cloned(),
+    );
+    report
+}
+
+pub fn format_report(report: &Report) -> String {
+    let mut output = format!(
+        "{:<10} {:>8} {:>14} {:>14} {:>8} {:>8}  verdict\n",
+        "experiment", "size(MB)", "baseline op/s", "candidate op/s", "ratio", "t"
+    );
+    for comparison in &report.comparisons {
+        let verdict = match comparison.verdict {
+            Verdict::Regression => "REGRESSION",
+            Verdict::Improvement => "improvement",
+            Verdict::Unchanged => "-",
+        };
+        writeln!(
+            output,
+            "{:<10} {:>8} {:>14.1} {:>14.1} {:>8.3} {:>8.2}  {verdict}",
+            comparison.experiment,
+            comparison.size,
+            comparison.baseline_mean,
+            comparison.candidate_mean,
+            comparison.ratio,
+            comparison.t,
+        )
+        .unwrap();
+    }
+    for (experiment, size) in &report.missing {
+        writeln!(output, "{experiment:<10} {size:>8}  only in one file").unwrap();
+    }
+    output
+}
+
+fn usage() -> String {
+    "usage: experiments bench-compare <baseline trials file> <candidate trials file> [--threshold <fraction>] [--min-t <t>]".to_string()
+}
+
+fn parse_args(args: &[String]) -> Result<(String, String, Thresholds), String> {
+    let mut files = Vec::new();
+    let mut thresholds = Thresholds::default();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let mut value = |name: &str| -> Result<f64, String> {
+            let value = args.next().ok_or_else(|| format!("{name} needs a value"))?;
+            value
+                .parse()
+                .map_err(|why| format!("invalid {name} \"{value}\", {why}"))
+        };
+        match arg.as_str() {
+            "--threshold" => thresholds.min_change = value("--threshold")?,
+            "--min-t" => thresholds.min_t = value("--min-t")?,
+            _ => files.push(arg.clone()),
+        }
+    }
+    let [baseline, candidate] = <[String; 2]>::try_from(files).map_err(|_| usage())?;
+    Ok((baseline, candidate, thresholds))
+}
+
+fn read_trials(path: &str) -> Result<Trials, String> {
+    let text = std::fs::read_to_string(path).map_err(|why| format!("{path}: {why}"))?;
+    results::parse_trials(&text).map_err(|why| format!("{path}: {why}"))
+}
+
+///Runs bench-compare with the arguments after the subcommand name, returns the process exit code
+pub fn run(args: &[String]) -> i32 {
+    let compare_files = || -> Result<Report, String> {
+        let (baseline_path, candidate_path, thresholds) = parse_args(args)?;
+        let baseline = read_trials(&baseline_path)?;
+        let candidate = read_trials(&candidate_path)?;
+        Ok(compare(&baseline, &candidate, &thresholds))
+    };
+    match compare_files() {
+        Ok(report) => {
+            print!("{}", format_report(&report));
+            if report.has_regression() {
+                REGRESSION_EXIT_CODE
+            } else {
+                0
+            }
+        }
+        Err(why) => {
+            eprintln!("{why}");
+            USAGE_EXIT_CODE
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{benchmarker::Measurement, distribution::KeyDistribution};
+
+    fn trials(rows: &[(&str, usize, &[f64])]) -> Trials {
+        rows.iter()
+            .map(|(name, size, trials)| ((name.to_string(), *size), trials.to_vec()))
+            .collect()
+    }
+
+    #[test]
+    fn test_compare() {
+        let baseline = trials(&[
+            ("get", 1, &[100.0, 102.0, 98.0]),
+            ("get", 2, &[100.0, 102.0, 98.0]),
+            ("scan", 1, &[50.0, 70.0, 30.0]), //noisy
+            ("put", 1, &[10.0]),
+            ("put", 2, &[10.0]),
+        ]);
+        let candidate = trials(&[
+            ("get", 1, &[80.0, 81.0, 79.0]),   //20% slower
+            ("get", 2, &[101.0, 103.0, 99.0]), //within noise
+            ("scan", 1, &[40.0, 60.0, 20.0]),  //20% slower but not significant
+            ("put", 1, &[12.0]),               //no variance, significant
+            ("delete", 1, &[1.0]),             //new experiment
+        ]);
+        let report = compare(&baseline, &candidate, &Thresholds::default());
+        let verdicts: Vec<(&str, usize, Verdict)> = report
+            .comparisons
+            .iter()
+            .map(|comparison| {
+                (
+                    comparison.experiment.as_str(),
+                    comparison.size,
+                    comparison.verdict,
+                )
+            })
+            .collect();
+        assert_eq!(
+            verdicts,
+            vec![
+                ("get", 1, Verdict::Regression),
+                ("get", 2, Verdict::Unchanged),
+                ("put", 1, Verdict::Improvement),
+                ("scan", 1, Verdict::Unchanged),
+            ]
+        );
+        assert!(report.has_regression());
+        assert_eq!(
+            report.missing,
+            vec![("put".to_string(), 2), ("delete".to_string(), 1)]
+        );
+
+        let get = &report.comparisons[0];
+        assert_eq!((get.baseline_mean, get.candidate_mean), (100.0, 80.0));
+        assert_eq!(get.ratio, 0.8);
+        assert!(get.t < -2.0);
+
+        let table = format_report(&report);
+        assert_eq!(table.lines().count(), 1 + 4 + 2);
+        assert!(table.contains("REGRESSION"));
+    }
+
+    #[test]
+    fn test_thresholds() {
+        let baseline = trials(&[("get", 1, &[100.0, 101.0, 99.0])]);
+        let candidate = trials(&[("get", 1, &[90.0, 91.0, 89.0])]);
+        let report = compare(&baseline, &candidate, &Thresholds::default());
+        assert!(report.has_regression());
+
+        //a 10% drop is tolerated with a 15% threshold
+        let lenient = Thresholds {
+            min_change: 0.15,
+            ..Default::default()
+        };
+        assert!(!compare(&baseline, &candidate, &lenient).has_regression());
+        //or if it has to span more standard errors than it does
+        let strict_t = Thresholds {
+            min_t: f64::MAX,
+            ..Default::default()
+        };
+        assert!(!compare(&baseline, &candidate, &strict_t).has_regression());
+    }
+
+    #[test]
+    fn test_run() {
+        let dir = "bench_compare_test_files";
+        std::fs::create_dir_all(dir).unwrap();
+        let write = |name: &str, ops_per_sec: f64| {
+            let path = format!("{dir}/{name}");
+            let measurement = Measurement {
+                trial_ops_per_sec: vec![ops_per_sec],
+                ..Default::default()
+            };
+            let text = results::format_trials(
+                &[1],
+                &[("get", KeyDistribution::Uniform, &vec![measurement])],
+            );
+            std::fs::write(&path, text).unwrap();
+            path
+        };
+        let fast = write("fast.csv", 100.0);
+        let slow = write("slow.csv", 50.0);
+        //files of the previous schema version compare with current ones
+        let old_fast = format!("{dir}/old_fast.csv");
+        let old_text = "#schema_version 1\nexperiment, size, trial, ops_per_sec\nget, 1, 0, 100\n";
+        std::fs::write(&old_fast, old_text).unwrap();
+        let args =
+            |args: &[&str]| -> Vec<String> { args.iter().map(|arg| arg.to_string()).collect() };
+
+        assert_eq!(run(&args(&[&fast, &fast])), 0);
+        assert_eq!(run(&args(&[&slow, &fast])), 0);
+        assert_eq!(run(&args(&[&fast, &slow])), REGRESSION_EXIT_CODE);
+        assert_eq!(run(&args(&[&old_fast, &fast])), 0);
+        assert_eq!(run(&args(&[&old_fast, &slow])), REGRESSION_EXIT_CODE);
+        assert_eq!(run(&args(&[&fast, &slow, "--threshold", "0.6"])), 0);
+        assert_eq!(run(&args(&[&fast])), USAGE_EXIT_CODE);
+        assert_eq!(run(&args(&[&fast, "missing.csv"])), USAGE_EXIT_CODE);
+        assert_eq!(run(&args(&[&fast, &slow, "--min-t"])), USAGE_EXIT_CODE);
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,292 @@
+use std::{fmt, hint::black_box, time::Instant};
+
+use key_value::util::types::Key;
+use rand::Rng;
+
+///Skew of YCSB's zipfian workloads, used by KeyDistribution::Latest
+pub const YCSB_ZIPFIAN_S: f64 = 0.99;
+
+///How the keys of an experiment are drawn from its key range
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum KeyDistribution {
+    Uniform,
+    ///The i-th smallest key of the range is drawn with probability proportional to 1/i^s, s > 0
+    Zipfian {
+        s: f64,
+    },
+    ///Every key of the range in order from <start>, wrapping around to the start of the range
+    Sequential {
+        start: Key,
+    },
+    ///Zipfian (s = YCSB_ZIPFIAN_S) from the end of the range, the largest keys (written last by a sequential fill)
+    /// are the hottest
+    Latest,
+}
+
+impl fmt::Display for KeyDistribution {
+    //no commas, this ends up in CSV files
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Uniform => write!(f, "uniform"),
+            Self::Zipfian { s } => write!(f, "zipfian s={s}"),
+            Self::Sequential { start } => write!(f, "sequential from {start}"),
+            Self::Latest => write!(f, "latest"),
+        }
+    }
+}
+
+///Draws keys in lower..upper following a distribution, a new generator is made for each trial so sequential keys
+/// restart every trial
+#[derive(Debug, Clone)]
+pub struct KeyGenerator {
+    lower: Key,
+    upper: Key,
+    num_keys: u64,
+    kind: GeneratorKind,
+}
+
+#[derive(Debug, Clone)]
+enum GeneratorKind {
+    Uniform,
+    Zipfian(ZipfianSampler),
+    Sequential { next_offset: u64 },
+    Latest(ZipfianSampler),
+}
+
+impl KeyGenerator {
+    pub fn new(distribution: KeyDistribution, key_range: &(Key, Key)) -> Self {
+        let (lower, upper) = *key_range;
+        assert!(lower < upper, "Empty key range {lower}..{upper}");
+        //the put experiments use almost every key, their count doesn't fit in a Key
+        let num_keys = (upper as i128 - lower as i128) as u64;
+        let kind = match distribution {
+            KeyDistribution::Uniform => GeneratorKind::Uniform,
+            KeyDistribution::Zipfian { s } => {
+                GeneratorKind::Zipfian(ZipfianSampler::new(num_keys, s))
+            }
+            KeyDistribution::Sequential { start } => {
+                assert!(
+                    (lower..upper).contains(&start),
+                    "Sequential keys start at {start}, outside of {lower}..{upper}"
+                );
+                GeneratorKind::Sequential {
+                    next_offset: (start as i128 - lower as i128) as u64,
+                }
+            }
+            KeyDistribution::Latest => {
+                GeneratorKind::Latest(ZipfianSampler::new(num_keys, YCSB_ZIPFIAN_S))
+            }
+        };
+        Self {
+            lower,
+            upper,
+            num_keys,
+            kind,
+        }
+    }
+    pub fn next_key(&mut self, rng: &mut impl Rng) -> Key {
+        let offset = match &mut self.kind {
+            GeneratorKind::Uniform => return rng.gen_range(self.lower..self.upper),
+            GeneratorKind::Zipfian(sampler) => sampler.sample(rng) - 1,
+            GeneratorKind::Sequential { next_offset } => {
+                let offset = *next_offset;
+                *next_offset = (offset + 1) % self.num_keys;
+                offset
+            }
+            GeneratorKind::Latest(sampler) => self.num_keys - sampler.sample(rng),
+        };
+        (self.lower as i128 + offset as i128) as Key
+    }
+}
+
+///Zipfian ranks 1..=n with rejection-inversion sampling (Hörmann and Derflinger, "Rejection-inversion to generate
+/// variates from monotone discrete distributions"), O(1) per sample and no table of n probabilities
+#[derive(Debug, Clone)]
+struct ZipfianSampler {
+    max_rank: u64,
+    n: f64,
+    s: f64,
+    h_integral_x1: f64,
+    h_integral_n: f64,
+    threshold: f64,
+}
+
+impl ZipfianSampler {
+    fn new(n: u64, s: f64) -> Self {
+        assert!(n > 0, "Zipfian sampler needs at least one key");
+        assert!(s > 0.0, "Zipfian exponent must be over 0, got {s}");
+        let mut sampler = Self {
+            max_rank: n,
+            n: n as f64,
+            s,
+            h_integral_x1: 0.0,
+            h_integral_n: 0.0,
+            threshold: 0.0,
+        };
+        sampler.h_integral_x1 = sampler.h_integral(1.5) - 1.0;
+        sampler.h_integral_n = sampler.h_integral(sampler.n + 0.5);
+        sampler.threshold =
+            2.0 - sampler.h_integral_inverse(sampler.h_integral(2.5) - sampler.h(2.0));
+        sampler
+    }
+    fn sample(&self, rng: &mut impl Rng) -> u64 {
+        loop {
+            let u = self.h_integral_n + rng.gen::<f64>() * (self.h_integral_x1 - self.h_integral_n);
+            let x = self.h_integral_inverse(u);
+            let k = (x + 0.5).clamp(1.0, self.n).floor();
+            //accepted right away for most samples, otherwise k is accepted with the ratio of its probability to
+            // the area under the hat function
+            if k - x <= self.threshold || u >= self.h_integral(k + 0.5) - self.h(k) {
+                return (k as u64).min(self.max_rank); //n may have been rounded up as a float
+            }
+        }
+    }
+    fn h(&self, x: f64) -> f64 {
+        (-self.s * x.ln()).exp()
+    }
+    ///Integral of h from 1 to x
+    fn h_integral(&self, x: f64) -> f64 {
+        let log_x = x.ln();
+        expm1_over_x((1.0 - self.s) * log_x) * log_x
+    }
+    fn h_integral_inverse(&self, x: f64) -> f64 {
+        let t = (x * (1.0 - self.s)).max(-1.0);
+        (ln1p_over_x(t) * x).exp()
+    }
+}
+
+///(e^x - 1) / x, accurate near 0
+fn expm1_over_x(x: f64) -> f64 {
+    match x.abs() > 1e-8 {
+        true => x.exp_m1() / x,
+        false => 1.0 + x / 2.0 * (1.0 + x / 3.0 * (1.0 + x / 4.0)),
+    }
+}
+
+///ln(1 + x) / x, accurate near 0
+fn ln1p_over_x(x: f64) -> f64 {
+    match x.abs() > 1e-8 {
+        true => x.ln_1p() / x,
+        false => 1.0 - x * (0.5 - x * (1.0 / 3.0 - 0.25 * x)),
+    }
+}
+
+///Keys generated per second, to check that drawing keys doesn't dominate the measured time of an operation
+pub fn bench_generator(
+    distribution: KeyDistribution,
+    key_range: &(Key, Key),
+    num_keys: usize,
+) -> f64 {
+    let mut generator = KeyGenerator::new(distribution, key_range);
+    let mut rng = rand::thread_rng();
+    let start = Instant::now();
+    for _ in 0..num_keys {
+        black_box(generator.next_key(&mut rng));
+    }
+    num_keys as f64 / start.elapsed().as_secs_f64()
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::{rngs::StdRng, SeedableRng};
+
+    use super::*;
+
+    const NUM_SAMPLES: usize = 200_000;
+
+    ///How often each key of <key_range> was drawn
+    fn frequencies(distribution: KeyDistribution, key_range: &(Key, Key)) -> Vec<f64> {
+        let mut generator = KeyGenerator::new(distribution, key_range);
+        let mut rng = StdRng::seed_from_u64(0);
+        let mut counts = vec![0; (key_range.1 - key_range.0) as usize];
+        for _ in 0..NUM_SAMPLES {
+            let key = generator.next_key(&mut rng);
+            assert!((key_range.0..key_range.1).contains(&key), "{key} drawn");
+            counts[(key - key_range.0) as usize] += 1;
+        }
+        counts
+            .into_iter()
+            .map(|count| count as f64 / NUM_SAMPLES as f64)
+            .collect()
+    }
+
+    ///Probability of each rank 1..=n
+    fn zipf_probabilities(n: usize, s: f64) -> Vec<f64> {
+        let weights: Vec<f64> = (1..=n).map(|rank| (rank as f64).powf(-s)).collect();
+        let total: f64 = weights.iter().sum();
+        weights.into_iter().map(|weight| weight / total).collect()
+    }
+
+    #[test]
+    fn test_zipfian_frequencies() {
+        //the top keys are drawn within 5 standard deviations of their probability
+        for (n, s) in [
+            (1000, 1.0),
+            (1000, YCSB_ZIPFIAN_S),
+            (50, 0.5),
+            (10_000, 1.5),
+        ] {
+            let key_range = (-20, n as Key - 20);
+            let frequencies = frequencies(KeyDistribution::Zipfian { s }, &key_range);
+            let probabilities = zipf_probabilities(n, s);
+            for rank in 0..3 {
+                let p = probabilities[rank];
+                let tolerance = 5.0 * (p * (1.0 - p) / NUM_SAMPLES as f64).sqrt();
+                assert!(
+                    (frequencies[rank] - p).abs() <= tolerance,
+                    "n {n}, s {s}: rank {} drawn {} of the time, expected {p}",
+                    rank + 1,
+                    frequencies[rank]
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_latest_frequencies() {
+        let frequencies = frequencies(KeyDistribution::Latest, &(0, 1000));
+        let probabilities = zipf_probabilities(1000, YCSB_ZIPFIAN_S);
+        let p = probabilities[0];
+        assert!((frequencies[999] - p).abs() <= 5.0 * (p * (1.0 - p) / NUM_SAMPLES as f64).sqrt());
+        assert!(frequencies[999] > frequencies[998] && frequencies[998] > frequencies[0]);
+    }
+
+    #[test]
+    fn test_uniform_and_sequential() {
+        let frequencies = frequencies(KeyDistribution::Uniform, &(0, 10));
+        assert!(frequencies
+            .iter()
+            .all(|frequency| (frequency - 0.1).abs() < 0.01));
+
+        let mut generator = KeyGenerator::new(KeyDistribution::Sequential { start: 8 }, &(5, 10));
+        let mut rng = StdRng::seed_from_u64(0);
+        let keys: Vec<Key> = (0..7).map(|_| generator.next_key(&mut rng)).collect();
+        assert_eq!(keys, vec![8, 9, 5, 6, 7, 8, 9]);
+    }
+
+    #[test]
+    fn test_whole_key_range() {
+        //the put experiments draw from every valid key
+        let key_range = (Key::MIN + 1, Key::MAX);
+        let mut rng = StdRng::seed_from_u64(0);
+        for distribution in [
+            KeyDistribution::Uniform,
+            KeyDistribution::Zipfian { s: YCSB_ZIPFIAN_S },
+            KeyDistribution::Latest,
+        ] {
+            let mut generator = KeyGenerator::new(distribution, &key_range);
+            for _ in 0..1000 {
+                let key = generator.next_key(&mut rng);
+                assert!((key_range.0..key_range.1).contains(&key));
+            }
+        }
+        let mut generator = KeyGenerator::new(
+            KeyDistribution::Sequential {
+                start: Key::MAX - 1,
+            },
+            &key_range,
+        );
+        assert_eq!(generator.next_key(&mut rng), Key::MAX - 1);
+        assert_eq!(generator.next_key(&mut rng), Key::MIN + 1);
+    }
+}
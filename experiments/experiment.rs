@@ -1,5 +1,8 @@
+use std::sync::{Arc, Mutex};
+
 use key_value::{
     db::Database,
+    stats::{self, CompactionStats},
     util::{
         system_info::ENTRY_SIZE,
         testing,
@@ -7,16 +10,32 @@ use key_value::{
     },
 };
 
-use crate::benchmarker::Benchmarker;
+use crate::{
+    benchmarker::{Benchmarker, Measurement},
+    distribution::{self, KeyDistribution},
+    results::{self, SizeResult},
+};
 
 const MEMTABLE_MB_SIZE: usize = 1;
 const BUFFER_POOL_INITIAL_MB_SIZE: usize = 2;
 const BUFFER_POOL_CAPACITY_MB_SIZE: usize = 10;
 const BLOOM_FILTER_BITS_PER_ENTRY: usize = 5;
+///B-tree and binary search are compared under uniform and hot key queries
+const PART2_DISTRIBUTIONS: [KeyDistribution; 2] = [
+    KeyDistribution::Uniform,
+    KeyDistribution::Zipfian {
+        s: distribution::YCSB_ZIPFIAN_S,
+    },
+];
 
+///Get, scan and put results of each distribution
+type DistributionResults = Vec<(KeyDistribution, [Vec<Measurement>; 3])>;
+
+///Runs the experiments once for each distribution of the input keys, the db is always filled uniformly
 pub fn run(
     database_alterations: Box<dyn FnMut(Database) -> Database>,
-) -> (Vec<usize>, [Vec<f64>; 3]) {
+    distributions: &[KeyDistribution],
+) -> (Vec<usize>, DistributionResults) {
     let bytes_per_mb = 2_usize.pow(20);
     let window_duration_sec = 10;
     let num_trials = 10;
@@ -33,7 +52,7 @@ pub fn run(
     println!("number of trials for each experiment: {num_trials}");
     println!("Window duration: {window_duration_sec} seconds");
     println!("Experiment sizes (MB): {:?}", db_mb_sizes);
-    println!("NOTE: results are in operations per second\n");
+    println!("NOTE: results are in operations per second and pages of I/O per operation\n");
 
     let mut bm = Benchmarker::new(
         Box::new(database_alterations),
@@ -42,18 +61,34 @@ pub fn run(
         num_trials,
     );
 
+    let mut results = DistributionResults::new();
+    for distribution in distributions {
+        println!("Key distribution: {distribution}\n");
+        results.push((
+            *distribution,
+            run_distribution(&mut bm, *distribution, num_elements_in_smallest_db),
+        ));
+    }
+    (db_mb_sizes, results)
+}
+
+fn run_distribution(
+    bm: &mut Benchmarker,
+    distribution: KeyDistribution,
+    num_elements_in_smallest_db: Key,
+) -> [Vec<Measurement>; 3] {
     //get range ensures that all get inputs are keys that actually exist in the db
     let get_range_lower = 0;
     let get_range_upper = num_elements_in_smallest_db;
     println!(
-        "Get experiment, get random value in range {}..{}",
+        "Get experiment, get {distribution} value in range {}..{}",
         get_range_lower, get_range_upper
     );
     let get_experiment = &mut |db: &mut Database, key: &Key, _value: &Value| {
         db.get(*key);
     };
     let get_experiment_input_range = (get_range_lower, get_range_upper);
-    let get_results = bm.run_experiment(get_experiment, &get_experiment_input_range);
+    let get_results = bm.run_experiment(get_experiment, &get_experiment_input_range, distribution);
     println!("{:?}", get_results);
 
     //scan range ensures that entire scan size is within db
@@ -61,48 +96,83 @@ pub fn run(
     let scan_range_lower = 0;
     let scan_range_upper = num_elements_in_smallest_db - scan_size;
     println!(
-        "Scan experiment, scan size:{scan_size} in range {}..{}",
+        "Scan experiment, scan size:{scan_size} from {distribution} keys in range {}..{}",
         scan_range_lower, scan_range_upper
     );
     let scan_experiment = &mut |db: &mut Database, key: &Key, _value: &Value| {
         db.scan(*key, *key + scan_size);
     };
     let scan_experiment_input_range = (scan_range_lower, scan_range_upper);
-    let scan_results = bm.run_experiment(scan_experiment, &scan_experiment_input_range);
+    let scan_results =
+        bm.run_experiment(scan_experiment, &scan_experiment_input_range, distribution);
     println!("{:?}", scan_results);
 
-    //put range has a low probability of adding something already in the memtable
+    //put range has a low probability of adding something already in the memtable (unless the keys are skewed)
     let put_range_lower = Key::MIN + 1;
     let put_range_upper = Key::MAX;
 
     println!(
-        "put experiment, put random (key, value) in range {}..{} (key range only, value can be anything)",
+        "put experiment, put {distribution} (key, value) in range {}..{} (key range only, value can be anything)",
         put_range_lower, put_range_upper
     );
     let put_experiment = &mut |db: &mut Database, key: &Key, value: &Value| {
-        db.put(*key, *value);
+        db.put(*key, *value).unwrap();
     };
     let put_experiment_input_range = (put_range_lower, put_range_upper);
-    let put_results = bm.run_reset_experiment(put_experiment, &put_experiment_input_range);
+    let put_results =
+        bm.run_reset_experiment(put_experiment, &put_experiment_input_range, distribution);
     println!("{:?}\n", put_results);
 
-    (db_mb_sizes, [get_results, scan_results, put_results])
+    [get_results, scan_results, put_results]
 }
 
-pub fn run_and_save(database_alterations: Box<dyn FnMut(Database) -> Database>, filename: &str) {
-    let (db_mb_sizes, [get_results, scan_results, put_results]) = run(database_alterations);
+pub fn run_and_save(
+    mut database_alterations: Box<dyn FnMut(Database) -> Database>,
+    filename: &str,
+    distributions: &[KeyDistribution],
+) {
+    let costs: Arc<Mutex<CompactionStats>> = Arc::default();
+    let recorded = Arc::clone(&costs);
+    let with_listener = move |db: Database| {
+        let recorded = Arc::clone(&recorded);
+        database_alterations(db)
+            .set_event_listener(move |event| recorded.lock().unwrap().record(event))
+    };
+    let (db_mb_sizes, results) = run(Box::new(with_listener), distributions);
 
-    let mut output = "size, get, scan, put\n".to_string();
-    for (i, size) in db_mb_sizes.iter().enumerate() {
-        let line = format!(
-            "{}, {}, {}, {}\n",
-            size, get_results[i], scan_results[i], put_results[i]
-        );
-        output.push_str(&line);
+    let mut size_results = Vec::new();
+    for (distribution, [get_results, scan_results, put_results]) in results.iter() {
+        for (i, size_mb) in db_mb_sizes.iter().enumerate() {
+            size_results.push(SizeResult {
+                distribution: *distribution,
+                size_mb: *size_mb,
+                get: &get_results[i],
+                scan: &scan_results[i],
+                put: &put_results[i],
+            });
+        }
     }
-
-    std::fs::write(format!("{filename}.csv"), output)
+    std::fs::write(format!("{filename}.csv"), stats::to_csv(&size_results))
         .unwrap_or_else(|_| panic!("Unable to write file for {}", filename));
+
+    //what compactions cost across every experiment
+    let compaction_stats = stats::to_json(&*costs.lock().unwrap());
+    std::fs::write(format!("{filename}_compaction.json"), compaction_stats)
+        .unwrap_or_else(|_| panic!("Unable to write compaction stats file for {}", filename));
+
+    //raw trials, for comparing runs with bench-compare
+    let experiments: Vec<(&str, KeyDistribution, &Vec<Measurement>)> = results
+        .iter()
+        .flat_map(|(distribution, measurements)| {
+            ["get", "scan", "put"]
+                .into_iter()
+                .zip(measurements.iter())
+                .map(|(name, measurement)| (name, *distribution, measurement))
+        })
+        .collect();
+    let trials = results::format_trials(&db_mb_sizes, &experiments);
+    std::fs::write(format!("{filename}{}", results::TRIALS_FILE_SUFFIX), trials)
+        .unwrap_or_else(|_| panic!("Unable to write trials file for {}", filename));
 }
 
 pub fn common_database_alterations(db: Database) -> Database {
@@ -114,14 +184,14 @@ pub fn common_database_alterations(db: Database) -> Database {
 
 pub fn part1() {
     /*
-        For this step of the project, please generate experiments that measure the 
-        performance of your three operators, put, get and scan, as you insert more 
-        data into the system. The x-axis should report the data volume that had been 
-        inserted, while the y-axes should report throughput. Three figures should be 
-        produced, one for each operation. These should be included in your report under 
-        the title “Experiments for Step 1”. Bandwidth can be measured by dividing time 
-        into consecutive windows (e.g., 10 seconds each), recording how many operations 
-        were performed in each window, and diving by the window length in seconds to get 
+        For this step of the project, please generate experiments that measure the
+        performance of your three operators, put, get and scan, as you insert more
+        data into the system. The x-axis should report the data volume that had been
+        inserted, while the y-axes should report throughput. Three figures should be
+        produced, one for each operation. These should be included in your report under
+        the title “Experiments for Step 1”. Bandwidth can be measured by dividing time
+        into consecutive windows (e.g., 10 seconds each), recording how many operations
+        were performed in each window, and diving by the window length in seconds to get
         the average number of operations per second
     */
     println!("Part 1: Experiment");
@@ -131,7 +201,11 @@ pub fn part1() {
         common_database_alterations(testing::part1_db_alterations(db))
     };
 
-    run_and_save(Box::new(database_alterations), "part1_experiments");
+    run_and_save(
+        Box::new(database_alterations),
+        "part1_experiments",
+        &[KeyDistribution::Uniform],
+    );
 }
 
 pub fn part2() {
@@ -142,6 +216,21 @@ pub fn part2() {
         The buffer pool should be enabled in this experiment, and the data should grow beyond the maximum
         buffer pool size so that evictions kick in. Explain your findings.
     */
+    //drawing a key has to be much faster than a get for the skewed results to be meaningful
+    println!("Key generator throughput (keys/sec):");
+    for distribution in [
+        KeyDistribution::Uniform,
+        KeyDistribution::Zipfian {
+            s: distribution::YCSB_ZIPFIAN_S,
+        },
+        KeyDistribution::Sequential { start: 0 },
+        KeyDistribution::Latest,
+    ] {
+        let keys_per_sec = distribution::bench_generator(distribution, &(0, Key::MAX), 1_000_000);
+        println!("{distribution}: {keys_per_sec:.0}");
+    }
+    println!();
+
     println!("Part 2: Experiment (b-tree)");
     println!("Memtable Size: {} MB", MEMTABLE_MB_SIZE);
     println!(
@@ -160,6 +249,7 @@ pub fn part2() {
     run_and_save(
         Box::new(btree_database_alterations),
         "part2_btree_experiments",
+        &PART2_DISTRIBUTIONS,
     );
 
     println!("Part 2: Experiment (binary search)");
@@ -180,6 +270,7 @@ pub fn part2() {
     run_and_save(
         Box::new(binary_search_database_alterations),
         "part2_binary_search_experiments",
+        &PART2_DISTRIBUTIONS,
     );
 }
 
@@ -209,5 +300,9 @@ pub fn part3() {
         common_database_alterations(testing::part3_db_alterations(db))
     };
 
-    run_and_save(Box::new(database_alterations), "part3_experiments");
+    run_and_save(
+        Box::new(database_alterations),
+        "part3_experiments",
+        &[KeyDistribution::Uniform],
+    );
 }
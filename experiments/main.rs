@@ -1,7 +1,33 @@
 mod benchmarker;
+mod compare;
+mod distribution;
 mod experiment;
+mod results;
+mod scan_keys;
+mod sorted_batch;
 
 fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if args
+        .first()
+        .is_some_and(|command| command == "bench-compare")
+    {
+        std::process::exit(compare::run(&args[1..]));
+    }
+    if args
+        .first()
+        .is_some_and(|command| command == "bench-sorted-batch")
+    {
+        std::process::exit(sorted_batch::run(&args[1..]));
+    }
+
+    if args
+        .first()
+        .is_some_and(|command| command == "bench-scan-keys")
+    {
+        std::process::exit(scan_keys::run(&args[1..]));
+    }
+
     experiment::part1();
     experiment::part2();
     experiment::part3();
@@ -0,0 +1,285 @@
+//!Result files of an experiment run, written with key_value::stats so every file starts with the schema version of
+//! its records. One summary line per distribution and size, and raw per-trial results, the input of bench-compare.
+//! Trial files of the previous schema version are still read, see parse_trials
+
+use std::collections::BTreeMap;
+
+use key_value::stats::{self, FieldValue, Record};
+
+use crate::{benchmarker::Measurement, distribution::KeyDistribution};
+
+///Appended to an experiment's result file name
+pub const TRIALS_FILE_SUFFIX: &str = "_trials.csv";
+///Columns of trial files of schema version 1, before the distribution had a column of its own
+const TRIAL_FIELDS_V1: &[&str] = &["experiment", "size", "trial", "ops_per_sec"];
+
+///Ops/sec of every trial, by experiment name and db size (MB)
+pub type Trials = BTreeMap<(String, usize), Vec<f64>>;
+
+///Get, scan and put results of a distribution on a database of <size_mb>
+#[derive(Debug, Clone)]
+pub struct SizeResult<'a> {
+    pub distribution: KeyDistribution,
+    pub size_mb: usize,
+    pub get: &'a Measurement,
+    pub scan: &'a Measurement,
+    pub put: &'a Measurement,
+}
+
+impl Record for SizeResult<'_> {
+    const SCHEMA_VERSION: u32 = 1;
+    const FIELDS: &'static [&'static str] = &[
+        "distribution",
+        "size_mb",
+        "get_ops_per_sec",
+        "get_pages_per_op",
+        "scan_ops_per_sec",
+        "scan_pages_per_op",
+        "put_ops_per_sec",
+        "put_pages_per_op",
+        "space_amplification",
+    ];
+    fn values(&self) -> Vec<FieldValue> {
+        let mut values = vec![
+            FieldValue::Text(self.distribution.to_string()),
+            FieldValue::Count(self.size_mb),
+        ];
+        for measurement in [self.get, self.scan, self.put] {
+            values.push(FieldValue::Number(measurement.ops_per_sec));
+            values.push(FieldValue::Number(measurement.pages_per_op));
+        }
+        //of the database the gets ran on, filled and not written to since
+        values.push(FieldValue::Number(self.get.space_amplification));
+        values
+    }
+}
+
+///Ops/sec of one trial
+#[derive(Debug, Clone)]
+pub struct TrialResult {
+    pub experiment: String,
+    pub distribution: KeyDistribution,
+    pub size_mb: usize,
+    pub trial: usize,
+    pub ops_per_sec: f64,
+}
+
+impl TrialResult {
+    ///Key of the trial in Trials. Uniform results keep the plain experiment name, so they compare with files written
+    /// before there were distributions
+    fn name(experiment: &str, distribution: &str) -> String {
+        match distribution {
+            "uniform" => experiment.to_string(),
+            _ => format!("{experiment} {distribution}"),
+        }
+    }
+}
+
+impl Record for TrialResult {
+    const SCHEMA_VERSION: u32 = 2;
+    const FIELDS: &'static [&'static str] = &[
+        "experiment",
+        "distribution",
+        "size_mb",
+        "trial",
+        "ops_per_sec",
+    ];
+    fn values(&self) -> Vec<FieldValue> {
+        vec![
+            FieldValue::Text(self.experiment.clone()),
+            FieldValue::Text(self.distribution.to_string()),
+            FieldValue::Count(self.size_mb),
+            FieldValue::Count(self.trial),
+            FieldValue::Number(self.ops_per_sec),
+        ]
+    }
+}
+
+///<experiments> are (name, distribution, one measurement per size in <sizes>)
+pub fn format_trials(
+    sizes: &[usize],
+    experiments: &[(&str, KeyDistribution, &Vec<Measurement>)],
+) -> String {
+    let mut records = Vec::new();
+    for (name, distribution, measurements) in experiments {
+        for (size, measurement) in sizes.iter().zip(measurements.iter()) {
+            for (trial, ops_per_sec) in measurement.trial_ops_per_sec.iter().enumerate() {
+                records.push(TrialResult {
+                    experiment: name.to_string(),
+                    distribution: *distribution,
+                    size_mb: *size,
+                    trial,
+                    ops_per_sec: *ops_per_sec,
+                });
+            }
+        }
+    }
+    stats::to_csv(&records)
+}
+
+///Reads trial files of the current and the previous schema version
+pub fn parse_trials(text: &str) -> Result<Trials, String> {
+    let table = stats::parse_csv(text)?;
+    let expected_columns = match table.schema_version {
+        1 => TRIAL_FIELDS_V1,
+        TrialResult::SCHEMA_VERSION => TrialResult::FIELDS,
+        version => {
+            return Err(format!(
+                "unsupported schema version {version}, expected 1 or {}",
+                TrialResult::SCHEMA_VERSION
+            ))
+        }
+    };
+    if table.columns != expected_columns {
+        return Err(format!(
+            "header of schema version {} should be \"{}\"",
+            table.schema_version,
+            expected_columns.join(", ")
+        ));
+    }
+
+    let mut trials = Trials::new();
+    for (row_index, row) in table.rows.iter().enumerate() {
+        let (name, size, ops_per_sec) = match &row[..] {
+            [name, size, _trial, ops_per_sec] => (name.clone(), size, ops_per_sec),
+            [experiment, distribution, size, _trial, ops_per_sec] => (
+                TrialResult::name(experiment, distribution),
+                size,
+                ops_per_sec,
+            ),
+            _ => unreachable!("rows have a value for each column"),
+        };
+        let row_number = row_index + 1;
+        let size = size
+            .parse::<usize>()
+            .map_err(|why| format!("row {row_number}: invalid size \"{size}\", {why}"))?;
+        let ops_per_sec = ops_per_sec
+            .parse::<f64>()
+            .map_err(|why| format!("row {row_number}: invalid ops/sec \"{ops_per_sec}\", {why}"))?;
+        trials.entry((name, size)).or_default().push(ops_per_sec);
+    }
+    Ok(trials)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn measurement(trials: &[f64]) -> Measurement {
+        Measurement {
+            trial_ops_per_sec: trials.to_vec(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_format_and_parse() {
+        let get = vec![measurement(&[10.0, 12.0]), measurement(&[5.0])];
+        let put = vec![measurement(&[100.5]), measurement(&[])];
+        let zipfian = KeyDistribution::Zipfian { s: 0.99 };
+        let text = format_trials(
+            &[1, 2],
+            &[
+                ("get", KeyDistribution::Uniform, &get),
+                ("put", zipfian, &put),
+            ],
+        );
+        assert_eq!(
+            text,
+            "#schema_version 2\n\
+             experiment, distribution, size_mb, trial, ops_per_sec\n\
+             get, uniform, 1, 0, 10\n\
+             get, uniform, 1, 1, 12\n\
+             get, uniform, 2, 0, 5\n\
+             put, zipfian s=0.99, 1, 0, 100.5\n"
+        );
+
+        let trials = parse_trials(&text).unwrap();
+        let expected = Trials::from([
+            (("get".to_string(), 1), vec![10.0, 12.0]),
+            (("get".to_string(), 2), vec![5.0]),
+            (("put zipfian s=0.99".to_string(), 1), vec![100.5]),
+        ]);
+        assert_eq!(trials, expected);
+    }
+
+    #[test]
+    fn test_parse_previous_version() {
+        //as written before the distribution had a column of its own, the same trials are read
+        let text = "#schema_version 1\n\
+                    experiment, size, trial, ops_per_sec\n\
+                    get, 1, 0, 10\n\
+                    put zipfian s=0.99, 1, 0, 100.5\n";
+        let current = format_trials(
+            &[1],
+            &[
+                ("get", KeyDistribution::Uniform, &vec![measurement(&[10.0])]),
+                (
+                    "put",
+                    KeyDistribution::Zipfian { s: 0.99 },
+                    &vec![measurement(&[100.5])],
+                ),
+            ],
+        );
+        assert_eq!(parse_trials(text).unwrap(), parse_trials(&current).unwrap());
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        let header = "experiment, distribution, size_mb, trial, ops_per_sec\n";
+        assert!(parse_trials(header).is_err()); //no version
+        let future = format!("#schema_version 3\n{header}");
+        assert!(parse_trials(&future).unwrap_err().contains("version 3"));
+        let mixed = format!("#schema_version 1\n{header}");
+        assert!(parse_trials(&mixed).unwrap_err().contains("header"));
+        let bad_row = format!("#schema_version 2\n{header}get, uniform, 1, 0\n");
+        assert!(parse_trials(&bad_row).unwrap_err().contains("line 3"));
+        let bad_number = format!("#schema_version 2\n{header}get, uniform, 1, 0, fast\n");
+        assert!(parse_trials(&bad_number).is_err());
+    }
+
+    #[test]
+    fn test_schemas_are_pinned() {
+        //if this fails, the fields of a record changed: bump its SCHEMA_VERSION (keeping the old version readable)
+        // and then update the expected fields
+        assert_eq!(
+            (SizeResult::SCHEMA_VERSION, SizeResult::FIELDS),
+            (
+                1,
+                &[
+                    "distribution",
+                    "size_mb",
+                    "get_ops_per_sec",
+                    "get_pages_per_op",
+                    "scan_ops_per_sec",
+                    "scan_pages_per_op",
+                    "put_ops_per_sec",
+                    "put_pages_per_op",
+                    "space_amplification",
+                ][..]
+            )
+        );
+        assert_eq!(
+            (TrialResult::SCHEMA_VERSION, TrialResult::FIELDS),
+            (
+                2,
+                &[
+                    "experiment",
+                    "distribution",
+                    "size_mb",
+                    "trial",
+                    "ops_per_sec"
+                ][..]
+            )
+        );
+        let result = measurement(&[]);
+        let size_result = SizeResult {
+            distribution: KeyDistribution::Uniform,
+            size_mb: 1,
+            get: &result,
+            scan: &result,
+            put: &result,
+        };
+        assert_eq!(size_result.values().len(), SizeResult::FIELDS.len());
+    }
+}
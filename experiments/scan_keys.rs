@@ -0,0 +1,75 @@
+//!bench-scan-keys: times scanning a wide range for its keys with Database::scan_keys against Database::scan, on a
+//! database kept in memory whose runs are spread over several levels and on one compacted into a single run.
+//! Usage: experiments bench-scan-keys [<number of entries>]
+
+use std::time::{Duration, Instant};
+
+use key_value::{
+    db::Database,
+    util::types::{CompactionPolicy, Entry, Key, Size},
+};
+
+use crate::compare::USAGE_EXIT_CODE;
+
+const DEFAULT_NUM_ENTRIES: Size = 1_000_000;
+const NUM_TRIALS: usize = 5;
+
+///Fastest of NUM_TRIALS runs of <scan>, with the number of results and the bytes they take
+fn time_scan<T>(
+    db: &mut Database,
+    scan: &dyn Fn(&mut Database) -> Vec<T>,
+) -> (Duration, Size, Size) {
+    let mut fastest = Duration::MAX;
+    let mut num_results = 0;
+    for _ in 0..NUM_TRIALS {
+        let start = Instant::now();
+        let results = scan(db);
+        fastest = fastest.min(start.elapsed());
+        num_results = results.len();
+    }
+    (fastest, num_results, num_results * std::mem::size_of::<T>())
+}
+
+pub fn run(args: &[String]) -> i32 {
+    let num_entries = match args {
+        [] => DEFAULT_NUM_ENTRIES,
+        [num_entries] => match num_entries.parse::<Size>() {
+            Ok(num_entries) => num_entries,
+            Err(why) => {
+                eprintln!("invalid number of entries \"{num_entries}\", {why}");
+                return USAGE_EXIT_CODE;
+            }
+        },
+        _ => {
+            eprintln!("usage: experiments bench-scan-keys [<number of entries>]");
+            return USAGE_EXIT_CODE;
+        }
+    };
+    let db_name = "scan_keys_bench";
+    //runs of a tenth of the entries each under tiering, then the whole database in the memtable and flushed once
+    for (memtable_capacity, shape) in [
+        (num_entries.div_ceil(10), "several levels"),
+        (num_entries, "a single run"),
+    ] {
+        let mut db = Database::open_in_memory(db_name)
+            .set_memtable_capacity(memtable_capacity)
+            .set_compaction_policy(CompactionPolicy::Tiered);
+        for key in 0..num_entries as Key {
+            db.put(key, -key).unwrap();
+        }
+        db.flush().unwrap();
+
+        let (key1, key2) = (0, num_entries as Key);
+        let (scan_time, num_results, scan_bytes) =
+            time_scan::<Entry>(&mut db, &|db| db.scan(key1, key2));
+        let (keys_time, _, keys_bytes) = time_scan::<Key>(&mut db, &|db| db.scan_keys(key1, key2));
+        println!(
+            "{num_results} keys in {shape}: scan {:.1}ms ({scan_bytes} bytes), scan_keys {:.1}ms ({keys_bytes} bytes), {:.1}x",
+            scan_time.as_secs_f64() * 1000.0,
+            keys_time.as_secs_f64() * 1000.0,
+            scan_time.as_secs_f64() / keys_time.as_secs_f64().max(f64::EPSILON)
+        );
+        db.close();
+    }
+    0
+}
@@ -0,0 +1,71 @@
+//!bench-sorted-batch: times putting a batch of sorted entries with Database::put_sorted_batch against a put per entry,
+//! in databases kept in memory so only the memtable and flushes are measured.
+//! Usage: experiments bench-sorted-batch [<number of entries>]
+
+use std::time::{Duration, Instant};
+
+use key_value::{
+    db::Database,
+    util::types::{Entry, Key, Size},
+};
+
+use crate::compare::USAGE_EXIT_CODE;
+
+const DEFAULT_NUM_ENTRIES: Size = 100_000;
+const NUM_TRIALS: usize = 3;
+
+///Fastest of NUM_TRIALS runs of <put> on a fresh database with room for <memtable_capacity> entries
+fn time_puts(
+    memtable_capacity: Size,
+    entries: &[Entry],
+    put: &dyn Fn(&mut Database, &[Entry]),
+) -> Duration {
+    let db_name = "sorted_batch_bench";
+    (0..NUM_TRIALS)
+        .map(|_| {
+            let mut db = Database::open_in_memory(db_name).set_memtable_capacity(memtable_capacity);
+            let start = Instant::now();
+            put(&mut db, entries);
+            let elapsed = start.elapsed();
+            db.close();
+            elapsed
+        })
+        .min()
+        .unwrap_or_default()
+}
+
+pub fn run(args: &[String]) -> i32 {
+    let num_entries = match args {
+        [] => DEFAULT_NUM_ENTRIES,
+        [num_entries] => match num_entries.parse::<Size>() {
+            Ok(num_entries) => num_entries,
+            Err(why) => {
+                eprintln!("invalid number of entries \"{num_entries}\", {why}");
+                return USAGE_EXIT_CODE;
+            }
+        },
+        _ => {
+            eprintln!("usage: experiments bench-sorted-batch [<number of entries>]");
+            return USAGE_EXIT_CODE;
+        }
+    };
+    let entries: Vec<Entry> = (0..num_entries as Key).map(|key| (key, -key)).collect();
+    //the whole batch in the memtable, then flushed every tenth of the batch
+    for memtable_capacity in [num_entries, num_entries.div_ceil(10)] {
+        let looped = time_puts(memtable_capacity, &entries, &|db, entries| {
+            for &(key, value) in entries {
+                db.put(key, value).unwrap();
+            }
+        });
+        let batched = time_puts(memtable_capacity, &entries, &|db, entries| {
+            db.put_sorted_batch(entries).unwrap();
+        });
+        println!(
+            "{num_entries} entries, memtable of {memtable_capacity}: puts {:.1}ms, sorted batch {:.1}ms ({:.1}x)",
+            looped.as_secs_f64() * 1000.0,
+            batched.as_secs_f64() * 1000.0,
+            looped.as_secs_f64() / batched.as_secs_f64().max(f64::EPSILON)
+        );
+    }
+    0
+}
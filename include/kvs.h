@@ -0,0 +1,35 @@
+#ifndef KVS_H
+#define KVS_H
+
+#include <stdint.h>
+
+#define KVS_OK 0
+
+#define KVS_NOT_FOUND 1
+
+#define KVS_BUFFER_TOO_SMALL 2
+
+#define KVS_INVALID_ARGUMENT -1
+
+#define KVS_ERROR -2
+
+typedef struct Database Database;
+
+Database *kvs_open(const char *name);
+
+int32_t kvs_put(Database *db, int64_t key, int64_t value);
+
+int32_t kvs_get(Database *db, int64_t key, int64_t *out_value);
+
+int32_t kvs_scan(Database *db,
+                 int64_t key1,
+                 int64_t key2,
+                 int64_t *out_buf,
+                 uintptr_t buf_len,
+                 uintptr_t *out_written);
+
+int32_t kvs_close(Database *db);
+
+const char *kvs_last_error(void);
+
+#endif /* KVS_H */
@@ -0,0 +1,594 @@
+use std::{
+    cell::Cell,
+    collections::{HashMap, HashSet},
+    path::{Path, PathBuf},
+    rc::Rc,
+};
+
+use super::{BufferPoolStats, FramePin, PinnedPage};
+use crate::{
+    data_structures::extendible_hash_table::ExtendibleHashTable,
+    file_io::generation::{self, Generation},
+    util::{
+        hash::FastHasher,
+        types::{Page, Size},
+    },
+};
+
+type PageKey = (PathBuf, Page);
+
+#[derive(Debug, Clone)]
+struct Frame {
+    //NOTE: all vectors should be at most system_info::page_size() number of bytes
+    bytes: Vec<u8>,
+    generation: Generation, //of the file when the page was read from it
+    pins: Rc<Cell<Size>>,   //FramePins held on the frame, pinned frames are never evicted
+}
+
+impl Frame {
+    fn new(bytes: Vec<u8>, generation: Generation) -> Self {
+        Self {
+            bytes,
+            generation,
+            pins: Rc::default(),
+        }
+    }
+    fn is_pinned(&self) -> bool {
+        self.pins.get() > 0
+    }
+}
+
+#[derive(Debug)]
+pub struct BufferPool {
+    frames: ExtendibleHashTable<PageKey, Frame, FastHasher>,
+    filename_pages: HashMap<PathBuf, HashSet<Page>>, //keeps track of the pages we have in the bufferpool for a given filename, NOTE: we need this for when files are deleted or replaced and the items in the buffer pool are no longer valid
+    capacity: Size, //in bytes, NOTE: frames can be smaller than a page (bloom filters, last page of an SST)
+    current_bytes: Size,
+    clock_handle: usize, //index into buckets array in our extendible hashtable, used for clock+LRU hybrid
+}
+
+#[allow(dead_code)] //TODO: remove when ready
+impl BufferPool {
+    pub fn new(initial_size: Size, capacity: Size) -> Self {
+        //NOTE: bucket capacity of 16 is arbitary, just need a number that isn't too small (so bucket splits aren't triggered more than they need to be)
+        Self {
+            frames: ExtendibleHashTable::with_capacity_buckets(16, initial_size, initial_size), //HashMap::with_capacity_and_hasher(capacity, hasher),
+            filename_pages: HashMap::new(),
+            capacity,
+            current_bytes: 0,
+            clock_handle: 0,
+        }
+    }
+
+    ///Number of frames in the buffer pool
+    pub fn len(&self) -> Size {
+        self.frames.len()
+    }
+
+    ///Number of bytes stored in the buffer pool
+    pub fn size_bytes(&self) -> Size {
+        self.current_bytes
+    }
+
+    ///Capacity in bytes
+    pub fn capacity(&self) -> Size {
+        self.capacity
+    }
+    pub fn set_capacity(&mut self, capacity: Size) {
+        self.capacity = capacity;
+        self.evict_until(capacity);
+    }
+
+    pub fn stats(&self) -> BufferPoolStats {
+        BufferPoolStats {
+            frames: self.len(),
+            bytes: self.size_bytes(),
+            capacity_bytes: self.capacity(),
+        }
+    }
+
+    pub fn get(&mut self, path: &Path, page_index: Page) -> Option<Vec<u8>> {
+        self.get_with_generation(path, page_index)
+            .map(|(bytes, _)| bytes)
+    }
+    ///Cached page along with the generation of the file it was read from
+    pub fn get_with_generation(
+        &mut self,
+        path: &Path,
+        page_index: Page,
+    ) -> Option<(Vec<u8>, Generation)> {
+        self.move_clock_handle();
+
+        let frame = self.frames.get(&(path.to_path_buf(), page_index))?;
+        Some((frame.bytes, frame.generation))
+    }
+    ///Cached page, its frame isn't evicted until the returned page is dropped
+    pub fn get_pinned(&mut self, path: &Path, page_index: Page) -> Option<PinnedPage> {
+        self.move_clock_handle();
+
+        let frame = self.frames.get(&(path.to_path_buf(), page_index))?;
+        Some(PinnedPage {
+            _pin: FramePin::new(&frame.pins),
+            bytes: frame.bytes,
+        })
+    }
+    ///Pins the frame of a cached page without copying it or counting an access, None if it isn't cached
+    pub fn pin(&self, path: &Path, page_index: Page) -> Option<FramePin> {
+        self.frames
+            .peek(&(path.to_path_buf(), page_index), |frame| {
+                FramePin::new(&frame.pins)
+            })
+    }
+
+    fn move_clock_handle(&mut self) {
+        let handle = &mut self.clock_handle;
+        *handle += 1;
+        *handle %= self.frames.num_buckets();
+        self.frames.set_accessed(*handle, false);
+    }
+
+    ///Evict the least recently used unpinned frame of the next bucket (according to the clock) that hasn't been
+    /// accessed, returns false if every frame is pinned
+    fn evict_next(&mut self) -> bool {
+        //two sweeps of the clock clear every bucket's access bit and then visit every bucket with it cleared
+        for _ in 0..=2 * self.frames.num_buckets() {
+            let handle = self.clock_handle;
+            let frames = &mut self.frames;
+
+            //if this bucket has not been accessed and we can remove its least recently used page, we're done
+            let evicted = if !frames.accessed(handle) {
+                frames.bucket_remove_lru_where(handle, |frame| !frame.is_pinned())
+            } else {
+                None
+            };
+            self.move_clock_handle();
+
+            if let Some(((path_evicted, page_index_evicted), frame)) = evicted {
+                self.current_bytes -= frame.bytes.len();
+                //remove page index from metadata
+                if let Some(filename_pages_of_evicted) = self.filename_pages.get_mut(&path_evicted)
+                {
+                    filename_pages_of_evicted.remove(&page_index_evicted);
+                    //if there is no metadata for this file left, remove its metadata set altogether
+                    if filename_pages_of_evicted.is_empty() {
+                        self.filename_pages.remove(&path_evicted);
+                    }
+                }
+                return true;
+            }
+        }
+        false
+    }
+
+    fn evict(&mut self, num_to_evict: Size) {
+        for _ in 0..num_to_evict.min(self.len()) {
+            if !self.evict_next() {
+                return;
+            }
+        }
+    }
+
+    ///Evict frames until at most <max_bytes> bytes are stored, or only pinned frames are left
+    fn evict_until(&mut self, max_bytes: Size) {
+        while self.current_bytes > max_bytes && self.len() > 0 {
+            if !self.evict_next() {
+                return;
+            }
+        }
+    }
+
+    ///Store a page read from the file as it is now
+    pub fn insert(&mut self, path: &Path, page_index: Page, page_data: &[u8]) {
+        self.insert_with_generation(path, page_index, page_data, generation::current(path));
+    }
+    ///Frames are evicted before the page is stored, so a page that fits is never evicted by its own insertion.
+    /// <generation> is the file's generation from before the page was read.
+    /// NOTE: pages larger than the entire capacity are not stored. If pinned frames take up the space the page needs,
+    /// the pool goes over capacity until they're unpinned and later insertions evict them
+    pub fn insert_with_generation(
+        &mut self,
+        path: &Path,
+        page_index: Page,
+        page_data: &[u8],
+        generation: Generation,
+    ) {
+        let key = (path.to_path_buf(), page_index);
+        //remove the old version first so its bytes don't count against the space we need
+        if let Some((.., old_frame)) = self.frames.remove(&key) {
+            self.current_bytes -= old_frame.bytes.len();
+        }
+        if page_data.len() > self.capacity {
+            self.forget_page(path, page_index);
+            return;
+        }
+        self.evict_until(self.capacity - page_data.len()); //evict enough, so that we have space for this page
+
+        self.frames
+            .put(key, Frame::new(page_data.to_vec(), generation));
+        self.current_bytes += page_data.len();
+
+        //Add page index to our metadata hashtable
+        match self.filename_pages.get_mut(path) {
+            Some(page_indexes) => {
+                page_indexes.insert(page_index);
+            }
+            None => {
+                let page_indexes = HashSet::from([page_index]);
+                self.filename_pages.insert(path.to_path_buf(), page_indexes);
+            }
+        };
+    }
+
+    ///Remove page from metadata (without touching frames)
+    fn forget_page(&mut self, path: &Path, page_index: Page) {
+        if let Some(page_indexes) = self.filename_pages.get_mut(path) {
+            page_indexes.remove(&page_index);
+            if page_indexes.is_empty() {
+                self.filename_pages.remove(path);
+            }
+        }
+    }
+
+    pub fn remove(&mut self, path: &Path) {
+        if let Some(page_indexes) = self.filename_pages.get(path) {
+            for page in page_indexes {
+                if let Some((.., frame)) = self.frames.remove(&(path.to_path_buf(), *page)) {
+                    self.current_bytes -= frame.bytes.len();
+                }
+            }
+        }
+        self.filename_pages.remove(path);
+    }
+
+    ///Remove the pages of every file under <directory>
+    pub fn remove_directory(&mut self, directory: &Path) {
+        let paths: Vec<PathBuf> = self
+            .filename_pages
+            .keys()
+            .filter(|path| path.starts_with(directory))
+            .cloned()
+            .collect();
+        for path in paths {
+            self.remove(&path);
+        }
+    }
+
+    ///Pages held for files under <directory>, in no particular order
+    pub fn resident_pages(&self, directory: &Path) -> Vec<PageKey> {
+        self.filename_pages
+            .iter()
+            .filter(|(path, _)| path.starts_with(directory))
+            .flat_map(|(path, pages)| pages.iter().map(|page| (path.clone(), *page)))
+            .collect()
+    }
+
+    ///Update keys in bufferpool to reflect new file name.
+    pub fn rename(&mut self, old_path: &Path, new_path: &Path) {
+        self.remove(new_path); //anything cached for the path being replaced is stale
+        if let Some(page_indexes) = self.filename_pages.remove(old_path) {
+            for page in page_indexes.iter() {
+                if let Some((.., frame)) = self.frames.remove(&(old_path.to_path_buf(), *page)) {
+                    self.frames.put((new_path.to_path_buf(), *page), frame);
+                }
+            }
+            self.filename_pages
+                .insert(new_path.to_path_buf(), page_indexes);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::util::system_info::page_size;
+
+    use super::*;
+    #[test]
+    fn test_insert() {
+        let mut b = BufferPool::new(1, 15);
+        let page_data = vec![0, 0, 1, 0, 1];
+        let path = Path::new("database/0/0.sst");
+        let page_index = 0;
+        b.insert(path, page_index, &page_data);
+
+        let result = b.get(path, page_index);
+        assert_eq!(result, Some(page_data));
+    }
+
+    #[test]
+    fn test_insert_replacement() {
+        let mut b = BufferPool::new(1, 15);
+        let page_data = vec![0, 0, 1, 0, 1];
+        let path = Path::new("database/0/0.sst");
+        let page_index = 0;
+        b.insert(path, page_index, &page_data);
+
+        let result = b.get(path, page_index);
+        assert_eq!(result, Some(page_data));
+
+        let replacement_data = vec![1, 1, 1, 1, 1];
+        b.insert(path, page_index, &replacement_data);
+
+        assert_eq!(b.len(), 1); //replacement should not change length
+
+        let result = b.get(path, page_index);
+        assert_eq!(result, Some(replacement_data));
+    }
+
+    #[test]
+    fn test_eviction() {
+        let mut b = BufferPool::new(1, 15);
+        let path = Path::new("database/0/0.sst");
+        b.insert(path, 0, &[0, 0, 0, 0, 0]);
+        b.insert(path, 1, &[0, 0, 0, 0, 1]);
+        b.insert(path, 2, &[0, 0, 0, 1, 0]);
+        b.insert(path, 3, &[0, 0, 0, 1, 1]);
+
+        assert_eq!(b.len(), 3); //Make sure that we don't go over capacity
+
+        //check if new page is added and oldest is evicted
+        assert_eq!(b.get(path, 3), Some(vec![0, 0, 0, 1, 1]));
+        assert_eq!(b.get(path, 0), None);
+        //check if evicted has it's metadata removed
+        assert_eq!(b.filename_pages.get(path).unwrap().get(&0), None);
+        assert_eq!(b.filename_pages.get(path).unwrap().len(), 3);
+
+        b.insert(path, 4, &[0, 0, 1, 0, 0]);
+
+        //check if new page is added and oldest is evicted
+        assert_eq!(b.get(path, 4), Some(vec![0, 0, 1, 0, 0]));
+        assert_eq!(b.get(path, 1), None);
+        //check if evicted has it's metadata removed
+        assert_eq!(b.filename_pages.get(path).unwrap().get(&1), None);
+        assert_eq!(b.filename_pages.get(path).unwrap().len(), 3);
+
+        //Our oldest page should be 2 at this point, when we access it, 3 should be our oldest and get evicted on next insert
+        b.get(path, 2);
+
+        b.insert(path, 5, &[0, 0, 1, 0, 1]);
+
+        //check if new page is added and oldest is evicted
+        assert_eq!(b.get(path, 5), Some(vec![0, 0, 1, 0, 1]));
+        assert_eq!(b.get(path, 3), None);
+        //check if evicted has it's metadata removed
+        assert_eq!(b.filename_pages.get(path).unwrap().get(&3), None);
+        assert_eq!(b.filename_pages.get(path).unwrap().len(), 3);
+
+        //now evict all remaining pages and check if metadata is empty
+        b.evict(b.len());
+        assert_eq!(b.filename_pages.len(), 0);
+    }
+
+    #[test]
+    fn test_pinned_frames() {
+        let mut b = BufferPool::new(1, 15);
+        let path = Path::new("database/0/0.sst");
+        b.insert(path, 0, &[0; 5]);
+        b.insert(path, 1, &[1; 5]);
+        b.insert(path, 2, &[2; 5]);
+
+        let page = b.get_pinned(path, 0).unwrap();
+        assert_eq!(&page[..], &[0; 5]);
+        let pin = b.pin(path, 1).unwrap();
+        assert!(b.pin(path, 7).is_none());
+
+        //only the unpinned frame can make room
+        b.insert(path, 3, &[3; 5]);
+        assert_eq!(b.get(path, 2), None);
+        b.insert(path, 4, &[4; 5]);
+        assert_eq!(b.get(path, 3), None);
+        assert_eq!(b.get(path, 0), Some(vec![0; 5]));
+        assert_eq!(b.get(path, 1), Some(vec![1; 5]));
+
+        //with every frame pinned the pool goes over capacity instead of evicting
+        let another_pin = b.pin(path, 4).unwrap();
+        b.insert(path, 5, &[5; 5]);
+        assert_eq!(b.len(), 4);
+        assert_eq!(b.size_bytes(), 20);
+        b.evict(b.len());
+        assert_eq!(b.len(), 3); //only the unpinned frame went
+
+        //dropped pins make frames evictable again
+        drop((page, pin, another_pin));
+        b.insert(path, 6, &[6; 5]);
+        assert!(b.size_bytes() <= b.capacity());
+        b.evict(b.len());
+        assert_eq!(b.len(), 0);
+        assert_eq!(b.filename_pages.len(), 0);
+    }
+
+    #[test]
+    fn test_remove() {
+        let mut b = BufferPool::new(1, 15);
+        let path = Path::new("database/0/0.sst");
+        let path2 = Path::new("database/0/1.sst");
+        b.insert(path, 0, &[0, 0, 0, 0, 0]);
+        b.insert(path, 1, &[0, 0, 0, 0, 1]);
+        b.insert(path2, 0, &[0, 0, 0, 1, 0]);
+
+        //Should remove all pages with path, but nothing else
+        b.remove(path);
+        assert_eq!(b.get(path, 0), None);
+        assert_eq!(b.get(path, 1), None);
+        assert_eq!(b.get(path2, 0), Some(vec![0, 0, 0, 1, 0]));
+    }
+
+    #[test]
+    fn test_rename() {
+        let mut b = BufferPool::new(1, 15);
+        let path = Path::new("database/0/0.sst");
+        let path2 = Path::new("database/0/1.sst");
+        b.insert(path, 0, &[0, 0, 0, 0, 0]);
+        b.insert(path, 1, &[0, 0, 0, 0, 1]);
+        b.insert(path2, 0, &[0, 0, 0, 1, 0]);
+
+        let new_path = Path::new("database/12/0.sst");
+
+        //Should remove all pages with path, but nothing else
+        b.rename(path, new_path);
+        assert_eq!(b.get(path, 0), None);
+        assert_eq!(b.get(path, 1), None);
+        assert_eq!(b.get(new_path, 0), Some(vec![0, 0, 0, 0, 0]));
+        assert_eq!(b.get(new_path, 1), Some(vec![0, 0, 0, 0, 1]));
+        assert_eq!(b.get(path2, 0), Some(vec![0, 0, 0, 1, 0]));
+
+        assert_eq!(b.len(), 3)
+    }
+
+    #[test]
+    fn test_resident_pages() {
+        let mut b = BufferPool::new(1, 15);
+        let path = Path::new("database/0/0.sst");
+        let other_database = Path::new("other/0/0.sst");
+        b.insert(path, 0, &[0, 0, 0, 0, 0]);
+        b.insert(path, 1, &[0, 0, 0, 0, 1]);
+        b.insert(other_database, 0, &[0, 0, 0, 1, 0]);
+
+        let mut pages = b.resident_pages(Path::new("database"));
+        pages.sort();
+        assert_eq!(
+            pages,
+            vec![(path.to_path_buf(), 0), (path.to_path_buf(), 1)]
+        );
+        b.set_capacity(5);
+        assert_eq!(b.resident_pages(Path::new("database")), vec![]); //evicted pages aren't listed
+    }
+
+    #[test]
+    fn test_set_capacity() {
+        let mut b = BufferPool::new(1, 15);
+        let path = Path::new("database/0/0.sst");
+        b.insert(path, 0, &[0, 0, 0, 0, 0]);
+        b.insert(path, 1, &[0, 0, 0, 0, 1]);
+        b.insert(path, 2, &[0, 0, 0, 1, 0]);
+        b.insert(path, 3, &[0, 0, 0, 1, 1]);
+
+        assert_eq!(b.len(), 3);
+        assert!(b.size_bytes() <= b.capacity());
+
+        b.set_capacity(5);
+        assert_eq!(b.get(path, 0), None);
+        assert_eq!(b.get(path, 1), None);
+        assert_eq!(b.get(path, 2), None);
+        assert_eq!(b.get(path, 3), Some(vec![0, 0, 0, 1, 1]));
+        assert_eq!(b.len(), 1);
+
+        assert!(b.size_bytes() <= b.capacity());
+    }
+
+    #[test]
+    fn test_large() {
+        let mut b = BufferPool::new(4, 10 * page_size());
+        let path = Path::new("database/0/0.sst");
+
+        let page = |_index| {
+            let page_data: Vec<u8> = vec![0; page_size()];
+            page_data
+        };
+
+        for i in 0..10000 {
+            b.insert(path, i, &page(i));
+            assert!(
+                b.size_bytes() <= b.capacity(),
+                "Went over capacity at insertion {}",
+                i
+            );
+        }
+
+        assert_eq!(b.len(), 10);
+        assert!(b.size_bytes() <= b.capacity());
+    }
+
+    #[test]
+    fn test_mixed_frame_sizes() {
+        let capacity = 4 * page_size();
+        let mut b = BufferPool::new(4, capacity);
+        let sst_path = Path::new("database/0/0.sst");
+        let bloom_path = Path::new("database/0/0.bloom");
+
+        //full SST pages mixed with small bloom filter pages, count by bytes not frames
+        for i in 0..100 {
+            b.insert(sst_path, i, &vec![0; page_size()]);
+            b.insert(bloom_path, i, &[1; 64]);
+            assert!(
+                b.size_bytes() <= b.capacity(),
+                "Went over capacity at insertion {}",
+                i
+            );
+        }
+        assert!(b.len() > 4); //small frames let us hold more than 4 frames
+        assert_eq!(
+            b.stats(),
+            BufferPoolStats {
+                frames: b.len(),
+                bytes: b.size_bytes(),
+                capacity_bytes: capacity
+            }
+        );
+
+        //replacing a frame should only count its new size
+        let bytes_before = b.size_bytes();
+        b.insert(bloom_path, 99, &[2; 32]);
+        assert_eq!(b.size_bytes(), bytes_before - 32);
+
+        b.remove(bloom_path);
+        b.remove(sst_path);
+        assert_eq!(b.len(), 0);
+        assert_eq!(b.size_bytes(), 0);
+    }
+
+    #[test]
+    fn test_single_frame_capacity() {
+        let mut b = BufferPool::new(1, page_size());
+        let path = Path::new("database/0/0.sst");
+        let page = |index: Page| vec![index as u8; page_size()];
+
+        //every insert evicts the previous frame, even if it was just accessed, and keeps the new one
+        for i in 0..100 {
+            b.insert(path, i, &page(i));
+            assert_eq!(b.get(path, i), Some(page(i)));
+            assert_eq!(b.get(path, i), Some(page(i)));
+            assert_eq!(b.len(), 1);
+            assert_eq!(b.filename_pages.get(path).unwrap().len(), 1);
+        }
+        assert_eq!(b.get(path, 98), None);
+        assert_eq!(b.size_bytes(), page_size());
+    }
+
+    #[test]
+    fn test_page_larger_than_capacity() {
+        let mut b = BufferPool::new(1, 10);
+        let path = Path::new("database/0/0.sst");
+        b.insert(path, 0, &[0; 5]);
+        b.insert(path, 1, &[0; 11]); //too large, should not be stored or evict anything
+
+        assert_eq!(b.get(path, 1), None);
+        assert_eq!(b.get(path, 0), Some(vec![0; 5]));
+        assert_eq!(b.size_bytes(), 5);
+
+        //replacing a cached page with one that is too large drops the old version
+        b.insert(path, 0, &[1; 11]);
+        assert_eq!(b.get(path, 0), None);
+        assert_eq!(b.size_bytes(), 0);
+        assert!(!b.filename_pages.contains_key(path));
+    }
+
+    #[test]
+    fn test_remove_directory() {
+        let mut b = BufferPool::new(1, 100);
+        let paths = [
+            Path::new("database/0/0.sst"),
+            Path::new("database/1/0.sst"),
+            Path::new("database2/0/0.sst"), //shares a prefix, but not the directory
+        ];
+        for path in paths {
+            b.insert(path, 0, &[0; 5]);
+        }
+
+        b.remove_directory(Path::new("database"));
+        assert_eq!(b.get(paths[0], 0), None);
+        assert_eq!(b.get(paths[1], 0), None);
+        assert_eq!(b.get(paths[2], 0), Some(vec![0; 5]));
+        assert_eq!(b.size_bytes(), 5);
+        assert_eq!(b.filename_pages.len(), 1);
+    }
+}
@@ -0,0 +1,70 @@
+use std::path::{Path, PathBuf};
+
+use super::{BufferPoolStats, FramePin, PinnedPage};
+use crate::{
+    file_io::generation::Generation,
+    util::types::{Page, Size},
+};
+
+///Stand in for builds without the buffer-pool feature, never holds any pages so every read goes to storage
+#[derive(Debug)]
+pub struct BufferPool {
+    capacity: Size,
+}
+
+#[allow(dead_code)]
+impl BufferPool {
+    pub fn new(_initial_size: Size, capacity: Size) -> Self {
+        Self { capacity }
+    }
+    pub fn len(&self) -> Size {
+        0
+    }
+    pub fn size_bytes(&self) -> Size {
+        0
+    }
+    pub fn capacity(&self) -> Size {
+        self.capacity
+    }
+    pub fn set_capacity(&mut self, capacity: Size) {
+        self.capacity = capacity;
+    }
+    pub fn stats(&self) -> BufferPoolStats {
+        BufferPoolStats {
+            frames: 0,
+            bytes: 0,
+            capacity_bytes: self.capacity,
+        }
+    }
+    pub fn get(&mut self, _path: &Path, _page_index: Page) -> Option<Vec<u8>> {
+        None
+    }
+    pub fn get_with_generation(
+        &mut self,
+        _path: &Path,
+        _page_index: Page,
+    ) -> Option<(Vec<u8>, Generation)> {
+        None
+    }
+    pub fn get_pinned(&mut self, _path: &Path, _page_index: Page) -> Option<PinnedPage> {
+        None
+    }
+    pub fn pin(&self, _path: &Path, _page_index: Page) -> Option<FramePin> {
+        None
+    }
+    pub fn insert(&mut self, _path: &Path, _page_index: Page, _page_data: &[u8]) {}
+    pub fn insert_with_generation(
+        &mut self,
+        _path: &Path,
+        _page_index: Page,
+        _page_data: &[u8],
+        _generation: Generation,
+    ) {
+    }
+    pub fn remove(&mut self, _path: &Path) {}
+    pub fn remove_directory(&mut self, _directory: &Path) {}
+    pub fn resident_pages(&self, _directory: &Path) -> Vec<(PathBuf, Page)> {
+        Vec::new()
+    }
+    pub fn rename(&mut self, _old_path: &Path, _new_path: &Path) {}
+}
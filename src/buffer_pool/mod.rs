@@ -0,0 +1,60 @@
+#[cfg(feature = "buffer-pool")]
+mod clock_pool;
+#[cfg(not(feature = "buffer-pool"))]
+mod disabled;
+
+use std::{cell::Cell, ops::Deref, rc::Rc};
+
+#[cfg(feature = "buffer-pool")]
+pub use clock_pool::BufferPool;
+#[cfg(not(feature = "buffer-pool"))]
+pub use disabled::BufferPool;
+
+use crate::util::types::Size;
+
+///Snapshot of buffer pool usage
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BufferPoolStats {
+    pub frames: Size,
+    pub bytes: Size,
+    pub capacity_bytes: Size,
+}
+
+///Keeps a frame from being evicted until dropped, see BufferPool::pin. Operations that fetch several pages hold these
+/// so the fetches later in the operation can't evict the pages it fetched earlier
+#[cfg_attr(not(feature = "buffer-pool"), allow(dead_code))]
+#[derive(Debug)]
+pub struct FramePin {
+    pins: Rc<Cell<Size>>, //shared with the frame
+}
+
+#[cfg_attr(not(feature = "buffer-pool"), allow(dead_code))]
+impl FramePin {
+    fn new(pins: &Rc<Cell<Size>>) -> Self {
+        pins.set(pins.get() + 1);
+        Self {
+            pins: Rc::clone(pins),
+        }
+    }
+}
+
+impl Drop for FramePin {
+    fn drop(&mut self) {
+        self.pins.set(self.pins.get() - 1);
+    }
+}
+
+///Cached page whose frame can't be evicted until this is dropped, see BufferPool::get_pinned
+#[cfg_attr(not(feature = "buffer-pool"), allow(dead_code))]
+#[derive(Debug)]
+pub struct PinnedPage {
+    bytes: Vec<u8>,
+    _pin: FramePin,
+}
+
+impl Deref for PinnedPage {
+    type Target = [u8];
+    fn deref(&self) -> &[u8] {
+        &self.bytes
+    }
+}
@@ -0,0 +1,292 @@
+use std::{
+    io,
+    path::PathBuf,
+    sync::{
+        mpsc::{self, Receiver, Sender},
+        Arc, Mutex, MutexGuard,
+    },
+    thread::{self, JoinHandle},
+};
+
+use crate::util::{
+    types::{
+        BloomLevels, CompactionPolicy, DatabaseName, KeyCodec, Level, MergeOperator, Sequence,
+        Size, SstImplementation, Timestamp,
+    },
+    yield_hook::YieldHook,
+};
+
+use super::{
+    cascade::{self, FileChange, Levels, RunLists},
+    garbage::KeySketch,
+    merge, size_ratio,
+    strategy::{LevelRuns, StrategySettings},
+};
+
+///Database settings needed to compact, captured when a job is queued so that config changes apply to the next job
+#[derive(Debug, Clone)]
+pub struct CompactionSettings {
+    pub db_name: DatabaseName, //along with its storage
+    pub compaction_policy: CompactionPolicy,
+    pub sst_size_ratio: Size,
+    pub memtable_capacity: Size,
+    #[cfg_attr(not(feature = "btree"), allow(dead_code))]
+    pub sst_implementation: SstImplementation,
+    pub key_codec: KeyCodec,
+    pub bloom_levels: BloomLevels,
+    pub bloom_filter_bits_per_entry: Size,
+    pub enable_fence_index: bool,
+    pub memory_budget: Size, //bytes a merge's buffers may hold, 0 for no limit
+    pub merge_operator: MergeOperator,
+    pub now: Timestamp, //entries that expired by the time the job was queued are merged away
+}
+
+///Level metadata shared between the database and the compaction thread.
+/// Files of the runs listed in entry_counts are only renamed or deleted while this is locked
+#[derive(Debug, Default)]
+pub struct LevelState {
+    pub entry_counts: Vec<Vec<Size>>, //same layout as the database metadata
+    pub tombstone_counts: Vec<Vec<Size>>,
+    pub expiry_counts: Vec<Vec<Size>>,
+    pub page_sizes: Vec<Vec<Size>>,
+    pub max_sequences: Vec<Vec<Sequence>>,
+    pub operand_counts: Vec<Vec<Size>>,
+    pub key_sketches: Vec<Vec<KeySketch>>,
+    pub entries_dropped: Size, //entries dropped by compactions since the state was last applied
+    pub level_bytes: Vec<Vec<Size>>, //size of each level after each compaction since the state was last applied
+    pub stale_paths: Vec<PathBuf>, //files renamed or deleted by the compactor, any cached pages for these are invalid
+    pub error: Option<String>, //first error hit by the compactor, no more jobs are run after this
+}
+
+impl LevelState {
+    pub fn run_lists(&mut self) -> RunLists<'_> {
+        RunLists {
+            entry_counts: &mut self.entry_counts,
+            tombstone_counts: &mut self.tombstone_counts,
+            expiry_counts: &mut self.expiry_counts,
+            page_sizes: &mut self.page_sizes,
+            max_sequences: &mut self.max_sequences,
+            operand_counts: &mut self.operand_counts,
+            key_sketches: &mut self.key_sketches,
+        }
+    }
+}
+
+#[derive(Debug)]
+enum Job {
+    Compact(CompactionSettings),
+    Drain(Sender<()>), //acknowledged once every job queued before it is done
+}
+
+///Runs the compactions of levels >= 1 on a dedicated thread. New level zero runs are appended by the
+/// database, the compactor moves them up and merges levels without blocking puts or reads of other levels
+#[derive(Debug)]
+pub struct BackgroundCompactor {
+    levels: Arc<Mutex<LevelState>>,
+    sender: Option<Sender<Job>>,
+    handle: Option<JoinHandle<()>>,
+}
+
+impl BackgroundCompactor {
+    pub fn new(
+        entry_counts: Vec<Vec<Size>>,
+        tombstone_counts: Vec<Vec<Size>>,
+        expiry_counts: Vec<Vec<Size>>,
+        page_sizes: Vec<Vec<Size>>,
+        max_sequences: Vec<Vec<Sequence>>,
+        operand_counts: Vec<Vec<Size>>,
+        key_sketches: Vec<Vec<KeySketch>>,
+    ) -> Self {
+        let levels = Arc::new(Mutex::new(LevelState {
+            entry_counts,
+            tombstone_counts,
+            expiry_counts,
+            page_sizes,
+            max_sequences,
+            operand_counts,
+            key_sketches,
+            ..Default::default()
+        }));
+        let (sender, receiver) = mpsc::channel();
+        let worker_levels = Arc::clone(&levels);
+        let handle = thread::Builder::new()
+            .name(String::from("background-compactor"))
+            .spawn(move || Self::run(receiver, worker_levels))
+            .expect("Failed to spawn background compaction thread");
+
+        Self {
+            levels,
+            sender: Some(sender),
+            handle: Some(handle),
+        }
+    }
+    pub fn levels(&self) -> Arc<Mutex<LevelState>> {
+        Arc::clone(&self.levels)
+    }
+    ///Queue compaction of every level, starting by moving level zero runs up
+    pub fn enqueue(&self, settings: CompactionSettings) {
+        self.send(Job::Compact(settings));
+    }
+    ///Block until every queued job is finished
+    pub fn drain(&self) {
+        let (ack_sender, ack_receiver) = mpsc::channel();
+        self.send(Job::Drain(ack_sender));
+        ack_receiver
+            .recv()
+            .expect("Background compaction thread stopped unexpectedly");
+    }
+    ///Finish queued jobs, stop the thread, and hand back the final level state
+    pub fn stop(mut self) -> LevelState {
+        self.drain();
+        self.shutdown();
+        std::mem::take(&mut *lock(&self.levels))
+    }
+
+    fn send(&self, job: Job) {
+        self.sender
+            .as_ref()
+            .expect("Background compactor is stopped")
+            .send(job)
+            .expect("Background compaction thread stopped unexpectedly");
+    }
+    fn shutdown(&mut self) {
+        self.sender = None; //closing the channel ends the worker loop
+        if let Some(handle) = self.handle.take() {
+            if handle.join().is_err() && !thread::panicking() {
+                panic!("Background compaction thread panicked");
+            }
+        }
+    }
+    fn run(receiver: Receiver<Job>, levels: Arc<Mutex<LevelState>>) {
+        for job in receiver {
+            match job {
+                Job::Compact(settings) => {
+                    if lock(&levels).error.is_some() {
+                        continue;
+                    }
+                    if let Err(why) = compact_levels(&settings, &levels) {
+                        lock(&levels).error = Some(why.to_string());
+                    }
+                }
+                Job::Drain(ack) => {
+                    let _ = ack.send(()); //nobody waiting is fine
+                }
+            }
+        }
+    }
+}
+
+impl Drop for BackgroundCompactor {
+    fn drop(&mut self) {
+        self.shutdown();
+    }
+}
+
+///What a queued job does: move level zero runs up and compact the levels that need it, holding the lock of <levels>
+/// only while files are renamed or deleted
+pub fn compact_levels(settings: &CompactionSettings, levels: &Mutex<LevelState>) -> io::Result<()> {
+    cascade::move_runs(&mut Compaction { settings, levels }, 0)
+}
+
+fn lock(levels: &Mutex<LevelState>) -> MutexGuard<'_, LevelState> {
+    levels
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+///A single compaction job. It takes the same steps as the database's own compactions (see cascade) but only holds
+/// the level lock while renaming or deleting files, merges happen against the old runs which stay readable
+struct Compaction<'a> {
+    settings: &'a CompactionSettings,
+    levels: &'a Mutex<LevelState>,
+}
+
+impl Compaction<'_> {
+    fn lock(&self) -> MutexGuard<'_, LevelState> {
+        lock(self.levels)
+    }
+}
+
+///Cached pages of the files a compaction renamed or removed are stale, the database drops them when it next syncs
+fn record_stale(stale_paths: &mut Vec<PathBuf>, change: FileChange) {
+    match change {
+        FileChange::Renamed { from, to } => {
+            stale_paths.extend([to.to_path_buf(), from.to_path_buf()])
+        }
+        FileChange::Removed(path) => stale_paths.push(path.to_path_buf()),
+    }
+}
+
+impl Levels for Compaction<'_> {
+    fn compaction_policy(&self) -> CompactionPolicy {
+        self.settings.compaction_policy
+    }
+    fn strategy_settings(&self) -> StrategySettings {
+        StrategySettings {
+            size_ratio: self.settings.sst_size_ratio,
+            memtable_capacity: self.settings.memtable_capacity,
+        }
+    }
+    fn with_level_runs<T>(&self, level: Level, f: impl FnOnce(&LevelRuns) -> T) -> T {
+        let state = self.lock();
+        f(&LevelRuns {
+            level,
+            is_last_level: level == state.entry_counts.len() - 1,
+            entry_counts: &state.entry_counts[level],
+            tombstone_counts: &state.tombstone_counts[level],
+        })
+    }
+    fn move_level_runs(&mut self, level: Level) -> io::Result<bool> {
+        let mut state = self.lock();
+        let num_runs = state.entry_counts.get(level).map_or(0, Vec::len);
+        if num_runs == 0 {
+            return Ok(false); //Nothing to move to the next level
+        }
+        let num_runs_in_next_level = state.entry_counts.get(level + 1).map_or(0, Vec::len);
+        let stale_paths = &mut state.stale_paths;
+        cascade::move_run_files(
+            &self.settings.db_name,
+            level,
+            num_runs,
+            num_runs_in_next_level,
+            self.settings.bloom_levels,
+            &mut |change| record_stale(stale_paths, change),
+        )?;
+        state.run_lists().move_runs(level);
+        Ok(true)
+    }
+    fn compact_level_runs(&mut self, level: Level, discard_tombstones: bool) -> io::Result<()> {
+        let settings = self.settings;
+        let entry_counts = self.lock().entry_counts[level].clone(); //only this thread changes levels >= 1
+        if entry_counts.len() < 2 {
+            return Ok(()); //nothing to compact
+        }
+
+        //the database's yield hook is only called on its own thread
+        let (stats, key_sketch) = merge::build_merged_run(
+            settings,
+            level,
+            &entry_counts,
+            discard_tombstones,
+            &mut YieldHook::default(),
+        )?;
+
+        //install the new run
+        let mut state = self.lock();
+        let stale_paths = &mut state.stale_paths;
+        cascade::install_merged_run_files(
+            &settings.db_name,
+            level,
+            entry_counts.len(),
+            stats.entries_written,
+            &mut |change| record_stale(stale_paths, change),
+        )?;
+        state.entries_dropped += stats.entries_dropped;
+        state
+            .run_lists()
+            .install_merged_run(level, &stats, key_sketch);
+        let level_bytes = size_ratio::level_bytes(&state.entry_counts);
+        state.level_bytes.push(level_bytes);
+        Ok(())
+    }
+}
@@ -0,0 +1,287 @@
+//!What a flush sets off in the levels under level 0: the runs of a level move after those of the next one, the next
+//! level compacts if its strategy says so, and runs move on from wherever the strategy points next. The database,
+//! its background compactor and CompactionPlan all go through move_runs here and differ only in how they carry out
+//! each step (see Levels), so they always take the same steps.
+//! The file and metadata changes of a move or an installed merge are shared here too
+
+use std::{
+    io,
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    sst::MergeStats,
+    util::{
+        filename::{self, RunPaths},
+        system_info,
+        types::{BloomLevels, CompactionPolicy, DatabaseName, Level, RunAddress, Sequence, Size},
+    },
+};
+
+use super::{
+    garbage::KeySketch,
+    merge::run_paths,
+    plan,
+    strategy::{self, CompactionDecision, LevelRuns, StrategySettings},
+};
+
+///Levels whose runs are moved and compacted, see move_runs
+pub trait Levels {
+    fn compaction_policy(&self) -> CompactionPolicy;
+    fn strategy_settings(&self) -> StrategySettings;
+    ///Calls <f> with the runs of <level> as compaction strategies see them
+    fn with_level_runs<T>(&self, level: Level, f: impl FnOnce(&LevelRuns) -> T) -> T;
+    ///Move every run of <level> after the runs of the next level, adding it if there isn't one.
+    /// Returns false if <level> has no runs, nothing is moved then
+    fn move_level_runs(&mut self, level: Level) -> io::Result<bool>;
+    ///Merge every run of <level> into one
+    fn compact_level_runs(&mut self, level: Level, discard_tombstones: bool) -> io::Result<()>;
+}
+
+///Move the runs of <level> into the next level, then compact and move runs again for as long as the compaction
+/// strategy calls for it. Stops at the first error, the steps taken before it stay done
+pub fn move_runs(levels: &mut impl Levels, level: Level) -> io::Result<()> {
+    let strategy = strategy::for_policy(levels.compaction_policy());
+    let settings = levels.strategy_settings();
+    let mut level = level;
+    while levels.move_level_runs(level)? {
+        let next_level = level + 1;
+        let (num_runs, decision) = levels.with_level_runs(next_level, |runs| {
+            (runs.num_runs(), strategy.should_compact(runs, &settings))
+        });
+        let CompactionDecision::Compact { discard_tombstones } = decision else {
+            break;
+        };
+        levels.compact_level_runs(next_level, discard_tombstones)?;
+        let move_runs = levels.with_level_runs(next_level, |runs| {
+            strategy.after_compact(runs, num_runs, &settings)
+        });
+        match move_runs {
+            Some(move_runs) => level = move_runs.level,
+            None => break,
+        }
+    }
+    Ok(())
+}
+
+///Per-run metadata of every level (same layout as the database metadata), borrowed from the database's metadata or
+/// the background compactor's level state
+pub struct RunLists<'a> {
+    pub entry_counts: &'a mut Vec<Vec<Size>>,
+    pub tombstone_counts: &'a mut Vec<Vec<Size>>,
+    pub expiry_counts: &'a mut Vec<Vec<Size>>,
+    pub page_sizes: &'a mut Vec<Vec<Size>>,
+    pub max_sequences: &'a mut Vec<Vec<Sequence>>,
+    pub operand_counts: &'a mut Vec<Vec<Size>>,
+    pub key_sketches: &'a mut Vec<Vec<KeySketch>>,
+}
+
+///Append the runs of <level> to those of the next level
+fn append_to_next_level<T>(lists: &mut [Vec<T>], level: Level) {
+    let moved = std::mem::take(&mut lists[level]);
+    lists[level + 1].extend(moved);
+}
+
+impl RunLists<'_> {
+    ///Runs of <level> after the move_run_files of its runs, the next level is added if there isn't one
+    pub fn move_runs(&mut self, level: Level) {
+        if self.entry_counts.len() == level + 1 {
+            self.entry_counts.push(vec![]);
+            self.tombstone_counts.push(vec![]);
+            self.expiry_counts.push(vec![]);
+            self.page_sizes.push(vec![]);
+            self.max_sequences.push(vec![]);
+            self.operand_counts.push(vec![]);
+            self.key_sketches.push(vec![]);
+        }
+        append_to_next_level(self.entry_counts, level);
+        append_to_next_level(self.tombstone_counts, level);
+        append_to_next_level(self.expiry_counts, level);
+        append_to_next_level(self.page_sizes, level);
+        append_to_next_level(self.max_sequences, level);
+        append_to_next_level(self.operand_counts, level);
+        append_to_next_level(self.key_sketches, level);
+    }
+    ///Runs of <level> after the install_merged_run_files of a merge of every run of it, see MergeStats
+    pub fn install_merged_run(&mut self, level: Level, stats: &MergeStats, key_sketch: KeySketch) {
+        if stats.entries_written == 0 {
+            self.entry_counts[level].clear();
+            self.tombstone_counts[level].clear();
+            self.expiry_counts[level].clear();
+            self.page_sizes[level].clear();
+            self.max_sequences[level].clear();
+            self.operand_counts[level].clear();
+            self.key_sketches[level].clear();
+            return;
+        }
+        self.entry_counts[level] = vec![stats.entries_written];
+        self.tombstone_counts[level] = vec![stats.tombstones_written];
+        self.expiry_counts[level] = vec![stats.expiries_written];
+        self.page_sizes[level] = vec![system_info::page_size()]; //merged runs are written with this machine's layout
+
+        //the merged run holds the newest write of every input run
+        let max_sequence = self.max_sequences[level].iter().max().copied();
+        self.max_sequences[level] = vec![max_sequence.unwrap_or(0)];
+        self.operand_counts[level] = vec![stats.operands_written];
+        self.key_sketches[level] = vec![key_sketch];
+    }
+}
+
+///A file of a run that was renamed or removed, any pages cached for it are out of date
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FileChange<'a> {
+    Renamed { from: &'a Path, to: &'a Path },
+    Removed(&'a Path),
+}
+
+///Rename the files of the <num_runs> runs of <level> into the next level (creating its directory if needed), after its
+/// <num_runs_in_next_level> runs. Bloom filters are removed instead if the next level doesn't keep them.
+/// <on_change> is called before each file is renamed or removed
+pub fn move_run_files(
+    db_name: &DatabaseName,
+    level: Level,
+    num_runs: Size,
+    num_runs_in_next_level: Size,
+    bloom_levels: BloomLevels,
+    on_change: &mut dyn FnMut(FileChange),
+) -> io::Result<()> {
+    let next_level = level + 1;
+    if plan::runs_after_move(num_runs, num_runs_in_next_level).is_none() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!(
+                "moving {num_runs} runs into level {next_level} would leave it with too many runs"
+            ),
+        ));
+    }
+    let directory = filename::lsm_level_directory(&(db_name, next_level));
+    if !db_name.storage().path_exists(&directory) {
+        db_name.storage().create_dir_all(&directory)?;
+    }
+    let drop_bloom_filters = !bloom_levels.includes(next_level);
+    for run in 0..num_runs {
+        //runs of this level are younger than every run already in the next level, so they go after them
+        // (in their current order) to keep higher run numbers younger
+        let new_run = run + num_runs_in_next_level;
+        let old_run_address = &(db_name, level, run);
+        let new_run_address = &(db_name, next_level, new_run);
+        let (sst_path, bloom_path) = (old_run_address.path_sst(), old_run_address.path_bloom());
+        if db_name.storage().path_exists(&new_run_address.path_sst()) {
+            return Err(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                format!("{} already exists", new_run_address.path_sst().display()),
+            ));
+        }
+        for (old_path, new_path) in run_paths(old_run_address)
+            .into_iter()
+            .zip(run_paths(new_run_address))
+        {
+            //every run has an SST file, the others are optional
+            if old_path != sst_path && !db_name.storage().path_exists(&old_path) {
+                continue;
+            }
+            if drop_bloom_filters && old_path == bloom_path {
+                on_change(FileChange::Removed(&old_path));
+                db_name.storage().remove_file(&old_path)?;
+            } else {
+                on_change(FileChange::Renamed {
+                    from: &old_path,
+                    to: &new_path,
+                });
+                db_name.storage().rename(&old_path, &new_path)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+///Replace the files of the <num_input_runs> runs of <level> with those of the run they were merged into, built in the
+/// level's staging run (see merge::staging_run), which holds <entries_written> entries.
+/// The merged run is renamed into place before any input run is removed: the first input run steps aside for it and
+/// is put back if the merged run can't be renamed in, so an error leaves the level's runs as they were (the staging run
+/// is removed, like after a failed merge). Once the merged run is in place the input runs are removed as far as they
+/// can be, what's left of them is past the level's only run.
+/// <on_change> is called before each file is renamed or removed
+pub fn install_merged_run_files(
+    db_name: &DatabaseName,
+    level: Level,
+    num_input_runs: Size,
+    entries_written: Size,
+    on_change: &mut dyn FnMut(FileChange),
+) -> io::Result<()> {
+    let staging_address = &(db_name, level, num_input_runs);
+    let first_address = &(db_name, level, 0);
+    //past the staging run, no run of the level is there
+    let retired_address = &(db_name, level, num_input_runs + 1);
+    if entries_written == 0 {
+        db_name.storage().remove_file(&staging_address.path_sst())?;
+    }
+    let installed =
+        rename_run_files(db_name, first_address, retired_address, on_change).and_then(|()| {
+            let renamed = rename_run_files(db_name, staging_address, first_address, on_change);
+            if renamed.is_err() {
+                //nothing of the merged run is left under the first run's number, it can have its files back
+                let _ = rename_run_files(db_name, retired_address, first_address, on_change);
+            }
+            renamed
+        });
+    if let Err(why) = installed {
+        //like a failed merge, no staging run is left in the way of the runs moved into the level next
+        remove_run_files(db_name, staging_address, on_change);
+        return Err(why);
+    }
+
+    //the level only has the merged run from here on, an input run that can't be removed is left behind rather than
+    // failing an install that's already done
+    for run in (1..num_input_runs).chain([num_input_runs + 1]) {
+        remove_run_files(db_name, &(db_name, level, run), on_change);
+    }
+    Ok(())
+}
+
+///Remove the files of the run at <run_address> as far as they can be. Its SST file goes last, so a run that's left
+/// behind is never just sidecars
+fn remove_run_files(
+    db_name: &DatabaseName,
+    run_address: &RunAddress,
+    on_change: &mut dyn FnMut(FileChange),
+) {
+    for path in run_paths(run_address).into_iter().rev() {
+        if db_name.storage().path_exists(&path) {
+            on_change(FileChange::Removed(&path));
+            let _ = db_name.storage().remove_file(&path);
+        }
+    }
+}
+
+///Rename the files of the run at <from> to <to>. If one can't be renamed, those renamed before it are renamed back (as
+/// far as they can be) and the error is returned
+fn rename_run_files(
+    db_name: &DatabaseName,
+    from: &RunAddress,
+    to: &RunAddress,
+    on_change: &mut dyn FnMut(FileChange),
+) -> io::Result<()> {
+    let renames: Vec<(PathBuf, PathBuf)> = run_paths(from)
+        .into_iter()
+        .zip(run_paths(to))
+        .filter(|(from_path, _)| db_name.storage().path_exists(from_path))
+        .collect();
+    for (done, (from_path, to_path)) in renames.iter().enumerate() {
+        on_change(FileChange::Renamed {
+            from: from_path,
+            to: to_path,
+        });
+        if let Err(why) = db_name.storage().rename(from_path, to_path) {
+            for (from_path, to_path) in renames[..done].iter().rev() {
+                on_change(FileChange::Renamed {
+                    from: to_path,
+                    to: from_path,
+                });
+                let _ = db_name.storage().rename(to_path, from_path);
+            }
+            return Err(why);
+        }
+    }
+    Ok(())
+}
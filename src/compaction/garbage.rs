@@ -0,0 +1,162 @@
+//!Estimates of how much of a level is garbage: entries shadowed by a younger entry for the same key, and tombstones.
+//! Tombstones are counted exactly per run, shadowed entries are estimated from a small sketch of the distinct keys in
+//! each run (the smallest hashes of its keys, a k minimum values sketch), so no run has to be read to tell
+
+#[cfg(feature = "serde-config")]
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    file_io::serde_state::impl_struct_codec,
+    util::types::{Key, Size},
+};
+
+///Smallest hashes of the keys of a run, from which the number of distinct keys across runs can be estimated
+#[cfg_attr(feature = "serde-config", derive(Serialize, Deserialize))]
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct KeySketch {
+    hashes: Vec<u64>, //sorted, distinct, at most KeySketch::CAPACITY of them
+}
+
+impl_struct_codec!(KeySketch { hashes });
+
+impl KeySketch {
+    ///Number of hashes kept, the relative error of an estimate is about 1 / sqrt(CAPACITY)
+    pub const CAPACITY: usize = 64;
+
+    pub fn from_keys(keys: impl IntoIterator<Item = Key>) -> Self {
+        let mut sketch = Self::default();
+        for key in keys {
+            sketch.insert(key);
+        }
+        sketch
+    }
+    pub fn insert(&mut self, key: Key) {
+        self.insert_hash(Self::hash(key));
+    }
+    ///Sketch of the keys of both sketches
+    pub fn union(&mut self, other: &KeySketch) {
+        for &hash in &other.hashes {
+            self.insert_hash(hash);
+        }
+    }
+    pub fn is_empty(&self) -> bool {
+        self.hashes.is_empty()
+    }
+    ///Estimated number of distinct keys inserted, exact while fewer than CAPACITY keys were
+    pub fn estimate(&self) -> Size {
+        if self.hashes.len() < Self::CAPACITY {
+            return self.hashes.len();
+        }
+        //the k-th smallest of n uniform hashes is expected around k / n of the way through the hash space
+        let kth_fraction = (self.hashes[Self::CAPACITY - 1] as f64 + 1.0) / (u64::MAX as f64 + 1.0);
+        ((Self::CAPACITY - 1) as f64 / kth_fraction).round() as Size
+    }
+    fn insert_hash(&mut self, hash: u64) {
+        if self.hashes.len() == Self::CAPACITY && hash >= self.hashes[Self::CAPACITY - 1] {
+            return;
+        }
+        if let Err(index) = self.hashes.binary_search(&hash) {
+            self.hashes.insert(index, hash);
+            self.hashes.truncate(Self::CAPACITY);
+        }
+    }
+    ///Spreads keys uniformly over the hash space (the splitmix64 finalizer), so close keys don't share a prefix
+    fn hash(key: Key) -> u64 {
+        let mut hash = (key as u64).wrapping_add(0x9e37_79b9_7f4a_7c15);
+        hash = (hash ^ (hash >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        hash = (hash ^ (hash >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        hash ^ (hash >> 31)
+    }
+}
+
+///Fraction of the entries of a level that aren't live, from the entry and tombstone counts and key sketches of its runs.
+/// Every tombstone is garbage, and so is every entry but the youngest for a key. A single run holds each key once,
+/// so its ratio is exact. Runs without a sketch (written before sketches were kept) are assumed to share no keys
+pub fn garbage_ratio(
+    entry_counts: &[Size],
+    tombstone_counts: &[Size],
+    key_sketches: &[KeySketch],
+) -> f64 {
+    let num_entries: Size = entry_counts.iter().sum();
+    if num_entries == 0 {
+        return 0.0;
+    }
+    let num_tombstones: Size = tombstone_counts.iter().sum();
+    let distinct_keys = match entry_counts {
+        [_] => num_entries,
+        _ => {
+            let has_every_sketch = entry_counts.iter().enumerate().all(|(run, count)| {
+                *count == 0
+                    || key_sketches
+                        .get(run)
+                        .is_some_and(|sketch| !sketch.is_empty())
+            });
+            if has_every_sketch {
+                let mut union = KeySketch::default();
+                key_sketches.iter().for_each(|sketch| union.union(sketch));
+                //there are at least as many keys as in the largest run, and no more than there are entries
+                let largest_run = entry_counts.iter().max().copied().unwrap_or(0);
+                union.estimate().clamp(largest_run, num_entries)
+            } else {
+                num_entries
+            }
+        }
+    };
+    //a key's youngest entry may itself be a tombstone, those keys aren't live either
+    let live_entries = distinct_keys.saturating_sub(num_tombstones);
+    1.0 - live_entries as f64 / num_entries as f64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_estimate() {
+        assert_eq!(KeySketch::default().estimate(), 0);
+        let small = KeySketch::from_keys([3, 1, 2, 3, 1]);
+        assert_eq!(small.estimate(), 3); //exact below capacity
+
+        for num_keys in [1_000, 50_000] {
+            let sketch = KeySketch::from_keys(0..num_keys as Key);
+            let estimate = sketch.estimate() as f64;
+            assert!(
+                (estimate - num_keys as f64).abs() < num_keys as f64 * 0.5,
+                "estimated {estimate} of {num_keys} keys"
+            );
+        }
+
+        //overlapping runs estimate their distinct keys, not their total
+        let mut union = KeySketch::from_keys(0..10_000);
+        union.union(&KeySketch::from_keys(5_000..15_000));
+        let estimate = union.estimate() as f64;
+        assert!(
+            (estimate - 15_000.0).abs() < 7_500.0,
+            "estimated {estimate}"
+        );
+        assert_eq!(union, KeySketch::from_keys(0..15_000));
+    }
+
+    #[test]
+    fn test_garbage_ratio() {
+        assert_eq!(garbage_ratio(&[], &[], &[]), 0.0);
+        //a single run is exact, only its tombstones are garbage
+        let sketch = KeySketch::from_keys(0..100);
+        assert_eq!(
+            garbage_ratio(&[100], &[25], std::slice::from_ref(&sketch)),
+            0.25
+        );
+
+        //the same 100 keys written 4 times
+        let sketches = vec![sketch.clone(); 4];
+        assert_eq!(garbage_ratio(&[100; 4], &[0; 4], &sketches), 0.75);
+
+        //disjoint runs hold no shadowed entries, sketches of few keys are exact
+        let disjoint = [KeySketch::from_keys(0..40), KeySketch::from_keys(40..60)];
+        assert_eq!(garbage_ratio(&[40, 20], &[0, 15], &disjoint), 0.25);
+
+        //a run without a sketch can't be compared with the others
+        let missing = [sketch, KeySketch::default()];
+        assert_eq!(garbage_ratio(&[100, 100], &[0, 0], &missing), 0.0);
+    }
+}
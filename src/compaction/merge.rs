@@ -0,0 +1,635 @@
+use std::{io, path::PathBuf};
+
+use crate::{
+    db::Database,
+    filter::bloom_io::BloomFilterBuilder,
+    sst::{
+        array_sst,
+        expiry::{self, MergeExpiries},
+        fence,
+        operands::{self, MergeOperands},
+        MergeSidecars, MergeStats,
+    },
+    util::{
+        filename::{self, RunPaths},
+        system_info::page_size,
+        types::{Entry, Key, Level, Run, RunAddress, Size, Timestamp},
+        yield_hook::YieldHook,
+    },
+};
+#[cfg(feature = "btree")]
+use crate::{
+    sst::btree_sst::{self, LeafDelimiters},
+    util::{system_info::PageLayout, types::SstImplementation},
+};
+
+use super::{
+    background_compactor::CompactionSettings,
+    garbage::KeySketch,
+    partition::{KeyRange, PartitionRun},
+};
+
+///Stage of a compaction, reported to the database's compaction progress callback
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CompactionProgress {
+    ///The merged run is fully written but not installed, readers still use the input runs
+    Merged { level: Level, stats: MergeStats },
+    ///The merged run replaced the input runs
+    Installed { level: Level },
+    ///The run's bloom filter was replaced by one rebuilt with new settings (see
+    /// Database::set_bloom_bits_per_entry_and_rebuild)
+    FilterRebuilt { level: Level, run: Run },
+}
+
+///Paths of every file that belongs to a run
+pub fn run_paths(run_address: &RunAddress) -> [PathBuf; 6] {
+    [
+        run_address.path_sst(),
+        run_address.path_btree(),
+        run_address.path_bloom(),
+        filename::expiry_path(run_address),
+        filename::fence_path(run_address),
+        filename::operands_path(run_address),
+    ]
+}
+
+///Run number the merged run is built under, one past the last input run so it's never read before it's installed
+pub fn staging_run(entry_counts: &[Size]) -> Run {
+    entry_counts.len()
+}
+
+///Indexes of a run built from its pages as they're written, instead of reading the run back: its B-tree, bloom
+/// filter, fence index and key sketch
+struct RunIndexBuilder {
+    #[cfg(feature = "btree")]
+    leaf_delimiters: Option<LeafDelimiters>,
+    bloom_filter: Option<BloomFilterBuilder>,
+    first_keys: Option<Vec<Key>>,
+    key_sketch: KeySketch,
+}
+
+impl RunIndexBuilder {
+    fn new(settings: &CompactionSettings, level: Level) -> Self {
+        Self {
+            #[cfg(feature = "btree")]
+            leaf_delimiters: matches!(settings.sst_implementation, SstImplementation::Btree)
+                .then(|| LeafDelimiters::new(PageLayout::host())),
+            //sized from the entries the merge keeps, not the entries of its inputs
+            bloom_filter: (settings.bloom_levels.includes(level)).then(|| {
+                BloomFilterBuilder::new(settings.bloom_filter_bits_per_entry, settings.key_codec)
+            }),
+            first_keys: settings.enable_fence_index.then(Vec::new),
+            key_sketch: KeySketch::default(),
+        }
+    }
+    ///Index the entries of the run's next page
+    fn push(&mut self, entries: &[Entry]) {
+        entries
+            .iter()
+            .for_each(|(key, _)| self.key_sketch.insert(*key));
+        if let Some(first_keys) = self.first_keys.as_mut() {
+            first_keys.push(entries[0].0);
+        }
+        #[cfg(feature = "btree")]
+        if let Some(leaf_delimiters) = self.leaf_delimiters.as_mut() {
+            leaf_delimiters.push(entries);
+        }
+        if let Some(bloom_filter) = self.bloom_filter.as_mut() {
+            bloom_filter.insert_entries(entries);
+        }
+    }
+    ///Write the indexes of the run at <run_address>, which holds <num_entries> entries, returns its key sketch
+    fn write(
+        self,
+        run_address: &RunAddress,
+        num_entries: Size,
+        settings: &CompactionSettings,
+    ) -> io::Result<KeySketch> {
+        #[cfg(feature = "btree")]
+        if let Some(leaf_delimiters) = self.leaf_delimiters {
+            btree_sst::Sst::new(settings.key_codec).write_inner_nodes_from_delimiters(
+                run_address,
+                num_entries,
+                &leaf_delimiters.finish(),
+            )?;
+        }
+        if let Some(first_keys) = self.first_keys {
+            fence::write(run_address, &first_keys, settings.key_codec)?;
+        }
+        if let Some(mut bloom_filter) = self.bloom_filter {
+            bloom_filter.seal(run_address, num_entries)?;
+        }
+        Ok(self.key_sketch)
+    }
+}
+
+///Read the expiry and operand sidecars of the runs of <level> listed in <runs>
+fn read_sidecars(
+    settings: &CompactionSettings,
+    level: Level,
+    runs: impl IntoIterator<Item = Run> + Clone,
+) -> io::Result<MergeSidecars> {
+    let level_address = &(&settings.db_name, level);
+    Ok(MergeSidecars {
+        expiries: MergeExpiries::read_inputs(
+            level_address,
+            runs.clone(),
+            settings.key_codec,
+            settings.now,
+        )?,
+        operands: MergeOperands::read_inputs(
+            level_address,
+            runs,
+            settings.key_codec,
+            settings.merge_operator,
+        )?,
+    })
+}
+
+///Merge every run listed in <entry_counts> into the staging run of the level, along with its B-tree, bloom filter,
+/// expiry, fence and operand files, and sketch its keys. Only reads the input runs, so the level stays readable until the
+/// result is installed, or if the merge fails (e.g. on an unsorted input run, or when <yield_hook> aborts it between
+/// pages)
+pub fn build_merged_run(
+    settings: &CompactionSettings,
+    level: Level,
+    entry_counts: &[Size],
+    discard_tombstones: bool,
+    yield_hook: &mut YieldHook,
+) -> io::Result<(MergeStats, KeySketch)> {
+    let db_name = &settings.db_name;
+    let staging_address = &(db_name, level, staging_run(entry_counts));
+    let mut sidecars = read_sidecars(settings, level, 0..entry_counts.len())?;
+    let mut indexes = RunIndexBuilder::new(settings, level);
+    let mut on_page_flushed = |entries: &[Entry]| {
+        indexes.push(entries);
+        yield_hook.call()
+    };
+    let staging_path = staging_address.path_sst();
+    let stats = array_sst::Sst::new(settings.key_codec)
+        .set_merge_memory_budget(settings.memory_budget)
+        .merge_runs(
+            &(db_name, level),
+            entry_counts,
+            discard_tombstones,
+            &staging_path,
+            &mut sidecars,
+            &mut on_page_flushed,
+        )
+        .inspect_err(|_| {
+            let _ = db_name.storage().remove_file(&staging_path); //partially written
+        })?;
+    let entries_written = stats.entries_written;
+    if entries_written == 0 {
+        return Ok((stats, indexes.key_sketch)); //nothing to index
+    }
+    if stats.expiries_written > 0 {
+        expiry::write(
+            staging_address,
+            sidecars.expiries.output(),
+            settings.key_codec,
+        )?;
+    }
+    if stats.operands_written > 0 {
+        operands::write(
+            staging_address,
+            sidecars.operands.output(),
+            settings.key_codec,
+        )?;
+    }
+    let key_sketch = indexes.write(staging_address, entries_written, settings)?;
+    Ok((stats, key_sketch))
+}
+
+///A run being written by build_partition_runs
+struct PartitionOutput {
+    indexes: RunIndexBuilder,
+    pages: Size,
+    entries: Size,
+    tombstones: Size,
+    key_range: KeyRange,
+}
+
+///Merge the runs of <level> listed in <inputs> (run number and entry count, oldest first) into runs of at most
+/// <pages_per_output> pages each, built under run numbers <first_output_run> onwards with all their indexes and
+/// sidecars (see build_merged_run). The runs written are returned in key order, their key ranges don't overlap. None
+/// are written if the merge keeps no entry. Partially written runs are removed if the merge fails
+pub fn build_partition_runs(
+    settings: &CompactionSettings,
+    level: Level,
+    inputs: &[(Run, Size)],
+    first_output_run: Run,
+    pages_per_output: Size,
+    discard_tombstones: bool,
+    yield_hook: &mut YieldHook,
+) -> io::Result<(MergeStats, Vec<PartitionRun>)> {
+    let db_name = &settings.db_name;
+    let output_address = |output: Size| (db_name, level, first_output_run + output);
+    let mut sidecars = read_sidecars(settings, level, inputs.iter().map(|(run, _)| *run))?;
+    let mut outputs: Vec<PartitionOutput> = Vec::new();
+    //same rule as the merge, a run is full once it holds <pages_per_output> pages
+    let mut on_page_flushed = |entries: &[Entry]| {
+        let is_full = |output: &PartitionOutput| output.pages == pages_per_output;
+        if outputs.last().is_none_or(is_full) {
+            outputs.push(PartitionOutput {
+                indexes: RunIndexBuilder::new(settings, level),
+                pages: 0,
+                entries: 0,
+                tombstones: 0,
+                key_range: (entries[0].0, entries[0].0),
+            });
+        }
+        let output = outputs.last_mut().expect("an output was just added");
+        output.indexes.push(entries);
+        output.pages += 1;
+        output.entries += entries.len();
+        output.tombstones += entries
+            .iter()
+            .filter(|(_, value)| *value == Database::TOMBSTONE_VALUE)
+            .count();
+        output.key_range.1 = entries[entries.len() - 1].0;
+        yield_hook.call()
+    };
+    let stats = array_sst::Sst::new(settings.key_codec)
+        .set_merge_memory_budget(settings.memory_budget)
+        .merge_run_list(
+            &(db_name, level),
+            inputs,
+            discard_tombstones,
+            &|output| output_address(output).path_sst(),
+            pages_per_output,
+            &mut sidecars,
+            &mut on_page_flushed,
+        )
+        .inspect_err(|_| {
+            //partially written, the merge creates its outputs in order
+            (0..)
+                .map(|output| output_address(output).path_sst())
+                .take_while(|path| db_name.storage().path_exists(path))
+                .for_each(|path| {
+                    let _ = db_name.storage().remove_file(&path);
+                });
+        })?;
+    if stats.entries_written == 0 {
+        db_name
+            .storage()
+            .remove_file(&output_address(0).path_sst())?; //the merge always creates its first output
+    }
+
+    let expiries = sidecars.expiries.output();
+    let operands = sidecars.operands.output();
+    let mut partition_runs = Vec::with_capacity(outputs.len());
+    for (output, written) in outputs.into_iter().enumerate() {
+        let run_address = &output_address(output);
+        let (low, high) = written.key_range;
+        let in_range = |key: Key| low <= key && key <= high;
+        let run_expiries: Vec<(Key, Timestamp)> = expiries
+            .iter()
+            .filter(|(key, _)| in_range(*key))
+            .copied()
+            .collect();
+        if !run_expiries.is_empty() {
+            expiry::write(run_address, &run_expiries, settings.key_codec)?;
+        }
+        let run_operands: Vec<Key> = operands
+            .iter()
+            .filter(|key| in_range(**key))
+            .copied()
+            .collect();
+        if !run_operands.is_empty() {
+            operands::write(run_address, &run_operands, settings.key_codec)?;
+        }
+        let key_sketch = written
+            .indexes
+            .write(run_address, written.entries, settings)?;
+        partition_runs.push(PartitionRun {
+            key_range: written.key_range,
+            entries: written.entries,
+            tombstones: written.tombstones,
+            expiries: run_expiries.len(),
+            operands: run_operands.len(),
+            page_size: page_size(),
+            key_sketch,
+        });
+    }
+    Ok((stats, partition_runs))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[cfg(feature = "bloom")]
+    use crate::filter::bloom_io::BloomFilterIO;
+    use crate::{
+        sst::SortedStringTable,
+        util::{
+            system_info::{num_entries_per_page, page_size},
+            testing::{self, setup_and_test_and_cleaup},
+            types::{
+                BloomLevels, CompactionPolicy, Key, KeyCodec, MergeOperator, SstImplementation,
+                Value,
+            },
+        },
+    };
+
+    #[test]
+    fn test_indexes_built_during_merge() {
+        //the B-tree and bloom filter built from the pages as they're written must match the ones built
+        // by reading the merged run back
+        let db_name = &testing::test_db_name("merge_indexes_built_during_merge");
+        const LEVEL: Level = 1;
+        let mut test = || {
+            let settings = CompactionSettings {
+                db_name: db_name.clone(),
+                compaction_policy: CompactionPolicy::Leveled,
+                sst_size_ratio: 2,
+                memtable_capacity: 1,
+                sst_implementation: SstImplementation::Btree,
+                key_codec: KeyCodec::default(),
+                bloom_levels: BloomLevels::All,
+                enable_fence_index: false,
+                memory_budget: 0,
+                merge_operator: MergeOperator::Add,
+                bloom_filter_bits_per_entry: 5,
+                now: 0,
+            };
+            //merged run needs 2 levels of inner nodes (with the btree feature)
+            let fanout = page_size() / std::mem::size_of::<Key>();
+            let num_keys = (2 * fanout * num_entries_per_page()) as Key;
+            let entries0: Vec<Entry> = (0..num_keys).step_by(2).map(|key| (key, 0)).collect();
+            let entries1: Vec<Entry> = (0..num_keys).step_by(3).map(|key| (key, 1)).collect();
+            let sst = array_sst::Sst::default();
+            sst.write(&(db_name, LEVEL, 0), &entries0).unwrap();
+            sst.write(&(db_name, LEVEL, 1), &entries1).unwrap();
+
+            let entry_counts = [entries0.len(), entries1.len()];
+            let (stats, _) = build_merged_run(
+                &settings,
+                LEVEL,
+                &entry_counts,
+                false,
+                &mut YieldHook::default(),
+            )
+            .unwrap();
+            let staging_address = &(db_name, LEVEL, staging_run(&entry_counts));
+            let num_entries = stats.entries_written;
+            assert_eq!(sst.len(staging_address).unwrap(), num_entries);
+
+            #[cfg(feature = "bloom")]
+            {
+                let bloom_path = staging_address.path_bloom();
+                let bloom_filter = db_name.storage().read_file(&bloom_path).unwrap();
+                BloomFilterIO::write_from_sst(staging_address, 5, num_entries, settings.key_codec)
+                    .unwrap();
+                assert_eq!(
+                    bloom_filter,
+                    db_name.storage().read_file(&bloom_path).unwrap()
+                );
+            }
+
+            #[cfg(feature = "btree")]
+            {
+                let btree_path = staging_address.path_btree();
+                let btree = db_name.storage().read_file(&btree_path).unwrap();
+                btree_sst::Sst::default()
+                    .write_inner_nodes(staging_address, num_entries)
+                    .unwrap();
+                assert_eq!(btree, db_name.storage().read_file(&btree_path).unwrap());
+            }
+        };
+        setup_and_test_and_cleaup(&(db_name, LEVEL), &mut test);
+    }
+
+    #[test]
+    fn test_merge_within_memory_budget() {
+        //a page of each of 1000 runs wouldn't fit, they're merged in passes of as many runs as the budget holds a page of
+        let db_name = &testing::test_db_name("merge_within_memory_budget");
+        const LEVEL: Level = 1;
+        const NUM_RUNS: Size = 1000;
+        const MEMORY_BUDGET: Size = 64 * 1024;
+        let mut test = || {
+            let settings = CompactionSettings {
+                db_name: db_name.clone(),
+                compaction_policy: CompactionPolicy::Tiered,
+                sst_size_ratio: NUM_RUNS,
+                memtable_capacity: 1,
+                sst_implementation: SstImplementation::Array,
+                key_codec: KeyCodec::default(),
+                bloom_levels: BloomLevels::None,
+                enable_fence_index: false,
+                memory_budget: MEMORY_BUDGET,
+                merge_operator: MergeOperator::Add,
+                bloom_filter_bits_per_entry: 5,
+                now: 0,
+            };
+            let sst = array_sst::Sst::default();
+            let mut expected = std::collections::BTreeMap::new();
+            let mut entry_counts = Vec::new();
+            for run in 0..NUM_RUNS {
+                //runs overlap, younger (higher numbered) runs shadow older ones
+                let entries: Vec<Entry> = (0..100)
+                    .map(|i| ((i * 3 + run % 5) as Key, run as Key))
+                    .collect();
+                sst.write(&(db_name, LEVEL, run), &entries).unwrap();
+                expected.extend(entries.iter().copied());
+                entry_counts.push(entries.len());
+            }
+            assert!(
+                array_sst::merge_memory_bytes(NUM_RUNS, num_entries_per_page()) > MEMORY_BUDGET
+            );
+
+            let (stats, _) = build_merged_run(
+                &settings,
+                LEVEL,
+                &entry_counts,
+                false,
+                &mut YieldHook::default(),
+            )
+            .unwrap();
+            assert!(
+                stats.memory_bytes <= MEMORY_BUDGET,
+                "{} bytes used, budget is {MEMORY_BUDGET}",
+                stats.memory_bytes
+            );
+            let expected: Vec<Entry> = expected.into_iter().collect();
+            assert_eq!(stats.entries_written, expected.len());
+            assert_eq!(
+                stats.entries_dropped,
+                entry_counts.iter().sum::<Size>() - expected.len()
+            );
+            let merged = sst
+                .read(&(db_name, LEVEL, staging_run(&entry_counts)))
+                .unwrap();
+            assert_eq!(merged, expected);
+        };
+        setup_and_test_and_cleaup(&(db_name, LEVEL), &mut test);
+    }
+
+    #[test]
+    fn test_merge_in_passes_keeps_sidecars() {
+        //operands, expiries and tombstones are merged in passes into the same run and sidecars a single merge writes
+        let db_name = &testing::test_db_name("merge_in_passes_keeps_sidecars");
+        const LEVEL: Level = 1;
+        const NUM_RUNS: Size = 16;
+        let mut test = || {
+            let key_codec = KeyCodec::default();
+            let settings = |memory_budget| CompactionSettings {
+                db_name: db_name.clone(),
+                compaction_policy: CompactionPolicy::Tiered,
+                sst_size_ratio: NUM_RUNS,
+                memtable_capacity: 1,
+                sst_implementation: SstImplementation::Array,
+                key_codec,
+                bloom_levels: BloomLevels::None,
+                enable_fence_index: false,
+                memory_budget,
+                merge_operator: MergeOperator::Add,
+                bloom_filter_bits_per_entry: 5,
+                now: 10,
+            };
+            let mut entry_counts = Vec::new();
+            for run in 0..NUM_RUNS {
+                let run_address = &(db_name, LEVEL, run);
+                let (mut entries, mut operand_keys, mut expiries) =
+                    (Vec::new(), Vec::new(), Vec::new());
+                for i in 0..30 {
+                    let key = (i * 3 + run % 3) as Key;
+                    match (i + run) % 7 {
+                        0 => entries.push((key, Database::TOMBSTONE_VALUE)),
+                        1 | 2 => {
+                            entries.push((key, (run + i) as Value));
+                            operand_keys.push(key);
+                        }
+                        3 => {
+                            entries.push((key, (run + i) as Value));
+                            let expiry = if i % 2 == 0 { 5 } else { 100 }; //some expired at 10
+                            expiries.push((key, expiry));
+                        }
+                        _ => entries.push((key, (run * 100 + i) as Value)),
+                    }
+                }
+                //only operands, nothing is under them in the level
+                entries.push((1000, run as Value));
+                operand_keys.push(1000);
+                array_sst::Sst::default()
+                    .write(run_address, &entries)
+                    .unwrap();
+                operands::write(run_address, &operand_keys, key_codec).unwrap();
+                expiry::write(run_address, &expiries, key_codec).unwrap();
+                entry_counts.push(entries.len());
+            }
+            let staging_address = &(db_name, LEVEL, staging_run(&entry_counts));
+            //files of the merged run, removed so the next merge starts over
+            let take_merged_files = || {
+                [
+                    staging_address.path_sst(),
+                    filename::expiry_path(staging_address),
+                    filename::operands_path(staging_address),
+                ]
+                .map(|path| {
+                    let bytes = db_name.storage().read_file(&path).ok(); //no operands are left on the last level
+                    let _ = db_name.storage().remove_file(&path);
+                    bytes
+                })
+            };
+
+            for discard_tombstones in [false, true] {
+                let merge = |memory_budget| {
+                    build_merged_run(
+                        &settings(memory_budget),
+                        LEVEL,
+                        &entry_counts,
+                        discard_tombstones,
+                        &mut YieldHook::default(),
+                    )
+                    .unwrap()
+                    .0
+                };
+                let expected_stats = merge(0);
+                let expected = take_merged_files();
+                assert_eq!(expected[2].is_some(), !discard_tombstones);
+                let budget = array_sst::merge_memory_bytes(4, num_entries_per_page());
+                let stats = merge(budget);
+                assert_eq!(take_merged_files(), expected);
+                assert_eq!(
+                    MergeStats {
+                        memory_bytes: 0,
+                        pages_read: 0,
+                        pages_written: 0,
+                        ..stats
+                    },
+                    MergeStats {
+                        memory_bytes: 0,
+                        pages_read: 0,
+                        pages_written: 0,
+                        ..expected_stats
+                    }
+                );
+            }
+        };
+        setup_and_test_and_cleaup(&(db_name, LEVEL), &mut test);
+    }
+
+    #[cfg(feature = "bloom")]
+    #[test]
+    fn test_bloom_filter_sized_from_kept_entries() {
+        use crate::{db::Database, filter::bloom_util::bitmap_len};
+
+        //most of the inputs are tombstones the merge discards, the filter is sized for the entries it keeps
+        let db_name = &testing::test_db_name("merge_bloom_filter_sized_from_kept_entries");
+        const LEVEL: Level = 2;
+        let mut test = || {
+            let settings = CompactionSettings {
+                db_name: db_name.clone(),
+                compaction_policy: CompactionPolicy::Leveled,
+                sst_size_ratio: 2,
+                memtable_capacity: 1,
+                sst_implementation: SstImplementation::Array,
+                key_codec: KeyCodec::default(),
+                bloom_levels: BloomLevels::All,
+                enable_fence_index: false,
+                memory_budget: 0,
+                merge_operator: MergeOperator::Add,
+                bloom_filter_bits_per_entry: 8,
+                now: 0,
+            };
+            let sst = array_sst::Sst::default();
+            let live: Vec<Entry> = (0..5_000).map(|key| (key * 2, key)).collect();
+            let tombstones: Vec<Entry> = (0..20_000)
+                .map(|key| (key * 2 + 1, Database::TOMBSTONE_VALUE))
+                .collect();
+            sst.write(&(db_name, LEVEL, 0), &live).unwrap();
+            sst.write(&(db_name, LEVEL, 1), &tombstones).unwrap();
+
+            let entry_counts = [live.len(), tombstones.len()];
+            let (stats, _) = build_merged_run(
+                &settings,
+                LEVEL,
+                &entry_counts,
+                true,
+                &mut YieldHook::default(),
+            )
+            .unwrap();
+            assert_eq!(stats.entries_written, live.len());
+            let staging_address = &(db_name, LEVEL, staging_run(&entry_counts));
+            let bitmap = db_name
+                .storage()
+                .read_file(&staging_address.path_bloom())
+                .unwrap();
+            assert_eq!(bitmap.len(), bitmap_len(live.len(), 8));
+            let contains = |key| {
+                BloomFilterIO::contains(
+                    staging_address,
+                    key,
+                    8,
+                    stats.entries_written,
+                    settings.key_codec,
+                    None,
+                )
+                .unwrap()
+            };
+            assert!(live.iter().all(|(key, _)| contains(*key)));
+        };
+        setup_and_test_and_cleaup(&(db_name, LEVEL), &mut test);
+    }
+}
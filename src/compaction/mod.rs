@@ -0,0 +1,8 @@
+pub mod background_compactor;
+pub mod cascade;
+pub mod garbage;
+pub mod merge;
+pub mod partition;
+pub mod plan;
+pub mod size_ratio;
+pub mod strategy;
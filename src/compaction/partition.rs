@@ -0,0 +1,92 @@
+//!Partitioned levels (see Database::set_partitioned_levels): the first runs of a level hold disjoint key ranges in key
+//! order, its partitions, so a get only needs the partition holding its key and a compaction only rewrites the
+//! partitions its incoming runs overlap. Runs after the partitions are incoming runs waiting to be merged into them
+
+use std::ops::Range;
+
+use crate::{
+    compaction::garbage::KeySketch,
+    util::types::{Key, Run, Size},
+};
+
+///Smallest and largest key of a run (inclusive)
+pub type KeyRange = (Key, Key);
+
+///A run written by a partitioned compaction (or an incoming run taken in as it is), along with its metadata
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PartitionRun {
+    pub key_range: KeyRange,
+    pub entries: Size,
+    pub tombstones: Size,
+    pub expiries: Size,
+    pub operands: Size,
+    pub page_size: Size,
+    pub key_sketch: KeySketch,
+}
+
+///Partition whose key range holds <key>, None if it falls between (or outside) them
+pub fn containing(partitions: &[KeyRange], key: Key) -> Option<Run> {
+    let run = partitions.partition_point(|(_, high)| *high < key);
+    partitions
+        .get(run)
+        .is_some_and(|(low, _)| *low <= key)
+        .then_some(run)
+}
+
+///Partitions whose key ranges intersect <key_range>, the empty range at the position a run holding it would be
+/// inserted if there are none
+pub fn overlapping(partitions: &[KeyRange], key_range: KeyRange) -> Range<Run> {
+    let (low, high) = key_range;
+    let start = partitions.partition_point(|(_, partition_high)| *partition_high < low);
+    let end = partitions.partition_point(|(partition_low, _)| *partition_low <= high);
+    start..end.max(start)
+}
+
+///Smallest range holding all of <key_ranges>, None if there are none
+pub fn union(key_ranges: impl IntoIterator<Item = KeyRange>) -> Option<KeyRange> {
+    key_ranges
+        .into_iter()
+        .reduce(|(low1, high1), (low2, high2)| (low1.min(low2), high1.max(high2)))
+}
+
+///Whether run <run> of a level with <partitions> can be skipped by a read of the keys in <key_range>
+pub fn skips(partitions: &[KeyRange], run: Run, key_range: KeyRange) -> bool {
+    partitions
+        .get(run)
+        .is_some_and(|(low, high)| *high < key_range.0 || key_range.1 < *low)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_lookups() {
+        let partitions = [(0, 9), (10, 19), (30, 39)];
+        assert_eq!(containing(&partitions, 0), Some(0));
+        assert_eq!(containing(&partitions, 15), Some(1));
+        assert_eq!(containing(&partitions, 25), None);
+        assert_eq!(containing(&partitions, 39), Some(2));
+        assert_eq!(containing(&partitions, 40), None);
+        assert_eq!(containing(&partitions, -1), None);
+        assert_eq!(containing(&[], 0), None);
+
+        assert_eq!(overlapping(&partitions, (5, 12)), 0..2);
+        assert_eq!(overlapping(&partitions, (20, 29)), 2..2);
+        assert_eq!(overlapping(&partitions, (-5, -1)), 0..0);
+        assert_eq!(overlapping(&partitions, (50, 60)), 3..3);
+        assert_eq!(overlapping(&partitions, (19, 30)), 1..3);
+        assert_eq!(overlapping(&partitions, (Key::MIN, Key::MAX)), 0..3);
+
+        assert_eq!(union([(5, 12), (-3, 4), (7, 8)]), Some((-3, 12)));
+        assert_eq!(union([]), None);
+
+        assert!(skips(&partitions, 0, (10, 29)));
+        assert!(!skips(&partitions, 1, (10, 29)));
+        assert!(skips(&partitions, 2, (10, 29)));
+        assert!(
+            !skips(&partitions, 3, (10, 29)),
+            "incoming runs are never skipped"
+        );
+    }
+}
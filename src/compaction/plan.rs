@@ -0,0 +1,291 @@
+use std::io;
+
+use crate::{
+    db::Database,
+    sst::sst_util::num_pages,
+    util::types::{CompactionPolicy, Level, Run, Size},
+};
+
+use super::{
+    cascade::{self, Levels},
+    strategy::{LevelRuns, StrategySettings},
+};
+
+///Number of runs the next level holds once the <num_runs> runs of a level are moved after its own
+/// <num_runs_in_next_level>, None if that's more than Database::MAX_RUNS_PER_LEVEL
+pub fn runs_after_move(num_runs: Size, num_runs_in_next_level: Size) -> Option<Size> {
+    num_runs_in_next_level
+        .checked_add(num_runs)
+        .filter(|num_runs| *num_runs <= Database::MAX_RUNS_PER_LEVEL)
+}
+
+///One file operation of a CompactionPlan
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CompactionStep {
+    ///Every run of <level> is renamed into the next level, run r becomes run <first_new_run> + r there
+    Move {
+        level: Level,
+        num_runs: Size,
+        first_new_run: Run,
+    },
+    ///Every run of <level> is merged into one run of <entries_written> entries (none if it's 0)
+    Compact {
+        level: Level,
+        num_runs: Size,
+        entries_written: Size,
+    },
+    ///The memtable is written to a new run of level 0
+    Flush { entries: Size },
+}
+
+///What the next flush would do to the levels, worked out from run sizes alone (see Database::compaction_plan).
+/// NOTE: merges are estimated as if no key is in more than one run, shadowed entries aren't subtracted
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CompactionPlan {
+    pub steps: Vec<CompactionStep>,
+    pub pages_read: Size,             //by merges, moves only rename files
+    pub pages_written: Size,          //by the flush and merges
+    pub entry_counts: Vec<Vec<Size>>, //entries of every run once the flush is done, same layout as the metadata
+}
+
+impl CompactionPlan {
+    ///Plan for flushing <flush_entries> entries into levels holding runs of <entry_counts> entries
+    /// (and <tombstone_counts> tombstones), following the same steps as Database::flush_memtable.
+    /// Under CompactionPolicy::None level 0 is merged first once it has <max_level_zero_runs> runs (0 for never)
+    pub fn for_flush(
+        compaction_policy: CompactionPolicy,
+        size_ratio: Size,
+        memtable_capacity: Size,
+        entry_counts: &[Vec<Size>],
+        tombstone_counts: &[Vec<Size>],
+        flush_entries: Size,
+        max_level_zero_runs: Size,
+    ) -> Self {
+        let mut simulation = Simulation {
+            compaction_policy,
+            size_ratio,
+            memtable_capacity,
+            tombstone_counts: tombstone_counts.to_vec(),
+            plan: Self {
+                entry_counts: entry_counts.to_vec(),
+                ..Self::default()
+            },
+        };
+        if simulation.plan.entry_counts.is_empty() {
+            simulation.plan.entry_counts.push(vec![]);
+            simulation.tombstone_counts.push(vec![]);
+        }
+        match compaction_policy {
+            CompactionPolicy::None => {
+                let num_runs = simulation.plan.entry_counts[0].len();
+                if max_level_zero_runs > 0 && num_runs >= max_level_zero_runs {
+                    let is_last_level = simulation.plan.entry_counts.len() == 1;
+                    simulation.compact(0, is_last_level);
+                }
+            }
+            _ => cascade::move_runs(&mut simulation, 0)
+                .expect("a simulation has no files to fail on"),
+        }
+        let plan = &mut simulation.plan;
+        plan.steps.push(CompactionStep::Flush {
+            entries: flush_entries,
+        });
+        plan.entry_counts[0].push(flush_entries);
+        plan.pages_written += num_pages(flush_entries);
+        simulation.plan
+    }
+    ///Number of runs in each level once the flush is done
+    #[allow(dead_code)]
+    pub fn run_counts(&self) -> Vec<Size> {
+        self.entry_counts.iter().map(Vec::len).collect()
+    }
+}
+
+///Levels as the plan changes them, tombstone counts decide whether tiered levels compact early. The plan takes the
+/// database's steps (see cascade), except for level 0 under CompactionPolicy::None which for_flush merges itself
+struct Simulation {
+    compaction_policy: CompactionPolicy,
+    size_ratio: Size,
+    memtable_capacity: Size,
+    tombstone_counts: Vec<Vec<Size>>,
+    plan: CompactionPlan,
+}
+
+impl Simulation {
+    ///Same as Database::compact_level, a level with one run is left as it is
+    fn compact(&mut self, level: Level, discard_tombstones: bool) {
+        let plan = &mut self.plan;
+        let num_runs = plan.entry_counts[level].len();
+        if num_runs < 2 {
+            return;
+        }
+        let num_entries: Size = plan.entry_counts[level].iter().sum();
+        let num_tombstones: Size = self.tombstone_counts[level].iter().sum();
+        let (entries_written, tombstones_written) = match discard_tombstones {
+            true => (num_entries - num_tombstones, 0),
+            false => (num_entries, num_tombstones),
+        };
+        plan.steps.push(CompactionStep::Compact {
+            level,
+            num_runs,
+            entries_written,
+        });
+        plan.pages_read += plan.entry_counts[level]
+            .iter()
+            .map(|num_entries| num_pages(*num_entries))
+            .sum::<Size>();
+        plan.pages_written += num_pages(entries_written);
+        if entries_written == 0 {
+            plan.entry_counts[level].clear();
+            self.tombstone_counts[level].clear();
+        } else {
+            plan.entry_counts[level] = vec![entries_written];
+            self.tombstone_counts[level] = vec![tombstones_written];
+        }
+    }
+}
+
+///The steps the database would take, recorded instead of carried out
+impl Levels for Simulation {
+    fn compaction_policy(&self) -> CompactionPolicy {
+        self.compaction_policy
+    }
+    fn strategy_settings(&self) -> StrategySettings {
+        StrategySettings {
+            size_ratio: self.size_ratio,
+            memtable_capacity: self.memtable_capacity,
+        }
+    }
+    fn with_level_runs<T>(&self, level: Level, f: impl FnOnce(&LevelRuns) -> T) -> T {
+        f(&LevelRuns {
+            level,
+            is_last_level: level == self.plan.entry_counts.len() - 1,
+            entry_counts: &self.plan.entry_counts[level],
+            tombstone_counts: &self.tombstone_counts[level],
+        })
+    }
+    fn move_level_runs(&mut self, level: Level) -> io::Result<bool> {
+        let next_level = level + 1;
+        let entry_counts = &mut self.plan.entry_counts;
+        if entry_counts[level].is_empty() {
+            return Ok(false);
+        }
+        if entry_counts.get(next_level).is_none() {
+            entry_counts.push(vec![]);
+            self.tombstone_counts.push(vec![]);
+        }
+        self.plan.steps.push(CompactionStep::Move {
+            level,
+            num_runs: entry_counts[level].len(),
+            first_new_run: entry_counts[next_level].len(),
+        });
+        let moved = std::mem::take(&mut entry_counts[level]);
+        entry_counts[next_level].extend(moved);
+        let moved = std::mem::take(&mut self.tombstone_counts[level]);
+        self.tombstone_counts[next_level].extend(moved);
+        Ok(true)
+    }
+    fn compact_level_runs(&mut self, level: Level, discard_tombstones: bool) -> io::Result<()> {
+        self.compact(level, discard_tombstones);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_plan_tiered_cascade() {
+        //level 1 is full, the moved run fills it, its merged run fills level 2 as well
+        let plan = CompactionPlan::for_flush(
+            CompactionPolicy::Tiered,
+            2,
+            4,
+            &[vec![4], vec![4], vec![8]],
+            &[vec![0], vec![0], vec![0]],
+            4,
+            0,
+        );
+        assert_eq!(
+            plan.steps,
+            vec![
+                CompactionStep::Move {
+                    level: 0,
+                    num_runs: 1,
+                    first_new_run: 1
+                },
+                CompactionStep::Compact {
+                    level: 1,
+                    num_runs: 2,
+                    entries_written: 8
+                },
+                CompactionStep::Move {
+                    level: 1,
+                    num_runs: 1,
+                    first_new_run: 1
+                },
+                CompactionStep::Compact {
+                    level: 2,
+                    num_runs: 2,
+                    entries_written: 16
+                },
+                CompactionStep::Move {
+                    level: 2,
+                    num_runs: 1,
+                    first_new_run: 0
+                },
+                CompactionStep::Flush { entries: 4 },
+            ]
+        );
+        assert_eq!(plan.entry_counts, vec![vec![4], vec![], vec![], vec![16]]);
+        assert_eq!(plan.run_counts(), vec![1, 0, 0, 1]);
+        assert_eq!(plan.pages_read, 2 * num_pages(4) + 2 * num_pages(8));
+        assert_eq!(
+            plan.pages_written,
+            num_pages(8) + num_pages(16) + num_pages(4)
+        );
+    }
+
+    #[test]
+    fn test_plan_level_zero_limit() {
+        let entry_counts = [vec![4, 4, 2]];
+        let tombstone_counts = [vec![0, 0, 2]];
+        let plan = CompactionPlan::for_flush(
+            CompactionPolicy::None,
+            2,
+            4,
+            &entry_counts,
+            &tombstone_counts,
+            4,
+            3,
+        );
+        assert_eq!(
+            plan.steps,
+            vec![
+                CompactionStep::Compact {
+                    level: 0,
+                    num_runs: 3,
+                    entries_written: 8
+                },
+                CompactionStep::Flush { entries: 4 },
+            ]
+        );
+        assert_eq!(plan.entry_counts, vec![vec![8, 4]]);
+
+        //under the limit (or without one) runs pile up
+        for max_level_zero_runs in [4, 0] {
+            let plan = CompactionPlan::for_flush(
+                CompactionPolicy::None,
+                2,
+                4,
+                &entry_counts,
+                &tombstone_counts,
+                4,
+                max_level_zero_runs,
+            );
+            assert_eq!(plan.steps, vec![CompactionStep::Flush { entries: 4 }]);
+            assert_eq!(plan.run_counts(), vec![4]);
+        }
+    }
+}
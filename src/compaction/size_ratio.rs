@@ -0,0 +1,143 @@
+//!Size ratio the data actually settles into between adjacent levels, next to the configured sst_size_ratio. They drift
+//! apart with overwrites and deletes (merges drop shadowed entries and tombstones), so each compaction that installs a
+//! run records the ratios it leaves behind into an exponentially weighted history
+
+use crate::util::{
+    system_info::ENTRY_SIZE,
+    types::{Level, Size},
+};
+
+///Size ratio between <level> and the level under it (bytes of level + 1 over bytes of level)
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LevelSizeRatio {
+    pub level: Level,
+    pub observed: f64, //after the latest compaction that saw both levels hold entries
+    pub smoothed: f64, //exponentially weighted over every compaction that did
+    pub samples: Size,
+}
+
+///Observed ratios of each pair of adjacent levels, indexed by the upper level of the pair
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SizeRatioHistory {
+    ratios: Vec<Option<LevelSizeRatio>>,
+}
+
+impl SizeRatioHistory {
+    ///Weight of the newest observation in the smoothed ratio
+    const SMOOTHING: f64 = 0.25;
+    ///Factor the smoothed ratio may be off from the configured one by before a change is suggested
+    const SUGGESTION_FACTOR: f64 = 1.5;
+
+    ///Record the ratios between levels holding <level_bytes> bytes, pairs with an empty level are skipped
+    pub fn record(&mut self, level_bytes: &[Size]) {
+        if self.ratios.len() + 1 < level_bytes.len() {
+            self.ratios.resize(level_bytes.len() - 1, None);
+        }
+        for (level, pair) in level_bytes.windows(2).enumerate() {
+            let [upper, lower] = [pair[0], pair[1]];
+            if upper == 0 || lower == 0 {
+                continue;
+            }
+            let observed = lower as f64 / upper as f64;
+            self.ratios[level] = Some(match self.ratios[level] {
+                Some(previous) => LevelSizeRatio {
+                    level,
+                    observed,
+                    smoothed: previous.smoothed + Self::SMOOTHING * (observed - previous.smoothed),
+                    samples: previous.samples + 1,
+                },
+                None => LevelSizeRatio {
+                    level,
+                    observed,
+                    smoothed: observed,
+                    samples: 1,
+                },
+            });
+        }
+    }
+    ///Pairs of levels observed so far, from the top of the tree down
+    pub fn ratios(&self) -> Vec<LevelSizeRatio> {
+        self.ratios.iter().flatten().copied().collect()
+    }
+    ///Advice on sst_size_ratio if the data settled into a ratio far from <configured_ratio>
+    pub fn suggestion(ratio: &LevelSizeRatio, configured_ratio: Size) -> Option<String> {
+        let configured = configured_ratio as f64;
+        let advice = if ratio.smoothed > configured * Self::SUGGESTION_FACTOR {
+            "consider increasing"
+        } else if ratio.smoothed * Self::SUGGESTION_FACTOR < configured {
+            "consider decreasing"
+        } else {
+            return None;
+        };
+        Some(format!(
+            "configured ratio {configured_ratio}, observed {:.1}, {advice}",
+            ratio.smoothed
+        ))
+    }
+}
+
+///Bytes of entries in each level, from entry counts laid out like the database metadata (index files aren't counted)
+pub fn level_bytes(entry_counts: &[Vec<Size>]) -> Vec<Size> {
+    entry_counts
+        .iter()
+        .map(|level_entry_counts| level_entry_counts.iter().sum::<Size>() * ENTRY_SIZE)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_record() {
+        let mut history = SizeRatioHistory::default();
+        assert!(history.ratios().is_empty());
+
+        history.record(&level_bytes(&[vec![2], vec![6], vec![10, 14]]));
+        let ratios = history.ratios();
+        assert_eq!(ratios.len(), 2);
+        assert_eq!(
+            (ratios[0].level, ratios[0].observed, ratios[0].samples),
+            (0, 3.0, 1)
+        );
+        assert_eq!(
+            (ratios[1].level, ratios[1].observed, ratios[1].samples),
+            (1, 4.0, 1)
+        );
+        assert_eq!(ratios[1].smoothed, 4.0);
+
+        //level 0 is empty, only the ratio between levels 1 and 2 changes
+        history.record(&level_bytes(&[vec![], vec![3], vec![24]]));
+        let ratios = history.ratios();
+        assert_eq!(ratios[0].samples, 1);
+        assert_eq!(ratios[0].smoothed, 3.0);
+        assert_eq!((ratios[1].observed, ratios[1].samples), (8.0, 2));
+        assert_eq!(ratios[1].smoothed, 4.0 + SizeRatioHistory::SMOOTHING * 4.0);
+
+        //a new level under the others
+        history.record(&[1, 2, 4, 16]);
+        let ratios = history.ratios();
+        assert_eq!(ratios.len(), 3);
+        assert_eq!((ratios[2].level, ratios[2].observed), (2, 4.0));
+    }
+
+    #[test]
+    fn test_suggestion() {
+        let ratio = |smoothed| LevelSizeRatio {
+            level: 0,
+            observed: smoothed,
+            smoothed,
+            samples: 1,
+        };
+        assert_eq!(
+            SizeRatioHistory::suggestion(&ratio(3.4), 2).as_deref(),
+            Some("configured ratio 2, observed 3.4, consider increasing")
+        );
+        assert_eq!(
+            SizeRatioHistory::suggestion(&ratio(2.5), 8).as_deref(),
+            Some("configured ratio 8, observed 2.5, consider decreasing")
+        );
+        assert_eq!(SizeRatioHistory::suggestion(&ratio(2.9), 2), None);
+        assert_eq!(SizeRatioHistory::suggestion(&ratio(4.0), 4), None);
+    }
+}
@@ -0,0 +1,331 @@
+//!When a level merges its runs and when they move on to the next level, one strategy per CompactionPolicy. The database,
+//! the background compactor and CompactionPlan all ask the policy's strategy, so they always agree. Strategies only
+//! decide, merging and moving runs is left to the caller. A new policy is a new CompactionStrategy plus its variant
+
+use crate::{
+    ceil_div,
+    db::Database,
+    util::types::{CompactionPolicy, Level, Size},
+};
+
+///Settings every strategy's decisions depend on
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StrategySettings {
+    pub size_ratio: Size,
+    pub memtable_capacity: Size,
+}
+
+///The runs of a level, same layout as a level of the database metadata
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LevelRuns<'a> {
+    pub level: Level,
+    pub is_last_level: bool,
+    pub entry_counts: &'a [Size],
+    pub tombstone_counts: &'a [Size],
+}
+
+impl LevelRuns<'_> {
+    pub fn num_runs(&self) -> Size {
+        self.entry_counts.len()
+    }
+    pub fn num_entries(&self) -> Size {
+        self.entry_counts.iter().sum()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CompactionDecision {
+    Keep,
+    ///Merge every run of the level into one, tombstones are only dropped on the last level (nothing is under them)
+    Compact {
+        discard_tombstones: bool,
+    },
+}
+
+///Move every run of <level> to the end of the next level
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MoveRuns {
+    pub level: Level,
+}
+
+pub trait CompactionStrategy: Sync {
+    ///What a level does once runs arrive in it. Level 0 never compacts here, its runs are only moved out of it
+    /// (see Database::max_level_zero_runs for CompactionPolicy::None)
+    fn should_compact(&self, runs: &LevelRuns, settings: &StrategySettings) -> CompactionDecision;
+    ///Whether the runs of a level move on once it's compacted. <runs> is the level after compacting,
+    /// <num_runs_before> the number of runs it had before
+    fn after_compact(
+        &self,
+        runs: &LevelRuns,
+        num_runs_before: Size,
+        settings: &StrategySettings,
+    ) -> Option<MoveRuns>;
+}
+
+///The strategy implementing <compaction_policy>
+pub fn for_policy(compaction_policy: CompactionPolicy) -> &'static dyn CompactionStrategy {
+    match compaction_policy {
+        CompactionPolicy::None => &NoCompaction,
+        CompactionPolicy::Leveled => &Leveled,
+        CompactionPolicy::Tiered => &Tiered,
+        CompactionPolicy::Dostoevsky => &Dostoevsky,
+        CompactionPolicy::Lazy => &LazyLeveled,
+    }
+}
+
+fn compact_if(runs: &LevelRuns, condition: bool) -> CompactionDecision {
+    match runs.level >= 1 && condition {
+        true => CompactionDecision::Compact {
+            discard_tombstones: runs.is_last_level,
+        },
+        false => CompactionDecision::Keep,
+    }
+}
+fn move_if(runs: &LevelRuns, condition: bool) -> Option<MoveRuns> {
+    condition.then_some(MoveRuns { level: runs.level })
+}
+///Whether a tiered level holding <max_runs> runs is full, levels full of tombstones merge early so deletes meet the
+/// values they shadow sooner
+fn tiered_is_full(runs: &LevelRuns, max_runs: Size) -> bool {
+    runs.num_runs() >= max_runs
+        || Database::is_tombstone_dense(runs.entry_counts, runs.tombstone_counts)
+}
+///Whether a leveled level is full: level 1 holds up to size_ratio memtables, each level after size_ratio times more
+fn leveled_is_full(runs: &LevelRuns, settings: &StrategySettings) -> bool {
+    let StrategySettings {
+        size_ratio,
+        memtable_capacity,
+    } = *settings;
+    let lower_lvl_run_size = memtable_capacity * size_ratio.pow(runs.level as u32 - 1);
+    ceil_div!(runs.num_entries(), lower_lvl_run_size) >= size_ratio
+}
+
+///Runs pile up in level 0, see Database::max_level_zero_runs
+pub struct NoCompaction;
+
+impl CompactionStrategy for NoCompaction {
+    fn should_compact(
+        &self,
+        _runs: &LevelRuns,
+        _settings: &StrategySettings,
+    ) -> CompactionDecision {
+        CompactionDecision::Keep
+    }
+    fn after_compact(
+        &self,
+        _runs: &LevelRuns,
+        _num_runs_before: Size,
+        _settings: &StrategySettings,
+    ) -> Option<MoveRuns> {
+        None
+    }
+}
+
+///Every level is a single run, merged greedily and moved on once it's full
+pub struct Leveled;
+
+impl CompactionStrategy for Leveled {
+    fn should_compact(&self, runs: &LevelRuns, _settings: &StrategySettings) -> CompactionDecision {
+        compact_if(runs, true)
+    }
+    fn after_compact(
+        &self,
+        runs: &LevelRuns,
+        _num_runs_before: Size,
+        settings: &StrategySettings,
+    ) -> Option<MoveRuns> {
+        move_if(runs, leveled_is_full(runs, settings))
+    }
+}
+
+///Levels collect size_ratio runs, then merge them and move the merged run on
+pub struct Tiered;
+
+impl CompactionStrategy for Tiered {
+    fn should_compact(&self, runs: &LevelRuns, settings: &StrategySettings) -> CompactionDecision {
+        compact_if(runs, tiered_is_full(runs, settings.size_ratio))
+    }
+    fn after_compact(
+        &self,
+        runs: &LevelRuns,
+        num_runs_before: Size,
+        settings: &StrategySettings,
+    ) -> Option<MoveRuns> {
+        move_if(runs, num_runs_before >= settings.size_ratio)
+    }
+}
+
+///Tiered on every level but the last, which is leveled
+pub struct Dostoevsky;
+
+impl CompactionStrategy for Dostoevsky {
+    fn should_compact(&self, runs: &LevelRuns, settings: &StrategySettings) -> CompactionDecision {
+        compact_if(
+            runs,
+            runs.is_last_level || tiered_is_full(runs, settings.size_ratio),
+        )
+    }
+    fn after_compact(
+        &self,
+        runs: &LevelRuns,
+        num_runs_before: Size,
+        settings: &StrategySettings,
+    ) -> Option<MoveRuns> {
+        match runs.is_last_level {
+            true => move_if(runs, leveled_is_full(runs, settings)),
+            false => move_if(runs, num_runs_before >= settings.size_ratio),
+        }
+    }
+}
+
+///Lazy leveling: like Dostoevsky, but levels above the last only collect size_ratio - 1 runs (at least one) before
+/// they merge and move on, so the last level (leveled) holds more of the data and fewer runs are searched above it
+pub struct LazyLeveled;
+
+impl LazyLeveled {
+    fn max_upper_level_runs(settings: &StrategySettings) -> Size {
+        settings.size_ratio.saturating_sub(1).max(1)
+    }
+}
+
+impl CompactionStrategy for LazyLeveled {
+    fn should_compact(&self, runs: &LevelRuns, settings: &StrategySettings) -> CompactionDecision {
+        let max_runs = Self::max_upper_level_runs(settings);
+        compact_if(runs, runs.is_last_level || tiered_is_full(runs, max_runs))
+    }
+    fn after_compact(
+        &self,
+        runs: &LevelRuns,
+        num_runs_before: Size,
+        settings: &StrategySettings,
+    ) -> Option<MoveRuns> {
+        match runs.is_last_level {
+            true => move_if(runs, leveled_is_full(runs, settings)),
+            false => move_if(
+                runs,
+                num_runs_before >= Self::max_upper_level_runs(settings),
+            ),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use CompactionDecision::*;
+
+    const SETTINGS: StrategySettings = StrategySettings {
+        size_ratio: 3,
+        memtable_capacity: 4,
+    };
+
+    fn runs<'a>(level: Level, is_last_level: bool, entry_counts: &'a [Size]) -> LevelRuns<'a> {
+        const NO_TOMBSTONES: [Size; 8] = [0; 8];
+        LevelRuns {
+            level,
+            is_last_level,
+            entry_counts,
+            tombstone_counts: &NO_TOMBSTONES[..entry_counts.len()],
+        }
+    }
+    fn decide(policy: CompactionPolicy, runs: &LevelRuns) -> CompactionDecision {
+        for_policy(policy).should_compact(runs, &SETTINGS)
+    }
+    fn moves(policy: CompactionPolicy, runs: &LevelRuns, num_runs_before: Size) -> bool {
+        let move_runs = for_policy(policy).after_compact(runs, num_runs_before, &SETTINGS);
+        assert!(move_runs.is_none_or(|move_runs| move_runs.level == runs.level));
+        move_runs.is_some()
+    }
+    const LAST: Option<CompactionDecision> = Some(Compact {
+        discard_tombstones: true,
+    });
+    const UPPER: Option<CompactionDecision> = Some(Compact {
+        discard_tombstones: false,
+    });
+
+    #[test]
+    fn test_level_zero_never_compacts() {
+        for policy in [
+            CompactionPolicy::None,
+            CompactionPolicy::Leveled,
+            CompactionPolicy::Tiered,
+            CompactionPolicy::Dostoevsky,
+            CompactionPolicy::Lazy,
+        ] {
+            assert_eq!(decide(policy, &runs(0, true, &[1, 1, 1, 1])), Keep);
+        }
+    }
+
+    #[test]
+    fn test_none() {
+        let policy = CompactionPolicy::None;
+        assert_eq!(decide(policy, &runs(1, true, &[1, 1, 1])), Keep);
+        assert!(!moves(policy, &runs(1, true, &[100]), 3));
+    }
+
+    #[test]
+    fn test_leveled() {
+        let policy = CompactionPolicy::Leveled;
+        assert_eq!(Some(decide(policy, &runs(1, true, &[1]))), LAST);
+        assert_eq!(Some(decide(policy, &runs(1, false, &[1, 1]))), UPPER);
+        //level 1 holds up to size_ratio memtables, level 2 size_ratio times more
+        assert!(!moves(policy, &runs(1, true, &[8]), 1));
+        assert!(moves(policy, &runs(1, true, &[12]), 1));
+        assert!(!moves(policy, &runs(2, true, &[12]), 1));
+        assert!(moves(policy, &runs(2, true, &[36]), 1));
+    }
+
+    #[test]
+    fn test_tiered() {
+        let policy = CompactionPolicy::Tiered;
+        assert_eq!(decide(policy, &runs(1, false, &[1, 1])), Keep);
+        assert_eq!(Some(decide(policy, &runs(1, false, &[1, 1, 1]))), UPPER);
+        let dense = LevelRuns {
+            tombstone_counts: &[4, 4],
+            ..runs(1, false, &[4, 4])
+        };
+        assert_eq!(Some(decide(policy, &dense)), UPPER);
+        assert!(moves(policy, &runs(1, false, &[12]), 3));
+        assert!(!moves(policy, &runs(1, false, &[12]), 2)); //compacted early for its tombstones
+    }
+
+    #[test]
+    fn test_dostoevsky() {
+        let policy = CompactionPolicy::Dostoevsky;
+        assert_eq!(Some(decide(policy, &runs(2, true, &[1]))), LAST);
+        assert_eq!(decide(policy, &runs(1, false, &[1, 1])), Keep);
+        assert_eq!(Some(decide(policy, &runs(1, false, &[1, 1, 1]))), UPPER);
+        assert!(!moves(policy, &runs(2, true, &[4]), 3));
+        assert!(moves(policy, &runs(2, true, &[36]), 1));
+        assert!(moves(policy, &runs(1, false, &[12]), 3));
+        assert!(!moves(policy, &runs(1, false, &[8]), 2));
+    }
+
+    #[test]
+    fn test_lazy() {
+        let policy = CompactionPolicy::Lazy;
+        assert_eq!(Some(decide(policy, &runs(2, true, &[1]))), LAST);
+        //upper levels merge at size_ratio - 1 runs, one sooner than Dostoevsky
+        assert_eq!(decide(policy, &runs(1, false, &[1])), Keep);
+        assert_eq!(Some(decide(policy, &runs(1, false, &[1, 1]))), UPPER);
+        assert!(moves(policy, &runs(1, false, &[8]), 2));
+        assert!(!moves(policy, &runs(1, false, &[4]), 1));
+        //the last level is leveled
+        assert!(!moves(policy, &runs(2, true, &[12]), 2));
+        assert!(moves(policy, &runs(2, true, &[36]), 1));
+
+        //with a size ratio of 2 upper levels hold a single run, which moves on as soon as it arrives
+        let settings = StrategySettings {
+            size_ratio: 2,
+            ..SETTINGS
+        };
+        let strategy = for_policy(policy);
+        assert_eq!(
+            Some(strategy.should_compact(&runs(1, false, &[4]), &settings)),
+            UPPER
+        );
+        assert!(strategy
+            .after_compact(&runs(1, false, &[4]), 1, &settings)
+            .is_some());
+    }
+}
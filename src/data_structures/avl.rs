@@ -1,6 +1,6 @@
 type NodePtr<K, V> = Option<Box<AvlNode<K, V>>>;
 
-#[derive(Debug, Default, PartialEq)]
+#[derive(Debug, Default, Clone, PartialEq)]
 struct AvlNode<K, V> {
     key: K,
     value: V,
@@ -133,7 +133,7 @@ impl<K: Clone + std::cmp::PartialOrd + std::fmt::Display, V: Clone> AvlNode<K, V
     }
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct AvlTree<K, V> {
     root: NodePtr<K, V>,
     len: usize,
@@ -148,6 +148,28 @@ impl<K: Clone + std::cmp::PartialOrd + std::fmt::Display, V: Clone> AvlTree<K, V
     pub fn len(&self) -> usize {
         self.len
     }
+    ///Builds a balanced tree out of <entries>, whose keys have to be strictly increasing. Takes linear time, unlike
+    /// inserting the entries one at a time
+    pub fn from_sorted(entries: Vec<(K, V)>) -> AvlTree<K, V> {
+        let len = entries.len();
+        let root = Self::build_sorted(&mut entries.into_iter(), len);
+        AvlTree { root, len }
+    }
+    ///Subtree of the next <len> entries with the middle one at its root, so sibling heights differ by at most one
+    fn build_sorted(entries: &mut impl Iterator<Item = (K, V)>, len: usize) -> NodePtr<K, V> {
+        if len == 0 {
+            return None;
+        }
+        let left = Self::build_sorted(entries, len / 2);
+        let (key, value) = entries
+            .next()
+            .expect("Fewer entries than the tree's length");
+        let mut node = AvlNode::new(key, value);
+        node.left = left;
+        node.right = Self::build_sorted(entries, len - len / 2 - 1);
+        node.recalc_height();
+        Some(Box::new(node))
+    }
     ///Insert key-value pair into Avl Subtree using recursion, returns true iff size size increases
     fn insert_recursive(subtree: &mut Box<AvlNode<K, V>>, key: K, value: V) -> bool {
         if subtree.key() == key {
@@ -213,6 +235,40 @@ impl<K: Clone + std::cmp::PartialOrd + std::fmt::Display, V: Clone> AvlTree<K, V
             Self::search_node_recursive(root, key).map(|result_node| result_node.value())
         })
     }
+    ///Smallest key >= <key>, along with its value
+    pub fn ceiling(&self, key: K) -> Option<(K, V)> {
+        let mut best: Option<&AvlNode<K, V>> = None;
+        let mut current = self.root.as_deref();
+        while let Some(node) = current {
+            if node.key == key {
+                return Some((node.key(), node.value()));
+            }
+            if node.key > key {
+                best = Some(node); //candidate, but there might be a smaller one on the left
+                current = node.left().as_deref();
+            } else {
+                current = node.right().as_deref();
+            }
+        }
+        best.map(|node| (node.key(), node.value()))
+    }
+    ///Largest key <= <key>, along with its value
+    pub fn floor(&self, key: K) -> Option<(K, V)> {
+        let mut best: Option<&AvlNode<K, V>> = None;
+        let mut current = self.root.as_deref();
+        while let Some(node) = current {
+            if node.key == key {
+                return Some((node.key(), node.value()));
+            }
+            if node.key < key {
+                best = Some(node); //candidate, but there might be a larger one on the right
+                current = node.right().as_deref();
+            } else {
+                current = node.left().as_deref();
+            }
+        }
+        best.map(|node| (node.key(), node.value()))
+    }
     // pub fn min_key(&self) -> Option<K> {
     //     self.root.as_ref().map(|node| {
     //         let mut current = node;
@@ -320,6 +376,7 @@ impl<K: Clone + std::cmp::PartialOrd + std::fmt::Display, V: Clone> AvlTree<K, V
             Self::for_each_recursive(node.right().as_deref(), callback);
         }
     }
+    #[allow(dead_code)]
     pub fn for_each(&self, callback: &mut dyn FnMut(&K, &V)) {
         Self::for_each_recursive(self.root.as_deref(), callback);
     }
@@ -330,6 +387,64 @@ impl<K: Clone + std::cmp::PartialOrd + std::fmt::Display, V: Clone> AvlTree<K, V
             }
         });
     }
+    ///Reverse inorder traversal of the keys in [key1, key2], only descends into subtrees that can hold keys in range
+    fn for_each_in_range_rev_recursive(
+        node: Option<&AvlNode<K, V>>,
+        key1: &K,
+        key2: &K,
+        callback: &mut dyn FnMut(&K, &V),
+    ) {
+        if let Some(node) = node {
+            if node.key < *key2 {
+                Self::for_each_in_range_rev_recursive(
+                    node.right().as_deref(),
+                    key1,
+                    key2,
+                    callback,
+                );
+            }
+            if node.key >= *key1 && node.key <= *key2 {
+                callback(&node.key, &node.value);
+            }
+            if node.key > *key1 {
+                Self::for_each_in_range_rev_recursive(node.left().as_deref(), key1, key2, callback);
+            }
+        }
+    }
+    ///Same as for_each_in_range, but from the largest key to the smallest
+    pub fn for_each_in_range_rev(&self, key1: &K, key2: &K, callback: &mut dyn FnMut(&K, &V)) {
+        Self::for_each_in_range_rev_recursive(self.root.as_deref(), key1, key2, callback);
+    }
+    ///Inorder traversal that can be paused, like for_each
+    pub fn iter(&self) -> Iter<'_, K, V> {
+        let mut iter = Iter { stack: Vec::new() };
+        iter.push_left_spine(self.root.as_deref());
+        iter
+    }
+}
+
+///Inorder iterator over the entries of an AvlTree, holds at most one node per level of the tree
+pub struct Iter<'a, K, V> {
+    stack: Vec<&'a AvlNode<K, V>>, //nodes whose left subtree is being visited, the next node to yield is on top
+}
+
+impl<'a, K, V> Iter<'a, K, V> {
+    fn push_left_spine(&mut self, mut node: Option<&'a AvlNode<K, V>>) {
+        while let Some(current) = node {
+            self.stack.push(current);
+            node = current.left.as_deref();
+        }
+    }
+}
+
+impl<'a, K, V> Iterator for Iter<'a, K, V> {
+    type Item = (&'a K, &'a V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let node = self.stack.pop()?;
+        self.push_left_spine(node.right.as_deref());
+        Some((&node.key, &node.value))
+    }
 }
 
 #[cfg(test)]
@@ -472,6 +587,39 @@ mod tests {
         assert_eq!(tree, get_big_balanced_tree());
     }
 
+    #[test]
+    fn test_from_sorted() {
+        assert_eq!(
+            AvlTree::from_sorted(vec![("a", 1), ("b", 2), ("c", 3)]),
+            get_balanced_tree()
+        );
+        assert_eq!(AvlTree::<&str, u64>::from_sorted(vec![]), AvlTree::new());
+
+        //heights are right and every node is balanced, so inserts keep rebalancing it correctly
+        fn check_heights(node: &NodePtr<u64, u64>) -> i32 {
+            let Some(node) = node else {
+                return AvlNode::<u64, u64>::NONE_HEIGHT;
+            };
+            let [left, right] = [check_heights(&node.left), check_heights(&node.right)];
+            assert!((right - left).abs() <= 1, "unbalanced at {}", node.key);
+            assert_eq!(node.height as i32, 1 + left.max(right));
+            node.height as i32
+        }
+        for len in [1, 2, 5, 64, 1000] {
+            let mut tree = AvlTree::from_sorted((0..len).map(|key| (key * 2, key)).collect());
+            assert_eq!(tree.len(), len as usize);
+            check_heights(&tree.root);
+            assert_eq!(tree.search((len - 1) * 2), Some(len - 1));
+            assert_eq!(tree.search(1), None);
+            for key in 0..len {
+                tree.insert(key * 2 + 1, key);
+            }
+            check_heights(&tree.root);
+            let keys: Vec<u64> = tree.iter().map(|(key, _)| *key).collect();
+            assert_eq!(keys, (0..len * 2).collect::<Vec<u64>>());
+        }
+    }
+
     #[test]
     fn test_search_at_root() {
         assert_eq!(2, get_balanced_tree().search("b").unwrap());
@@ -482,6 +630,36 @@ mod tests {
         assert_eq!(6, get_big_balanced_tree().search("f").unwrap());
     }
 
+    #[test]
+    fn test_ceiling_and_floor() {
+        let tree = get_big_balanced_tree(); //keys "a" to "f"
+        assert_eq!(tree.ceiling("c"), Some(("c", 3)));
+        assert_eq!(tree.floor("c"), Some(("c", 3)));
+        assert_eq!(tree.ceiling("cc"), Some(("d", 4)));
+        assert_eq!(tree.floor("cc"), Some(("c", 3)));
+
+        //boundaries
+        assert_eq!(tree.ceiling("0"), Some(("a", 1)));
+        assert_eq!(tree.floor("0"), None);
+        assert_eq!(tree.ceiling("z"), None);
+        assert_eq!(tree.floor("z"), Some(("f", 6)));
+
+        let empty = AvlTree::<&str, u64>::new();
+        assert_eq!(empty.ceiling("a"), None);
+        assert_eq!(empty.floor("a"), None);
+    }
+
+    #[test]
+    fn test_iter() {
+        let tree = get_big_balanced_tree();
+        let mut expected = Vec::new();
+        tree.for_each(&mut |key, value| expected.push((*key, *value)));
+        let entries: Vec<(&str, u64)> = tree.iter().map(|(key, value)| (*key, *value)).collect();
+        assert_eq!(entries, expected);
+        assert_eq!(entries.len(), 6);
+        assert_eq!(AvlTree::<&str, u64>::new().iter().next(), None);
+    }
+
     #[test]
     fn test_take_min() {
         let mut tree = get_big_balanced_tree();
@@ -99,10 +99,7 @@ impl<K: Hash + Eq + Debug, V: Debug> Bucket<K, V> {
         for (index, element) in elements.iter().enumerate() {
             if element.0 == key && index < self.elements.len() {
                 // let output = self.elements[index];
-                let removed = match self.remove_element(index) {
-                    Some(e) => e,
-                    None => return None,
-                };
+                let removed = self.remove_element(index)?;
 
                 self.add_element_ignore(removed);
                 let element = self.elements.get(self.get_size() - 1);
@@ -161,16 +158,27 @@ impl<K: Debug, V: Debug> Default for Bucket<K, V> {
 impl<K: Hash + Eq + Debug + Clone, V: Debug + Clone, H: Hasher + Default + Debug>
     ExtendibleHashTable<K, V, H>
 {
+    ///Directory doubling stops here (2^16 slots), after that a full bucket is allowed to overflow
+    const MAX_GLOBAL_DEPTH: usize = 16;
+
     pub fn with_capacity_buckets(
         bucket_capacity: usize,
         num_buckets: usize,
         dir_size: usize,
     ) -> Self {
+        let global_depth = (dir_size).ilog2() as usize;
+        //NOTE: each directory slot starts with its own bucket, so a bucket's local depth must match the global depth,
+        //      otherwise splitting it overwrites directory slots that belong to other buckets (and loses their elements)
+        let local_depth = if num_buckets >= dir_size {
+            global_depth
+        } else {
+            1
+        };
         let mut buckets = Vec::with_capacity(num_buckets);
         for i in 0..num_buckets {
             buckets.push(Rc::new(RefCell::new(Bucket::new(
                 bucket_capacity,
-                1,
+                local_depth,
                 i + 1,
             ))));
         }
@@ -180,7 +188,7 @@ impl<K: Hash + Eq + Debug + Clone, V: Debug + Clone, H: Hasher + Default + Debug
 
         ExtendibleHashTable {
             directory,
-            global_depth: (dir_size).ilog2() as usize,
+            global_depth,
             current_size: 0,
             hasher: H::default(),
             num_buckets,
@@ -248,10 +256,7 @@ impl<K: Hash + Eq + Debug + Clone, V: Debug + Clone, H: Hasher + Default + Debug
 
         if let Some(index) = index_to_remove {
             let mut bucket = bucket.borrow_mut();
-            let element = match bucket.remove_element(index) {
-                Some(e) => e,
-                None => return None,
-            };
+            let element = bucket.remove_element(index)?;
             bucket.add_element_ignore(element);
             let elements = bucket.get_elements();
             let last_element = match elements.iter().last() {
@@ -280,91 +285,111 @@ impl<K: Hash + Eq + Debug + Clone, V: Debug + Clone, H: Hasher + Default + Debug
                     return false;
                 }
             };
-            added = bucket.add_element_ignore((key, value));
+            added = bucket.add_element_ignore((key.clone(), value));
         } // Mutable borrow ends here
 
         if added {
             self.current_size += 1;
         }
 
-        let (local_depth, is_full) = {
-            let bucket = match self.get_bucket(index) {
-                Some(bucket) => bucket.borrow(),
-                None => {
-                    return false;
-                }
+        //keep splitting until the bucket our key landed in has room, all of its elements can rehash into the same child
+        //NOTE: past MAX_GLOBAL_DEPTH we stop doubling the directory and let the bucket overflow instead
+        loop {
+            let index = self.hash_key(&key) as usize;
+            let (local_depth, is_full) = {
+                let bucket = match self.get_bucket(index) {
+                    Some(bucket) => bucket.borrow(),
+                    None => {
+                        return false;
+                    }
+                };
+                (bucket.get_local_depth(), bucket.is_full())
             };
-            (bucket.get_local_depth(), bucket.is_full())
-        };
+            if !is_full || local_depth >= Self::MAX_GLOBAL_DEPTH {
+                break;
+            }
+            self.split_bucket(index, local_depth);
+        }
+        added
+    }
 
-        if is_full {
-            let global_depth = self.get_global_depth();
-            if local_depth == global_depth {
-                // need to double since can't split this bucket further
-                self.global_depth += 1;
-                let new_directory_size = 2usize.pow(self.global_depth as u32);
-                let mut new_directory: Vec<Rc<RefCell<Bucket<K, V>>>> =
-                    Vec::with_capacity(new_directory_size);
-                for index in 0..new_directory_size {
-                    new_directory.push(
-                        self.directory
-                            [truncate_binary(index as u64, self.global_depth - 1) as usize]
-                            .clone(),
-                    );
-                }
-                self.directory = new_directory;
+    ///Split the bucket at directory index <index> into two buckets with a local depth one higher, doubling the directory if needed
+    fn split_bucket(&mut self, index: usize, local_depth: usize) {
+        let global_depth = self.get_global_depth();
+        if local_depth == global_depth {
+            // need to double since can't split this bucket further
+            self.global_depth += 1;
+            let new_directory_size = 2usize.pow(self.global_depth as u32);
+            let mut new_directory: Vec<Rc<RefCell<Bucket<K, V>>>> =
+                Vec::with_capacity(new_directory_size);
+            for index in 0..new_directory_size {
+                new_directory.push(
+                    self.directory[truncate_binary(index as u64, self.global_depth - 1) as usize]
+                        .clone(),
+                );
             }
+            self.directory = new_directory;
+        }
 
-            let bucket = match self.get_bucket(index) {
-                Some(bucket) => bucket.borrow(),
-                None => {
-                    return false;
-                }
-            };
-            let bucket1 = Rc::new(RefCell::new(Bucket::new(
-                bucket.capacity,
-                local_depth + 1,
-                self.num_buckets + 1,
-            )));
-            let bucket2 = Rc::new(RefCell::new(Bucket::new(
-                bucket.capacity,
-                local_depth + 1,
-                self.num_buckets + 2,
-            )));
-            drop(bucket);
-            self.buckets.push(Rc::clone(&bucket1));
-            self.buckets.push(Rc::clone(&bucket2));
-            let mut bucket = match self.get_bucket_mut(index) {
-                Some(bucket) => bucket.borrow_mut(),
-                None => {
-                    return false;
-                }
-            };
+        let old_bucket = Rc::clone(&self.directory[index]);
+        let capacity = old_bucket.borrow().capacity;
+        let bucket1 = Rc::new(RefCell::new(Bucket::new(
+            capacity,
+            local_depth + 1,
+            self.num_buckets + 1,
+        )));
+        let bucket2 = Rc::new(RefCell::new(Bucket::new(
+            capacity,
+            local_depth + 1,
+            self.num_buckets + 2,
+        )));
+        self.num_buckets += 2;
+
+        //replace the old bucket in the bucket list so the clock (eviction) never visits a dead bucket
+        match self
+            .buckets
+            .iter()
+            .position(|bucket| Rc::ptr_eq(bucket, &old_bucket))
+        {
+            Some(position) => self.buckets[position] = Rc::clone(&bucket1),
+            None => self.buckets.push(Rc::clone(&bucket1)),
+        }
+        self.buckets.push(Rc::clone(&bucket2));
 
-            let high_bit = bucket.get_high_bit();
-            let elements = std::mem::take(&mut bucket.elements);
-            drop(bucket);
+        let high_bit = old_bucket.borrow().get_high_bit();
+        let elements = std::mem::take(&mut old_bucket.borrow_mut().elements);
 
-            for element in elements {
-                let index = self.hash_key(&element.0);
-                if index & high_bit == 0 {
-                    bucket1.borrow_mut().add_element_ignore(element);
-                } else {
-                    bucket2.borrow_mut().add_element_ignore(element);
-                }
+        for element in elements {
+            let index = self.hash_key(&element.0);
+            if index & high_bit == 0 {
+                bucket1.borrow_mut().add_element_ignore(element);
+            } else {
+                bucket2.borrow_mut().add_element_ignore(element);
             }
+        }
 
-            for i in ((index as u64 & (high_bit - 1))..self.directory.len() as u64)
-                .step_by(high_bit as usize)
-            {
-                if i & high_bit == 0 {
-                    self.add_to_directory(bucket1.clone(), i as usize);
-                } else {
-                    self.add_to_directory(bucket2.clone(), i as usize);
-                }
+        for i in ((index as u64 & (high_bit - 1))..self.directory.len() as u64)
+            .step_by(high_bit as usize)
+        {
+            if i & high_bit == 0 {
+                self.add_to_directory(bucket1.clone(), i as usize);
+            } else {
+                self.add_to_directory(bucket2.clone(), i as usize);
             }
         }
-        added
+    }
+
+    ///Number of elements over the total capacity of all buckets
+    pub fn load_factor(&self) -> f64 {
+        let total_capacity: usize = self
+            .buckets
+            .iter()
+            .map(|bucket| bucket.borrow().capacity)
+            .sum();
+        if total_capacity == 0 {
+            return 0.0;
+        }
+        self.current_size as f64 / total_capacity as f64
     }
 
     pub fn remove(&mut self, key: &K) -> Option<(K, V)> {
@@ -409,8 +434,30 @@ impl<K: Hash + Eq + Debug + Clone, V: Debug + Clone, H: Hasher + Default + Debug
     }
     ///Removes the least recently used element in the the bucket at index <bucket_index>.
     /// Returns key if successfully removed an element
-    pub fn bucket_remove_lru(&mut self, bucket_index: usize) -> Option<K> {
-        self.bucket_pop_front(bucket_index).map(|(key, ..)| key) //NOTE: elements are moved to the back on access, so front is least recently accessed
+    pub fn bucket_remove_lru(&mut self, bucket_index: usize) -> Option<(K, V)> {
+        self.bucket_pop_front(bucket_index) //NOTE: elements are moved to the back on access, so front is least recently accessed
+    }
+    ///Removes the least recently used element of the bucket at index <bucket_index> that <removable> accepts, the ones
+    /// it skips (e.g. pinned frames, see BufferPool::get_pinned) keep their place
+    pub fn bucket_remove_lru_where(
+        &mut self,
+        bucket_index: usize,
+        removable: impl Fn(&V) -> bool,
+    ) -> Option<(K, V)> {
+        let mut bucket = self.buckets[bucket_index].borrow_mut();
+        let index = bucket
+            .get_elements()
+            .iter()
+            .position(|(_, value)| removable(value))?;
+        let removed = bucket.remove_element(index)?;
+        self.current_size -= 1;
+        Some(removed)
+    }
+    ///Calls <f> on the value of <key> without counting it as an access
+    pub fn peek<R>(&self, key: &K, f: impl FnOnce(&V) -> R) -> Option<R> {
+        let bucket = self.get_bucket(self.hash_key(key) as usize)?.borrow();
+        let (_, value) = bucket.get_elements().iter().find(|(k, _)| k == key)?;
+        Some(f(value))
     }
     fn hash_key(&self, key: &K) -> u64 {
         let mut hasher: H = H::default();
@@ -765,6 +812,30 @@ mod extendible_hash_table_tests {
         assert!(!hash_table.accessed(1));
     }
 
+    #[test]
+    fn test_remove_lru_where() {
+        let mut hash_table =
+            ExtendibleHashTable::<i32, i32, DefaultHasher>::with_capacity_buckets(16, 1, 1);
+        for i in 0..4 {
+            hash_table.put(i, i * 100);
+        }
+        hash_table.get(&0); //most recently used now
+
+        //skipped elements keep their place, the next one that is accepted goes
+        assert_eq!(
+            hash_table.bucket_remove_lru_where(0, |value| *value != 100),
+            Some((2, 200))
+        );
+        assert_eq!(hash_table.bucket_remove_lru_where(0, |_| false), None);
+        assert_eq!(hash_table.len(), 3);
+        assert_eq!(hash_table.bucket_remove_lru(0), Some((1, 100)));
+
+        //peeking doesn't count as an access
+        assert_eq!(hash_table.peek(&3, |value| *value + 1), Some(301));
+        assert_eq!(hash_table.peek(&2, |value| *value), None);
+        assert_eq!(hash_table.bucket_remove_lru(0), Some((3, 300)));
+    }
+
     #[test]
     fn test_small_bucket_size() {
         let mut hash_table = ExtendibleHashTable::<i32, i32, DefaultHasher>::new(2);
@@ -790,4 +861,68 @@ mod extendible_hash_table_tests {
             assert_eq!(hash_table.get(&i).unwrap(), 10000000);
         }
     }
+
+    ///Hashes keys to themselves shifted left, so every key has the same low bits and lands in the same bucket
+    /// until the directory is deep enough to tell them apart
+    #[derive(Debug, Default)]
+    struct CollidingHasher {
+        state: u64,
+    }
+
+    impl Hasher for CollidingHasher {
+        fn finish(&self) -> u64 {
+            self.state << 8
+        }
+        fn write(&mut self, bytes: &[u8]) {
+            for (i, byte) in bytes.iter().enumerate().take(8) {
+                self.state |= (*byte as u64) << (8 * i);
+            }
+        }
+    }
+
+    #[test]
+    fn test_colliding_keys_split_until_within_capacity() {
+        let capacity = 4;
+        let mut hash_table = ExtendibleHashTable::<i32, i32, CollidingHasher>::new(capacity);
+        let iters = 500;
+        for i in 0..iters {
+            hash_table.put(i, i);
+        }
+
+        for bucket in hash_table.get_directory() {
+            assert!(bucket.borrow().get_size() <= 2 * capacity);
+        }
+        for i in 0..iters {
+            assert_eq!(hash_table.get(&i), Some(i));
+        }
+        assert!(hash_table.load_factor() > 0.0 && hash_table.load_factor() <= 1.0);
+    }
+
+    #[derive(Debug, Default)]
+    struct ConstantHasher;
+
+    impl Hasher for ConstantHasher {
+        fn finish(&self) -> u64 {
+            0
+        }
+        fn write(&mut self, _bytes: &[u8]) {}
+    }
+
+    #[test]
+    fn test_identical_hashes_overflow_at_max_depth() {
+        let mut hash_table = ExtendibleHashTable::<i32, i32, ConstantHasher>::new(2);
+        let iters = 20;
+        for i in 0..iters {
+            hash_table.put(i, i);
+        }
+
+        assert_eq!(
+            hash_table.get_global_depth(),
+            ExtendibleHashTable::<i32, i32, ConstantHasher>::MAX_GLOBAL_DEPTH
+        );
+        for i in 0..iters {
+            assert_eq!(hash_table.get(&i), Some(i));
+        }
+        assert_eq!(hash_table.len(), iters as usize);
+    }
 }
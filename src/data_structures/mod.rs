@@ -1,2 +1,3 @@
 pub mod avl;
+#[cfg(feature = "buffer-pool")]
 pub mod extendible_hash_table;
@@ -1,39 +1,150 @@
+#[cfg(feature = "serde-config")]
 use serde::{Deserialize, Serialize};
 use std::{
-    collections::{BinaryHeap, HashSet},
-    fs,
-    path::Path,
+    cell::{Cell, RefCell},
+    cmp::Reverse,
+    collections::{BTreeMap, BinaryHeap, HashSet},
+    fmt,
+    io::{self, BufRead},
+    mem,
+    ops::{ControlFlow, Range},
+    path::{Path, PathBuf},
+    rc::Rc,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex, MutexGuard,
+    },
+    thread,
+    time::{Duration, Instant},
 };
 
 use crate::{
-    buffer_pool::BufferPool,
-    ceil_div,
-    file_io::{direct_io, file_interface},
-    filter::{bloom_filter::BloomFilter, bloom_io::BloomFilterIO},
-    memtable::Memtable,
-    sst::{array_sst, btree_sst, SortedStringTable},
-    util::{filename, types::SstSearchAlgorithm},
+    buffer_pool::{BufferPool, BufferPoolStats},
+    compaction::{
+        background_compactor::{BackgroundCompactor, CompactionSettings, LevelState},
+        cascade::{self, FileChange, Levels, RunLists},
+        garbage::{self, KeySketch},
+        merge::{self, run_paths, CompactionProgress},
+        partition::{self, KeyRange, PartitionRun},
+        plan::CompactionPlan,
+        size_ratio::{self, LevelSizeRatio, SizeRatioHistory},
+        strategy::{self, CompactionDecision, LevelRuns, StrategySettings},
+    },
+    disk_usage::{self, DiskUsage},
+    error::DbError,
+    event::DbEvent,
+    explain::{
+        BloomProbe, GetTrace, MemtableCheck, Resolution, RunOutcome, RunTrace, SearchMethod,
+    },
+    file_io::{
+        file_interface,
+        io_stats::{self, IoStats},
+        page_buffers::{self, PageBufferStats},
+        serde_entry,
+        serde_state::{self, impl_struct_codec},
+        storage::Storage,
+    },
+    filter::bloom_io::{BloomFilterBuilder, BloomFilterIO},
+    flush_audit::{self, AuditCheck, FlushAudit},
+    health::{CheckStatus, HealthCheck, HealthReport},
+    import::{self, Delimiter, ImportOptions},
+    level_shape::LevelShape,
+    maintenance::{MaintenanceScheduler, MaintenanceTask, RunStats},
+    memtable::{Memtable, MemtableHit},
+    read_cache::ReadCache,
+    salvage::{self, SalvageReport},
+    snapshot::{self, Snapshot},
+    sst::{
+        array_sst, compression, expiry, fence, operands, sst_util, MergeStats, RunCursor,
+        SortedStringTable,
+    },
+    stats::DbStats,
     util::{
+        clock::{Clock, ManualClock, SystemClock},
         system_info::{self, ENTRY_SIZE},
-        types::{CompactionPolicy, Entry, Key, Level, Run, Size, SstImplementation, Value},
+        types::{
+            BloomLevels, CompactionPolicy, Compression, DatabaseName, Entry, IoMode, Key, KeyCodec,
+            Level, LevelZeroOverflowBehavior, MergeOperator, Page, Run, RunAddress, Sequence, Size,
+            SstImplementation, StallBehavior, SyncPolicy, Timestamp, Value, VersionMerge, WarmUp,
+        },
+        yield_hook::{self, YieldHook},
+    },
+    util::{
+        filename::{self, RunPaths},
+        types::SstSearchAlgorithm,
     },
 };
 
-#[derive(Serialize, Deserialize, Debug)]
+#[cfg_attr(feature = "serde-config", derive(Serialize, Deserialize))]
+#[derive(Debug, Clone)]
 struct Config {
     memtable_capacity: Size, //in terms of number of entries
     sst_size_ratio: Size,    //size ratio between sst levels
     sst_implementation: SstImplementation,
     sst_search_algorithm: SstSearchAlgorithm,
     enable_buffer_pool: bool,
-    buffer_pool_capacity: Size,
     buffer_pool_initial_size: Size,
     compaction_policy: CompactionPolicy,
-    enable_bloom_filter: bool,
+    bloom_levels: BloomLevels,
     bloom_filter_bits_per_entry: Size,
+    key_codec: KeyCodec, //on disk key encoding, cannot change once SSTs exist
+    background_compaction: bool,
+    buffer_pool_capacity: Size, //in bytes
+    enable_fence_index: bool,
+    compaction_memory_budget: Size, //in bytes, 0 for no limit
+    io_mode: IoMode,
+    merge_operator: MergeOperator, //how Database::merge combines operands with values
+    max_level_zero_runs: Size, //most runs level 0 holds under CompactionPolicy::None, 0 for no limit
+    level_zero_overflow: LevelZeroOverflowBehavior,
+    persist_buffer_pool: bool, //save which pages are in the buffer pool on close, see set_persist_buffer_pool
+    warm_up: WarmUp,
+    max_garbage_ratio: Option<f64>, //compact a level once this fraction of it is garbage, see set_max_garbage_ratio
+    level_zero_slowdown_runs: Size, //writes sleep once level 0 holds this many runs, 0 for never
+    level_zero_stop_runs: Size,     //writes stall once level 0 holds this many runs, 0 for never
+    level_zero_slowdown_base_micros: u64, //how long the first slowed down write sleeps
+    stall_behavior: StallBehavior,
+    partitioned_levels: bool, //split leveled levels into runs by key range, see set_partitioned_levels
+    version_merge: VersionMerge, //what a put does with the version of its key under it
+    sync_policy: SyncPolicy,  //which writes are flushed to storage, see set_sync_policy
+    compression: Option<Compression>, //how the pages of new runs are compressed, None to write them as they are
 }
 
+impl_struct_codec!(Config {
+    memtable_capacity,
+    sst_size_ratio,
+    sst_implementation,
+    sst_search_algorithm,
+    enable_buffer_pool,
+    buffer_pool_initial_size,
+    compaction_policy,
+    bloom_levels,
+    bloom_filter_bits_per_entry,
+    key_codec,
+    background_compaction,
+    buffer_pool_capacity,
+    enable_fence_index,
+    compaction_memory_budget,
+    io_mode,
+    merge_operator,
+    max_level_zero_runs,
+    level_zero_overflow,
+    persist_buffer_pool,
+    warm_up,
+    max_garbage_ratio,
+    level_zero_slowdown_runs,
+    level_zero_stop_runs,
+    level_zero_slowdown_base_micros,
+    stall_behavior,
+    partitioned_levels,
+    version_merge,
+    sync_policy,
+    compression,
+});
+
 impl Config {
+    ///Layout version written to the config file, unversioned files are migrated when read
+    const VERSION: u32 = 1;
+
     fn new() -> Self {
         Self {
             memtable_capacity: system_info::num_entries_per_page(),
@@ -41,43 +152,575 @@ impl Config {
             sst_implementation: SstImplementation::Array,
             sst_search_algorithm: SstSearchAlgorithm::Default,
             enable_buffer_pool: true,
-            buffer_pool_capacity: Database::DEFAULT_BUFFER_POOL_CAPACITY,
             buffer_pool_initial_size: Database::DEFAULT_BUFFER_POOL_INITIAL_SIZE,
             compaction_policy: CompactionPolicy::None,
-            enable_bloom_filter: true,
+            bloom_levels: BloomLevels::All,
             bloom_filter_bits_per_entry: Database::DEFAULT_BITS_PER_ENTRY,
+            key_codec: KeyCodec::LittleEndian,
+            background_compaction: false,
+            buffer_pool_capacity: Database::DEFAULT_BUFFER_POOL_CAPACITY,
+            enable_fence_index: false,
+            compaction_memory_budget: 0,
+            io_mode: IoMode::Auto,
+            merge_operator: MergeOperator::Add,
+            max_level_zero_runs: Database::DEFAULT_MAX_LEVEL_ZERO_RUNS,
+            level_zero_overflow: LevelZeroOverflowBehavior::Auto,
+            persist_buffer_pool: false,
+            warm_up: WarmUp::Eager,
+            max_garbage_ratio: None,
+            level_zero_slowdown_runs: 0,
+            level_zero_stop_runs: 0,
+            level_zero_slowdown_base_micros: Database::DEFAULT_LEVEL_ZERO_SLOWDOWN_BASE_MICROS,
+            stall_behavior: StallBehavior::Fail,
+            partitioned_levels: false,
+            version_merge: VersionMerge::KeepNewest,
+            sync_policy: SyncPolicy::Never,
+            compression: None,
+        }
+    }
+    ///Switch off settings for features that aren't compiled in, so the files we write always match the config.
+    /// Runs written as B-trees are still readable, their entries are stored the same way as array SSTs
+    fn disable_uncompiled_features(&mut self) {
+        if !cfg!(feature = "btree") {
+            self.sst_implementation = SstImplementation::Array;
+        }
+        if !cfg!(feature = "bloom") {
+            self.bloom_levels = BloomLevels::None;
+        }
+        if !cfg!(feature = "buffer-pool") {
+            self.enable_buffer_pool = false;
+        }
+    }
+    fn serialize(&self) -> Vec<u8> {
+        serde_state::serialize(&(Self::VERSION, self))
+    }
+    fn deserialize(bytes: &[u8]) -> Result<Self, DbError> {
+        let corrupt = |reason| DbError::Corrupt {
+            file: "config",
+            reason,
+        };
+        let config: Config = match serde_state::split_version(bytes) {
+            Some((Self::VERSION, contents)) => serde_state::deserialize(contents).map_err(corrupt),
+            Some((found, _)) => Err(DbError::UnsupportedVersion {
+                found,
+                supported: Self::VERSION,
+            }),
+            None => serde_state::deserialize::<LegacyConfig>(bytes)
+                .map(Config::from)
+                .map_err(corrupt),
+        }?;
+        if config.memtable_capacity == 0 {
+            return Err(corrupt("memtable capacity is 0".to_string()));
+        }
+        if config.max_level_zero_runs > Database::MAX_RUNS_PER_LEVEL
+            || config.sst_size_ratio > Database::MAX_RUNS_PER_LEVEL
+            || config.level_zero_slowdown_runs > Database::MAX_RUNS_PER_LEVEL
+            || config.level_zero_stop_runs > Database::MAX_RUNS_PER_LEVEL
+        {
+            return Err(corrupt(format!(
+                "run limits above {} runs per level",
+                Database::MAX_RUNS_PER_LEVEL
+            )));
+        }
+        if let Some(ratio) = config
+            .max_garbage_ratio
+            .filter(|ratio| !(0.0..1.0).contains(ratio))
+        {
+            return Err(corrupt(format!(
+                "max garbage ratio {ratio} is not in [0, 1)"
+            )));
+        }
+        Ok(config)
+    }
+}
+
+///Config layout from before files were versioned
+#[cfg_attr(feature = "serde-config", derive(Serialize, Deserialize))]
+struct LegacyConfig {
+    memtable_capacity: Size,
+    sst_size_ratio: Size,
+    sst_implementation: SstImplementation,
+    sst_search_algorithm: SstSearchAlgorithm,
+    enable_buffer_pool: bool,
+    buffer_pool_capacity: Size, //in pages
+    buffer_pool_initial_size: Size,
+    compaction_policy: CompactionPolicy,
+    enable_bloom_filter: bool,
+    bloom_filter_bits_per_entry: Size,
+}
+
+impl_struct_codec!(LegacyConfig {
+    memtable_capacity,
+    sst_size_ratio,
+    sst_implementation,
+    sst_search_algorithm,
+    enable_buffer_pool,
+    buffer_pool_capacity,
+    buffer_pool_initial_size,
+    compaction_policy,
+    enable_bloom_filter,
+    bloom_filter_bits_per_entry,
+});
+
+impl From<LegacyConfig> for Config {
+    fn from(legacy: LegacyConfig) -> Self {
+        //settings added since keep their defaults, so the database behaves as it did
+        Self {
+            memtable_capacity: legacy.memtable_capacity,
+            sst_size_ratio: legacy.sst_size_ratio,
+            sst_implementation: legacy.sst_implementation,
+            sst_search_algorithm: legacy.sst_search_algorithm,
+            enable_buffer_pool: legacy.enable_buffer_pool,
+            buffer_pool_initial_size: legacy.buffer_pool_initial_size,
+            compaction_policy: legacy.compaction_policy,
+            bloom_levels: if legacy.enable_bloom_filter {
+                BloomLevels::All
+            } else {
+                BloomLevels::None
+            },
+            bloom_filter_bits_per_entry: legacy.bloom_filter_bits_per_entry,
+            buffer_pool_capacity: legacy.buffer_pool_capacity * system_info::page_size(),
+            ..Config::new()
         }
     }
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[cfg_attr(feature = "serde-config", derive(Serialize, Deserialize))]
+#[derive(Debug, Clone)]
 struct Metadata {
     entry_counts: Vec<Vec<Size>>, //number of entries in each SST (outer index: Level, inner Index: run)
+    tombstone_counts: Vec<Vec<Size>>, //number of tombstones in each SST, same layout as entry_counts
+    compaction_entries_dropped: Size, //total entries shadowed or discarded by compactions
+    expiry_counts: Vec<Vec<Size>>, //number of entries with a TTL in each SST (length of its expiry sidecar)
+    page_sizes: Vec<Vec<Size>>, //page size each SST was written with, B-tree runs are read with it
+    last_sequence: Sequence,    //sequence of the newest put or delete
+    max_sequences: Vec<Vec<Sequence>>, //sequence of the newest write in each SST
+    operand_counts: Vec<Vec<Size>>, //number of merge operands in each SST (length of its operand sidecar)
+    key_sketches: Vec<Vec<KeySketch>>, //sketch of the keys in each SST, for estimating shadowed entries
+    //key range of each partition of a level (its first runs, see Database::set_partitioned_levels), levels past the
+    // end have none
+    partitions: Vec<Vec<KeyRange>>,
 }
 
+impl_struct_codec!(Metadata {
+    entry_counts,
+    tombstone_counts,
+    compaction_entries_dropped,
+    expiry_counts,
+    page_sizes,
+    last_sequence,
+    max_sequences,
+    operand_counts,
+    key_sketches,
+    partitions,
+});
+
 impl Metadata {
+    fn run_lists(&mut self) -> RunLists<'_> {
+        RunLists {
+            entry_counts: &mut self.entry_counts,
+            tombstone_counts: &mut self.tombstone_counts,
+            expiry_counts: &mut self.expiry_counts,
+            page_sizes: &mut self.page_sizes,
+            max_sequences: &mut self.max_sequences,
+            operand_counts: &mut self.operand_counts,
+            key_sketches: &mut self.key_sketches,
+        }
+    }
+    ///Layout version written to the metadata file, unversioned files are migrated when read
+    const VERSION: u32 = 1;
+
     fn new() -> Self {
         Self {
             entry_counts: vec![vec![]],
+            tombstone_counts: vec![vec![]],
+            compaction_entries_dropped: 0,
+            expiry_counts: vec![vec![]],
+            page_sizes: vec![vec![]],
+            last_sequence: 0,
+            max_sequences: vec![vec![]],
+            operand_counts: vec![vec![]],
+            key_sketches: vec![vec![]],
+            partitions: vec![],
+        }
+    }
+    ///Sequence of the newest write in any run, 0 if there are none
+    fn newest_run_sequence(&self) -> Sequence {
+        (self.max_sequences.iter().flatten().max().copied()).unwrap_or(0)
+    }
+    ///Key ranges of the partitions of <level>, none if it isn't partitioned
+    fn level_partitions(&self, level: Level) -> &[KeyRange] {
+        self.partitions.get(level).map_or(&[], Vec::as_slice)
+    }
+    fn set_level_partitions(&mut self, level: Level, partitions: Vec<KeyRange>) {
+        if level >= self.partitions.len() {
+            self.partitions.resize(level + 1, vec![]);
+        }
+        self.partitions[level] = partitions;
+    }
+    ///A single empty level, sequence numbers keep counting from ours (see Database::truncate)
+    fn truncated(&self) -> Self {
+        Self {
+            last_sequence: self.last_sequence,
+            compaction_entries_dropped: self.compaction_entries_dropped,
+            ..Self::new()
+        }
+    }
+    fn serialize(&self) -> Vec<u8> {
+        serde_state::serialize(&(Self::VERSION, self))
+    }
+    fn deserialize(bytes: &[u8]) -> Result<Self, DbError> {
+        let corrupt = |reason| DbError::Corrupt {
+            file: "metadata",
+            reason,
+        };
+        let metadata: Metadata = match serde_state::split_version(bytes) {
+            Some((Self::VERSION, contents)) => serde_state::deserialize(contents).map_err(corrupt),
+            Some((found, _)) => Err(DbError::UnsupportedVersion {
+                found,
+                supported: Self::VERSION,
+            }),
+            None => serde_state::deserialize::<LegacyMetadata>(bytes)
+                .map(Metadata::from)
+                .map_err(corrupt),
+        }?;
+        //run numbers name files and index every per run vector, so they can't be allowed to grow without bound
+        let oversized = metadata
+            .entry_counts
+            .iter()
+            .position(|runs| runs.len() > Database::MAX_RUNS_PER_LEVEL);
+        if let Some(level) = oversized {
+            return Err(corrupt(format!(
+                "level {level} holds {} runs, at most {} are supported",
+                metadata.entry_counts[level].len(),
+                Database::MAX_RUNS_PER_LEVEL
+            )));
+        }
+        //a partition is one of the level's runs
+        let overpartitioned = (metadata.partitions.len() > metadata.entry_counts.len())
+            .then(|| metadata.partitions.len() - 1)
+            .or_else(|| {
+                (metadata.partitions.iter().zip(&metadata.entry_counts))
+                    .position(|(partitions, runs)| partitions.len() > runs.len())
+            });
+        if let Some(level) = overpartitioned {
+            return Err(corrupt(format!(
+                "level {level} has more partitions than runs"
+            )));
+        }
+        Ok(metadata)
+    }
+}
+
+///Metadata layout from before files were versioned
+#[cfg_attr(feature = "serde-config", derive(Serialize, Deserialize))]
+struct LegacyMetadata {
+    entry_counts: Vec<Vec<Size>>,
+}
+
+impl_struct_codec!(LegacyMetadata { entry_counts });
+
+impl From<LegacyMetadata> for Metadata {
+    fn from(legacy: LegacyMetadata) -> Self {
+        fn per_run<T: Clone>(entry_counts: &[Vec<Size>], value: T) -> Vec<Vec<T>> {
+            (entry_counts.iter())
+                .map(|level| vec![value.clone(); level.len()])
+                .collect()
+        }
+        let entry_counts = &legacy.entry_counts;
+        Self {
+            //tombstone counts are unknown, zero is the conservative choice (no probes are skipped)
+            tombstone_counts: per_run(entry_counts, 0),
+            compaction_entries_dropped: 0,
+            //no older run has an expiry or operand sidecar
+            expiry_counts: per_run(entry_counts, 0),
+            //the page size of older runs is unknown, the best guess is that they were written on this machine
+            page_sizes: per_run(entry_counts, system_info::page_size()),
+            //older writes all count as sequence 0, they come before any write made since
+            last_sequence: 0,
+            max_sequences: per_run(entry_counts, 0),
+            operand_counts: per_run(entry_counts, 0),
+            //the keys of older runs are unknown until they're compacted, see garbage::garbage_ratio
+            key_sketches: per_run(entry_counts, KeySketch::default()),
+            //no level was partitioned
+            partitions: vec![],
+            entry_counts: legacy.entry_counts.clone(),
+        }
+    }
+}
+
+///A rewrite of a run whose replacement is complete, written to the rewrite marker before any of the run's files are
+/// replaced so a crash part way through can be finished, see Database::rewrite_run
+#[cfg_attr(feature = "serde-config", derive(Serialize, Deserialize))]
+struct RunRewrite {
+    level: Level,
+    run: Run,
+    staging_run: Run,          //where the replacement was written
+    replaced_files: Vec<bool>, //which of the run's files (see run_paths) the replacement has
+    metadata: Metadata,        //the database's metadata with the replacement in place
+}
+
+impl_struct_codec!(RunRewrite {
+    level,
+    run,
+    staging_run,
+    replaced_files,
+    metadata,
+});
+
+///Tells whether the database it came from was truncated since it was taken, see Database::truncate
+#[derive(Debug, Clone)]
+pub(crate) struct TruncationWatch {
+    truncations: Rc<Cell<Size>>, //the database's count of truncates
+    seen: Size,                  //the count when the watch was taken
+}
+
+impl TruncationWatch {
+    ///DbError::Truncated if the database was truncated since the watch was taken
+    pub(crate) fn check(&self) -> Result<(), DbError> {
+        match self.truncations.get() == self.seen {
+            true => Ok(()),
+            false => Err(DbError::Truncated),
+        }
+    }
+}
+
+///Entries of a range as they were when Database::iter_range created it, in key order. It doesn't borrow the
+/// database, puts, deletes, flushes and compactions after it was created are never seen by it.
+/// NOTE: if the database is truncated, the next item is Err(DbError::Truncated) and the iterator ends after it
+#[derive(Debug, Clone)]
+pub struct RangeIter {
+    entries: std::vec::IntoIter<Entry>,
+    truncation: TruncationWatch,
+    truncation_reported: bool, //the Truncated error was returned, nothing comes after it
+}
+
+impl RangeIter {
+    ///DbError::Truncated if the database was truncated after the iterator was created
+    pub fn check(&self) -> Result<(), DbError> {
+        self.truncation.check()
+    }
+    fn next_with(
+        &mut self,
+        next: impl FnOnce(&mut std::vec::IntoIter<Entry>) -> Option<Entry>,
+    ) -> Option<Result<Entry, DbError>> {
+        if self.truncation_reported {
+            return None;
+        }
+        if let Err(why) = self.check() {
+            self.truncation_reported = true;
+            return Some(Err(why));
+        }
+        next(&mut self.entries).map(Ok)
+    }
+}
+
+impl Iterator for RangeIter {
+    type Item = Result<Entry, DbError>;
+    fn next(&mut self) -> Option<Self::Item> {
+        self.next_with(Iterator::next)
+    }
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        match (self.truncation_reported, self.check()) {
+            (true, _) => (0, Some(0)),
+            (false, Err(_)) => (1, Some(1)), //the error is still to come
+            (false, Ok(())) => self.entries.size_hint(),
+        }
+    }
+}
+
+impl DoubleEndedIterator for RangeIter {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        self.next_with(DoubleEndedIterator::next_back)
+    }
+}
+
+impl ExactSizeIterator for RangeIter {}
+
+///Options for Database::scan_with_options
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ScanOptions {
+    pub limit: Option<Size>, //max number of entries returned, None for the whole range
+    pub reverse: bool,       //return entries from the largest key to the smallest
+}
+
+///What a scan returns of each entry, the whole entry or (for scan_keys) only its key, so a key-only scan never
+/// holds the values
+trait ScanItem {
+    fn from_entry(key: Key, value: Value) -> Self;
+}
+
+impl ScanItem for Entry {
+    fn from_entry(key: Key, value: Value) -> Self {
+        (key, value)
+    }
+}
+
+impl ScanItem for Key {
+    fn from_entry(key: Key, _: Value) -> Self {
+        key
+    }
+}
+
+///A run's place in the database and what's on disk for it, see Database::list_runs
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RunInfo {
+    pub level: Level,
+    pub run: Run,
+    pub entries: Size, //tombstones and shadowed entries included
+    pub bytes: Size, //size of the SST file, its B-tree, bloom filter and other sidecars aren't counted
+    pub has_sst: bool, //false for a run whose SST file is missing or unreadable, its bytes are 0
+    pub has_btree: bool,
+    pub has_bloom: bool,
+    pub page_size: Size, //page size the run was written with, which it's read with on any machine
+}
+
+///Outcome of Database::compare_and_put
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CasResult {
+    ///The key held the expected value and now holds the new one
+    Swapped,
+    ///Nothing was written, the key holds <actual> (None if it's absent, deleted or expired)
+    Mismatch { actual: Option<Value> },
+}
+
+///Direction of a nearest key search
+#[derive(Debug, Clone, Copy)]
+enum Nearest {
+    Ceiling,
+    Floor,
+}
+
+///Sources of Database::merge_scan_sources, source 0 is the memtable and source i is cursors[i - 1]
+struct ScanSources<'a, 'b, 'c> {
+    memtable_entries: std::vec::IntoIter<(Entry, bool)>,
+    memtable_value: Value, //value of the memtable entry last pulled
+    cursors: &'a mut [RunCursor<'b>],
+    buffer_pool: Option<&'c mut BufferPool>,
+}
+
+impl ScanSources<'_, '_, '_> {
+    ///Move <source> to its next entry, returns its key and whether it's a merge operand
+    fn pull(&mut self, source: usize) -> io::Result<Option<(Key, bool)>> {
+        if source == 0 {
+            let Some(((key, value), is_operand)) = self.memtable_entries.next() else {
+                return Ok(None);
+            };
+            self.memtable_value = value;
+            return Ok(Some((key, is_operand)));
+        }
+        let cursor = &mut self.cursors[source - 1];
+        let key = cursor
+            .next_key(self.buffer_pool.as_deref_mut())
+            .map_err(|why| {
+                let (_, level, run) = cursor.run_address();
+                io::Error::new(
+                    why.kind(),
+                    format!("Something went wrong trying to scan level {level}, sst {run}, reason: {why}"),
+                )
+            })?;
+        Ok(key.map(|key| (key, cursor.is_operand(key))))
+    }
+    ///Value of the entry <source> was last pulled to, only run entries decode it here
+    fn value(&self, source: usize) -> Value {
+        match source {
+            0 => self.memtable_value,
+            _ => self.cursors[source - 1].value(),
+        }
+    }
+}
+
+type CompactionProgressFn = dyn FnMut(&Database, CompactionProgress) + Send;
+///Called with each stage of a compaction run on the database's own thread (background compactions aren't reported)
+struct CompactionProgressCallback(Box<CompactionProgressFn>);
+
+type EventListenerFn = dyn Fn(DbEvent) + Send;
+///Told about flushes, compactions and moves of runs done on the database's own thread (the background compactor's
+/// aren't reported)
+struct EventListener(Box<EventListenerFn>);
+
+impl fmt::Debug for EventListener {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("EventListener")
+    }
+}
+
+impl fmt::Debug for CompactionProgressCallback {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("CompactionProgressCallback")
+    }
+}
+
+///Options for Database::open_with
+#[derive(Debug, Clone, Default)]
+pub struct DatabaseOptions {
+    pub storage: Storage, //where the database's files are kept, on disk unless set
+}
+
+impl DatabaseOptions {
+    ///Keep the database's files in memory, see Database::open_in_memory
+    pub fn in_memory() -> Self {
+        Self {
+            storage: Storage::in_memory(),
         }
     }
 }
 
 #[derive(Debug)]
 pub struct Database {
-    name: String, //name of db (directory that holds SSTs)
+    name: DatabaseName, //root directory of the db (holds SSTs), any path the OS accepts, and its storage
     config: Config,
     metadata: Metadata,
-    memtable: Memtable,
-    buffer_pool: BufferPool,
+    config_dirty: bool,   //config changed since the config file was last written
+    metadata_dirty: bool, //metadata changed since the metadata file was last written
+    level_shape: LevelShape, //summary of metadata.entry_counts, rebuilt on open and updated along with it
+    memtable: Rc<Memtable>, //shared with snapshots, copied before it's changed while a snapshot holds it
+    buffer_pool: Rc<RefCell<BufferPool>>, //shared with other databases when opened by a DatabaseManager
+    owns_buffer_pool: bool, //false if the buffer pool is shared, its capacity is the manager's to set
+    background_compactor: Option<BackgroundCompactor>, //owns level metadata for levels >= 1 while enabled
+    read_cache: ReadCache, //NOTE: its capacity is saved along with its contents, not in the config
+    compaction_progress_callback: Option<CompactionProgressCallback>,
+    event_listener: Option<EventListener>, //None unless set, so nothing is timed for it
+    durability_lag_threshold: Option<Duration>, //see set_durability_lag_threshold
+    durability_lag_reported: Option<Timestamp>, //oldest unflushed write a DurabilityLag event was reported for, reset by flushes
+    flush_audit_dir: Option<PathBuf>, //where flushes write their audits, see set_flush_audit_dir
+    yield_hook: YieldHook,            //called between pages of long operations, see set_yield_hook
+    clock: Box<dyn Clock>,            //decides when entries put with a TTL expire
+    io_stats_baseline: IoStats, //this thread's I/O counters when the database was opened (or its stats were reset)
+    compaction_error: Option<io::Error>, //why the last compaction failed, cleared by the next one that succeeds
+    maintenance: MaintenanceScheduler,   //NOTE: its progress is saved on close, like the read cache
+    warm_pages: Vec<(PathBuf, Page)>, //pages listed by the warm-start file that haven't been read back in, see warm_up
+    size_ratios: SizeRatioHistory, //recorded by compactions since the database was opened, see level_size_ratios
+    truncations: Rc<Cell<Size>>, //number of truncates since the database was opened, see TruncationWatch
+    #[cfg(any(test, feature = "paranoid-reads"))]
+    paranoid_reads: bool, //check every get against full reads of the runs, see set_paranoid_reads
 }
 
 #[allow(dead_code)]
 impl Database {
     const DEFAULT_SST_SIZE_RATIO: Size = 2;
-    const DEFAULT_BUFFER_POOL_CAPACITY: Size = 2560; //Enough for 10MB of 4096 byte pages
-    const DEFAULT_BUFFER_POOL_INITIAL_SIZE: Size = 97; //NOTE: this was arbitrarily chosen: closest prime number to 100
+    const DEFAULT_BUFFER_POOL_CAPACITY: Size = 10 * 2_usize.pow(20); //10MB
+    pub(crate) const DEFAULT_BUFFER_POOL_INITIAL_SIZE: Size = 97; //NOTE: this was arbitrarily chosen: closest prime number to 100
     const DEFAULT_BITS_PER_ENTRY: Size = 5;
+    const DEFAULT_MAX_LEVEL_ZERO_RUNS: Size = 64;
+    const DEFAULT_LEVEL_ZERO_SLOWDOWN_BASE_MICROS: u64 = 1000;
+    ///Most times a slowed down write's sleep is doubled, so it stays under a second at the default base
+    const MAX_SLOWDOWN_DOUBLINGS: Size = 9;
+    ///Most runs any level may hold. Runs are numbered by their position in the level (which names their files), so
+    /// run numbers stay below this too
+    pub const MAX_RUNS_PER_LEVEL: Size = 1 << 16;
+    ///Most entries the memtable may hold. The config file starts with the memtable capacity, which has to fit in 32
+    /// bits for it to be told apart from a config written before files were versioned (see serde_state::split_version)
+    pub const MAX_MEMTABLE_CAPACITY: Size = u32::MAX as Size;
+    const BYTES_PER_MB: Size = 2_usize.pow(20);
+    ///Most megabytes the *_mb setters take, any more is more bytes than a Size holds (4095MB on 32-bit targets)
+    pub const MAX_CAPACITY_MB: Size = Size::MAX / Self::BYTES_PER_MB;
+    ///Fraction of tombstones in a level that triggers compacting it early
+    const TOMBSTONE_DENSITY_COMPACTION_THRESHOLD: f64 = 0.5;
+    ///Pages worth of entries scan_with_retry returns between checks that its runs weren't replaced
+    const RETRY_SCAN_CHUNK_PAGES: Size = 16;
+    ///Chunks in a row scan_with_retry merges again before it holds the compactor off for the next one
+    const MAX_SCAN_RETRIES: Size = 4;
 
     const LEVEL_ZERO: Level = 0;
 
@@ -90,56 +733,157 @@ impl Database {
     const NO_OPEN_DB_NAME: &str = "";
 
     ///INTERNAL ONLY (do not make public), externally should use open()
-    fn new(name: &str, config: Config, metadata: Metadata) -> Database {
-        if name == Self::NO_OPEN_DB_NAME {
-            panic!("\"{name}\" is an invalid Database name");
-        }
-        if name.contains(char::is_whitespace) {
-            panic!("\"{name}\" is an invalid Database name (cannot contain whitespaces)")
+    fn new(name: DatabaseName, mut config: Config, metadata: Metadata) -> Database {
+        if name.as_os_str() == Self::NO_OPEN_DB_NAME {
+            panic!("\"{}\" is an invalid Database name", name.display());
         }
+        config.disable_uncompiled_features();
         let Config {
             buffer_pool_initial_size,
             buffer_pool_capacity,
+            background_compaction,
             ..
         } = config;
+        let background_compactor = background_compaction.then(|| {
+            BackgroundCompactor::new(
+                metadata.entry_counts.clone(),
+                metadata.tombstone_counts.clone(),
+                metadata.expiry_counts.clone(),
+                metadata.page_sizes.clone(),
+                metadata.max_sequences.clone(),
+                metadata.operand_counts.clone(),
+                metadata.key_sketches.clone(),
+            )
+        });
         Database {
-            name: String::from(name),
+            name,
             config,
+            level_shape: LevelShape::from_entry_counts(&metadata.entry_counts),
             metadata,
-            memtable: Memtable::new(),
-            buffer_pool: BufferPool::new(buffer_pool_initial_size, buffer_pool_capacity),
+            config_dirty: false,
+            metadata_dirty: false,
+            memtable: Rc::new(Memtable::new()),
+            buffer_pool: Rc::new(RefCell::new(BufferPool::new(
+                buffer_pool_initial_size,
+                buffer_pool_capacity,
+            ))),
+            owns_buffer_pool: true,
+            background_compactor,
+            read_cache: ReadCache::default(),
+            compaction_progress_callback: None,
+            event_listener: None,
+            durability_lag_threshold: None,
+            durability_lag_reported: None,
+            flush_audit_dir: None,
+            yield_hook: YieldHook::default(),
+            clock: Box::new(SystemClock),
+            io_stats_baseline: io_stats::snapshot(),
+            compaction_error: None,
+            maintenance: MaintenanceScheduler::default(),
+            warm_pages: Vec::new(),
+            size_ratios: SizeRatioHistory::default(),
+            truncations: Rc::new(Cell::new(0)),
+            #[cfg(any(test, feature = "paranoid-reads"))]
+            paranoid_reads: false,
         }
     }
     //GETTERS AND SETTERS (start)
+    ///Root directory of the database as a string, non UTF-8 parts are replaced, see path
     pub fn name(&self) -> String {
-        self.name.clone()
+        self.name.to_string_lossy().into_owned()
+    }
+    ///Root directory of the database, as it was opened
+    pub fn path(&self) -> &Path {
+        &self.name
     }
     pub fn memtable_capacity(&self) -> Size {
         self.config.memtable_capacity
     }
+    ///Number of entries the memtable holds, each run flushed from it has exactly this many.
+    /// Panics if <memtable_capacity> is 0 or over Database::MAX_MEMTABLE_CAPACITY, see try_set_memtable_capacity
     pub fn set_memtable_capacity(mut self, memtable_capacity: Size) -> Self {
+        self.try_set_memtable_capacity(memtable_capacity)
+            .unwrap_or_else(|why| panic!("{why}"));
+        self
+    }
+    ///set_memtable_capacity, or an error (leaving the setting as it was) if <memtable_capacity> is 0 or over
+    /// Database::MAX_MEMTABLE_CAPACITY
+    pub fn try_set_memtable_capacity(&mut self, memtable_capacity: Size) -> Result<(), DbError> {
         if memtable_capacity < 1 {
-            panic!("{memtable_capacity} is an invalid memtable capacity");
+            return Err(DbError::InvalidConfig {
+                setting: "memtable capacity",
+                reason: "the memtable must hold at least 1 entry".to_string(),
+            });
+        }
+        if memtable_capacity > Self::MAX_MEMTABLE_CAPACITY {
+            return Err(DbError::InvalidConfig {
+                setting: "memtable capacity",
+                reason: format!(
+                    "the memtable can hold at most {} entries",
+                    Self::MAX_MEMTABLE_CAPACITY
+                ),
+            });
         }
         self.config.memtable_capacity = memtable_capacity;
+        self.config_dirty = true;
+        Ok(())
+    }
+    ///Panics if <memtable_capacity_mb> is 0, over Database::MAX_CAPACITY_MB or holds more than
+    /// Database::MAX_MEMTABLE_CAPACITY entries, see try_set_memtable_capacity_mb
+    pub fn set_memtable_capacity_mb(mut self, memtable_capacity_mb: Size) -> Self {
+        self.try_set_memtable_capacity_mb(memtable_capacity_mb)
+            .unwrap_or_else(|why| panic!("{why}"));
         self
     }
-    pub fn set_memtable_capacity_mb(self, memtable_capacity_mb: Size) -> Self {
-        self.set_memtable_capacity(memtable_capacity_mb * 2_usize.pow(20) / ENTRY_SIZE)
+    ///set_memtable_capacity_mb, or an error (leaving the setting as it was) if <memtable_capacity_mb> is 0, over
+    /// Database::MAX_CAPACITY_MB or holds more than Database::MAX_MEMTABLE_CAPACITY entries
+    pub fn try_set_memtable_capacity_mb(
+        &mut self,
+        memtable_capacity_mb: Size,
+    ) -> Result<(), DbError> {
+        let bytes = Self::mb_to_bytes("memtable capacity", memtable_capacity_mb)?;
+        self.try_set_memtable_capacity(bytes / ENTRY_SIZE)
+    }
+    ///Bytes in <count> units of <unit_bytes> bytes (named <unit>), or an error for <setting> if they don't fit in a Size
+    fn checked_bytes(
+        setting: &'static str,
+        count: Size,
+        unit_bytes: Size,
+        unit: &str,
+    ) -> Result<Size, DbError> {
+        count
+            .checked_mul(unit_bytes)
+            .ok_or_else(|| DbError::InvalidConfig {
+                setting,
+                reason: format!("{count} {unit} is more than {} bytes", Size::MAX),
+            })
+    }
+    fn mb_to_bytes(setting: &'static str, mb: Size) -> Result<Size, DbError> {
+        Self::checked_bytes(setting, mb, Self::BYTES_PER_MB, "MB")
     }
     pub fn sst_size_ratio(&self) -> Size {
         self.config.sst_size_ratio
     }
+    ///Panics unless <sst_size_ratio> is in 2..=Database::MAX_RUNS_PER_LEVEL, tiered levels hold up to that many runs
     pub fn set_sst_size_ratio(mut self, sst_size_ratio: Size) -> Self {
         assert!(sst_size_ratio > 1, "Size ratio must be greater than 1");
+        assert!(
+            sst_size_ratio <= Self::MAX_RUNS_PER_LEVEL,
+            "Size ratio must be at most {}",
+            Self::MAX_RUNS_PER_LEVEL
+        );
         self.config.sst_size_ratio = sst_size_ratio;
+        self.config_dirty = true;
         self
     }
     pub fn sst_implementation(&self) -> SstImplementation {
         self.config.sst_implementation
     }
+    ///NOTE: without the btree feature this is always Array
     pub fn set_sst_implementation(mut self, sst_implementation: SstImplementation) -> Self {
         self.config.sst_implementation = sst_implementation;
+        self.config_dirty = true;
+        self.config.disable_uncompiled_features();
         self
     }
     pub fn sst_search_algorithm(&self) -> SstSearchAlgorithm {
@@ -147,879 +891,11484 @@ impl Database {
     }
     pub fn set_sst_search_algorithm(mut self, sst_search_algorithm: SstSearchAlgorithm) -> Self {
         self.config.sst_search_algorithm = sst_search_algorithm;
+        self.config_dirty = true;
         self
     }
     pub fn enable_buffer_pool(&self) -> bool {
         self.config.enable_buffer_pool
     }
+    ///Disabling drops this database's pages from the buffer pool (a shared pool keeps other databases' pages): files
+    /// replaced while it's disabled don't tell the pool, so re-enabling starts cold instead of with stale pages.
+    /// NOTE: without the buffer-pool feature this is always disabled
     pub fn set_enable_buffer_pool(mut self, enable_buffer_pool: bool) -> Self {
         self.config.enable_buffer_pool = enable_buffer_pool;
+        self.config_dirty = true;
+        self.config.disable_uncompiled_features();
+        if !self.config.enable_buffer_pool {
+            self.buffer_pool.borrow_mut().remove_directory(&self.name);
+        }
         self
     }
+    ///Buffer pool capacity in bytes
     pub fn buffer_pool_capacity(&self) -> Size {
         self.config.buffer_pool_capacity
     }
+    ///Set buffer pool capacity in bytes. Capacities smaller than an operation's working set (even smaller than a page)
+    /// are allowed, operations stay correct but their pages are evicted before they can be reused.
+    /// NOTE: a buffer pool shared through a DatabaseManager keeps the manager's capacity, this is only saved.
+    /// Panics if <buffer_pool_capacity> is 0, see try_set_buffer_pool_capacity
     pub fn set_buffer_pool_capacity(mut self, buffer_pool_capacity: Size) -> Self {
-        assert!(
-            buffer_pool_capacity > 0,
-            "Buffer pool capacity must be over zero"
-        );
-        self.buffer_pool.set_capacity(buffer_pool_capacity);
+        self.try_set_buffer_pool_capacity(buffer_pool_capacity)
+            .unwrap_or_else(|why| panic!("{why}"));
+        self
+    }
+    ///set_buffer_pool_capacity, or an error (leaving the setting as it was) if <buffer_pool_capacity> is 0
+    pub fn try_set_buffer_pool_capacity(
+        &mut self,
+        buffer_pool_capacity: Size,
+    ) -> Result<(), DbError> {
+        if buffer_pool_capacity < 1 {
+            return Err(DbError::InvalidConfig {
+                setting: "buffer pool capacity",
+                reason: "the buffer pool must hold at least 1 byte".to_string(),
+            });
+        }
+        if self.owns_buffer_pool {
+            self.buffer_pool
+                .borrow_mut()
+                .set_capacity(buffer_pool_capacity);
+        }
         self.config.buffer_pool_capacity = buffer_pool_capacity;
+        self.config_dirty = true;
+        Ok(())
+    }
+    ///Panics if <buffer_pool_capacity_mb> is 0 or over Database::MAX_CAPACITY_MB, see try_set_buffer_pool_capacity_mb
+    pub fn set_buffer_pool_capacity_mb(mut self, buffer_pool_capacity_mb: Size) -> Self {
+        self.try_set_buffer_pool_capacity_mb(buffer_pool_capacity_mb)
+            .unwrap_or_else(|why| panic!("{why}"));
+        self
+    }
+    ///set_buffer_pool_capacity_mb, or an error (leaving the setting as it was) if <buffer_pool_capacity_mb> is 0
+    /// or over Database::MAX_CAPACITY_MB
+    pub fn try_set_buffer_pool_capacity_mb(
+        &mut self,
+        buffer_pool_capacity_mb: Size,
+    ) -> Result<(), DbError> {
+        let bytes = Self::mb_to_bytes("buffer pool capacity", buffer_pool_capacity_mb)?;
+        self.try_set_buffer_pool_capacity(bytes)
+    }
+    ///Panics if <num_pages> is 0 or more pages than a Size holds the bytes of
+    pub fn set_buffer_pool_capacity_pages(mut self, num_pages: Size) -> Self {
+        let bytes = Self::checked_bytes(
+            "buffer pool capacity",
+            num_pages,
+            system_info::page_size(),
+            "pages",
+        );
+        bytes
+            .and_then(|bytes| self.try_set_buffer_pool_capacity(bytes))
+            .unwrap_or_else(|why| panic!("{why}"));
         self
     }
-    pub fn set_buffer_pool_capacity_mb(self, buffer_pool_capacity_mb: Size) -> Self {
-        let num_pages = buffer_pool_capacity_mb * 2_usize.pow(20) / system_info::page_size();
-        self.set_buffer_pool_capacity(num_pages)
+    ///Usage of the whole pool, which includes other databases' pages if it's shared
+    pub fn buffer_pool_stats(&self) -> BufferPoolStats {
+        self.buffer_pool.borrow().stats()
+    }
+    ///Page I/O done on this thread since the database was opened or reset_io_stats was called.
+    /// NOTE: the background compactor's I/O isn't counted, and I/O of other databases used on this thread is
+    pub fn io_stats(&self) -> IoStats {
+        io_stats::snapshot().since(&self.io_stats_baseline)
+    }
+    pub fn reset_io_stats(&mut self) {
+        self.io_stats_baseline = io_stats::snapshot();
+    }
+    ///Allocations and reuses of the aligned buffers pages are read and written through, counted on this thread since
+    /// it started (see page_buffers)
+    pub fn page_buffer_stats(&self) -> PageBufferStats {
+        page_buffers::stats()
+    }
+    ///I/O counters, buffer pool usage and disk usage in one snapshot, written for tooling with stats::to_csv or
+    /// stats::to_json
+    pub fn stats(&self) -> DbStats {
+        DbStats {
+            io: self.io_stats(),
+            buffer_pool: self.buffer_pool_stats(),
+            disk: self.approximate_size_bytes(),
+        }
     }
     pub fn buffer_pool_initial_size(&self) -> Size {
         self.config.buffer_pool_initial_size
     }
+    ///Panics if <buffer_pool_initial_size> is 0, see try_set_buffer_pool_initial_size
     pub fn set_buffer_pool_initial_size(mut self, buffer_pool_initial_size: Size) -> Self {
-        assert!(
-            buffer_pool_initial_size > 0,
-            "Buffer pool initial size must be over zero"
-        );
-        if buffer_pool_initial_size != self.buffer_pool_initial_size() {
-            self.buffer_pool =
-                BufferPool::new(buffer_pool_initial_size, self.buffer_pool_capacity())
+        self.try_set_buffer_pool_initial_size(buffer_pool_initial_size)
+            .unwrap_or_else(|why| panic!("{why}"));
+        self
+    }
+    ///set_buffer_pool_initial_size, or an error (leaving the setting as it was) if <buffer_pool_initial_size> is 0
+    pub fn try_set_buffer_pool_initial_size(
+        &mut self,
+        buffer_pool_initial_size: Size,
+    ) -> Result<(), DbError> {
+        if buffer_pool_initial_size < 1 {
+            return Err(DbError::InvalidConfig {
+                setting: "buffer pool initial size",
+                reason: "the buffer pool must start with room for at least 1 page".to_string(),
+            });
+        }
+        if self.owns_buffer_pool && buffer_pool_initial_size != self.buffer_pool_initial_size() {
+            self.buffer_pool = Rc::new(RefCell::new(BufferPool::new(
+                buffer_pool_initial_size,
+                self.buffer_pool_capacity(),
+            )));
         }
         self.config.buffer_pool_initial_size = buffer_pool_initial_size;
+        self.config_dirty = true;
+        Ok(())
+    }
+    ///Panics if <buffer_pool_initial_size_mb> is under a page or over Database::MAX_CAPACITY_MB, see
+    /// try_set_buffer_pool_initial_size_mb
+    pub fn set_buffer_pool_initial_size_mb(mut self, buffer_pool_initial_size_mb: Size) -> Self {
+        self.try_set_buffer_pool_initial_size_mb(buffer_pool_initial_size_mb)
+            .unwrap_or_else(|why| panic!("{why}"));
+        self
+    }
+    ///set_buffer_pool_initial_size_mb, or an error (leaving the setting as it was) if <buffer_pool_initial_size_mb>
+    /// is under a page or over Database::MAX_CAPACITY_MB
+    pub fn try_set_buffer_pool_initial_size_mb(
+        &mut self,
+        buffer_pool_initial_size_mb: Size,
+    ) -> Result<(), DbError> {
+        let bytes = Self::mb_to_bytes("buffer pool initial size", buffer_pool_initial_size_mb)?;
+        self.try_set_buffer_pool_initial_size(bytes / system_info::page_size())
+    }
+    pub fn persist_buffer_pool(&self) -> bool {
+        self.config.persist_buffer_pool
+    }
+    ///Save which pages of this database are in the buffer pool on close (only their paths and page numbers, not their
+    /// bytes), so the next open can read them back in, see set_warm_up
+    pub fn set_persist_buffer_pool(mut self, persist_buffer_pool: bool) -> Self {
+        self.config.persist_buffer_pool = persist_buffer_pool;
+        self.config_dirty = true;
         self
     }
-    pub fn set_buffer_pool_initial_size_mb(self, buffer_pool_initial_size_mb: Size) -> Self {
-        let num_pages = buffer_pool_initial_size_mb * 2_usize.pow(20) / system_info::page_size();
-        self.set_buffer_pool_initial_size(num_pages)
+    pub fn warm_up_mode(&self) -> WarmUp {
+        self.config.warm_up
+    }
+    pub fn set_warm_up(mut self, warm_up: WarmUp) -> Self {
+        self.config.warm_up = warm_up;
+        self.config_dirty = true;
+        self
     }
     pub fn compaction_policy(&self) -> CompactionPolicy {
         self.config.compaction_policy
     }
     pub fn set_compaction_policy(mut self, compaction_policy: CompactionPolicy) -> Self {
         self.config.compaction_policy = compaction_policy;
+        self.config_dirty = true;
         self
     }
+    ///Whether any level gets bloom filters, see bloom_levels
     pub fn enable_bloom_filter(&self) -> bool {
-        self.config.enable_bloom_filter
+        self.config.bloom_levels != BloomLevels::None
     }
-    pub fn set_enable_bloom_filter(mut self, enable_bloom_filter: bool) -> Self {
-        self.config.enable_bloom_filter = enable_bloom_filter;
+    ///Bloom filters on every level (BloomLevels::All) or none.
+    /// NOTE: without the bloom feature this is always disabled
+    pub fn set_enable_bloom_filter(self, enable_bloom_filter: bool) -> Self {
+        self.set_bloom_levels(if enable_bloom_filter {
+            BloomLevels::All
+        } else {
+            BloomLevels::None
+        })
+    }
+    pub fn bloom_levels(&self) -> BloomLevels {
+        self.config.bloom_levels
+    }
+    ///Levels whose runs get a bloom filter when they're flushed, compacted or moved there. Runs already written
+    /// keep what they have until maintenance or the next open (see verify_filters) writes their filters, runs without
+    /// a filter are always searched.
+    /// NOTE: without the bloom feature this is always BloomLevels::None
+    pub fn set_bloom_levels(mut self, bloom_levels: BloomLevels) -> Self {
+        self.config.bloom_levels = bloom_levels;
+        self.config_dirty = true;
+        self.config.disable_uncompiled_features();
         self
     }
-    pub fn bloom_filter_bits_per_entry(&self) -> Size {
-        self.config.bloom_filter_bits_per_entry
+    pub fn enable_fence_index(&self) -> bool {
+        self.config.enable_fence_index
     }
-    pub fn set_bloom_filter_bits_per_entry(mut self, bits_per_entry: Size) -> Self {
-        self.config.bloom_filter_bits_per_entry = bits_per_entry;
+    ///Write a fence index (the first key of every page) for each run that's flushed or compacted, gets and scans
+    /// use it to go straight to the pages they need instead of binary searching array runs.
+    /// NOTE: runs written while this was off have none, they're binary searched as before
+    pub fn set_enable_fence_index(mut self, enable_fence_index: bool) -> Self {
+        self.config.enable_fence_index = enable_fence_index;
+        self.config_dirty = true;
         self
     }
-    fn is_closed(&self) -> bool {
-        self.name == Self::NO_OPEN_DB_NAME
+    pub fn io_mode(&self) -> IoMode {
+        self.config.io_mode
     }
-    ///Gets number of SST runs in level, NOTE: a value of 0 can mean that there is no level
-    fn sst_count(&self, level: Level) -> Size {
-        match self.metadata.entry_counts.get(level) {
-            None => 0,
-            Some(level_entry_counts) => level_entry_counts.len(),
-        }
+    ///How the database's files are opened, see IoMode. Panics if <io_mode> is Direct and the filesystem doesn't
+    /// support direct I/O, see try_set_io_mode.
+    /// NOTE: files already open (e.g. by a running compaction) keep the mode they were opened with
+    pub fn set_io_mode(mut self, io_mode: IoMode) -> Self {
+        self.try_set_io_mode(io_mode)
+            .unwrap_or_else(|why| panic!("{why}"));
+        self
     }
-    ///Gets total number of SST runs in database
-    fn sst_total(&self) -> Size {
-        self.metadata
-            .entry_counts
-            .iter()
-            .fold(0, |acc, counts| acc + counts.len())
+    ///set_io_mode, or an error (leaving the setting as it was) if <io_mode> is Direct and the filesystem doesn't
+    /// support it
+    pub fn try_set_io_mode(&mut self, io_mode: IoMode) -> Result<(), DbError> {
+        Self::register_io_mode(&mut self.name, io_mode)?;
+        self.config.io_mode = io_mode;
+        self.config_dirty = true;
+        Ok(())
     }
-    //GETTERS AND SETTERS (end)
-
-    fn write_config_file(&self) {
-        let mut file = match fs::File::create(filename::config(&self.name)) {
-            Ok(f) => f,
-            Err(why) => {
-                panic!(
-                    "Unable to write to config file for {}, reason {}",
-                    self.name(),
-                    why
-                );
-            }
-        };
-        bincode::serialize_into(&mut file, &self.config).expect("Unable to serialize config file");
+    pub fn sync_policy(&self) -> SyncPolicy {
+        self.config.sync_policy
     }
-    fn write_metadata_file(&self) {
-        let mut file = match fs::File::create(filename::metadata(&self.name)) {
-            Ok(f) => f,
-            Err(why) => {
-                panic!(
-                    "Unable to write to metadata file for {}, reason {}",
-                    self.name(),
-                    why
-                );
-            }
-        };
-        bincode::serialize_into(&mut file, &self.metadata)
-            .expect("Unable to serialize metadata file");
+    ///Which writes are flushed to storage before they're relied on, see SyncPolicy. Never (the default) leaves it to
+    /// the OS, so writes cost what they always did. Always syncs every file the database writes, OnFlush skips the
+    /// config and metadata (the runs they describe survive a power failure, the latest changes to them may not).
+    pub fn set_sync_policy(mut self, sync_policy: SyncPolicy) -> Self {
+        self.name.storage_mut().set_sync_policy(sync_policy);
+        self.config.sync_policy = sync_policy;
+        self.config_dirty = true;
+        self
     }
-    ///Writes config and metadata files
-    fn write_db_state(&self) {
-        if self.is_closed() {
-            //NOTE: this should not happen unless we write after closing
-            panic!("Attempted to write database state with no database opened")
-        }
-        self.write_config_file();
-        self.write_metadata_file();
+    pub fn compression(&self) -> Option<Compression> {
+        self.config.compression
+    }
+    ///Compress the pages of the runs flushes and compactions write from now on (None, the default, doesn't), see
+    /// sst::compression. Runs already written keep their format until they're compacted, both are read the same way
+    pub fn set_compression(mut self, compression: Option<Compression>) -> Self {
+        self.name.storage_mut().set_compression(compression);
+        self.config.compression = compression;
+        self.config_dirty = true;
+        self
+    }
+    ///Whether the database's files bypass the OS page cache, false if IoMode::Auto found no direct I/O support
+    pub fn uses_direct_io(&self) -> bool {
+        self.name.storage().io_mode() == IoMode::Direct
+    }
+    pub fn compaction_memory_budget(&self) -> Size {
+        self.config.compaction_memory_budget
+    }
+    ///Most bytes a compaction's merge buffers may hold (0 for no limit, the default). More runs than the budget holds a
+    /// page of each for are merged in passes, a few runs at a time into intermediate runs (see
+    /// array_sst::merge_group_size), so entries are written more than once. A budget too small for a page of two runs
+    /// reads less than a page at a time, so the same pages are read more than once.
+    /// NOTE: one entry per run is always buffered, a budget too small for that is exceeded
+    pub fn set_compaction_memory_budget(mut self, compaction_memory_budget: Size) -> Self {
+        self.config.compaction_memory_budget = compaction_memory_budget;
+        self.config_dirty = true;
+        self
+    }
+    pub fn bloom_filter_bits_per_entry(&self) -> Size {
+        self.config.bloom_filter_bits_per_entry
     }
-    pub fn open(name: &str) -> Database {
-        if name == Self::NO_OPEN_DB_NAME {
-            panic!("Cannot open a database with the empty string as its name!")
+    pub fn set_bloom_filter_bits_per_entry(mut self, bits_per_entry: Size) -> Self {
+        self.config.bloom_filter_bits_per_entry = bits_per_entry;
+        self.config_dirty = true;
+        self
+    }
+    ///Change bloom_filter_bits_per_entry and rebuild the filter of every run from its SST, so runs already written get
+    /// the new false positive rate too. Every new filter is built before the old ones are removed and the config is
+    /// saved, then they're put in place one run at a time (reported to the compaction progress callback as
+    /// FilterRebuilt). If this is cut short, runs that are left without a filter are searched instead
+    pub fn set_bloom_bits_per_entry_and_rebuild(&mut self, bits_per_entry: Size) -> io::Result<()> {
+        if let Some(compactor) = &self.background_compactor {
+            compactor.drain(); //queued jobs would write filters with the old setting
         }
-        match fs::read_dir(name) {
-            Ok(_) => {
-                //directory exists, assume that this is a valid db
-                //read config and metadata files
-                let config_file = match fs::File::open(filename::config(name)) {
-                    Ok(f) => f,
-                    Err(why) => {
-                        panic!("Unable to read config file for {}, reason: {}", name, why);
-                    }
-                };
-                let metadata_file = match fs::File::open(filename::metadata(name)) {
-                    Ok(f) => f,
-                    Err(why) => {
-                        panic!("Unable to read metadata file for {}, reason: {}", name, why);
-                    }
-                };
+        let background_levels = self.background_levels();
+        let _background_state = background_levels
+            .as_deref()
+            .map(|levels| self.sync_levels(levels));
 
-                let config: Config = bincode::deserialize_from(config_file)
-                    .expect("Failed to deserialize database config");
-                let metadata: Metadata = bincode::deserialize_from(metadata_file)
-                    .expect("Failed to deserialize database config");
+        let db_name = self.name.clone();
+        let key_codec = self.key_codec();
+        let runs: Vec<(Level, Run, Size)> = (self.metadata.entry_counts.iter().enumerate())
+            .filter(|(level, _)| self.config.bloom_levels.includes(*level))
+            .flat_map(|(level, counts)| {
+                (counts.iter().enumerate())
+                    .map(move |(run, num_entries)| (level, run, *num_entries))
+            })
+            .collect();
+
+        //Step 1: build the new filters next to the old ones
+        for &(level, run, num_entries) in &runs {
+            let run_address = &(&db_name, level, run);
+            BloomFilterIO::write_from_sst_to(
+                &filename::bloom_filter_rebuild_path(run_address),
+                run_address,
+                bits_per_entry,
+                num_entries,
+                key_codec,
+            )?;
+        }
 
-                Database::new(name, config, metadata)
+        //Step 2: no run may have a filter of the old size once the config has the new one
+        {
+            let mut pool = self.buffer_pool.borrow_mut();
+            let mut buffer_pool = if self.config.enable_buffer_pool {
+                Some(&mut *pool)
+            } else {
+                None
+            };
+            for &(level, run, _) in &runs {
+                let bloom_path = (&db_name, level, run).path_bloom();
+                if self.name.storage().path_exists(&bloom_path) {
+                    file_interface::remove_file(
+                        self.name.storage(),
+                        &bloom_path,
+                        buffer_pool.as_deref_mut(),
+                    )?;
+                }
             }
-            Err(_) => {
-                //directory doesn't exist
-                fs::create_dir(name)
-                    .unwrap_or_else(|_| panic!("Unable to create directory for {}", name));
+        }
+        self.config.bloom_filter_bits_per_entry = bits_per_entry;
+        self.write_config_file()?;
+        self.config_dirty = false;
 
-                //Step 1: make db
-                let db = Database::new(name, Config::new(), Metadata::new());
+        //Step 3: put the new filters in place
+        for &(level, run, _) in &runs {
+            let run_address = &(&db_name, level, run);
+            self.name.storage().rename(
+                &filename::bloom_filter_rebuild_path(run_address),
+                &run_address.path_bloom(),
+            )?;
+            self.report_compaction_progress(CompactionProgress::FilterRebuilt { level, run });
+        }
+        Ok(())
+    }
+    ///Check that every run of a level with bloom filters has its filter, of the size its entries call for, rebuilding
+    /// the filter from the run's SST where it doesn't (reported as DbEvent::FilterRepaired). Returns the runs whose
+    /// filters were rebuilt, fails if a run's SST can't be read. try_open runs it whenever bloom filters are enabled
+    /// (before an event listener can be set). NOTE: runs written before their level got filters get one too
+    pub fn verify_filters(&mut self) -> Result<Vec<(Level, Run)>, DbError> {
+        self.ensure_open()?;
+        let background_levels = self.background_levels();
+        let _background_state = background_levels
+            .as_deref()
+            .map(|levels| self.sync_levels(levels));
 
-                //Step 2: Create config file with default settings
-                db.write_db_state();
-                db
+        let bits_per_entry = self.bloom_filter_bits_per_entry();
+        let key_codec = self.key_codec();
+        let runs: Vec<(Level, Run, Size)> = (self.metadata.entry_counts.iter().enumerate())
+            .filter(|(level, _)| self.config.bloom_levels.includes(*level))
+            .flat_map(|(level, counts)| {
+                (counts.iter().enumerate())
+                    .map(move |(run, num_entries)| (level, run, *num_entries))
+            })
+            .collect();
+        let mut repaired = Vec::new();
+        for (level, run, num_entries) in runs {
+            let run_address = &(&self.name, level, run);
+            if BloomFilterIO::is_intact(run_address, bits_per_entry, num_entries)? {
+                continue;
+            }
+            //the pool mustn't keep pages of the filter being replaced
+            let bloom_path = run_address.path_bloom();
+            if self.name.storage().path_exists(&bloom_path) {
+                let mut pool = self.buffer_pool.borrow_mut();
+                let buffer_pool = self.config.enable_buffer_pool.then_some(&mut *pool);
+                file_interface::remove_file(self.name.storage(), &bloom_path, buffer_pool)?;
             }
+            BloomFilterIO::write_from_sst(run_address, bits_per_entry, num_entries, key_codec)?;
+            self.report_event(DbEvent::FilterRepaired { level, run });
+            repaired.push((level, run));
         }
+        Ok(repaired)
     }
-    pub fn clear(&mut self) {
-        self.name = String::from(Self::NO_OPEN_DB_NAME);
-        self.config = Config::new();
-        self.memtable.clear();
+    pub fn key_codec(&self) -> KeyCodec {
+        self.config.key_codec
     }
-    ///Move all SST runs to the next larger (and older) level, along with any files and metadata tied to those SST runs.
-    fn move_runs(&mut self, level: Level) {
-        let db_name = &self.name;
-        let next_level = level + 1;
-        let entry_counts = &mut self.metadata.entry_counts;
-        let mut buffer_pool = if self.config.enable_buffer_pool {
-            Some(&mut self.buffer_pool)
-        } else {
-            None
-        };
-
+    ///Panics if the database already has SST runs written with a different codec
+    pub fn set_key_codec(mut self, key_codec: KeyCodec) -> Self {
         assert!(
-            entry_counts.get(level).is_some(),
-            "Level {level} does not exist, cannot flush"
+            key_codec == self.key_codec() || self.sst_total() == 0,
+            "Database {} was written with key codec {:?}, cannot open it with {:?}",
+            self.name.display(),
+            self.key_codec(),
+            key_codec
+        );
+        self.config.key_codec = key_codec;
+        self.config_dirty = true;
+        self
+    }
+    pub fn merge_operator(&self) -> MergeOperator {
+        self.config.merge_operator
+    }
+    ///Operator Database::merge combines operands with. Panics if the database holds operands that haven't been
+    /// combined with the value under them yet and were merged with a different operator, or if puts are written as
+    /// MergeOperator::Add operands (VersionMerge::SumValues) and <merge_operator> is another one
+    pub fn set_merge_operator(mut self, merge_operator: MergeOperator) -> Self {
+        assert!(
+            merge_operator == MergeOperator::Add || self.version_merge() != VersionMerge::SumValues,
+            "Database {} sums the values put, its merge operator can't be {merge_operator:?}",
+            self.name.display()
+        );
+        let has_operands = !self.memtable.operands().is_empty()
+            || self
+                .metadata
+                .operand_counts
+                .iter()
+                .flatten()
+                .any(|count| *count > 0);
+        assert!(
+            merge_operator == self.merge_operator() || !has_operands,
+            "Database {} has operands merged with {:?}, cannot open it with {:?}",
+            self.name.display(),
+            self.merge_operator(),
+            merge_operator
+        );
+        self.config.merge_operator = merge_operator;
+        self.config_dirty = true;
+        self
+    }
+    pub fn version_merge(&self) -> VersionMerge {
+        self.config.version_merge
+    }
+    ///What a put does with the version of its key under it (in the memtable or any run), which gets, scans and
+    /// compactions all agree on. VersionMerge::SumValues writes puts as operands, so it switches the merge operator
+    /// to MergeOperator::Add (panicking like set_merge_operator if it can't). Puts written before keep what they did.
+    /// NOTE: under VersionMerge::KeepOldest every put reads its key first
+    pub fn set_version_merge(mut self, version_merge: VersionMerge) -> Self {
+        if version_merge == VersionMerge::SumValues {
+            self = self.set_merge_operator(MergeOperator::Add);
+        }
+        self.config.version_merge = version_merge;
+        self.config_dirty = true;
+        self
+    }
+    pub fn max_level_zero_runs(&self) -> Size {
+        self.config.max_level_zero_runs
+    }
+    ///Most runs level 0 may hold under CompactionPolicy::None (0 for no limit other than Database::MAX_RUNS_PER_LEVEL),
+    /// every miss searches each of them. What a flush does once the limit is reached is decided by
+    /// set_level_zero_overflow. Panics if <max_level_zero_runs> is 1, the run a compaction leaves would already be at
+    /// the limit, or more than Database::MAX_RUNS_PER_LEVEL
+    pub fn set_max_level_zero_runs(mut self, max_level_zero_runs: Size) -> Self {
+        assert!(
+            max_level_zero_runs != 1,
+            "Max level 0 runs must be 0 (no limit) or at least 2"
+        );
+        assert!(
+            max_level_zero_runs <= Self::MAX_RUNS_PER_LEVEL,
+            "Max level 0 runs must be at most {}",
+            Self::MAX_RUNS_PER_LEVEL
+        );
+        self.config.max_level_zero_runs = max_level_zero_runs;
+        self.config_dirty = true;
+        self
+    }
+    ///Runs level 0 holds before a flush has to make room, max_level_zero_runs or the hard limit if that's 0
+    fn level_zero_run_limit(&self) -> Size {
+        match self.config.max_level_zero_runs {
+            0 => Self::MAX_RUNS_PER_LEVEL,
+            max_runs => max_runs,
+        }
+    }
+    pub fn level_zero_overflow(&self) -> LevelZeroOverflowBehavior {
+        self.config.level_zero_overflow
+    }
+    pub fn set_level_zero_overflow(
+        mut self,
+        level_zero_overflow: LevelZeroOverflowBehavior,
+    ) -> Self {
+        self.config.level_zero_overflow = level_zero_overflow;
+        self.config_dirty = true;
+        self
+    }
+    pub fn level_zero_slowdown_runs(&self) -> Size {
+        self.config.level_zero_slowdown_runs
+    }
+    ///Once level 0 holds <level_zero_slowdown_runs> runs, every write sleeps before going ahead, twice as long for each
+    /// run past it (starting at level_zero_slowdown_base), so compactions can catch up with a burst of writes.
+    /// 0 (the default) never slows writes down. Panics if it's more than MAX_RUNS_PER_LEVEL
+    pub fn set_level_zero_slowdown_runs(mut self, level_zero_slowdown_runs: Size) -> Self {
+        assert!(
+            level_zero_slowdown_runs <= Self::MAX_RUNS_PER_LEVEL,
+            "Level 0 can't hold more than {} runs",
+            Self::MAX_RUNS_PER_LEVEL
+        );
+        self.config.level_zero_slowdown_runs = level_zero_slowdown_runs;
+        self.config_dirty = true;
+        self
+    }
+    pub fn level_zero_stop_runs(&self) -> Size {
+        self.config.level_zero_stop_runs
+    }
+    ///Once level 0 holds <level_zero_stop_runs> runs, writes are stalled until it's compacted (see
+    /// set_stall_behavior). 0 (the default) never stalls writes. Panics if it's 1 (a merged level 0 would still be
+    /// stalled) or more than MAX_RUNS_PER_LEVEL
+    pub fn set_level_zero_stop_runs(mut self, level_zero_stop_runs: Size) -> Self {
+        assert!(
+            level_zero_stop_runs != 1,
+            "Writes can't stall at 1 level 0 run, that's all a compaction leaves"
+        );
+        assert!(
+            level_zero_stop_runs <= Self::MAX_RUNS_PER_LEVEL,
+            "Level 0 can't hold more than {} runs",
+            Self::MAX_RUNS_PER_LEVEL
+        );
+        self.config.level_zero_stop_runs = level_zero_stop_runs;
+        self.config_dirty = true;
+        self
+    }
+    pub fn level_zero_slowdown_base(&self) -> Duration {
+        Duration::from_micros(self.config.level_zero_slowdown_base_micros)
+    }
+    ///How long a write sleeps once level 0 reaches level_zero_slowdown_runs, 1ms by default.
+    /// NOTE: saved with microsecond precision
+    pub fn set_level_zero_slowdown_base(mut self, level_zero_slowdown_base: Duration) -> Self {
+        self.config.level_zero_slowdown_base_micros = level_zero_slowdown_base
+            .as_micros()
+            .try_into()
+            .unwrap_or(u64::MAX);
+        self.config_dirty = true;
+        self
+    }
+    pub fn stall_behavior(&self) -> StallBehavior {
+        self.config.stall_behavior
+    }
+    pub fn set_stall_behavior(mut self, stall_behavior: StallBehavior) -> Self {
+        self.config.stall_behavior = stall_behavior;
+        self.config_dirty = true;
+        self
+    }
+    pub fn partitioned_levels(&self) -> bool {
+        self.config.partitioned_levels
+    }
+    ///Under CompactionPolicy::Leveled, keep each level as runs holding disjoint key ranges (partitions) of about a
+    /// memtable each, instead of a single run. Runs moved into a level are only merged with the partitions they
+    /// overlap, so writes to a small key range don't rewrite the whole level. Gets only search the partition holding
+    /// their key and scans the partitions their range reaches. Disabling it leaves the partitions until their level is
+    /// next compacted. Panics with background compaction enabled, see try_set_partitioned_levels
+    pub fn set_partitioned_levels(mut self, partitioned_levels: bool) -> Self {
+        self.try_set_partitioned_levels(partitioned_levels)
+            .unwrap_or_else(|why| panic!("{why}"));
+        self
+    }
+    ///set_partitioned_levels, or an error (leaving the setting as it was) if background compaction is enabled: the
+    /// background compactor only merges whole levels
+    pub fn try_set_partitioned_levels(&mut self, partitioned_levels: bool) -> Result<(), DbError> {
+        if partitioned_levels && self.config.background_compaction {
+            return Err(Self::partitioned_background_error());
+        }
+        self.config.partitioned_levels = partitioned_levels;
+        self.config_dirty = true;
+        Ok(())
+    }
+    fn partitioned_background_error() -> DbError {
+        DbError::InvalidConfig {
+            setting: "partitioned levels",
+            reason:
+                "background compaction doesn't partition levels, only one of them can be enabled"
+                    .to_string(),
+        }
+    }
+    pub fn max_garbage_ratio(&self) -> Option<f64> {
+        self.config.max_garbage_ratio
+    }
+    ///After each flush, compact every level where more than <max_garbage_ratio> of the entries are shadowed or
+    /// tombstones (see garbage_ratio), whatever the compaction policy. None to only compact when the policy does.
+    /// Panics if the ratio isn't in [0, 1), see try_set_max_garbage_ratio
+    pub fn set_max_garbage_ratio(mut self, max_garbage_ratio: Option<f64>) -> Self {
+        self.try_set_max_garbage_ratio(max_garbage_ratio)
+            .unwrap_or_else(|why| panic!("{why}"));
+        self
+    }
+    ///set_max_garbage_ratio, or an error (leaving the setting as it was) if the ratio isn't in [0, 1)
+    pub fn try_set_max_garbage_ratio(
+        &mut self,
+        max_garbage_ratio: Option<f64>,
+    ) -> Result<(), DbError> {
+        if let Some(ratio) = max_garbage_ratio.filter(|ratio| !(0.0..1.0).contains(ratio)) {
+            return Err(DbError::InvalidConfig {
+                setting: "max garbage ratio",
+                reason: format!("{ratio} is not in [0, 1)"),
+            });
+        }
+        self.config.max_garbage_ratio = max_garbage_ratio;
+        self.config_dirty = true;
+        Ok(())
+    }
+    pub fn background_compaction(&self) -> bool {
+        self.config.background_compaction
+    }
+    ///Run compaction on a separate thread instead of blocking the put that triggered the flush.
+    /// Disabling waits for queued compactions to finish. Panics with partitioned levels enabled, see
+    /// try_set_background_compaction
+    pub fn set_background_compaction(mut self, background_compaction: bool) -> Self {
+        self.try_set_background_compaction(background_compaction)
+            .unwrap_or_else(|why| panic!("{why}"));
+        self
+    }
+    ///set_background_compaction, or an error if partitioned levels are enabled (see set_partitioned_levels), leaving
+    /// the setting as it was, or if a background compaction failed while disabling it, which is disabled all the same
+    pub fn try_set_background_compaction(
+        &mut self,
+        background_compaction: bool,
+    ) -> Result<(), DbError> {
+        if background_compaction && self.config.partitioned_levels {
+            return Err(Self::partitioned_background_error());
+        }
+        let mut stopped = Ok(());
+        if background_compaction && self.background_compactor.is_none() {
+            self.start_background_compactor();
+        } else if !background_compaction {
+            stopped = self.stop_background_compactor(); //the compactor is gone even if it failed
+        }
+        self.config.background_compaction = background_compaction;
+        self.config_dirty = true;
+        Ok(stopped?)
+    }
+    pub fn read_cache_capacity(&self) -> Size {
+        self.read_cache.capacity()
+    }
+    ///Max number of frequently read SST values to keep in the read cache, 0 disables it. Capacities over u32::MAX are
+    /// capped at it. A cache run holding more is truncated when it's next written
+    pub fn set_read_cache_capacity(mut self, read_cache_capacity: Size) -> Self {
+        self.read_cache.set_capacity(read_cache_capacity);
+        self
+    }
+    pub fn set_compaction_progress_callback(
+        mut self,
+        callback: impl FnMut(&Database, CompactionProgress) + Send + 'static,
+    ) -> Self {
+        self.compaction_progress_callback = Some(CompactionProgressCallback(Box::new(callback)));
+        self
+    }
+    ///Called with a DbEvent for each memtable flush, compaction, move of runs to the next level and held back write,
+    /// e.g. to see what compactions cost under a size ratio. Nothing is measured while no listener is set
+    pub fn set_event_listener(mut self, listener: impl Fn(DbEvent) + Send + 'static) -> Self {
+        self.event_listener = Some(EventListener(Box::new(listener)));
+        self
+    }
+    ///Report a DbEvent::DurabilityLag once the oldest write the memtable holds hasn't been flushed for <threshold>
+    /// (by the database's clock), see unflushed_age. Checked by writes, which read the clock every time while it's set
+    pub fn set_durability_lag_threshold(mut self, threshold: Duration) -> Self {
+        self.durability_lag_threshold = Some(threshold);
+        self
+    }
+    pub fn durability_lag_threshold(&self) -> Option<Duration> {
+        self.durability_lag_threshold
+    }
+    ///Every memtable flush also writes the entries it flushed to a file in <directory> (see flush_audit), which
+    /// verify_flush_audits checks against the runs. For debugging, the directory isn't saved with the config
+    pub fn set_flush_audit_dir(mut self, directory: impl AsRef<Path>) -> Self {
+        self.flush_audit_dir = Some(directory.as_ref().to_path_buf());
+        self
+    }
+    ///Check every get against full reads of the runs, panicking where they disagree (see check_get). Off by default,
+    /// it reads every run for each get so it's only for debugging small databases. The check's reads bypass the
+    /// buffer pool and aren't counted in io_stats. Not saved with the config
+    #[cfg(any(test, feature = "paranoid-reads"))]
+    pub fn set_paranoid_reads(mut self, paranoid_reads: bool) -> Self {
+        self.paranoid_reads = paranoid_reads;
+        self
+    }
+    ///Called between pages of compactions, flushes, try_scan_with_options and between maintenance tasks, so a host
+    /// running the database on its own thread (e.g. an event loop) gets a turn during long operations.
+    /// ControlFlow::Break aborts the operation with DbError::Aborted (an io::Error for which yield_hook::is_aborted
+    /// holds where io::Error is returned), which leaves the database as it was before the operation: a flush keeps
+    /// the memtable, a compaction keeps its input runs. The default hook never aborts.
+    /// NOTE: the background compactor's merges don't call the hook
+    pub fn set_yield_hook(mut self, hook: impl FnMut() -> ControlFlow<()> + 'static) -> Self {
+        self.yield_hook = YieldHook::new(hook);
+        self
+    }
+    ///Clock used to expire entries put with a TTL, defaults to the system clock
+    pub fn set_clock(mut self, clock: impl Clock + 'static) -> Self {
+        self.clock = Box::new(clock);
+        self
+    }
+    fn now(&self) -> Timestamp {
+        self.clock.now()
+    }
+    ///How long ago (by the database's clock) the oldest write that isn't flushed yet happened, None if the memtable
+    /// (and any view frozen by freeze_memtable) is empty. There's no write-ahead log, so that's how long a crash
+    /// would have lost writes for
+    pub fn unflushed_age(&self) -> Option<Duration> {
+        let first_write = self.memtable.oldest_unflushed_write()?;
+        Some(Duration::from_secs(self.now().saturating_sub(first_write)))
+    }
+    ///Number of entries a crash would lose, those in the memtable and in any view frozen by freeze_memtable.
+    /// NOTE: a key written again since the freeze is counted twice
+    pub fn unflushed_entries(&self) -> Size {
+        self.memtable.len_unflushed()
+    }
+    ///Use <buffer_pool> instead of a buffer pool of our own, the pool's capacity isn't changed by our config
+    pub(crate) fn share_buffer_pool(mut self, buffer_pool: Rc<RefCell<BufferPool>>) -> Self {
+        self.buffer_pool = buffer_pool;
+        self.owns_buffer_pool = false;
+        self
+    }
+    pub(crate) fn is_closed(&self) -> bool {
+        self.name.as_os_str() == Self::NO_OPEN_DB_NAME
+    }
+    ///DbError::Closed once the database is closed, operations that write check this first, reads only in their try_
+    /// forms (get finds nothing in a closed database)
+    fn ensure_open(&self) -> Result<(), DbError> {
+        match self.is_closed() {
+            true => Err(DbError::Closed),
+            false => Ok(()),
+        }
+    }
+    ///Gets number of SST runs in level, NOTE: a value of 0 can mean that there is no level
+    fn sst_count(&self, level: Level) -> Size {
+        self.level_shape.run_count(level)
+    }
+    ///Gets total number of SST runs in database
+    fn sst_total(&self) -> Size {
+        self.level_shape.total_runs()
+    }
+    ///Number of runs and entries in each level, as of the last time the background compactor was synced with
+    pub fn level_shape(&self) -> &LevelShape {
+        &self.level_shape
+    }
+    ///What the next flush of a full memtable would do to the levels under the current compaction policy: runs moved,
+    /// levels merged, pages read and written, and the resulting entry counts. Works from the metadata alone, without
+    /// any I/O. NOTE: merges are estimated as if no key is in more than one run. With background compaction the same
+    /// steps happen on the compaction thread, starting from the levels as of the last time it was synced with
+    pub fn compaction_plan(&self) -> CompactionPlan {
+        CompactionPlan::for_flush(
+            self.config.compaction_policy,
+            self.config.sst_size_ratio,
+            self.config.memtable_capacity,
+            &self.metadata.entry_counts,
+            &self.metadata.tombstone_counts,
+            self.config.memtable_capacity,
+            match self.config.level_zero_overflow {
+                LevelZeroOverflowBehavior::Auto => self.level_zero_run_limit(),
+                LevelZeroOverflowBehavior::Backpressure => 0, //the flush fails instead
+            },
+        )
+    }
+    ///Why the last compaction failed (e.g. an input run isn't sorted), the level it was merging keeps its runs.
+    /// Cleared once a compaction succeeds
+    pub fn compaction_error(&self) -> Option<&io::Error> {
+        self.compaction_error.as_ref()
+    }
+    ///Debug builds check that every change to entry_counts was mirrored in the level shape
+    fn check_level_shape(&self) {
+        debug_assert_eq!(
+            self.level_shape,
+            LevelShape::from_entry_counts(&self.metadata.entry_counts),
+            "Level shape diverged from entry counts"
         );
-        if entry_counts[level].is_empty() {
-            return; //Nothing to flush to the next level
+    }
+    //GETTERS AND SETTERS (end)
+
+    ///Whether enough of a level's entries are tombstones that it's worth compacting early
+    pub(crate) fn is_tombstone_dense(entry_counts: &[Size], tombstone_counts: &[Size]) -> bool {
+        if entry_counts.len() < 2 {
+            return false; //nothing to merge
         }
+        let num_entries: Size = entry_counts.iter().sum();
+        let num_tombstones: Size = tombstone_counts.iter().sum();
+        num_tombstones as f64 >= num_entries as f64 * Self::TOMBSTONE_DENSITY_COMPACTION_THRESHOLD
+    }
+    ///Human readable summary of every level and run, along with per run statistics
+    pub fn describe_layout(&self) -> String {
+        let background_levels = self.background_levels();
+        let background_state = background_levels.as_deref().map(|levels| {
+            levels
+                .lock()
+                .unwrap_or_else(|poisoned| poisoned.into_inner())
+        });
+        let Metadata {
+            entry_counts,
+            tombstone_counts,
+            compaction_entries_dropped,
+            page_sizes,
+            ..
+        } = &self.metadata;
+        //the background compactor's copy is the most up to date
+        let (entry_counts, tombstone_counts, compaction_entries_dropped, page_sizes) =
+            match &background_state {
+                Some(state) => (
+                    &state.entry_counts,
+                    &state.tombstone_counts,
+                    compaction_entries_dropped + state.entries_dropped,
+                    &state.page_sizes,
+                ),
+                None => (
+                    entry_counts,
+                    tombstone_counts,
+                    *compaction_entries_dropped,
+                    page_sizes,
+                ),
+            };
 
-        //Step 0: make sure we have entry counts on the next level, if not add an empty vec
-        //        and the directory needed to hold files for that level
-        let num_runs_in_next_level: Run = match entry_counts.get(next_level) {
-            Some(entry_count) => entry_count.len(),
-            None => {
-                let directory = filename::lsm_level_directory(&(db_name, next_level));
-                if !direct_io::path_exists(&directory) {
-                    fs::create_dir(&directory).unwrap_or_else(|why| {
-                        panic!("Unable to create directory for level {next_level}, reason {why}")
-                    });
+        let mut layout = String::new();
+        for (level, level_entry_counts) in entry_counts.iter().enumerate() {
+            layout.push_str(&format!(
+                "level {level}: {} runs, {}\n",
+                level_entry_counts.len(),
+                if self.config.bloom_levels.includes(level) {
+                    "bloom filters"
+                } else {
+                    "no bloom filters"
                 }
-                entry_counts.push(vec![]);
-                0
+            ));
+            for (run, num_entries) in level_entry_counts.iter().enumerate() {
+                layout.push_str(&format!(
+                    "  run {run}: {num_entries} entries, {} tombstones",
+                    tombstone_counts[level][run]
+                ));
+                //copied from a machine with another page size, read with the pages it was written with
+                let page_size = page_sizes[level][run];
+                if !Self::has_host_pages(page_size) {
+                    layout.push_str(&format!(", {page_size} byte pages"));
+                }
+                layout.push('\n');
             }
-        };
-
-        //Step 1: Move each run and associated files to next level with new run number
-        for run in 0..entry_counts[level].len() {
-            let new_run = run + num_runs_in_next_level; //run number to rename assign to our run when it's moved
-
-            let old_run_address = &(db_name.as_str(), level, run);
-            let new_run_address = &(db_name.as_str(), next_level, new_run);
-            //rename SST file
-            let sst_path = filename::sst_path(old_run_address);
-            let new_sst_path = filename::sst_path(new_run_address);
-
-            assert!(
-                !Path::new(&new_sst_path).exists(),
-                "{new_sst_path} already_exists!"
-            );
-            file_interface::rename_file(&sst_path, &new_sst_path, buffer_pool.as_deref_mut()).unwrap_or_else(|why| panic!("Failed to rename SST file from {sst_path} to {new_sst_path}, reason: {why}")); //every run has an SST file (don't need to check if it exists)
-
-            //rename B-tree file (if applicable)
-            let btree_path = filename::sst_btree_path(old_run_address);
-            if Path::new(&btree_path).exists() {
-                let new_btree_path = filename::sst_btree_path(new_run_address);
-                file_interface::rename_file(&btree_path, &new_btree_path, buffer_pool.as_deref_mut()).unwrap_or_else(|why| panic!("Failed to rename B-tree file from {btree_path} to {new_btree_path}, reason: {why}"));
+        }
+        layout.push_str(&format!(
+            "entries dropped by compaction: {compaction_entries_dropped}\n"
+        ));
+        for ratio in self
+            .size_ratio_history(background_state.as_deref())
+            .ratios()
+        {
+            let LevelSizeRatio {
+                level,
+                observed,
+                smoothed,
+                ..
+            } = ratio;
+            layout.push_str(&format!(
+                "size ratio of levels {level} and {}: {observed:.1} observed, {smoothed:.1} on average\n",
+                level + 1
+            ));
+            if let Some(suggestion) =
+                SizeRatioHistory::suggestion(&ratio, self.config.sst_size_ratio)
+            {
+                layout.push_str(&format!("  {suggestion}\n"));
             }
+        }
+        layout
+    }
+    ///Size ratio the data settled into between each pair of adjacent levels, as recorded by the compactions since the
+    /// database was opened (background ones included). Pairs where either level was empty after every compaction aren't
+    /// listed, compare with sst_size_ratio
+    pub fn level_size_ratios(&self) -> Vec<LevelSizeRatio> {
+        let background_levels = self.background_levels();
+        let background_state = background_levels.as_deref().map(|levels| {
+            levels
+                .lock()
+                .unwrap_or_else(|poisoned| poisoned.into_inner())
+        });
+        self.size_ratio_history(background_state.as_deref())
+            .ratios()
+    }
+    ///Our size ratio history, plus the compactions the background compactor did since we last synced with it
+    fn size_ratio_history(&self, background_state: Option<&LevelState>) -> SizeRatioHistory {
+        let mut size_ratios = self.size_ratios.clone();
+        for level_bytes in background_state.iter().flat_map(|state| &state.level_bytes) {
+            size_ratios.record(level_bytes);
+        }
+        size_ratios
+    }
 
-            //rename bloom filter (if applicable)
-            let bloom_path = filename::bloom_filter_path(old_run_address);
-            if Path::new(&bloom_path).exists() {
-                let new_bloom_path = filename::bloom_filter_path(new_run_address);
-                file_interface::rename_file(&bloom_path, &new_bloom_path, buffer_pool.as_deref_mut()).unwrap_or_else(|why| panic!("Failed to rename bloom filter file from {bloom_path} to {new_bloom_path}, reason: {why}"));
+    ///Estimated fraction of the entries of <level> that are garbage: tombstones, and entries shadowed by a younger entry
+    /// for the same key in the level. Exact for a level with one run, shadowed entries across runs are estimated from
+    /// a sketch of each run's keys. 0 for an empty level or one that doesn't exist
+    pub fn garbage_ratio(&self, level: Level) -> f64 {
+        let background_levels = self.background_levels();
+        match background_levels.as_deref() {
+            Some(levels) => {
+                let state = levels
+                    .lock()
+                    .unwrap_or_else(|poisoned| poisoned.into_inner());
+                match state.entry_counts.get(level) {
+                    Some(entry_counts) => garbage::garbage_ratio(
+                        entry_counts,
+                        &state.tombstone_counts[level],
+                        &state.key_sketches[level],
+                    ),
+                    None => 0.0,
+                }
             }
+            None => Self::level_garbage_ratio(&self.metadata, level),
         }
-
-        //Step 2: move metadata to next level
-        let mut curr_level_counts = Vec::<Size>::new();
-        std::mem::swap(&mut curr_level_counts, &mut entry_counts[level]);
-        entry_counts[next_level].extend(curr_level_counts);
-
-        //Step 3: handle compaction
-        self.handle_compaction(level + 1)
     }
-    ///compacts depending on number of ssts at level and compaction policy
-    fn handle_compaction(&mut self, level: Level) {
-        if level < 1 {
-            return; //Level 0 never needs to compact runs
+    fn level_garbage_ratio(metadata: &Metadata, level: Level) -> f64 {
+        match metadata.entry_counts.get(level) {
+            Some(entry_counts) => garbage::garbage_ratio(
+                entry_counts,
+                &metadata.tombstone_counts[level],
+                &metadata.key_sketches[level],
+            ),
+            None => 0.0,
         }
+    }
 
-        let num_runs = self.sst_count(level);
-        let size_ratio = self.config.sst_size_ratio;
-        let is_last_level = level == self.metadata.entry_counts.len() - 1; //should discard tombstones on last level only
-
-        let compact = |db: &mut Database| {
-            let num_runs = db.sst_count(level);
-            let sst = db.sst_interface();
-            let mut buffer_pool = if db.config.enable_buffer_pool {
-                Some(&mut db.buffer_pool)
-            } else {
-                None
-            };
-
-            //remove bloom filter files, a new one will be made after compaction
-            for run in 0..num_runs {
-                file_interface::remove_file(&filename::bloom_filter_path(&(&db.name, level, run)), buffer_pool.as_deref_mut()).unwrap_or_else(|why| panic!("Failed to delete bloom filter file at level: {level} run: {run}, reason: {why}"))
+    ///Every run of every level, from the youngest run of level 0 to the oldest run of the last level, with the size of
+    /// its SST file and which of its files exist. A run whose SST file can't be read is still listed, flagged by has_sst.
+    /// Pair with export::export_run to read a run's entries
+    pub fn list_runs(&self) -> Vec<RunInfo> {
+        let background_levels = self.background_levels();
+        let background_state = background_levels.as_deref().map(|levels| {
+            levels
+                .lock()
+                .unwrap_or_else(|poisoned| poisoned.into_inner())
+        }); //held while the files are looked at, so a compaction can't replace them
+        let (entry_counts, page_sizes) = match &background_state {
+            Some(state) => (&state.entry_counts, &state.page_sizes),
+            None => (&self.metadata.entry_counts, &self.metadata.page_sizes),
+        };
+        let mut runs = Vec::new();
+        for (level, level_entry_counts) in entry_counts.iter().enumerate() {
+            for run in (0..level_entry_counts.len()).rev() {
+                let run_address = (&self.name, level, run);
+                let bytes = self
+                    .name
+                    .storage()
+                    .open_read_buffered(&run_address.path_sst())
+                    .and_then(|file| file.len_bytes());
+                runs.push(RunInfo {
+                    level,
+                    run,
+                    entries: level_entry_counts[run],
+                    bytes: bytes.as_ref().map_or(0, |bytes| *bytes as Size),
+                    has_sst: bytes.is_ok(),
+                    has_btree: self.name.storage().path_exists(&run_address.path_btree()),
+                    has_bloom: self.name.storage().path_exists(&run_address.path_bloom()),
+                    page_size: page_sizes[level][run],
+                });
             }
+        }
+        runs
+    }
 
-            //compact SSTs
-            sst.compact(
-                &(&db.name, level),
-                &mut db.metadata.entry_counts[level],
-                is_last_level,
-                buffer_pool,
-            )
-            .unwrap_or_else(|why| panic!("Failed to compact level {level}, reason {why}"));
-
-            //write new bloom filter (if there is something left after compaction)
-            if db.enable_bloom_filter() && !db.metadata.entry_counts[level].is_empty() {
-                let run = 0;
-                let run_address = &(db.name.as_str(), level, run);
-                BloomFilterIO::write_from_sst(
-                    run_address,
-                    db.bloom_filter_bits_per_entry(),
-                    db.metadata.entry_counts[level][run],
-                )
-                .unwrap_or_else(|why| panic!("Failed to compact level {level}, reason {why}"));
-            }
+    ///Bytes the database takes on disk per level and per kind of file, read from file metadata (no page is read). Files
+    /// under the database's directory that don't belong to a run or to its state (e.g. snapshots) are counted apart
+    pub fn approximate_size_bytes(&self) -> DiskUsage {
+        let background_levels = self.background_levels();
+        let background_state = background_levels.as_deref().map(|levels| {
+            levels
+                .lock()
+                .unwrap_or_else(|poisoned| poisoned.into_inner())
+        }); //held while the files are looked at, so a compaction can't replace them
+        let entry_counts = match &background_state {
+            Some(state) => &state.entry_counts,
+            None => &self.metadata.entry_counts,
         };
-        let leveled_compact = |db: &mut Database| {
-            //LEVELED COMPACTION: greedily compact, only move runs to next level if we reach our size limit (size_ratio * run size of prev level)
-            compact(db);
+        disk_usage::measure(&self.name, entry_counts)
+    }
 
-            //check if we reached our size limit for this level, if so, move our compacted run to next level
-            let num_entries = db.metadata.entry_counts[level].first().unwrap_or(&0);
-            let lower_lvl_run_size = db.config.memtable_capacity * size_ratio.pow(level as u32 - 1);
-            if ceil_div!(num_entries, lower_lvl_run_size) >= size_ratio {
-                db.move_runs(level)
-            }
-        };
-        let tiered_compact = |db: &mut Database| {
-            //TIERED COMPACTION: only compact once we reach our size ratio (in # of runs), then move items to the next level
-            if num_runs >= size_ratio {
-                compact(db);
-                db.move_runs(level);
-            }
-        };
+    ///Check every flush audit in <directory> (see set_flush_audit_dir) against the runs, oldest flush first. A flushed
+    /// run is found by the largest sequence number it holds, wherever it was moved since, and must hold exactly the
+    /// audited bytes. Runs compacted since can't be checked
+    pub fn verify_flush_audits(
+        &mut self,
+        directory: &Path,
+    ) -> Result<Vec<(FlushAudit, AuditCheck)>, DbError> {
+        let background_levels = self.background_levels();
+        let _background_state = background_levels
+            .as_deref()
+            .map(|levels| self.sync_levels(levels));
 
-        match self.config.compaction_policy {
-            CompactionPolicy::None => {}
-            CompactionPolicy::Leveled => leveled_compact(self),
-            CompactionPolicy::Tiered => tiered_compact(self),
-            CompactionPolicy::Dostoevsky => {
-                if is_last_level {
-                    leveled_compact(self);
-                } else {
-                    tiered_compact(self);
+        let mut checks = Vec::new();
+        for audit in flush_audit::list(directory)? {
+            let bytes = audit.read()?;
+            let mut check = AuditCheck::Superseded;
+            let Metadata {
+                entry_counts,
+                max_sequences,
+                ..
+            } = &self.metadata;
+            'runs: for (level, level_max_sequences) in max_sequences.iter().enumerate() {
+                for (run, max_sequence) in level_max_sequences.iter().enumerate() {
+                    //a compaction keeps the largest sequence number of its inputs, but not their entry count
+                    if *max_sequence != audit.max_sequence
+                        || entry_counts[level][run] * ENTRY_SIZE != bytes.len()
+                    {
+                        continue;
+                    }
+                    let run_bytes = compression::read_run(
+                        self.name.storage(),
+                        &(&self.name, level, run).path_sst(),
+                    )?;
+                    if run_bytes == bytes {
+                        check = AuditCheck::Matches { level, run };
+                        break 'runs;
+                    }
+                    check = AuditCheck::Mismatch { level, run };
                 }
             }
+            checks.push((audit, check));
         }
+        Ok(checks)
     }
-    fn sst_interface(&self) -> Box<dyn SortedStringTable> {
-        match self.config.sst_implementation {
-            SstImplementation::Array => Box::new(array_sst::Sst {}),
-            SstImplementation::Btree => Box::new(btree_sst::Sst {}),
-        }
-    }
-    ///Writes memtable contents to disk, clears memtable, and handles compaction if needed
-    fn flush_memtable(&mut self) {
-        if self.memtable.len() < 1 {
-            //shouldn't flush if there's nothing to flush
-            return;
-        }
-
-        let level = Self::LEVEL_ZERO;
 
-        //if we want compaction, we need to move the current SST at level 0 up a level
-        match self.config.compaction_policy {
-            CompactionPolicy::None => {}
-            _ => self.move_runs(level),
+    ///Cheap checks that the database is still operational, for readiness probes: the handle is open, the directory,
+    /// config and metadata files are there and decode, the buffer pool is within its capacity and no compaction
+    /// failure is latched. With <read_canary> the first page of the newest run is read too, bypassing the buffer pool.
+    /// Nothing panics, a background compaction failure is reported here rather than on the next write
+    pub fn health_check(&self, read_canary: bool) -> HealthReport {
+        let status = |result: Result<(), String>| match result {
+            Ok(()) => CheckStatus::Pass,
+            Err(why) => CheckStatus::Fail(why),
         };
+        let mut checks = vec![(
+            HealthCheck::Open,
+            status(match self.is_closed() {
+                true => Err("the database was closed".to_string()),
+                false => Ok(()),
+            }),
+        )];
+        if self.is_closed() {
+            return HealthReport { checks };
+        }
+        checks.push((
+            HealthCheck::Directory,
+            status(match self.name.storage().path_exists(&self.name) {
+                true => Ok(()),
+                false => Err(format!("{} doesn't exist", self.name.display())),
+            }),
+        ));
+        let read_state_files = || -> Result<(), String> {
+            let read = |path: PathBuf| {
+                self.name
+                    .storage()
+                    .read_file(&path)
+                    .map_err(|why| format!("{}: {why}", path.display()))
+            };
+            Config::deserialize(&read(filename::config(&self.name))?)
+                .map_err(|why| why.to_string())?;
+            Metadata::deserialize(&read(filename::metadata(&self.name))?)
+                .map_err(|why| why.to_string())?;
+            Ok(())
+        };
+        checks.push((HealthCheck::Metadata, status(read_state_files())));
+        let pool_stats = self.buffer_pool_stats();
+        checks.push((
+            HealthCheck::BufferPool,
+            status(match pool_stats.bytes <= pool_stats.capacity_bytes {
+                true => Ok(()),
+                false => Err(format!(
+                    "{} bytes cached, over the capacity of {} bytes",
+                    pool_stats.bytes, pool_stats.capacity_bytes
+                )),
+            }),
+        ));
 
-        let next_run_num = self.sst_count(level); //this will be zero after moving runs
-        let run_address = &(self.name.as_str(), level, next_run_num);
-
-        //Write memtable to storage
-        let entries = self.memtable.as_vec();
-        let num_entries = entries.len();
+        let background_levels = self.background_levels();
+        let background_state = background_levels.as_deref().map(|levels| {
+            levels
+                .lock()
+                .unwrap_or_else(|poisoned| poisoned.into_inner())
+        }); //held through the canary read, so a compaction can't replace the run
+        let background_error = background_state
+            .as_ref()
+            .and_then(|state| state.error.clone());
+        checks.push((
+            HealthCheck::LatchedError,
+            status(match (&self.compaction_error, background_error) {
+                (Some(why), _) => Err(format!("compaction failed, {why}")),
+                (None, Some(why)) => Err(format!("background compaction failed, {why}")),
+                (None, None) => Ok(()),
+            }),
+        ));
 
-        self.sst_interface()
-            .write(run_address, &entries)
-            .unwrap_or_else(|why| panic!("Failed to flush memtable to SST, reason: {why}"));
+        let entry_counts = match &background_state {
+            Some(state) => &state.entry_counts,
+            None => &self.metadata.entry_counts,
+        };
+        let mut newest_run = None;
+        Self::for_each_sst(entry_counts, &mut |level, run| {
+            newest_run = Some((level, run));
+            true //stop at the first, youngest, run
+        });
+        let canary = match (read_canary, newest_run) {
+            (false, _) => CheckStatus::Skipped("not requested".to_string()),
+            (true, None) => CheckStatus::Skipped("there are no runs".to_string()),
+            (true, Some((level, run))) => {
+                let run_address = (&self.name, level, run);
+                let min_entries = sst_util::num_entries_at_page(entry_counts[level][run], 0);
+                let read_page = || -> io::Result<()> {
+                    let mut file = self.name.storage().open_read(&run_address.path_sst())?;
+                    let index = compression::read_index(&mut file)?;
+                    let page = compression::read_page(&mut file, index.as_ref(), 0)?;
+                    sst_util::check_page_len(&run_address, 0, &page, min_entries)
+                };
+                status(read_page().map_err(|why| format!("level {level}, sst {run}: {why}")))
+            }
+        };
+        checks.push((HealthCheck::Canary, canary));
+        HealthReport { checks }
+    }
 
-        if self.enable_bloom_filter() {
-            let filter = BloomFilter::from_entries(&entries, self.bloom_filter_bits_per_entry());
-            BloomFilterIO::write(run_address, &filter.bitmap).unwrap_or_else(|why| {
-                panic!("Failed to write bloom filter for memtable flush, reason: {why}")
-            });
+    fn compaction_settings(&self) -> CompactionSettings {
+        CompactionSettings {
+            db_name: self.name.clone(),
+            compaction_policy: self.config.compaction_policy,
+            sst_size_ratio: self.config.sst_size_ratio,
+            memtable_capacity: self.config.memtable_capacity,
+            sst_implementation: self.config.sst_implementation,
+            key_codec: self.config.key_codec,
+            bloom_levels: self.config.bloom_levels,
+            bloom_filter_bits_per_entry: self.config.bloom_filter_bits_per_entry,
+            enable_fence_index: self.config.enable_fence_index,
+            memory_budget: self.config.compaction_memory_budget,
+            merge_operator: self.config.merge_operator,
+            now: self.now(),
         }
-
-        self.metadata.entry_counts[level].push(num_entries);
-
-        self.memtable.clear();
     }
-    pub fn close(&mut self) {
-        if self.is_closed() {
-            return;
+    fn background_levels(&self) -> Option<Arc<Mutex<LevelState>>> {
+        self.background_compactor
+            .as_ref()
+            .map(BackgroundCompactor::levels)
+    }
+    ///Lock the background compactor's level state and bring our metadata and buffer pool up to date with it.
+    /// Run files can't be replaced while the returned guard is held.
+    /// After a failed compaction the levels are as the last one that succeeded left them, so reads go on, the failure
+    /// is recorded in compaction_error and flushes fail with it (see background_error)
+    fn sync_levels<'a>(&mut self, levels: &'a Mutex<LevelState>) -> MutexGuard<'a, LevelState> {
+        let mut state = levels
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        if let Some(why) = Self::background_error(&state) {
+            self.compaction_error = Some(why);
         }
-        self.flush_memtable();
-        self.write_db_state();
-        self.clear();
+        self.metadata_dirty |= Self::apply_level_state(
+            &mut self.metadata,
+            &mut self.buffer_pool.borrow_mut(),
+            &mut self.size_ratios,
+            &mut state,
+        );
+        self.level_shape = LevelShape::from_entry_counts(&self.metadata.entry_counts); //compactor rewrote the levels
+        state
     }
-    fn put_unchecked(&mut self, key: Key, value: Value) {
-        if self.memtable.len() < self.memtable_capacity() {
-            self.memtable.put(key, value);
-            return;
+    ///Returns whether the metadata changed
+    fn apply_level_state(
+        metadata: &mut Metadata,
+        buffer_pool: &mut BufferPool,
+        size_ratios: &mut SizeRatioHistory,
+        state: &mut LevelState,
+    ) -> bool {
+        for path in state.stale_paths.drain(..) {
+            buffer_pool.remove(&path);
         }
-
-        self.flush_memtable();
-
-        if self.memtable.len() >= self.memtable_capacity() {
-            //This should only happen if capacity is zero, which should never happen
-            panic!("Memtable at (or over) capacity after flush");
+        for level_bytes in state.level_bytes.drain(..) {
+            size_ratios.record(&level_bytes);
         }
-
-        self.memtable.put(key, value);
+        let changed = metadata.entry_counts != state.entry_counts
+            || metadata.tombstone_counts != state.tombstone_counts
+            || metadata.expiry_counts != state.expiry_counts
+            || metadata.page_sizes != state.page_sizes
+            || metadata.max_sequences != state.max_sequences
+            || metadata.operand_counts != state.operand_counts
+            || metadata.key_sketches != state.key_sketches
+            || state.entries_dropped > 0;
+        metadata.entry_counts.clone_from(&state.entry_counts);
+        metadata
+            .tombstone_counts
+            .clone_from(&state.tombstone_counts);
+        metadata.expiry_counts.clone_from(&state.expiry_counts);
+        metadata.page_sizes.clone_from(&state.page_sizes);
+        metadata.max_sequences.clone_from(&state.max_sequences);
+        metadata.operand_counts.clone_from(&state.operand_counts);
+        metadata.key_sketches.clone_from(&state.key_sketches);
+        metadata.compaction_entries_dropped += std::mem::take(&mut state.entries_dropped);
+        if changed {
+            metadata.partitions.clear(); //the compactor doesn't keep partitions
+        }
+        changed
+    }
+    ///Why the background compactor stopped, None if it hasn't failed
+    fn background_error(state: &LevelState) -> Option<io::Error> {
+        state
+            .error
+            .as_ref()
+            .map(|why| io::Error::other(format!("Background compaction failed, reason: {why}")))
     }
-    pub fn put(&mut self, key: Key, value: Value) {
-        if value == Database::TOMBSTONE_VALUE {
-            panic!("Attempted to insert tombstone value");
+    ///Hand ownership of level metadata to a new background compactor
+    fn start_background_compactor(&mut self) {
+        self.background_compactor = Some(BackgroundCompactor::new(
+            self.metadata.entry_counts.clone(),
+            self.metadata.tombstone_counts.clone(),
+            self.metadata.expiry_counts.clone(),
+            self.metadata.page_sizes.clone(),
+            self.metadata.max_sequences.clone(),
+            self.metadata.operand_counts.clone(),
+            self.metadata.key_sketches.clone(),
+        ));
+    }
+    ///Wait for queued compactions, then take back ownership of level metadata. Fails if a compaction failed, the
+    /// levels are left as the last compaction that succeeded left them
+    fn stop_background_compactor(&mut self) -> io::Result<()> {
+        let Some(compactor) = self.background_compactor.take() else {
+            return Ok(());
+        };
+        let mut state = compactor.stop();
+        self.metadata_dirty |= Self::apply_level_state(
+            &mut self.metadata,
+            &mut self.buffer_pool.borrow_mut(),
+            &mut self.size_ratios,
+            &mut state,
+        );
+        self.level_shape = LevelShape::from_entry_counts(&self.metadata.entry_counts);
+        match Self::background_error(&state) {
+            Some(why) => Err(why),
+            None => Ok(()),
         }
-        if value == Self::INVALID_KEY {
-            panic!("Attempted to insert invalid key");
+    }
+
+    ///Context for an error writing one of our files on close, e.g. "Unable to write config file"
+    fn file_error(&self, what: &str, why: io::Error) -> io::Error {
+        io::Error::new(
+            why.kind(),
+            format!(
+                "Unable to write {what} for {}, reason {why}",
+                self.name.display()
+            ),
+        )
+    }
+    fn write_config_file(&self) -> io::Result<()> {
+        self.name
+            .storage()
+            .write_file(&filename::config(&self.name), &self.config.serialize())
+            .map_err(|why| self.file_error("config file", why))
+    }
+    fn write_metadata_file(&self) -> io::Result<()> {
+        self.name
+            .storage()
+            .write_file(&filename::metadata(&self.name), &self.metadata.serialize())
+            .map_err(|why| self.file_error("metadata file", why))
+    }
+    ///Writes the read cache's entries to its run and its descriptor (if enabled), so hot keys stay cached across
+    /// restarts. A cache run that can't be written is discarded, the close goes on without it
+    fn write_read_cache_file(&mut self) -> io::Result<()> {
+        if self.read_cache.capacity() == 0 {
+            return Ok(());
         }
-        self.put_unchecked(key, value);
+        let _ = self.rewrite_read_cache();
+        self.name
+            .storage()
+            .write_file(
+                &filename::read_cache(&self.name),
+                &self.read_cache.serialize(),
+            )
+            .map_err(|why| self.file_error("read cache file", why))
     }
-    pub fn delete(&mut self, key: Key) {
-        self.put_unchecked(key, Self::TOMBSTONE_VALUE);
+    ///Loads the read cache written by the last close, see ReadCache::open. Reads fill the cache again, so a descriptor
+    /// that can't be deserialized or a cache run that isn't intact is removed, leaving the cache empty
+    fn read_read_cache_file(&mut self) -> io::Result<()> {
+        self.read_cache = ReadCache::open(&self.name).map_err(|why| {
+            io::Error::new(
+                why.kind(),
+                format!(
+                    "Unable to remove read cache files of {}, reason {why}",
+                    self.name.display()
+                ),
+            )
+        })?;
+        Ok(())
     }
-    ///For each sst, from youngest to oldest, run a callback function (the callback returns true if we want to return early)
-    fn for_each_sst(entry_counts: &[Vec<Size>], callback: &mut dyn FnMut(Level, Run) -> bool) {
-        for (level, level_entry_counts) in entry_counts.iter().enumerate() {
-            //lower level is younger
-            let runs_in_level = level_entry_counts.len();
-            for run in (0..runs_in_level).rev() {
-                //higher number sst is younger
-                if callback(level, run) {
-                    return;
-                }
+    ///Saves which runs maintenance already collected and verified, see maintain
+    fn write_maintenance_file(&mut self) -> io::Result<()> {
+        self.maintenance
+            .retain_runs(&Self::run_stats(&self.metadata));
+        let path = filename::maintenance(&self.name);
+        let result = match self.maintenance.is_empty() {
+            true if self.name.storage().path_exists(&path) => {
+                self.name.storage().remove_file(&path)
             }
+            true => Ok(()),
+            false => self
+                .name
+                .storage()
+                .write_file(&path, &self.maintenance.serialize()),
+        };
+        result.map_err(|why| self.file_error("maintenance file", why))
+    }
+    ///Read back which runs maintenance already collected and verified. A file that can't be deserialized is ignored,
+    /// maintenance starts over (and the file is rewritten on close)
+    fn read_maintenance_file(&mut self) {
+        let Ok(bytes) = self
+            .name
+            .storage()
+            .read_file(&filename::maintenance(&self.name))
+        else {
+            return; //no maintenance done yet
+        };
+        if let Ok(maintenance) = MaintenanceScheduler::deserialize(&bytes) {
+            self.maintenance = maintenance;
         }
     }
-    pub fn get(&mut self, key: Key) -> Option<Value> {
-        //check memtable first
-        if let Some(value) = self.memtable.get(key) {
-            if value == Self::TOMBSTONE_VALUE {
-                return None;
+    ///Saves which of our pages are in the buffer pool (paths relative to our root), see set_persist_buffer_pool
+    fn write_warm_file(&self) -> io::Result<()> {
+        let path = filename::buffer_pool_warm(&self.name);
+        let pages: Vec<(String, Page)> =
+            match self.persist_buffer_pool() && self.enable_buffer_pool() {
+                true => self
+                    .buffer_pool
+                    .borrow()
+                    .resident_pages(&self.name)
+                    .into_iter()
+                    .filter_map(|(page_path, page)| {
+                        let relative = page_path.strip_prefix(&self.name).ok()?;
+                        Some((relative.to_str()?.to_string(), page))
+                    })
+                    .collect(),
+                false => Vec::new(),
+            };
+        let result = match pages.is_empty() {
+            true if self.name.storage().path_exists(&path) => {
+                self.name.storage().remove_file(&path)
             }
-            return Some(value);
+            true => Ok(()),
+            false => self
+                .name
+                .storage()
+                .write_file(&path, &serde_state::serialize(&pages)),
+        };
+        result.map_err(|why| self.file_error("buffer pool warm-start file", why))
+    }
+    ///Read back the pages saved by write_warm_file. They're only a hint, a file that can't be deserialized is removed
+    /// like one that was read
+    fn read_warm_file(&mut self) -> io::Result<()> {
+        let path = filename::buffer_pool_warm(&self.name);
+        let Ok(bytes) = self.name.storage().read_file(&path) else {
+            return Ok(()); //nothing saved
+        };
+        if let Ok(pages) = serde_state::deserialize::<Vec<(String, Page)>>(&bytes) {
+            self.warm_pages = pages
+                .into_iter()
+                .map(|(relative, page)| (self.name.join(relative), page))
+                .collect();
         }
-        let sst = self.sst_interface();
-        let search_algorithm = self.sst_search_algorithm();
-
-        //search ssts within levels from youngest to oldest, return youngest value found
-        let mut sst_search_result: Option<Value> = None;
-        let enable_bloom_filter = self.enable_bloom_filter();
-
-        let mut buffer_pool = if self.config.enable_buffer_pool {
-            Some(&mut self.buffer_pool)
+        self.name.storage().remove_file(&path).map_err(|why| {
+            io::Error::new(
+                why.kind(),
+                format!(
+                    "Unable to remove buffer pool warm-start file {}, reason {why}",
+                    path.display()
+                ),
+            )
+        })
+    }
+    ///Read the pages saved by the last close (see set_persist_buffer_pool) into the buffer pool, if they haven't been
+    /// already. Pages of files that don't exist anymore (e.g. compacted away since) are skipped.
+    /// Returns the number of pages read in, which is 0 if the buffer pool is disabled
+    pub fn warm_up(&mut self) -> io::Result<Size> {
+        let warm_pages = std::mem::take(&mut self.warm_pages);
+        if !self.enable_buffer_pool() {
+            return Ok(0);
+        }
+        let mut pool = self.buffer_pool.borrow_mut();
+        let mut num_pages = 0;
+        for (path, page) in warm_pages {
+            let is_sst = path
+                .extension()
+                .is_some_and(|extension| extension == filename::SST_FILE_EXTENSION);
+            let read = match is_sst {
+                //a compressed run's footer comes back with its first page
+                true if page == compression::INDEX_PAGE => continue,
+                true => compression::get_page(self.name.storage(), &path, page, Some(&mut pool)),
+                false => {
+                    file_interface::get_page(self.name.storage(), &path, page, Some(&mut pool))
+                }
+            };
+            match read {
+                Ok(bytes) if !bytes.is_empty() => num_pages += 1,
+                Ok(_) => {} //past the end of a file that shrank since
+                Err(why) if why.kind() == io::ErrorKind::NotFound => {}
+                Err(why) => return Err(why),
+            }
+        }
+        Ok(num_pages)
+    }
+    ///Writes config and metadata files, skipping the ones that haven't changed since they were last written
+    fn write_db_state(&mut self) -> Result<(), DbError> {
+        self.ensure_open()?;
+        //the marker's metadata would undo ours if it was left for the next open to finish
+        let mut pool = self.buffer_pool.borrow_mut();
+        let buffer_pool = if self.config.enable_buffer_pool {
+            Some(&mut *pool)
         } else {
             None
         };
-        let entry_counts = &self.metadata.entry_counts;
-        let bits_per_entry = &self.config.bloom_filter_bits_per_entry;
-        let mut callback = |level, run| {
-            let run_address = &(self.name.as_str(), level, run);
-            if enable_bloom_filter && !BloomFilterIO::contains(run_address, key, *bits_per_entry, entry_counts[level][run], buffer_pool.as_deref_mut())
-                .unwrap_or_else(|why| panic!("Something went wrong trying to query bloom filter for key {key} at level {level}, sst {run}, reason: {why}")) {
-                return false;
-            }
-            let mut get = || match search_algorithm {
-                SstSearchAlgorithm::Default => sst.get(
-                    run_address,
-                    key,
-                    entry_counts[level][run],
-                    buffer_pool.as_deref_mut(),
-                ),
-                SstSearchAlgorithm::BinarySearch => sst.binary_search_get(
-                    run_address,
-                    key,
-                    entry_counts[level][run],
+        Self::finish_interrupted_rewrite(&self.name, buffer_pool)?;
+        drop(pool);
+        if self.config_dirty {
+            self.write_config_file()?;
+            self.config_dirty = false;
+        }
+        if self.metadata_dirty {
+            self.write_metadata_file()?;
+            self.metadata_dirty = false;
+        }
+        Ok(())
+    }
+    ///Opens the database rooted at the directory <name>, creating it (and any missing parent directories) if it
+    /// doesn't exist
+    pub fn open(name: impl AsRef<Path>) -> Database {
+        Self::open_with(name, DatabaseOptions::default())
+    }
+    ///Like open, but with the files kept where <options> says, see DatabaseOptions
+    pub fn open_with(name: impl AsRef<Path>, options: DatabaseOptions) -> Database {
+        let name = name.as_ref();
+        Self::try_open_with(name, options).unwrap_or_else(|why| {
+            panic!("Unable to open database {}, reason: {why}", name.display())
+        })
+    }
+    ///Open a new database whose files are kept in memory rather than on disk, e.g. for tests or a cache that doesn't
+    /// have to outlive the process. Nothing is durable: the files are freed once the database (and anything sharing
+    /// its storage, see storage) is dropped, to reopen it pass a clone of its storage to open_with.
+    /// NOTE: direct I/O doesn't apply, see IoMode
+    pub fn open_in_memory(name: impl AsRef<Path>) -> Database {
+        Self::open_with(name, DatabaseOptions::in_memory())
+    }
+    ///Whether the database's files are kept in memory, see open_in_memory
+    pub fn is_in_memory(&self) -> bool {
+        self.name.storage().is_in_memory()
+    }
+    ///Where the database's files are kept, a clone shares them (e.g. to reopen a database kept in memory)
+    pub fn storage(&self) -> &Storage {
+        self.name.storage()
+    }
+    ///Key codec the runs of the database at <name> were written with, read from its config file without opening it
+    /// (e.g. for export::export_run)
+    pub fn stored_key_codec(name: impl AsRef<Path>) -> Result<KeyCodec, DbError> {
+        Self::read_key_codec(&DatabaseName::new(name.as_ref(), Storage::disk()))
+    }
+    pub(crate) fn read_key_codec(name: &DatabaseName) -> Result<KeyCodec, DbError> {
+        let config_file = name.storage().read_file(&filename::config(name))?;
+        Ok(Config::deserialize(&config_file)?.key_codec)
+    }
+    ///Recover what can still be read of the database at <name> into a new database at <out_dir> (which mustn't exist),
+    /// for a database that can't be opened or has damaged runs. Only the runs' SST files are read, not the metadata: the
+    /// youngest usable entry of each key is kept, pages that can't be read or don't hold sorted entries are skipped and
+    /// listed in the report. NOTE: TTLs and merge operands aren't recovered, such entries are salvaged as plain values
+    pub fn salvage(
+        name: impl AsRef<Path>,
+        out_dir: impl AsRef<Path>,
+    ) -> Result<SalvageReport, DbError> {
+        Self::salvage_with(name, out_dir, DatabaseOptions::default())
+    }
+    ///Like salvage, for a database (and its new copy) kept where <options> says
+    pub fn salvage_with(
+        name: impl AsRef<Path>,
+        out_dir: impl AsRef<Path>,
+        options: DatabaseOptions,
+    ) -> Result<SalvageReport, DbError> {
+        let name = DatabaseName::new(name.as_ref(), options.storage.clone());
+        let out_dir = out_dir.as_ref();
+        if options.storage.path_exists(out_dir) {
+            return Err(DbError::Io(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                format!("{} already exists", out_dir.display()),
+            )));
+        }
+        let mut out = Database::try_open_with(out_dir, options)?;
+        if let Ok(key_codec) = Self::read_key_codec(&name) {
+            out = out.set_key_codec(key_codec);
+        }
+        let report = salvage::salvage_into(&name, &mut out);
+        out.close();
+        report
+    }
+    ///Like open, but returns an error if an existing database's files can't be read
+    pub fn try_open(name: impl AsRef<Path>) -> Result<Database, DbError> {
+        Self::try_open_with(name, DatabaseOptions::default())
+    }
+    ///Like open_with, but returns an error if an existing database's files can't be read
+    pub fn try_open_with(
+        name: impl AsRef<Path>,
+        options: DatabaseOptions,
+    ) -> Result<Database, DbError> {
+        let name = name.as_ref();
+        if name.as_os_str() == Self::NO_OPEN_DB_NAME {
+            return Err(DbError::InvalidConfig {
+                setting: "database name",
+                reason: "a database can't have the empty string as its name".to_string(),
+            });
+        }
+        let name = DatabaseName::new(name, options.storage);
+        let storage = name.storage();
+        match storage.path_exists(&name) {
+            true => {
+                //directory exists, assume that this is a valid db
+                Self::remove_tmp_files(&name)?;
+                snapshot::remove_stale_snapshots(&name)?;
+                if storage.path_exists(&filename::truncate_marker(&name)) {
+                    //a crash cut a truncate short, finish it
+                    let metadata =
+                        Metadata::deserialize(&storage.read_file(&filename::metadata(&name))?)?;
+                    Self::finish_truncate(&name, &metadata.truncated())?;
+                }
+                //a crash cut the swap of a rewritten run short
+                Self::finish_interrupted_rewrite(&name, None)?;
+                //read config and metadata files
+                let config_file = storage.read_file(&filename::config(&name))?;
+                let metadata_file = storage.read_file(&filename::metadata(&name))?;
+                let config = Config::deserialize(&config_file)?;
+                let metadata = Metadata::deserialize(&metadata_file)?;
+                let mut db = Database::new(name, config, metadata);
+                db.apply_storage_settings()?;
+                //files from older versions (or with features that aren't compiled in) are rewritten on close
+                db.config_dirty = db.config.serialize() != config_file;
+                db.metadata_dirty = db.metadata.serialize() != metadata_file;
+                if db.enable_bloom_filter() {
+                    //a filter removed or cut short behind our back would hide keys its run holds
+                    db.verify_filters()?;
+                }
+                db.read_read_cache_file()?;
+                db.read_maintenance_file();
+                db.read_warm_file()?;
+                if db.config.warm_up == WarmUp::Eager {
+                    //the pages are only a hint, a run that can't be read fails the read that needs it instead
+                    let _ = db.warm_up();
+                }
+                Ok(db)
+            }
+            false => {
+                //directory doesn't exist
+                storage.create_dir_all(&name).map_err(|why| {
+                    io::Error::new(
+                        why.kind(),
+                        format!(
+                            "Unable to create directory for {}, reason {why}",
+                            name.display()
+                        ),
+                    )
+                })?;
+
+                //Step 1: make db
+                let mut db = Database::new(name, Config::new(), Metadata::new());
+
+                //Step 2: Create config file with default settings
+                db.write_config_file()?;
+                db.write_metadata_file()?;
+                db.apply_storage_settings()?;
+                Ok(db)
+            }
+        }
+    }
+    ///Write the database's files the way its config says from now on (see Storage), once it's opened
+    fn apply_storage_settings(&mut self) -> io::Result<()> {
+        Self::register_io_mode(&mut self.name, self.config.io_mode)?;
+        let storage = self.name.storage_mut();
+        storage.set_sync_policy(self.config.sync_policy);
+        storage.set_compression(self.config.compression);
+        Ok(())
+    }
+    ///Resolve <io_mode> for the files of the database at <name>, probing its config file
+    fn register_io_mode(name: &mut DatabaseName, io_mode: IoMode) -> io::Result<IoMode> {
+        let (directory, probe_path) = (name.to_path_buf(), filename::config(name));
+        name.storage_mut()
+            .register_io_mode(&directory, &probe_path, io_mode)
+    }
+    ///Remove the temporary files of writes that were never published (see Storage::publish), i.e. that a crash cut
+    /// short. Nothing refers to them, the files they were going to replace are still in place
+    fn remove_tmp_files(name: &DatabaseName) -> io::Result<()> {
+        let storage = name.storage();
+        let mut directories = vec![name.to_path_buf()];
+        directories.extend(Self::level_directories(name)?);
+        for directory in directories {
+            for path in storage.read_dir(&directory)? {
+                if filename::is_tmp_path(&path) {
+                    storage.remove_file(&path)?;
+                }
+            }
+        }
+        Ok(())
+    }
+    ///Directories of the levels of the database at <name>, whether the metadata refers to them or not
+    fn level_directories(name: &DatabaseName) -> io::Result<Vec<PathBuf>> {
+        let mut directories = name.storage().read_dir(name)?;
+        directories.retain(|path| {
+            path.file_name()
+                .and_then(|file_name| file_name.to_str())
+                .is_some_and(|file_name| file_name.parse::<Level>().is_ok())
+        });
+        Ok(directories)
+    }
+    ///Second half of truncate, also run by try_open if a crash cut a truncate short (its marker is still there).
+    /// <metadata> (empty) is written before any run's files are removed, so a crash never leaves metadata that refers
+    /// to a removed file. NOTE: every step can be done again, files that are already gone are skipped
+    fn finish_truncate(name: &DatabaseName, metadata: &Metadata) -> io::Result<()> {
+        let storage = name.storage();
+        storage.write_file(&filename::metadata(name), &metadata.serialize())?;
+        for directory in Self::level_directories(name)? {
+            for path in storage.read_dir(&directory)? {
+                storage.remove_file(&path)?;
+            }
+            storage.remove_dir_all(&directory)?;
+        }
+        let maintenance = filename::maintenance(name);
+        if storage.path_exists(&maintenance) {
+            storage.remove_file(&maintenance)?; //its progress is through runs that are gone
+        }
+        storage.remove_file(&filename::truncate_marker(name))
+    }
+    pub fn clear(&mut self) {
+        //queued compactions replace run files, the metadata on storage has to follow them before ours is reset. A
+        // failed one leaves the levels as the last one that succeeded left them, which is what's on storage too
+        let _ = self.stop_background_compactor();
+        let _ = self.write_db_state();
+        self.name = DatabaseName::new(Self::NO_OPEN_DB_NAME, self.name.storage().clone());
+        self.config = Config::new();
+        self.clear_memtable();
+        //no runs either, so reads of a closed database find nothing instead of looking for its files
+        self.metadata = Metadata::new();
+        self.metadata_dirty = false;
+        self.level_shape = LevelShape::from_entry_counts(&self.metadata.entry_counts);
+        self.read_cache = ReadCache::default();
+        self.maintenance = MaintenanceScheduler::default();
+        self.warm_pages.clear();
+        self.size_ratios = SizeRatioHistory::default();
+    }
+    ///Empty the memtable, leaving the entries to any snapshot that shares them
+    fn clear_memtable(&mut self) {
+        match Rc::get_mut(&mut self.memtable) {
+            Some(memtable) => memtable.clear(),
+            None => self.memtable = Rc::new(Memtable::new()),
+        }
+        self.durability_lag_reported = None;
+    }
+    ///Move all SST runs to the next larger (and older) level, along with any files and metadata tied to those SST runs,
+    /// then compact what that calls for (see cascade). A failed merge is recorded in compaction_error and leaves the
+    /// level's runs in place, they are only moved on once they're merged
+    fn move_runs(&mut self, level: Level) {
+        if let Err(why) = cascade::move_runs(self, level) {
+            self.compaction_error = Some(why);
+        }
+    }
+    ///Runs of <level> as compaction strategies see them
+    fn level_runs(&self, level: Level) -> LevelRuns<'_> {
+        LevelRuns {
+            level,
+            is_last_level: level == self.level_shape.num_levels() - 1,
+            entry_counts: &self.metadata.entry_counts[level],
+            tombstone_counts: &self.metadata.tombstone_counts[level],
+        }
+    }
+    ///Merge all runs in a level into a single run. The merge works off a snapshot of the level's run list and
+    /// builds the new run next to the old ones, metadata only changes once it's installed.
+    /// Returns an error if the merge fails, the level is left untouched in that case
+    fn compact_level(&mut self, level: Level, discard_tombstones: bool) -> io::Result<()> {
+        let entry_counts = self.metadata.entry_counts[level].clone();
+        if entry_counts.len() < 2 {
+            return Ok(()); //nothing to compact
+        }
+        let start = self.event_listener.is_some().then(Instant::now);
+        self.report_event(DbEvent::CompactionStarted {
+            level,
+            runs: entry_counts.len(),
+            input_entries: entry_counts.iter().sum(),
+        });
+        let mut yield_hook = mem::take(&mut self.yield_hook);
+        let merged = merge::build_merged_run(
+            &self.compaction_settings(),
+            level,
+            &entry_counts,
+            discard_tombstones,
+            &mut yield_hook,
+        );
+        self.yield_hook = yield_hook;
+        let (stats, key_sketch) = merged?;
+        self.report_compaction_progress(CompactionProgress::Merged { level, stats });
+
+        self.install_merged_run(level, entry_counts.len(), stats, key_sketch)?;
+        self.report_compaction_progress(CompactionProgress::Installed { level });
+        if let Some(start) = start {
+            self.report_event(DbEvent::CompactionFinished {
+                level,
+                output_entries: stats.entries_written,
+                input_pages_read: stats.pages_read,
+                output_pages_written: stats.pages_written,
+                duration: start.elapsed(),
+            });
+        }
+        Ok(())
+    }
+    ///Replace the input runs of a compaction with the merged run built in the level's staging run
+    fn install_merged_run(
+        &mut self,
+        level: Level,
+        num_input_runs: Size,
+        stats: MergeStats,
+        key_sketch: KeySketch,
+    ) -> io::Result<()> {
+        let mut pool = self.buffer_pool.borrow_mut();
+        let enable_buffer_pool = self.config.enable_buffer_pool;
+        cascade::install_merged_run_files(
+            &self.name,
+            level,
+            num_input_runs,
+            stats.entries_written,
+            &mut |change| {
+                if enable_buffer_pool {
+                    Self::apply_file_change(&mut pool, change);
+                }
+            },
+        )?;
+        drop(pool);
+
+        self.metadata.compaction_entries_dropped += stats.entries_dropped;
+        self.metadata
+            .run_lists()
+            .install_merged_run(level, &stats, key_sketch);
+        self.metadata.set_level_partitions(level, vec![]);
+        self.metadata_dirty = true;
+        self.size_ratios
+            .record(&size_ratio::level_bytes(&self.metadata.entry_counts));
+        self.level_shape.merge_runs(level, stats.entries_written);
+        self.check_level_shape();
+        Ok(())
+    }
+    ///Keep the cached pages of a renamed file under its new path, drop those of a removed one
+    fn apply_file_change(buffer_pool: &mut BufferPool, change: FileChange) {
+        match change {
+            FileChange::Renamed { from, to } => buffer_pool.rename(from, to),
+            FileChange::Removed(path) => buffer_pool.remove(path),
+        }
+    }
+    ///Whether leveled levels are compacted into partitions, see set_partitioned_levels
+    fn is_partitioned(&self) -> bool {
+        self.config.partitioned_levels && self.config.compaction_policy == CompactionPolicy::Leveled
+    }
+    ///Merge the incoming runs of a level (the runs after its partitions) with the partitions whose key ranges they
+    /// overlap into new partitions of at most a memtable each (rounded up to whole pages), see set_partitioned_levels.
+    /// The other partitions aren't read or rewritten, a lone incoming run that overlaps none and is no bigger than a
+    /// partition becomes one as it is.
+    /// Returns an error if the merge fails, the level is left untouched in that case
+    fn compact_partitions(&mut self, level: Level, discard_tombstones: bool) -> io::Result<()> {
+        let entry_counts = self.metadata.entry_counts[level].clone();
+        let partitions = self.metadata.level_partitions(level).to_vec();
+        let num_partitions = partitions.len();
+        let incoming = num_partitions..entry_counts.len();
+        if incoming.is_empty() {
+            return Ok(()); //nothing to merge
+        }
+        let key_codec = self.key_codec();
+        let mut incoming_ranges = Vec::with_capacity(incoming.len());
+        for run in incoming.clone() {
+            let run_address = &(&self.name, level, run);
+            incoming_ranges.extend(sst_util::key_range(
+                run_address,
+                entry_counts[run],
+                key_codec,
+                None,
+            )?);
+        }
+        let replaced = partition::union(incoming_ranges.iter().copied())
+            .map_or(num_partitions..num_partitions, |key_range| {
+                partition::overlapping(&partitions, key_range)
+            });
+        let pages_per_partition = sst_util::num_pages(self.config.memtable_capacity).max(1);
+
+        let staging_run = merge::staging_run(&entry_counts);
+        if let ([key_range], true) = (incoming_ranges.as_slice(), replaced.is_empty()) {
+            let run = incoming.start;
+            if sst_util::num_pages(entry_counts[run]) <= pages_per_partition {
+                let Metadata {
+                    tombstone_counts,
+                    expiry_counts,
+                    page_sizes,
+                    operand_counts,
+                    key_sketches,
+                    ..
+                } = &self.metadata;
+                let partition_run = PartitionRun {
+                    key_range: *key_range,
+                    entries: entry_counts[run],
+                    tombstones: tombstone_counts[level][run],
+                    expiries: expiry_counts[level][run],
+                    operands: operand_counts[level][run],
+                    page_size: page_sizes[level][run],
+                    key_sketch: key_sketches[level][run].clone(),
+                };
+                //out of the way of the partitions renumbered after it
+                let mut pool = self.buffer_pool.borrow_mut();
+                let buffer_pool = if self.config.enable_buffer_pool {
+                    Some(&mut *pool)
+                } else {
+                    None
+                };
+                let staged = Self::rename_run(
+                    &(&self.name, level, run),
+                    &(&self.name, level, staging_run),
+                    buffer_pool,
+                );
+                drop(pool);
+                staged?;
+                return self.install_partition_runs(
+                    level,
+                    replaced,
+                    staging_run,
+                    vec![partition_run],
+                    0,
+                );
+            }
+        }
+
+        let inputs: Vec<(Run, Size)> = replaced
+            .clone()
+            .chain(incoming)
+            .map(|run| (run, entry_counts[run]))
+            .collect();
+        let input_entries = inputs.iter().map(|(_, entries)| entries).sum();
+        let max_written = sst_util::num_pages(input_entries)
+            .div_ceil(pages_per_partition)
+            .max(1);
+        let max_partitions = num_partitions - replaced.len() + max_written;
+        if max_partitions > Self::MAX_RUNS_PER_LEVEL {
+            return self.compact_level(level, discard_tombstones);
+        }
+        //built past every run number the level uses, before or after they're installed
+        let first_written_run = staging_run.max(max_partitions);
+        let start = self.event_listener.is_some().then(Instant::now);
+        self.report_event(DbEvent::CompactionStarted {
+            level,
+            runs: inputs.len(),
+            input_entries,
+        });
+        let mut yield_hook = mem::take(&mut self.yield_hook);
+        let merged = merge::build_partition_runs(
+            &self.compaction_settings(),
+            level,
+            &inputs,
+            first_written_run,
+            pages_per_partition,
+            discard_tombstones,
+            &mut yield_hook,
+        );
+        self.yield_hook = yield_hook;
+        let (stats, partition_runs) = merged?;
+        self.report_compaction_progress(CompactionProgress::Merged { level, stats });
+
+        self.install_partition_runs(
+            level,
+            replaced,
+            first_written_run,
+            partition_runs,
+            stats.entries_dropped,
+        )?;
+        self.report_compaction_progress(CompactionProgress::Installed { level });
+        if let Some(start) = start {
+            self.report_event(DbEvent::CompactionFinished {
+                level,
+                output_entries: stats.entries_written,
+                input_pages_read: stats.pages_read,
+                output_pages_written: stats.pages_written,
+                duration: start.elapsed(),
+            });
+        }
+        Ok(())
+    }
+    ///Replace the partitions <replaced> of <level> and its incoming runs with <partition_runs>, built under run
+    /// numbers <first_staged_run> onwards. The partitions after the replaced ones are renumbered to follow the new ones
+    fn install_partition_runs(
+        &mut self,
+        level: Level,
+        replaced: Range<Run>,
+        first_staged_run: Run,
+        partition_runs: Vec<PartitionRun>,
+        entries_dropped: Size,
+    ) -> io::Result<()> {
+        let db_name = &self.name;
+        let num_partitions = self.metadata.level_partitions(level).len();
+        let num_runs = self.metadata.entry_counts[level].len();
+        let num_written = partition_runs.len();
+        let mut pool = self.buffer_pool.borrow_mut();
+        let mut buffer_pool = if self.config.enable_buffer_pool {
+            Some(&mut *pool)
+        } else {
+            None
+        };
+        for run in replaced.clone().chain(num_partitions..num_runs) {
+            for path in run_paths(&(db_name, level, run)) {
+                if self.name.storage().path_exists(&path) {
+                    file_interface::remove_file(
+                        self.name.storage(),
+                        &path,
+                        buffer_pool.as_deref_mut(),
+                    )?;
+                }
+            }
+        }
+        //moving up starts from the last partition, so none is renamed onto one that hasn't moved yet
+        let first_later_run = replaced.start + num_written;
+        let later_moves =
+            (replaced.end..num_partitions).map(|run| (run, run - replaced.end + first_later_run));
+        let later_moves: Vec<(Run, Run)> = match first_later_run > replaced.end {
+            true => later_moves.rev().collect(),
+            false => later_moves.collect(),
+        };
+        for (run, new_run) in later_moves {
+            Self::rename_run(
+                &(db_name, level, run),
+                &(db_name, level, new_run),
+                buffer_pool.as_deref_mut(),
+            )?;
+        }
+        for output in 0..num_written {
+            Self::rename_run(
+                &(db_name, level, first_staged_run + output),
+                &(db_name, level, replaced.start + output),
+                buffer_pool.as_deref_mut(),
+            )?;
+        }
+        drop(pool);
+
+        ///Replace the runs <replaced> of a level's per run vector with <written>, dropping its incoming runs
+        fn splice<T>(
+            runs: &mut Vec<T>,
+            replaced: &Range<Run>,
+            num_partitions: Size,
+            written: impl IntoIterator<Item = T>,
+        ) {
+            runs.truncate(num_partitions);
+            runs.splice(replaced.clone(), written);
+        }
+        let Metadata {
+            entry_counts,
+            tombstone_counts,
+            compaction_entries_dropped,
+            expiry_counts,
+            page_sizes,
+            max_sequences,
+            operand_counts,
+            key_sketches,
+            ..
+        } = &mut self.metadata;
+        //each new partition may hold the newest write of any input
+        let max_sequence = (max_sequences[level][replaced.clone()].iter())
+            .chain(&max_sequences[level][num_partitions..])
+            .max()
+            .copied()
+            .unwrap_or(0);
+        let written = &partition_runs;
+        splice(
+            &mut entry_counts[level],
+            &replaced,
+            num_partitions,
+            written.iter().map(|run| run.entries),
+        );
+        splice(
+            &mut tombstone_counts[level],
+            &replaced,
+            num_partitions,
+            written.iter().map(|run| run.tombstones),
+        );
+        splice(
+            &mut expiry_counts[level],
+            &replaced,
+            num_partitions,
+            written.iter().map(|run| run.expiries),
+        );
+        splice(
+            &mut page_sizes[level],
+            &replaced,
+            num_partitions,
+            written.iter().map(|run| run.page_size),
+        );
+        splice(
+            &mut max_sequences[level],
+            &replaced,
+            num_partitions,
+            written.iter().map(|_| max_sequence),
+        );
+        splice(
+            &mut operand_counts[level],
+            &replaced,
+            num_partitions,
+            written.iter().map(|run| run.operands),
+        );
+        splice(
+            &mut key_sketches[level],
+            &replaced,
+            num_partitions,
+            written.iter().map(|run| run.key_sketch.clone()),
+        );
+        *compaction_entries_dropped += entries_dropped;
+        let num_entries = entry_counts[level].iter().sum();
+        let num_runs = entry_counts[level].len();
+        let mut partitions = self.metadata.level_partitions(level).to_vec();
+        splice(
+            &mut partitions,
+            &replaced,
+            num_partitions,
+            written.iter().map(|run| run.key_range),
+        );
+        self.metadata.set_level_partitions(level, partitions);
+        self.metadata_dirty = true;
+        self.size_ratios
+            .record(&size_ratio::level_bytes(&self.metadata.entry_counts));
+        self.level_shape.replace_runs(level, num_runs, num_entries);
+        self.check_level_shape();
+        Ok(())
+    }
+    ///Rename every file of a run to another run's name, the other run must have no files
+    fn rename_run(
+        run_address: &RunAddress,
+        new_run_address: &RunAddress,
+        mut buffer_pool: Option<&mut BufferPool>,
+    ) -> io::Result<()> {
+        for (path, new_path) in run_paths(run_address)
+            .into_iter()
+            .zip(run_paths(new_run_address))
+        {
+            if run_address.0.storage().path_exists(&path) {
+                file_interface::rename_file(
+                    run_address.0.storage(),
+                    &path,
+                    &new_path,
                     buffer_pool.as_deref_mut(),
+                )?;
+            }
+        }
+        Ok(())
+    }
+    fn report_event(&self, event: DbEvent) {
+        if let Some(listener) = &self.event_listener {
+            (listener.0)(event);
+        }
+    }
+    fn report_compaction_progress(&mut self, progress: CompactionProgress) {
+        if let Some(mut callback) = self.compaction_progress_callback.take() {
+            (callback.0)(self, progress);
+            self.compaction_progress_callback = Some(callback);
+        }
+    }
+    fn sst_interface(&self) -> Box<dyn SortedStringTable> {
+        Self::run_sst_interface(
+            self.config.sst_implementation,
+            self.key_codec(),
+            system_info::page_size(),
+        )
+    }
+    ///Whether a run written with <page_size> pages has them in the layout the host reads, fence indexes are only
+    /// written for those
+    fn has_host_pages(page_size: Size) -> bool {
+        page_size == system_info::page_size()
+    }
+    ///SST interface for reading a run written with <page_size>, which only matters for B-tree runs
+    #[cfg_attr(not(feature = "btree"), allow(unused_variables))]
+    fn run_sst_interface(
+        sst_implementation: SstImplementation,
+        key_codec: KeyCodec,
+        page_size: Size,
+    ) -> Box<dyn SortedStringTable> {
+        match sst_implementation {
+            SstImplementation::Array => Box::new(array_sst::Sst::new(key_codec)),
+            #[cfg(feature = "btree")]
+            SstImplementation::Btree => Box::new(
+                crate::sst::btree_sst::Sst::new(key_codec)
+                    .set_layout(system_info::PageLayout::new(page_size)),
+            ),
+            #[cfg(not(feature = "btree"))]
+            SstImplementation::Btree => Box::new(array_sst::Sst::new(key_codec)),
+        }
+    }
+    ///Write the audit of the flush of <memtable> to <run> of <level> at <now>, see set_flush_audit_dir
+    fn write_flush_audit(
+        &self,
+        memtable: &Memtable,
+        directory: &Path,
+        level: Level,
+        run: Run,
+        max_sequence: Sequence,
+        now: Timestamp,
+    ) -> io::Result<()> {
+        //the entries as the flush wrote them, expired ones are tombstones
+        let entries: Vec<Entry> = memtable
+            .iter()
+            .map(
+                |(key, value)| match expiry::is_expired(memtable.expiry(key), now) {
+                    true => (key, Self::TOMBSTONE_VALUE),
+                    false => (key, value),
+                },
+            )
+            .collect();
+        let bytes = serde_entry::serialize(&entries, self.config.key_codec);
+        flush_audit::write(directory, level, run, max_sequence, now, &bytes).map_err(|why| {
+            io::Error::new(
+                why.kind(),
+                format!(
+                    "Unable to write the audit of a flush to {}, reason {why}",
+                    directory.display()
                 ),
+            )
+        })?;
+        Ok(())
+    }
+    ///Writes memtable contents to disk, clears memtable, and handles compaction if needed. A memtable holding only
+    /// tombstones is dropped instead if there are no runs for them to hide values in.
+    /// Fails with DbError::TooManyRuns, leaving the memtable as it is, if level 0 is full (see make_room_in_level_zero)
+    fn flush_memtable(&mut self) -> Result<(), DbError> {
+        //a view frozen by freeze_memtable is older than anything written since, it's flushed first
+        self.flush_frozen()?;
+        if self.memtable.len() < 1 {
+            //shouldn't flush if there's nothing to flush
+            return Ok(());
+        }
+        Memtable::freeze_view(&mut self.memtable);
+        let flushed = self.flush_frozen();
+        if flushed.is_err() {
+            //nothing was written since the freeze, the memtable goes back to how it was
+            Memtable::thaw(&mut self.memtable);
+        }
+        flushed
+    }
+    ///Second step of a flush started by freeze_memtable, writes the frozen entries out as a level 0 run (compacting
+    /// like any other flush). Does nothing if nothing is frozen, fails like flush does, keeping the frozen entries
+    pub fn flush_frozen(&mut self) -> Result<(), DbError> {
+        self.ensure_open()?;
+        let Some(frozen) = self.memtable.frozen_to_flush() else {
+            return Ok(());
+        };
+        let level = Self::LEVEL_ZERO;
+
+        //with background compaction, level 0 runs are moved up by the compactor after we're done writing
+        let background_levels = self.background_levels();
+        let mut background_state = background_levels
+            .as_deref()
+            .map(|levels| self.sync_levels(levels));
+        //the compactor runs no more jobs once one failed, level 0 would only grow
+        if let Some(why) = background_state.as_deref().and_then(Self::background_error) {
+            return Err(DbError::Io(why));
+        }
+
+        //with no runs under it a memtable of nothing but tombstones hides nothing, so it isn't worth a run
+        // (and there's nothing left to flush if every frozen entry was written again since)
+        if frozen.len() == 0 || frozen.len_live() == 0 && self.sst_total() == 0 {
+            Rc::make_mut(&mut self.memtable).release_frozen();
+            self.durability_lag_reported = None;
+            return Ok(());
+        }
+
+        //if we want compaction, we need to move the current SST at level 0 up a level
+        match self.config.compaction_policy {
+            CompactionPolicy::None => {
+                self.make_room_in_level_zero(background_state.as_deref_mut())?
+            }
+            _ if background_state.is_some() => {}
+            _ => self.move_runs(level),
+        };
+
+        let start = self.event_listener.is_some().then(Instant::now);
+        let next_run_num = self.sst_count(level); //this will be zero after moving runs
+        let run_address = &(&self.name, level, next_run_num);
+
+        //Write memtable to storage, entries that already expired are written as tombstones so they still hide older values
+        let now = self.now();
+        let memtable = &*frozen;
+        let expiries: Vec<(Key, Timestamp)> = memtable
+            .expiries()
+            .into_iter()
+            .filter(|(_, expiry)| !expiry::is_expired(Some(*expiry), now))
+            .collect();
+        let operands = memtable.operands();
+        let num_entries = memtable.len();
+        let num_expiries = expiries.len();
+        let num_operands = operands.len();
+        let max_sequence = memtable.max_sequence();
+
+        //entries are streamed out of the memtable a page at a time
+        let mut num_tombstones = 0;
+        let mut key_sketch = KeySketch::default();
+        let mut entries = memtable.iter().map(|(key, value)| {
+            let value = match expiry::is_expired(memtable.expiry(key), now) {
+                true => Self::TOMBSTONE_VALUE,
+                false => value,
             };
-            match get() {
-                Err(why) => panic!("Something went wrong trying to get key {key} at level {level}, sst {run}, reason: {why}"),
-                Ok(get_attempt_result) => {
-                    if get_attempt_result.is_none() {
-                        return false;
-                    }
-                    sst_search_result = get_attempt_result; //found value
-                    true//exit from "for each" loop
+            if value == Self::TOMBSTONE_VALUE {
+                num_tombstones += 1;
+            }
+            key_sketch.insert(key);
+            (key, value)
+        });
+        let mut yield_hook = mem::take(&mut self.yield_hook);
+        let written = self.write_run_files(
+            run_address,
+            &mut entries,
+            num_entries,
+            &expiries,
+            &operands,
+            &mut yield_hook,
+        );
+        drop(entries); //done counting tombstones and sketching keys
+        self.yield_hook = yield_hook;
+        written?; //nothing was published if it failed, the memtable still holds the entries
+                  //the run isn't in the metadata yet, if its audit can't be written the next flush writes over it
+        if let Some(directory) = &self.flush_audit_dir {
+            self.write_flush_audit(memtable, directory, level, next_run_num, max_sequence, now)?;
+        }
+
+        self.metadata.entry_counts[level].push(num_entries);
+        self.metadata.tombstone_counts[level].push(num_tombstones);
+        self.metadata.expiry_counts[level].push(num_expiries);
+        self.metadata.page_sizes[level].push(system_info::page_size());
+        self.metadata.max_sequences[level].push(max_sequence);
+        self.metadata.operand_counts[level].push(num_operands);
+        self.metadata.key_sketches[level].push(key_sketch.clone());
+        self.metadata_dirty = true;
+        self.level_shape.push_run(level, num_entries);
+        self.check_level_shape();
+
+        Rc::make_mut(&mut self.memtable).release_frozen();
+        self.durability_lag_reported = None;
+        //the run's files are all published, only now can the metadata on storage refer to them. The run is in place
+        // either way, if the metadata can't be written it's still dirty and the next flush or close writes it
+        let state_written = self.write_db_state();
+        if let Some(start) = start {
+            self.report_event(DbEvent::MemtableFlush {
+                entries: num_entries,
+                duration: start.elapsed(),
+            });
+        }
+
+        if let Some(state) = background_state.as_mut() {
+            state.entry_counts[level].push(num_entries);
+            state.tombstone_counts[level].push(num_tombstones);
+            state.expiry_counts[level].push(num_expiries);
+            state.page_sizes[level].push(system_info::page_size());
+            state.max_sequences[level].push(max_sequence);
+            state.operand_counts[level].push(num_operands);
+            state.key_sketches[level].push(key_sketch);
+        }
+        self.compact_garbage_levels(background_state.as_deref_mut());
+        drop(background_state);
+        if let Some(compactor) = &self.background_compactor {
+            if self.config.compaction_policy != CompactionPolicy::None {
+                compactor.enqueue(self.compaction_settings());
+            }
+        }
+        state_written
+    }
+    ///Write a new run at <run_address> in <level> holding the <num_entries> <entries> (sorted by key), along with its
+    /// sidecars and the fence index and bloom filter the config asks for, which are built as the pages are written
+    fn write_run_files(
+        &self,
+        run_address: &RunAddress,
+        entries: &mut dyn Iterator<Item = Entry>,
+        num_entries: Size,
+        expiries: &[(Key, Timestamp)],
+        operands: &[Key],
+        yield_hook: &mut YieldHook,
+    ) -> io::Result<()> {
+        let (_, level, _) = *run_address;
+        let mut first_keys = self.config.enable_fence_index.then(Vec::new);
+        let mut filter = (self.config.bloom_levels.includes(level))
+            .then(|| BloomFilterBuilder::new(self.bloom_filter_bits_per_entry(), self.key_codec()));
+        let entries_written = self.sst_interface().write_from_iter(
+            run_address,
+            entries,
+            &mut |page: &[Entry]| {
+                if let Some(first_keys) = first_keys.as_mut() {
+                    first_keys.push(page[0].0);
+                }
+                if let Some(filter) = filter.as_mut() {
+                    filter.insert_entries(page);
+                }
+                yield_hook.call()
+            },
+        )?;
+        assert_eq!(
+            entries_written, num_entries,
+            "Miscounted the entries of the run"
+        );
+        if !expiries.is_empty() {
+            expiry::write(run_address, expiries, self.key_codec())?;
+        }
+        if !operands.is_empty() {
+            operands::write(run_address, operands, self.key_codec())?;
+        }
+        if let Some(first_keys) = first_keys {
+            fence::write(run_address, &first_keys, self.key_codec())?;
+        }
+        if let Some(mut filter) = filter {
+            filter.seal(run_address, entries_written)?;
+        }
+        Ok(())
+    }
+    ///Level 0 only grows under CompactionPolicy::None. Once it holds max_level_zero_runs runs (see
+    /// level_zero_run_limit), either merge them into one (Auto, a failed merge is recorded in compaction_error and the
+    /// flush goes ahead unless level 0 is at Database::MAX_RUNS_PER_LEVEL) or refuse the flush
+    fn make_room_in_level_zero(
+        &mut self,
+        background_state: Option<&mut LevelState>,
+    ) -> Result<(), DbError> {
+        let max_runs = self.level_zero_run_limit();
+        if self.sst_count(Self::LEVEL_ZERO) < max_runs {
+            return Ok(());
+        }
+        match self.config.level_zero_overflow {
+            LevelZeroOverflowBehavior::Backpressure => Err(DbError::TooManyRuns { max_runs }),
+            LevelZeroOverflowBehavior::Auto => {
+                match self.merge_level_zero(background_state) {
+                    Ok(()) => self.compaction_error = None,
+                    Err(why) => self.compaction_error = Some(why),
+                }
+                //no level may go past the hard limit, even if the merge failed
+                match self.sst_count(Self::LEVEL_ZERO) < Self::MAX_RUNS_PER_LEVEL {
+                    true => Ok(()),
+                    false => Err(DbError::TooManyRuns {
+                        max_runs: Self::MAX_RUNS_PER_LEVEL,
+                    }),
                 }
             }
+        }
+    }
+    ///Compact every level holding more than max_garbage_ratio garbage, whatever the compaction policy. With background
+    /// compaction only level 0 is checked, the compactor owns the levels under it. A failed merge is recorded in
+    /// compaction_error and leaves the level as it was
+    fn compact_garbage_levels(&mut self, mut background_state: Option<&mut LevelState>) {
+        let Some(max_garbage_ratio) = self.config.max_garbage_ratio else {
+            return;
         };
-        Self::for_each_sst(&self.metadata.entry_counts, &mut callback);
-        if sst_search_result.is_some_and(|value| value == Self::TOMBSTONE_VALUE) {
-            return None;
+        let num_levels = match background_state {
+            Some(_) => 1,
+            None => self.level_shape.num_levels(),
+        };
+        for level in 0..num_levels {
+            let ratio = Self::level_garbage_ratio(&self.metadata, level);
+            if self.sst_count(level) < 2 || ratio <= max_garbage_ratio {
+                continue; //a single run is only rewritten by compactions, never merged on its own
+            }
+            let merged = match level {
+                Self::LEVEL_ZERO => self.merge_level_zero(background_state.as_deref_mut()),
+                _ => self.compact_level(level, level == self.level_shape.num_levels() - 1),
+            };
+            self.compaction_error = merged.err();
         }
-        sst_search_result
     }
-    pub fn scan(&mut self, key1: Key, key2: Key) -> Vec<Entry> {
-        //NOTE: might be able to improve this by doing a "for each in range" on each SST instead, might not be worth it though
-        let results = self.memtable.scan(key1, key2);
-        let mut unique_key_set: HashSet<Key> =
-            results.iter().map(|(key, _)| key.to_owned()).collect();
-        //NOTE: the reason we can use negative keys in our max_heap is because negative Key::MIN is not allowed to be inserted, otherwise that would cause an overflow
-        let mut max_heap: BinaryHeap<Entry> = results
+    ///Merge every level 0 run into one, e.g. after a write failed with DbError::TooManyRuns. Tombstones are only
+    /// dropped if there are no levels under it. Returns an error if the merge fails, level 0 keeps its runs then
+    pub fn compact_level_zero(&mut self) -> io::Result<()> {
+        let background_levels = self.background_levels();
+        let mut background_state = background_levels
+            .as_deref()
+            .map(|levels| self.sync_levels(levels));
+        self.merge_level_zero(background_state.as_deref_mut())
+    }
+    fn merge_level_zero(&mut self, background_state: Option<&mut LevelState>) -> io::Result<()> {
+        let level = Self::LEVEL_ZERO;
+        let is_last_level = self.level_shape.num_levels() == 1;
+        self.compact_level(level, is_last_level)?;
+        //the background compactor keeps its own copy of level 0, which would undo the merge the next time we sync
+        if let Some(state) = background_state {
+            Self::mirror_level(state, &self.metadata, level);
+        }
+        Ok(())
+    }
+    ///Copy what our metadata says about <level> into the background compactor's level state
+    fn mirror_level(state: &mut LevelState, metadata: &Metadata, level: Level) {
+        state.entry_counts[level].clone_from(&metadata.entry_counts[level]);
+        state.tombstone_counts[level].clone_from(&metadata.tombstone_counts[level]);
+        state.expiry_counts[level].clone_from(&metadata.expiry_counts[level]);
+        state.page_sizes[level].clone_from(&metadata.page_sizes[level]);
+        state.max_sequences[level].clone_from(&metadata.max_sequences[level]);
+        state.operand_counts[level].clone_from(&metadata.operand_counts[level]);
+        state.key_sketches[level].clone_from(&metadata.key_sketches[level]);
+    }
+    ///Rewrite run <run> of <level> without the entries younger runs hide, reclaiming their space without compacting
+    /// the whole level. The replacement is written tightly packed with host sized pages and its B-tree, bloom filter,
+    /// fence index and sidecars rebuilt, then swapped in over the run's files, no other run is touched. Returns the
+    /// number of entries dropped. If writing the replacement fails the run is left as it was. Once it's written the
+    /// metadata is saved with it in place before any of the run's files are replaced, and a crash (or error) part way
+    /// through the swap is finished by the next open, so the run is never seen half replaced.
+    /// NOTE: a run whose entries are all hidden keeps one of them, only compactions remove runs
+    pub fn rewrite_run(&mut self, level: Level, run: Run) -> Result<Size, DbError> {
+        self.ensure_open()?;
+        Ok(self.rewrite_run_unchecked(level, run)?)
+    }
+    fn rewrite_run_unchecked(&mut self, level: Level, run: Run) -> io::Result<Size> {
+        if let Some(compactor) = &self.background_compactor {
+            compactor.drain(); //a queued compaction could replace the run while we read it
+        }
+        let background_levels = self.background_levels();
+        let mut background_state = background_levels
+            .as_deref()
+            .map(|levels| self.sync_levels(levels));
+
+        let Some(&num_entries) = self
+            .metadata
+            .entry_counts
+            .get(level)
+            .and_then(|level_entry_counts| level_entry_counts.get(run))
+        else {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("level {level} has no run {run}"),
+            ));
+        };
+        let key_codec = self.key_codec();
+        let db_name = self.name.clone();
+        let run_address = &(&db_name, level, run);
+
+        let mut entries = Vec::with_capacity(num_entries);
+        {
+            let mut pool = self.buffer_pool.borrow_mut();
+            let mut buffer_pool = if self.config.enable_buffer_pool {
+                Some(&mut *pool)
+            } else {
+                None
+            };
+            let mut cursor = RunCursor::new(*run_address, key_codec, 0..num_entries, false);
+            while let Some(entry) = cursor.next(buffer_pool.as_deref_mut())? {
+                entries.push(entry);
+            }
+        }
+        //a run always holds an entry, metadata that says otherwise is damaged
+        if entries.is_empty() || entries.len() != num_entries {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "Level {level}, sst {run} holds {} entries instead of {num_entries}",
+                    entries.len()
+                ),
+            ));
+        }
+        let expiries = match self.metadata.expiry_counts[level][run] {
+            0 => Vec::new(),
+            _ => expiry::read(run_address, key_codec)?,
+        };
+        let operands = match self.metadata.operand_counts[level][run] {
+            0 => Vec::new(),
+            _ => operands::read(run_address, key_codec)?,
+        };
+
+        let key_range = (entries[0].0, entries[num_entries - 1].0);
+        let hidden_keys = self.shadowing_keys(level, run, key_range)?;
+        let mut kept: Vec<Entry> = entries
             .iter()
-            .map(|(key, value)| (-key, value.to_owned()))
+            .filter(|(key, _)| !hidden_keys.contains(key))
+            .copied()
             .collect();
+        if kept.is_empty() {
+            kept.push(entries[0]); //still hidden, see NOTE
+        }
+        let is_kept = |key: &Key| {
+            kept.binary_search_by_key(key, |(kept_key, _)| *kept_key)
+                .is_ok()
+        };
+        let expiries: Vec<(Key, Timestamp)> = expiries
+            .into_iter()
+            .filter(|(key, _)| is_kept(key))
+            .collect();
+        let operands: Vec<Key> = operands.into_iter().filter(is_kept).collect();
 
-        let sst = self.sst_interface();
-        let search_algorithm = self.sst_search_algorithm();
+        //build the replacement under the level's staging run number, like a compaction would
+        let staging_run = self.metadata.entry_counts[level].len();
+        let staging_address = &(&db_name, level, staging_run);
+        let remove_staging_run = || {
+            for path in run_paths(staging_address) {
+                if db_name.storage().path_exists(&path) {
+                    let _ = db_name.storage().remove_file(&path); //best effort, the write error is what matters
+                }
+            }
+        };
+        let mut yield_hook = mem::take(&mut self.yield_hook);
+        let written = self.write_run_files(
+            staging_address,
+            &mut kept.iter().copied(),
+            kept.len(),
+            &expiries,
+            &operands,
+            &mut yield_hook,
+        );
+        self.yield_hook = yield_hook;
+        if let Err(why) = written {
+            remove_staging_run();
+            return Err(why);
+        }
 
-        //for every sst (youngest to oldest)
-        //scan and add values that have not been seen to the hashmap
-        let mut buffer_pool = if self.config.enable_buffer_pool {
-            Some(&mut self.buffer_pool)
+        let entries_dropped = num_entries - kept.len();
+        let mut metadata = self.metadata.clone();
+        metadata.entry_counts[level][run] = kept.len();
+        metadata.tombstone_counts[level][run] = kept
+            .iter()
+            .filter(|(_, value)| *value == Self::TOMBSTONE_VALUE)
+            .count();
+        metadata.expiry_counts[level][run] = expiries.len();
+        metadata.page_sizes[level][run] = system_info::page_size();
+        metadata.operand_counts[level][run] = operands.len();
+        metadata.key_sketches[level][run] = KeySketch::from_keys(kept.iter().map(|(key, _)| *key));
+        let rewrite = RunRewrite {
+            level,
+            run,
+            staging_run,
+            replaced_files: run_paths(staging_address)
+                .iter()
+                .map(|path| db_name.storage().path_exists(path))
+                .collect(),
+            metadata,
+        };
+        //the rewrite is done once its marker is written, up to then a crash leaves the run as it was
+        let marked = db_name.storage().write_file(
+            &filename::rewrite_marker(&db_name),
+            &serde_state::serialize(&rewrite),
+        );
+        if let Err(why) = marked {
+            remove_staging_run();
+            return Err(self.file_error("rewrite marker", why));
+        }
+
+        self.metadata = rewrite.metadata.clone();
+        self.level_shape.shrink_run(level, entries_dropped);
+        self.check_level_shape();
+        if let Some(state) = background_state.as_deref_mut() {
+            Self::mirror_level(state, &self.metadata, level);
+        }
+        let mut pool = self.buffer_pool.borrow_mut();
+        let buffer_pool = if self.config.enable_buffer_pool {
+            Some(&mut *pool)
         } else {
             None
         };
-        let entry_counts = &self.metadata.entry_counts;
-        let mut callback = |level, run| {
-            let run_address = &(self.name.as_str(), level, run);
-            let key_range = (key1, key2);
-            let mut scan = || match search_algorithm {
-                SstSearchAlgorithm::Default => sst.scan(
-                    run_address,
-                    key_range,
-                    entry_counts[level][run],
-                    buffer_pool.as_deref_mut(),
-                ),
-                SstSearchAlgorithm::BinarySearch => sst.binary_search_scan(
-                    run_address,
-                    key_range,
-                    entry_counts[level][run],
+        //if the swap fails part way it's finished before the metadata is next written, see write_db_state
+        let finished = Self::finish_rewrite(&db_name, &rewrite, buffer_pool);
+        self.metadata_dirty = finished.is_err();
+        finished?;
+        Ok(entries_dropped)
+    }
+    ///Finish the rewrite of a run whose swap was cut short, if its marker is still there
+    fn finish_interrupted_rewrite(
+        name: &DatabaseName,
+        buffer_pool: Option<&mut BufferPool>,
+    ) -> Result<(), DbError> {
+        let marker = filename::rewrite_marker(name);
+        if !name.storage().path_exists(&marker) {
+            return Ok(());
+        }
+        let rewrite: RunRewrite = serde_state::deserialize(&name.storage().read_file(&marker)?)
+            .map_err(|reason| DbError::Corrupt {
+                file: "rewrite marker",
+                reason,
+            })?;
+        Ok(Self::finish_rewrite(name, &rewrite, buffer_pool)?)
+    }
+    ///Second half of rewrite_run, also run by try_open if a crash cut the swap short (its marker is still there).
+    /// The metadata is written before any of the run's files are replaced, the replacement's SST file goes last.
+    /// NOTE: every step can be done again, files that were already renamed or removed are skipped
+    fn finish_rewrite(
+        name: &DatabaseName,
+        rewrite: &RunRewrite,
+        mut buffer_pool: Option<&mut BufferPool>,
+    ) -> io::Result<()> {
+        let storage = name.storage();
+        storage.write_file(&filename::metadata(name), &rewrite.metadata.serialize())?;
+        let staging_address = &(name, rewrite.level, rewrite.staging_run);
+        let run_address = &(name, rewrite.level, rewrite.run);
+        for ((staging_path, path), &replaced) in run_paths(staging_address)
+            .into_iter()
+            .zip(run_paths(run_address))
+            .zip(&rewrite.replaced_files)
+            .rev()
+        {
+            if !replaced && storage.path_exists(&path) {
+                file_interface::remove_file(storage, &path, buffer_pool.as_deref_mut())?;
+            } else if replaced && storage.path_exists(&staging_path) {
+                file_interface::rename_file(
+                    storage,
+                    &staging_path,
+                    &path,
                     buffer_pool.as_deref_mut(),
-                ),
+                )?;
+            }
+        }
+        storage.remove_file(&filename::rewrite_marker(name))
+    }
+    ///Keys within <key_range> (inclusive) that a run younger than run <run> of <level> holds a value or tombstone for,
+    /// so that run's entries for them are never read. Merge operands don't hide older entries. The memtable isn't
+    /// looked at, its writes are lost in a crash and the run's entries would be needed again
+    fn shadowing_keys(
+        &self,
+        level: Level,
+        run: Run,
+        key_range: (Key, Key),
+    ) -> io::Result<HashSet<Key>> {
+        let mut keys = HashSet::new();
+
+        let key_codec = self.key_codec();
+        let Metadata {
+            entry_counts,
+            operand_counts,
+            page_sizes,
+            ..
+        } = &self.metadata;
+        let mut pool = self.buffer_pool.borrow_mut();
+        let mut buffer_pool = if self.config.enable_buffer_pool {
+            Some(&mut *pool)
+        } else {
+            None
+        };
+        let younger_runs = (0..level)
+            .flat_map(|younger_level| {
+                (0..entry_counts[younger_level].len())
+                    .map(move |younger_run| (younger_level, younger_run))
+            })
+            .chain((run + 1..entry_counts[level].len()).map(|younger_run| (level, younger_run)));
+        for (younger_level, younger_run) in younger_runs {
+            let run_address = (&self.name, younger_level, younger_run);
+            let bounds = Self::run_sst_interface(
+                self.config.sst_implementation,
+                key_codec,
+                page_sizes[younger_level][younger_run],
+            )
+            .scan_bounds(
+                &run_address,
+                key_range,
+                entry_counts[younger_level][younger_run],
+                buffer_pool.as_deref_mut(),
+            )?;
+            let mut cursor = RunCursor::new(run_address, key_codec, bounds, false);
+            if operand_counts[younger_level][younger_run] > 0 {
+                cursor = cursor.with_operands(operands::read(&run_address, key_codec)?);
+            }
+            while let Some(key) = cursor.next_key(buffer_pool.as_deref_mut())? {
+                if !cursor.is_operand(key) {
+                    keys.insert(key);
+                }
+            }
+        }
+        Ok(keys)
+    }
+    ///Spend up to <budget> on pending maintenance, one task at a time in MaintenanceTask order: compacting levels that
+    /// hold more runs than they should, collecting what younger tombstones hide from older runs, writing bloom
+    /// filters runs are missing, then verifying runs. Returns the tasks that were done, none once nothing is pending.
+    /// Progress is kept across calls (and opens), so repeated calls with a small budget work through everything.
+    /// NOTE: a task isn't interrupted once started, at least one runs per call and the last may go over the budget
+    pub fn maintain(&mut self, budget: Duration) -> io::Result<Vec<MaintenanceTask>> {
+        let start = Instant::now();
+        let mut tasks = Vec::new();
+        while tasks.is_empty() || start.elapsed() < budget {
+            if !tasks.is_empty() {
+                self.yield_hook.call()?;
+            }
+            let Some(task) = self.next_maintenance_task() else {
+                break;
             };
-            match scan() {
-                Err(why) => panic!("Something went wrong trying to scan range ({key1} to {key2}) at level {level}, sst {run}, reason: {why}"),
-                Ok(scan_result) => {
-                    for (key, value) in scan_result {
-                        //NOTE: because we only allow unique keys to be pushed to the min_heap, it will only ever compare the first item in the tuple (the key) when ordering
-                        if !unique_key_set.contains(&key) {
-                            unique_key_set.insert(key);
-                            max_heap.push((-key, value));
-                        }
-                    }
+            self.run_maintenance_task(task)?;
+            tasks.push(task);
+        }
+        Ok(tasks)
+    }
+    ///The task maintain would do next, None once nothing is pending
+    pub fn next_maintenance(&mut self) -> Option<MaintenanceTask> {
+        self.next_maintenance_task()
+    }
+    fn run_stats(metadata: &Metadata) -> RunStats<'_> {
+        RunStats {
+            entry_counts: &metadata.entry_counts,
+            tombstone_counts: &metadata.tombstone_counts,
+            max_sequences: &metadata.max_sequences,
+        }
+    }
+    fn next_maintenance_task(&mut self) -> Option<MaintenanceTask> {
+        let background_levels = self.background_levels();
+        let _background_state = background_levels
+            .as_deref()
+            .map(|levels| self.sync_levels(levels));
+
+        let Config {
+            compaction_policy,
+            bloom_levels,
+            ..
+        } = self.config;
+        let entry_counts = &self.metadata.entry_counts;
+        let num_levels = entry_counts.len();
+        let mut debt_levels = Vec::new();
+        if compaction_policy == CompactionPolicy::None
+            && self.sst_count(Self::LEVEL_ZERO) >= self.level_zero_run_limit().max(2)
+        {
+            debt_levels.push(Self::LEVEL_ZERO);
+        }
+        //the background compactor keeps the other levels in shape itself
+        if self.background_compactor.is_none() {
+            let strategy = strategy::for_policy(compaction_policy);
+            let settings = self.strategy_settings();
+            debt_levels.extend((1..num_levels).filter(|level| {
+                entry_counts[*level].len() >= 2
+                    && strategy.should_compact(&self.level_runs(*level), &settings)
+                        != CompactionDecision::Keep
+            }));
+        }
+        let missing_filters: Vec<(Level, Run)> = (0..num_levels)
+            .filter(|level| bloom_levels.includes(*level))
+            .flat_map(|level| (0..entry_counts[level].len()).map(move |run| (level, run)))
+            .filter(|(level, run)| {
+                !self
+                    .name
+                    .storage()
+                    .path_exists(&(&self.name, *level, *run).path_bloom())
+            })
+            .collect();
+        self.maintenance.next_task(
+            &Self::run_stats(&self.metadata),
+            &debt_levels,
+            &missing_filters,
+        )
+    }
+    fn run_maintenance_task(&mut self, task: MaintenanceTask) -> io::Result<()> {
+        match task {
+            MaintenanceTask::Compact {
+                level: Self::LEVEL_ZERO,
+            } => self.compact_level_zero(),
+            MaintenanceTask::Compact { level } => {
+                let is_last_level = level == self.level_shape.num_levels() - 1;
+                self.compact_level(level, is_last_level)?;
+                self.compaction_error = None;
+                Ok(())
+            }
+            MaintenanceTask::CollectTombstones { level, run } => {
+                self.rewrite_run_unchecked(level, run)?;
+                let stats = Self::run_stats(&self.metadata);
+                self.maintenance.record_collected(&stats, level, run);
+                Ok(())
+            }
+            MaintenanceTask::BackfillFilter { level, run } => {
+                let background_levels = self.background_levels();
+                let _background_state = background_levels
+                    .as_deref()
+                    .map(|levels| self.sync_levels(levels));
+                BloomFilterIO::write_from_sst(
+                    &(&self.name, level, run),
+                    self.bloom_filter_bits_per_entry(),
+                    self.metadata.entry_counts[level][run],
+                    self.key_codec(),
+                )
+            }
+            MaintenanceTask::Verify { level, run } => {
+                let background_levels = self.background_levels();
+                let _background_state = background_levels
+                    .as_deref()
+                    .map(|levels| self.sync_levels(levels));
+                let sst = Self::run_sst_interface(
+                    self.sst_implementation(),
+                    self.key_codec(),
+                    self.metadata.page_sizes[level][run],
+                );
+                let entries = sst.read(&(&self.name, level, run))?;
+                let num_entries = self.metadata.entry_counts[level][run];
+                let problem = if entries.len() != num_entries {
+                    Some(format!(
+                        "holds {} entries instead of {num_entries}",
+                        entries.len()
+                    ))
+                } else {
+                    (entries.windows(2).position(|pair| pair[0].0 >= pair[1].0))
+                        .map(|index| format!("isn't sorted at entry {}", index + 1))
+                };
+                if let Some(problem) = problem {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!("Level {level}, sst {run} {problem}"),
+                    ));
+                }
+                let stats = Self::run_stats(&self.metadata);
+                self.maintenance.record_verified(&stats, level, run);
+                Ok(())
+            }
+        }
+    }
+    ///Write the memtable out as a level 0 run now instead of once it's full, compacting like any other flush.
+    /// Fails with DbError::TooManyRuns like a write would (see LevelZeroOverflowBehavior::Backpressure), or with
+    /// DbError::Aborted if the yield hook stops it or with DbError::Io if the run can't be written, keeping the memtable
+    pub fn flush(&mut self) -> Result<(), DbError> {
+        self.flush_memtable()
+    }
+    ///First step of a flush in two: the memtable's entries are frozen for flush_frozen to write out, while writes go
+    /// on into an empty memtable. Until then gets and scans read the memtable, then the frozen entries, then the runs.
+    /// Entries frozen earlier are flushed first, which fails like flush does. Freezing copies no entries
+    pub fn freeze_memtable(&mut self) -> Result<(), DbError> {
+        self.flush_frozen()?;
+        if self.memtable.len() > 0 {
+            Memtable::freeze_view(&mut self.memtable);
+        }
+        Ok(())
+    }
+    ///try_close that panics if closing fails
+    pub fn close(&mut self) {
+        self.try_close()
+            .unwrap_or_else(|why| panic!("Unable to close database, reason: {why}"));
+    }
+    ///Flush the memtable, wait for queued compactions and write our files, then close the database, every operation
+    /// that writes fails with DbError::Closed from then on. Closing a closed database does nothing.
+    /// If anything fails the database stays open, so closing can be retried (e.g. once there's space on the disk)
+    pub fn try_close(&mut self) -> Result<(), DbError> {
+        if self.is_closed() {
+            return Ok(());
+        }
+        self.yield_hook = YieldHook::default(); //an aborted flush would lose the memtable
+        match self.flush_memtable() {
+            //backpressure only holds back writes, the memtable isn't lost on close
+            Err(DbError::TooManyRuns { .. }) => {
+                self.compact_level_zero()?;
+                self.flush_memtable()?;
+            }
+            flushed => flushed?,
+        }
+        self.stop_background_compactor()?;
+        self.write_db_state()?;
+        self.write_read_cache_file()?;
+        self.write_maintenance_file()?;
+        self.write_warm_file()?;
+        self.invalidate();
+        Ok(())
+    }
+    ///Close the database without flushing the memtable, as if the process crashed right after the last flush:
+    /// whatever wasn't flushed is lost. Queued background compactions still finish and the metadata is updated for
+    /// them if it can be, errors are ignored
+    pub fn abandon(mut self) {
+        self.invalidate();
+    }
+    ///Mark us closed (see clear) and free our pages for any other database sharing the buffer pool (they're useless
+    /// now)
+    fn invalidate(&mut self) {
+        let name = self.name.clone();
+        self.clear();
+        self.buffer_pool.borrow_mut().remove_directory(&name);
+    }
+    ///Remove every entry: the memtable, every run with its B-tree, bloom filter and sidecars, and the database's pages
+    /// in the buffer pool and read cache. The directory, config and sequence numbers are kept, the levels are reset to
+    /// a single empty one. Snapshots and iterators taken before fail with DbError::Truncated from then on.
+    /// A crash part way through is finished by the next open, it never leaves some of the runs behind
+    pub fn truncate(&mut self) -> Result<(), DbError> {
+        self.ensure_open()?;
+        self.stop_background_compactor()?;
+        self.compaction_error = None;
+        self.name
+            .storage()
+            .write_file(&filename::truncate_marker(&self.name), &[])?;
+        let metadata = self.metadata.truncated();
+        Self::finish_truncate(&self.name, &metadata)?;
+        self.metadata = metadata;
+        self.metadata_dirty = false;
+        self.level_shape = LevelShape::from_entry_counts(&self.metadata.entry_counts);
+        self.clear_memtable();
+        self.read_cache.discard(&self.name, None); //its values are the removed runs'
+        self.read_cache = ReadCache::new(self.read_cache.capacity());
+        self.maintenance = MaintenanceScheduler::default();
+        self.warm_pages.clear();
+        self.buffer_pool.borrow_mut().remove_directory(&self.name);
+        self.truncations.set(self.truncations.get() + 1);
+        if self.config.background_compaction {
+            self.start_background_compactor();
+        }
+        Ok(())
+    }
+    ///Watch for truncates of the database from now on, see RangeIter and Snapshot
+    pub(crate) fn truncation_watch(&self) -> TruncationWatch {
+        TruncationWatch {
+            truncations: Rc::clone(&self.truncations),
+            seen: self.truncations.get(),
+        }
+    }
+    fn put_unchecked(&mut self, key: Key, value: Value) -> Result<(), DbError> {
+        self.make_room_in_memtable()?;
+        let sequence = self.next_sequence();
+        Rc::make_mut(&mut self.memtable).put(key, value, sequence);
+        Ok(())
+    }
+    fn next_sequence(&mut self) -> Sequence {
+        self.metadata.last_sequence += 1;
+        self.metadata_dirty = true;
+        self.metadata.last_sequence
+    }
+    ///Flush the memtable if it's full, so the run holds exactly memtable_capacity entries and the entry about to be
+    /// put starts the fresh memtable (capacity is at least 1, so there's always room after). Every write goes through
+    /// here, so writes are held back first (see admit_write)
+    fn make_room_in_memtable(&mut self) -> Result<(), DbError> {
+        self.ensure_open()?;
+        self.admit_write()?;
+        if self.memtable.len() >= self.memtable_capacity() {
+            self.flush_memtable()?;
+        }
+        if self.memtable.first_write().is_none() {
+            let now = self.now();
+            Rc::make_mut(&mut self.memtable).record_write(now);
+        }
+        self.check_durability_lag();
+        Ok(())
+    }
+    ///Report a DurabilityLag event if the oldest unflushed write is at least durability_lag_threshold old, once per
+    /// oldest write
+    fn check_durability_lag(&mut self) {
+        let Some(threshold) = self.durability_lag_threshold else {
+            return;
+        };
+        let oldest_write = self.memtable.oldest_unflushed_write();
+        if oldest_write == self.durability_lag_reported {
+            return;
+        }
+        if let Some(age) = self.unflushed_age().filter(|age| *age >= threshold) {
+            self.durability_lag_reported = oldest_write;
+            self.report_event(DbEvent::DurabilityLag {
+                age,
+                unflushed_entries: self.unflushed_entries(),
+            });
+        }
+    }
+    ///Hold back a write while level 0 piles up: stall it at level_zero_stop_runs runs (see StallBehavior), otherwise
+    /// sleep at level_zero_slowdown_runs runs and up. Neither reads the clock or sleeps while they're unset
+    fn admit_write(&mut self) -> Result<(), DbError> {
+        let Config {
+            level_zero_slowdown_runs,
+            level_zero_stop_runs,
+            stall_behavior,
+            ..
+        } = self.config;
+        if level_zero_slowdown_runs == 0 && level_zero_stop_runs == 0 {
+            return Ok(());
+        }
+        let mut runs = self.sst_count(Self::LEVEL_ZERO);
+        if level_zero_stop_runs != 0 && runs >= level_zero_stop_runs {
+            self.report_event(DbEvent::WriteStopped {
+                level_zero_runs: runs,
+            });
+            match stall_behavior {
+                StallBehavior::Fail => {
+                    return Err(DbError::WriteStalled {
+                        level_zero_runs: runs,
+                    })
+                }
+                StallBehavior::Block => self.compact_level_zero()?,
+            }
+            runs = self.sst_count(Self::LEVEL_ZERO);
+        }
+        if level_zero_slowdown_runs != 0 && runs >= level_zero_slowdown_runs {
+            let doublings = (runs - level_zero_slowdown_runs).min(Self::MAX_SLOWDOWN_DOUBLINGS);
+            let delay = self
+                .level_zero_slowdown_base()
+                .checked_mul(1 << doublings)
+                .unwrap_or(Duration::MAX);
+            self.report_event(DbEvent::WriteSlowed {
+                level_zero_runs: runs,
+                delay,
+            });
+            thread::sleep(delay);
+        }
+        Ok(())
+    }
+    ///<key> and <value> can't be stored if they're the reserved INVALID_KEY or TOMBSTONE_VALUE
+    fn check_reserved(key: Key, value: Value) -> Result<(), DbError> {
+        if key == Self::INVALID_KEY {
+            return Err(DbError::ReservedKey);
+        }
+        if value == Self::TOMBSTONE_VALUE {
+            return Err(DbError::ReservedValue);
+        }
+        Ok(())
+    }
+    ///Fails without changing anything if <key> or <value> is reserved, if the memtable is full and level 0 has no
+    /// room for it (see LevelZeroOverflowBehavior::Backpressure), or while writes are stalled (see
+    /// set_level_zero_stop_runs)
+    pub fn put(&mut self, key: Key, value: Value) -> Result<(), DbError> {
+        Self::check_reserved(key, value)?;
+        self.put_version(key, value)
+    }
+    ///Put <value> for <key> as set_version_merge says, which can mean writing nothing
+    fn put_version(&mut self, key: Key, value: Value) -> Result<(), DbError> {
+        match self.version_merge() {
+            VersionMerge::KeepNewest => self.put_unchecked(key, value),
+            VersionMerge::KeepOldest if self.try_get(key)?.is_some() => Ok(()),
+            VersionMerge::KeepOldest => self.put_unchecked(key, value),
+            VersionMerge::SumValues => self.merge_unchecked(key, value),
+        }
+    }
+    ///Put an entry that <get> and <scan> treat as absent once <ttl_seconds> have passed (by the database's clock),
+    /// compaction drops it after that. A later put or delete of the key replaces it along with its expiry.
+    /// NOTE: under VersionMerge::SumValues the entry replaces the value under it too, under KeepOldest it's only
+    /// written if the key has no live value
+    pub fn put_with_ttl(
+        &mut self,
+        key: Key,
+        value: Value,
+        ttl_seconds: u64,
+    ) -> Result<(), DbError> {
+        Self::check_reserved(key, value)?;
+        if self.version_merge() == VersionMerge::KeepOldest && self.try_get(key)?.is_some() {
+            return Ok(());
+        }
+        let expiry = self.now().saturating_add(ttl_seconds);
+        self.make_room_in_memtable()?;
+        let sequence = self.next_sequence();
+        Rc::make_mut(&mut self.memtable).put_with_expiry(key, value, expiry, sequence);
+        Ok(())
+    }
+    ///Apply <operand> to the value of <key> with the merge operator (see set_merge_operator) without reading it, e.g.
+    /// merging 1 with MergeOperator::Add increments a counter. An absent or deleted key is treated as holding the
+    /// operator's identity. The operand is written as is and combined with the value under it by gets, scans and
+    /// compactions. Fails without changing anything if <key> or <operand> is reserved.
+    /// NOTE: a value put with a TTL keeps its expiry once operands are combined with it
+    pub fn merge(&mut self, key: Key, operand: Value) -> Result<(), DbError> {
+        Self::check_reserved(key, operand)?;
+        self.merge_unchecked(key, operand)
+    }
+    fn merge_unchecked(&mut self, key: Key, operand: Value) -> Result<(), DbError> {
+        self.make_room_in_memtable()?;
+        let operator = self.merge_operator();
+        let now = self.now();
+        let sequence = self.next_sequence();
+        //combined with the memtable's entry for the key right away, the memtable holds one entry per key
+        let memtable = Rc::make_mut(&mut self.memtable);
+        match memtable.get_entry(key) {
+            MemtableHit::Absent => memtable.put_operand(key, operand, sequence),
+            MemtableHit::Deleted => {
+                memtable.put(key, operator.apply(operator.identity(), operand), sequence)
+            }
+            MemtableHit::Live(value) if memtable.is_operand(key) => {
+                memtable.put_operand(key, operator.apply(value, operand), sequence)
+            }
+            MemtableHit::Live(value) => match memtable.expiry(key) {
+                Some(expiry) if expiry::is_expired(Some(expiry), now) => {
+                    memtable.put(key, operator.apply(operator.identity(), operand), sequence)
+                }
+                Some(expiry) => {
+                    memtable.put_with_expiry(key, operator.apply(value, operand), expiry, sequence)
+                }
+                None => memtable.put(key, operator.apply(value, operand), sequence),
+            },
+        }
+        Ok(())
+    }
+    ///Put <new> under <key> only if the key's live value is <expected> (None for a key that's absent, deleted or
+    /// expired), otherwise report the value it holds. Reading and writing can't be interleaved with other operations
+    /// on this handle, they all need it mutably. Fails without changing anything if <key>, <expected> or <new> is
+    /// reserved, if the key can't be read (DbError::Io) or if level 0 has no room (see put)
+    pub fn compare_and_put(
+        &mut self,
+        key: Key,
+        expected: Option<Value>,
+        new: Value,
+    ) -> Result<CasResult, DbError> {
+        Self::check_reserved(key, new)?;
+        if expected == Some(Self::TOMBSTONE_VALUE) {
+            return Err(DbError::ReservedValue); //no key holds it, the swap could never happen
+        }
+        let actual = self.try_get(key)?;
+        if actual != expected {
+            return Ok(CasResult::Mismatch { actual });
+        }
+        self.put_unchecked(key, new)?;
+        Ok(CasResult::Swapped)
+    }
+    ///Put <entries>, whose keys have to be strictly increasing, for less than a put per entry costs (see
+    /// Memtable::put_sorted). The whole batch is checked before anything is written, it's rejected with
+    /// DbError::UnsortedBatch if a key isn't larger than the one before it or with ReservedKey / ReservedValue.
+    /// The memtable is flushed whenever the batch fills it, if a flush fails (see put) the entries before it stay put.
+    /// NOTE: under a VersionMerge other than KeepNewest the entries are put one at a time
+    pub fn put_sorted_batch(&mut self, entries: &[Entry]) -> Result<(), DbError> {
+        if let Some(index) = entries.windows(2).position(|pair| pair[0].0 >= pair[1].0) {
+            return Err(DbError::UnsortedBatch { index: index + 1 });
+        }
+        for &(key, value) in entries {
+            Self::check_reserved(key, value)?;
+        }
+        if self.version_merge() != VersionMerge::KeepNewest {
+            //each entry is combined with the version under it
+            for &(key, value) in entries {
+                self.put_version(key, value)?;
+            }
+            return Ok(());
+        }
+        let mut remaining = entries;
+        while !remaining.is_empty() {
+            self.make_room_in_memtable()?;
+            let room = self.memtable_capacity().saturating_sub(self.memtable.len());
+            let (part, rest) = remaining.split_at(room.clamp(1, remaining.len()));
+            let first_sequence = self.metadata.last_sequence + 1;
+            self.metadata.last_sequence += part.len() as Sequence;
+            self.metadata_dirty = true;
+            Rc::make_mut(&mut self.memtable).put_sorted(part, first_sequence);
+            remaining = rest;
+        }
+        Ok(())
+    }
+    ///Fails without changing anything if <key> is reserved, or if level 0 has no room (see put)
+    pub fn delete(&mut self, key: Key) -> Result<(), DbError> {
+        if key == Self::INVALID_KEY {
+            return Err(DbError::ReservedKey);
+        }
+        self.put_unchecked(key, Self::TOMBSTONE_VALUE)
+    }
+    ///Put every "key<delimiter>value" line of <reader> (e.g. a TSV or CSV file of integers), in any key order.
+    /// Returns the number of keys imported, a key on several lines gets the value of its last line
+    pub fn import_delimited(
+        &mut self,
+        reader: impl BufRead,
+        delimiter: Delimiter,
+        has_header: bool,
+    ) -> Result<Size, DbError> {
+        self.import_delimited_with_options(reader, ImportOptions::new(delimiter, has_header))
+    }
+    ///The whole input is parsed, validated and sorted before anything is put, so an invalid line (the error has its
+    /// line number) leaves the database unchanged. Input past <options.memory_budget> is sorted in chunks that are
+    /// spilled to a temporary directory inside the database's directory and merged, then put in key order
+    pub fn import_delimited_with_options(
+        &mut self,
+        reader: impl BufRead,
+        options: ImportOptions,
+    ) -> Result<Size, DbError> {
+        self.ensure_open()?;
+        let (storage, temp_dir) = (
+            self.name.storage().clone(),
+            filename::import_directory(&self.name),
+        );
+        //lines with reserved keys or values are rejected while parsing
+        import::import_sorted(reader, &options, &storage, &temp_dir, &mut |key, value| {
+            self.put_version(key, value)
+        })
+    }
+    ///For each sst, from youngest to oldest, run a callback function (the callback returns true if we want to return early)
+    fn for_each_sst(entry_counts: &[Vec<Size>], callback: &mut dyn FnMut(Level, Run) -> bool) {
+        for (level, level_entry_counts) in entry_counts.iter().enumerate() {
+            //lower level is younger
+            let runs_in_level = level_entry_counts.len();
+            for run in (0..runs_in_level).rev() {
+                //higher number sst is younger
+                if callback(level, run) {
+                    return;
+                }
+            }
+        }
+    }
+    ///Value of <key>, if it has one. A closed database holds nothing, so this is None once it's closed. Panics if a
+    /// run can't be read, try_get tells the three apart
+    pub fn get(&mut self, key: Key) -> Option<Value> {
+        self.read_value(key).unwrap_or_else(|why| panic!("{why}"))
+    }
+    ///get that fails with DbError::Closed once the database is closed, and with DbError::Io if a run can't be read
+    pub fn try_get(&mut self, key: Key) -> Result<Option<Value>, DbError> {
+        self.ensure_open()?;
+        Ok(self.read_value(key)?)
+    }
+    ///search, checked against full reads of the runs when paranoid reads are on
+    fn read_value(&mut self, key: Key) -> io::Result<Option<Value>> {
+        let value = self.search(key, None)?;
+        #[cfg(any(test, feature = "paranoid-reads"))]
+        if self.paranoid_reads {
+            io_stats::uncounted(|| self.check_get(key, value));
+        }
+        Ok(value)
+    }
+    ///Get <key>, recording each step: what the memtable and read cache held, and for each run searched what its
+    /// bloom filter said, how it was searched, what it held and the pages that took. The answer is the one get gives,
+    /// so it's None once the database is closed too, and it panics like get does (see try_explain_get)
+    pub fn explain_get(&mut self, key: Key) -> GetTrace {
+        self.trace_get(key).unwrap_or_else(|why| panic!("{why}"))
+    }
+    ///explain_get that fails with DbError::Closed once the database is closed, and with DbError::Io if a run can't
+    /// be read
+    pub fn try_explain_get(&mut self, key: Key) -> Result<GetTrace, DbError> {
+        self.ensure_open()?;
+        Ok(self.trace_get(key)?)
+    }
+    fn trace_get(&mut self, key: Key) -> io::Result<GetTrace> {
+        let mut trace = GetTrace::new(key);
+        let baseline = io_stats::snapshot();
+        trace.value = self.search(key, Some(&mut trace))?;
+        trace.io = io_stats::snapshot().since(&baseline);
+        Ok(trace)
+    }
+    ///Whether <key> has a value, get without handing it back
+    pub fn contains(&mut self, key: Key) -> bool {
+        self.get(key).is_some()
+    }
+    ///Whether <key> might have a value, from the memtable, read cache and the runs' bloom filters only: no run's
+    /// entries are read. It can be wrong about a key that has no value (a filter's false positive, or a run without
+    /// a filter, says maybe), never about one that has. A key deleted in the memtable has no value
+    pub fn may_contain(&mut self, key: Key) -> bool {
+        match self.memtable.get_entry(key) {
+            MemtableHit::Deleted => return false,
+            MemtableHit::Live(_) => {
+                return !expiry::is_expired(self.memtable.expiry(key), self.now())
+            }
+            MemtableHit::Absent => {}
+        }
+        let background_levels = self.background_levels();
+        let _background_state = background_levels
+            .as_deref()
+            .map(|levels| self.sync_levels(levels));
+        if self.cached_value(key).is_some() {
+            return true;
+        }
+
+        let mut pool = self.buffer_pool.borrow_mut();
+        let mut buffer_pool = if self.config.enable_buffer_pool {
+            Some(&mut *pool)
+        } else {
+            None
+        };
+        let Metadata {
+            entry_counts,
+            tombstone_counts,
+            ..
+        } = &self.metadata;
+        let bloom_levels = self.config.bloom_levels;
+        let bits_per_entry = self.config.bloom_filter_bits_per_entry;
+        let key_codec = self.config.key_codec;
+        let mut may_contain = false;
+        Self::for_each_sst(entry_counts, &mut |level, run| {
+            //a run of only tombstones holds no value for any key
+            if tombstone_counts[level][run] == entry_counts[level][run] {
+                return false;
+            }
+            if !bloom_levels.includes(level) {
+                may_contain = true;
+                return true;
+            }
+            let run_address = &(&self.name, level, run);
+            //a run written before its level got filters, or whose filter can't be read (see verify_filters), might
+            // hold any key
+            may_contain = BloomFilterIO::contains(
+                run_address,
+                key,
+                bits_per_entry,
+                entry_counts[level][run],
+                key_codec,
+                buffer_pool.as_deref_mut(),
+            )
+            .unwrap_or(true);
+            may_contain
+        });
+        may_contain
+    }
+    ///Read-only view of the database as it is now, its gets and scans don't see anything written, flushed or
+    /// compacted afterwards. Panics if the runs can't be linked into the snapshot's directory, see try_snapshot_view
+    pub fn snapshot_view(&mut self) -> Snapshot {
+        self.try_snapshot_view().unwrap_or_else(|why| {
+            panic!(
+                "Unable to take a snapshot of {}, reason: {why}",
+                self.name.display()
+            )
+        })
+    }
+    ///snapshot_view, or an error if the runs can't be linked. Each file of every run is hard linked into the
+    /// snapshot's directory (see snapshot), so no data is copied and no run it reads goes away while it's alive.
+    /// The memtable is shared, the database copies it the next time it's changed. Entries with a TTL are read as of
+    /// when the snapshot was taken
+    pub fn try_snapshot_view(&mut self) -> Result<Snapshot, DbError> {
+        static NEXT_SNAPSHOT_ID: AtomicUsize = AtomicUsize::new(0);
+        //the background compactor can't replace runs while they're linked
+        let background_levels = self.background_levels();
+        let _background_state = background_levels
+            .as_deref()
+            .map(|levels| self.sync_levels(levels));
+
+        let id = NEXT_SNAPSHOT_ID.fetch_add(1, Ordering::Relaxed);
+        let directory = Snapshot::create_directory(&self.name, id)?;
+        let mut config = self.config.clone();
+        config.background_compaction = false;
+        config.enable_buffer_pool = false; //its pages would be the database's pages over again
+        let mut db = Database::new(directory.clone(), config, self.metadata.clone());
+        db.memtable = Rc::clone(&self.memtable);
+        db.clock = Box::new(ManualClock::new(self.now()));
+        //dropping the snapshot removes whatever was linked if a link fails
+        let snapshot = Snapshot::new(db, directory.clone(), self.truncation_watch());
+        for (level, entry_counts) in self.metadata.entry_counts.iter().enumerate() {
+            if entry_counts.is_empty() {
+                continue;
+            }
+            self.name
+                .storage()
+                .create_dir_all(&filename::lsm_level_directory(&(&directory, level)))?;
+            for run in 0..entry_counts.len() {
+                let links = run_paths(&(&directory, level, run));
+                for (path, link) in run_paths(&(&self.name, level, run)).iter().zip(&links) {
+                    if self.name.storage().path_exists(path) {
+                        self.name.storage().hard_link(path, link)?;
+                    }
+                }
+            }
+        }
+        Ok(snapshot)
+    }
+    ///Value of <key> from the memtable, read cache and runs, youngest first. Fails if a run can't be read
+    fn search(&mut self, key: Key, mut trace: Option<&mut GetTrace>) -> io::Result<Option<Value>> {
+        let now = self.now();
+        let operator = self.merge_operator();
+        //operands found so far combined (youngest first), they're applied to the first value found under them
+        let mut operand: Option<Value> = None;
+        //check memtable first
+        let memtable_check = match self.memtable.get_entry(key) {
+            MemtableHit::Deleted => MemtableCheck::Deleted,
+            MemtableHit::Live(_) if expiry::is_expired(self.memtable.expiry(key), now) => {
+                MemtableCheck::Expired
+            }
+            MemtableHit::Live(value) if !self.memtable.is_operand(key) => {
+                MemtableCheck::Live(value)
+            }
+            MemtableHit::Live(value) => MemtableCheck::Operand(value),
+            MemtableHit::Absent => MemtableCheck::Absent,
+        };
+        if let Some(trace) = trace.as_deref_mut() {
+            trace.memtable = memtable_check;
+            trace.resolution = Resolution::Memtable;
+        }
+        match memtable_check {
+            MemtableCheck::Deleted | MemtableCheck::Expired => return Ok(None),
+            MemtableCheck::Live(value) => return Ok(Some(value)),
+            MemtableCheck::Operand(value) => operand = Some(value),
+            MemtableCheck::Absent => {}
+        }
+        //hold the background compactor's lock so the runs we search aren't replaced mid-read
+        let background_levels = self.background_levels();
+        let _background_state = background_levels
+            .as_deref()
+            .map(|levels| self.sync_levels(levels));
+        //the cache holds what the runs resolved to as of a sequence, it's never filled while the memtable has an
+        // operand for the key
+        if let Some(value) = self.cached_value(key) {
+            if let Some(trace) = trace.as_deref_mut() {
+                trace.read_cache_hit = true;
+                trace.resolution = Resolution::ReadCache;
+            }
+            return Ok(Some(
+                operand.map_or(value, |operand| operator.apply(value, operand)),
+            ));
+        }
+
+        let sst_implementation = self.sst_implementation();
+        let search_algorithm = self.sst_search_algorithm();
+
+        //search ssts within levels from youngest to oldest, return youngest value found
+        let mut sst_search_result: Option<Value> = None;
+        let mut sst_search_expiry: Option<Timestamp> = None;
+        let mut resolution = Resolution::NotFound;
+        let bloom_levels = self.config.bloom_levels;
+        //the B-tree is already a cheap way to the key's page
+        let use_fences = self.config.enable_fence_index
+            && matches!(sst_implementation, SstImplementation::Array)
+            && matches!(search_algorithm, SstSearchAlgorithm::Default);
+
+        let mut pool = self.buffer_pool.borrow_mut();
+        let mut buffer_pool = if self.config.enable_buffer_pool {
+            Some(&mut *pool)
+        } else {
+            None
+        };
+        let entry_counts = &self.metadata.entry_counts;
+        let tombstone_counts = &self.metadata.tombstone_counts;
+        let expiry_counts = &self.metadata.expiry_counts;
+        let operand_counts = &self.metadata.operand_counts;
+        let page_sizes = &self.metadata.page_sizes;
+        let bits_per_entry = &self.config.bloom_filter_bits_per_entry;
+        let mut run_operand: Option<Value> = None; //operands found in runs, combined youngest first
+        let key_codec = self.config.key_codec;
+        let mut run_error = None;
+        let mut search_run = |level: Level, run: Run| -> io::Result<_> {
+            let run_address = &(&self.name, level, run);
+            //runs that are all tombstones (mass deletes) are searched directly, their filter would only say "maybe"
+            let is_all_tombstones = tombstone_counts[level][run] == entry_counts[level][run];
+            let bloom = match bloom_levels.includes(level) {
+                false => BloomProbe::NoFilter,
+                true if is_all_tombstones => BloomProbe::Skipped,
+                //a run written before its level got filters (bloom_levels changed) has none, it's searched directly
+                true => match BloomFilterIO::contains(
+                    run_address,
+                    key,
+                    *bits_per_entry,
+                    entry_counts[level][run],
+                    key_codec,
+                    buffer_pool.as_deref_mut(),
+                ) {
+                    Ok(true) => BloomProbe::Positive,
+                    Ok(false) => return Ok((BloomProbe::Negative, None, RunOutcome::Filtered)),
+                    //a filter that can't be read is no reason to fail the get, see verify_filters
+                    Err(_) => BloomProbe::NoFilter,
+                },
+            };
+            let sst =
+                Self::run_sst_interface(sst_implementation, key_codec, page_sizes[level][run]);
+            let fenced = use_fences && Self::has_host_pages(page_sizes[level][run]);
+            let mut method = match (search_algorithm, sst_implementation) {
+                (SstSearchAlgorithm::BinarySearch, _) => SearchMethod::BinarySearch,
+                _ if fenced => SearchMethod::Fence,
+                (_, SstImplementation::Btree) if cfg!(feature = "btree") => SearchMethod::Btree,
+                _ => SearchMethod::Array,
+            };
+            let mut get = || match search_algorithm {
+                SstSearchAlgorithm::Default if fenced => fence::get(
+                    run_address,
+                    key,
+                    entry_counts[level][run],
+                    key_codec,
+                    buffer_pool.as_deref_mut(),
+                )
+                .or_else(|why| match why.kind() {
+                    io::ErrorKind::NotFound => {
+                        method = SearchMethod::Array; //no fence index was written for the run
+                        sst.get(
+                            run_address,
+                            key,
+                            entry_counts[level][run],
+                            buffer_pool.as_deref_mut(),
+                        )
+                    }
+                    _ => Err(why),
+                }),
+                SstSearchAlgorithm::Default => sst.get(
+                    run_address,
+                    key,
+                    entry_counts[level][run],
+                    buffer_pool.as_deref_mut(),
+                ),
+                SstSearchAlgorithm::BinarySearch => sst.binary_search_get(
+                    run_address,
+                    key,
+                    entry_counts[level][run],
+                    buffer_pool.as_deref_mut(),
+                ),
+            };
+            let context = |what: &str, why: io::Error| {
+                io::Error::new(
+                    why.kind(),
+                    format!("Something went wrong trying to get the {what} of key {key} at level {level}, sst {run}, reason: {why}"),
+                )
+            };
+            let value = get().map_err(|why| context("value", why))?;
+            let outcome = match value {
+                None => RunOutcome::NotFound,
+                Some(value)
+                    if operand_counts[level][run] > 0
+                        && operands::contains(
+                            run_address,
+                            key,
+                            operand_counts[level][run],
+                            key_codec,
+                            buffer_pool.as_deref_mut(),
+                        )
+                        .map_err(|why| context("record type", why))? =>
+                {
+                    RunOutcome::Operand(value)
+                }
+                Some(value) => {
+                    let expiry = match expiry_counts[level][run] {
+                        0 => None,
+                        num_expiries => expiry::get(
+                            run_address,
+                            key,
+                            num_expiries,
+                            key_codec,
+                            buffer_pool.as_deref_mut(),
+                        )
+                        .map_err(|why| context("expiry", why))?,
+                    };
+                    RunOutcome::Found { value, expiry }
+                }
+            };
+            Ok((bloom, Some(method), outcome))
+        };
+        //only the partition holding the key is searched in a partitioned level
+        let partitions = &self.metadata.partitions;
+        let partition_hits: Vec<Option<Run>> = partitions
+            .iter()
+            .map(|partitions| partition::containing(partitions, key))
+            .collect();
+        let mut callback = |level: Level, run: Run| {
+            let is_partition =
+                (partitions.get(level)).is_some_and(|partitions| run < partitions.len());
+            if is_partition && partition_hits[level] != Some(run) {
+                return false;
+            }
+            let baseline = io_stats::snapshot();
+            let (bloom, search, outcome) = match search_run(level, run) {
+                Ok(searched) => searched,
+                Err(why) => {
+                    run_error = Some(why);
+                    return true;
+                }
+            };
+            let found = match outcome {
+                RunOutcome::Filtered | RunOutcome::NotFound => false,
+                RunOutcome::Operand(value) => {
+                    run_operand =
+                        Some(run_operand.map_or(value, |younger| operator.apply(value, younger)));
+                    false //keep looking for the value under it
+                }
+                RunOutcome::Found { value, expiry } => {
+                    sst_search_result = Some(value);
+                    sst_search_expiry = expiry;
+                    resolution = Resolution::Run { level, run };
+                    true //exit from "for each" loop
+                }
+            };
+            if let Some(trace) = trace.as_deref_mut() {
+                trace.runs.push(RunTrace {
+                    level,
+                    run,
+                    bloom,
+                    search,
+                    outcome,
+                    io: io_stats::snapshot().since(&baseline),
+                });
+            }
+            found
+        };
+        Self::for_each_sst(&self.metadata.entry_counts, &mut callback);
+        if let Some(why) = run_error {
+            return Err(why);
+        }
+        if let Some(trace) = trace {
+            trace.resolution = resolution;
+        }
+        if sst_search_result.is_some_and(|value| value == Self::TOMBSTONE_VALUE)
+            || expiry::is_expired(sst_search_expiry, now)
+        {
+            sst_search_result = None;
+        }
+        //operands with nothing (live) under them are applied to the identity
+        if let Some(run_operand) = run_operand {
+            let value = sst_search_result.unwrap_or(operator.identity());
+            sst_search_result = Some(operator.apply(value, run_operand));
+        }
+        //entries with a TTL aren't cached, the cache has no way to expire them
+        if let (Some(value), None, None) = (sst_search_result, sst_search_expiry, operand) {
+            if self
+                .read_cache
+                .record_read(key, value, self.metadata.last_sequence)
+            {
+                drop(pool);
+                let _ = self.rewrite_read_cache(); //a cache that can't be written is no reason to fail the get
+            }
+        }
+        Ok(match operand {
+            Some(operand) => {
+                Some(operator.apply(sst_search_result.unwrap_or(operator.identity()), operand))
+            }
+            None => sst_search_result,
+        })
+    }
+    ///Value the read cache holds for <key>, unless a run written since it was cached might hold a newer write to it:
+    /// the cache run is older than every level, so it only answers when no newer run does. A cache run that can't be
+    /// read is discarded, the key is searched for in the levels then
+    fn cached_value(&mut self, key: Key) -> Option<Value> {
+        let mut pool = self.buffer_pool.borrow_mut();
+        let mut buffer_pool = self.config.enable_buffer_pool.then_some(&mut *pool);
+        let cached = self
+            .read_cache
+            .get(&self.name, key, buffer_pool.as_deref_mut());
+        let (value, sequence) = match cached {
+            Ok(cached) => cached?,
+            Err(_) => {
+                self.read_cache.discard(&self.name, buffer_pool);
+                return None;
+            }
+        };
+        let (name, metadata, config) = (&self.name, &self.metadata, &self.config);
+        if Self::newer_runs_may_hold(name, metadata, config, key, sequence, buffer_pool) {
+            return None;
+        }
+        self.read_cache.record_hit(key);
+        Some(value)
+    }
+    ///Whether a run written after <sequence> might hold a write to <key>, from the runs' bloom filters alone. Runs
+    /// without a filter (or whose filter can't be read) and runs of only tombstones might
+    fn newer_runs_may_hold(
+        name: &DatabaseName,
+        metadata: &Metadata,
+        config: &Config,
+        key: Key,
+        sequence: Sequence,
+        mut buffer_pool: Option<&mut BufferPool>,
+    ) -> bool {
+        let Metadata {
+            entry_counts,
+            tombstone_counts,
+            max_sequences,
+            partitions,
+            ..
+        } = metadata;
+        let mut may_hold = false;
+        Self::for_each_sst(entry_counts, &mut |level, run| {
+            if max_sequences[level][run] <= sequence {
+                return false;
+            }
+            //only the partition holding the key can hold a write to it
+            if let Some(partitions) = partitions.get(level).filter(|p| run < p.len()) {
+                if partition::containing(partitions, key) != Some(run) {
+                    return false;
+                }
+            }
+            may_hold = tombstone_counts[level][run] == entry_counts[level][run]
+                || !config.bloom_levels.includes(level)
+                || BloomFilterIO::contains(
+                    &(name, level, run),
+                    key,
+                    config.bloom_filter_bits_per_entry,
+                    entry_counts[level][run],
+                    config.key_codec,
+                    buffer_pool.as_deref_mut(),
+                )
+                .unwrap_or(true);
+            may_hold //exit from "for each" loop
+        });
+        may_hold
+    }
+    ///Write the keys admitted to the read cache since its run was written to a new cache run, see ReadCache::rewrite.
+    /// The cache is discarded if that fails
+    fn rewrite_read_cache(&mut self) -> io::Result<()> {
+        let mut pool = self.buffer_pool.borrow_mut();
+        let mut buffer_pool = self.config.enable_buffer_pool.then_some(&mut *pool);
+        let (name, metadata, config) = (&self.name, &self.metadata, &self.config);
+        let rewritten = self.read_cache.rewrite(
+            name,
+            metadata.newest_run_sequence(),
+            config.key_codec,
+            &mut |key, sequence, buffer_pool| {
+                !Self::newer_runs_may_hold(name, metadata, config, key, sequence, buffer_pool)
+            },
+            buffer_pool.as_deref_mut(),
+        );
+        if rewritten.is_err() {
+            self.read_cache.discard(name, buffer_pool);
+        }
+        rewritten
+    }
+    ///Work out <key>'s value the slow way, from the memtable and full reads of the runs (no bloom filters, B-trees,
+    /// fences, buffer pool or read cache), and panic if get found <value> instead. The message names the run that
+    /// decided it
+    #[cfg(any(test, feature = "paranoid-reads"))]
+    fn check_get(&mut self, key: Key, value: Option<Value>) {
+        let now = self.now();
+        let operator = self.merge_operator();
+        let background_levels = self.background_levels();
+        let _background_state = background_levels
+            .as_deref()
+            .map(|levels| self.sync_levels(levels));
+
+        let memtable = &self.memtable;
+        let memtable_operand = memtable
+            .is_operand(key)
+            .then(|| memtable.get(key))
+            .flatten();
+        //the value under any operands and where it came from, None until something other than an operand is found
+        let mut found: Option<(Option<Value>, String)> = match memtable.get_entry(key) {
+            MemtableHit::Live(_) if memtable_operand.is_some() => None,
+            MemtableHit::Live(value) if !expiry::is_expired(memtable.expiry(key), now) => {
+                Some((Some(value), "the memtable".to_string()))
+            }
+            MemtableHit::Live(_) | MemtableHit::Deleted => Some((None, "the memtable".to_string())),
+            MemtableHit::Absent => None,
+        };
+        let mut run_operand: Option<Value> = None; //combined youngest first, like get does
+        let key_codec = self.key_codec();
+        let sst_implementation = self.sst_implementation();
+        let Metadata {
+            entry_counts,
+            expiry_counts,
+            page_sizes,
+            operand_counts,
+            ..
+        } = &self.metadata;
+        for (level, runs) in entry_counts.iter().enumerate() {
+            for run in (0..runs.len()).rev() {
+                if found.is_some() {
+                    break;
+                }
+                let run_address = &(&self.name, level, run);
+                let sst =
+                    Self::run_sst_interface(sst_implementation, key_codec, page_sizes[level][run]);
+                let entries = sst.read(run_address).unwrap_or_else(|why| panic!("Something went wrong trying to read level {level}, sst {run}, reason: {why}"));
+                let Some(&(_, run_value)) = entries.iter().find(|(entry_key, _)| *entry_key == key)
+                else {
+                    continue;
+                };
+                let is_operand = operand_counts[level][run] > 0
+                    && operands::read(run_address, key_codec)
+                        .unwrap_or_else(|why| panic!("Something went wrong trying to read operands at level {level}, sst {run}, reason: {why}"))
+                        .contains(&key);
+                if is_operand {
+                    run_operand = Some(
+                        run_operand.map_or(run_value, |younger| operator.apply(run_value, younger)),
+                    );
+                    continue;
+                }
+                let expiry = match expiry_counts[level][run] {
+                    0 => None,
+                    _ => expiry::read(run_address, key_codec)
+                        .unwrap_or_else(|why| panic!("Something went wrong trying to read expiries at level {level}, sst {run}, reason: {why}"))
+                        .into_iter()
+                        .find_map(|(expiry_key, expiry)| (expiry_key == key).then_some(expiry)),
+                };
+                let is_deleted =
+                    run_value == Self::TOMBSTONE_VALUE || expiry::is_expired(expiry, now);
+                found = Some((
+                    (!is_deleted).then_some(run_value),
+                    format!("level {level}, sst {run}"),
+                ));
+            }
+        }
+
+        let (mut expected, source) = found.unwrap_or((None, "no run".to_string()));
+        if let Some(run_operand) = run_operand {
+            expected = Some(operator.apply(expected.unwrap_or(operator.identity()), run_operand));
+        }
+        if let Some(operand) = memtable_operand {
+            expected = Some(operator.apply(expected.unwrap_or(operator.identity()), operand));
+        }
+        assert_eq!(
+            value, expected,
+            "get({key}) disagrees with full reads of the runs, the value under any operands comes from {source}"
+        );
+    }
+    ///Entry with the smallest key >= <key>. Panics if a run can't be read, see try_get_ceiling
+    pub fn get_ceiling(&mut self, key: Key) -> Option<Entry> {
+        self.get_nearest(key, Nearest::Ceiling)
+            .unwrap_or_else(|why| panic!("{why}"))
+    }
+    ///get_ceiling that fails with DbError::Closed once the database is closed, and with DbError::Io if a run can't
+    /// be read
+    pub fn try_get_ceiling(&mut self, key: Key) -> Result<Option<Entry>, DbError> {
+        self.ensure_open()?;
+        Ok(self.get_nearest(key, Nearest::Ceiling)?)
+    }
+    ///Entry with the largest key <= <key>. Panics if a run can't be read, see try_get_floor
+    pub fn get_floor(&mut self, key: Key) -> Option<Entry> {
+        self.get_nearest(key, Nearest::Floor)
+            .unwrap_or_else(|why| panic!("{why}"))
+    }
+    ///get_floor that fails with DbError::Closed once the database is closed, and with DbError::Io if a run can't be
+    /// read
+    pub fn try_get_floor(&mut self, key: Key) -> Result<Option<Entry>, DbError> {
+        self.ensure_open()?;
+        Ok(self.get_nearest(key, Nearest::Floor)?)
+    }
+    ///Finds the nearest candidate in the memtable and every run, the best candidate's youngest version wins.
+    /// If that is a tombstone, sources positioned on the deleted key move past it and we try again.
+    /// Every source holding the best key is positioned on it, so merge operands are combined with the older versions
+    fn get_nearest(&mut self, key: Key, nearest: Nearest) -> io::Result<Option<Entry>> {
+        let background_levels = self.background_levels();
+        let _background_state = background_levels
+            .as_deref()
+            .map(|levels| self.sync_levels(levels));
+
+        let sst_implementation = self.sst_implementation();
+        let now = self.now();
+        let key_codec = self.key_codec();
+        let operator = self.merge_operator();
+        let mut pool = self.buffer_pool.borrow_mut();
+        let mut buffer_pool = if self.config.enable_buffer_pool {
+            Some(&mut *pool)
+        } else {
+            None
+        };
+        let name = &self.name;
+        let memtable = &self.memtable;
+        let entry_counts = &self.metadata.entry_counts;
+        let expiry_counts = &self.metadata.expiry_counts;
+        let operand_counts = &self.metadata.operand_counts;
+
+        let mut runs: Vec<(Level, Run)> = Vec::new(); //youngest to oldest
+        Self::for_each_sst(entry_counts, &mut |level, run| {
+            runs.push((level, run));
+            false
+        });
+        let run_ssts: Vec<Box<dyn SortedStringTable>> = runs
+            .iter()
+            .map(|&(level, run)| {
+                let page_size = self.metadata.page_sizes[level][run];
+                Self::run_sst_interface(sst_implementation, key_codec, page_size)
+            })
+            .collect();
+
+        //source 0 is the memtable, source i is runs[i - 1], so a lower source is younger. Candidates are (key, value,
+        // whether it's a merge operand). Expired entries come back as tombstones, so they're skipped but still hide
+        // older values
+        let mut query = |source: usize, key: Key| -> io::Result<Option<(Key, Value, bool)>> {
+            if source == 0 {
+                let Some((key, value)) = (match nearest {
+                    Nearest::Ceiling => memtable.ceiling(key),
+                    Nearest::Floor => memtable.floor(key),
+                }) else {
+                    return Ok(None);
+                };
+                if expiry::is_expired(memtable.expiry(key), now) {
+                    return Ok(Some((key, Self::TOMBSTONE_VALUE, false)));
+                }
+                return Ok(Some((key, value, memtable.is_operand(key))));
+            }
+            let (level, run) = runs[source - 1];
+            let run_address = &(name, level, run);
+            let num_entries = entry_counts[level][run];
+            let sst = &run_ssts[source - 1];
+            let result = match nearest {
+                Nearest::Ceiling => {
+                    sst.ceiling(run_address, key, num_entries, buffer_pool.as_deref_mut())
+                }
+                Nearest::Floor => {
+                    sst.floor(run_address, key, num_entries, buffer_pool.as_deref_mut())
+                }
+            };
+            let context = |what: String, why: io::Error| {
+                io::Error::new(
+                    why.kind(),
+                    format!("Something went wrong trying to {what} at level {level}, sst {run}, reason: {why}"),
+                )
+            };
+            let Some((found_key, value)) =
+                result.map_err(|why| context(format!("find the {nearest:?} of key {key}"), why))?
+            else {
+                return Ok(None);
+            };
+            if expiry_counts[level][run] > 0 && value != Self::TOMBSTONE_VALUE {
+                let expiry = expiry::get(
+                    run_address,
+                    found_key,
+                    expiry_counts[level][run],
+                    key_codec,
+                    buffer_pool.as_deref_mut(),
+                )
+                .map_err(|why| context(format!("get the expiry of key {found_key}"), why))?;
+                if expiry::is_expired(expiry, now) {
+                    return Ok(Some((found_key, Self::TOMBSTONE_VALUE, false)));
+                }
+            }
+            let is_operand = operand_counts[level][run] > 0
+                && operands::contains(
+                    run_address,
+                    found_key,
+                    operand_counts[level][run],
+                    key_codec,
+                    buffer_pool.as_deref_mut(),
+                )
+                .map_err(|why| context(format!("get the record type of key {found_key}"), why))?;
+            Ok(Some((found_key, value, is_operand)))
+        };
+
+        let mut candidates: Vec<Option<(Key, Value, bool)>> = (0..=runs.len())
+            .map(|source| query(source, key))
+            .collect::<io::Result<_>>()?;
+        loop {
+            let Some(best_key) = candidates
+                .iter()
+                .flatten()
+                .map(|(candidate_key, ..)| *candidate_key)
+                .reduce(|a, b| match nearest {
+                    Nearest::Ceiling => a.min(b),
+                    Nearest::Floor => a.max(b),
+                })
+            else {
+                return Ok(None);
+            };
+            //versions of the best key from the youngest source to the oldest, operands are combined with the first
+            // value under them (the identity if there's none)
+            let mut operand: Option<Value> = None;
+            let mut versions = candidates
+                .iter()
+                .flatten()
+                .filter(|(candidate_key, ..)| *candidate_key == best_key);
+            let value = loop {
+                match versions.next() {
+                    Some(&(_, value, true)) => {
+                        operand =
+                            Some(operand.map_or(value, |younger| operator.apply(value, younger)))
+                    }
+                    Some(&(_, value, false)) => break value,
+                    None => break Self::TOMBSTONE_VALUE,
+                }
+            };
+            if let Some(operand) = operand {
+                let value = match value {
+                    Self::TOMBSTONE_VALUE => operator.identity(),
+                    value => value,
+                };
+                return Ok(Some((best_key, operator.apply(value, operand))));
+            }
+            if value != Self::TOMBSTONE_VALUE {
+                return Ok(Some((best_key, value)));
+            }
+
+            //deleted, every source positioned on it moves to its next candidate
+            let next_key = match nearest {
+                Nearest::Ceiling => best_key.checked_add(1),
+                Nearest::Floor => best_key.checked_sub(1),
+            };
+            for (source, candidate) in candidates.iter_mut().enumerate() {
+                if candidate.is_some_and(|(candidate_key, ..)| candidate_key == best_key) {
+                    *candidate = match next_key {
+                        Some(next_key) => query(source, next_key)?,
+                        None => None,
+                    };
+                }
+            }
+        }
+    }
+    ///Entries with keys from <key1> to <key2> (inclusive), in key order. Empty once the database is closed, and panics
+    /// if a run can't be read, like get (see try_scan)
+    pub fn scan(&mut self, key1: Key, key2: Key) -> Vec<Entry> {
+        self.scan_without_yielding(key1, key2, ScanOptions::default(), &|_, _| true)
+    }
+    ///scan that fails with DbError::Closed once the database is closed, and with DbError::Io if a run can't be read
+    pub fn try_scan(&mut self, key1: Key, key2: Key) -> Result<Vec<Entry>, DbError> {
+        self.ensure_open()?;
+        let scanned = self.scan_with_predicate(
+            key1,
+            key2,
+            ScanOptions::default(),
+            &|_, _| true,
+            &mut YieldHook::default(),
+            true,
+        );
+        Ok(scanned?)
+    }
+    ///Estimated number of entries scan(<key1>, <key2>) returns, without running it. The memtable's live entries in the
+    /// range are counted exactly, B-tree runs exactly too (their inner nodes lead to the two pages holding the bounds,
+    /// the only pages read). Array runs are estimated from their fence index, interpolating between the first keys of
+    /// the pages the bounds are in, none of their pages are read. Runs with neither count in full.
+    /// NOTE: a key in several sources is counted once for each, and tombstones and expired entries in runs are counted
+    /// too, so past the interpolation error this is an upper bound (exact with the memtable empty and a single run)
+    pub fn estimate_scan_count(&mut self, key1: Key, key2: Key) -> Size {
+        if key1 > key2 {
+            return 0;
+        }
+        let mut estimate = self.memtable.count_live(key1, key2);
+
+        let background_levels = self.background_levels();
+        let _background_state = background_levels
+            .as_deref()
+            .map(|levels| self.sync_levels(levels));
+
+        let sst_implementation = self.sst_implementation();
+        let key_codec = self.key_codec();
+        let mut pool = self.buffer_pool.borrow_mut();
+        let mut buffer_pool = if self.config.enable_buffer_pool {
+            Some(&mut *pool)
+        } else {
+            None
+        };
+        let Metadata {
+            entry_counts,
+            tombstone_counts,
+            page_sizes,
+            ..
+        } = &self.metadata;
+        Self::for_each_sst(entry_counts, &mut |level, run| {
+            let num_entries = entry_counts[level][run];
+            //a run of only tombstones holds nothing a scan returns
+            if tombstone_counts[level][run] == num_entries {
+                return false;
+            }
+            let run_address = &(&self.name, level, run);
+            let page_size = page_sizes[level][run];
+            //a B-tree run of a single page has no inner nodes, its bounds would be searched for in its entries
+            let has_inner_nodes = matches!(sst_implementation, SstImplementation::Btree)
+                && cfg!(feature = "btree")
+                && num_entries > system_info::PageLayout::new(page_size).entries_per_page();
+            let count = if has_inner_nodes {
+                Self::run_sst_interface(sst_implementation, key_codec, page_size)
+                    .scan_bounds(
+                        run_address,
+                        (key1, key2),
+                        num_entries,
+                        buffer_pool.as_deref_mut(),
+                    )
+                    .map(|bounds| bounds.len())
+            } else if self.config.enable_fence_index && Self::has_host_pages(page_size) {
+                fence::estimate_count(
+                    run_address,
+                    (key1, key2),
+                    num_entries,
+                    key_codec,
+                    buffer_pool.as_deref_mut(),
+                )
+                .or_else(|why| match why.kind() {
+                    io::ErrorKind::NotFound => Ok(num_entries), //no fence index was written for the run
+                    _ => Err(why),
+                })
+            } else {
+                Ok(num_entries)
+            };
+            estimate += count.unwrap_or_else(|why| {
+                panic!("Failed to estimate the entries of level {level}, run {run} in [{key1}, {key2}], reason: {why}")
+            });
+            false
+        });
+        estimate
+    }
+    ///Iterator over the entries with keys in [<key1>, <key2>] as of this call. The range is read up front (like
+    /// scan), runs can be rewritten by a flush or compaction at any later put, so the iterator holds the whole range
+    /// instead of references into the memtable or run files
+    pub fn iter_range(&mut self, key1: Key, key2: Key) -> RangeIter {
+        RangeIter {
+            entries: self.scan(key1, key2).into_iter(),
+            truncation: self.truncation_watch(),
+            truncation_reported: false,
+        }
+    }
+    ///Sequence of the newest put or delete, see changes_since
+    pub fn last_sequence(&self) -> Sequence {
+        self.metadata.last_sequence
+    }
+    ///Newest state of every key written after <sequence>, in key order: its value (None if it was deleted or has
+    /// expired) and the sequence of the write. Runs whose newest write is at or before <sequence> aren't read.
+    /// NOTE: runs only record the sequence of their newest write, every entry of a run that also holds older writes is
+    /// returned with it, so applying the changes to a copy of the database as of <sequence> still gives our contents.
+    /// Deletes whose tombstones were discarded by a compaction of the last level (or by a flush into an empty database)
+    /// aren't returned
+    pub fn changes_since(
+        &mut self,
+        sequence: Sequence,
+    ) -> impl Iterator<Item = (Key, Option<Value>, Sequence)> {
+        let now = self.now();
+        //keys are resolved by their youngest source, None if that write isn't after <sequence>
+        let mut changes: BTreeMap<Key, Option<(Option<Value>, Sequence)>> = BTreeMap::new();
+        //changed keys whose youngest write is a merge operand, their value is looked up once every run is read
+        let mut operand_keys: Vec<Key> = Vec::new();
+        let memtable = &self.memtable;
+        for (key, hit) in memtable.scan_entries(Key::MIN, Key::MAX) {
+            let key_sequence = memtable.sequence(key).unwrap_or(0);
+            let change = (key_sequence > sequence).then(|| match hit {
+                MemtableHit::Live(value) if !expiry::is_expired(memtable.expiry(key), now) => {
+                    (Some(value), key_sequence)
+                }
+                _ => (None, key_sequence),
+            });
+            if change.is_some() && memtable.is_operand(key) {
+                operand_keys.push(key);
+            }
+            changes.insert(key, change);
+        }
+
+        {
+            let background_levels = self.background_levels();
+            let _background_state = background_levels
+                .as_deref()
+                .map(|levels| self.sync_levels(levels));
+
+            let key_codec = self.key_codec();
+            let mut pool = self.buffer_pool.borrow_mut();
+            let mut buffer_pool = if self.config.enable_buffer_pool {
+                Some(&mut *pool)
+            } else {
+                None
+            };
+            let entry_counts = &self.metadata.entry_counts;
+            let expiry_counts = &self.metadata.expiry_counts;
+            let operand_counts = &self.metadata.operand_counts;
+            let max_sequences = &self.metadata.max_sequences;
+            let mut callback = |level: Level, run: Run| {
+                let run_sequence = max_sequences[level][run];
+                if run_sequence <= sequence {
+                    return true; //runs are visited youngest first, the rest are older
+                }
+                let run_address = (&self.name, level, run);
+                let mut cursor =
+                    RunCursor::new(run_address, key_codec, 0..entry_counts[level][run], false);
+                if expiry_counts[level][run] > 0 {
+                    let expiries = expiry::read(&run_address, key_codec).unwrap_or_else(|why| panic!("Something went wrong trying to read expiries at level {level}, sst {run}, reason: {why}"));
+                    cursor = cursor.with_expiries(expiries, now);
+                }
+                if operand_counts[level][run] > 0 {
+                    let operands = operands::read(&run_address, key_codec).unwrap_or_else(|why| panic!("Something went wrong trying to read operands at level {level}, sst {run}, reason: {why}"));
+                    cursor = cursor.with_operands(operands);
+                }
+                while let Some((key, value)) = cursor.next(buffer_pool.as_deref_mut()).unwrap_or_else(|why| panic!("Something went wrong trying to read level {level}, sst {run}, reason: {why}")) {
+                    let value = (value != Self::TOMBSTONE_VALUE).then_some(value);
+                    if !changes.contains_key(&key) && cursor.is_operand(key) {
+                        operand_keys.push(key);
+                    }
+                    changes.entry(key).or_insert(Some((value, run_sequence)));
+                }
+                false
+            };
+            Self::for_each_sst(entry_counts, &mut callback);
+        }
+
+        //an operand's value depends on the writes under it, which may be older than <sequence>
+        for key in operand_keys {
+            let value = self.get(key);
+            if let Some(Some((change, _))) = changes.get_mut(&key) {
+                *change = value;
+            }
+        }
+        changes.into_iter().filter_map(|(key, change)| {
+            change.map(|(value, key_sequence)| (key, value, key_sequence))
+        })
+    }
+    ///Range scan that can run from key2 down to key1 and stop after <limit> entries. Runs are read one page at a
+    /// time as the merge reaches them, so a limited scan doesn't pay for the rest of the range. Panics if a run can't
+    /// be read, see try_scan_with_options.
+    /// NOTE: deleted keys don't count towards the limit
+    pub fn scan_with_options(&mut self, key1: Key, key2: Key, options: ScanOptions) -> Vec<Entry> {
+        self.scan_without_yielding(key1, key2, options, &|_, _| true)
+    }
+    ///scan_with_options that calls the yield hook every page worth of entries merged, fails with DbError::Aborted if
+    /// the hook aborts it and with DbError::Io if a run can't be read. The other scans don't call the hook
+    pub fn try_scan_with_options(
+        &mut self,
+        key1: Key,
+        key2: Key,
+        options: ScanOptions,
+    ) -> Result<Vec<Entry>, DbError> {
+        self.ensure_open()?;
+        let mut yield_hook = mem::take(&mut self.yield_hook);
+        let scanned =
+            self.scan_with_predicate(key1, key2, options, &|_, _| true, &mut yield_hook, true);
+        self.yield_hook = yield_hook;
+        Ok(scanned?)
+    }
+    ///Range scan that only returns entries the predicate accepts. The predicate is checked against the youngest
+    /// value of each key as runs are read, so rejected entries never reach the merge heap. Panics if a run can't be
+    /// read, see try_scan_filtered.
+    /// NOTE: a rejected young value still hides older values of the same key
+    pub fn scan_filtered(
+        &mut self,
+        key1: Key,
+        key2: Key,
+        predicate: impl Fn(Key, Value) -> bool,
+    ) -> Vec<Entry> {
+        self.scan_without_yielding(key1, key2, ScanOptions::default(), &predicate)
+    }
+    ///scan_filtered that fails with DbError::Closed once the database is closed, and with DbError::Io if a run can't
+    /// be read
+    pub fn try_scan_filtered(
+        &mut self,
+        key1: Key,
+        key2: Key,
+        predicate: impl Fn(Key, Value) -> bool,
+    ) -> Result<Vec<Entry>, DbError> {
+        self.ensure_open()?;
+        let options = ScanOptions::default();
+        let scanned = self.scan_with_predicate(
+            key1,
+            key2,
+            options,
+            &predicate,
+            &mut YieldHook::default(),
+            true,
+        );
+        Ok(scanned?)
+    }
+    ///Keys scan(<key1>, <key2>) returns, without their values. When a single run holds the range (and the memtable
+    /// none of it) only the keys of its pages are decoded, tombstones are told apart by their value bytes.
+    /// Panics if a run can't be read, see try_scan_keys
+    pub fn scan_keys(&mut self, key1: Key, key2: Key) -> Vec<Key> {
+        self.read_keys(key1, key2)
+            .unwrap_or_else(|why| panic!("Scan of ({key1} to {key2}) failed, reason: {why}"))
+    }
+    ///scan_keys that fails with DbError::Closed once the database is closed, and with DbError::Io if a run can't be
+    /// read
+    pub fn try_scan_keys(&mut self, key1: Key, key2: Key) -> Result<Vec<Key>, DbError> {
+        self.ensure_open()?;
+        Ok(self.read_keys(key1, key2)?)
+    }
+    fn read_keys(&mut self, key1: Key, key2: Key) -> io::Result<Vec<Key>> {
+        if let Some(keys) = self.scan_keys_of_single_run(key1, key2)? {
+            return Ok(keys);
+        }
+        let options = ScanOptions::default();
+        self.scan_with_predicate(
+            key1,
+            key2,
+            options,
+            &|_, _| true,
+            &mut YieldHook::default(),
+            true,
+        )
+    }
+    ///Keys in range of the database's only run, None if there's more than one run, or if the memtable holds keys in
+    /// range, or if the run has expiries or merge operands (whose values depend on more than their bytes) or was
+    /// written with another machine's page size
+    fn scan_keys_of_single_run(&mut self, key1: Key, key2: Key) -> io::Result<Option<Vec<Key>>> {
+        if key1 > key2 || !self.memtable.scan_entries(key1, key2).is_empty() {
+            return Ok(None);
+        }
+        let background_levels = self.background_levels();
+        let _background_state = background_levels
+            .as_deref()
+            .map(|levels| self.sync_levels(levels));
+
+        let Metadata {
+            entry_counts,
+            expiry_counts,
+            operand_counts,
+            page_sizes,
+            ..
+        } = &self.metadata;
+        let mut runs = entry_counts
+            .iter()
+            .enumerate()
+            .flat_map(|(level, counts)| (0..counts.len()).map(move |run| (level, run)));
+        let (Some((level, run)), None) = (runs.next(), runs.next()) else {
+            return Ok(None);
+        };
+        if expiry_counts[level][run] > 0
+            || operand_counts[level][run] > 0
+            || !Self::has_host_pages(page_sizes[level][run])
+        {
+            return Ok(None);
+        }
+        let mut pool = self.buffer_pool.borrow_mut();
+        let buffer_pool = if self.config.enable_buffer_pool {
+            Some(&mut *pool)
+        } else {
+            None
+        };
+        let sst = Self::run_sst_interface(
+            self.sst_implementation(),
+            self.key_codec(),
+            page_sizes[level][run],
+        );
+        let run_address = (&self.name, level, run);
+        let keys = sst
+            .scan_keys(&run_address, (key1, key2), entry_counts[level][run], buffer_pool)
+            .map_err(|why| io::Error::new(why.kind(), format!("Something went wrong trying to scan range ({key1} to {key2}) at level {level}, sst {run}, reason: {why}")))?;
+        Ok(Some(keys))
+    }
+    ///Range scan like scan that doesn't hold the background compactor off for the whole scan, so a long scan doesn't
+    /// delay compactions (and the space they free). The range is merged a chunk at a time and a chunk is only kept if
+    /// the runs it was read from are still listed once it's done. A chunk a compaction got in the way of (a run's file
+    /// is gone, or runs were replaced) is merged again from the runs listed now, starting after the largest key
+    /// already returned, so no key is missed or returned twice. Without a background compactor this is scan.
+    /// Panics if a run can't be read for any other reason, see try_scan_with_retry.
+    /// NOTE: runs are read around the buffer pool, it can hold pages of runs replaced under the scan
+    pub fn scan_with_retry(&mut self, key1: Key, key2: Key) -> Vec<Entry> {
+        self.scan_retrying(key1, key2, &mut YieldHook::default())
+            .map(|(entries, _)| entries)
+            .unwrap_or_else(|why| panic!("Scan of ({key1} to {key2}) failed, reason: {why}"))
+    }
+    ///scan_with_retry that fails with DbError::Closed once the database is closed, and with DbError::Io if a run
+    /// can't be read (for a reason other than a compaction replacing it)
+    pub fn try_scan_with_retry(&mut self, key1: Key, key2: Key) -> Result<Vec<Entry>, DbError> {
+        self.ensure_open()?;
+        let (entries, _) = self.scan_retrying(key1, key2, &mut YieldHook::default())?;
+        Ok(entries)
+    }
+    ///scan_with_retry, also returns how many chunks were merged again
+    fn scan_retrying(
+        &mut self,
+        key1: Key,
+        key2: Key,
+        yield_hook: &mut YieldHook,
+    ) -> io::Result<(Vec<Entry>, Size)> {
+        let Some(levels) = self.background_levels() else {
+            let options = ScanOptions::default();
+            let entries =
+                self.scan_with_predicate(key1, key2, options, &|_, _| true, yield_hook, true)?;
+            return Ok((entries, 0));
+        };
+        let chunk_len = Self::RETRY_SCAN_CHUNK_PAGES * system_info::num_entries_per_page();
+        let options = ScanOptions {
+            limit: Some(chunk_len),
+            reverse: false,
+        };
+        let run_listing = |db: &mut Self| {
+            let state = db.sync_levels(&levels);
+            (state.entry_counts.clone(), state.max_sequences.clone())
+        };
+        let mut results: Vec<Entry> = Vec::new();
+        let (mut retries, mut retries_in_a_row) = (0, 0);
+        let mut start = key1;
+        let mut listing = run_listing(self);
+        loop {
+            //a chunk merged again too many times holds the compactor off, so the scan can't starve
+            let hold_levels = retries_in_a_row >= Self::MAX_SCAN_RETRIES;
+            let chunk: io::Result<Vec<Entry>> = self.scan_with_predicate(
+                start,
+                key2,
+                options,
+                &|_, _| true,
+                yield_hook,
+                hold_levels,
+            );
+            let chunk_listing = run_listing(self);
+            let replaced = !hold_levels && chunk_listing != listing;
+            listing = chunk_listing;
+            let chunk = match chunk {
+                Err(why) if yield_hook::is_aborted(&why) => return Err(why),
+                _ if replaced => {
+                    retries += 1;
+                    retries_in_a_row += 1;
+                    continue;
+                }
+                chunk => chunk?,
+            };
+            retries_in_a_row = 0;
+            let is_last_chunk = chunk.len() < chunk_len;
+            results.extend(chunk);
+            match results.last() {
+                Some((last_key, _)) if !is_last_chunk && *last_key < key2 => start = last_key + 1,
+                _ => return Ok((results, retries)),
+            }
+        }
+    }
+    fn scan_without_yielding(
+        &mut self,
+        key1: Key,
+        key2: Key,
+        options: ScanOptions,
+        predicate: &dyn Fn(Key, Value) -> bool,
+    ) -> Vec<Entry> {
+        self.scan_with_predicate(
+            key1,
+            key2,
+            options,
+            predicate,
+            &mut YieldHook::default(),
+            true,
+        )
+        .unwrap_or_else(|why| panic!("Scan of ({key1} to {key2}) failed, reason: {why}"))
+    }
+    ///Merge of the memtable and the runs in range. With <hold_levels> unset the background compactor isn't kept from
+    /// replacing runs while they're read (nor is the buffer pool used, its pages of replaced runs would be stale), the
+    /// caller has to check that the runs it started from are still listed, see scan_with_retry
+    fn scan_with_predicate<T: ScanItem>(
+        &mut self,
+        key1: Key,
+        key2: Key,
+        options: ScanOptions,
+        predicate: &dyn Fn(Key, Value) -> bool,
+        yield_hook: &mut YieldHook,
+        hold_levels: bool,
+    ) -> io::Result<Vec<T>> {
+        if options.limit == Some(0) {
+            return Ok(Vec::new());
+        }
+        let now = self.now();
+        let memtable = &self.memtable;
+        let memtable_entries = if options.reverse {
+            memtable.scan_entries_rev(key1, key2)
+        } else {
+            memtable.scan_entries(key1, key2)
+        };
+        //deleted and expired entries go into the merge as tombstones, so they still hide older values
+        let memtable_entries = memtable_entries
+            .into_iter()
+            .map(|(key, hit)| match hit {
+                MemtableHit::Live(value) if !expiry::is_expired(memtable.expiry(key), now) => {
+                    ((key, value), memtable.is_operand(key))
+                }
+                _ => ((key, Self::TOMBSTONE_VALUE), false),
+            })
+            .collect();
+
+        let background_levels = self.background_levels();
+        let _background_state = background_levels
+            .as_deref()
+            .map(|levels| self.sync_levels(levels))
+            .filter(|_| hold_levels);
+
+        let sst_implementation = self.sst_implementation();
+        let search_algorithm = self.sst_search_algorithm();
+        let key_codec = self.key_codec();
+        let use_fences = self.config.enable_fence_index
+            && matches!(search_algorithm, SstSearchAlgorithm::Default);
+
+        let mut pool = self.buffer_pool.borrow_mut();
+        let mut buffer_pool = if self.config.enable_buffer_pool && hold_levels {
+            Some(&mut *pool)
+        } else {
+            None
+        };
+        //cursor over the entries in range for every sst (youngest to oldest), nothing past the bounds is read yet
+        let mut cursors = Vec::new();
+        let mut run_error = None;
+        let entry_counts = &self.metadata.entry_counts;
+        let expiry_counts = &self.metadata.expiry_counts;
+        let operand_counts = &self.metadata.operand_counts;
+        let page_sizes = &self.metadata.page_sizes;
+        let partitions = &self.metadata.partitions;
+        let mut callback = |level: Level, run: Run| {
+            let run_address = (&self.name, level, run);
+            let key_range = (key1, key2);
+            let level_partitions = partitions.get(level).map_or(&[][..], Vec::as_slice);
+            if partition::skips(level_partitions, run, key_range) {
+                return false; //a partition outside of the range
+            }
+            let sst =
+                Self::run_sst_interface(sst_implementation, key_codec, page_sizes[level][run]);
+            let fenced = use_fences && Self::has_host_pages(page_sizes[level][run]);
+            let bounds = match search_algorithm {
+                SstSearchAlgorithm::Default if fenced => fence::scan_bounds(
+                    &run_address,
+                    key_range,
+                    entry_counts[level][run],
+                    key_codec,
+                    buffer_pool.as_deref_mut(),
+                )
+                .or_else(|why| match why.kind() {
+                    io::ErrorKind::NotFound => sst.scan_bounds(
+                        &run_address,
+                        key_range,
+                        entry_counts[level][run],
+                        buffer_pool.as_deref_mut(),
+                    ),
+                    _ => Err(why),
+                }),
+                SstSearchAlgorithm::Default => sst.scan_bounds(
+                    &run_address,
+                    key_range,
+                    entry_counts[level][run],
+                    buffer_pool.as_deref_mut(),
+                ),
+                SstSearchAlgorithm::BinarySearch => sst.binary_search_scan_bounds(
+                    &run_address,
+                    key_range,
+                    entry_counts[level][run],
+                    buffer_pool.as_deref_mut(),
+                ),
+            };
+            let context = |what: &str, why: io::Error| {
+                io::Error::new(
+                    why.kind(),
+                    format!("Something went wrong trying to {what} at level {level}, sst {run}, reason: {why}"),
+                )
+            };
+            let bounds = match bounds {
+                Err(why) => {
+                    run_error = Some(context(&format!("scan range ({key1} to {key2})"), why));
+                    return true;
+                }
+                Ok(bounds) if bounds.is_empty() => return false,
+                Ok(bounds) => bounds,
+            };
+            let mut cursor = RunCursor::new(run_address, key_codec, bounds, options.reverse);
+            if expiry_counts[level][run] > 0 {
+                match expiry::read(&run_address, key_codec) {
+                    Ok(expiries) => {
+                        let expiries = expiries
+                            .into_iter()
+                            .filter(|(key, _)| key1 <= *key && *key <= key2)
+                            .collect();
+                        cursor = cursor.with_expiries(expiries, now);
+                    }
+                    Err(why) => {
+                        run_error = Some(context("read expiries", why));
+                        return true;
+                    }
+                }
+            }
+            if operand_counts[level][run] > 0 {
+                match operands::read(&run_address, key_codec) {
+                    Ok(operands) => {
+                        let operands = operands
+                            .into_iter()
+                            .filter(|key| key1 <= *key && *key <= key2)
+                            .collect();
+                        cursor = cursor.with_operands(operands);
+                    }
+                    Err(why) => {
+                        run_error = Some(context("read operands", why));
+                        return true;
+                    }
+                }
+            }
+            cursors.push(cursor);
+            false
+        };
+        Self::for_each_sst(&self.metadata.entry_counts, &mut callback);
+        if let Some(why) = run_error {
+            return Err(why);
+        }
+
+        Self::merge_scan_sources(
+            memtable_entries,
+            &mut cursors,
+            buffer_pool,
+            options,
+            predicate,
+            self.config.merge_operator,
+            yield_hook,
+        )
+    }
+    ///K-way merge of the memtable's entries and SST run cursors (youngest to oldest), in the order of the scan.
+    /// Only the youngest version of each key is looked at, older versions are skipped without decoding their values
+    /// (unless merge operands are waiting on them), and tombstones or entries rejected by the predicate never reach
+    /// the output. Merge operands (flagged in <memtable_entries>, listed by the cursors) are combined with the older
+    /// versions of their key until a value is found under them. Cursors are only pulled from while more results are
+    /// needed, so the merge stops reading as soon as the limit is reached. <yield_hook> is called every page worth of
+    /// entries merged
+    fn merge_scan_sources<T: ScanItem>(
+        memtable_entries: Vec<(Entry, bool)>,
+        cursors: &mut [RunCursor],
+        buffer_pool: Option<&mut BufferPool>,
+        options: ScanOptions,
+        predicate: &dyn Fn(Key, Value) -> bool,
+        operator: MergeOperator,
+        yield_hook: &mut YieldHook,
+    ) -> io::Result<Vec<T>> {
+        //source 0 is the memtable, source i is cursors[i - 1]
+        let num_sources = cursors.len() + 1;
+        let mut sources = ScanSources {
+            memtable_entries: memtable_entries.into_iter(),
+            memtable_value: Self::TOMBSTONE_VALUE,
+            cursors,
+            buffer_pool,
+        };
+        //bitwise not reverses the order of keys without overflowing, so reverse scans pop the largest key first
+        let scan_order = |key: Key| if options.reverse { !key } else { key };
+
+        //min heap of (key in scan order, source index, is operand), on ties the youngest source (lowest index) comes
+        // out first. Values are only decoded once their entry comes out and turns out to matter
+        let mut min_heap: BinaryHeap<Reverse<(Key, usize, bool)>> = BinaryHeap::new();
+        for source in 0..num_sources {
+            if let Some((key, is_operand)) = sources.pull(source)? {
+                min_heap.push(Reverse((scan_order(key), source, is_operand)));
+            }
+        }
+
+        let mut results = Vec::new();
+        let mut recent_key: Option<Key> = None;
+        //operands of the recent key combined so far (youngest first), waiting for the value under them
+        let mut pending_operand: Option<Value> = None;
+        let entries_per_page = system_info::num_entries_per_page();
+        let mut num_merged: Size = 0;
+        loop {
+            num_merged += 1;
+            if num_merged.is_multiple_of(entries_per_page) {
+                yield_hook.call()?;
+            }
+            let next = min_heap.pop();
+            let next_key = next.map(|Reverse((ordered_key, ..))| scan_order(ordered_key));
+            //older versions of a resolved key are skipped without decoding, unless operands are waiting on them
+            let next_value = match next {
+                Some(Reverse((_, source, _)))
+                    if next_key != recent_key || pending_operand.is_some() =>
+                {
+                    Some(sources.value(source))
+                }
+                _ => None,
+            };
+            //the value under the pending operands was found, or none is left (they're applied to the identity)
+            let value_under = match next {
+                Some(Reverse((.., false))) if next_key == recent_key => next_value,
+                _ if next_key != recent_key => Some(Self::TOMBSTONE_VALUE),
+                _ => None,
+            };
+            if let (Some(operand), Some(value)) = (pending_operand, value_under) {
+                pending_operand = None;
+                let value = match value {
+                    Self::TOMBSTONE_VALUE => operator.identity(),
+                    value => value,
+                };
+                let key = recent_key.expect("Operands belong to the recent key");
+                let value = operator.apply(value, operand);
+                if predicate(key, value) {
+                    results.push(T::from_entry(key, value));
+                    if options.limit == Some(results.len()) {
+                        break;
+                    }
+                }
+            }
+
+            let Some(Reverse((ordered_key, source, is_operand))) = next else {
+                break;
+            };
+            if let Some((next_key, next_is_operand)) = sources.pull(source)? {
+                min_heap.push(Reverse((scan_order(next_key), source, next_is_operand)));
+            }
+
+            let key = scan_order(ordered_key);
+            if recent_key == Some(key) {
+                if let (Some(operand), true) = (pending_operand, is_operand) {
+                    let value = next_value.expect("Values are decoded while operands are pending");
+                    pending_operand = Some(operator.apply(value, operand));
+                }
+                continue; //older version of a key we've already resolved
+            }
+            recent_key = Some(key);
+            let value = next_value.expect("Values of newly resolved keys are decoded");
+            if is_operand {
+                pending_operand = Some(value);
+                continue;
+            }
+            //predicate is checked after recency is resolved, so a rejected young value still hides older ones
+            if value != Self::TOMBSTONE_VALUE && predicate(key, value) {
+                results.push(T::from_entry(key, value));
+                if options.limit == Some(results.len()) {
+                    break;
+                }
+            }
+        }
+        Ok(results)
+    }
+}
+
+///The database compacting its own levels, on the thread that flushed
+impl Levels for Database {
+    fn compaction_policy(&self) -> CompactionPolicy {
+        self.config.compaction_policy
+    }
+    fn strategy_settings(&self) -> StrategySettings {
+        StrategySettings {
+            size_ratio: self.config.sst_size_ratio,
+            memtable_capacity: self.config.memtable_capacity,
+        }
+    }
+    fn with_level_runs<T>(&self, level: Level, f: impl FnOnce(&LevelRuns) -> T) -> T {
+        f(&self.level_runs(level))
+    }
+    fn move_level_runs(&mut self, level: Level) -> io::Result<bool> {
+        let next_level = level + 1;
+        assert!(
+            self.metadata.entry_counts.get(level).is_some(),
+            "Level {level} does not exist, cannot flush"
+        );
+        let num_runs = self.sst_count(level);
+        if num_runs == 0 {
+            return Ok(false); //Nothing to flush to the next level
+        }
+        let num_runs_in_next_level = self
+            .metadata
+            .entry_counts
+            .get(next_level)
+            .map_or(0, Vec::len);
+
+        //Step 1: Move each run and associated files to next level with new run number
+        let mut pool = self.buffer_pool.borrow_mut();
+        let enable_buffer_pool = self.config.enable_buffer_pool;
+        cascade::move_run_files(
+            &self.name,
+            level,
+            num_runs,
+            num_runs_in_next_level,
+            self.config.bloom_levels,
+            &mut |change| {
+                if enable_buffer_pool {
+                    Self::apply_file_change(&mut pool, change);
+                }
+            },
+        )?;
+        drop(pool);
+
+        //Step 2: move metadata to next level
+        self.metadata.run_lists().move_runs(level);
+        //partitions stay partitions in an empty level, otherwise they're incoming runs after the next level's own
+        let curr_level_partitions = self.metadata.level_partitions(level).to_vec();
+        self.metadata.set_level_partitions(level, vec![]);
+        if num_runs_in_next_level == 0 {
+            self.metadata
+                .set_level_partitions(next_level, curr_level_partitions);
+        }
+        self.metadata_dirty = true;
+        self.level_shape.move_runs(level);
+        self.check_level_shape();
+        self.report_event(DbEvent::RunsMoved {
+            from_level: level,
+            to_level: next_level,
+            count: num_runs,
+        });
+        Ok(true)
+    }
+    fn compact_level_runs(&mut self, level: Level, discard_tombstones: bool) -> io::Result<()> {
+        //a failed merge leaves the level's runs in place
+        match self.is_partitioned() {
+            true => self.compact_partitions(level, discard_tombstones)?,
+            false => self.compact_level(level, discard_tombstones)?,
+        };
+        self.compaction_error = None;
+        Ok(())
+    }
+}
+
+///Runs on destruction, closes DB automatically. A failed close is reported to the event listener
+/// (DbEvent::CloseFailed) rather than panicking, which would abort the process while unwinding from another panic
+impl Drop for Database {
+    fn drop(&mut self) {
+        if let Err(why) = self.try_close() {
+            let kind = match &why {
+                DbError::Io(why) => why.kind(),
+                _ => io::ErrorKind::Other,
+            };
+            self.report_event(DbEvent::CloseFailed { kind });
+            self.invalidate();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use crate::compaction::plan;
+    use crate::file_io::direct_io;
+    use crate::import::DuplicateKeys;
+    use crate::sst::export;
+    use crate::stats::{self, Record};
+    #[cfg(feature = "btree")]
+    use crate::util::btree_info::fanout;
+    use crate::util::clock::ManualClock;
+    use crate::util::testing;
+
+    use super::*;
+
+    ///Run <test> against a database in memory
+    fn setup_and_test_and_cleaup(
+        test_name: impl AsRef<Path>,
+        database_alterations: &mut dyn FnMut(Database) -> Database,
+        test: &mut dyn FnMut(Database) -> Database,
+    ) {
+        setup_and_test_and_cleaup_with(
+            DatabaseOptions::in_memory(),
+            test_name,
+            database_alterations,
+            test,
+        );
+    }
+
+    ///setup_and_test_and_cleaup for tests that look at (or break) the database's files on disk
+    fn setup_and_test_and_cleaup_on_disk(
+        test_name: impl AsRef<Path>,
+        database_alterations: &mut dyn FnMut(Database) -> Database,
+        test: &mut dyn FnMut(Database) -> Database,
+    ) {
+        setup_and_test_and_cleaup_with(
+            DatabaseOptions::default(),
+            test_name,
+            database_alterations,
+            test,
+        );
+    }
+
+    ///Run <test> against a database in memory, then against one on disk, for the tests of the basics that every
+    /// storage has to pass
+    fn setup_and_test_and_cleaup_on_both(
+        test_name: impl AsRef<Path>,
+        database_alterations: &mut dyn FnMut(Database) -> Database,
+        test: &mut dyn FnMut(Database) -> Database,
+    ) {
+        let test_dir = testing::test_dir(test_name);
+        for options in [DatabaseOptions::in_memory(), DatabaseOptions::default()] {
+            setup_and_test_and_cleaup_with(options, test_dir, database_alterations, test);
+        }
+    }
+
+    fn setup_and_test_and_cleaup_with(
+        options: DatabaseOptions,
+        test_name: impl AsRef<Path>,
+        database_alterations: &mut dyn FnMut(Database) -> Database,
+        test: &mut dyn FnMut(Database) -> Database,
+    ) {
+        let test_dir = testing::test_dir(test_name);
+        let db_name = test_dir.join("test");
+        let storage = options.storage.clone();
+        if storage.path_exists(test_dir) {
+            storage.remove_dir_all(test_dir).unwrap(); //remove previous directory if panicked during tests and didn't clean up
+        }
+        storage.create_dir_all(test_dir).unwrap();
+
+        let mut db = test(database_alterations(Database::open_with(&db_name, options)));
+
+        db.close();
+
+        storage.remove_dir_all(test_dir).unwrap();
+    }
+
+    ///Open (or create) <name> in <storage>, e.g. to reopen a test's database in the storage it was opened in
+    fn open_in(storage: &Storage, name: impl AsRef<Path>) -> Database {
+        Database::open_with(
+            name,
+            DatabaseOptions {
+                storage: storage.clone(),
+            },
+        )
+    }
+
+    fn try_open_in(storage: &Storage, name: impl AsRef<Path>) -> Result<Database, DbError> {
+        Database::try_open_with(
+            name,
+            DatabaseOptions {
+                storage: storage.clone(),
+            },
+        )
+    }
+
+    fn small_db_test(test_name: &str, database_alterations: &mut dyn FnMut(Database) -> Database) {
+        let mut alterations = |db: Database| {
+            database_alterations(db).set_memtable_capacity(2) //memtable capacity needed for tests below
+        };
+        let mut test = |db: Database| {
+            let mut db = db.set_paranoid_reads(true); //small enough to check every get against full reads of the runs
+
+            //Test puts, and check if younger values are used instead of older values in gets
+            db.put(0, 1).unwrap(); //This value should be immediately replaced
+            assert_eq!(db.get(0), Some(1));
+            db.put(0, 2).unwrap();
+            assert_eq!(db.get(0), Some(2)); //This value should be replaced after flushing
+            db.put(10, 100).unwrap();
+            db.put(20, 200).unwrap();
+            db.put(0, 3).unwrap(); //original value of 0, should have been flushed
+            assert_eq!(db.get(0), Some(3)); //case: memtable "blocking" sst search
+            db.put(30, 300).unwrap();
+            db.put(40, 400).unwrap();
+            assert_eq!(db.get(0), Some(3)); //case: sst blocking older sst search
+            db.put(0, 10).unwrap();
+
+            if db.compaction_policy() == CompactionPolicy::None {
+                //each put into a full memtable flushed its 2 entries first, overwriting 0 while it was still in the
+                // memtable took no room: runs {0, 10}, {20, 0} and {30, 40}, the last put is in the memtable
+                assert_eq!(db.sst_count(0), 3);
+            }
+
+            //Test gets
+            assert_eq!(db.get(0), Some(10));
+            assert_eq!(db.get(20), Some(200));
+            assert_eq!(db.get(10), Some(100));
+            assert_eq!(db.get(40), Some(400));
+            assert_eq!(db.get(30), Some(300));
+            assert_eq!(db.get(-1), None);
+            assert_eq!(db.get(1), None);
+            assert_eq!(db.get(300), None);
+            assert_eq!(db.get(400), None);
+
+            //test scans
+            assert_eq!(db.scan(1, 19), vec![(10, 100)]);
+            assert_eq!(db.scan(0, 19), vec![(0, 10), (10, 100)]);
+            assert_eq!(db.scan(1, 20), vec![(10, 100), (20, 200)]);
+            assert_eq!(db.scan(0, 20), vec![(0, 10), (10, 100), (20, 200)]);
+            assert_eq!(
+                db.scan(-1, 9999),
+                vec![(0, 10), (10, 100), (20, 200), (30, 300), (40, 400)]
+            );
+
+            //Test deletes
+            db.delete(30).unwrap();
+            db.delete(20).unwrap();
+            assert_eq!(db.get(30), None);
+            assert_eq!(db.get(20), None);
+            assert_eq!(db.get(0), Some(10));
+            assert_eq!(db.get(10), Some(100));
+            assert_eq!(db.get(40), Some(400));
+
+            //Test how deletes affect scan
+            assert_eq!(db.scan(1, 19), vec![(10, 100)]);
+            assert_eq!(db.scan(0, 19), vec![(0, 10), (10, 100)]);
+            assert_eq!(db.scan(1, 20), vec![(10, 100)]);
+            assert_eq!(db.scan(0, 20), vec![(0, 10), (10, 100)]);
+            assert_eq!(db.scan(-1, 9999), vec![(0, 10), (10, 100), (40, 400)]);
+
+            db
+        };
+        setup_and_test_and_cleaup_on_both(test_name, &mut alterations, &mut test)
+    }
+
+    fn large_db_test(test_name: &str, database_alterations: &mut dyn FnMut(Database) -> Database) {
+        let memtable_cap = 896; //3.5 pages (4096 bytes * 3.5/16)
+
+        let mut alterations = |db: Database| {
+            database_alterations(db).set_memtable_capacity(memtable_cap) //memtable capacity needed for tests below
+        };
+        let mut test = |mut db: Database| {
+            let (min, max) = (-1000, 1000);
+            let range = min..max + 1; //3.5 pages of
+            let mut entries = Vec::<Entry>::new();
+
+            //Test puts
+            for i in range.into_iter() {
+                let entry = (i, i * 10);
+                db.put(entry.0, entry.1).unwrap();
+                entries.push(entry);
+            }
+
+            if db.compaction_policy() == CompactionPolicy::None {
+                //a run of exactly memtable_cap entries for every full memtable, the remainder is still in the memtable
+                assert_eq!(db.sst_count(0), entries.len() / memtable_cap);
+            }
+            //Test gets
+            for (key, value) in entries.iter() {
+                assert_eq!(db.get(*key), Some(*value), "key: {key}");
+            }
+
+            //Test getting values that are not in the db
+            assert_eq!(db.get(min - 1000), None);
+            assert_eq!(db.get(max + 1000), None);
+
+            //Test scans
+            //scan from [..i+1]
+            for i in 0..entries.len() {
+                let slice = entries[..i + 1].to_vec();
+                let scan = db.scan(entries.first().unwrap().0, entries[i].0);
+                assert_eq!(
+                    scan.len(),
+                    slice.len(),
+                    "scan range ({}, {}), scan results {:?}",
+                    entries.first().unwrap().0,
+                    entries[i].0,
+                    scan
+                );
+                assert_eq!(scan, slice);
+            }
+
+            //scan from [i..]
+            for i in 0..entries.len() {
+                let slice = entries[i..].to_vec();
+                let scan = db.scan(entries[i].0, entries.last().unwrap().0);
+                assert_eq!(scan.len(), slice.len());
+                assert_eq!(scan, slice);
+            }
+
+            //scan from [i..j+1]
+            for i in (0..entries.len()).step_by(57) {
+                for j in (i..entries.len() - 1).step_by(37) {
+                    let slice = entries[i..j + 1].to_vec();
+                    let scan = db.scan(entries[i].0, entries[j].0);
+                    assert_eq!(scan.len(), slice.len());
+                    assert_eq!(scan, slice);
+                }
+            }
+
+            //Test scans that include keys outside of min and max of db
+            assert_eq!(db.scan(min - 1000, max + 1000), entries);
+
+            //Test overwriting existing keys
+            let step = 3;
+            for (key, value) in entries.iter().step_by(step) {
+                db.put(*key, value * 2).unwrap();
+            }
+            for (i, (key, value)) in entries.iter().enumerate() {
+                if i % step == 0 {
+                    assert_eq!(db.get(*key), Some(value * 2));
+                } else {
+                    assert_eq!(db.get(*key), Some(*value));
+                }
+            }
+
+            //Test deletes
+            let delete_step = 3; //NOTE: if this value is different from <step>, you'll need a new vec to hold onto the new db values that the prev test changed
+            for (key, _) in entries.iter().step_by(delete_step) {
+                db.delete(*key).unwrap();
+                assert_eq!(db.get(*key), None);
+            }
+
+            //Test how deletes affect scan
+            let filtered: Vec<Entry> = entries
+                .iter()
+                .enumerate()
+                .filter(|&(i, _)| i % delete_step != 0)
+                .map(|(_, v)| v.to_owned())
+                .collect(); //entries without every <delete_step>th element
+            let slice = &filtered;
+            let scan = db.scan(entries.first().unwrap().0, entries.last().unwrap().0);
+            assert_eq!(scan.len(), slice.len());
+            assert_eq!(scan, slice.to_vec());
+
+            db
+        };
+
+        setup_and_test_and_cleaup(test_name, &mut alterations, &mut test)
+    }
+
+    #[cfg(feature = "btree")]
+    fn multi_page_btree_single_sst_db_test(
+        test_name: &str,
+        database_alterations: &mut dyn FnMut(Database) -> Database,
+    ) {
+        let memtable_cap = fanout().pow(2) + 1; //Btrees will have 3 inner nodes and root
+
+        let mut alterations = |db: Database| {
+            database_alterations(db).set_memtable_capacity(memtable_cap) //memtable capacity needed for tests below
+        };
+        let mut test = |mut db: Database| {
+            let (min, max) = (0, memtable_cap as i64);
+            let range = min..max + 1; //1 extra to force a flush to sst
+            let mut entries = Vec::<Entry>::new();
+
+            //Test puts
+            for i in range.into_iter() {
+                let entry = (i, i * 10);
+                db.put(entry.0, entry.1).unwrap();
+                entries.push(entry);
+            }
+
+            assert_eq!(db.sst_count(0), 1); //confirm that we have a single sst
+
+            //Test getting values that are not in the db
+            assert_eq!(db.get(min - 1000), None);
+            assert_eq!(db.get(max + 1000), None);
+
+            //Test gets
+            for (key, value) in entries.iter() {
+                assert_eq!(db.get(*key), Some(*value), "key: {key}");
+            }
+
+            //check that scan of db returns all entries
+            let scan_range = (entries.first().unwrap().0, entries.last().unwrap().0);
+            let scan = db.scan(scan_range.0, scan_range.1);
+            assert_eq!(
+                scan.len(),
+                entries.len(),
+                "scan range ({}, {})",
+                scan_range.0,
+                scan_range.1
+            );
+            assert_eq!(scan, entries);
+
+            //Test scans that include keys outside of min and max of db
+            assert_eq!(db.scan(min - 1000, max + 1000), entries);
+
+            //Test overwriting existing keys
+            let step = 3;
+            for (key, value) in entries.iter().step_by(step) {
+                db.put(*key, value * 2).unwrap();
+            }
+            for (i, (key, value)) in entries.iter().enumerate() {
+                if i % step == 0 {
+                    assert_eq!(db.get(*key), Some(value * 2));
+                } else {
+                    assert_eq!(db.get(*key), Some(*value));
+                }
+            }
+
+            //Test deletes
+            let delete_step = step; //NOTE: if this value is different from <step>, you'll need a new vec to hold onto the new db values that the prev test changed
+            for (key, _) in entries.iter().step_by(delete_step) {
+                db.delete(*key).unwrap();
+                assert_eq!(db.get(*key), None);
+            }
+
+            //Test how deletes affect scan
+            let filtered: Vec<Entry> = entries
+                .iter()
+                .enumerate()
+                .filter(|&(i, _)| i % delete_step != 0)
+                .map(|(_, v)| v.to_owned())
+                .collect(); //entries without every <delete_step>th element
+            let slice = &filtered;
+            let scan = db.scan(entries.first().unwrap().0, entries.last().unwrap().0);
+            assert_eq!(scan.len(), slice.len());
+            assert_eq!(scan, slice.to_vec());
+
+            db
+        };
+
+        setup_and_test_and_cleaup(test_name, &mut alterations, &mut test)
+    }
+
+    #[test]
+    fn part_1_test_small() {
+        small_db_test("part1_small_db_test", &mut testing::part1_db_alterations);
+    }
+
+    #[test]
+    fn part1_test_large() {
+        large_db_test("part1_large_db_test", &mut testing::part1_db_alterations);
+    }
+
+    #[cfg(feature = "btree")]
+    #[test]
+    #[ignore = "Test case redundant with large test"]
+    fn part1_test_multi_page_btree_single_sst() {
+        multi_page_btree_single_sst_db_test(
+            "part1_multi_page_btree_single_sst_db_test",
+            &mut testing::part1_db_alterations,
+        );
+    }
+
+    #[test]
+    fn part_2_test_small() {
+        small_db_test("part2_small_db_test", &mut testing::part2_db_alterations);
+    }
+
+    #[test]
+    fn part2_test_large() {
+        large_db_test("part2_large_db_test", &mut testing::part2_db_alterations);
+    }
+
+    #[cfg(feature = "btree")]
+    #[test]
+    fn part2_test_multi_page_btree_single_sst() {
+        multi_page_btree_single_sst_db_test(
+            "part2_multi_page_btree_single_sst_db_test",
+            &mut testing::part2_db_alterations,
+        );
+    }
+
+    #[test]
+    fn part_3_test_small() {
+        small_db_test("part3_small_db_test", &mut testing::part3_db_alterations);
+    }
+
+    #[test]
+    fn part3_test_large() {
+        large_db_test("part3_large_db_test", &mut testing::part3_db_alterations);
+    }
+
+    #[cfg(feature = "btree")]
+    #[test]
+    #[ignore = "Test case redundant with large test"]
+    fn part3_test_multi_page_btree_single_sst() {
+        multi_page_btree_single_sst_db_test(
+            "part3_multi_page_btree_single_sst_db_test",
+            &mut testing::part3_db_alterations,
+        );
+    }
+
+    ///Buffer pool capacities (in bytes) smaller than an operation's working set: 1 byte caches nothing,
+    /// 1 page holds a single frame. They must only make reads slower, never wrong
+    fn tiny_buffer_pool_capacities() -> [Size; 3] {
+        [1, system_info::page_size(), 2 * system_info::page_size()]
+    }
+
+    fn tiny_buffer_pool_alterations(
+        database_alterations: fn(Database) -> Database,
+        capacity: Size,
+    ) -> impl FnMut(Database) -> Database {
+        move |db| {
+            database_alterations(db)
+                .set_enable_buffer_pool(true)
+                .set_buffer_pool_capacity(capacity)
+        }
+    }
+
+    #[test]
+    fn tiny_buffer_pool_test_small() {
+        for capacity in tiny_buffer_pool_capacities() {
+            for (part, alterations) in [
+                (2, testing::part2_db_alterations as fn(Database) -> Database),
+                (3, testing::part3_db_alterations),
+            ] {
+                small_db_test(
+                    &format!("tiny_buffer_pool_{capacity}_part{part}_small_db_test"),
+                    &mut tiny_buffer_pool_alterations(alterations, capacity),
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn tiny_buffer_pool_test_large() {
+        let capacity = system_info::page_size(); //every fetch evicts the previous page, the slowest case
+        for (part, alterations) in [
+            (2, testing::part2_db_alterations as fn(Database) -> Database),
+            (3, testing::part3_db_alterations),
+        ] {
+            large_db_test(
+                &format!("tiny_buffer_pool_part{part}_large_db_test"),
+                &mut tiny_buffer_pool_alterations(alterations, capacity),
+            );
+        }
+    }
+
+    #[cfg(feature = "btree")]
+    #[test]
+    fn tiny_buffer_pool_btree_depth_test() {
+        let memtable_cap = fanout().pow(2) + 1; //root, inner and leaf pages on every get
+        for capacity in tiny_buffer_pool_capacities() {
+            let mut alterations = |db: Database| {
+                tiny_buffer_pool_alterations(testing::part2_db_alterations, capacity)(db)
+                    .set_memtable_capacity(memtable_cap)
+            };
+            let mut test = |mut db: Database| {
+                let entries: Vec<Entry> = (0..memtable_cap as Key)
+                    .map(|key| (key, key * 10))
+                    .collect();
+                for (key, value) in &entries {
+                    db.put(*key, *value).unwrap();
+                }
+                db.flush_memtable().unwrap();
+                assert_eq!(db.sst_count(0), 1);
+
+                for (key, value) in entries.iter().step_by(97) {
+                    assert_eq!(db.get(*key), Some(*value), "key: {key}");
+                    assert_eq!(db.get(*key), Some(*value), "key: {key}"); //served from whatever is cached now
+                }
+                assert_eq!(db.get(-1), None);
+                assert_eq!(db.get(memtable_cap as Key), None);
+                assert_eq!(db.scan(0, memtable_cap as Key), entries);
+                assert!(db.buffer_pool_stats().bytes <= capacity);
+                db
+            };
+            setup_and_test_and_cleaup(
+                format!("tiny_buffer_pool_{capacity}_btree_depth_test"),
+                &mut alterations,
+                &mut test,
+            );
+        }
+    }
+
+    #[test]
+    fn order_preserving_key_codec_test_small() {
+        small_db_test("order_preserving_small_db_test", &mut |db| {
+            testing::part3_db_alterations(db).set_key_codec(KeyCodec::OrderPreserving)
+        });
+    }
+
+    #[test]
+    fn order_preserving_key_codec_test_large() {
+        large_db_test("order_preserving_large_db_test", &mut |db| {
+            testing::part3_db_alterations(db).set_key_codec(KeyCodec::OrderPreserving)
+        });
+    }
+
+    #[test]
+    fn background_compaction_test_small() {
+        small_db_test("background_compaction_small_db_test", &mut |db| {
+            testing::part3_db_alterations(db).set_background_compaction(true)
+        });
+    }
+
+    #[test]
+    fn background_compaction_test_large() {
+        large_db_test("background_compaction_large_db_test", &mut |db| {
+            testing::part3_db_alterations(db).set_background_compaction(true)
+        });
+    }
+
+    #[test]
+    fn background_compaction_stress_test() {
+        use rand::{Rng, SeedableRng};
+
+        let mut alterations = |db: Database| {
+            testing::part3_db_alterations(db)
+                .set_compaction_policy(CompactionPolicy::Leveled)
+                .set_memtable_capacity(256)
+                .set_buffer_pool_capacity_pages(64)
+                .set_background_compaction(true)
+        };
+        let mut test = |mut db: Database| {
+            let num_puts: Key = 100_000;
+            let mut rng = rand::rngs::StdRng::seed_from_u64(0);
+            for key in 0..num_puts {
+                db.put(key, -key).unwrap();
+
+                //every key we've put so far must be readable while compaction runs in the background
+                let read_key = rng.gen_range(0..=key);
+                assert_eq!(db.get(read_key), Some(-read_key), "key {read_key} missing");
+            }
+            let name = db.name();
+            db.close();
+
+            //close should have finished every queued compaction, all keys should have been moved out of level 0
+            let mut db = open_in(db.storage(), &name);
+            assert!(db.background_compaction());
+            assert_eq!(db.sst_count(0), 0);
+            for key in 0..num_puts {
+                assert_eq!(db.get(key), Some(-key));
+            }
+            db
+        };
+        setup_and_test_and_cleaup(
+            "background_compaction_stress_test",
+            &mut alterations,
+            &mut test,
+        );
+    }
+
+    #[test]
+    fn background_compaction_error_test() {
+        let mut alterations = |db: Database| {
+            testing::part3_db_alterations(db)
+                .set_memtable_capacity(4)
+                .set_background_compaction(true)
+        };
+        let mut test = |mut db: Database| {
+            for key in 0..32 {
+                db.put(key, key).unwrap();
+            }
+            db.background_compactor.as_ref().unwrap().drain();
+            let levels = db.background_levels().unwrap();
+            levels.lock().unwrap().error = Some("disk full".to_string());
+
+            //the levels are as the last compaction that succeeded left them, reads go on
+            assert_eq!(db.get(7), Some(7));
+            assert_eq!(db.scan(0, 31).len(), 32);
+            let why = db.compaction_error().unwrap();
+            assert!(why.to_string().contains("disk full"), "{why}");
+            //flushes fail instead of piling runs up in level 0, the full memtable keeps its entries
+            let why = db.put(100, 100).unwrap_err();
+            assert!(matches!(why, DbError::Io(ref why) if why.to_string().contains("disk full")));
+            assert_eq!(db.get(31), Some(31));
+            assert_eq!(db.get(100), None);
+
+            levels.lock().unwrap().error = None;
+            db.compaction_error = None;
+            db.put(100, 100).unwrap();
+            db
+        };
+        setup_and_test_and_cleaup(
+            "background_compaction_error_test",
+            &mut alterations,
+            &mut test,
+        );
+    }
+
+    #[test]
+    fn partitioned_background_compaction_rejected_test() {
+        let db_name = testing::test_dir("partitioned_background_compaction_rejected_test");
+        let mut db = Database::open_in_memory(db_name.join("0")).set_background_compaction(true);
+        let why = db.try_set_partitioned_levels(true).unwrap_err();
+        assert!(matches!(why, DbError::InvalidConfig { .. }), "{why}");
+        assert!(!db.partitioned_levels());
+        let mut db = Database::open_in_memory(db_name.join("1")).set_partitioned_levels(true);
+        let why = db.try_set_background_compaction(true).unwrap_err();
+        assert!(matches!(why, DbError::InvalidConfig { .. }), "{why}");
+        assert!(!db.background_compaction());
+        db.put(1, 1).unwrap(); //still open
+    }
+
+    #[test]
+    fn scan_filtered_test() {
+        let mut test = |mut db: Database| {
+            let is_even = |_: Key, value: Value| value % 2 == 0;
+            //old values pass the predicate, every key ends up in a different sst than its overwrite
+            for key in 0..8 {
+                db.put(key, key * 2).unwrap();
+            }
+            db.put(0, 1).unwrap(); //young value fails, old value passes: key must be hidden
+            db.put(1, 3).unwrap();
+            db.put(2, 4).unwrap(); //young value passes
+            db.delete(3).unwrap(); //tombstone hides an old passing value
+            db.put(4, 5).unwrap(); //young value in memtable fails
+            db.put(5, 7).unwrap();
+            assert_eq!(
+                db.scan_filtered(0, 7, is_even),
+                vec![(2, 4), (6, 12), (7, 14)]
+            );
+            assert_eq!(db.scan_filtered(0, 7, |_, _| false), vec![]);
+            assert_eq!(db.scan_filtered(0, 7, |_, _| true), db.scan(0, 7));
+
+            //old value fails the predicate, younger value passes
+            db.put(0, 100).unwrap();
+            assert_eq!(db.scan_filtered(0, 1, is_even), vec![(0, 100)]);
+            assert_eq!(
+                db.scan_filtered(0, 7, |key, _| key > 5),
+                vec![(6, 12), (7, 14)]
+            );
+            db
+        };
+        for (test_name, alterations) in [
+            (
+                "scan_filtered_part1_test",
+                testing::part1_db_alterations as fn(Database) -> Database,
+            ),
+            ("scan_filtered_part2_test", testing::part2_db_alterations),
+            ("scan_filtered_part3_test", testing::part3_db_alterations),
+        ] {
+            setup_and_test_and_cleaup(
+                test_name,
+                &mut |db| {
+                    alterations(db)
+                        .set_compaction_policy(CompactionPolicy::None)
+                        .set_memtable_capacity(2)
+                },
+                &mut test,
+            );
+        }
+    }
+
+    #[test]
+    fn legacy_buffer_pool_capacity_config_test() {
+        let test_dir = testing::test_dir("legacy_buffer_pool_capacity_config_test");
+        let db_name = test_dir.join("test");
+        if test_dir.exists() {
+            std::fs::remove_dir_all(test_dir).unwrap();
+        }
+        std::fs::create_dir_all(&db_name).unwrap();
+
+        //unversioned config from when buffer pool capacity was a number of pages
+        let legacy_pages: Size = 3;
+        let legacy_config = LegacyConfig {
+            memtable_capacity: 7,
+            sst_size_ratio: 2,
+            sst_implementation: SstImplementation::Btree,
+            sst_search_algorithm: SstSearchAlgorithm::Default,
+            enable_buffer_pool: true,
+            buffer_pool_capacity: legacy_pages,
+            buffer_pool_initial_size: 4,
+            compaction_policy: CompactionPolicy::None,
+            enable_bloom_filter: true,
+            bloom_filter_bits_per_entry: 5,
+        };
+        std::fs::write(
+            filename::config(&db_name),
+            serde_state::serialize(&legacy_config),
+        )
+        .unwrap();
+        std::fs::write(
+            filename::metadata(&db_name),
+            serde_state::serialize(&Metadata::new()),
+        )
+        .unwrap();
+
+        let mut db = Database::open(&db_name);
+        assert_eq!(db.memtable_capacity(), 7);
+        assert_eq!(
+            db.buffer_pool_capacity(),
+            legacy_pages * system_info::page_size()
+        );
+        assert_eq!(
+            db.buffer_pool_stats().capacity_bytes,
+            db.buffer_pool_capacity()
+        );
+        db.close();
+
+        //the migrated config is written back in the current layout
+        let db = Database::open(&db_name);
+        assert_eq!(
+            db.buffer_pool_capacity(),
+            legacy_pages * system_info::page_size()
+        );
+        drop(db);
+        std::fs::remove_dir_all(test_dir).unwrap();
+    }
+
+    #[test]
+    fn scan_mostly_deleted_range_test() {
+        let mut alterations =
+            |db: Database| testing::part3_db_alterations(db).set_memtable_capacity(1024);
+        let mut test = |mut db: Database| {
+            let num_keys: Key = 100_000;
+            for key in 0..num_keys {
+                db.put(key, key).unwrap();
+            }
+            //delete 90% of keys, some tombstones end up in the same level as the values they delete and some don't
+            for key in (0..num_keys).filter(|key| key % 10 != 0) {
+                db.delete(key).unwrap();
+            }
+
+            let scan = db.scan(0, num_keys);
+            let expected: Vec<Entry> = (0..num_keys).step_by(10).map(|key| (key, key)).collect();
+            assert_eq!(scan, expected);
+            //output is sized by live keys, tombstoned keys never enter it
+            assert!(
+                scan.capacity() <= 2 * expected.len(),
+                "scan result has capacity {} for {} live keys",
+                scan.capacity(),
+                expected.len()
+            );
+            db
+        };
+        setup_and_test_and_cleaup(
+            "scan_mostly_deleted_range_test",
+            &mut alterations,
+            &mut test,
+        );
+    }
+
+    #[test]
+    fn scan_skips_shadowed_values_test() {
+        let num_keys: Key = 1000;
+        let mut alterations = |db: Database| {
+            testing::part1_db_alterations(db).set_memtable_capacity(num_keys as Size)
+        };
+        let mut test = |mut db: Database| {
+            //every key is in all 10 runs, 90% of the entries in range are shadowed by a younger run
+            for round in 0..10 {
+                for key in 0..num_keys {
+                    db.put(key, key * 10 + round).unwrap();
+                }
+            }
+            db.flush_memtable().unwrap();
+            assert_eq!(db.sst_count(0), 10);
+
+            let decoded_before = crate::sst::values_decoded();
+            let scan = db.scan(0, num_keys);
+            let values_decoded = crate::sst::values_decoded() - decoded_before;
+            let expected: Vec<Entry> = (0..num_keys).map(|key| (key, key * 10 + 9)).collect();
+            assert_eq!(scan, expected);
+            //only the youngest run's values are decoded
+            assert_eq!(values_decoded, num_keys as Size);
+
+            //reverse and limited scans only decode what they resolve too
+            let decoded_before = crate::sst::values_decoded();
+            let options = ScanOptions {
+                limit: Some(10),
+                reverse: true,
+            };
+            let scan = db.scan_with_options(0, num_keys, options);
+            assert_eq!(
+                scan,
+                expected.iter().rev().take(10).copied().collect::<Vec<_>>()
+            );
+            assert_eq!(crate::sst::values_decoded() - decoded_before, 10);
+            db
+        };
+        setup_and_test_and_cleaup(
+            "scan_skips_shadowed_values_test",
+            &mut alterations,
+            &mut test,
+        );
+    }
+
+    #[test]
+    fn open_in_memory_test() {
+        let test_dir = testing::test_dir("open_in_memory_test");
+        let db_name = test_dir.join("db");
+        let _ = fs::remove_dir_all(test_dir);
+        let mut db = Database::open_in_memory(&db_name)
+            .set_memtable_capacity(4)
+            .set_compaction_policy(CompactionPolicy::Leveled);
+        assert!(db.is_in_memory());
+        assert!(!db.uses_direct_io());
+        for key in 0..100 {
+            db.put(key, key * 10).unwrap();
+        }
+        db.close();
+        assert!(!test_dir.exists(), "nothing is written to disk");
+
+        //another database in memory doesn't see the files, they outlive close in the storage they were written to
+        assert!(Database::open_in_memory(&db_name).scan(0, 99).is_empty());
+        let mut db = open_in(db.storage(), &db_name);
+        assert_eq!(db.memtable_capacity(), 4);
+        assert_eq!(db.scan(0, 99).len(), 100);
+        assert_eq!(db.get(42), Some(420));
+        let why = db.try_set_io_mode(IoMode::Direct).unwrap_err();
+        assert!(matches!(why, DbError::Io(ref why) if why.kind() == io::ErrorKind::Unsupported));
+        assert_eq!(db.io_mode(), IoMode::Auto);
+        assert!(!test_dir.exists());
+    }
+
+    #[test]
+    fn rewrite_run_test() {
+        let entries_per_page = system_info::num_entries_per_page();
+        let memtable_capacity = 4 * entries_per_page;
+        let mut alterations = |db: Database| {
+            testing::part1_db_alterations(db)
+                .set_memtable_capacity(memtable_capacity)
+                .set_enable_buffer_pool(true)
+                .set_enable_bloom_filter(true)
+                .set_enable_fence_index(true)
+        };
+        let mut test = |mut db: Database| {
+            let num_keys = memtable_capacity as Key;
+            let num_shadowed = (memtable_capacity - entries_per_page) as Key;
+            //run 0 holds every key, run 1 overwrites all but its last page and the memtable deletes one more, which
+            // doesn't hide it from a rewrite (it's lost in a crash)
+            for key in 0..num_keys {
+                db.put(key, key).unwrap();
+            }
+            for key in (0..num_shadowed).chain(num_keys..num_keys + entries_per_page as Key) {
+                db.put(key, key + 1).unwrap();
+            }
+            db.delete(num_keys - 1).unwrap();
+            assert_eq!(db.sst_count(0), 2);
+            let expected_scan = db.scan(0, Key::MAX - 1);
+            let expected_gets: Vec<Option<Value>> = (0..=num_keys + entries_per_page as Key)
+                .map(|key| db.get(key))
+                .collect();
+            let check = |db: &mut Database| {
+                assert_eq!(db.scan(0, Key::MAX - 1), expected_scan);
+                for (key, expected) in expected_gets.iter().enumerate() {
+                    assert_eq!(db.get(key as Key), *expected, "key {key}");
+                }
+            };
+            let sst_path = (&db.name, 0, 0).path_sst();
+            let size_before = fs::metadata(&sst_path).unwrap().len();
+
+            assert!(matches!(
+                db.rewrite_run(0, 2),
+                Err(DbError::Io(why)) if why.kind() == io::ErrorKind::InvalidInput
+            ));
+
+            //the replacement's fence index can't be written, so the rewrite fails before the run is replaced
+            let staging_fence_path = filename::fence_path(&(&db.name, 0, 2));
+            fs::create_dir_all(&staging_fence_path).unwrap();
+            assert!(db.rewrite_run(0, 0).is_err());
+            fs::remove_dir(&staging_fence_path).unwrap();
+            for path in run_paths(&(&db.name, 0, 2)) {
+                assert!(!path.exists(), "{path:?} was left behind");
+            }
+            assert_eq!(fs::metadata(&sst_path).unwrap().len(), size_before);
+            assert_eq!(db.metadata.entry_counts[0], vec![memtable_capacity; 2]);
+            check(&mut db);
+
+            let entries_dropped = db.rewrite_run(0, 0).unwrap();
+            assert_eq!(entries_dropped, num_shadowed as Size);
+            assert_eq!(db.metadata.entry_counts[0][0], entries_per_page);
+            assert!(fs::metadata(&sst_path).unwrap().len() < size_before);
+            assert!(!db
+                .storage()
+                .path_exists(&filename::rewrite_marker(&db.name)));
+            check(&mut db);
+
+            //nothing left to drop, the run is rewritten as it is
+            assert_eq!(db.rewrite_run(0, 0).unwrap(), 0);
+            check(&mut db);
+
+            //the value the memtable's delete hid is still there if the delete is lost
+            let (storage, name) = (db.storage().clone(), db.path().to_path_buf());
+            db.abandon();
+            let mut db = open_in(&storage, &name);
+            assert_eq!(db.get(num_keys - 1), Some(num_keys - 1));
+
+            //a run the metadata says is empty can't be rewritten
+            db.metadata.entry_counts[0][0] = 0;
+            assert!(matches!(
+                db.rewrite_run(0, 0),
+                Err(DbError::Io(why)) if why.kind() == io::ErrorKind::InvalidData
+            ));
+            db.metadata.entry_counts[0][0] = entries_per_page;
+
+            //nor can one that holds fewer entries than the metadata says
+            db.abandon();
+            fs::OpenOptions::new()
+                .write(true)
+                .open(&sst_path)
+                .unwrap()
+                .set_len((entries_per_page / 2 * ENTRY_SIZE) as u64)
+                .unwrap();
+            let mut db = open_in(&storage, &name);
+            assert!(matches!(
+                db.rewrite_run(0, 0),
+                Err(DbError::Io(why)) if why.kind() == io::ErrorKind::InvalidData
+            ));
+            db.close();
+            assert!(matches!(db.rewrite_run(0, 0), Err(DbError::Closed)));
+            db
+        };
+        setup_and_test_and_cleaup_on_disk("rewrite_run_test", &mut alterations, &mut test);
+    }
+
+    #[test]
+    fn rewrite_run_crash_points_test() {
+        let entries_per_page = system_info::num_entries_per_page();
+        let memtable_capacity = 2 * entries_per_page + 1; //B-trees have inner nodes
+        let mut alterations = |db: Database| {
+            testing::part1_db_alterations(db)
+                .set_sst_implementation(SstImplementation::Btree)
+                .set_memtable_capacity(memtable_capacity)
+                .set_enable_bloom_filter(true)
+                .set_enable_fence_index(true)
+        };
+        let mut test = |mut db: Database| {
+            //run 0 holds every key (some with a ttl, so it has an expiry sidecar), run 1 overwrites all but a page
+            let num_keys = memtable_capacity as Key;
+            let num_shadowed = num_keys - entries_per_page as Key;
+            for key in 0..num_keys {
+                match key % 4 {
+                    0 => db.put_with_ttl(key, key, 3600).unwrap(),
+                    _ => db.put(key, key).unwrap(),
+                }
+            }
+            for key in (0..num_shadowed).chain(1000..1000 + entries_per_page as Key) {
+                db.put(key, -key).unwrap();
+            }
+            db.flush().unwrap();
+            assert_eq!(db.metadata.entry_counts[0], vec![memtable_capacity; 2]);
+            let expected = db.scan(0, Key::MAX - 1);
+
+            //copies of the database's directory before every file change, each is what a crash there leaves behind
+            let crashes = db.path().parent().unwrap().join("crashes");
+            direct_io::tests::record_crash_points(db.path(), &crashes);
+            db.rewrite_run(0, 0).unwrap();
+            let crashes = direct_io::tests::stop_recording_crash_points(db.path());
+            assert!(crashes.len() > 6, "{} crash points", crashes.len());
+            assert_eq!(db.scan(0, Key::MAX - 1), expected);
+
+            //a crash leaves the run either as it was or rewritten, never part of each
+            let (mut num_rewritten, mut num_finished_by_open) = (0, 0);
+            for crash in &crashes {
+                num_finished_by_open += filename::rewrite_marker(crash).exists() as Size;
+                let mut recovered = Database::try_open(crash).unwrap();
+                assert!(!recovered
+                    .storage()
+                    .path_exists(&filename::rewrite_marker(crash)));
+                let num_entries = recovered.metadata.entry_counts[0][0];
+                match num_entries == memtable_capacity {
+                    true => {}
+                    false => {
+                        num_rewritten += 1;
+                        assert_eq!(num_entries, entries_per_page, "{crash:?}");
+                    }
+                }
+                let run_address = &(&recovered.name, 0, 0);
+                let entries = recovered.sst_interface().read(run_address).unwrap();
+                assert_eq!(entries.len(), num_entries, "{crash:?}");
+                assert_eq!(recovered.scan(0, Key::MAX - 1), expected, "{crash:?}");
+                for key in (0..num_keys).step_by(7) {
+                    assert_eq!(
+                        recovered.get(key),
+                        expected_value(&expected, key),
+                        "{crash:?}"
+                    );
+                }
+                recovered.close();
+            }
+            assert!(num_rewritten > 0 && num_rewritten < crashes.len());
+            assert!(
+                num_finished_by_open > 1,
+                "{num_finished_by_open} swaps cut short"
+            );
+            db
+        };
+        fn expected_value(expected: &[Entry], key: Key) -> Option<Value> {
+            let index = expected.binary_search_by_key(&key, |(key, _)| *key).ok()?;
+            Some(expected[index].1)
+        }
+        setup_and_test_and_cleaup_on_disk(
+            "rewrite_run_crash_points_test",
+            &mut alterations,
+            &mut test,
+        );
+    }
+
+    #[test]
+    fn maintain_test() {
+        let memtable_capacity = 4;
+        let mut test = |mut db: Database| {
+            //level 2 holds keys 0 to 11, the level 1 run deletes 0 and 1, no level has bloom filters
+            for key in 0..3 * memtable_capacity as Key {
+                db.put(key, key).unwrap();
+            }
+            db.put(12, 12).unwrap();
+            db.put(13, 13).unwrap();
+            db.delete(0).unwrap();
+            db.delete(1).unwrap();
+            for key in 16..20 {
+                db.put(key, key).unwrap();
+            }
+            db.flush().unwrap();
+            assert_eq!(db.metadata.entry_counts, vec![vec![4], vec![4], vec![12]]);
+            assert_eq!(db.metadata.tombstone_counts[1], vec![2]);
+
+            //level 0 fills up to its limit
+            db = db
+                .set_compaction_policy(CompactionPolicy::None)
+                .set_max_level_zero_runs(2)
+                .set_bloom_levels(BloomLevels::UpTo(2));
+            for key in 100..104 {
+                db.put(key, key).unwrap();
+            }
+            db.flush().unwrap();
+            assert_eq!(db.sst_count(0), 2);
+
+            assert_eq!(
+                db.maintain(Duration::ZERO).unwrap(),
+                vec![MaintenanceTask::Compact { level: 0 }]
+            );
+            assert_eq!(db.sst_count(0), 1);
+            assert_eq!(
+                db.maintain(Duration::ZERO).unwrap(),
+                vec![MaintenanceTask::CollectTombstones { level: 2, run: 0 }]
+            );
+            assert_eq!(db.metadata.entry_counts[2], vec![10]);
+
+            //without the bloom feature no level has bloom filters, so none are missing
+            let has_filters = db.enable_bloom_filter();
+            if has_filters {
+                assert_eq!(
+                    db.maintain(Duration::ZERO).unwrap(),
+                    vec![MaintenanceTask::BackfillFilter { level: 1, run: 0 }]
+                );
+            }
+            assert_eq!(
+                db.storage().path_exists(&(&db.name, 1, 0).path_bloom()),
+                has_filters
+            );
+
+            //progress survives a reopen, the rest fits in one call with a large enough budget
+            let name = db.path().to_path_buf();
+            db.close();
+            let mut db = open_in(db.storage(), &name);
+            let verify =
+                [(0, 0), (1, 0), (2, 0)].map(|(level, run)| MaintenanceTask::Verify { level, run });
+            assert_eq!(db.maintain(Duration::MAX).unwrap(), verify);
+            assert!(db.maintain(Duration::MAX).unwrap().is_empty());
+
+            db.close();
+            let mut db = open_in(db.storage(), &name);
+            assert!(db.maintain(Duration::ZERO).unwrap().is_empty());
+            for key in (2..14).chain(16..20).chain(100..104) {
+                assert_eq!(db.get(key), Some(key));
+            }
+            assert_eq!(db.get(0), None);
+            assert_eq!(db.get(1), None);
+            db
+        };
+        setup_and_test_and_cleaup(
+            "maintain_test",
+            &mut |db| {
+                db.set_memtable_capacity(memtable_capacity)
+                    .set_compaction_policy(CompactionPolicy::Tiered)
+                    .set_sst_size_ratio(3)
+                    .set_bloom_levels(BloomLevels::None)
+            },
+            &mut test,
+        );
+    }
+
+    #[test]
+    fn corrupt_maintenance_file_test() {
+        let mut test = |mut db: Database| {
+            for key in 0..8 {
+                db.put(key, key).unwrap();
+            }
+            db.flush().unwrap();
+            assert!(!db.maintain(Duration::MAX).unwrap().is_empty());
+            assert!(db.maintain(Duration::MAX).unwrap().is_empty());
+            let name = db.path().to_path_buf();
+            let maintenance_file = filename::maintenance(&name);
+            db.close();
+            db.storage()
+                .write_file(&maintenance_file, &[0xff; 3])
+                .unwrap();
+
+            //the progress is lost, maintenance starts over
+            let mut db = open_in(db.storage(), &name);
+            assert!(!db.maintain(Duration::MAX).unwrap().is_empty());
+            assert!(db.maintain(Duration::MAX).unwrap().is_empty());
+            for key in 0..8 {
+                assert_eq!(db.get(key), Some(key));
+            }
+            db
+        };
+        setup_and_test_and_cleaup(
+            "corrupt_maintenance_file_test",
+            &mut |db| db.set_memtable_capacity(4),
+            &mut test,
+        );
+    }
+
+    #[test]
+    fn get_ceiling_and_floor_test() {
+        let mut test = |mut db: Database| {
+            for key in (10..=50).step_by(10) {
+                db.put(key, key * 10).unwrap();
+            }
+            db.delete(30).unwrap(); //younger tombstone over an older value
+            db.put(20, 200).unwrap();
+
+            assert_eq!(db.get_ceiling(25), Some((40, 400))); //30 is skipped
+            assert_eq!(db.get_floor(35), Some((20, 200)));
+            assert_eq!(db.get_ceiling(30), Some((40, 400)));
+            assert_eq!(db.get_floor(30), Some((20, 200)));
+            assert_eq!(db.get_ceiling(20), Some((20, 200)));
+
+            //boundaries at the extreme stored keys
+            assert_eq!(db.get_ceiling(10), Some((10, 100)));
+            assert_eq!(db.get_floor(10), Some((10, 100)));
+            assert_eq!(db.get_floor(9), None);
+            assert_eq!(db.get_ceiling(Key::MIN + 1), Some((10, 100)));
+            assert_eq!(db.get_ceiling(50), Some((50, 500)));
+            assert_eq!(db.get_ceiling(51), None);
+            assert_eq!(db.get_floor(Key::MAX), Some((50, 500)));
+
+            //deleting the largest key exposes the next one
+            db.delete(50).unwrap();
+            assert_eq!(db.get_floor(Key::MAX), Some((40, 400)));
+            assert_eq!(db.get_ceiling(41), None);
+            db
+        };
+        for (test_name, alterations) in [
+            (
+                "get_ceiling_and_floor_part1_test",
+                testing::part1_db_alterations as fn(Database) -> Database,
+            ),
+            (
+                "get_ceiling_and_floor_part2_test",
+                testing::part2_db_alterations,
+            ),
+            (
+                "get_ceiling_and_floor_part3_test",
+                testing::part3_db_alterations,
+            ),
+        ] {
+            setup_and_test_and_cleaup(
+                test_name,
+                &mut |db| alterations(db).set_memtable_capacity(2),
+                &mut test,
+            );
+        }
+    }
+
+    #[test]
+    fn get_ceiling_and_floor_large_test() {
+        use std::collections::BTreeMap;
+
+        let mut alterations =
+            |db: Database| testing::part3_db_alterations(db).set_memtable_capacity(896);
+        let mut test = |mut db: Database| {
+            let mut expected = BTreeMap::new();
+            for key in (0..9000).step_by(3) {
+                db.put(key, key * 10).unwrap();
+                expected.insert(key, key * 10);
+            }
+            //deletes land in younger runs than the values they hide
+            for key in (0..9000).step_by(6) {
+                db.delete(key).unwrap();
+                expected.remove(&key);
+            }
+
+            for key in (-10..9010).step_by(7) {
+                let ceiling = expected.range(key..).next().map(|(k, v)| (*k, *v));
+                let floor = expected.range(..=key).next_back().map(|(k, v)| (*k, *v));
+                assert_eq!(db.get_ceiling(key), ceiling, "ceiling of {key}");
+                assert_eq!(db.get_floor(key), floor, "floor of {key}");
+            }
+            db
+        };
+        setup_and_test_and_cleaup(
+            "get_ceiling_and_floor_large_test",
+            &mut alterations,
+            &mut test,
+        );
+    }
+
+    #[test]
+    fn scan_with_options_test() {
+        use std::collections::BTreeMap;
+
+        let mut test = |mut db: Database| {
+            let num_keys: Key = 6000;
+            let mut expected = BTreeMap::new();
+            for i in 0..num_keys {
+                let key = i * 7919 % num_keys; //every key once, out of order
+                db.put(key, key * 10).unwrap();
+                expected.insert(key, key * 10);
+            }
+            //tombstones and overwrites land in younger runs than the values they replace
+            for key in (0..num_keys).filter(|key| key % 3 == 0) {
+                db.delete(key).unwrap();
+                expected.remove(&key);
+            }
+            for key in (0..num_keys).step_by(5) {
+                db.put(key, -key).unwrap();
+                expected.insert(key, -key);
+            }
+
+            for (key1, key2) in [
+                (-100, num_keys + 100),
+                (1000, 1500),
+                (3, 3),
+                (6, 6),
+                (50, 10),
+            ] {
+                let forward = db.scan_with_options(key1, key2, ScanOptions::default());
+                let mut reverse = db.scan_with_options(
+                    key1,
+                    key2,
+                    ScanOptions {
+                        reverse: true,
+                        ..Default::default()
+                    },
+                );
+                let in_range: Vec<Entry> = if key1 <= key2 {
+                    expected.range(key1..=key2).map(|(k, v)| (*k, *v)).collect()
+                } else {
+                    vec![]
+                };
+                assert_eq!(forward, in_range, "forward scan of ({key1}, {key2})");
+                assert_eq!(forward, db.scan(key1, key2));
+                reverse.reverse();
+                assert_eq!(reverse, forward, "reverse scan of ({key1}, {key2})");
+            }
+
+            //deleted keys don't count towards the limit
+            for limit in [1, 2, 50, 1000] {
+                let first = db.scan_with_options(
+                    0,
+                    num_keys,
+                    ScanOptions {
+                        limit: Some(limit),
+                        reverse: false,
+                    },
+                );
+                let expected_first: Vec<Entry> =
+                    expected.iter().take(limit).map(|(k, v)| (*k, *v)).collect();
+                assert_eq!(first, expected_first);
+
+                let last = db.scan_with_options(
+                    0,
+                    num_keys,
+                    ScanOptions {
+                        limit: Some(limit),
+                        reverse: true,
+                    },
+                );
+                let expected_last: Vec<Entry> = expected
+                    .iter()
+                    .rev()
+                    .take(limit)
+                    .map(|(k, v)| (*k, *v))
+                    .collect();
+                assert_eq!(last, expected_last);
+            }
+            //limit larger than the range
+            let all = db.scan_with_options(
+                0,
+                20,
+                ScanOptions {
+                    limit: Some(1000),
+                    reverse: true,
+                },
+            );
+            assert_eq!(all.len(), expected.range(0..=20).count());
+            db
+        };
+        for (test_name, alterations) in [
+            (
+                "scan_with_options_part1_test",
+                testing::part1_db_alterations as fn(Database) -> Database,
+            ),
+            (
+                "scan_with_options_part2_test",
+                testing::part2_db_alterations,
+            ),
+            (
+                "scan_with_options_part3_test",
+                testing::part3_db_alterations,
+            ),
+        ] {
+            setup_and_test_and_cleaup(
+                test_name,
+                &mut |db| alterations(db).set_memtable_capacity(600),
+                &mut test,
+            );
+        }
+    }
+
+    #[test]
+    fn scan_source_states_test() {
+        //every combination of live, deleted and absent for a key in the memtable, a young run and an old run,
+        // the youngest source that has the key decides whether it's returned
+        #[derive(Clone, Copy)]
+        enum State {
+            Live,
+            Deleted,
+            Absent,
+        }
+        const STATES: [State; 3] = [State::Live, State::Deleted, State::Absent];
+        let combinations: Vec<[State; 3]> = STATES
+            .iter()
+            .flat_map(|&memtable| {
+                STATES
+                    .iter()
+                    .flat_map(move |&young| STATES.iter().map(move |&old| [memtable, young, old]))
+            })
+            .collect();
+
+        let mut test = |mut db: Database| {
+            let write = |db: &mut Database, source: usize| {
+                for (key, states) in combinations.iter().enumerate() {
+                    let key = key as Key;
+                    match states[source] {
+                        State::Live => db.put(key, 100 * source as Value + key).unwrap(),
+                        State::Deleted => db.delete(key).unwrap(),
+                        State::Absent => {}
+                    }
+                }
+            };
+            write(&mut db, 2); //old run
+            db.flush_memtable().unwrap();
+            write(&mut db, 1); //young run
+            db.flush_memtable().unwrap();
+            write(&mut db, 0); //memtable
+            assert_eq!(db.sst_count(0), 2);
+
+            let expected: Vec<Entry> = combinations
+                .iter()
+                .enumerate()
+                .filter_map(|(key, states)| {
+                    let key = key as Key;
+                    let source = states
+                        .iter()
+                        .position(|state| !matches!(state, State::Absent))?;
+                    matches!(states[source], State::Live)
+                        .then(|| (key, 100 * source as Value + key))
+                })
+                .collect();
+            for key in 0..combinations.len() as Key {
+                let value = expected
+                    .iter()
+                    .find(|entry| entry.0 == key)
+                    .map(|entry| entry.1);
+                assert_eq!(db.get(key), value, "key {key}");
+                assert_eq!(
+                    db.scan(key, key),
+                    Vec::from_iter(value.map(|value| (key, value)))
+                );
+            }
+            assert_eq!(db.scan(Key::MIN, Key::MAX), expected);
+            let reverse = ScanOptions {
+                reverse: true,
+                ..Default::default()
+            };
+            let mut reversed = expected.clone();
+            reversed.reverse();
+            assert_eq!(db.scan_with_options(Key::MIN, Key::MAX, reverse), reversed);
+            for limit in 1..=expected.len() {
+                let options = ScanOptions {
+                    limit: Some(limit),
+                    ..Default::default()
+                };
+                assert_eq!(
+                    db.scan_with_options(Key::MIN, Key::MAX, options),
+                    expected[..limit]
+                );
+            }
+            db
+        };
+        for sst_implementation in [SstImplementation::Array, SstImplementation::Btree] {
+            setup_and_test_and_cleaup(
+                format!("scan_source_states_test_{sst_implementation:?}"),
+                &mut |db| {
+                    db.set_memtable_capacity(64)
+                        .set_compaction_policy(CompactionPolicy::None)
+                        .set_sst_implementation(sst_implementation)
+                },
+                &mut test,
+            );
+        }
+    }
+
+    #[test]
+    fn scan_limit_zero_test() {
+        let test_dir = testing::test_dir("scan_limit_zero_test");
+        let mut alterations =
+            |db: Database| testing::part1_db_alterations(db).set_memtable_capacity(2);
+        let mut test = |mut db: Database| {
+            for key in 0..10 {
+                db.put(key, key).unwrap();
+            }
+            //with the runs moved away, any read of an sst would panic
+            let level_directory = filename::lsm_level_directory(&(&db.name, 0));
+            let moved_directory = test_dir.join("moved");
+            fs::rename(&level_directory, &moved_directory).unwrap();
+            let options = ScanOptions {
+                limit: Some(0),
+                reverse: false,
+            };
+            assert_eq!(db.scan_with_options(0, 10, options), vec![]);
+            fs::rename(&moved_directory, &level_directory).unwrap();
+            db
+        };
+        setup_and_test_and_cleaup_on_disk(test_dir, &mut alterations, &mut test);
+    }
+
+    #[test]
+    fn scan_with_retry_test() {
+        use crate::compaction::background_compactor;
+
+        let memtable_capacity = system_info::num_entries_per_page();
+        let mut alterations = |db: Database| {
+            testing::part1_db_alterations(db)
+                .set_memtable_capacity(memtable_capacity)
+                .set_compaction_policy(CompactionPolicy::None) //runs pile up in level 0 until the test compacts them
+                .set_background_compaction(true)
+        };
+        let mut test = |mut db: Database| {
+            //overlapping runs, with overwrites and deletes a compaction merges away
+            let num_keys = 20 * memtable_capacity as Key;
+            for key in 0..num_keys {
+                db.put(key, key).unwrap();
+            }
+            for key in (0..num_keys).step_by(3) {
+                db.put(key, -key).unwrap();
+            }
+            for key in (0..num_keys).step_by(7) {
+                db.delete(key).unwrap();
+            }
+            db.flush().unwrap();
+            let expected = db.scan(Key::MIN + 1, Key::MAX);
+            let (low, high) = (expected[10].0, expected[expected.len() - 10].0);
+            let expected_range = db.scan(low, high);
+            assert!(expected_range.len() > Database::RETRY_SCAN_CHUNK_PAGES * memtable_capacity);
+            assert_eq!(db.scan_with_retry(low, high), expected_range);
+            assert_eq!(db.scan_with_retry(high, low), vec![]);
+
+            //a compaction installed midway through the first chunk, its runs' files are deleted under the scan
+            let levels = db.background_levels().unwrap();
+            let settings = CompactionSettings {
+                compaction_policy: CompactionPolicy::Leveled,
+                ..db.compaction_settings()
+            };
+            let runs_before = db.metadata.entry_counts.clone();
+            let mut num_calls = 0;
+            let mut yield_hook = YieldHook::new(move || {
+                num_calls += 1;
+                if num_calls == 3 {
+                    background_compactor::compact_levels(&settings, &levels).unwrap();
+                }
+                ControlFlow::Continue(())
+            });
+            let (scanned, retries) = db.scan_retrying(low, high, &mut yield_hook).unwrap();
+            assert_eq!(scanned, expected_range);
+            assert_eq!(retries, 1);
+            assert_ne!(db.metadata.entry_counts, runs_before);
+            assert_eq!(db.metadata.entry_counts[0], vec![]);
+            assert_eq!(db.scan(Key::MIN + 1, Key::MAX), expected);
+            db
+        };
+        setup_and_test_and_cleaup("scan_with_retry_test", &mut alterations, &mut test);
+    }
+
+    #[test]
+    fn scan_keys_test() {
+        let entries_per_page = system_info::num_entries_per_page();
+        let check = |db: &mut Database| {
+            let ranges = [
+                (Key::MIN, Key::MAX),
+                (100, 2000),
+                (-10, 0),
+                (77, 77),
+                (500, 20),
+            ];
+            for (key1, key2) in ranges {
+                let expected: Vec<Key> = db
+                    .scan(key1, key2)
+                    .into_iter()
+                    .map(|(key, _)| key)
+                    .collect();
+                assert_eq!(db.scan_keys(key1, key2), expected, "range ({key1}, {key2})");
+            }
+        };
+        let mut all_alterations: Vec<fn(Database) -> Database> =
+            vec![testing::part1_db_alterations];
+        if cfg!(feature = "btree") {
+            all_alterations.push(testing::part2_db_alterations);
+        }
+        for db_alterations in all_alterations {
+            //runs over several levels, with keys deleted and overwritten by younger runs
+            let mut alterations = |db: Database| {
+                db_alterations(db)
+                    .set_memtable_capacity(entries_per_page)
+                    .set_compaction_policy(CompactionPolicy::Tiered)
+            };
+            let mut test = |mut db: Database| {
+                let num_keys = 12 * entries_per_page as Key;
+                for key in 0..num_keys {
+                    db.put(key * 2, key).unwrap();
+                }
+                for key in (0..num_keys).step_by(5) {
+                    db.delete(key * 2).unwrap();
+                }
+                for key in (0..num_keys).step_by(3) {
+                    db.put(key * 2 + 1, -key).unwrap();
+                }
+                db.flush().unwrap();
+                assert!(db.metadata.entry_counts.len() > 1);
+                check(&mut db);
+                db.delete(100).unwrap();
+                db.put(1001, 1).unwrap();
+                check(&mut db);
+                db
+            };
+            setup_and_test_and_cleaup("scan_keys_test", &mut alterations, &mut test);
+
+            //a single run is read for its keys alone, no value is decoded
+            let mut alterations =
+                |db: Database| db_alterations(db).set_memtable_capacity(4 * entries_per_page);
+            let mut test = |mut db: Database| {
+                for key in 0..(3 * entries_per_page) as Key {
+                    db.put(key * 2, key).unwrap();
+                }
+                db.flush().unwrap();
+                assert_eq!(db.sst_total(), 1);
+                let decoded_before = crate::sst::values_decoded();
+                let keys = db.scan_keys(100, 1000);
+                assert_eq!(crate::sst::values_decoded(), decoded_before);
+                assert_eq!(keys, (50..=500).map(|key| key * 2).collect::<Vec<Key>>());
+                check(&mut db);
+                //once the memtable holds keys in range the runs are merged with it
+                db.delete(200).unwrap();
+                check(&mut db);
+                assert!(crate::sst::values_decoded() > decoded_before);
+                db
+            };
+            setup_and_test_and_cleaup("scan_keys_single_run_test", &mut alterations, &mut test);
+        }
+    }
+
+    #[test]
+    fn run_statistics_test() {
+        let mut alterations = |db: Database| {
+            testing::part1_db_alterations(db)
+                .set_compaction_policy(CompactionPolicy::Leveled)
+                .set_enable_bloom_filter(true)
+                .set_memtable_capacity(4)
+        };
+        let mut test = |mut db: Database| {
+            for key in 0..4 {
+                db.put(key, key).unwrap();
+            }
+            db.flush_memtable().unwrap();
+            for key in 0..4 {
+                db.delete(key).unwrap();
+            }
+            db.flush_memtable().unwrap();
+            //first run was moved up a level, second run is entirely tombstones
+            assert_eq!(db.metadata.entry_counts, vec![vec![4], vec![4]]);
+            assert_eq!(db.metadata.tombstone_counts, vec![vec![4], vec![0]]);
+            for key in 0..4 {
+                assert_eq!(db.get(key), None);
+            }
+
+            //last level compaction discards the tombstones and the values they shadow
+            db.put(10, 10).unwrap();
+            db.flush_memtable().unwrap();
+            assert_eq!(db.metadata.entry_counts, vec![vec![1], vec![]]);
+            assert_eq!(db.metadata.tombstone_counts, vec![vec![0], vec![]]);
+            assert_eq!(db.metadata.compaction_entries_dropped, 8);
+            assert!(db
+                .describe_layout()
+                .contains("entries dropped by compaction: 8"));
+            db
+        };
+        setup_and_test_and_cleaup("run_statistics_test", &mut alterations, &mut test);
+    }
+
+    #[cfg(feature = "bloom")]
+    #[test]
+    fn all_tombstone_run_skips_bloom_filter_test() {
+        let mut alterations = |db: Database| {
+            testing::part1_db_alterations(db)
+                .set_enable_bloom_filter(true)
+                .set_memtable_capacity(2)
+        };
+        let mut test = |mut db: Database| {
+            db.put(1, 1).unwrap();
+            db.put(2, 2).unwrap();
+            db.delete(1).unwrap();
+            db.delete(2).unwrap();
+            db.put(3, 3).unwrap();
+            assert_eq!(db.metadata.tombstone_counts, vec![vec![0, 2]]);
+
+            //without its filter, this run can only be searched if the probe is skipped
+            db.storage()
+                .remove_file(&(&db.name, 0, 1).path_bloom())
+                .unwrap();
+            assert_eq!(db.get(1), None);
+            assert_eq!(db.get(2), None);
+            assert_eq!(db.get(3), Some(3));
+            assert_eq!(db.get(5), None);
+
+            //counts are persisted
+            let name = db.name();
+            db.close();
+            let db = open_in(db.storage(), &name);
+            assert_eq!(db.metadata.tombstone_counts, vec![vec![0, 2, 0]]);
+            db
+        };
+        setup_and_test_and_cleaup(
+            "all_tombstone_run_skips_bloom_filter_test",
+            &mut alterations,
+            &mut test,
+        );
+    }
+
+    #[test]
+    fn tombstone_density_compaction_test() {
+        let mut alterations = |db: Database| {
+            testing::part1_db_alterations(db)
+                .set_compaction_policy(CompactionPolicy::Tiered)
+                .set_sst_size_ratio(3)
+                .set_memtable_capacity(2)
+        };
+        let mut test = |mut db: Database| {
+            db.put(1, 1).unwrap();
+            db.put(2, 2).unwrap();
+            db.flush_memtable().unwrap();
+            db.delete(1).unwrap();
+            db.delete(2).unwrap();
+            db.flush_memtable().unwrap();
+            db.put(3, 3).unwrap();
+            db.flush_memtable().unwrap();
+
+            //level 1 is under the size ratio, but half of it is tombstones so it was compacted anyways
+            assert_eq!(db.sst_count(1), 0);
+            assert_eq!(db.metadata.compaction_entries_dropped, 4);
+            assert_eq!(db.get(1), None);
+            assert_eq!(db.get(3), Some(3));
+            db
+        };
+        setup_and_test_and_cleaup(
+            "tombstone_density_compaction_test",
+            &mut alterations,
+            &mut test,
+        );
+    }
+
+    #[test]
+    fn legacy_metadata_test() {
+        let test_dir = testing::test_dir("legacy_metadata_test");
+        let db_name = test_dir.join("test");
+        if test_dir.exists() {
+            std::fs::remove_dir_all(test_dir).unwrap();
+        }
+        std::fs::create_dir_all(test_dir).unwrap();
+
+        //write a current config, bloom filters off so open doesn't look for the SSTs of the made up runs below
+        Database::open(&db_name)
+            .set_enable_bloom_filter(false)
+            .close();
+        let legacy_entry_counts: Vec<Vec<Size>> = vec![vec![2, 2], vec![4]];
+        std::fs::write(
+            filename::metadata(&db_name),
+            serde_state::serialize(&legacy_entry_counts),
+        )
+        .unwrap();
+
+        let db = Database::open(&db_name);
+        assert_eq!(db.metadata.entry_counts, legacy_entry_counts);
+        assert_eq!(db.metadata.tombstone_counts, vec![vec![0, 0], vec![0]]);
+        assert_eq!(db.metadata.expiry_counts, vec![vec![0, 0], vec![0]]);
+        let page_size = system_info::page_size();
+        assert_eq!(
+            db.metadata.page_sizes,
+            vec![vec![page_size; 2], vec![page_size]]
+        );
+        //older runs have no key sketch, they're assumed to share no keys
+        assert_eq!(
+            db.metadata.key_sketches,
+            vec![vec![KeySketch::default(); 2], vec![KeySketch::default()]]
+        );
+        assert_eq!(db.garbage_ratio(0), 0.0);
+        drop(db);
+        std::fs::remove_dir_all(test_dir).unwrap();
+    }
+
+    #[test]
+    fn read_cache_test() {
+        let mut alterations = |db: Database| {
+            testing::part1_db_alterations(db)
+                .set_memtable_capacity(2)
+                .set_read_cache_capacity(2)
+        };
+        let mut test = |mut db: Database| {
+            for key in 0..6 {
+                db.put(key, key * 10).unwrap();
+            }
+            db.flush_memtable().unwrap();
+            for _ in 0..ReadCache::ADMISSION_THRESHOLD {
+                assert_eq!(db.get(1), Some(10));
+                assert_eq!(db.get(2), Some(20));
+            }
+            assert_eq!(db.cached_value(1), Some(10));
+            assert_eq!(db.cached_value(2), Some(20));
+            let trace = db.explain_get(1);
+            assert!(trace.read_cache_hit && trace.runs.is_empty());
+
+            //writes must never be hidden by a cached value, the runs they're flushed to are newer than the cache run
+            db.put(1, 11).unwrap();
+            assert_eq!(db.get(1), Some(11));
+            db.delete(2).unwrap();
+            assert_eq!(db.get(2), None);
+            db.flush_memtable().unwrap();
+            assert_eq!(db.cached_value(1), None);
+            assert_eq!(db.cached_value(2), None);
+            assert_eq!(db.get(1), Some(11));
+            assert_eq!(db.get(2), None);
+            //compacting them with the runs the values were cached from doesn't bring the old values back
+            db.compact_level_zero().unwrap();
+            assert_eq!(db.get(1), Some(11));
+            assert_eq!(db.get(2), None);
+            assert_eq!(db.cached_value(1), Some(11)); //read often enough to be cached again
+
+            //capacity is respected, the most read keys are kept (these are read more than key 1 was)
+            for key in 3..6 {
+                for _ in 0..ReadCache::ADMISSION_THRESHOLD * 4 + key as Size {
+                    assert_eq!(db.get(key), Some(key * 10));
+                }
+            }
+            assert_eq!(db.read_cache.len(), 2);
+            assert_eq!(db.cached_value(5), Some(50));
+            assert_eq!(db.cached_value(4), Some(40));
+
+            //cache survives a clean restart
+            let name = db.path().to_path_buf();
+            db.close();
+            let mut db = open_in(db.storage(), &name);
+            assert_eq!(db.read_cache_capacity(), 2);
+            assert_eq!(db.read_cache.len(), 2);
+            assert_eq!(db.cached_value(5), Some(50));
+            assert!(!db.storage().path_exists(&filename::read_cache(&name)));
+            assert_eq!(db.get(1), Some(11));
+
+            //and what's written after it is still seen
+            db.put(5, 51).unwrap();
+            db.flush_memtable().unwrap();
+            assert_eq!(db.get(5), Some(51));
+            db
+        };
+        setup_and_test_and_cleaup("read_cache_test", &mut alterations, &mut test);
+    }
+
+    #[test]
+    fn read_cache_truncation_test() {
+        let mut alterations = |db: Database| {
+            testing::part1_db_alterations(db)
+                .set_memtable_capacity(4)
+                .set_read_cache_capacity(4)
+        };
+        let mut test = |mut db: Database| {
+            for key in 0..8 {
+                db.put(key, key * 10).unwrap();
+            }
+            db.flush_memtable().unwrap();
+            //key 7 is read the most, key 0 the least
+            for key in 0..8 {
+                for _ in 0..ReadCache::ADMISSION_THRESHOLD + key as Size {
+                    assert_eq!(db.get(key), Some(key * 10));
+                }
+            }
+            let name = db.path().to_path_buf();
+            db.close();
+
+            //a smaller capacity truncates the persisted cache run to the most read keys when it's next written
+            let db = open_in(db.storage(), &name);
+            assert_eq!(db.read_cache.len(), 4);
+            let mut db = db.set_read_cache_capacity(2);
+            db.close();
+            let mut db = open_in(db.storage(), &name);
+            assert_eq!(db.read_cache_capacity(), 2);
+            assert_eq!(db.read_cache.len(), 2);
+            for key in [7, 6] {
+                assert_eq!(db.cached_value(key), Some(key * 10));
+            }
+            for key in 0..6 {
+                assert_eq!(db.cached_value(key), None);
+                assert_eq!(db.get(key), Some(key * 10));
+            }
+
+            //disabling the cache removes its run
+            let mut db = db.set_read_cache_capacity(0);
+            assert_eq!(db.cached_value(7), None);
+            db.close();
+            let db = open_in(db.storage(), &name);
+            assert_eq!(db.read_cache.len(), 0);
+            let cache_directory = filename::read_cache_directory(&name);
+            assert!(!db.storage().path_exists(&cache_directory));
+            db
+        };
+        setup_and_test_and_cleaup("read_cache_truncation_test", &mut alterations, &mut test);
+    }
+
+    #[test]
+    fn corrupt_read_cache_file_test() {
+        let mut alterations = |db: Database| {
+            testing::part1_db_alterations(db)
+                .set_memtable_capacity(2)
+                .set_read_cache_capacity(2)
+        };
+        let mut test = |mut db: Database| {
+            for key in 0..4 {
+                db.put(key, key * 10).unwrap();
+            }
+            let name = db.path().to_path_buf();
+            let cache_file = filename::read_cache(&name);
+            let cache_directory = filename::read_cache_directory(&name);
+            db.close();
+            db.storage().write_file(&cache_file, &[0xff; 3]).unwrap();
+
+            //the cache is rebuilt by reads, open drops the file and goes on (its capacity went with it)
+            let db = open_in(db.storage(), &name);
+            assert_eq!(db.read_cache.len(), 0);
+            assert!(!db.storage().path_exists(&cache_file));
+            let mut db = db.set_read_cache_capacity(2);
+            for _ in 0..ReadCache::ADMISSION_THRESHOLD {
+                assert_eq!(db.get(1), Some(10));
+            }
+            assert_eq!(db.cached_value(1), Some(10));
+
+            //a cache run cut short is dropped, not read
+            db.close();
+            let storage = db.storage();
+            let run_path = (storage.read_dir(&cache_directory.join("0")).unwrap())
+                .into_iter()
+                .find(|path| path.extension().is_some_and(|extension| extension == "sst"))
+                .unwrap();
+            let run = storage.read_file(&run_path).unwrap();
+            storage
+                .write_file(&run_path, &run[..run.len() / 2])
+                .unwrap();
+            let mut db = open_in(db.storage(), &name);
+            assert_eq!(db.read_cache_capacity(), 2);
+            assert_eq!(db.read_cache.len(), 0);
+            assert!(!db.storage().path_exists(&cache_directory));
+            assert_eq!(db.get(1), Some(10));
+
+            //nor is the cache of a session that never closed, its runs can be newer than the metadata
+            for _ in 0..ReadCache::ADMISSION_THRESHOLD {
+                assert_eq!(db.get(2), Some(20));
+            }
+            assert!(db.storage().path_exists(&cache_directory));
+            db.abandon();
+            let db = open_in(storage, &name);
+            assert_eq!(db.read_cache.len(), 0);
+            assert!(!db.storage().path_exists(&cache_directory));
+            db
+        };
+        setup_and_test_and_cleaup("corrupt_read_cache_file_test", &mut alterations, &mut test);
+    }
+
+    #[test]
+    fn ttl_test() {
+        let all_alterations: [fn(Database) -> Database; 3] = [
+            testing::part1_db_alterations,
+            testing::part2_db_alterations,
+            testing::part3_db_alterations,
+        ];
+        for (i, part_alterations) in all_alterations.into_iter().enumerate() {
+            let clock = ManualClock::new(1_000);
+            let mut alterations = |db: Database| {
+                part_alterations(db)
+                    .set_memtable_capacity(4)
+                    .set_clock(clock.clone())
+            };
+            let mut test = |mut db: Database| {
+                //even keys expire after 10 seconds, the last few stay in the memtable
+                for key in 0..22 {
+                    if key % 2 == 0 {
+                        db.put_with_ttl(key, key * 10, 10).unwrap();
+                    } else {
+                        db.put(key, key * 10).unwrap();
+                    }
+                }
+                assert!(db.sst_total() > 0);
+                clock.advance(9);
+                for key in 0..22 {
+                    assert_eq!(db.get(key), Some(key * 10));
+                }
+                assert_eq!(db.scan(0, 21).len(), 22);
+
+                clock.advance(1);
+                for key in 0..22 {
+                    let expected = if key % 2 == 0 { None } else { Some(key * 10) };
+                    assert_eq!(db.get(key), expected, "key {key}");
+                }
+                let live: Vec<Entry> = (0..22)
+                    .filter(|key| key % 2 == 1)
+                    .map(|key| (key, key * 10))
+                    .collect();
+                assert_eq!(db.scan(0, 21), live);
+                assert_eq!(db.scan(4, 8), vec![(5, 50), (7, 70)]);
+                let reverse = ScanOptions {
+                    limit: Some(2),
+                    reverse: true,
+                };
+                assert_eq!(
+                    db.scan_with_options(0, 20, reverse),
+                    vec![(19, 190), (17, 170)]
+                );
+                assert_eq!(db.get_ceiling(2), Some((3, 30)));
+                assert_eq!(db.get_floor(20), Some((19, 190)));
+
+                //a delete beats an older value that expires later
+                db.put_with_ttl(100, 1, 1_000).unwrap();
+                db.flush_memtable().unwrap();
+                db.delete(100).unwrap();
+                assert_eq!(db.get(100), None);
+                db.flush_memtable().unwrap();
+                assert_eq!(db.get(100), None);
+                assert_eq!(db.scan(100, 100), vec![]);
+
+                //an expired value still hides an older value without a TTL
+                db.put(101, 1).unwrap();
+                db.flush_memtable().unwrap();
+                db.put_with_ttl(101, 2, 5).unwrap();
+                assert_eq!(db.get(101), Some(2));
+                db.flush_memtable().unwrap();
+                clock.advance(5);
+                assert_eq!(db.get(101), None);
+                assert_eq!(db.get_ceiling(100), None);
+                assert_eq!(db.scan(100, 101), vec![]);
+
+                //putting again without a TTL clears the expiry
+                db.put_with_ttl(102, 1, 5).unwrap();
+                db.put(102, 2).unwrap();
+                clock.advance(5);
+                assert_eq!(db.get(102), Some(2));
+
+                //expiries survive a restart
+                db.put_with_ttl(103, 1, 5).unwrap();
+                let name = db.name();
+                db.close();
+                let mut db = open_in(db.storage(), &name).set_clock(clock.clone());
+                assert_eq!(db.get(103), Some(1));
+                assert_eq!(db.get(101), None);
+                clock.advance(5);
+                assert_eq!(db.get(103), None);
+                db
+            };
+            setup_and_test_and_cleaup(format!("ttl_test_{i}"), &mut alterations, &mut test);
+        }
+    }
+
+    #[test]
+    fn ttl_compaction_test() {
+        let clock = ManualClock::new(1_000);
+        let mut alterations = |db: Database| {
+            testing::part3_db_alterations(db)
+                .set_memtable_capacity(8)
+                .set_clock(clock.clone())
+        };
+        let mut test = |mut db: Database| {
+            for key in 0..200 {
+                db.put_with_ttl(key, key, 50 + key as u64 % 2 * 1_000)
+                    .unwrap();
+            }
+            db.flush_memtable().unwrap();
+            assert!(db.metadata.entry_counts.len() > 2);
+            let expiries: Size = db.metadata.expiry_counts.iter().flatten().sum();
+            assert_eq!(expiries, db.level_shape().total_entries());
+            for key in 0..200 {
+                assert_eq!(db.get(key), Some(key));
+            }
+
+            //compaction turns expired entries into tombstones, the last level drops them
+            clock.advance(50);
+            let dropped_before = db.metadata.compaction_entries_dropped;
+            for key in 1_000..1_200 {
+                db.put(key, key).unwrap();
+            }
+            db.flush_memtable().unwrap();
+            assert!(db.metadata.compaction_entries_dropped > dropped_before);
+            let live: Vec<Entry> = (0..200)
+                .filter(|key| key % 2 == 1)
+                .map(|key| (key, key))
+                .collect();
+            assert_eq!(db.scan(0, 199), live);
+            for key in 0..200 {
+                let expected = if key % 2 == 0 { None } else { Some(key) };
+                assert_eq!(db.get(key), expected, "key {key}");
+            }
+            db
+        };
+        setup_and_test_and_cleaup("ttl_compaction_test", &mut alterations, &mut test);
+    }
+
+    #[cfg(feature = "btree")]
+    #[test]
+    fn foreign_page_size_runs_test() {
+        let mut alterations = |db: Database| {
+            testing::part2_db_alterations(db)
+                .set_memtable_capacity(system_info::num_entries_per_page() * 20)
+                .set_enable_bloom_filter(true)
+                .set_enable_fence_index(true)
+        };
+        let mut test = |mut db: Database| {
+            let num_entries = db.memtable_capacity();
+            for key in 0..num_entries as Key * 2 {
+                match key % 100 {
+                    0 => db.put_with_ttl(key, key * 10, 3600).unwrap(), //expiry sidecars too
+                    _ => db.put(key, key * 10).unwrap(),
+                }
+            }
+            db.flush_memtable().unwrap();
+            assert_eq!(
+                db.metadata.entry_counts,
+                vec![vec![num_entries, num_entries]]
+            );
+
+            //pretend both runs were copied over from machines with other page sizes
+            let host = system_info::PageLayout::host();
+            let layouts = [
+                system_info::PageLayout::new(host.page_size() / 2),
+                system_info::PageLayout::new(host.page_size() * 2),
+            ];
+            for (run, layout) in layouts.into_iter().enumerate() {
+                let run_address = &(&db.name, 0, run);
+                db.storage().remove_file(&run_address.path_btree()).unwrap();
+                crate::sst::btree_sst::Sst::new(db.key_codec())
+                    .set_layout(layout)
+                    .write_inner_nodes(run_address, num_entries)
+                    .unwrap();
+                db.metadata.page_sizes[0][run] = layout.page_size();
+            }
+            db.metadata_dirty = true;
+            let name = db.name();
+            db.close();
+
+            let mut db = open_in(db.storage(), &name);
+            assert_eq!(
+                db.metadata.page_sizes,
+                vec![layouts.map(|layout| layout.page_size()).to_vec()]
+            );
+            let runs = db.list_runs();
+            assert_eq!(
+                runs.iter().map(|info| info.page_size).collect::<Vec<_>>(),
+                vec![layouts[1].page_size(), layouts[0].page_size()] //youngest first
+            );
+            let layout = db.describe_layout();
+            for page_size in layouts.map(|layout| layout.page_size()) {
+                assert!(
+                    layout.contains(&format!("{page_size} byte pages")),
+                    "{layout}"
+                );
+            }
+            //bloom filters and sidecars are flat arrays, fence indexes are only used for runs with this machine's pages
+            for key in (0..num_entries as Key * 2).step_by(61) {
+                assert_eq!(db.get(key), Some(key * 10));
+            }
+            assert_eq!(db.get(300), Some(3000));
+            assert_eq!(db.get(num_entries as Key * 2), None);
+            let first_leaf_end = layouts[0].entries_per_page() as Key;
+            assert_eq!(
+                db.scan(first_leaf_end - 1, first_leaf_end),
+                vec![
+                    (first_leaf_end - 1, (first_leaf_end - 1) * 10),
+                    (first_leaf_end, first_leaf_end * 10)
+                ]
+            );
+            assert_eq!(db.scan(0, Key::MAX).len(), num_entries * 2);
+            assert_eq!(
+                db.get_ceiling(num_entries as Key),
+                Some((num_entries as Key, num_entries as Key * 10))
+            );
+
+            //runs written from here on use this machine's page size
+            db.put(-1, 1).unwrap();
+            db.flush_memtable().unwrap();
+            assert_eq!(db.metadata.page_sizes[0][2], host.page_size());
+            assert!(!db
+                .describe_layout()
+                .contains(&format!("{} byte pages", host.page_size())));
+            db
+        };
+        setup_and_test_and_cleaup("foreign_page_size_runs_test", &mut alterations, &mut test);
+    }
+
+    #[test]
+    fn io_stats_test() {
+        let mut alterations =
+            |db: Database| testing::part1_db_alterations(db).set_memtable_capacity(2);
+        let mut test = |mut db: Database| {
+            //3 runs on level 0, each fits in a page
+            for key in 1..=6 {
+                db.put(key, key * 10).unwrap();
+            }
+            db.reset_io_stats();
+            db.flush_memtable().unwrap();
+            assert_eq!(db.metadata.entry_counts, vec![vec![2, 2, 2]]);
+            assert_eq!(
+                db.io_stats(),
+                IoStats {
+                    pages_written: 1, //nothing is synced under the default sync policy
+                    ..Default::default()
+                }
+            );
+
+            db.reset_io_stats();
+            db.put(100, 1).unwrap();
+            assert_eq!(db.get(100), Some(1));
+            assert_eq!(db.io_stats(), IoStats::default()); //served from the memtable
+
+            //every run is searched, youngest first
+            assert_eq!(db.get(1), Some(10));
+            assert_eq!(db.io_stats().pages_read, 3);
+            db.reset_io_stats();
+            assert_eq!(db.get(999), None);
+            assert_eq!(db.io_stats().pages(), 3);
+
+            //checking gets against full reads of the runs isn't counted, and stays out of the buffer pool below
+            let mut db = db.set_paranoid_reads(true);
+            db.reset_io_stats();
+            assert_eq!(db.get(1), Some(10));
+            assert_eq!(db.io_stats().pages_read, 3);
+
+            if !cfg!(feature = "buffer-pool") {
+                return db;
+            }
+            //second lookup of the same pages is served by the buffer pool
+            let mut db = db
+                .set_enable_buffer_pool(true)
+                .set_buffer_pool_capacity_pages(10);
+            db.reset_io_stats();
+            db.get(1);
+            db.get(1);
+            let stats = db.io_stats();
+            assert_eq!(stats.pages_read, 3);
+            assert_eq!((stats.buffer_pool_hits, stats.buffer_pool_misses), (3, 3));
+
+            db
+        };
+        setup_and_test_and_cleaup("io_stats_test", &mut alterations, &mut test);
+    }
+
+    #[test]
+    fn crash_points_test() {
+        let run_capacity = 2 * system_info::num_entries_per_page() + 1; //B-trees have inner nodes
+        let mut alterations = |db: Database| {
+            testing::part1_db_alterations(db)
+                .set_sst_implementation(SstImplementation::Btree)
+                .set_memtable_capacity(run_capacity)
+                .set_enable_bloom_filter(true)
+                .set_enable_fence_index(true)
+        };
+        fn tmp_files(directory: &Path) -> Vec<PathBuf> {
+            let mut found = Vec::new();
+            for entry in fs::read_dir(directory).unwrap() {
+                let path = entry.unwrap().path();
+                match path.is_dir() {
+                    true => found.extend(tmp_files(&path)),
+                    false if filename::is_tmp_path(&path) => found.push(path),
+                    false => {}
+                }
+            }
+            found
+        }
+        let mut test = |mut db: Database| {
+            //copies of the database's directory before every file change, each is what a crash there leaves behind
+            let crashes = db.path().parent().unwrap().join("crashes");
+            direct_io::tests::record_crash_points(db.path(), &crashes);
+            let num_keys = 3 * run_capacity as Key + 5;
+            for key in 0..num_keys {
+                match key % 4 {
+                    0 => db.put_with_ttl(key, -key, 3600).unwrap(), //runs get expiry sidecars too
+                    _ => db.put(key, -key).unwrap(),
+                }
+            }
+            db.flush().unwrap();
+            let crashes = direct_io::tests::stop_recording_crash_points(db.path());
+            assert!(crashes.len() > 3 * 6, "{} crash points", crashes.len());
+
+            let mut num_interrupted_writes = 0;
+            for crash in &crashes {
+                num_interrupted_writes += !tmp_files(crash).is_empty() as Size;
+                let mut recovered = Database::try_open(crash).unwrap();
+                assert_eq!(tmp_files(crash), Vec::<PathBuf>::new());
+                //every file of a run the metadata refers to is complete
+                let entry_counts = recovered.metadata.entry_counts.clone();
+                for (level, level_entry_counts) in entry_counts.iter().enumerate() {
+                    for (run, &num_entries) in level_entry_counts.iter().enumerate() {
+                        let run_address = &(&recovered.name, level, run);
+                        let sst_len = fs::metadata(run_address.path_sst()).unwrap().len();
+                        assert_eq!(sst_len, (num_entries * ENTRY_SIZE) as u64);
+                        let entries = recovered.sst_interface().read(run_address).unwrap();
+                        assert_eq!(entries.len(), num_entries);
+                    }
+                }
+                //and its keys are found through the run's bloom filter, B-tree, fence index and expiry sidecar
+                let num_flushed = entry_counts.iter().flatten().sum::<Size>() as Key;
+                assert!(num_flushed < num_keys);
+                for key in (0..num_flushed).step_by(17) {
+                    assert_eq!(recovered.get(key), Some(-key), "key {key} in {crash:?}");
+                }
+                assert_eq!(recovered.get(num_flushed), None);
+                recovered.close();
+            }
+            assert!(num_interrupted_writes > 0);
+            db
+        };
+        setup_and_test_and_cleaup_on_disk("crash_points_test", &mut alterations, &mut test);
+    }
+
+    #[test]
+    fn truncate_test() {
+        let mut alterations = |db: Database| {
+            testing::part2_db_alterations(db)
+                .set_compaction_policy(CompactionPolicy::Leveled)
+                .set_memtable_capacity(4)
+                .set_enable_bloom_filter(true)
+        };
+        let mut test = |mut db: Database| {
+            let num_keys = 100;
+            for key in 0..num_keys {
+                db.put(key, key).unwrap();
+            }
+            assert!(db.metadata.entry_counts.len() > 1);
+            assert!(db.list_runs().iter().map(|run| run.bytes).sum::<Size>() > 0);
+            let last_sequence = db.last_sequence();
+            let mut snapshot = db.snapshot_view();
+            let mut iter = db.iter_range(0, num_keys);
+            assert_eq!(iter.next().unwrap().unwrap(), (0, 0));
+
+            db.truncate().unwrap();
+            for key in 0..num_keys {
+                assert_eq!(db.get(key), None);
+            }
+            assert_eq!(db.scan(0, num_keys), vec![]);
+            assert_eq!(db.list_runs(), vec![]);
+            assert_eq!(db.metadata.entry_counts, vec![vec![]]);
+            assert_eq!(
+                Database::level_directories(&db.name).unwrap(),
+                Vec::<PathBuf>::new()
+            );
+            assert_eq!(db.buffer_pool.borrow().resident_pages(db.path()).len(), 0);
+            assert_eq!(db.last_sequence(), last_sequence);
+            //what was taken before can't be read anymore
+            assert!(matches!(snapshot.try_get(0), Err(DbError::Truncated)));
+            assert!(matches!(
+                snapshot.try_scan(0, num_keys),
+                Err(DbError::Truncated)
+            ));
+            assert!(matches!(iter.check(), Err(DbError::Truncated)));
+            //the iterator reports the truncation once instead of just ending
+            assert_eq!(iter.len(), 1);
+            assert!(matches!(iter.next(), Some(Err(DbError::Truncated))));
+            assert_eq!(iter.len(), 0);
+            assert!(iter.next().is_none());
+            assert!(iter.next_back().is_none());
+            drop(snapshot);
+
+            //and the database works as before
+            for key in 0..num_keys {
+                db.put(key, -key).unwrap();
+            }
+            assert!(db.metadata.entry_counts.len() > 1);
+            assert!(db.last_sequence() > last_sequence);
+            let mut snapshot = db.snapshot_view();
+            assert_eq!(snapshot.try_get(7).unwrap(), Some(-7));
+            let iter = db.iter_range(0, num_keys);
+            iter.check().unwrap();
+            assert_eq!(iter.count(), num_keys as Size);
+            let name = db.path().to_path_buf();
+            db.close();
+            let mut db = open_in(db.storage(), &name);
+            for key in 0..num_keys {
+                assert_eq!(db.get(key), Some(-key));
+            }
+            db
+        };
+        setup_and_test_and_cleaup("truncate_test", &mut alterations, &mut test);
+    }
+
+    #[test]
+    fn truncate_crash_points_test() {
+        let mut alterations = |db: Database| {
+            testing::part1_db_alterations(db)
+                .set_sst_implementation(SstImplementation::Btree)
+                .set_compaction_policy(CompactionPolicy::Leveled)
+                .set_memtable_capacity(4)
+                .set_enable_bloom_filter(true)
+        };
+        let mut test = |mut db: Database| {
+            let num_keys = 40;
+            for key in 0..num_keys {
+                db.put(key, key).unwrap();
+            }
+            db.flush().unwrap();
+            assert!(db.metadata.entry_counts.len() > 1);
+            let crashes = db.path().parent().unwrap().join("crashes");
+            direct_io::tests::record_crash_points(db.path(), &crashes);
+            db.truncate().unwrap();
+            let crashes = direct_io::tests::stop_recording_crash_points(db.path());
+            assert!(crashes.len() > 3, "{} crash points", crashes.len());
+
+            //a crash leaves either every entry or none of them, with none of their files
+            let mut num_truncated = 0;
+            for crash in &crashes {
+                let mut recovered = Database::try_open(crash).unwrap();
+                match recovered.get(0) {
+                    Some(_) => {
+                        for key in 0..num_keys {
+                            assert_eq!(recovered.get(key), Some(key), "key {key} in {crash:?}");
+                        }
+                    }
+                    None => {
+                        num_truncated += 1;
+                        assert_eq!(recovered.scan(0, num_keys), vec![], "{crash:?}");
+                        assert_eq!(recovered.metadata.entry_counts, vec![vec![]]);
+                        assert_eq!(
+                            Database::level_directories(&recovered.name).unwrap(),
+                            Vec::<PathBuf>::new()
+                        );
+                        assert!(!db.storage().path_exists(&filename::truncate_marker(crash)));
+                    }
+                }
+                recovered.close();
+            }
+            assert!(num_truncated > 0 && num_truncated < crashes.len());
+            db
+        };
+        setup_and_test_and_cleaup_on_disk(
+            "truncate_crash_points_test",
+            &mut alterations,
+            &mut test,
+        );
+    }
+
+    #[test]
+    fn buffer_pool_warm_start_test() {
+        let mut alterations = |db: Database| {
+            testing::part1_db_alterations(db)
+                .set_memtable_capacity(2)
+                .set_enable_buffer_pool(true)
+                .set_buffer_pool_capacity_pages(10)
+                .set_enable_bloom_filter(true)
+                .set_persist_buffer_pool(true)
+        };
+        let mut test = |mut db: Database| {
+            if !cfg!(feature = "buffer-pool") {
+                return db;
+            }
+            //3 runs on level 0, a get of a key in the oldest one reads a page of each
+            for key in 1..=6 {
+                db.put(key, key * 10).unwrap();
+            }
+            db.flush_memtable().unwrap();
+            assert_eq!(db.get(1), Some(10));
+            let name = db.path().to_path_buf();
+            let warm_file = filename::buffer_pool_warm(&name);
+            db.close();
+            assert!(db.storage().path_exists(&warm_file));
+
+            let mut db = open_in(db.storage(), &name);
+            assert!(!db.storage().path_exists(&warm_file)); //read back in by open
+            db.reset_io_stats();
+            assert_eq!(db.get(1), Some(10));
+            assert_eq!(db.io_stats().pages_read, 0);
+
+            //runs the warm-start file lists are compacted away before it's used
+            let mut db = db.set_warm_up(WarmUp::Manual);
+            db.close();
+            let mut db = open_in(db.storage(), &name);
+            assert!(!db.warm_pages.is_empty());
+            db.compact_level_zero().unwrap();
+            assert_eq!(db.metadata.entry_counts, vec![vec![6]]);
+            let num_pages = db.warm_up().unwrap();
+            assert!(num_pages < 3, "{num_pages} pages read in");
+            assert_eq!(db.warm_up().unwrap(), 0); //nothing left to read in
+            assert_eq!(db.get(1), Some(10));
+
+            //nothing is saved with the setting off
+            let mut db = db.set_persist_buffer_pool(false);
+            db.close();
+            assert!(!db.storage().path_exists(&warm_file));
+            open_in(db.storage(), &name)
+        };
+        setup_and_test_and_cleaup("buffer_pool_warm_start_test", &mut alterations, &mut test);
+    }
+
+    #[test]
+    fn corrupt_warm_file_test() {
+        let mut alterations = |db: Database| {
+            testing::part1_db_alterations(db)
+                .set_memtable_capacity(2)
+                .set_enable_buffer_pool(true)
+                .set_persist_buffer_pool(true)
+        };
+        let mut test = |mut db: Database| {
+            for key in 1..=4 {
+                db.put(key, key * 10).unwrap();
+            }
+            let name = db.path().to_path_buf();
+            let warm_file = filename::buffer_pool_warm(&name);
+            db.close();
+            db.storage().write_file(&warm_file, &[0xff; 3]).unwrap();
+
+            //it's only a hint, open goes on without it
+            let mut db = open_in(db.storage(), &name);
+            assert!(db.warm_pages.is_empty());
+            assert!(!db.storage().path_exists(&warm_file));
+            assert_eq!(db.get(1), Some(10));
+            db
+        };
+        setup_and_test_and_cleaup("corrupt_warm_file_test", &mut alterations, &mut test);
+    }
+
+    #[test]
+    fn page_buffers_test() {
+        //a cold scan of a large run reads every page through a buffer from the free list, instead of allocating one
+        // for each
+        const NUM_PAGES: Size = 64;
+        let mut alterations = |db: Database| {
+            testing::part1_db_alterations(db)
+                .set_memtable_capacity(system_info::num_entries_per_page())
+        };
+        let mut test = |mut db: Database| {
+            let num_keys = (NUM_PAGES * system_info::num_entries_per_page()) as Key;
+            let entries: Vec<Entry> = (0..num_keys).map(|key| (key, key * 2)).collect();
+            db.put_sorted_batch(&entries).unwrap();
+            db.flush().unwrap();
+            db.compact_level_zero().unwrap();
+
+            db.reset_io_stats();
+            let baseline = db.page_buffer_stats();
+            assert_eq!(db.scan(0, num_keys), entries);
+            let pages_read = db.io_stats().pages_read;
+            let stats = db.page_buffer_stats().since(&baseline);
+            assert!(pages_read >= NUM_PAGES, "{pages_read} pages read");
+            assert!(stats.allocations * 10 <= pages_read, "{stats:?}");
+            assert!(stats.reuses >= pages_read - stats.allocations, "{stats:?}");
+            db
+        };
+        setup_and_test_and_cleaup("page_buffers_test", &mut alterations, &mut test);
+    }
+
+    #[cfg(feature = "bloom")]
+    #[test]
+    fn io_stats_bloom_test() {
+        let mut alterations = |db: Database| {
+            testing::part1_db_alterations(db)
+                .set_memtable_capacity(2)
+                .set_enable_bloom_filter(true)
+        };
+        let mut test = |mut db: Database| {
+            for key in 1..=6 {
+                db.put(key, key * 10).unwrap();
+            }
+            db.flush_memtable().unwrap();
+            db.reset_io_stats();
+            assert_eq!(db.get(999), None);
+
+            //bloom filter pages are read too, runs they rule out aren't searched
+            let stats = db.io_stats();
+            assert_eq!(stats.bloom_probes, 3);
+            let runs_searched = 3 - stats.bloom_negatives;
+            assert_eq!(stats.pages_read, 3 + runs_searched);
+            db
+        };
+        setup_and_test_and_cleaup("io_stats_bloom_test", &mut alterations, &mut test);
+    }
+
+    #[test]
+    fn yield_hook_test() {
+        let entries_per_page = system_info::num_entries_per_page();
+        let num_keys = (4 * entries_per_page) as Key;
+        //the hook counts its calls and aborts every call past the limit
+        let calls = Rc::new(std::cell::Cell::new(0));
+        let limit = Rc::new(std::cell::Cell::new(usize::MAX));
+        let mut alterations = |db: Database| {
+            let (calls, limit) = (Rc::clone(&calls), Rc::clone(&limit));
+            testing::part1_db_alterations(db)
+                .set_memtable_capacity(num_keys as Size)
+                .set_yield_hook(move || {
+                    calls.set(calls.get() + 1);
+                    match calls.get() > limit.get() {
+                        true => ControlFlow::Break(()),
+                        false => ControlFlow::Continue(()),
+                    }
+                })
+        };
+        let mut test = |mut db: Database| {
+            let abort_after = |num_calls| {
+                calls.set(0);
+                limit.set(num_calls);
+            };
+            //a flush stops after the page the hook aborted on, the memtable keeps its entries
+            for key in 0..num_keys {
+                db.put(key, key).unwrap();
+            }
+            abort_after(2);
+            assert!(matches!(db.flush(), Err(DbError::Aborted)));
+            assert_eq!(calls.get(), 3);
+            assert_eq!(db.sst_count(0), 0);
+            assert_eq!(db.get(num_keys - 1), Some(num_keys - 1));
+            abort_after(usize::MAX);
+            db.flush().unwrap();
+            assert_eq!(calls.get(), 4);
+
+            //so does a compaction, the level keeps its input runs
+            for key in 0..num_keys {
+                db.put(key, -key).unwrap();
+            }
+            db.flush().unwrap();
+            abort_after(1);
+            let why = db.compact_level_zero().unwrap_err();
+            assert!(yield_hook::is_aborted(&why), "{why}");
+            assert_eq!(calls.get(), 2);
+            assert_eq!(db.metadata.entry_counts[0].len(), 2);
+            let staging_address = (&db.name, 0, 2);
+            assert!(!db.storage().path_exists(&staging_address.path_sst()));
+            assert_eq!(db.get(0), Some(0));
+            assert_eq!(db.get(1), Some(-1));
+
+            //scans that can report an abort call the hook every page worth of entries
+            let expected: Vec<Entry> = (0..num_keys).map(|key| (key, -key)).collect();
+            abort_after(1);
+            assert!(matches!(
+                db.try_scan_with_options(0, num_keys, ScanOptions::default()),
+                Err(DbError::Aborted)
+            ));
+            assert_eq!(calls.get(), 2);
+            abort_after(0);
+            assert_eq!(db.scan(0, num_keys), expected);
+            assert_eq!(calls.get(), 0);
+
+            abort_after(usize::MAX);
+            db.compact_level_zero().unwrap();
+            assert_eq!(db.metadata.entry_counts[0], vec![num_keys as Size]);
+            assert_eq!(
+                db.try_scan_with_options(0, num_keys, ScanOptions::default())
+                    .unwrap(),
+                expected
+            );
+            db
+        };
+        setup_and_test_and_cleaup("yield_hook_test", &mut alterations, &mut test);
+    }
+
+    #[test]
+    fn snapshot_view_test() {
+        let mut alterations =
+            |db: Database| testing::part1_db_alterations(db).set_memtable_capacity(4);
+        let mut test = |mut db: Database| {
+            //two runs at level 0, the rest in the memtable
+            for key in 0..10 {
+                db.put(key, key).unwrap();
+            }
+            let old_entries: Vec<Entry> = (0..10).map(|key| (key, key)).collect();
+            let mut snapshot = db.snapshot_view();
+
+            for key in 0..10 {
+                match key % 3 {
+                    0 => db.delete(key).unwrap(),
+                    _ => db.put(key, key * 100).unwrap(),
+                }
+            }
+            db.flush_memtable().unwrap();
+            db.compact_level_zero().unwrap();
+            assert_eq!(db.metadata.entry_counts[0].len(), 1);
+            let new_entries: Vec<Entry> = (0..10)
+                .filter(|key| key % 3 != 0)
+                .map(|key| (key, key * 100))
+                .collect();
+            for key in 0..10 {
+                assert_eq!(snapshot.get(key), Some(key));
+            }
+            assert_eq!(snapshot.scan(0, 9), old_entries);
+            assert_eq!(db.scan(0, 9), new_entries);
+
+            //a live snapshot outlives a reopen, the directory of one that was never dropped doesn't
+            let name = db.path().to_path_buf();
+            let stale = filename::snapshot_directory(&name, usize::MAX);
+            db.storage().create_dir_all(&stale.join("0")).unwrap();
+            db.close();
+            let mut db = open_in(db.storage(), &name);
+            assert!(!db.storage().path_exists(&stale));
+            assert_eq!(snapshot.scan(0, 9), old_entries);
+
+            //a second snapshot sees the new values, dropping the first removes its links
+            let mut second = db.snapshot_view();
+            db.put(1, -1).unwrap();
+            assert_eq!(second.scan(0, 9), new_entries);
+            assert_eq!(db.get(1), Some(-1));
+            let directory = snapshot.path().to_path_buf();
+            drop(snapshot);
+            assert!(!db.storage().path_exists(&directory));
+            assert_eq!(second.get(2), Some(200));
+            db
+        };
+        setup_and_test_and_cleaup("snapshot_view_test", &mut alterations, &mut test);
+    }
+
+    #[cfg(feature = "bloom")]
+    #[test]
+    fn explain_get_test() {
+        let mut alterations = |db: Database| {
+            testing::part1_db_alterations(db)
+                .set_memtable_capacity(8)
+                .set_enable_bloom_filter(true)
+        };
+        let mut test = |mut db: Database| {
+            //a run at level 2, then a run at level 0 of other keys
+            for key in 100..108 {
+                db.put(key, key * 10).unwrap();
+            }
+            db.flush_memtable().unwrap();
+            db.move_runs(0);
+            db.move_runs(1);
+            for key in 0..8 {
+                db.put(key, key).unwrap();
+            }
+            db.flush_memtable().unwrap();
+            assert_eq!(db.metadata.entry_counts, vec![vec![8], vec![], vec![8]]);
+
+            //with a bit per entry, some level 2 key gets past the level 0 filter
+            let trace = (100..108)
+                .map(|key| db.explain_get(key))
+                .find(|trace| trace.runs[0].is_bloom_false_positive())
+                .expect("no bloom filter false positive at level 0");
+            let key = trace.key;
+            assert_eq!(trace.memtable, MemtableCheck::Absent);
+            assert!(!trace.read_cache_hit);
+            assert_eq!(
+                trace
+                    .runs
+                    .iter()
+                    .map(|run| (run.level, run.run))
+                    .collect::<Vec<_>>(),
+                vec![(0, 0), (2, 0)]
+            );
+            assert_eq!(trace.runs[0].search, Some(SearchMethod::Array));
+            assert_eq!(trace.runs[0].outcome, RunOutcome::NotFound);
+            assert_eq!(trace.runs[1].bloom, BloomProbe::Positive);
+            assert_eq!(
+                trace.runs[1].outcome,
+                RunOutcome::Found {
+                    value: key * 10,
+                    expiry: None
+                }
+            );
+            //each run costs its filter's page and the page its key would be on
+            assert!(trace.runs.iter().all(|run| run.io.pages_read == 2));
+            assert_eq!(trace.io.pages_read, 4);
+            assert_eq!(trace.io.bloom_probes, 2);
+            assert_eq!(trace.resolution, Resolution::Run { level: 2, run: 0 });
+            assert_eq!(trace.value, Some(key * 10));
+            assert_eq!(db.get(key), trace.value);
+            assert!(trace.to_string().contains("level 2, run 0: bloom Positive"));
+
+            //keys the filters rule out aren't searched, and the memtable answers before any run
+            let trace = db.explain_get(1000);
+            assert!(trace
+                .runs
+                .iter()
+                .all(|run| run.outcome == RunOutcome::Filtered || run.is_bloom_false_positive()));
+            assert_eq!(trace.resolution, Resolution::NotFound);
+            assert_eq!(trace.value, None);
+            db.delete(3).unwrap();
+            let trace = db.explain_get(3);
+            assert_eq!(trace.memtable, MemtableCheck::Deleted);
+            assert_eq!(trace.resolution, Resolution::Memtable);
+            assert!(trace.runs.is_empty());
+            db
+        };
+        setup_and_test_and_cleaup("explain_get_test", &mut alterations, &mut test);
+    }
+
+    #[cfg(feature = "bloom")]
+    #[test]
+    fn may_contain_test() {
+        use rand::{Rng, SeedableRng};
+        let num_keys: Key = 1000;
+        let mut alterations = |db: Database| {
+            testing::part3_db_alterations(db)
+                .set_bloom_levels(BloomLevels::All)
+                .set_bloom_filter_bits_per_entry(1) //plenty of false positives
+                .set_memtable_capacity(16)
+                .set_read_cache_capacity(0) //every may_contain gets to the filters
+        };
+        let mut test = |mut db: Database| {
+            let mut rng = rand::rngs::StdRng::seed_from_u64(0);
+            let mut expected = BTreeMap::new();
+            for _ in 0..600 {
+                let key = rng.gen_range(0..num_keys);
+                if rng.gen_bool(0.2) {
+                    db.delete(key).unwrap();
+                    expected.remove(&key);
+                } else {
+                    db.put(key, key).unwrap();
+                    expected.insert(key, key);
+                }
+            }
+            let check = |db: &mut Database, expected: &BTreeMap<Key, Value>| {
+                let mut false_positives = 0;
+                for key in 0..num_keys {
+                    let has_value = expected.contains_key(&key);
+                    assert_eq!(db.contains(key), has_value, "key {key}");
+                    match (db.may_contain(key), has_value) {
+                        (false, true) => panic!("may_contain({key}) is a false negative"),
+                        (true, false) => false_positives += 1,
+                        _ => {}
+                    }
+                }
+                false_positives
+            };
+            assert!(check(&mut db, &expected) > 0);
+
+            //a tombstone in the memtable hides the runs' values
+            let deleted: Vec<Key> = expected.keys().copied().take(5).collect();
+            for &key in &deleted {
+                db.delete(key).unwrap();
+                expected.remove(&key);
+                assert!(!db.may_contain(key));
+                assert!(!db.contains(key));
+            }
+            check(&mut db, &expected);
+
+            //only bloom filter pages are read: with the runs' entries gone any read of them fails. At 1 bit per
+            // entry each probe reads at most one page
+            db.flush().unwrap();
+            for (level, entry_counts) in db.metadata.entry_counts.iter().enumerate() {
+                for run in 0..entry_counts.len() {
+                    let run_address = (&db.name, level, run);
+                    db.storage().remove_file(&run_address.path_sst()).unwrap();
+                }
+            }
+            db.reset_io_stats();
+            for key in 0..num_keys {
+                assert!(!expected.contains_key(&key) || db.may_contain(key));
+            }
+            let io = db.io_stats();
+            assert!(io.bloom_probes > 0);
+            assert!(io.pages_read <= io.bloom_probes, "{io:?}");
+            db
+        };
+        setup_and_test_and_cleaup("may_contain_test", &mut alterations, &mut test);
+    }
+
+    #[test]
+    fn estimate_scan_count_test() {
+        let entries_per_page = system_info::num_entries_per_page() as Key;
+        let ranges = |max_key: Key| {
+            [
+                (0, max_key),
+                (-100, 10),
+                (max_key / 3, max_key / 2),
+                (entries_per_page * 3 - 1, entries_per_page * 9 + 1), //bounds between keys
+                (max_key - 5, max_key + 100),
+                (max_key + 1, max_key + 100),
+                (10, 9),
+            ]
+        };
+
+        //a single B-tree run is counted exactly, reading a node and a leaf for each bound
+        if cfg!(feature = "btree") {
+            let num_entries = entries_per_page * 8;
+            let mut alterations = |db: Database| {
+                testing::part2_db_alterations(db)
+                    .set_enable_buffer_pool(false)
+                    .set_memtable_capacity(num_entries as Size)
+            };
+            let mut test = |mut db: Database| {
+                for key in 0..num_entries {
+                    db.put(key * 3, key).unwrap();
+                }
+                db.flush().unwrap();
+                assert_eq!(db.sst_total(), 1);
+                for (key1, key2) in ranges(num_entries * 3) {
+                    db.reset_io_stats();
+                    let estimate = db.estimate_scan_count(key1, key2);
+                    assert!(db.io_stats().pages_read <= 4, "{:?}", db.io_stats());
+                    assert_eq!(estimate, db.scan(key1, key2).len(), "[{key1}, {key2}]");
+                }
+                db
+            };
+            setup_and_test_and_cleaup("estimate_scan_count_btree", &mut alterations, &mut test);
+        }
+
+        //array runs are interpolated from their fence indexes, the memtable is counted exactly
+        let num_runs = 4;
+        let run_entries = entries_per_page * 10 + 7;
+        let mut alterations = |db: Database| {
+            testing::part2_db_alterations(db)
+                .set_sst_implementation(SstImplementation::Array)
+                .set_enable_fence_index(true)
+                .set_enable_buffer_pool(false)
+                .set_memtable_capacity(run_entries as Size)
+        };
+        let mut test = |mut db: Database| {
+            //runs of disjoint, evenly spread keys, and a few memtable entries (one of them a tombstone)
+            let max_key = num_runs * run_entries * 2;
+            for key in 0..num_runs * run_entries {
+                db.put(key * 2, key).unwrap();
+            }
+            db.flush().unwrap();
+            for key in [1, 3, 5, max_key + 1] {
+                db.put(key, key).unwrap();
+            }
+            db.delete(7).unwrap();
+            assert_eq!(db.estimate_scan_count(0, 7), 4 + 3);
+
+            let estimates: Vec<Size> = ranges(max_key)
+                .into_iter()
+                .map(|(key1, key2)| {
+                    let estimate = db.estimate_scan_count(key1, key2);
+                    let actual = db.scan(key1, key2).len();
+                    //off by at most an entry at each bound of each run
+                    assert!(
+                        estimate.abs_diff(actual) <= 2 * num_runs as Size,
+                        "[{key1}, {key2}]: {estimate} estimated, {actual} scanned"
+                    );
+                    estimate
+                })
+                .collect();
+
+            //with the runs' entries gone any read of them fails, only fence pages are read
+            for (level, entry_counts) in db.metadata.entry_counts.iter().enumerate() {
+                for run in 0..entry_counts.len() {
+                    let run_address = (&db.name, level, run);
+                    db.storage().remove_file(&run_address.path_sst()).unwrap();
+                }
+            }
+            for ((key1, key2), estimate) in ranges(max_key).into_iter().zip(estimates) {
+                assert_eq!(db.estimate_scan_count(key1, key2), estimate);
+            }
+            db
+        };
+        setup_and_test_and_cleaup("estimate_scan_count_test", &mut alterations, &mut test);
+    }
+
+    #[cfg(feature = "bloom")]
+    #[test]
+    fn filter_consistency_test() {
+        use crate::filter::bloom_util::bitmap_len;
+        let events: Arc<Mutex<Vec<DbEvent>>> = Arc::default();
+        let recorded = Arc::clone(&events);
+        let mut alterations = |db: Database| {
+            let recorded = Arc::clone(&recorded);
+            testing::part1_db_alterations(db)
+                .set_compaction_policy(CompactionPolicy::None)
+                .set_memtable_capacity(64)
+                .set_enable_bloom_filter(true)
+                .set_event_listener(move |event| {
+                    if matches!(event, DbEvent::FilterRepaired { .. }) {
+                        recorded.lock().unwrap().push(event)
+                    }
+                })
+        };
+        let filter_len = |run_address: &RunAddress| {
+            (run_address.0.storage())
+                .open_read_buffered(&run_address.path_bloom())
+                .and_then(|file| file.len_bytes())
+                .unwrap() as Size
+        };
+        //one filter removed, one cut short to zeros that would rule out keys the run holds
+        let damage = |db: &Database| {
+            let name = &db.name;
+            db.storage()
+                .remove_file(&(name, 0, 0).path_bloom())
+                .unwrap();
+            let truncated = (name, 0, 2).path_bloom();
+            let len = filter_len(&(name, 0, 2));
+            let file = db.storage().open_write(&truncated).unwrap();
+            file.set_len(len as u64 / 2).unwrap();
+        };
+        let mut test = |mut db: Database| {
+            events.lock().unwrap().clear(); //a previous storage's run
+            for key in 0..256 {
+                db.put(key, key).unwrap();
+            }
+            db.flush().unwrap();
+            let num_runs = db.sst_count(0);
+            assert_eq!(num_runs, 4);
+            assert_eq!(db.verify_filters().unwrap(), vec![]);
+
+            damage(&db);
+            assert_eq!(db.verify_filters().unwrap(), vec![(0, 0), (0, 2)]);
+            assert_eq!(
+                *events.lock().unwrap(),
+                vec![
+                    DbEvent::FilterRepaired { level: 0, run: 0 },
+                    DbEvent::FilterRepaired { level: 0, run: 2 }
+                ]
+            );
+            for key in 0..256 {
+                assert_eq!(db.get(key), Some(key));
+            }
+
+            //open repairs them before anything is read
+            damage(&db);
+            let name = db.path().to_path_buf();
+            db.close();
+            let mut db = open_in(db.storage(), &name);
+            for run in 0..num_runs {
+                let run_address = &(&db.name, 0, run);
+                let expected = bitmap_len(
+                    db.metadata.entry_counts[0][run],
+                    db.bloom_filter_bits_per_entry(),
+                );
+                assert_eq!(filter_len(run_address), expected, "run {run}");
+            }
+            for key in 0..256 {
+                assert_eq!(db.get(key), Some(key));
+            }
+            assert_eq!(db.verify_filters().unwrap(), vec![]);
+            db
+        };
+        setup_and_test_and_cleaup("filter_consistency_test", &mut alterations, &mut test);
+    }
+
+    #[cfg(feature = "bloom")]
+    #[test]
+    fn bloom_rebuild_test() {
+        let test_dir = testing::test_dir("bloom_rebuild_test");
+        let db_name = test_dir.join("test");
+        if test_dir.exists() {
+            std::fs::remove_dir_all(test_dir).unwrap();
+        }
+        let rebuilt = Arc::new(Mutex::new(0));
+        let counter = Arc::clone(&rebuilt);
+        let mut db = Database::open(&db_name)
+            .set_enable_buffer_pool(false)
+            .set_compaction_policy(CompactionPolicy::None)
+            .set_memtable_capacity(64)
+            .set_enable_bloom_filter(true)
+            .set_bloom_filter_bits_per_entry(1)
+            .set_compaction_progress_callback(move |_, progress| {
+                if let CompactionProgress::FilterRebuilt { .. } = progress {
+                    *counter.lock().unwrap() += 1;
+                }
+            });
+        for key in 0..256 {
+            db.put(key * 2, key).unwrap();
+        }
+        db.flush_memtable().unwrap();
+        let num_runs = db.sst_count(0);
+        assert_eq!(num_runs, 4);
+
+        //every key is odd so every run is searched unless its filter rules it out
+        let bloom_negatives = |db: &mut Database| {
+            db.reset_io_stats();
+            for key in 0..256 {
+                assert_eq!(db.get(key * 2 + 1), None);
+            }
+            db.io_stats().bloom_negatives
+        };
+        let before = bloom_negatives(&mut db);
+        db.set_bloom_bits_per_entry_and_rebuild(10).unwrap();
+        let after = bloom_negatives(&mut db);
+        assert!(
+            after > before,
+            "{after} negatives after rebuild, {before} before"
+        );
+        assert_eq!(*rebuilt.lock().unwrap(), num_runs);
+        for run in 0..num_runs {
+            let run_address = &(&db.name, 0, run);
+            assert!(run_address.path_bloom().exists());
+            assert!(!filename::bloom_filter_rebuild_path(run_address).exists());
+        }
+        db.close();
+
+        //the new setting is saved with the filters it was used for
+        let mut db = Database::open(&db_name);
+        assert_eq!(db.bloom_filter_bits_per_entry(), 10);
+        assert_eq!(bloom_negatives(&mut db), after);
+        for key in 0..256 {
+            assert_eq!(db.get(key * 2), Some(key));
+        }
+        drop(db);
+        std::fs::remove_dir_all(test_dir).unwrap();
+    }
+
+    #[test]
+    fn unsorted_run_compaction_test() {
+        let mut alterations = |db: Database| {
+            testing::part1_db_alterations(db)
+                .set_compaction_policy(CompactionPolicy::Leveled)
+                .set_sst_size_ratio(10)
+        };
+        let mut test = |mut db: Database| {
+            let put_run = |db: &mut Database, keys: std::ops::Range<Key>| {
+                for key in keys {
+                    db.put(key, key * 10).unwrap();
+                }
+                db.flush_memtable().unwrap();
+            };
+            let write_level_one_run = |db: &Database, keys: &[Key]| {
+                let entries: Vec<Entry> = keys.iter().map(|key| (*key, key * 10)).collect();
+                let path = (&db.name, 1, 0).path_sst();
+                let mut file = db.storage().create(&path).unwrap();
+                crate::file_io::serde_entry::serialize_into(&mut file, &entries, db.key_codec())
+                    .unwrap();
+            };
+            put_run(&mut db, 0..8);
+            put_run(&mut db, 100..108); //moves the first run to level 1
+
+            //corrupt it, keys 0-3 and 6-7 can still be found by binary search
+            write_level_one_run(&db, &[0, 1, 2, 3, 5, 4, 6, 7]);
+
+            put_run(&mut db, 200..208);
+            let why = db.compaction_error().expect("compaction should fail");
+            assert_eq!(why.kind(), io::ErrorKind::InvalidData);
+            assert_eq!(db.level_shape().run_count(1), 2); //input runs stay in place
+            let staging_path = (&db.name, 1, 2).path_sst();
+            assert!(!db.storage().path_exists(&staging_path));
+            for key in [0, 3, 6, 100, 107] {
+                assert_eq!(db.get(key), Some(key * 10));
+            }
+
+            //the next compaction succeeds once the run is repaired
+            write_level_one_run(&db, &[0, 1, 2, 3, 4, 5, 6, 7]);
+            put_run(&mut db, 300..308);
+            assert!(db.compaction_error().is_none());
+            assert_eq!(db.level_shape().run_count(1), 1);
+            let expected: Vec<Entry> = (0..8)
+                .chain(100..108)
+                .chain(200..208)
+                .map(|key| (key, key * 10))
+                .collect();
+            assert_eq!(db.scan(0, 299), expected);
+            db
+        };
+        setup_and_test_and_cleaup("unsorted_run_compaction_test", &mut alterations, &mut test);
+    }
+
+    #[test]
+    fn failed_run_move_test() {
+        let mut alterations = |db: Database| {
+            testing::part1_db_alterations(db)
+                .set_compaction_policy(CompactionPolicy::Leveled)
+                .set_sst_size_ratio(10)
+        };
+        let mut test = |mut db: Database| {
+            let put_run = |db: &mut Database, keys: std::ops::Range<Key>| {
+                for key in keys {
+                    db.put(key, key * 10).unwrap();
+                }
+                db.flush_memtable().unwrap();
+            };
+            put_run(&mut db, 0..8);
+            //a stray file where the moved run would go makes the move fail
+            let stray_path = (&db.name, 1, 0).path_sst();
+            db.storage()
+                .create_dir_all(stray_path.parent().unwrap())
+                .unwrap();
+            db.storage().create(&stray_path).unwrap();
+
+            put_run(&mut db, 100..108);
+            let why = db.compaction_error().expect("moving the runs should fail");
+            assert_eq!(why.kind(), io::ErrorKind::AlreadyExists);
+            assert_eq!(db.level_shape().run_count(1), 0);
+            for key in [0, 7, 100, 107] {
+                assert_eq!(db.get(key), Some(key * 10));
+            }
+            db.storage().remove_file(&stray_path).unwrap();
+            db
+        };
+        setup_and_test_and_cleaup("failed_run_move_test", &mut alterations, &mut test);
+    }
+
+    #[test]
+    fn failed_merge_install_test() {
+        let mut alterations = |db: Database| {
+            testing::part1_db_alterations(db)
+                .set_compaction_policy(CompactionPolicy::Leveled)
+                .set_sst_size_ratio(10)
+                .set_enable_bloom_filter(true)
+        };
+        let mut test = |mut db: Database| {
+            let put_run = |db: &mut Database, keys: std::ops::Range<Key>| {
+                for key in keys {
+                    db.put(key, key * 10).unwrap();
+                }
+                db.flush_memtable().unwrap();
+            };
+            put_run(&mut db, 0..8);
+            put_run(&mut db, 100..108); //moves the first run to level 1
+
+            //a directory where the first run steps aside to makes the install fail part way (after its SST if it
+            // has a bloom filter too)
+            let retired_address = (&db.name, 1, 3);
+            let blocked_path = match db.enable_bloom_filter() {
+                true => retired_address.path_bloom(),
+                false => retired_address.path_sst(),
+            };
+            let first_sst_path = (&db.name, 1, 0).path_sst();
+            db.storage().create_dir_all(&blocked_path).unwrap();
+            put_run(&mut db, 200..208);
+            assert!(
+                db.compaction_error().is_some(),
+                "installing the run should fail"
+            );
+            assert_eq!(db.level_shape().run_count(1), 2); //input runs stay in place
+            assert!(fs::metadata(&first_sst_path).unwrap().is_file()); //renamed back
+            for key in [0, 7, 100, 107] {
+                assert_eq!(db.get(key), Some(key * 10));
+            }
+
+            //the next compaction succeeds, leaving none of the input runs' files behind
+            db.storage().remove_dir_all(&blocked_path).unwrap();
+            put_run(&mut db, 300..308);
+            assert!(
+                db.compaction_error().is_none(),
+                "{:?}",
+                db.compaction_error()
+            );
+            assert_eq!(db.level_shape().run_count(1), 1);
+            for run in 1..5 {
+                assert!(!db.storage().path_exists(&(&db.name, 1, run).path_sst()));
+            }
+            let expected: Vec<Entry> = (0..8)
+                .chain(100..108)
+                .chain(200..208)
+                .map(|key| (key, key * 10))
+                .collect();
+            assert_eq!(db.scan(0, 299), expected);
+            db
+        };
+        setup_and_test_and_cleaup_on_disk("failed_merge_install_test", &mut alterations, &mut test);
+    }
+
+    #[test]
+    fn level_shape_matches_entry_counts_test() {
+        let mut test = |mut db: Database| {
+            //flushes, moves and compactions (including ones that delete every entry of a level)
+            for round in 0..3 {
+                for key in 0..40 {
+                    db.put(key, key + round).unwrap();
+                    assert_eq!(
+                        db.level_shape,
+                        LevelShape::from_entry_counts(&db.metadata.entry_counts)
+                    );
+                }
+                for key in 0..40 {
+                    db.delete(key).unwrap();
+                }
+            }
+            db.flush_memtable().unwrap();
+            let shape = LevelShape::from_entry_counts(&db.metadata.entry_counts);
+            assert_eq!(db.level_shape(), &shape);
+            assert_eq!(db.sst_total(), shape.total_runs());
+            db
+        };
+        for (test_name, compaction_policy, background_compaction) in [
+            ("level_shape_none_test", CompactionPolicy::None, false),
+            ("level_shape_leveled_test", CompactionPolicy::Leveled, false),
+            ("level_shape_tiered_test", CompactionPolicy::Tiered, false),
+            (
+                "level_shape_dostoevsky_test",
+                CompactionPolicy::Dostoevsky,
+                false,
+            ),
+            ("level_shape_lazy_test", CompactionPolicy::Lazy, false),
+            (
+                "level_shape_background_test",
+                CompactionPolicy::Leveled,
+                true,
+            ),
+        ] {
+            setup_and_test_and_cleaup(
+                test_name,
+                &mut |db| {
+                    testing::part1_db_alterations(db)
+                        .set_compaction_policy(compaction_policy)
+                        .set_memtable_capacity(3)
+                        .set_background_compaction(background_compaction)
+                },
+                &mut test,
+            );
+        }
+
+        //rebuilt from the metadata file on open
+        let test_dir = testing::test_dir("level_shape_reopen_test");
+        setup_and_test_and_cleaup(
+            test_dir,
+            &mut |db| testing::part1_db_alterations(db).set_memtable_capacity(2),
+            &mut |mut db| {
+                for key in 0..9 {
+                    db.put(key, key).unwrap();
+                }
+                db.close(); //flushes the last entry
+                let db = open_in(db.storage(), test_dir.join("test"));
+                assert_eq!(db.level_shape().total_runs(), 5);
+                assert_eq!(db.level_shape().total_entries(), 9);
+                db
+            },
+        );
+    }
+
+    #[test]
+    fn compaction_progress_layout_test() {
+        type Snapshot = (CompactionProgress, Vec<Vec<Size>>, String);
+        let snapshots: Arc<Mutex<Vec<Snapshot>>> = Arc::default();
+        let recorded = Arc::clone(&snapshots);
+        let mut alterations = move |db: Database| {
+            let recorded = Arc::clone(&recorded);
+            testing::part2_db_alterations(db)
+                .set_compaction_policy(CompactionPolicy::Leveled)
+                .set_enable_bloom_filter(true)
+                .set_memtable_capacity(2)
+                .set_sst_size_ratio(10)
+                .set_compaction_progress_callback(move |db, progress| {
+                    //every listed run must exist, whatever stage the compaction is at
+                    for (level, counts) in db.metadata.entry_counts.iter().enumerate() {
+                        for run in 0..counts.len() {
+                            let path = (&db.name, level, run).path_sst();
+                            assert!(
+                                db.storage().path_exists(&path),
+                                "{} is listed but missing",
+                                path.display()
+                            );
+                        }
+                    }
+                    recorded.lock().unwrap().push((
+                        progress,
+                        db.metadata.entry_counts.clone(),
+                        db.describe_layout(),
+                    ));
+                })
+        };
+        let mut test = |mut db: Database| {
+            for key in 0..8 {
+                db.put(key, key * 10).unwrap();
+            }
+            db.delete(0).unwrap();
+            db.flush_memtable().unwrap();
+            for key in 1..8 {
+                assert_eq!(db.get(key), Some(key * 10));
+            }
+            assert_eq!(db.get(0), None);
+            db
+        };
+        setup_and_test_and_cleaup(
+            "compaction_progress_layout_test",
+            &mut alterations,
+            &mut test,
+        );
+
+        let snapshots = snapshots.lock().unwrap();
+        assert!(!snapshots.is_empty());
+        for stages in snapshots.chunks(2) {
+            let [(merged, before, before_layout), (installed, after, after_layout)] = stages else {
+                panic!("Every merge must be followed by its install, got {stages:?}");
+            };
+            let CompactionProgress::Merged { level, stats } = *merged else {
+                panic!("Expected a merge, got {merged:?}");
+            };
+            assert_eq!(*installed, CompactionProgress::Installed { level });
+            //merged but not installed: the layout is exactly the pre-compaction one
+            assert!(before[level].len() >= 2);
+            assert!(before_layout.contains(&format!("level {level}: {} runs", before[level].len())));
+            //installed: the level is the single merged run
+            assert_eq!(after[level], vec![stats.entries_written]);
+            assert!(after_layout.contains(&format!("level {level}: 1 runs")));
+        }
+    }
+
+    #[test]
+    fn write_stall_test() {
+        let events: Arc<Mutex<Vec<DbEvent>>> = Arc::default();
+        let recorded = Arc::clone(&events);
+        let mut alterations = move |db: Database| {
+            let recorded = Arc::clone(&recorded);
+            testing::part1_db_alterations(db)
+                .set_compaction_policy(CompactionPolicy::None)
+                .set_max_level_zero_runs(0)
+                .set_memtable_capacity(2)
+                .set_event_listener(move |event| {
+                    if matches!(
+                        event,
+                        DbEvent::WriteSlowed { .. } | DbEvent::WriteStopped { .. }
+                    ) {
+                        recorded.lock().unwrap().push(event)
+                    }
+                })
+        };
+        let slowed = |level_zero_runs, micros| DbEvent::WriteSlowed {
+            level_zero_runs,
+            delay: Duration::from_micros(micros),
+        };
+        let mut test = |mut db: Database| {
+            events.lock().unwrap().clear(); //a previous storage's run
+
+            //nothing is held back while the thresholds are unset
+            for key in 0..20 {
+                db.put(key, key).unwrap();
+            }
+            assert_eq!(db.sst_count(Database::LEVEL_ZERO), 9);
+            assert_eq!(*events.lock().unwrap(), vec![]);
+            db.compact_level_zero().unwrap();
+
+            //writes sleep from 2 level 0 runs on, twice as long at 3, and fail at 4
+            let mut db = db
+                .set_level_zero_slowdown_runs(2)
+                .set_level_zero_stop_runs(4)
+                .set_level_zero_slowdown_base(Duration::from_micros(1));
+            let mut key = 20;
+            let stalled = loop {
+                match db.put(key, key) {
+                    Ok(()) => key += 1,
+                    Err(why) => break why,
+                }
+            };
+            assert!(matches!(
+                stalled,
+                DbError::WriteStalled { level_zero_runs: 4 }
+            ));
+            assert_eq!(db.sst_count(Database::LEVEL_ZERO), 4);
+            assert_eq!(key, 25); //the first put flushed the full memtable, then 2 more flushes of 2 entries
+            assert_eq!(db.get(key), None);
+            assert_eq!(
+                *events.lock().unwrap(),
+                vec![
+                    slowed(2, 1),
+                    slowed(2, 1),
+                    slowed(3, 2),
+                    slowed(3, 2),
+                    DbEvent::WriteStopped { level_zero_runs: 4 }
+                ]
+            );
+            //writes go ahead again once level 0 is compacted
+            db.compact_level_zero().unwrap();
+            db.put(key, key).unwrap();
+            key += 1;
+
+            //or wait for level 0 to be compacted
+            let mut db = db.set_stall_behavior(StallBehavior::Block);
+            while db.sst_count(Database::LEVEL_ZERO) < 4 {
+                db.put(key, key).unwrap();
+                key += 1;
+            }
+            events.lock().unwrap().clear();
+            db.put(key, key).unwrap();
+            assert_eq!(
+                *events.lock().unwrap(),
+                vec![DbEvent::WriteStopped { level_zero_runs: 4 }]
+            );
+            assert_eq!(db.sst_count(Database::LEVEL_ZERO), 1);
+            for key in 0..=key {
+                assert_eq!(db.get(key), Some(key));
+            }
+
+            //the thresholds are saved with the config
+            let name = db.path().to_path_buf();
+            db.close();
+            let db = open_in(db.storage(), &name);
+            assert_eq!(db.level_zero_slowdown_runs(), 2);
+            assert_eq!(db.level_zero_stop_runs(), 4);
+            assert_eq!(db.level_zero_slowdown_base(), Duration::from_micros(1));
+            assert_eq!(db.stall_behavior(), StallBehavior::Block);
+            db
+        };
+        setup_and_test_and_cleaup("write_stall_test", &mut alterations, &mut test);
+    }
+
+    #[test]
+    fn partitioned_levels_test() {
+        use rand::{Rng, SeedableRng};
+
+        //level 1 holds keys 0..num_keys in partitions of a memtable each, writing to the first memtable's worth of
+        // keys again only rewrites the partition holding them. The same writes without partitions rewrite the level
+        const MEMTABLES: Key = 32;
+        let memtable_capacity = 2 * system_info::num_entries_per_page();
+        let num_keys = MEMTABLES * memtable_capacity as Key;
+        let pages_per_memtable = sst_util::num_pages(memtable_capacity);
+        //pages written by each compaction of level 1
+        let record_compactions = |written: &Arc<Mutex<Vec<Size>>>| {
+            let recorded = Arc::clone(written);
+            move |event| {
+                if let DbEvent::CompactionFinished {
+                    level: 1,
+                    output_pages_written,
+                    ..
+                } = event
+                {
+                    recorded.lock().unwrap().push(output_pages_written);
+                }
+            }
+        };
+        let partitioned_compactions: Arc<Mutex<Vec<Size>>> = Arc::default();
+        let baseline_compactions: Arc<Mutex<Vec<Size>>> = Arc::default();
+        let compactions =
+            |written: &Arc<Mutex<Vec<Size>>>| mem::take(&mut *written.lock().unwrap());
+        for (index, base_alterations) in
+            [testing::part1_db_alterations, testing::part2_db_alterations]
+                .into_iter()
+                .enumerate()
+        {
+            let settings = move |db: Database| {
+                base_alterations(db)
+                    .set_compaction_policy(CompactionPolicy::Leveled)
+                    .set_memtable_capacity(memtable_capacity)
+                    .set_sst_size_ratio(2 * MEMTABLES as Size) //level 1 never fills up
+            };
+            let mut alterations = |db: Database| {
+                settings(db)
+                    .set_partitioned_levels(true)
+                    .set_event_listener(record_compactions(&partitioned_compactions))
+            };
+            let mut test = |mut db: Database| {
+                let mut baseline =
+                    settings(open_in(db.storage(), db.path().with_file_name("baseline")))
+                        .set_event_listener(record_compactions(&baseline_compactions));
+                //a previous storage's run
+                compactions(&partitioned_compactions);
+                compactions(&baseline_compactions);
+
+                //runs of keys in order never overlap, each one becomes a partition without being rewritten
+                for key in 0..num_keys {
+                    db.put(key, key).unwrap();
+                    baseline.put(key, key).unwrap();
+                }
+                assert_eq!(compactions(&partitioned_compactions), vec![]);
+                assert!(compactions(&baseline_compactions).len() > 1);
+                let num_partitions = db.metadata.partitions[1].len();
+                assert_eq!(num_partitions, db.sst_count(1));
+                //the last memtable isn't flushed yet, the one before it is in level 0
+                assert_eq!(num_partitions, MEMTABLES as Size - 2);
+                assert_eq!(baseline.sst_count(1), 1);
+
+                //the first memtable's keys twice, so the first of them is moved into level 1
+                for round in 1..=2 {
+                    for key in 0..memtable_capacity as Key {
+                        db.put(key, key + round).unwrap();
+                        baseline.put(key, key + round).unwrap();
+                    }
+                    db.flush_memtable().unwrap();
+                    baseline.flush_memtable().unwrap();
+                }
+                assert_eq!(
+                    compactions(&partitioned_compactions),
+                    vec![pages_per_memtable]
+                );
+                //every run moved into level 1 is merged with all of it
+                let baseline_pages = compactions(&baseline_compactions);
+                assert_eq!(
+                    baseline_pages.last(),
+                    Some(&(MEMTABLES as Size * pages_per_memtable))
+                );
+                assert_eq!(db.metadata.partitions[1].len(), MEMTABLES as Size);
+
+                for key in (0..num_keys).step_by(7) {
+                    assert_eq!(db.get(key), baseline.get(key), "key {key}");
+                    //a single run of level 1 is searched
+                    let level_one_runs = (db.explain_get(key).runs.iter())
+                        .filter(|run| run.level == 1)
+                        .count();
+                    assert!(level_one_runs <= 1, "key {key}");
+                }
+                assert_eq!(db.get(0), Some(2));
+                assert_eq!(db.get(num_keys), None);
+                assert_eq!(
+                    db.scan(Key::MIN, Key::MAX),
+                    baseline.scan(Key::MIN, Key::MAX)
+                );
+                for (low, high) in [
+                    (0, 10),
+                    (100, 3000),
+                    (num_keys - 5, num_keys + 5),
+                    (-10, -1),
+                ] {
+                    assert_eq!(
+                        db.scan(low, high),
+                        baseline.scan(low, high),
+                        "{low}..={high}"
+                    );
+                }
+
+                //writes all over the level merge with every partition, split again into partitions of a memtable
+                let mut rng = rand::rngs::StdRng::seed_from_u64(index as u64);
+                for _ in 0..3 * memtable_capacity {
+                    let key = rng.gen_range(0..num_keys);
+                    if rng.gen_bool(0.2) {
+                        db.delete(key).unwrap();
+                        baseline.delete(key).unwrap();
+                    } else {
+                        db.put(key, -key).unwrap();
+                        baseline.put(key, -key).unwrap();
+                    }
+                }
+                db.flush_memtable().unwrap();
+                baseline.flush_memtable().unwrap();
+                let partitions = &db.metadata.partitions[1];
+                assert_eq!(partitions.len(), db.sst_count(1));
+                assert!(partitions.windows(2).all(|pair| pair[0].1 < pair[1].0));
+                for run in 0..db.sst_count(1) {
+                    assert!(db.metadata.entry_counts[1][run] <= memtable_capacity);
+                }
+                for key in (0..num_keys).step_by(3) {
+                    assert_eq!(db.get(key), baseline.get(key), "key {key}");
+                }
+                assert_eq!(
+                    db.scan(Key::MIN, Key::MAX),
+                    baseline.scan(Key::MIN, Key::MAX)
+                );
+
+                //partitions are kept across a reopen
+                let db_name = db.path().to_path_buf();
+                let num_partitions = db.metadata.partitions[1].len();
+                db.close();
+                let mut db = open_in(db.storage(), &db_name);
+                assert_eq!(db.metadata.partitions[1].len(), num_partitions);
+                assert_eq!(
+                    db.scan(Key::MIN, Key::MAX),
+                    baseline.scan(Key::MIN, Key::MAX)
+                );
+                baseline.close();
+                db
+            };
+            setup_and_test_and_cleaup(
+                format!("partitioned_levels_test_{index}"),
+                &mut alterations,
+                &mut test,
+            );
+        }
+    }
+
+    #[test]
+    fn event_listener_test() {
+        //events with their durations left out, so they can be compared
+        fn untimed(event: DbEvent) -> DbEvent {
+            match event {
+                DbEvent::MemtableFlush { entries, .. } => DbEvent::MemtableFlush {
+                    entries,
+                    duration: Duration::ZERO,
+                },
+                DbEvent::CompactionFinished {
+                    level,
+                    output_entries,
+                    input_pages_read,
+                    output_pages_written,
+                    ..
+                } => DbEvent::CompactionFinished {
+                    level,
+                    output_entries,
+                    input_pages_read,
+                    output_pages_written,
+                    duration: Duration::ZERO,
+                },
+                event => event,
+            }
+        }
+        let flush = |entries| DbEvent::MemtableFlush {
+            entries,
+            duration: Duration::ZERO,
+        };
+        let moved = |from_level| DbEvent::RunsMoved {
+            from_level,
+            to_level: from_level + 1,
+            count: 1,
+        };
+        //every run fits in a page
+        let compaction = |level, runs, entries| {
+            [
+                DbEvent::CompactionStarted {
+                    level,
+                    runs,
+                    input_entries: entries,
+                },
+                DbEvent::CompactionFinished {
+                    level,
+                    output_entries: entries,
+                    input_pages_read: runs,
+                    output_pages_written: 1,
+                    duration: Duration::ZERO,
+                },
+            ]
+        };
+        //Leveled merges level 1 whenever a run is moved in and moves it once it's 3 times a memtable,
+        // Tiered waits for 3 runs
+        let leveled_quarter = [
+            vec![flush(2), moved(0), flush(2), moved(0)],
+            compaction(1, 2, 4).to_vec(),
+            vec![flush(2), moved(0)],
+            compaction(1, 2, 6).to_vec(),
+            vec![moved(1)],
+        ]
+        .concat();
+        let leveled = [
+            leveled_quarter.clone(),
+            leveled_quarter,
+            compaction(2, 2, 12).to_vec(),
+            vec![flush(1)],
+        ]
+        .concat();
+        let tiered_half = [
+            vec![flush(2), moved(0), flush(2), moved(0), flush(2), moved(0)],
+            compaction(1, 3, 6).to_vec(),
+            vec![moved(1)],
+        ]
+        .concat();
+        let tiered = [tiered_half.clone(), tiered_half, vec![flush(1)]].concat();
+
+        for (policy, expected) in [
+            (CompactionPolicy::Leveled, leveled),
+            (CompactionPolicy::Tiered, tiered),
+        ] {
+            let events: Arc<Mutex<Vec<DbEvent>>> = Arc::default();
+            let recorded = Arc::clone(&events);
+            let mut alterations = move |db: Database| {
+                let recorded = Arc::clone(&recorded);
+                testing::part1_db_alterations(db)
+                    .set_compaction_policy(policy)
+                    .set_memtable_capacity(2)
+                    .set_sst_size_ratio(3)
+                    .set_event_listener(move |event| recorded.lock().unwrap().push(untimed(event)))
+            };
+            let mut test = |mut db: Database| {
+                events.lock().unwrap().clear(); //a previous storage's run
+                for key in 0..12 {
+                    db.put(key, key).unwrap();
+                }
+                db.delete(0).unwrap();
+                db.flush_memtable().unwrap();
+                db
+            };
+            setup_and_test_and_cleaup("event_listener_test", &mut alterations, &mut test);
+            assert_eq!(*events.lock().unwrap(), expected, "{policy:?}");
+        }
+    }
+
+    #[test]
+    fn durability_lag_test() {
+        let clock = ManualClock::new(1_000);
+        let events: Arc<Mutex<Vec<DbEvent>>> = Arc::default();
+        let recorded = Arc::clone(&events);
+        let mut alterations = |db: Database| {
+            let recorded = Arc::clone(&recorded);
+            testing::part1_db_alterations(db)
+                .set_memtable_capacity(8)
+                .set_clock(clock.clone())
+                .set_durability_lag_threshold(Duration::from_secs(10))
+                .set_event_listener(move |event| {
+                    if matches!(event, DbEvent::DurabilityLag { .. }) {
+                        recorded.lock().unwrap().push(event)
+                    }
+                })
+        };
+        let lag = |seconds, unflushed_entries| DbEvent::DurabilityLag {
+            age: Duration::from_secs(seconds),
+            unflushed_entries,
+        };
+        let mut test = |mut db: Database| {
+            events.lock().unwrap().clear(); //a previous storage's run
+            assert_eq!(db.unflushed_age(), None);
+            assert_eq!(db.unflushed_entries(), 0);
+
+            //the age counts from the first write, later ones don't move it
+            db.put(0, 0).unwrap();
+            assert_eq!(db.unflushed_age(), Some(Duration::ZERO));
+            clock.advance(5);
+            db.put(1, 1).unwrap();
+            assert_eq!(db.unflushed_age(), Some(Duration::from_secs(5)));
+            assert_eq!(db.unflushed_entries(), 2);
+            assert!(events.lock().unwrap().is_empty());
+
+            //reported once by the first write to find it past the threshold
+            clock.advance(5);
+            db.put(2, 2).unwrap();
+            clock.advance(1);
+            db.delete(0).unwrap();
+            assert_eq!(*events.lock().unwrap(), vec![lag(10, 2)]);
+            assert_eq!(db.unflushed_age(), Some(Duration::from_secs(11)));
+            assert_eq!(db.unflushed_entries(), 3);
+
+            //a flush starts over
+            db.flush().unwrap();
+            assert_eq!(db.unflushed_age(), None);
+            assert_eq!(db.unflushed_entries(), 0);
+            clock.advance(20);
+            assert_eq!(db.unflushed_age(), None);
+            db.put(3, 3).unwrap();
+            assert_eq!(db.unflushed_age(), Some(Duration::ZERO));
+
+            //frozen entries keep their age until their flush, writes since count from the first of them
+            clock.advance(4);
+            db.freeze_memtable().unwrap();
+            clock.advance(3);
+            db.put(4, 4).unwrap();
+            assert_eq!(db.unflushed_age(), Some(Duration::from_secs(7)));
+            assert_eq!(db.unflushed_entries(), 2);
+            clock.advance(3);
+            db.put(5, 5).unwrap();
+            db.flush_frozen().unwrap();
+            assert_eq!(db.unflushed_age(), Some(Duration::from_secs(3)));
+            assert_eq!(db.unflushed_entries(), 2);
+            assert_eq!(*events.lock().unwrap(), vec![lag(10, 2), lag(10, 2)]);
+
+            //a full memtable's flush starts over too
+            for key in 6..12 {
+                db.put(key, key).unwrap();
+            }
+            db.put(12, 12).unwrap();
+            assert_eq!(db.unflushed_age(), Some(Duration::ZERO));
+            assert_eq!(db.unflushed_entries(), 1);
+            db
+        };
+        setup_and_test_and_cleaup("durability_lag_test", &mut alterations, &mut test);
+    }
+
+    #[test]
+    fn flush_audit_test() {
+        let audit_dir = testing::test_dir("flush_audit_test_audits");
+        for compaction_policy in [CompactionPolicy::None, CompactionPolicy::Tiered] {
+            let mut alterations = |db: Database| {
+                testing::part1_db_alterations(db)
+                    .set_compaction_policy(compaction_policy)
+                    .set_sst_size_ratio(3)
+                    .set_memtable_capacity(4)
+                    .set_flush_audit_dir(audit_dir)
+            };
+            let mut test = |mut db: Database| {
+                let _ = fs::remove_dir_all(audit_dir); //audits of the last database
+
+                //keys put out of order, runs (and audits) hold them in key order
+                for value in 0..22 {
+                    db.put(value * 7 % 22, value).unwrap();
+                }
+                db.delete(3).unwrap();
+                db.flush().unwrap();
+                let checks = db.verify_flush_audits(audit_dir).unwrap();
+                assert_eq!(checks.len(), 6);
+                for (audit, check) in &checks {
+                    let entries = serde_entry::deserialize(&audit.read().unwrap(), db.key_codec());
+                    assert!(entries.unwrap().is_sorted(), "{}", audit.path.display());
+                    assert_ne!(
+                        *check,
+                        AuditCheck::Mismatch {
+                            level: audit.level,
+                            run: audit.run
+                        }
+                    );
+                }
+                match compaction_policy {
+                    //every run is still where its flush put it
+                    CompactionPolicy::None => {
+                        for (run, (audit, check)) in checks.iter().enumerate() {
+                            assert_eq!((audit.level, audit.run), (0, run));
+                            assert_eq!(*check, AuditCheck::Matches { level: 0, run });
+                        }
+                    }
+                    _ => {
+                        assert!(checks
+                            .iter()
+                            .any(|(_, check)| *check == AuditCheck::Superseded));
+                        //the last flush's run hasn't moved
+                        assert_eq!(checks[5].1, AuditCheck::Matches { level: 0, run: 0 });
+                    }
+                }
+
+                //an audit of other bytes than the run's is a mismatch
+                let (audit, check) = checks.last().unwrap();
+                let mut entries =
+                    serde_entry::deserialize(&audit.read().unwrap(), db.key_codec()).unwrap();
+                entries.reverse();
+                fs::write(
+                    &audit.path,
+                    serde_entry::serialize(&entries, db.key_codec()),
+                )
+                .unwrap();
+                let AuditCheck::Matches { level, run } = *check else {
+                    panic!("{check:?}");
+                };
+                let checks = db.verify_flush_audits(audit_dir).unwrap();
+                assert_eq!(checks[5].1, AuditCheck::Mismatch { level, run });
+
+                //a flush whose audit can't be written fails, keeping its entries in the memtable
+                fs::remove_dir_all(audit_dir).unwrap();
+                fs::write(audit_dir, b"not a directory").unwrap();
+                db.put(100, 1).unwrap();
+                assert!(db.flush().is_err());
+                assert_eq!(db.get(100), Some(1));
+                fs::remove_file(audit_dir).unwrap();
+                db.flush().unwrap();
+                assert_eq!(db.get(100), Some(1));
+                db
+            };
+            setup_and_test_and_cleaup("flush_audit_test", &mut alterations, &mut test);
+        }
+        fs::remove_dir_all(audit_dir).unwrap();
+    }
+
+    #[test]
+    fn level_size_ratios_test() {
+        let expected: Arc<Mutex<SizeRatioHistory>> = Arc::default();
+        let recorded = Arc::clone(&expected);
+        let mut alterations = move |db: Database| {
+            let recorded = Arc::clone(&recorded);
+            testing::part1_db_alterations(db)
+                .set_compaction_policy(CompactionPolicy::Leveled)
+                .set_memtable_capacity(2)
+                .set_compaction_progress_callback(move |db, progress| {
+                    if let CompactionProgress::Installed { .. } = progress {
+                        let level_bytes = size_ratio::level_bytes(&db.metadata.entry_counts);
+                        recorded.lock().unwrap().record(&level_bytes);
+                    }
+                })
+        };
+        let num_samples =
+            |ratios: &[LevelSizeRatio]| ratios.iter().map(|ratio| ratio.samples).sum::<Size>();
+        let mut test = |mut db: Database| {
+            assert!(db.level_size_ratios().is_empty());
+            *expected.lock().unwrap() = SizeRatioHistory::default(); //a previous storage's run
+            for key in 0..64 {
+                db.put(key, key).unwrap();
+                assert_eq!(db.level_size_ratios(), expected.lock().unwrap().ratios());
+            }
+            let ratios = db.level_size_ratios();
+            assert!(ratios.iter().any(|ratio| ratio.samples > 1), "{ratios:?}");
+            let layout = db.describe_layout();
+            for ratio in &ratios {
+                let line = format!(
+                    "size ratio of levels {} and {}",
+                    ratio.level,
+                    ratio.level + 1
+                );
+                assert!(layout.contains(&line), "{layout}");
+            }
+
+            //background compactions are recorded too
+            let mut db = db.set_background_compaction(true);
+            for key in 64..128 {
+                db.put(key, key).unwrap();
+            }
+            let db = db.set_background_compaction(false);
+            assert!(num_samples(&db.level_size_ratios()) > num_samples(&ratios));
+            db
+        };
+        setup_and_test_and_cleaup("level_size_ratios_test", &mut alterations, &mut test);
+    }
+
+    #[test]
+    fn legacy_files_test() {
+        let test_dir = testing::test_dir("legacy_files_test");
+        let db_name = test_dir.join("test");
+        if test_dir.exists() {
+            std::fs::remove_dir_all(test_dir).unwrap();
+        }
+        std::fs::create_dir_all(&db_name).unwrap();
+
+        let legacy_pages: Size = 3;
+        let legacy_config = LegacyConfig {
+            memtable_capacity: 7,
+            sst_size_ratio: 3,
+            sst_implementation: SstImplementation::Btree,
+            sst_search_algorithm: SstSearchAlgorithm::Default,
+            enable_buffer_pool: true,
+            buffer_pool_capacity: legacy_pages,
+            buffer_pool_initial_size: 4,
+            compaction_policy: CompactionPolicy::Leveled,
+            enable_bloom_filter: false,
+            bloom_filter_bits_per_entry: 5,
+        };
+        let legacy_metadata = LegacyMetadata {
+            entry_counts: vec![vec![], vec![]],
+        };
+        std::fs::write(
+            filename::config(&db_name),
+            serde_state::serialize(&legacy_config),
+        )
+        .unwrap();
+        std::fs::write(
+            filename::metadata(&db_name),
+            serde_state::serialize(&legacy_metadata),
+        )
+        .unwrap();
+
+        let mut db = Database::try_open(&db_name).unwrap();
+        assert_eq!(db.memtable_capacity(), 7);
+        assert_eq!(db.sst_size_ratio(), 3);
+        assert_eq!(db.compaction_policy(), CompactionPolicy::Leveled);
+        assert_eq!(db.bloom_levels(), BloomLevels::None);
+        assert_eq!(
+            db.buffer_pool_capacity(),
+            legacy_pages * system_info::page_size()
+        );
+        assert_eq!(db.metadata.entry_counts, vec![vec![], vec![]]);
+        assert_eq!(db.metadata.tombstone_counts, vec![vec![], vec![]]);
+        assert_eq!(db.metadata.compaction_entries_dropped, 0);
+        assert_eq!(db.metadata.expiry_counts, vec![vec![], vec![]]);
+        assert_eq!(db.metadata.page_sizes, vec![vec![], vec![]]);
+        assert_eq!(db.metadata.max_sequences, vec![vec![], vec![]]);
+        assert_eq!(db.last_sequence(), 0);
+        db.close();
+
+        //files are written back versioned
+        for (path, version) in [
+            (filename::config(&db_name), Config::VERSION),
+            (filename::metadata(&db_name), Metadata::VERSION),
+        ] {
+            let bytes = std::fs::read(path).unwrap();
+            assert_eq!(bytes[..4], version.to_le_bytes());
+        }
+        let db = Database::try_open(&db_name).unwrap();
+        assert_eq!(db.memtable_capacity(), 7);
+        drop(db);
+        std::fs::remove_dir_all(test_dir).unwrap();
+    }
+
+    #[test]
+    fn salvage_test() {
+        let entries_per_page = system_info::num_entries_per_page();
+        let mut alterations = |db: Database| {
+            testing::part1_db_alterations(db).set_memtable_capacity(3 * entries_per_page)
+        };
+        let mut test = |mut db: Database| {
+            //4 runs of 3 pages, each a page further along the keys than the one before it. The last deletes some keys
+            let mut runs: Vec<Vec<Entry>> = Vec::new();
+            for run in 0..4 {
+                let first = (run * entries_per_page) as Key;
+                let entries: Vec<Entry> = (first..first + 3 * entries_per_page as Key)
+                    .map(|key| match run == 3 && key % 5 == 0 {
+                        true => (key, Database::TOMBSTONE_VALUE),
+                        false => (key, key * 10 + run as Value),
+                    })
+                    .collect();
+                for &(key, value) in &entries {
+                    match value {
+                        Database::TOMBSTONE_VALUE => db.delete(key).unwrap(),
+                        _ => db.put(key, value).unwrap(),
+                    }
+                }
+                db.flush().unwrap();
+                runs.push(entries);
+            }
+            assert_eq!(db.sst_count(Database::LEVEL_ZERO), 4);
+            let db_name = db.name.clone();
+            db.close();
+
+            //garbage over page 1 of run 1, the last page of run 2 cut short in the middle of an entry, and config and
+            // metadata files that can't be decoded
+            let page_size = system_info::page_size();
+            let run_path = |run| (&db_name, Database::LEVEL_ZERO, run).path_sst();
+            let mut bytes = db.storage().read_file(&run_path(1)).unwrap();
+            bytes[page_size..2 * page_size].fill(0xFF);
+            db.storage().write_file(&run_path(1), &bytes).unwrap();
+            let mut bytes = db.storage().read_file(&run_path(2)).unwrap();
+            bytes.truncate(bytes.len() - page_size / 2 - 3);
+            db.storage().write_file(&run_path(2), &bytes).unwrap();
+            db.storage()
+                .write_file(&filename::config(&db_name), b"garbage")
+                .unwrap();
+            db.storage()
+                .write_file(&filename::metadata(&db_name), b"garbage")
+                .unwrap();
+            assert!(try_open_in(db.storage(), &db_name).is_err());
+
+            let out_dir = db_name.with_file_name("salvaged");
+            let options = DatabaseOptions {
+                storage: db.storage().clone(),
+            };
+            let report = Database::salvage_with(&db_name, &out_dir, options.clone()).unwrap();
+            let skipped: Vec<(Level, Run, Page)> = (report.skipped_pages.iter())
+                .map(|skipped| (skipped.level, skipped.run, skipped.page))
+                .collect();
+            assert_eq!(skipped, vec![(0, 1, 1), (0, 2, 2)], "{report}");
+            assert!(report.skipped_runs.is_empty());
+            assert!(report.default_key_codec);
+            assert!(!report.is_complete());
+            assert_eq!(report.runs_read, 4);
+            assert_eq!(report.entries_read, 10 * entries_per_page);
+            assert!(matches!(
+                Database::salvage_with(&db_name, &out_dir, options),
+                Err(DbError::Io(why)) if why.kind() == io::ErrorKind::AlreadyExists
+            ));
+
+            //the youngest entry of each key outside the skipped pages
+            let mut expected = BTreeMap::new();
+            for (run, entries) in runs.iter().enumerate() {
+                for (page, page_entries) in entries.chunks(entries_per_page).enumerate() {
+                    if !skipped.contains(&(0, run, page)) {
+                        expected.extend(page_entries.iter().copied());
+                    }
+                }
+            }
+            expected.retain(|_, value| *value != Database::TOMBSTONE_VALUE);
+            let mut salvaged = try_open_in(db.storage(), &out_dir).unwrap();
+            let expected: Vec<Entry> = expected.into_iter().collect();
+            assert_eq!(salvaged.scan(Key::MIN + 1, Key::MAX), expected);
+            salvaged
+        };
+        setup_and_test_and_cleaup("salvage_test", &mut alterations, &mut test);
+    }
+
+    #[test]
+    fn close_failure_test() {
+        let failures: Arc<Mutex<Vec<DbEvent>>> = Arc::default();
+        let recorded = Arc::clone(&failures);
+        let mut alterations = |db: Database| {
+            let recorded = Arc::clone(&recorded);
+            db.set_memtable_capacity(4)
+                .set_event_listener(move |event| {
+                    if matches!(event, DbEvent::CloseFailed { .. }) {
+                        recorded.lock().unwrap().push(event)
+                    }
+                })
+        };
+        //a file where level 0's directory goes fails the flush, a read-only directory wouldn't stop root
+        let break_level_zero = |name: &Path| {
+            let level_zero =
+                filename::lsm_level_directory(&(&DatabaseName::new(name, Storage::disk()), 0));
+            if level_zero.exists() {
+                fs::remove_dir_all(&level_zero).unwrap();
+            }
+            fs::write(&level_zero, []).unwrap();
+            level_zero
+        };
+        let mut test = |mut db: Database| {
+            let name = db.path().to_path_buf();
+            for key in 0..3 {
+                db.put(key, key).unwrap();
+            }
+            let level_zero = break_level_zero(&name);
+            assert!(db.try_close().is_err());
+            assert_eq!(db.get(0), Some(0)); //still open
+            drop(db); //fails to close again, which is reported instead of panicking
+            assert_eq!(
+                failures.lock().unwrap()[..],
+                [DbEvent::CloseFailed {
+                    kind: io::ErrorKind::NotADirectory
+                }]
+            );
+            fs::remove_file(&level_zero).unwrap();
+            let mut db = Database::open(&name);
+            assert_eq!(db.get(0), None); //never flushed
+
+            //closing can be retried once what failed it is fixed
+            for key in 0..3 {
+                db.put(key, key).unwrap();
+            }
+            let level_zero = break_level_zero(&name);
+            assert!(db.try_close().is_err());
+            fs::remove_file(&level_zero).unwrap();
+            db.try_close().unwrap();
+            db.try_close().unwrap(); //closing again does nothing
+
+            assert!(matches!(db.put(5, 5), Err(DbError::Closed)));
+            assert!(matches!(db.delete(0), Err(DbError::Closed)));
+            assert!(matches!(db.flush(), Err(DbError::Closed)));
+            assert!(matches!(db.truncate(), Err(DbError::Closed)));
+            //reads find nothing, their try_ forms tell that apart from a missing key
+            assert_eq!(db.get(2), None);
+            assert!(db.scan(0, 10).is_empty());
+            assert!(matches!(db.try_get(2), Err(DbError::Closed)));
+            assert!(matches!(db.try_explain_get(2), Err(DbError::Closed)));
+            assert!(matches!(db.try_scan(0, 10), Err(DbError::Closed)));
+            assert!(matches!(db.try_get_ceiling(2), Err(DbError::Closed)));
+            assert!(matches!(db.try_scan_keys(0, 10), Err(DbError::Closed)));
+            assert!(matches!(
+                db.try_scan_with_options(0, 10, ScanOptions::default()),
+                Err(DbError::Closed)
+            ));
+            drop(db);
+            assert_eq!(failures.lock().unwrap().len(), 1);
+
+            let mut db = Database::open(&name);
+            assert_eq!(db.get(2), Some(2));
+            db
+        };
+        setup_and_test_and_cleaup_on_disk("close_failure_test", &mut alterations, &mut test);
+    }
+
+    #[test]
+    fn read_error_test() {
+        let mut alterations = |db: Database| db.set_memtable_capacity(4);
+        let mut test = |mut db: Database| {
+            for key in 0..6 {
+                db.put(key, key).unwrap();
+            }
+            //runs whose SSTs were removed behind our back fail the reads that reach them instead of panicking
+            db.flush().unwrap();
+            let db_name = db.name.clone();
+            let entry_counts = db.metadata.entry_counts.clone();
+            db.close();
+            for (level, runs) in entry_counts.iter().enumerate() {
+                for run in 0..runs.len() {
+                    db_name
+                        .storage()
+                        .remove_file(&(&db_name, level, run).path_sst())
+                        .unwrap();
+                }
+            }
+            let mut db = open_in(db_name.storage(), &db_name);
+            let why = db.try_get(1).unwrap_err();
+            assert!(
+                matches!(why, DbError::Io(ref why) if why.kind() == io::ErrorKind::NotFound),
+                "{why}"
+            );
+            assert!(why.to_string().contains("key 1"), "{why}");
+            assert!(matches!(db.try_explain_get(1), Err(DbError::Io(_))));
+            assert!(matches!(db.try_scan(0, 10), Err(DbError::Io(_))));
+            assert!(matches!(db.try_scan_keys(0, 10), Err(DbError::Io(_))));
+            assert!(matches!(db.try_scan_with_retry(0, 10), Err(DbError::Io(_))));
+            assert!(matches!(
+                db.try_scan_filtered(0, 10, |_, _| true),
+                Err(DbError::Io(_))
+            ));
+            assert!(matches!(db.try_get_ceiling(1), Err(DbError::Io(_))));
+            assert!(matches!(db.try_get_floor(1), Err(DbError::Io(_))));
+            //keys the memtable decides never reach the runs
+            db.put(1, 10).unwrap();
+            assert_eq!(db.try_get(1).unwrap(), Some(10));
+            db
+        };
+        setup_and_test_and_cleaup("read_error_test", &mut alterations, &mut test);
+    }
+
+    #[test]
+    fn close_metadata_failure_test() {
+        let mut alterations = |db: Database| db.set_memtable_capacity(4);
+        let mut test = |mut db: Database| {
+            for key in 0..3 {
+                db.put(key, key).unwrap();
+            }
+            //a directory where the metadata file goes fails writing it once the memtable is flushed
+            let metadata_path = filename::metadata(&db.name);
+            fs::remove_file(&metadata_path).unwrap();
+            fs::create_dir_all(metadata_path.join("blocked")).unwrap();
+            assert!(matches!(db.try_close(), Err(DbError::Io(_))));
+            assert_eq!(db.get(2), Some(2)); //still open, the run is in place
+
+            fs::remove_dir_all(&metadata_path).unwrap();
+            let name = db.path().to_path_buf();
+            db.try_close().unwrap();
+            let mut db = Database::open(name);
+            assert_eq!(db.scan(0, 10), vec![(0, 0), (1, 1), (2, 2)]);
+            db
+        };
+        setup_and_test_and_cleaup_on_disk(
+            "close_metadata_failure_test",
+            &mut alterations,
+            &mut test,
+        );
+    }
+
+    #[test]
+    fn abandon_test() {
+        let mut alterations = |db: Database| db.set_memtable_capacity(4);
+        let mut test = |mut db: Database| {
+            for key in 0..6 {
+                db.put(key, key).unwrap();
+            }
+            let (name, storage) = (db.path().to_path_buf(), db.storage().clone());
+            db.abandon(); //as if we crashed, the memtable is lost
+            let mut db = open_in(&storage, &name);
+            assert_eq!(db.scan(0, 10).len(), 4); //flushed when the memtable filled
+            assert_eq!(db.get(5), None);
+            db.put(5, 5).unwrap();
+            db
+        };
+        setup_and_test_and_cleaup("abandon_test", &mut alterations, &mut test);
+    }
+
+    #[test]
+    fn clear_with_queued_compactions_test() {
+        let mut alterations = |db: Database| {
+            testing::part3_db_alterations(db)
+                .set_memtable_capacity(4)
+                .set_background_compaction(true)
+        };
+        let mut test = |mut db: Database| {
+            for key in 0..32 {
+                db.put(key, key).unwrap();
+            }
+            //the compactions still queued finish, and the metadata written for them, before ours is reset
+            let (name, storage) = (db.path().to_path_buf(), db.storage().clone());
+            db.clear();
+            assert!(db.is_closed());
+            let mut db = open_in(&storage, &name);
+            assert_eq!(
+                db.scan(0, 31),
+                (0..28).map(|key| (key, key)).collect::<Vec<_>>()
+            );
+            db.put(100, 100).unwrap();
+            db
+        };
+        setup_and_test_and_cleaup(
+            "clear_with_queued_compactions_test",
+            &mut alterations,
+            &mut test,
+        );
+    }
+
+    #[test]
+    fn health_check_test() {
+        let memtable_capacity = 2 * system_info::num_entries_per_page();
+        let mut alterations = |db: Database| {
+            testing::part3_db_alterations(db)
+                .set_memtable_capacity(memtable_capacity)
+                .set_background_compaction(true)
+        };
+        //every check but the ones in <failing> passes
+        let assert_failing = |report: &HealthReport, failing: &[HealthCheck]| {
+            let failures: Vec<HealthCheck> = report.failures().map(|(check, _)| check).collect();
+            assert_eq!(failures, failing, "{report}");
+            assert_eq!(report.is_healthy(), failing.is_empty());
+        };
+        let mut test = |mut db: Database| {
+            let report = db.health_check(true);
+            assert_failing(&report, &[]);
+            assert!(matches!(
+                report.status(HealthCheck::Canary),
+                Some(CheckStatus::Skipped(_))
+            ));
+            for key in 0..(3 * memtable_capacity) as Key {
+                db.put(key, key).unwrap();
+            }
+            let report = db.health_check(true);
+            assert_failing(&report, &[]);
+            assert_eq!(report.status(HealthCheck::Canary), Some(&CheckStatus::Pass));
+            assert!(matches!(
+                db.health_check(false).status(HealthCheck::Canary),
+                Some(CheckStatus::Skipped(_))
+            ));
+
+            //a compaction failure latched on the background compactor is reported instead of panicking
+            let levels = db.background_levels().unwrap();
+            levels.lock().unwrap().error = Some("disk full".to_string());
+            assert_failing(&db.health_check(false), &[HealthCheck::LatchedError]);
+            levels.lock().unwrap().error = None;
+            db.compaction_error = Some(io::Error::other("disk full"));
+            assert_failing(&db.health_check(false), &[HealthCheck::LatchedError]);
+            db.compaction_error = None;
+
+            //the newest run lost its entries
+            db.stop_background_compactor().unwrap();
+            let mut newest_run = None;
+            Database::for_each_sst(&db.metadata.entry_counts, &mut |level, run| {
+                newest_run = Some((level, run));
+                true
+            });
+            let (level, run) = newest_run.unwrap();
+            let sst_path = (&db.name, level, run).path_sst();
+            let sst = db.storage().read_file(&sst_path).unwrap();
+            db.storage()
+                .write_file(&sst_path, &sst[..ENTRY_SIZE])
+                .unwrap();
+            assert_failing(&db.health_check(true), &[HealthCheck::Canary]);
+            assert_failing(&db.health_check(false), &[]);
+            db.storage().write_file(&sst_path, &sst).unwrap();
+
+            let metadata_path = filename::metadata(&db.name);
+            let metadata = db.storage().read_file(&metadata_path).unwrap();
+            db.storage().remove_file(&metadata_path).unwrap();
+            assert_failing(&db.health_check(true), &[HealthCheck::Metadata]);
+            db.storage()
+                .write_file(&metadata_path, &metadata[..4])
+                .unwrap();
+            assert_failing(&db.health_check(true), &[HealthCheck::Metadata]);
+            db.storage().write_file(&metadata_path, &metadata).unwrap();
+            assert_failing(&db.health_check(true), &[]);
+
+            let name = db.path().to_path_buf();
+            db.close();
+            assert_failing(&db.health_check(true), &[HealthCheck::Open]);
+            open_in(db.storage(), name)
+        };
+        setup_and_test_and_cleaup("health_check_test", &mut alterations, &mut test);
+    }
+
+    #[test]
+    fn unsupported_version_test() {
+        let test_dir = testing::test_dir("unsupported_version_test");
+        let db_name = test_dir.join("test");
+        if test_dir.exists() {
+            std::fs::remove_dir_all(test_dir).unwrap();
+        }
+        std::fs::create_dir_all(test_dir).unwrap();
+        Database::open(&db_name).close();
+
+        let future_metadata = serde_state::serialize(&(999_u32, Metadata::new()));
+        std::fs::write(filename::metadata(&db_name), future_metadata).unwrap();
+        assert!(matches!(
+            Database::try_open(&db_name),
+            Err(DbError::UnsupportedVersion {
+                found: 999,
+                supported: Metadata::VERSION
+            })
+        ));
+
+        let future_config = serde_state::serialize(&(999_u32, Config::new()));
+        std::fs::write(filename::config(&db_name), future_config).unwrap();
+        assert!(matches!(
+            Database::try_open(&db_name),
+            Err(DbError::UnsupportedVersion {
+                found: 999,
+                supported: Config::VERSION
+            })
+        ));
+        std::fs::remove_dir_all(test_dir).unwrap();
+    }
+
+    #[test]
+    fn try_open_errors_test() {
+        assert!(matches!(
+            Database::try_open(""),
+            Err(DbError::InvalidConfig {
+                setting: "database name",
+                ..
+            })
+        ));
+
+        //a file where a parent directory goes
+        let test_dir = testing::test_dir("try_open_errors_test");
+        if test_dir.exists() {
+            std::fs::remove_dir_all(test_dir).unwrap();
+        }
+        std::fs::create_dir_all(test_dir).unwrap();
+        let blocked = test_dir.join("blocked");
+        std::fs::write(&blocked, []).unwrap();
+        assert!(matches!(
+            Database::try_open(blocked.join("test")),
+            Err(DbError::Io(_))
+        ));
+        std::fs::remove_dir_all(test_dir).unwrap();
+    }
+
+    #[cfg(feature = "serde-config")]
+    #[test]
+    fn hand_rolled_state_encoding_matches_bincode_test() {
+        let mut config = Config::new();
+        config.max_garbage_ratio = Some(0.75);
+        let versioned_config = (Config::VERSION, &config);
+        assert_eq!(
+            serde_state::encode(&versioned_config),
+            bincode::serialize(&versioned_config).unwrap()
+        );
+        let metadata = Metadata {
+            entry_counts: vec![vec![3, 1], vec![], vec![8]],
+            tombstone_counts: vec![vec![1, 0], vec![], vec![2]],
+            compaction_entries_dropped: 5,
+            expiry_counts: vec![vec![0, 1], vec![], vec![0]],
+            page_sizes: vec![vec![4096, 16384], vec![], vec![4096]],
+            last_sequence: 42,
+            max_sequences: vec![vec![30, 42], vec![], vec![7]],
+            operand_counts: vec![vec![2, 0], vec![], vec![0]],
+            key_sketches: vec![
+                vec![KeySketch::from_keys([1, 2, 3]), KeySketch::from_keys([-4])],
+                vec![],
+                vec![KeySketch::from_keys(0..8)],
+            ],
+            partitions: vec![vec![], vec![], vec![(-5, 40)]],
+        };
+        let bytes = serde_state::encode(&(Metadata::VERSION, &metadata));
+        assert_eq!(
+            bytes,
+            bincode::serialize(&(Metadata::VERSION, &metadata)).unwrap()
+        );
+        let decoded = Metadata::deserialize(&bytes).unwrap();
+        assert_eq!(decoded.entry_counts, metadata.entry_counts);
+        assert_eq!(decoded.tombstone_counts, metadata.tombstone_counts);
+        assert_eq!(decoded.expiry_counts, metadata.expiry_counts);
+        assert_eq!(decoded.page_sizes, metadata.page_sizes);
+        assert_eq!(decoded.last_sequence, metadata.last_sequence);
+        assert_eq!(decoded.max_sequences, metadata.max_sequences);
+        assert_eq!(decoded.operand_counts, metadata.operand_counts);
+        assert_eq!(decoded.key_sketches, metadata.key_sketches);
+        assert_eq!(decoded.partitions, metadata.partitions);
+    }
+
+    #[cfg(not(all(feature = "bloom", feature = "btree", feature = "buffer-pool")))]
+    #[test]
+    fn uncompiled_features_are_disabled_test() {
+        let mut alterations = |db: Database| {
+            db.set_enable_bloom_filter(true)
+                .set_sst_implementation(SstImplementation::Btree)
+                .set_enable_buffer_pool(true)
+        };
+        let mut test = |db: Database| {
+            assert_eq!(db.enable_bloom_filter(), cfg!(feature = "bloom"));
+            assert_eq!(
+                matches!(db.sst_implementation(), SstImplementation::Btree),
+                cfg!(feature = "btree")
+            );
+            assert_eq!(db.enable_buffer_pool(), cfg!(feature = "buffer-pool"));
+            db
+        };
+        setup_and_test_and_cleaup(
+            "uncompiled_features_are_disabled_test",
+            &mut alterations,
+            &mut test,
+        );
+    }
+
+    #[test]
+    fn key_codec_mismatch_test() {
+        let test_dir = testing::test_dir("key_codec_mismatch_test");
+        let db_name = test_dir.join("test");
+        if test_dir.exists() {
+            std::fs::remove_dir_all(test_dir).unwrap();
+        }
+        std::fs::create_dir_all(test_dir).unwrap();
+
+        let mut db = Database::open(&db_name)
+            .set_memtable_capacity(2)
+            .set_key_codec(KeyCodec::OrderPreserving);
+        for key in -3..3 {
+            db.put(key, key).unwrap();
+        }
+        db.close();
+
+        //codec is persisted, reopening without specifying it should use the original codec
+        let mut db = Database::open(&db_name);
+        assert_eq!(db.key_codec(), KeyCodec::OrderPreserving);
+        assert_eq!(
+            db.scan(-3, 2),
+            (-3..3).map(|key| (key, key)).collect::<Vec<_>>()
+        );
+        db.close();
+
+        let mismatch = std::panic::catch_unwind(|| {
+            Database::open(&db_name).set_key_codec(KeyCodec::LittleEndian)
+        });
+        assert!(
+            mismatch.is_err(),
+            "Opened database with the wrong key codec"
+        );
+
+        std::fs::remove_dir_all(test_dir).unwrap();
+    }
+
+    #[test]
+    fn import_delimited_test() {
+        //an imported file reads the same as putting its lines one by one, whether it's sorted in memory or spilled
+        let num_keys: Key = 2000;
+        let mut lines: Vec<Entry> = (0..num_keys).map(|i| ((i * 7919) % num_keys, i)).collect(); //shuffled keys
+        lines.extend((0..num_keys).step_by(13).map(|key| (key, -key))); //duplicates, the last line wins
+        let text: String = std::iter::once("key\tvalue\n".to_string())
+            .chain(lines.iter().map(|(key, value)| format!("{key}\t{value}\n")))
+            .collect();
+
+        for memory_budget in [ImportOptions::DEFAULT_MEMORY_BUDGET, 100 * 24] {
+            let mut alterations = |db: Database| {
+                testing::part3_db_alterations(db)
+                    .set_memtable_capacity(system_info::num_entries_per_page())
+            };
+            let mut test = |mut db: Database| {
+                let mut expected_db = testing::part3_db_alterations(open_in(
+                    db.storage(),
+                    db.name.with_file_name("test_puts"),
+                ))
+                .set_memtable_capacity(system_info::num_entries_per_page());
+                for (key, value) in &lines {
+                    expected_db.put(*key, *value).unwrap();
+                }
+
+                let options = ImportOptions {
+                    memory_budget,
+                    ..ImportOptions::new(Delimiter::Tab, true)
+                };
+                let imported = db
+                    .import_delimited_with_options(text.as_bytes(), options)
+                    .unwrap();
+                assert_eq!(imported, num_keys as Size);
+                assert!(!filename::import_directory(&db.name).exists());
+                let expected = expected_db.scan(Key::MIN + 1, Key::MAX);
+                assert_eq!(expected.len(), num_keys as Size);
+                assert_eq!(db.scan(Key::MIN + 1, Key::MAX), expected);
+                for key in [0, 13, 14, num_keys - 1] {
+                    assert_eq!(db.get(key), expected_db.get(key));
+                }
+                expected_db.close();
+                db
+            };
+            setup_and_test_and_cleaup("import_delimited_test", &mut alterations, &mut test);
+        }
+    }
+
+    #[test]
+    fn import_delimited_error_test() {
+        let mut alterations = testing::part1_db_alterations;
+        let mut test = |mut db: Database| {
+            db.put(1, 1).unwrap();
+            let error_line = |result: Result<Size, DbError>| match result {
+                Err(DbError::Import { line, .. }) => line,
+                other => panic!("expected an import error, got {other:?}"),
+            };
+            let tombstone = format!("2,2\n3,{}\n", Database::TOMBSTONE_VALUE);
+            assert_eq!(
+                error_line(db.import_delimited(tombstone.as_bytes(), Delimiter::Comma, false)),
+                2
+            );
+            assert_eq!(
+                error_line(db.import_delimited(
+                    "k,v\n2,2\n\n3\n".as_bytes(),
+                    Delimiter::Comma,
+                    true
+                )),
+                4
+            );
+
+            //duplicates across spilled chunks are found before anything is put
+            let options = ImportOptions {
+                duplicate_keys: DuplicateKeys::Error,
+                memory_budget: 1,
+                ..ImportOptions::new(Delimiter::Comma, false)
+            };
+            let duplicate = "5,5\n1,2\n6,6\n5,7\n".as_bytes();
+            let why = db
+                .import_delimited_with_options(duplicate, options)
+                .unwrap_err();
+            assert!(
+                why.to_string().starts_with("line 4: duplicate key 5"),
+                "{why}"
+            );
+            assert_eq!(db.scan(0, 10), vec![(1, 1)]); //nothing was imported
+            db
+        };
+        setup_and_test_and_cleaup("import_delimited_error_test", &mut alterations, &mut test);
+    }
+
+    ///Open a database at <path>, check it round trips a few flushed entries through a reopen, then remove <test_dir>
+    fn open_at_path_test(test_dir: &Path, path: &Path) {
+        if test_dir.exists() {
+            std::fs::remove_dir_all(test_dir).unwrap();
+        }
+        let mut db = Database::open(path).set_memtable_capacity(2);
+        for key in 0..5 {
+            db.put(key, key * 10).unwrap();
+        }
+        assert_eq!(db.path(), path);
+        db.close();
+        assert!((&DatabaseName::new(path, Storage::disk()), 0, 0)
+            .path_sst()
+            .exists());
+
+        let mut db = Database::open(path);
+        assert_eq!(
+            db.scan(0, 4),
+            (0..5).map(|key| (key, key * 10)).collect::<Vec<_>>()
+        );
+        db.close();
+        std::fs::remove_dir_all(test_dir).unwrap();
+    }
+
+    #[test]
+    fn open_nested_path_test() {
+        let test_dir = testing::test_dir("open_nested_path_test");
+        open_at_path_test(test_dir, &test_dir.join("data").join("prod").join("db"));
+    }
+
+    #[test]
+    fn open_path_with_spaces_test() {
+        let test_dir = testing::test_dir("open path with spaces test");
+        open_at_path_test(test_dir, &test_dir.join("my db"));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn open_non_utf8_path_test() {
+        use std::{ffi::OsStr, os::unix::ffi::OsStrExt};
+        let test_dir = testing::test_dir("open_non_utf8_path_test");
+        open_at_path_test(test_dir, &test_dir.join(OsStr::from_bytes(b"db\xff")));
+    }
+
+    #[cfg(windows)]
+    #[test]
+    fn open_backslash_path_test() {
+        let test_dir = testing::test_dir("open_backslash_path_test");
+        open_at_path_test(test_dir, &test_dir.join(r"data\db"));
+    }
+
+    ///Reads every key after each flush so the pages of every run (and their bloom filters) are cached when the next
+    /// flush moves, compacts or rewrites them. Test builds panic if a cached page outlives the file it came from
+    fn cached_pages_survive_file_replacement_test(
+        test_name: &str,
+        compaction_policy: CompactionPolicy,
+        sst_size_ratio: Size,
+        sst_implementation: SstImplementation,
+    ) {
+        let mut alterations = |db: Database| {
+            testing::part3_db_alterations(db)
+                .set_compaction_policy(compaction_policy)
+                .set_sst_size_ratio(sst_size_ratio)
+                .set_sst_implementation(sst_implementation)
+                .set_buffer_pool_capacity_pages(64)
+                .set_memtable_capacity(4)
+        };
+        let mut test = |mut db: Database| {
+            let num_keys = 12;
+            for round in 0..12 {
+                for key in 0..num_keys {
+                    if key % 3 == round % 3 {
+                        db.put(key, round * 100 + key).unwrap();
+                    }
+                }
+                for key in 0..num_keys {
+                    let expected = (0..=round).rev().find(|written| key % 3 == written % 3);
+                    assert_eq!(
+                        db.get(key),
+                        expected.map(|written| written * 100 + key),
+                        "key {key} after round {round}"
+                    );
+                }
+            }
+            db
+        };
+        setup_and_test_and_cleaup(test_name, &mut alterations, &mut test);
+    }
+
+    #[test]
+    fn cached_pages_survive_move_runs_test() {
+        //level 1 never fills up, every flush only renames level 0's run into it
+        cached_pages_survive_file_replacement_test(
+            "cached_pages_survive_move_runs_test",
+            CompactionPolicy::Tiered,
+            100,
+            SstImplementation::Btree,
+        );
+    }
+
+    #[test]
+    fn cached_pages_survive_compaction_test() {
+        //compaction replaces run 0 (and its bloom filter) with the merged run while the old pages are cached
+        for (index, compaction_policy) in [
+            CompactionPolicy::Leveled,
+            CompactionPolicy::Tiered,
+            CompactionPolicy::Dostoevsky,
+        ]
+        .into_iter()
+        .enumerate()
+        {
+            for sst_implementation in [SstImplementation::Array, SstImplementation::Btree] {
+                cached_pages_survive_file_replacement_test(
+                    &format!("cached_pages_survive_compaction_test_{index}"),
+                    compaction_policy,
+                    2,
+                    sst_implementation,
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn toggle_buffer_pool_test() {
+        let mut alterations = |db: Database| {
+            testing::part3_db_alterations(db)
+                .set_compaction_policy(CompactionPolicy::Leveled)
+                .set_buffer_pool_capacity_pages(64)
+                .set_memtable_capacity(4)
+        };
+        let mut test = |mut db: Database| {
+            let num_keys = 16;
+            //every key holds <base> + key
+            let check = |db: &mut Database, base: Value| {
+                for key in 0..num_keys {
+                    assert_eq!(db.get(key), Some(base + key), "base {base}");
+                }
+            };
+            for round in 0..4 {
+                for key in 0..num_keys {
+                    db.put(key, round * 100 + key).unwrap();
+                }
+                check(&mut db, round * 100); //caches the pages of every run
+                let cached_frames = db.buffer_pool_stats().frames;
+                assert_eq!(cached_frames > 0, db.enable_buffer_pool());
+
+                //compactions replace every cached run while the pool is disabled
+                db = db.set_enable_buffer_pool(false);
+                assert_eq!(db.buffer_pool_stats().frames, 0);
+                for key in 0..num_keys {
+                    db.put(key, round * 100 + key + 50).unwrap();
+                }
+                db.flush_memtable().unwrap();
+                check(&mut db, round * 100 + 50);
+                assert_eq!(db.buffer_pool_stats().frames, 0);
+
+                //test builds panic if a page cached before the pool was disabled is served
+                db = db.set_enable_buffer_pool(true);
+                check(&mut db, round * 100 + 50);
+                for key in 0..num_keys {
+                    db.put(key, round * 100 + key + 75).unwrap();
+                }
+                check(&mut db, round * 100 + 75);
+            }
+            db
+        };
+        setup_and_test_and_cleaup("toggle_buffer_pool_test", &mut alterations, &mut test);
+    }
+
+    #[test]
+    fn lazy_leveling_layout_test() {
+        const SIZE_RATIO: Size = 3;
+        //unique keys, so merges keep every entry and the layouts only depend on the policy
+        for (compaction_policy, expected_layout, max_upper_level_runs) in [
+            (
+                CompactionPolicy::Lazy,
+                vec![vec![4], vec![], vec![], vec![], vec![76]],
+                SIZE_RATIO - 2,
+            ),
+            (
+                CompactionPolicy::Dostoevsky,
+                vec![vec![4], vec![4], vec![], vec![72]],
+                SIZE_RATIO - 1,
+            ),
+        ] {
+            let mut alterations = |db: Database| {
+                testing::part1_db_alterations(db)
+                    .set_compaction_policy(compaction_policy)
+                    .set_memtable_capacity(4)
+                    .set_sst_size_ratio(SIZE_RATIO)
+            };
+            let mut test = |mut db: Database| {
+                //most runs seen in a level between level 0 and the last level
+                let mut most_upper_level_runs = 0;
+                for key in 0..4 * 20 {
+                    db.put(key, key).unwrap();
+                    let entry_counts = &db.metadata.entry_counts;
+                    let upper_levels = entry_counts.len().saturating_sub(1).max(1);
+                    for level_entry_counts in &entry_counts[1..upper_levels] {
+                        most_upper_level_runs = most_upper_level_runs.max(level_entry_counts.len());
+                    }
+                }
+                db.flush_memtable().unwrap();
+                assert_eq!(
+                    db.metadata.entry_counts, expected_layout,
+                    "{compaction_policy:?}"
+                );
+                assert_eq!(
+                    most_upper_level_runs, max_upper_level_runs,
+                    "{compaction_policy:?}"
+                );
+                for key in 0..4 * 20 {
+                    assert_eq!(db.get(key), Some(key));
+                }
+                db
+            };
+            setup_and_test_and_cleaup("lazy_leveling_layout_test", &mut alterations, &mut test);
+        }
+    }
+
+    #[test]
+    fn compaction_plan_matches_flushes_test() {
+        //random flushes of unique keys and deletes, so merges drop nothing but discarded tombstones and the plan's
+        // estimates are exact
+        use rand::{Rng, SeedableRng};
+
+        const MEMTABLE_CAPACITY: Size = 4;
+        for (index, compaction_policy) in [
+            CompactionPolicy::None,
+            CompactionPolicy::Leveled,
+            CompactionPolicy::Tiered,
+            CompactionPolicy::Dostoevsky,
+            CompactionPolicy::Lazy,
+        ]
+        .into_iter()
+        .enumerate()
+        {
+            let mut rng = rand::rngs::StdRng::seed_from_u64(index as u64);
+            let size_ratio = rng.gen_range(2..=4);
+            let mut alterations = |db: Database| {
+                db.set_memtable_capacity(MEMTABLE_CAPACITY)
+                    .set_compaction_policy(compaction_policy)
+                    .set_sst_size_ratio(size_ratio)
+            };
+            let mut test = |mut db: Database| {
+                let mut next_key: Key = 0;
+                let mut write = |db: &mut Database, rng: &mut rand::rngs::StdRng| {
+                    if rng.gen_bool(0.3) {
+                        db.delete(next_key).unwrap();
+                    } else {
+                        db.put(next_key, next_key).unwrap();
+                    }
+                    next_key += 1;
+                };
+                for _ in 0..MEMTABLE_CAPACITY {
+                    write(&mut db, &mut rng);
+                }
+                for _ in 0..60 {
+                    let plan = db.compaction_plan();
+                    write(&mut db, &mut rng); //memtable is full, this flushes it
+                    assert_eq!(
+                        db.metadata.entry_counts, plan.entry_counts,
+                        "{compaction_policy:?} with size ratio {size_ratio}, planned {:?}",
+                        plan.steps
+                    );
+                    let run_counts: Vec<Size> = (0..db.level_shape.num_levels())
+                        .map(|level| db.sst_count(level))
+                        .collect();
+                    assert_eq!(plan.run_counts(), run_counts);
+                    for _ in 1..MEMTABLE_CAPACITY {
+                        write(&mut db, &mut rng);
+                    }
+                }
+                db
+            };
+            setup_and_test_and_cleaup(
+                format!("compaction_plan_matches_flushes_test_{index}"),
+                &mut alterations,
+                &mut test,
+            );
+        }
+    }
+
+    #[test]
+    fn io_mode_without_direct_io_test() {
+        //a filesystem without direct I/O (like tmpfs on older kernels) works in Auto and Buffered mode, forcing
+        // direct I/O is an error
+        let test_dir = testing::test_dir("io_mode_without_direct_io_test");
+        direct_io::tests::simulate_no_direct_io(test_dir);
+        let num_keys: Key = 500;
+        let check = |db: &mut Database| {
+            for key in 0..num_keys {
+                let expected = (key % 3 != 0).then_some(-key);
+                assert_eq!(db.get(key), expected, "key {key}");
+            }
+            assert_eq!(
+                db.scan(0, num_keys).len(),
+                (0..num_keys).filter(|key| key % 3 != 0).count()
+            );
+        };
+        let mut alterations = |db: Database| {
+            db.set_memtable_capacity(16)
+                .set_compaction_policy(CompactionPolicy::Leveled)
+                .set_sst_size_ratio(2)
+                .set_enable_fence_index(true)
+        };
+        let mut test = |mut db: Database| {
+            assert_eq!(db.io_mode(), IoMode::Auto);
+            assert!(!db.uses_direct_io());
+            for key in 0..num_keys {
+                db.put(key, -key).unwrap();
+            }
+            for key in (0..num_keys).step_by(3) {
+                db.delete(key).unwrap();
+            }
+            check(&mut db);
+            let name = db.name();
+            let why = db.try_set_io_mode(IoMode::Direct).unwrap_err();
+            assert!(
+                why.to_string().contains("direct I/O isn't supported"),
+                "{why}"
+            );
+            //the database stays open, in the mode it had
+            assert_eq!(db.io_mode(), IoMode::Auto);
+            check(&mut db);
+
+            let mut db = db.set_io_mode(IoMode::Buffered);
+            check(&mut db);
+            db.close();
+            let mut db = open_in(db.storage(), &name);
+            assert_eq!(db.io_mode(), IoMode::Buffered);
+            check(&mut db);
+            db
+        };
+        setup_and_test_and_cleaup(test_dir, &mut alterations, &mut test);
+
+        //a database saved in Direct mode doesn't open on a filesystem without it
+        let test_dir = testing::test_dir("io_mode_direct_reopen_test");
+        if test_dir.exists() {
+            fs::remove_dir_all(test_dir).unwrap();
+        }
+        let name = test_dir.join("test");
+        let mut db = Database::open(&name);
+        if db.try_set_io_mode(IoMode::Direct).is_ok() {
+            assert!(db.uses_direct_io());
+            db.put(1, 1).unwrap();
+            db.close();
+            direct_io::tests::simulate_no_direct_io(test_dir);
+            match try_open_in(db.storage(), &name) {
+                Err(DbError::Io(why)) => assert_eq!(why.kind(), io::ErrorKind::Unsupported),
+                other => panic!("expected an unsupported direct I/O error, got {other:?}"),
+            }
+        } //else the filesystem really has no direct I/O
+        fs::remove_dir_all(test_dir).unwrap();
+    }
+
+    #[test]
+    fn move_runs_keeps_recency_test() {
+        //runs moved into a level that already has runs are younger than them, so they must get higher run numbers
+        for background_compaction in [false, true] {
+            let mut alterations = |db: Database| {
+                db.set_compaction_policy(CompactionPolicy::Tiered)
+                    .set_sst_size_ratio(10) //level 1 never fills up
+                    .set_memtable_capacity(1)
+                    .set_background_compaction(background_compaction)
+            };
+            let mut test = |mut db: Database| {
+                db.put(7, 1).unwrap();
+                db.put(8, 0).unwrap(); //flushes 7=1
+                db.put(7, 2).unwrap(); //moves 7=1 to level 1
+                db.put(9, 0).unwrap();
+                db.put(10, 0).unwrap(); //moves 7=2 to level 1 as well
+                let check = |db: &mut Database| {
+                    assert_eq!(db.get(7), Some(2));
+                    assert_eq!(db.scan(7, 7), vec![(7, 2)]);
+                    assert_eq!(db.scan(0, 100).first(), Some(&(7, 2)));
+                };
+                check(&mut db);
+                if !background_compaction {
+                    assert_eq!(db.metadata.entry_counts[1], vec![1, 1, 1]);
+                }
+                let name = db.name();
+                db.close();
+                let mut db = open_in(db.storage(), &name);
+                check(&mut db);
+                db
+            };
+            setup_and_test_and_cleaup(
+                format!("move_runs_keeps_recency_test_{background_compaction}"),
+                &mut alterations,
+                &mut test,
+            );
+        }
+    }
+
+    ///Puts and deletes <ops> (None deletes) with the memtable holding <memtable_capacity> entries, checking after
+    /// each that every flushed run holds exactly memtable_capacity entries and the triggering write is in the memtable
+    fn memtable_capacity_boundary_test(
+        test_name: &str,
+        memtable_capacity: Size,
+        ops: &[(Key, Option<Value>)],
+    ) {
+        let mut alterations = |db: Database| db.set_memtable_capacity(memtable_capacity);
+        let mut test = |mut db: Database| {
+            let mut expected = std::collections::BTreeMap::new();
+            let mut memtable_keys = std::collections::BTreeSet::new();
+            let mut runs = 0;
+            for &(key, value) in ops {
+                match value {
+                    Some(value) => {
+                        db.put(key, value).unwrap();
+                        expected.insert(key, value);
+                    }
+                    None => {
+                        db.delete(key).unwrap();
+                        expected.remove(&key);
+                    }
+                }
+                if memtable_keys.len() == memtable_capacity {
+                    runs += 1;
+                    memtable_keys.clear();
+                }
+                memtable_keys.insert(key);
+
+                assert_eq!(db.sst_count(0), runs, "after writing key {key}");
+                assert_eq!(db.memtable.len(), memtable_keys.len());
+                assert!(db.metadata.entry_counts[0]
+                    .iter()
+                    .all(|&count| count == memtable_capacity));
+                assert_eq!(db.get(key), value);
+                assert_eq!(
+                    db.scan(Key::MIN + 1, Key::MAX),
+                    expected.clone().into_iter().collect::<Vec<_>>()
+                );
+            }
+            db
+        };
+        setup_and_test_and_cleaup(test_name, &mut alterations, &mut test);
+    }
+
+    #[test]
+    fn memtable_capacity_one_test() {
+        //every write but the first flushes the previous one
+        memtable_capacity_boundary_test(
+            "memtable_capacity_one_test",
+            1,
+            &[
+                (1, Some(10)),
+                (1, Some(11)),
+                (2, Some(20)),
+                (1, None),
+                (3, Some(30)),
+                (3, None),
+                (2, None),
+                (1, Some(12)),
+            ],
+        );
+    }
+
+    #[test]
+    fn memtable_capacity_two_test() {
+        //deletes take up room like puts, overwriting a key in a memtable with room doesn't
+        memtable_capacity_boundary_test(
+            "memtable_capacity_two_test",
+            2,
+            &[
+                (1, Some(10)),
+                (1, None),
+                (2, Some(20)),
+                (3, Some(30)),
+                (2, None),
+                (2, Some(21)),
+                (4, None),
+                (1, Some(11)),
+                (5, Some(50)),
+                (5, None),
+                (3, None),
+            ],
+        );
+    }
+
+    #[test]
+    fn zero_memtable_capacity_rejected_test() {
+        let mut alterations = |db: Database| db;
+        let mut test = |mut db: Database| {
+            let capacity = db.memtable_capacity();
+            let why = db.try_set_memtable_capacity(0).unwrap_err();
+            assert!(matches!(why, DbError::InvalidConfig { .. }), "{why}");
+            //the database stays open with the capacity it had
+            assert_eq!(db.memtable_capacity(), capacity);
+            db.put(1, 1).unwrap();
+            db.try_set_memtable_capacity(1).unwrap();
+            assert_eq!(db.memtable_capacity(), 1);
+
+            //a capacity that doesn't fit in 32 bits couldn't be told apart from an unversioned config
+            if Size::BITS > 32 {
+                let why = db
+                    .try_set_memtable_capacity(Database::MAX_MEMTABLE_CAPACITY + 1)
+                    .unwrap_err();
+                assert!(matches!(why, DbError::InvalidConfig { .. }), "{why}");
+                let why = db.try_set_memtable_capacity_mb(65536).unwrap_err(); //2^32 entries
+                assert!(matches!(why, DbError::InvalidConfig { .. }), "{why}");
+                assert_eq!(db.memtable_capacity(), 1);
+            }
+            let mut config = db.config.clone();
+            config.memtable_capacity = Database::MAX_MEMTABLE_CAPACITY;
+            let reread = Config::deserialize(&config.serialize()).unwrap();
+            assert_eq!(reread.memtable_capacity, Database::MAX_MEMTABLE_CAPACITY);
+            db
+        };
+        setup_and_test_and_cleaup(
+            "zero_memtable_capacity_rejected_test",
+            &mut alterations,
+            &mut test,
+        );
+    }
+
+    #[test]
+    fn capacity_overflow_rejected_test() {
+        let max_mb = Database::MAX_CAPACITY_MB;
+        assert_eq!(
+            Database::mb_to_bytes("test", max_mb).unwrap(),
+            max_mb * 2_usize.pow(20)
+        );
+        for mb in [max_mb + 1, Size::MAX] {
+            let why = Database::mb_to_bytes("test", mb).unwrap_err();
+            assert!(matches!(why, DbError::InvalidConfig { .. }), "{why}");
+        }
+        let why = Database::checked_bytes("test", Size::MAX / 2 + 1, 2, "pages").unwrap_err();
+        assert!(why.to_string().contains("pages"), "{why}");
+        //what wrapped around on 32-bit targets: 4096MB is 2^32 bytes
+        assert_eq!(Database::mb_to_bytes("test", 4096).is_ok(), Size::BITS > 32);
+
+        let db_name = testing::test_dir("capacity_overflow_rejected_test");
+        type Setter = fn(&mut Database) -> Result<(), DbError>;
+        let setters: [Setter; 5] = [
+            |db| db.try_set_memtable_capacity_mb(Size::MAX),
+            |db| db.try_set_buffer_pool_capacity_mb(Database::MAX_CAPACITY_MB + 1),
+            |db| db.try_set_buffer_pool_initial_size_mb(Size::MAX),
+            |db| db.try_set_buffer_pool_capacity(0),
+            |db| db.try_set_buffer_pool_initial_size(0),
+        ];
+        let mut db = Database::open_in_memory(db_name);
+        let config = db.config.serialize();
+        for setter in setters {
+            let why = setter(&mut db).unwrap_err();
+            assert!(matches!(why, DbError::InvalidConfig { .. }), "{why}");
+        }
+        assert_eq!(db.config.serialize(), config); //nothing was set
+        let too_many_pages = std::panic::catch_unwind(|| {
+            Database::open_in_memory(db_name).set_buffer_pool_capacity_pages(Size::MAX)
+        });
+        assert!(too_many_pages.is_err());
+
+        db.try_set_memtable_capacity_mb(1).unwrap();
+        db.try_set_buffer_pool_capacity_mb(2).unwrap();
+        db.try_set_buffer_pool_initial_size_mb(1).unwrap();
+        assert_eq!(db.memtable_capacity(), 2_usize.pow(20) / ENTRY_SIZE);
+        assert_eq!(db.buffer_pool_capacity(), 2 * 2_usize.pow(20));
+        assert_eq!(
+            db.buffer_pool_initial_size(),
+            2_usize.pow(20) / system_info::page_size()
+        );
+    }
+
+    #[test]
+    fn reserved_put_rejected_test() {
+        let mut alterations = |db: Database| db.set_memtable_capacity(4);
+        let mut test = |mut db: Database| {
+            db.put(1, 10).unwrap();
+            let memtable_len = db.memtable.len();
+            assert!(matches!(
+                db.put(2, Database::TOMBSTONE_VALUE),
+                Err(DbError::ReservedValue)
+            ));
+            assert!(matches!(
+                db.put(Database::INVALID_KEY, 20),
+                Err(DbError::ReservedKey)
+            ));
+            assert!(matches!(
+                db.put_with_ttl(Database::INVALID_KEY, 20, 60),
+                Err(DbError::ReservedKey)
+            ));
+            assert!(matches!(
+                db.delete(Database::INVALID_KEY),
+                Err(DbError::ReservedKey)
+            ));
+            //nothing was put
+            assert_eq!(db.memtable.len(), memtable_len);
+            assert_eq!(db.get(2), None);
+            assert_eq!(db.scan(Key::MIN + 1, Key::MAX), vec![(1, 10)]);
+            db
+        };
+        setup_and_test_and_cleaup("reserved_put_rejected_test", &mut alterations, &mut test);
+    }
+
+    #[test]
+    fn delete_invalid_key_test() {
+        //deleting the reserved key used to insert a tombstone for it, which overflowed the scan's heap once flushed
+        let mut alterations = |db: Database| db.set_memtable_capacity(2);
+        let mut test = |mut db: Database| {
+            assert!(db.delete(Database::INVALID_KEY).is_err());
+            let expected: Vec<Entry> = (0..8).map(|key| (key, key * 10)).collect();
+            for (key, value) in &expected {
+                db.put(*key, *value).unwrap();
+            }
+            assert_eq!(db.scan(Key::MIN + 1, Key::MAX), expected);
+            assert_eq!(db.scan(Key::MIN + 1, 3), expected[..4]);
+            db
+        };
+        setup_and_test_and_cleaup("delete_invalid_key_test", &mut alterations, &mut test);
+    }
+
+    #[cfg(feature = "bloom")]
+    #[test]
+    fn bloom_levels_test() {
+        for background_compaction in [false, true] {
+            let test_dir = testing::test_dir("bloom_levels_test");
+            let db_name = test_dir.join("test");
+            if test_dir.exists() {
+                std::fs::remove_dir_all(test_dir).unwrap();
+            }
+            let num_keys: Key = 64;
+            let mut db = testing::part3_db_alterations(Database::open(&db_name))
+                .set_bloom_levels(BloomLevels::UpTo(2))
+                .set_memtable_capacity(4)
+                .set_background_compaction(background_compaction);
+            for key in 0..num_keys {
+                db.put(key, key * 10).unwrap();
+            }
+            db.close();
+
+            //only levels 0 and 1 keep filters, runs moved or compacted past them lose theirs
+            let mut db = Database::open(&db_name);
+            assert_eq!(db.bloom_levels(), BloomLevels::UpTo(2));
+            let entry_counts = db.metadata.entry_counts.clone();
+            assert!(
+                entry_counts.iter().skip(2).any(|counts| !counts.is_empty()),
+                "{entry_counts:?}"
+            );
+            for (level, counts) in entry_counts.iter().enumerate() {
+                for run in 0..counts.len() {
+                    let bloom_path = (&db.name, level, run).path_bloom();
+                    assert_eq!(bloom_path.exists(), level < 2, "{}", bloom_path.display());
                 }
             }
-            false
-        };
-        Self::for_each_sst(&self.metadata.entry_counts, &mut callback);
-        let mut sorted_values = Vec::with_capacity(max_heap.len());
-        while let Some((negative_key, value)) = max_heap.pop() {
-            if value != Self::TOMBSTONE_VALUE {
-                sorted_values.push((-negative_key, value))
+            assert!(db.describe_layout().contains("no bloom filters"));
+            for key in 0..num_keys {
+                assert_eq!(db.get(key), Some(key * 10));
             }
-        }
+            assert_eq!(db.get(num_keys), None);
 
-        sorted_values
+            //turning filters back on doesn't write them for existing runs, those are searched without one
+            let mut db = db.set_bloom_levels(BloomLevels::All);
+            for key in 0..num_keys {
+                assert_eq!(db.get(key), Some(key * 10));
+            }
+            assert_eq!(db.get(-1), None);
+            db.close();
+            std::fs::remove_dir_all(test_dir).unwrap();
+        }
     }
-}
 
-///Runs on destruction, closes DB automatically
-impl Drop for Database {
-    fn drop(&mut self) {
-        self.close();
+    #[test]
+    fn iter_range_across_flushes_test() {
+        let memtable_capacity = 4;
+        let mut alterations = |db: Database| {
+            testing::part3_db_alterations(db)
+                .set_compaction_policy(CompactionPolicy::Leveled)
+                .set_memtable_capacity(memtable_capacity)
+        };
+        let mut test = |mut db: Database| {
+            //half the range is in runs and half in the memtable when the iterator is created
+            for key in 0..6 {
+                db.put(key, key).unwrap();
+            }
+            let expected = db.scan(0, 100);
+            assert_eq!(expected.len(), 6);
+            let iter = db.iter_range(0, 100);
+            assert_eq!(iter.len(), expected.len());
+
+            let mut seen = Vec::new();
+            let mut next_key = 6;
+            for entry in iter {
+                let entry = entry.unwrap();
+                seen.push(entry);
+                //overwrite and delete what's already been seen and what's still ahead, and add new keys to the range
+                db.put(entry.0, -1).unwrap();
+                db.delete(5 - entry.0).unwrap();
+                for _ in 0..memtable_capacity {
+                    db.put(next_key, next_key).unwrap();
+                    next_key += 1;
+                }
+            }
+            assert_eq!(seen, expected);
+            //several flushes, and compactions that dropped the overwritten values the iterator was created over
+            assert!(db.metadata.compaction_entries_dropped > 0);
+            assert_eq!(db.get(0), None);
+            assert_eq!(db.get(5), Some(-1));
+
+            //an iterator that's never advanced sees the range as it was too
+            let before = db.scan(0, 100);
+            let iter = db.iter_range(0, 100);
+            for key in 0..20 {
+                db.delete(key).unwrap();
+            }
+            db.flush_memtable().unwrap();
+            assert_eq!(
+                iter.rev().collect::<Result<Vec<_>, _>>().unwrap(),
+                before.into_iter().rev().collect::<Vec<_>>()
+            );
+            db
+        };
+        setup_and_test_and_cleaup(
+            "iter_range_across_flushes_test",
+            &mut alterations,
+            &mut test,
+        );
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use crate::util::{btree_info::fanout, testing};
+    #[test]
+    fn fence_index_test() {
+        let entries_per_page = system_info::num_entries_per_page();
+        let num_keys = (32 * entries_per_page) as Key;
+        let boundary = (10 * entries_per_page) as Key; //first key of page 5 of the runs of even keys
+        let key_ranges = [
+            (boundary + 3, boundary + 9),   //within 1 page
+            (boundary - 10, boundary + 10), //across a page boundary
+            (-1, num_keys),                 //the whole run
+            (num_keys + 1, num_keys + 10),  //past the run
+        ];
+        //(results, pages read by the scans, pages read by the gets)
+        let run = |enable_fence_index: bool| {
+            let test_dir = testing::test_dir(format!("fence_index_test_{enable_fence_index}"));
+            let db_name = test_dir.join("test");
+            if test_dir.exists() {
+                std::fs::remove_dir_all(test_dir).unwrap();
+            }
+            let mut db = Database::open(&db_name)
+                .set_sst_implementation(SstImplementation::Array)
+                .set_compaction_policy(CompactionPolicy::Leveled)
+                .set_enable_buffer_pool(false)
+                .set_enable_bloom_filter(false)
+                .set_memtable_capacity(8 * entries_per_page)
+                .set_enable_fence_index(enable_fence_index);
+            //interleaved flushes, so the compacted run's pages hold keys from every flush
+            for key in (0..num_keys).step_by(2).chain((1..num_keys).step_by(2)) {
+                db.put(key, key * 10).unwrap();
+            }
+            db.flush_memtable().unwrap();
+            let fence_path = filename::fence_path(&(&db.name, 1, 0));
+            assert_eq!(fence_path.exists(), enable_fence_index);
 
-    use super::*;
+            db.reset_io_stats();
+            let results: Vec<Vec<Entry>> = key_ranges
+                .iter()
+                .map(|(key1, key2)| db.scan(*key1, *key2))
+                .collect();
+            let scan_pages_read = db.io_stats().pages_read;
+            db.reset_io_stats();
+            for key in [0, boundary - 1, boundary, num_keys - 1, num_keys] {
+                let expected = (key < num_keys).then_some(key * 10);
+                assert_eq!(db.get(key), expected);
+            }
+            let get_pages_read = db.io_stats().pages_read;
+            db.close();
+            std::fs::remove_dir_all(test_dir).unwrap();
+            (results, scan_pages_read, get_pages_read)
+        };
+        let (without_fences, scan_pages_without, get_pages_without) = run(false);
+        let (with_fences, scan_pages_with, get_pages_with) = run(true);
+        assert_eq!(with_fences, without_fences);
+        assert_eq!(without_fences[2].len(), num_keys as usize);
+        assert!(without_fences[3].is_empty());
+        assert!(
+            scan_pages_with < scan_pages_without,
+            "{scan_pages_with} pages read with fences, {scan_pages_without} without"
+        );
+        assert!(
+            get_pages_with < get_pages_without,
+            "{get_pages_with} pages read with fences, {get_pages_without} without"
+        );
+    }
 
-    fn setup_and_test_and_cleaup(
-        test_name: &str,
-        database_alterations: &mut dyn FnMut(Database) -> Database,
-        test: &mut dyn FnMut(Database) -> Database,
-    ) {
-        let test_dir = test_name;
-        let db_name = format!("{test_dir}/test");
-        if std::path::Path::new(test_dir).exists() {
-            std::fs::remove_dir_all(test_dir).unwrap(); //remove previous directory if panicked during tests and didn't clean up
+    #[test]
+    fn read_only_session_test() {
+        use std::hash::{DefaultHasher, Hash, Hasher};
+        //hash of every file's path, contents and modification time under <directory>
+        fn directory_hash(directory: &Path) -> u64 {
+            fn hash_files(directory: &Path, hasher: &mut DefaultHasher) {
+                let mut paths: Vec<PathBuf> = fs::read_dir(directory)
+                    .unwrap()
+                    .map(|entry| entry.unwrap().path())
+                    .collect();
+                paths.sort();
+                for path in paths {
+                    path.hash(hasher);
+                    if path.is_dir() {
+                        hash_files(&path, hasher);
+                    } else {
+                        fs::read(&path).unwrap().hash(hasher);
+                        fs::metadata(&path)
+                            .unwrap()
+                            .modified()
+                            .unwrap()
+                            .hash(hasher);
+                    }
+                }
+            }
+            let mut hasher = DefaultHasher::new();
+            hash_files(directory, &mut hasher);
+            hasher.finish()
         }
-        std::fs::create_dir_all(test_dir).unwrap();
+        let read = |db: &mut Database| {
+            assert_eq!(db.get(3), Some(30));
+            assert_eq!(db.get(100), None);
+            assert_eq!(db.scan(0, 9).len(), 10);
+            assert_eq!(db.get_floor(Key::MAX), Some((19, 190)));
+        };
 
-        let mut db = test(database_alterations(Database::open(&db_name)));
+        for (test_dir, background_compaction) in [
+            ("read_only_session_test", false),
+            ("read_only_session_background_test", true),
+        ] {
+            let test_dir = testing::test_dir(test_dir);
+            let db_name = test_dir.join("test");
+            if test_dir.exists() {
+                fs::remove_dir_all(test_dir).unwrap();
+            }
+            let mut db = testing::part3_db_alterations(Database::open(&db_name))
+                .set_memtable_capacity(4)
+                .set_background_compaction(background_compaction);
+            for key in 0..20 {
+                db.put(key, key * 10).unwrap();
+            }
+            db.close();
+            let written = directory_hash(&db_name);
 
-        db.close();
+            let mut db = Database::open(&db_name);
+            read(&mut db);
+            db.close();
+            assert_eq!(directory_hash(&db_name), written);
 
-        std::fs::remove_dir_all(test_dir).unwrap();
+            //dropping closes the same way
+            let mut db = Database::open(&db_name);
+            read(&mut db);
+            drop(db);
+            assert_eq!(directory_hash(&db_name), written);
+
+            //a changed setting rewrites the config
+            let mut db = Database::open(&db_name).set_sst_size_ratio(3);
+            read(&mut db);
+            db.close();
+            assert_ne!(directory_hash(&db_name), written);
+            assert_eq!(Database::open(&db_name).sst_size_ratio(), 3);
+
+            fs::remove_dir_all(test_dir).unwrap();
+        }
     }
 
-    fn small_db_test(test_name: &str, database_alterations: &mut dyn FnMut(Database) -> Database) {
-        let mut alterations = |db: Database| {
-            database_alterations(db).set_memtable_capacity(2) //memtable capacity needed for tests below
-        };
+    #[test]
+    fn changes_since_test() {
+        let mut alterations =
+            |db: Database| testing::part1_db_alterations(db).set_memtable_capacity(4);
         let mut test = |mut db: Database| {
-            //Test puts, and check if younger values are used instead of older values in gets
-            db.put(0, 1); //This value should be immediately replaced
-            assert_eq!(db.get(0), Some(1));
-            db.put(0, 2);
-            assert_eq!(db.get(0), Some(2)); //This value should be replaced after flushing
-            db.put(10, 100);
-            db.put(20, 200);
-            db.put(0, 3); //original value of 0, should have been flushed
-            assert_eq!(db.get(0), Some(3)); //case: memtable "blocking" sst search
-            db.put(30, 300);
-            db.put(40, 400);
-            assert_eq!(db.get(0), Some(3)); //case: sst blocking older sst search
-            db.put(0, 10);
-
-            if db.compaction_policy() == CompactionPolicy::None {
-                assert_eq!(db.sst_count(0), 3); // Should have flushed 3 times (since capacity is 2), NOTE: this will be wrong if the previous call to this function panicked
+            for key in 0..8 {
+                db.put(key, key * 10).unwrap();
             }
+            db.flush_memtable().unwrap();
+            let sequence = db.last_sequence();
+            assert_eq!(sequence, 8);
 
-            //Test gets
-            assert_eq!(db.get(0), Some(10));
-            assert_eq!(db.get(20), Some(200));
-            assert_eq!(db.get(10), Some(100));
-            assert_eq!(db.get(40), Some(400));
-            assert_eq!(db.get(30), Some(300));
-            assert_eq!(db.get(-1), None);
-            assert_eq!(db.get(1), None);
-            assert_eq!(db.get(300), None);
-            assert_eq!(db.get(400), None);
+            //nothing was written since, so no run is read
+            db.reset_io_stats();
+            assert_eq!(db.changes_since(sequence).count(), 0);
+            assert_eq!(db.io_stats().pages_read, 0);
 
-            //test scans
-            assert_eq!(db.scan(1, 19), vec![(10, 100)]);
-            assert_eq!(db.scan(0, 19), vec![(0, 10), (10, 100)]);
-            assert_eq!(db.scan(1, 20), vec![(10, 100), (20, 200)]);
-            assert_eq!(db.scan(0, 20), vec![(0, 10), (10, 100), (20, 200)]);
+            db.put(1, 11).unwrap();
+            db.put(1, 12).unwrap(); //modified twice, only the latest state is returned
+            db.delete(2).unwrap();
+            db.put(20, 200).unwrap();
+            db.flush_memtable().unwrap();
+            db.put(3, 33).unwrap();
+            db.put_with_ttl(4, 44, 0).unwrap(); //already expired
+            db.reset_io_stats();
+            let changes: Vec<_> = db.changes_since(sequence).collect();
             assert_eq!(
-                db.scan(-1, 9999),
-                vec![(0, 10), (10, 100), (20, 200), (30, 300), (40, 400)]
+                changes,
+                vec![
+                    (1, Some(12), 12),
+                    (2, None, 12),
+                    (3, Some(33), 13),
+                    (4, None, 14),
+                    (20, Some(200), 12),
+                ]
             );
+            assert_eq!(db.io_stats().pages_read, 1); //only the run flushed after <sequence>
+            assert_eq!(
+                db.changes_since(12).collect::<Vec<_>>(),
+                vec![(3, Some(33), 13), (4, None, 14)]
+            );
+            assert_eq!(db.changes_since(db.last_sequence()).count(), 0);
 
-            //Test deletes
-            db.delete(30);
-            db.delete(20);
-            assert_eq!(db.get(30), None);
-            assert_eq!(db.get(20), None);
-            assert_eq!(db.get(0), Some(10));
-            assert_eq!(db.get(10), Some(100));
-            assert_eq!(db.get(40), Some(400));
-
-            //Test how deletes affect scan
-            assert_eq!(db.scan(1, 19), vec![(10, 100)]);
-            assert_eq!(db.scan(0, 19), vec![(0, 10), (10, 100)]);
-            assert_eq!(db.scan(1, 20), vec![(10, 100)]);
-            assert_eq!(db.scan(0, 20), vec![(0, 10), (10, 100)]);
-            assert_eq!(db.scan(-1, 9999), vec![(0, 10), (10, 100), (40, 400)]);
-
+            //sequences survive a restart
+            let name = db.name();
+            db.close();
+            let mut db = open_in(db.storage(), &name);
+            assert_eq!(db.last_sequence(), 14);
+            let reopened: Vec<_> = db
+                .changes_since(sequence)
+                .map(|(key, value, _)| (key, value))
+                .collect();
+            let before: Vec<_> = changes
+                .iter()
+                .map(|(key, value, _)| (*key, *value))
+                .collect();
+            assert_eq!(reopened, before);
+            db.put(5, 50).unwrap();
+            assert_eq!(
+                db.changes_since(14).collect::<Vec<_>>(),
+                vec![(5, Some(50), 15)]
+            );
             db
         };
-        setup_and_test_and_cleaup(test_name, &mut alterations, &mut test)
+        setup_and_test_and_cleaup("changes_since_test", &mut alterations, &mut test);
     }
 
-    fn large_db_test(test_name: &str, database_alterations: &mut dyn FnMut(Database) -> Database) {
-        let memtable_cap = 896; //3.5 pages (4096 bytes * 3.5/16)
-
-        let mut alterations = |db: Database| {
-            database_alterations(db).set_memtable_capacity(memtable_cap) //memtable capacity needed for tests below
-        };
+    #[test]
+    fn changes_since_after_compaction_test() {
         let mut test = |mut db: Database| {
-            let (min, max) = (-1000, 1000);
-            let range = min..max + 1; //3.5 pages of
-            let mut entries = Vec::<Entry>::new();
-
-            //Test puts
-            for i in range.into_iter() {
-                let entry = (i, i * 10);
-                db.put(entry.0, entry.1);
-                entries.push(entry);
+            for key in 0..40 {
+                db.put(key, key).unwrap();
             }
+            let sequence = db.last_sequence();
+            let mut replica: BTreeMap<Key, Value> =
+                db.scan(Key::MIN + 1, Key::MAX).into_iter().collect();
 
-            if db.compaction_policy() == CompactionPolicy::None {
-                assert_eq!(db.sst_count(0), entries.len() / memtable_cap); //NOTE: this will be wrong if the previous call to this function panicked
+            //enough flushes that the changes are merged down with runs written before <sequence>
+            for key in (0..40).step_by(3) {
+                db.put(key, -key).unwrap();
             }
-            //Test gets
-            for (key, value) in entries.iter() {
-                assert_eq!(db.get(*key), Some(*value), "key: {key}");
+            for key in 40..80 {
+                db.put(key, key).unwrap();
             }
+            db.delete(1).unwrap();
+            db.delete(2).unwrap();
+            if let Some(levels) = db.background_levels() {
+                //the compactor can fall behind, files in memory are written faster than it merges them
+                db.background_compactor.as_ref().unwrap().drain();
+                drop(db.sync_levels(&levels));
+            }
+            let changes: Vec<_> = db.changes_since(sequence).collect();
+            assert!(db.metadata.entry_counts.len() > 2);
 
-            //Test getting values that are not in the db
-            assert_eq!(db.get(min - 1000), None);
-            assert_eq!(db.get(max + 1000), None);
-
-            //Test scans
-            //scan from [..i+1]
-            for i in 0..entries.len() {
-                let slice = entries[..i + 1].to_vec();
-                let scan = db.scan(entries.first().unwrap().0, entries[i].0);
-                assert_eq!(
-                    scan.len(),
-                    slice.len(),
-                    "scan range ({}, {}), scan results {:?}",
-                    entries.first().unwrap().0,
-                    entries[i].0,
-                    scan
-                );
-                assert_eq!(scan, slice);
+            //each key once, in key order, with its latest state
+            assert!(changes.windows(2).all(|pair| pair[0].0 < pair[1].0));
+            for key in 0..80 {
+                let change = changes.iter().find(|(changed_key, ..)| *changed_key == key);
+                if key % 3 == 0 || key >= 40 || key == 1 || key == 2 {
+                    let (_, value, change_sequence) = change.unwrap();
+                    assert_eq!(*value, db.get(key));
+                    assert!(*change_sequence > sequence);
+                }
             }
 
-            //scan from [i..]
-            for i in 0..entries.len() {
-                let slice = entries[i..].to_vec();
-                let scan = db.scan(entries[i].0, entries.last().unwrap().0);
-                assert_eq!(scan.len(), slice.len());
-                assert_eq!(scan, slice);
+            //a replica as of <sequence> catches up
+            for (key, value, _) in changes {
+                match value {
+                    Some(value) => replica.insert(key, value),
+                    None => replica.remove(&key),
+                };
             }
+            assert_eq!(
+                replica.into_iter().collect::<Vec<_>>(),
+                db.scan(Key::MIN + 1, Key::MAX)
+            );
+            db
+        };
+        for (test_name, background_compaction) in [
+            ("changes_since_after_compaction_test", false),
+            ("changes_since_after_compaction_background_test", true),
+        ] {
+            setup_and_test_and_cleaup(
+                test_name,
+                &mut |db| {
+                    testing::part3_db_alterations(db)
+                        .set_compaction_policy(CompactionPolicy::Leveled)
+                        .set_memtable_capacity(4)
+                        .set_background_compaction(background_compaction)
+                },
+                &mut test,
+            );
+        }
+    }
 
-            //scan from [i..j+1]
-            for i in (0..entries.len()).step_by(57) {
-                for j in (i..entries.len() - 1).step_by(37) {
-                    let slice = entries[i..j + 1].to_vec();
-                    let scan = db.scan(entries[i].0, entries[j].0);
-                    assert_eq!(scan.len(), slice.len());
-                    assert_eq!(scan, slice);
+    #[test]
+    fn merge_operator_test() {
+        //random puts, deletes and merges across flushes and compactions, checked against a model
+        use rand::{Rng, SeedableRng};
+        use SstImplementation::*;
+
+        const NUM_KEYS: Key = 24;
+        for (index, (operator, compaction_policy, sst_implementation, background_compaction)) in [
+            (MergeOperator::Add, CompactionPolicy::None, Array, false),
+            (MergeOperator::Add, CompactionPolicy::Leveled, Btree, false),
+            (MergeOperator::Max, CompactionPolicy::Tiered, Array, false),
+            (
+                MergeOperator::Min,
+                CompactionPolicy::Dostoevsky,
+                Btree,
+                false,
+            ),
+            (MergeOperator::Add, CompactionPolicy::Leveled, Array, true),
+        ]
+        .into_iter()
+        .enumerate()
+        {
+            let mut rng = rand::rngs::StdRng::seed_from_u64(index as u64);
+            let mut alterations = |db: Database| {
+                testing::part2_db_alterations(db)
+                    .set_memtable_capacity(4)
+                    .set_compaction_policy(compaction_policy)
+                    .set_sst_implementation(sst_implementation)
+                    .set_merge_operator(operator)
+                    .set_background_compaction(background_compaction)
+            };
+            let mut test = |mut db: Database| {
+                let mut model: BTreeMap<Key, Value> = BTreeMap::new();
+                let check = |db: &mut Database, model: &BTreeMap<Key, Value>| {
+                    let expected: Vec<Entry> = model.iter().map(|(k, v)| (*k, *v)).collect();
+                    for key in 0..NUM_KEYS {
+                        assert_eq!(
+                            db.get(key),
+                            model.get(&key).copied(),
+                            "{operator:?} key {key}"
+                        );
+                        assert_eq!(
+                            db.get_ceiling(key),
+                            model.range(key..).next().map(|(k, v)| (*k, *v))
+                        );
+                        assert_eq!(
+                            db.get_floor(key),
+                            model.range(..=key).next_back().map(|(k, v)| (*k, *v))
+                        );
+                    }
+                    assert_eq!(db.scan(0, NUM_KEYS), expected);
+                    let options = ScanOptions {
+                        limit: None,
+                        reverse: true,
+                    };
+                    let reversed: Vec<Entry> = expected.iter().rev().copied().collect();
+                    assert_eq!(db.scan_with_options(0, NUM_KEYS, options), reversed);
+                    let changes: Vec<Entry> = db
+                        .changes_since(0)
+                        .filter_map(|(key, value, _)| Some((key, value?)))
+                        .collect();
+                    assert_eq!(changes, expected);
+                };
+                for step in 0..400 {
+                    let key = rng.gen_range(0..NUM_KEYS);
+                    let value = rng.gen_range(-50..50);
+                    match rng.gen_range(0..6) {
+                        0 => {
+                            db.put(key, value).unwrap();
+                            model.insert(key, value);
+                        }
+                        1 => {
+                            db.delete(key).unwrap();
+                            model.remove(&key);
+                        }
+                        _ => {
+                            db.merge(key, value).unwrap();
+                            let old = model.get(&key).copied().unwrap_or(operator.identity());
+                            model.insert(key, operator.apply(old, value));
+                        }
+                    }
+                    if step % 40 == 0 {
+                        check(&mut db, &model);
+                    }
                 }
-            }
+                check(&mut db, &model);
+                let path = db.path().to_path_buf();
+                db.close();
+                let mut db = open_in(db.storage(), path);
+                assert_eq!(db.merge_operator(), operator);
+                check(&mut db, &model);
+                db
+            };
+            setup_and_test_and_cleaup(
+                format!("merge_operator_test_{index}"),
+                &mut alterations,
+                &mut test,
+            );
+        }
+    }
 
-            //Test scans that include keys outside of min and max of db
-            assert_eq!(db.scan(min - 1000, max + 1000), entries);
+    #[test]
+    fn version_merge_test() {
+        //the same puts and deletes under each strategy, checked against a model before and after every run is merged
+        // into one
+        use rand::{Rng, SeedableRng};
 
-            //Test overwriting existing keys
-            let step = 3;
-            for (key, value) in entries.iter().step_by(step) {
-                db.put(*key, value * 2);
-            }
-            for (i, (key, value)) in entries.iter().enumerate() {
-                if i % step == 0 {
-                    assert_eq!(db.get(*key), Some(value * 2));
-                } else {
-                    assert_eq!(db.get(*key), Some(*value));
+        const NUM_KEYS: Key = 24;
+        for version_merge in [
+            VersionMerge::KeepNewest,
+            VersionMerge::KeepOldest,
+            VersionMerge::SumValues,
+        ] {
+            let mut rng = rand::rngs::StdRng::seed_from_u64(7);
+            let mut alterations = |db: Database| {
+                testing::part2_db_alterations(db)
+                    .set_memtable_capacity(4)
+                    .set_compaction_policy(CompactionPolicy::None)
+                    .set_max_level_zero_runs(0)
+                    .set_version_merge(version_merge)
+            };
+            let mut test = |mut db: Database| {
+                let mut model: BTreeMap<Key, Value> = BTreeMap::new();
+                let check = |db: &mut Database, model: &BTreeMap<Key, Value>| {
+                    for key in 0..NUM_KEYS {
+                        assert_eq!(
+                            db.get(key),
+                            model.get(&key).copied(),
+                            "{version_merge:?} key {key}"
+                        );
+                    }
+                    let expected: Vec<Entry> = model.iter().map(|(k, v)| (*k, *v)).collect();
+                    assert_eq!(db.scan(0, NUM_KEYS), expected, "{version_merge:?}");
+                };
+                for step in 0..300 {
+                    let key = rng.gen_range(0..NUM_KEYS);
+                    let value = rng.gen_range(-50..50);
+                    if rng.gen_range(0..6) == 0 {
+                        //nothing under a tombstone is combined with what's put after it
+                        db.delete(key).unwrap();
+                        model.remove(&key);
+                    } else {
+                        db.put(key, value).unwrap();
+                        let old = model.get(&key).copied();
+                        let new = match (version_merge, old) {
+                            (VersionMerge::KeepOldest, Some(old)) => old,
+                            (VersionMerge::SumValues, Some(old)) => {
+                                MergeOperator::Add.apply(old, value)
+                            }
+                            _ => value,
+                        };
+                        model.insert(key, new);
+                    }
+                    if step % 30 == 0 {
+                        check(&mut db, &model);
+                    }
                 }
-            }
+                db.flush().unwrap();
+                assert!(db.sst_count(0) > 1);
+                check(&mut db, &model);
 
-            //Test deletes
-            let delete_step = 3; //NOTE: if this value is different from <step>, you'll need a new vec to hold onto the new db values that the prev test changed
-            for (key, _) in entries.iter().step_by(delete_step) {
-                db.delete(*key);
-                assert_eq!(db.get(*key), None);
-            }
+                db.compact_level_zero().unwrap();
+                assert_eq!(db.sst_count(0), 1);
+                check(&mut db, &model);
 
-            //Test how deletes affect scan
-            let filtered: Vec<Entry> = entries
-                .iter()
-                .enumerate()
-                .filter(|&(i, _)| i % delete_step != 0)
-                .map(|(_, v)| v.to_owned())
-                .collect(); //entries without every <delete_step>th element
-            let slice = &filtered;
-            let scan = db.scan(entries.first().unwrap().0, entries.last().unwrap().0);
-            assert_eq!(scan.len(), slice.len());
-            assert_eq!(scan, slice.to_vec());
+                let path = db.path().to_path_buf();
+                db.close();
+                let mut db = open_in(db.storage(), path);
+                assert_eq!(db.version_merge(), version_merge);
+                check(&mut db, &model);
+                db
+            };
+            setup_and_test_and_cleaup(
+                format!("version_merge_test_{version_merge:?}"),
+                &mut alterations,
+                &mut test,
+            );
+        }
+    }
+
+    #[test]
+    fn sync_policy_test() {
+        //nothing is synced unless asked for, writes cost what they did before sync policies
+        let db = Database::open_in_memory("sync_policy_test_default");
+        assert_eq!(db.sync_policy(), SyncPolicy::Never);
+        assert_eq!(db.storage().sync_policy(), SyncPolicy::Never);
+        drop(db);
+
+        //syncs of a flush, a compaction and a state write under each policy, in-memory files count too
+        for sync_policy in [SyncPolicy::Never, SyncPolicy::OnFlush, SyncPolicy::Always] {
+            let mut alterations = |db: Database| {
+                testing::part1_db_alterations(db)
+                    .set_memtable_capacity(2)
+                    .set_compaction_policy(CompactionPolicy::None)
+                    .set_max_level_zero_runs(0)
+                    .set_sync_policy(sync_policy)
+            };
+            let mut test = |mut db: Database| {
+                for key in 1..=5 {
+                    db.put(key, key * 10).unwrap();
+                }
+                db.reset_io_stats();
+                db.flush().unwrap();
+                let flush_syncs = db.io_stats().syncs;
+                assert!(db.sst_count(0) > 1);
+
+                db.reset_io_stats();
+                db.compact_level_zero().unwrap();
+                let compaction_syncs = db.io_stats().syncs;
+
+                db.reset_io_stats();
+                db.config_dirty = true;
+                db.metadata_dirty = true;
+                db.write_db_state().unwrap();
+                let state_syncs = db.io_stats().syncs;
+
+                let syncs = (flush_syncs, compaction_syncs, state_syncs);
+                match sync_policy {
+                    SyncPolicy::Never => assert_eq!(syncs, (0, 0, 0)),
+                    SyncPolicy::OnFlush => {
+                        assert!(flush_syncs > 0 && compaction_syncs > 0, "{syncs:?}");
+                        assert_eq!(state_syncs, 0);
+                    }
+                    SyncPolicy::Always => {
+                        assert!(flush_syncs > 0 && compaction_syncs > 0, "{syncs:?}");
+                        assert_eq!(state_syncs, 4, "config and metadata, then their renames");
+                    }
+                }
+
+                let path = db.path().to_path_buf();
+                db.close();
+                let db = open_in(db.storage(), path);
+                assert_eq!(db.sync_policy(), sync_policy);
+                assert_eq!(db.storage().sync_policy(), sync_policy);
+                db
+            };
+            setup_and_test_and_cleaup(
+                format!("sync_policy_test_{sync_policy:?}"),
+                &mut alterations,
+                &mut test,
+            );
+        }
+    }
+
+    #[test]
+    fn compression_test() {
+        //a run written before compression is turned on and one after, read and compacted together, with compressible
+        // (sequential keys, constant values) and incompressible (random) entries
+        use rand::{Rng, SeedableRng};
+
+        let num_entries = system_info::num_entries_per_page() * 4;
+        let mut all_alterations: Vec<fn(Database) -> Database> =
+            vec![testing::part1_db_alterations];
+        if cfg!(feature = "btree") {
+            all_alterations.push(testing::part2_db_alterations);
+        }
+        for (alterations_index, db_alterations) in all_alterations.into_iter().enumerate() {
+            for is_compressible in [true, false] {
+                let mut alterations = |db: Database| {
+                    db_alterations(db).set_memtable_capacity(num_entries as Size * 2)
+                };
+                let mut test = |mut db: Database| {
+                    let mut rng = rand::rngs::StdRng::seed_from_u64(0);
+                    let mut entry = |index: usize| -> Entry {
+                        match is_compressible {
+                            true => (index as Key, 7),
+                            false => (rng.gen(), rng.gen()),
+                        }
+                    };
+                    let mut model = BTreeMap::new();
+                    for index in 0..num_entries {
+                        let (key, value) = entry(index);
+                        db.put(key, value).unwrap();
+                        model.insert(key, value);
+                    }
+                    db.flush().unwrap();
+                    db = db.set_compression(Some(Compression::DeltaVarint));
+                    for index in num_entries..num_entries * 2 {
+                        let (key, value) = entry(index);
+                        db.put(key, value).unwrap();
+                        model.insert(key, value);
+                    }
+                    db.flush().unwrap();
+
+                    let runs = db.list_runs(); //youngest first
+                    assert_eq!(runs.len(), 2);
+                    let (compressed, uncompressed) = (&runs[0], &runs[1]);
+                    assert_eq!(compressed.entries, uncompressed.entries);
+                    if is_compressible {
+                        assert!(
+                            compressed.bytes * 4 < uncompressed.bytes,
+                            "{} compressed bytes, {} uncompressed",
+                            compressed.bytes,
+                            uncompressed.bytes
+                        );
+                    }
+
+                    let check = |db: &mut Database| {
+                        for (key, value) in model.iter().step_by(7) {
+                            assert_eq!(db.get(*key), Some(*value), "key {key}");
+                        }
+                        assert_eq!(db.get(Key::MIN + 1), None);
+                        let expected: Vec<Entry> = model.iter().map(|(k, v)| (*k, *v)).collect();
+                        assert_eq!(db.scan(Key::MIN, Key::MAX), expected);
+                        let (low, high) = (expected[100].0, expected[num_entries + 300].0);
+                        let in_range: Vec<Entry> =
+                            model.range(low..=high).map(|(k, v)| (*k, *v)).collect();
+                        assert_eq!(db.scan(low, high), in_range);
+                    };
+                    check(&mut db);
 
-            db
-        };
+                    db.compact_level_zero().unwrap();
+                    let runs = db.list_runs();
+                    assert_eq!(runs.len(), 1);
+                    assert_eq!(runs[0].entries, model.len());
+                    check(&mut db);
+                    assert!(db.health_check(true).is_healthy());
 
-        setup_and_test_and_cleaup(test_name, &mut alterations, &mut test)
+                    let path = db.path().to_path_buf();
+                    db.close();
+                    let mut db = open_in(db.storage(), path);
+                    assert_eq!(db.compression(), Some(Compression::DeltaVarint));
+                    check(&mut db);
+                    db
+                };
+                setup_and_test_and_cleaup(
+                    format!("compression_test_{alterations_index}_{is_compressible}"),
+                    &mut alterations,
+                    &mut test,
+                );
+            }
+        }
     }
 
-    fn multi_page_btree_single_sst_db_test(
-        test_name: &str,
-        database_alterations: &mut dyn FnMut(Database) -> Database,
-    ) {
-        let memtable_cap = fanout().pow(2) + 1; //Btrees will have 3 inner nodes and root
+    #[test]
+    fn storage_settings_per_database_test() {
+        //two databases at the same path in separate storages, the sync policy and compression of one don't leak into
+        // the other's flushes
+        let test_dir = testing::test_dir("storage_settings_per_database_test");
+        let db_name = test_dir.join("db");
+        let num_entries = system_info::num_entries_per_page() * 2;
+        let mut tuned = Database::open_in_memory(&db_name)
+            .set_memtable_capacity(num_entries as Size * 2)
+            .set_sync_policy(SyncPolicy::Never)
+            .set_compression(Some(Compression::DeltaVarint));
+        let mut plain = Database::open_in_memory(&db_name)
+            .set_memtable_capacity(num_entries as Size * 2)
+            .set_sync_policy(SyncPolicy::OnFlush);
+        let mut flush_syncs = Vec::new();
+        for db in [&mut tuned, &mut plain] {
+            for key in 0..num_entries {
+                db.put(key as Key, 7).unwrap();
+            }
+            db.reset_io_stats();
+            db.flush().unwrap();
+            flush_syncs.push(db.io_stats().syncs); //both count on this thread, read before the other flushes
+        }
+        assert_eq!(flush_syncs[0], 0);
+        assert!(flush_syncs[1] > 0);
+        assert!(tuned.list_runs()[0].bytes < num_entries * ENTRY_SIZE);
+        assert_eq!(plain.list_runs()[0].bytes, num_entries * ENTRY_SIZE);
+        assert_eq!(
+            tuned.storage().compression(),
+            Some(Compression::DeltaVarint)
+        );
+        assert_eq!(plain.storage().compression(), None);
+        tuned.close();
+        plain.close();
+    }
 
-        let mut alterations = |db: Database| {
-            database_alterations(db).set_memtable_capacity(memtable_cap) //memtable capacity needed for tests below
-        };
-        let mut test = |mut db: Database| {
-            let (min, max) = (0, memtable_cap as i64);
-            let range = min..max + 1; //1 extra to force a flush to sst
-            let mut entries = Vec::<Entry>::new();
+    #[test]
+    fn frozen_memtable_test() {
+        //writes, gets and scans interleaved with flushes in two steps (freeze_memtable, then flush_frozen), checked
+        // against a model so an entry lost or read twice while frozen shows up
+        use rand::{Rng, SeedableRng};
 
-            //Test puts
-            for i in range.into_iter() {
-                let entry = (i, i * 10);
-                db.put(entry.0, entry.1);
-                entries.push(entry);
-            }
+        const NUM_KEYS: Key = 32;
+        for (index, compaction_policy) in [CompactionPolicy::None, CompactionPolicy::Leveled]
+            .into_iter()
+            .enumerate()
+        {
+            let mut rng = rand::rngs::StdRng::seed_from_u64(index as u64);
+            let mut alterations = |db: Database| {
+                db.set_memtable_capacity(8)
+                    .set_compaction_policy(compaction_policy)
+                    .set_max_level_zero_runs(64)
+                    .set_merge_operator(MergeOperator::Add)
+            };
+            let mut test = |mut db: Database| {
+                let mut model: BTreeMap<Key, Value> = BTreeMap::new();
+                for step in 0..600 {
+                    let key = rng.gen_range(0..NUM_KEYS);
+                    let value = rng.gen_range(-50..50);
+                    match rng.gen_range(0..20) {
+                        0..=5 => {
+                            db.put(key, value).unwrap();
+                            model.insert(key, value);
+                        }
+                        6..=8 => {
+                            db.delete(key).unwrap();
+                            model.remove(&key);
+                        }
+                        9..=11 => {
+                            db.merge(key, value).unwrap();
+                            *model.entry(key).or_insert(0) += value;
+                        }
+                        12..=14 => assert_eq!(db.get(key), model.get(&key).copied(), "step {step}"),
+                        15..=17 => db.freeze_memtable().unwrap(),
+                        _ => db.flush_frozen().unwrap(),
+                    }
+                    let expected: Vec<Entry> = model.iter().map(|(k, v)| (*k, *v)).collect();
+                    assert_eq!(db.scan(0, NUM_KEYS), expected, "step {step}");
+                }
 
-            assert_eq!(db.sst_count(0), 1); //confirm that we have a single sst
+                //frozen entries are read after the memtable's and before the runs'
+                db.put(0, 1).unwrap();
+                db.flush().unwrap();
+                db.put(0, 2).unwrap();
+                db.put(1, 2).unwrap();
+                db.merge(NUM_KEYS, 1).unwrap();
+                db.freeze_memtable().unwrap();
+                assert_eq!(db.memtable.len(), 0);
+                db.put(1, 3).unwrap();
+                db.merge(0, 10).unwrap();
+                db.merge(NUM_KEYS, 1).unwrap();
+                assert_eq!(db.get(0), Some(12));
+                assert_eq!(db.scan(0, 1), vec![(0, 12), (1, 3)]);
+                db.flush_frozen().unwrap();
+                assert!(db.memtable.frozen_to_flush().is_none());
+                assert_eq!(db.memtable.len(), 3);
+                assert_eq!(db.scan(0, 1), vec![(0, 12), (1, 3)]);
+                //an operand written over a frozen one was combined with it, the flushed one isn't applied again
+                assert_eq!(db.get(NUM_KEYS), Some(2));
 
-            //Test getting values that are not in the db
-            assert_eq!(db.get(min - 1000), None);
-            assert_eq!(db.get(max + 1000), None);
+                //closing flushes the frozen entries too
+                db.put(2, 4).unwrap();
+                db.freeze_memtable().unwrap();
+                let path = db.path().to_path_buf();
+                db.close();
+                let mut db = open_in(db.storage(), path);
+                assert_eq!(db.scan(0, 2), vec![(0, 12), (1, 3), (2, 4)]);
+                db
+            };
+            setup_and_test_and_cleaup(
+                format!("frozen_memtable_test_{index}"),
+                &mut alterations,
+                &mut test,
+            );
+        }
+    }
 
-            //Test gets
-            for (key, value) in entries.iter() {
-                assert_eq!(db.get(*key), Some(*value), "key: {key}");
+    #[test]
+    fn compare_and_put_test() {
+        let mut alterations =
+            |db: Database| testing::part1_db_alterations(db).set_memtable_capacity(2);
+        let mut test = |mut db: Database| {
+            use CasResult::{Mismatch, Swapped};
+            //expected absent: absent swaps, present reports the value it holds
+            assert_eq!(db.compare_and_put(1, None, 10).unwrap(), Swapped);
+            assert_eq!(db.get(1), Some(10));
+            assert_eq!(
+                db.compare_and_put(1, None, 11).unwrap(),
+                Mismatch { actual: Some(10) }
+            );
+            assert_eq!(db.get(1), Some(10));
+            //expected present: a match swaps, another value or an absent key is reported
+            assert_eq!(db.compare_and_put(1, Some(10), 12).unwrap(), Swapped);
+            assert_eq!(
+                db.compare_and_put(1, Some(10), 13).unwrap(),
+                Mismatch { actual: Some(12) }
+            );
+            assert_eq!(
+                db.compare_and_put(2, Some(10), 20).unwrap(),
+                Mismatch { actual: None }
+            );
+            assert_eq!(db.get(2), None);
+
+            //values and tombstones in runs rather than the memtable
+            db.delete(1).unwrap();
+            for key in 3..8 {
+                db.put(key, key * 10).unwrap();
             }
+            db.flush_memtable().unwrap();
+            assert!(db.memtable.len() == 0 && db.sst_total() > 0);
+            assert_eq!(
+                db.compare_and_put(1, Some(12), 14).unwrap(),
+                Mismatch { actual: None }
+            );
+            assert_eq!(db.compare_and_put(1, None, 14).unwrap(), Swapped); //deleted counts as absent
+            assert_eq!(
+                db.compare_and_put(3, Some(31), 0).unwrap(),
+                Mismatch { actual: Some(30) }
+            );
+            assert_eq!(db.compare_and_put(3, Some(30), 0).unwrap(), Swapped);
+            assert_eq!((db.get(1), db.get(3)), (Some(14), Some(0)));
 
-            //check that scan of db returns all entries
-            let scan_range = (entries.first().unwrap().0, entries.last().unwrap().0);
-            let scan = db.scan(scan_range.0, scan_range.1);
+            //expired entries count as absent too
+            db.put_with_ttl(8, 80, 0).unwrap();
             assert_eq!(
-                scan.len(),
-                entries.len(),
-                "scan range ({}, {})",
-                scan_range.0,
-                scan_range.1
+                db.compare_and_put(8, Some(80), 81).unwrap(),
+                Mismatch { actual: None }
             );
-            assert_eq!(scan, entries);
+            assert_eq!(db.compare_and_put(8, None, 81).unwrap(), Swapped);
+            assert_eq!(db.get(8), Some(81));
 
-            //Test scans that include keys outside of min and max of db
-            assert_eq!(db.scan(min - 1000, max + 1000), entries);
+            //reserved keys and values are rejected without reading or writing
+            let tombstone = Database::TOMBSTONE_VALUE;
+            assert!(matches!(
+                db.compare_and_put(Database::INVALID_KEY, None, 1),
+                Err(DbError::ReservedKey)
+            ));
+            assert!(matches!(
+                db.compare_and_put(4, Some(40), tombstone),
+                Err(DbError::ReservedValue)
+            ));
+            assert!(matches!(
+                db.compare_and_put(4, Some(tombstone), 1),
+                Err(DbError::ReservedValue)
+            ));
+            assert_eq!(db.get(4), Some(40));
 
-            //Test overwriting existing keys
-            let step = 3;
-            for (key, value) in entries.iter().step_by(step) {
-                db.put(*key, value * 2);
+            //a key that can't be read fails the swap (and a KeepOldest put) without writing anything
+            let storage = db.storage().clone();
+            let ssts: Vec<(PathBuf, Vec<u8>)> = (db.metadata.entry_counts.iter().enumerate())
+                .flat_map(|(level, runs)| (0..runs.len()).map(move |run| (level, run)))
+                .map(|(level, run)| {
+                    let path = (&db.name, level, run).path_sst();
+                    let bytes = storage.read_file(&path).unwrap();
+                    storage.remove_file(&path).unwrap();
+                    (path, bytes)
+                })
+                .collect();
+            db.buffer_pool.borrow_mut().remove_directory(&db.name);
+            assert!(matches!(
+                db.compare_and_put(5, Some(50), 51),
+                Err(DbError::Io(_))
+            ));
+            db = db.set_version_merge(VersionMerge::KeepOldest);
+            assert!(matches!(db.put(6, 61), Err(DbError::Io(_))));
+            assert!(matches!(db.put_with_ttl(6, 61, 10), Err(DbError::Io(_))));
+            for (path, bytes) in ssts {
+                storage.write_file(&path, &bytes).unwrap();
             }
-            for (i, (key, value)) in entries.iter().enumerate() {
-                if i % step == 0 {
-                    assert_eq!(db.get(*key), Some(value * 2));
-                } else {
-                    assert_eq!(db.get(*key), Some(*value));
-                }
+            assert_eq!((db.get(5), db.get(6)), (Some(50), Some(60)));
+            db
+        };
+        setup_and_test_and_cleaup("compare_and_put_test", &mut alterations, &mut test);
+    }
+
+    #[test]
+    fn put_sorted_batch_test() {
+        let mut alterations = |db: Database| {
+            testing::part1_db_alterations(db)
+                .set_memtable_capacity(10)
+                .set_read_cache_capacity(8)
+        };
+        let mut test = |mut db: Database| {
+            let mut expected = BTreeMap::new();
+            let batch = |keys: std::ops::Range<Key>, value: Value| -> Vec<Entry> {
+                keys.map(|key| (key * 3, value + key)).collect()
+            };
+            //batches fill the memtable and are flushed part way through, puts and deletes in between
+            for round in 0..6 {
+                let entries = batch(round * 4..round * 4 + 13, round * 100);
+                db.put_sorted_batch(&entries).unwrap();
+                expected.extend(entries.iter().copied());
+                let key = round * 12;
+                assert_eq!(db.get(key), expected.get(&key).copied()); //cached, then overwritten
+                db.put(key, -1).unwrap();
+                db.delete(key + 3).unwrap();
+                expected.insert(key, -1);
+                expected.remove(&(key + 3));
+            }
+            assert!(db.sst_total() > 5);
+            assert!(db.memtable.len() <= db.memtable_capacity());
+            let entries: Vec<Entry> = expected.into_iter().collect();
+            assert_eq!(db.scan(Key::MIN + 1, Key::MAX), entries);
+            for (key, value) in entries {
+                assert_eq!(db.get(key), Some(value));
             }
+            let last_sequence = db.last_sequence();
+            assert_eq!(db.changes_since(last_sequence - 1).count(), 1);
 
-            //Test deletes
-            let delete_step = step; //NOTE: if this value is different from <step>, you'll need a new vec to hold onto the new db values that the prev test changed
-            for (key, _) in entries.iter().step_by(delete_step) {
-                db.delete(*key);
-                assert_eq!(db.get(*key), None);
+            //nothing is written from a batch that's rejected
+            let rejected = [(vec![(1, 1), (3, 3), (3, 4)], 2), (vec![(5, 1), (4, 1)], 1)];
+            for (entries, index) in rejected {
+                assert!(matches!(
+                    db.put_sorted_batch(&entries),
+                    Err(DbError::UnsortedBatch { index: found }) if found == index
+                ));
             }
+            assert!(matches!(
+                db.put_sorted_batch(&[(1, 1), (2, Database::TOMBSTONE_VALUE)]),
+                Err(DbError::ReservedValue)
+            ));
+            assert!(matches!(
+                db.put_sorted_batch(&[(Database::INVALID_KEY, 1), (2, 2)]),
+                Err(DbError::ReservedKey)
+            ));
+            assert_eq!(db.get(1), None);
+            assert_eq!(db.last_sequence(), last_sequence);
+            db.put_sorted_batch(&[]).unwrap();
+            db
+        };
+        setup_and_test_and_cleaup("put_sorted_batch_test", &mut alterations, &mut test);
+    }
 
-            //Test how deletes affect scan
-            let filtered: Vec<Entry> = entries
-                .iter()
-                .enumerate()
-                .filter(|&(i, _)| i % delete_step != 0)
-                .map(|(_, v)| v.to_owned())
-                .collect(); //entries without every <delete_step>th element
-            let slice = &filtered;
-            let scan = db.scan(entries.first().unwrap().0, entries.last().unwrap().0);
-            assert_eq!(scan.len(), slice.len());
-            assert_eq!(scan, slice.to_vec());
+    #[test]
+    fn merge_deleted_key_test() {
+        let mut test = |mut db: Database| {
+            //deleted in the memtable
+            db.put(1, 10).unwrap();
+            db.delete(1).unwrap();
+            db.merge(1, 5).unwrap();
+            assert_eq!(db.get(1), Some(5));
+
+            //deleted in a run, the operand is flushed to a younger run
+            db.put(2, 10).unwrap();
+            db.delete(2).unwrap();
+            db.flush_memtable().unwrap();
+            db.merge(2, 7).unwrap();
+            db.flush_memtable().unwrap();
+            assert_eq!(db.get(2), Some(7));
+            assert_eq!(db.scan(0, 10), vec![(1, 5), (2, 7)]);
 
+            //the operand is combined with the live value under it
+            db.put(3, 1).unwrap();
+            db.flush_memtable().unwrap();
+            db.merge(3, 2).unwrap();
+            assert_eq!(db.get(3), Some(3));
+            db.flush_memtable().unwrap();
+            assert_eq!(db.get(3), Some(3));
             db
         };
-
-        setup_and_test_and_cleaup(test_name, &mut alterations, &mut test)
+        setup_and_test_and_cleaup(
+            "merge_deleted_key_test",
+            &mut |db| db.set_compaction_policy(CompactionPolicy::None),
+            &mut test,
+        );
     }
 
     #[test]
-    fn part_1_test_small() {
-        small_db_test("part1_small_db_test", &mut testing::part1_db_alterations);
+    fn merge_chain_compacts_test() {
+        let mut test = |mut db: Database| {
+            db.put(0, 100).unwrap();
+            for _ in 0..10 {
+                db.merge(0, 1).unwrap(); //every merge flushes the one before it
+            }
+            db.flush_memtable().unwrap();
+            assert_eq!(db.get(0), Some(110));
+
+            //the chain is collapsed into the base value by the merges of level 1, only the last operand is left
+            // in level 0
+            assert_eq!(db.metadata.entry_counts, vec![vec![1], vec![1]]);
+            assert_eq!(db.metadata.operand_counts, vec![vec![1], vec![0]]);
+            assert_eq!(db.scan(0, 10), vec![(0, 110)]);
+            db
+        };
+        setup_and_test_and_cleaup(
+            "merge_chain_compacts_test",
+            &mut |db| {
+                db.set_memtable_capacity(1)
+                    .set_compaction_policy(CompactionPolicy::Leveled)
+                    .set_sst_size_ratio(2)
+            },
+            &mut test,
+        );
     }
 
     #[test]
-    fn part1_test_large() {
-        large_db_test("part1_large_db_test", &mut testing::part1_db_alterations);
+    fn level_zero_run_limit_auto_test() {
+        let memtable_capacity = 4;
+        let mut test = |mut db: Database| {
+            for key in 0..10 * memtable_capacity as Key {
+                db.put(key, key * 10).unwrap();
+                if key % 3 == 0 {
+                    db.delete(key).unwrap();
+                }
+                assert!(db.sst_count(0) <= 4);
+            }
+            db.flush_memtable().unwrap();
+            assert!(db.sst_count(0) <= 4);
+            assert_eq!(db.level_shape.num_levels(), 1);
+            for key in 0..10 * memtable_capacity as Key {
+                let expected = (key % 3 != 0).then_some(key * 10);
+                assert_eq!(db.get(key), expected, "key {key}");
+            }
+            //level 0 is the only level, so merging it dropped tombstones
+            assert!(db.metadata.compaction_entries_dropped > 0);
+            assert!(db.compaction_error().is_none());
+            db
+        };
+        setup_and_test_and_cleaup(
+            "level_zero_run_limit_auto_test",
+            &mut |db| {
+                db.set_memtable_capacity(memtable_capacity)
+                    .set_compaction_policy(CompactionPolicy::None)
+                    .set_max_level_zero_runs(4)
+                    .set_level_zero_overflow(LevelZeroOverflowBehavior::Auto)
+            },
+            &mut test,
+        );
     }
 
     #[test]
-    #[ignore = "Test case redundant with large test"]
-    fn part1_test_multi_page_btree_single_sst() {
-        multi_page_btree_single_sst_db_test(
-            "part1_multi_page_btree_single_sst_db_test",
-            &mut testing::part1_db_alterations,
+    fn max_garbage_ratio_test() {
+        let num_keys: Key = 1000;
+        let memtable_capacity = num_keys as Size / 2;
+        let max_garbage_ratio = 0.5;
+        let mut test = |mut db: Database| {
+            //the same keys written 50 times, each run holds half of them
+            for round in 0..50 {
+                for key in 0..num_keys {
+                    db.put(key, key * 100 + round).unwrap();
+                }
+                assert!(db.garbage_ratio(0) <= max_garbage_ratio);
+                assert!(db.sst_count(0) <= 5, "{} runs", db.sst_count(0));
+            }
+            assert!(db.metadata.compaction_entries_dropped > 0);
+            assert!(db.compaction_error().is_none());
+
+            //what's left is one merged run of every key and the few runs flushed since
+            let run_bytes =
+                (num_keys as Size * ENTRY_SIZE).next_multiple_of(system_info::page_size());
+            let bytes: Size = db.list_runs().iter().map(|run| run.bytes).sum();
+            assert!(bytes <= 3 * run_bytes, "{bytes} bytes of runs");
+            for key in 0..num_keys {
+                assert_eq!(db.get(key), Some(key * 100 + 49), "key {key}");
+            }
+
+            //a merged run is exact, only its tombstones are garbage
+            db.flush_memtable().unwrap();
+            db.compact_level_zero().unwrap();
+            assert_eq!(db.garbage_ratio(0), 0.0);
+            for key in 0..num_keys / 4 {
+                db.delete(key).unwrap();
+            }
+            db.flush_memtable().unwrap();
+            let ratio = db.garbage_ratio(0);
+            assert!((0.3..0.5).contains(&ratio), "garbage ratio {ratio}");
+
+            let why = db.try_set_max_garbage_ratio(Some(1.0)).unwrap_err();
+            assert!(matches!(why, DbError::InvalidConfig { .. }), "{why}");
+            assert_eq!(db.max_garbage_ratio(), Some(max_garbage_ratio));
+            db
+        };
+        setup_and_test_and_cleaup_on_disk(
+            "max_garbage_ratio_test",
+            &mut |db| {
+                testing::part1_db_alterations(db)
+                    .set_memtable_capacity(memtable_capacity)
+                    .set_compaction_policy(CompactionPolicy::None)
+                    .set_max_level_zero_runs(0)
+                    .set_max_garbage_ratio(Some(max_garbage_ratio))
+            },
+            &mut test,
         );
     }
 
     #[test]
-    fn part_2_test_small() {
-        small_db_test("part2_small_db_test", &mut testing::part2_db_alterations);
+    fn level_zero_run_limit_backpressure_test() {
+        let memtable_capacity = 4;
+        let mut test = |mut db: Database| {
+            //4 runs fill level 0, the 5th memtable fills up but can't be flushed
+            let num_keys = 5 * memtable_capacity as Key;
+            for key in 0..num_keys {
+                db.put(key, key).unwrap();
+            }
+            assert_eq!(db.sst_count(0), 4);
+            let why = db.put(num_keys, num_keys).unwrap_err();
+            assert!(matches!(why, DbError::TooManyRuns { max_runs: 4 }), "{why}");
+            assert!(db.delete(0).is_err());
+            assert!(db.merge(0, 1).is_err());
+            assert_eq!(db.sst_count(0), 4);
+            assert_eq!(db.memtable.len(), memtable_capacity);
+            assert_eq!(db.get(num_keys), None);
+            assert_eq!(db.scan(0, num_keys).len(), num_keys as Size);
+
+            //once level 0 is compacted the memtable is flushed by the next write
+            db.compact_level_zero().unwrap();
+            assert_eq!(db.sst_count(0), 1);
+            db.put(num_keys, num_keys).unwrap();
+            assert_eq!(db.sst_count(0), 2);
+            for key in 0..=num_keys {
+                assert_eq!(db.get(key), Some(key));
+            }
+
+            //closing a database with a full level 0 still writes out the (full) memtable
+            let last_key = num_keys + 3 * memtable_capacity as Key - 1;
+            for key in num_keys + 1..=last_key {
+                db.put(key, key).unwrap();
+            }
+            assert_eq!(db.sst_count(0), 4);
+            let name = db.path().to_path_buf();
+            db.close();
+            let mut db = open_in(db.storage(), &name);
+            assert_eq!(
+                db.level_zero_overflow(),
+                LevelZeroOverflowBehavior::Backpressure
+            );
+            assert_eq!(db.sst_count(0), 2);
+            for key in 0..=last_key {
+                assert_eq!(db.get(key), Some(key));
+            }
+            db
+        };
+        setup_and_test_and_cleaup(
+            "level_zero_run_limit_backpressure_test",
+            &mut |db| {
+                db.set_memtable_capacity(memtable_capacity)
+                    .set_compaction_policy(CompactionPolicy::None)
+                    .set_max_level_zero_runs(4)
+                    .set_level_zero_overflow(LevelZeroOverflowBehavior::Backpressure)
+            },
+            &mut test,
+        );
     }
 
     #[test]
-    fn part2_test_large() {
-        large_db_test("part2_large_db_test", &mut testing::part2_db_alterations);
+    fn run_limits_test() {
+        let max_runs = Database::MAX_RUNS_PER_LEVEL;
+        let db_name = testing::test_dir("run_limits_test");
+        let db = Database::open_in_memory(db_name).set_max_level_zero_runs(0);
+        assert_eq!(db.level_zero_run_limit(), max_runs);
+        let db = db
+            .set_max_level_zero_runs(max_runs)
+            .set_sst_size_ratio(max_runs);
+        assert_eq!(db.level_zero_run_limit(), max_runs);
+        let too_many_runs = std::panic::catch_unwind(|| {
+            Database::open_in_memory(db_name).set_max_level_zero_runs(max_runs + 1)
+        });
+        assert!(too_many_runs.is_err());
+        let too_large_ratio = std::panic::catch_unwind(|| {
+            Database::open_in_memory(db_name).set_sst_size_ratio(max_runs + 1)
+        });
+        assert!(too_large_ratio.is_err());
+        drop(db);
+
+        //a level can't be moved into one that would go over the limit, or past the end of the run numbers
+        assert_eq!(plan::runs_after_move(2, max_runs - 2), Some(max_runs));
+        assert_eq!(plan::runs_after_move(2, max_runs - 1), None);
+        assert_eq!(plan::runs_after_move(2, Size::MAX), None);
+
+        //files claiming huge levels are rejected when opened
+        let metadata = Metadata {
+            entry_counts: vec![vec![1; 2], vec![1; max_runs]],
+            ..Metadata::new()
+        };
+        assert!(Metadata::deserialize(&metadata.serialize()).is_ok());
+        let metadata = Metadata {
+            entry_counts: vec![vec![1; 2], vec![1; max_runs + 1]],
+            ..Metadata::new()
+        };
+        let why = Metadata::deserialize(&metadata.serialize()).unwrap_err();
+        assert!(
+            matches!(
+                why,
+                DbError::Corrupt {
+                    file: "metadata",
+                    ..
+                }
+            ),
+            "{why}"
+        );
+        let config = Config {
+            max_level_zero_runs: max_runs + 1,
+            ..Config::new()
+        };
+        let why = Config::deserialize(&config.serialize()).unwrap_err();
+        assert!(
+            matches!(why, DbError::Corrupt { file: "config", .. }),
+            "{why}"
+        );
     }
 
     #[test]
-    fn part2_test_multi_page_btree_single_sst() {
-        multi_page_btree_single_sst_db_test(
-            "part2_multi_page_btree_single_sst_db_test",
-            &mut testing::part2_db_alterations,
+    fn list_runs_test() {
+        let memtable_capacity = 3 * system_info::num_entries_per_page() + 5; //runs end with a partial page
+        let mut alterations = |db: Database| {
+            testing::part3_db_alterations(db)
+                .set_memtable_capacity(memtable_capacity)
+                .set_bloom_levels(BloomLevels::UpTo(1))
+        };
+        let mut test = |mut db: Database| {
+            assert!(db.list_runs().is_empty());
+            for key in 0..(3 * memtable_capacity) as Key {
+                db.put(key % 1000, key).unwrap();
+            }
+            db.delete(7).unwrap();
+            db.flush().unwrap();
+
+            let runs = db.list_runs();
+            assert_eq!(runs.len(), db.sst_total());
+            let key_codec = db.key_codec();
+            for (index, info) in runs.iter().enumerate() {
+                //youngest first
+                if let Some(next) = runs.get(index + 1) {
+                    assert!((info.level, Reverse(info.run)) < (next.level, Reverse(next.run)));
+                }
+                assert_eq!(info.entries, db.metadata.entry_counts[info.level][info.run]);
+                assert_eq!(info.bytes, info.entries * ENTRY_SIZE);
+                assert!(info.has_sst);
+                assert_eq!(info.has_bloom, db.enable_bloom_filter() && info.level < 1);
+                let run_address = (&db.name, info.level, info.run);
+                assert_eq!(
+                    info.has_btree,
+                    matches!(db.sst_implementation(), SstImplementation::Btree)
+                        && info.entries > system_info::num_entries_per_page()
+                );
+                let entries = export::export_run(&run_address, key_codec).unwrap();
+                assert_eq!(entries, db.sst_interface().read(&run_address).unwrap());
+            }
+            assert_eq!(
+                runs.iter().any(|info| info.has_btree),
+                cfg!(feature = "btree")
+            );
+            assert!(runs.iter().any(|info| !info.has_bloom));
+
+            //a run whose SST is gone is flagged, not left out
+            let last = *runs.last().unwrap();
+            let run_address = (&db.name, last.level, last.run);
+            db.name
+                .storage()
+                .remove_file(&run_address.path_sst())
+                .unwrap();
+            let runs = db.list_runs();
+            assert_eq!(runs.len(), db.sst_total());
+            assert_eq!(
+                *runs.last().unwrap(),
+                RunInfo {
+                    bytes: 0,
+                    has_sst: false,
+                    ..last
+                }
+            );
+            assert!(runs[..runs.len() - 1].iter().all(|info| info.has_sst));
+            db
+        };
+        setup_and_test_and_cleaup("list_runs_test", &mut alterations, &mut test);
+
+        //the key codec can be read without opening the database
+        let db_name = testing::test_dir("list_runs_key_codec_test");
+        let mut db = Database::open_in_memory(db_name).set_key_codec(KeyCodec::OrderPreserving);
+        db.close();
+        assert_eq!(
+            Database::read_key_codec(&DatabaseName::new(db_name, db.storage().clone())).unwrap(),
+            KeyCodec::OrderPreserving
         );
+        //stored_key_codec reads from disk
+        assert!(matches!(
+            Database::stored_key_codec(db_name),
+            Err(DbError::Io(_))
+        ));
     }
 
     #[test]
-    fn part_3_test_small() {
-        small_db_test("part3_small_db_test", &mut testing::part3_db_alterations);
+    fn approximate_size_bytes_test() {
+        let memtable_capacity = 3 * system_info::num_entries_per_page() + 5;
+        let mut alterations = |db: Database| {
+            testing::part3_db_alterations(db)
+                .set_memtable_capacity(memtable_capacity)
+                .set_bloom_levels(BloomLevels::UpTo(1))
+        };
+        let mut test = |mut db: Database| {
+            let usage = db.approximate_size_bytes();
+            assert_eq!(usage.logical_bytes(), 0);
+            assert_eq!(usage.space_amplification(), 0.0);
+            for key in 0..(3 * memtable_capacity) as Key {
+                db.put(key % 1000, key).unwrap();
+            }
+            db.flush().unwrap();
+            db.write_db_state().unwrap();
+
+            #[cfg(feature = "bloom")]
+            let bloom_len = crate::filter::bloom_util::bitmap_len;
+            #[cfg(not(feature = "bloom"))]
+            let bloom_len = |_, _| 0; //no filters are written
+
+            let usage = db.approximate_size_bytes();
+            let runs = db.list_runs();
+            assert_eq!(usage.levels.len(), db.metadata.entry_counts.len());
+            for (level, level_usage) in usage.levels.iter().enumerate() {
+                let level_runs = runs.iter().filter(|info| info.level == level);
+                let sst_bytes: Size = level_runs.clone().map(|info| info.bytes).sum();
+                assert_eq!(level_usage.sst_bytes, sst_bytes);
+                assert_eq!(sst_bytes, level_usage.entries * ENTRY_SIZE);
+                assert_eq!(
+                    level_usage.entries,
+                    level_runs.clone().map(|info| info.entries).sum::<Size>()
+                );
+                assert_eq!(
+                    level_usage.btree_bytes > 0,
+                    level_runs.clone().any(|info| info.has_btree)
+                );
+                let bloom_bytes: Size = match level < 1 {
+                    true => level_runs
+                        .clone()
+                        .map(|info| bloom_len(info.entries, db.bloom_filter_bits_per_entry()))
+                        .sum(),
+                    false => 0,
+                };
+                assert_eq!(level_usage.bloom_bytes, bloom_bytes);
+                assert_eq!(level_usage.sidecar_bytes, 0); //no expiries, fences or merge operands
+            }
+            let state_bytes = [filename::config(&db.name), filename::metadata(&db.name)]
+                .iter()
+                .map(|path| db.storage().file_len(path).unwrap() as Size)
+                .sum::<Size>();
+            assert!(usage.overhead_bytes >= state_bytes && state_bytes > 0);
+            assert_eq!(
+                usage.total_bytes,
+                usage
+                    .levels
+                    .iter()
+                    .map(|level| level.total_bytes())
+                    .sum::<Size>()
+                    + usage.overhead_bytes
+            );
+            assert_eq!(
+                usage.logical_bytes(),
+                runs.iter().map(|info| info.entries).sum::<Size>() * ENTRY_SIZE
+            );
+            assert!(usage.space_amplification() > 1.0);
+            assert_eq!(usage.unaccounted_bytes, 0);
+
+            //files that aren't the database's are counted apart, nested ones too
+            let stray_directory = db.name.join("stray");
+            db.storage().create_dir_all(&stray_directory).unwrap();
+            db.storage()
+                .write_file(&db.name.join("0").join("leftover"), &[0; 100])
+                .unwrap();
+            db.storage()
+                .write_file(&stray_directory.join("file"), &[0; 20])
+                .unwrap();
+            let with_strays = db.approximate_size_bytes();
+            assert_eq!(with_strays.unaccounted_bytes, 120);
+            assert_eq!(with_strays.total_bytes, usage.total_bytes);
+            db.storage()
+                .remove_file(&db.name.join("0").join("leftover"))
+                .unwrap();
+            db.storage().remove_dir_all(&stray_directory).unwrap();
+            assert_eq!(db.approximate_size_bytes(), usage);
+            db
+        };
+        setup_and_test_and_cleaup("approximate_size_bytes_test", &mut alterations, &mut test);
     }
 
     #[test]
-    fn part3_test_large() {
-        large_db_test("part3_large_db_test", &mut testing::part3_db_alterations);
+    fn stats_test() {
+        let mut alterations = testing::part1_db_alterations;
+        let mut test = |mut db: Database| {
+            for key in 0..(3 * db.memtable_capacity()) as Key {
+                db.put(key, key).unwrap();
+            }
+            db.get(1);
+            let stats = db.stats();
+            assert_eq!(stats.io, db.io_stats());
+            assert_eq!(stats.buffer_pool, db.buffer_pool_stats());
+            assert_eq!(stats.disk, db.approximate_size_bytes());
+            assert!(stats.io.pages_written > 0);
+
+            let table = stats::parse_csv(&stats::to_csv(std::slice::from_ref(&stats))).unwrap();
+            assert_eq!(table.schema_version, DbStats::SCHEMA_VERSION);
+            let pages_written = table.rows[0][table.column("io_pages_written").unwrap()].clone();
+            assert_eq!(pages_written, stats.io.pages_written.to_string());
+            assert!(
+                stats::to_json(&stats).starts_with("{\"schema_version\": 1, \"io_pages_read\": ")
+            );
+            db
+        };
+        setup_and_test_and_cleaup("stats_test", &mut alterations, &mut test);
     }
 
     #[test]
-    #[ignore = "Test case redundant with large test"]
-    fn part3_test_multi_page_btree_single_sst() {
-        multi_page_btree_single_sst_db_test(
-            "part3_multi_page_btree_single_sst_db_test",
-            &mut testing::part3_db_alterations,
+    fn memtable_tombstones_test() {
+        let memtable_capacity = 4;
+        let mut test = |mut db: Database| {
+            //deletes of keys that were never written leave no run behind
+            for key in 0..memtable_capacity as Key {
+                db.delete(key).unwrap();
+            }
+            db.flush_memtable().unwrap();
+            assert_eq!(db.sst_total(), 0);
+            assert_eq!(db.memtable.len(), 0);
+
+            //overwrite, delete and overwrite again, with the versions spread over the memtable and runs
+            for key in 0..8 {
+                db.put(key, key).unwrap();
+            }
+            for key in (0..8).step_by(2) {
+                db.delete(key).unwrap();
+            }
+            for key in (0..8).step_by(4) {
+                db.put(key, key * 100).unwrap();
+            }
+            let expected: Vec<Entry> = (0..8)
+                .filter_map(|key| match key {
+                    _ if key % 4 == 0 => Some((key, key * 100)),
+                    _ if key % 2 == 0 => None,
+                    _ => Some((key, key)),
+                })
+                .collect();
+            for _ in 0..2 {
+                for key in 0..8 {
+                    let value = expected.iter().find(|(k, _)| *k == key).map(|(_, v)| *v);
+                    assert_eq!(db.get(key), value, "key {key}");
+                }
+                assert_eq!(db.scan(0, 7), expected);
+                let options = ScanOptions {
+                    reverse: true,
+                    ..ScanOptions::default()
+                };
+                let mut reversed = expected.clone();
+                reversed.reverse();
+                assert_eq!(db.scan_with_options(0, 7, options), reversed);
+
+                //with runs under it a memtable of tombstones is still written, its deletes hide older values
+                for key in (1..8).step_by(2) {
+                    db.delete(key).unwrap();
+                }
+                assert_eq!(db.memtable.len_live(), 0);
+                let num_runs = db.sst_total();
+                db.flush_memtable().unwrap();
+                assert_eq!(db.sst_total(), num_runs + 1);
+                for key in (1..8).step_by(2) {
+                    db.put(key, key).unwrap();
+                }
+            }
+            db
+        };
+        setup_and_test_and_cleaup(
+            "memtable_tombstones_test",
+            &mut |db| {
+                db.set_memtable_capacity(memtable_capacity)
+                    .set_compaction_policy(CompactionPolicy::None)
+            },
+            &mut test,
         );
     }
+
+    #[test]
+    fn model_test() {
+        //random puts, deletes, gets, scans, flushes and reopens of keys from a small domain (so versions of a key end
+        // up spread over the memtable and runs), checked against a BTreeMap after every step and gets against full
+        // reads of the runs (see check_get). Runs every compaction policy, SST implementation, bloom filter and
+        // buffer pool setting
+        use rand::{Rng, SeedableRng};
+
+        const NUM_OPS: usize = 2000;
+        const NUM_KEYS: Key = 64;
+        let mut seed = 0;
+        for compaction_policy in [
+            CompactionPolicy::None,
+            CompactionPolicy::Leveled,
+            CompactionPolicy::Tiered,
+            CompactionPolicy::Dostoevsky,
+        ] {
+            for sst_implementation in [SstImplementation::Array, SstImplementation::Btree] {
+                for enable_bloom_filter in [false, true] {
+                    for enable_buffer_pool in [false, true] {
+                        seed += 1;
+                        let mut rng = rand::rngs::StdRng::seed_from_u64(seed);
+                        let size_ratio = rng.gen_range(2..=4);
+                        let context = format!(
+                            "seed {seed}: {compaction_policy:?}, {sst_implementation:?}, size ratio {size_ratio}, \
+                             bloom filter {enable_bloom_filter}, buffer pool {enable_buffer_pool}"
+                        );
+                        let mut alterations = |db: Database| {
+                            db.set_memtable_capacity(8)
+                                .set_compaction_policy(compaction_policy)
+                                .set_sst_size_ratio(size_ratio)
+                                .set_sst_implementation(sst_implementation)
+                                .set_enable_bloom_filter(enable_bloom_filter)
+                                .set_enable_buffer_pool(enable_buffer_pool)
+                                .set_max_level_zero_runs(4)
+                        };
+                        let mut test = |db: Database| {
+                            let mut db = db.set_paranoid_reads(true);
+                            let mut model: BTreeMap<Key, Value> = BTreeMap::new();
+                            for step in 0..NUM_OPS {
+                                let key = rng.gen_range(0..NUM_KEYS);
+                                match rng.gen_range(0..100) {
+                                    0..=39 => {
+                                        let value = rng.gen_range(-1000..1000);
+                                        db.put(key, value).unwrap();
+                                        model.insert(key, value);
+                                    }
+                                    40..=54 => {
+                                        db.delete(key).unwrap();
+                                        model.remove(&key);
+                                    }
+                                    55..=74 => assert_eq!(
+                                        db.get(key),
+                                        model.get(&key).copied(),
+                                        "get {key} at step {step}, {context}"
+                                    ),
+                                    75..=92 => {
+                                        let key2 = rng.gen_range(key..NUM_KEYS);
+                                        let expected: Vec<Entry> = model
+                                            .range(key..=key2)
+                                            .map(|(key, value)| (*key, *value))
+                                            .collect();
+                                        assert_eq!(
+                                            db.scan(key, key2),
+                                            expected,
+                                            "scan {key}..={key2} at step {step}, {context}"
+                                        );
+                                    }
+                                    93..=96 => db.flush().unwrap(),
+                                    _ => {
+                                        let name = db.path().to_path_buf();
+                                        db.close();
+                                        db = open_in(db.storage(), &name).set_paranoid_reads(true);
+                                    }
+                                }
+                            }
+                            let expected: Vec<Entry> =
+                                model.iter().map(|(key, value)| (*key, *value)).collect();
+                            assert_eq!(db.scan(0, NUM_KEYS), expected, "final scan, {context}");
+                            db
+                        };
+                        setup_and_test_and_cleaup(
+                            format!("model_test_{seed}"),
+                            &mut alterations,
+                            &mut test,
+                        );
+                    }
+                }
+            }
+        }
+    }
 }
@@ -0,0 +1,169 @@
+//!How much disk a database takes, see Database::approximate_size_bytes. Sizes come from the metadata of the files the
+//! database's metadata says it has, no page is read. A walk of its directory finds the files that aren't accounted
+//! for (e.g. snapshots, or temporary files a crash left behind) as a cross-check
+
+use std::{
+    collections::HashSet,
+    fmt,
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    file_io::storage::Storage,
+    util::{
+        filename::{self, RunPaths},
+        system_info::ENTRY_SIZE,
+        types::{DatabaseName, Size},
+    },
+};
+
+///Bytes of the files of a level's runs
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct LevelDiskUsage {
+    pub sst_bytes: Size,
+    pub btree_bytes: Size,
+    pub bloom_bytes: Size,
+    pub sidecar_bytes: Size, //expiry, fence and merge operand sidecars
+    pub entries: Size,
+}
+
+impl LevelDiskUsage {
+    pub fn total_bytes(&self) -> Size {
+        self.sst_bytes + self.btree_bytes + self.bloom_bytes + self.sidecar_bytes
+    }
+}
+
+///Bytes a database takes on disk, see Database::approximate_size_bytes
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct DiskUsage {
+    pub total_bytes: Size, //runs and overhead, unaccounted_bytes aren't included
+    pub levels: Vec<LevelDiskUsage>,
+    pub overhead_bytes: Size, //config, metadata, read cache (descriptor and run), maintenance and buffer pool warm-start files
+    pub unaccounted_bytes: Size, //every other file under the database's directory
+}
+
+impl DiskUsage {
+    ///Bytes the entries of every run take uncompressed, tombstones and shadowed entries included
+    pub fn logical_bytes(&self) -> Size {
+        self.levels.iter().map(|level| level.entries).sum::<Size>() * ENTRY_SIZE
+    }
+    ///Bytes on disk per logical byte (total_bytes / logical_bytes), 0 for a database without runs
+    pub fn space_amplification(&self) -> f64 {
+        match self.logical_bytes() {
+            0 => 0.0,
+            logical_bytes => self.total_bytes as f64 / logical_bytes as f64,
+        }
+    }
+}
+
+impl fmt::Display for DiskUsage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "{} bytes ({} overhead, {} unaccounted), space amplification {:.2}",
+            self.total_bytes,
+            self.overhead_bytes,
+            self.unaccounted_bytes,
+            self.space_amplification()
+        )?;
+        for (level, usage) in self.levels.iter().enumerate() {
+            writeln!(
+                f,
+                "level {level}: {} bytes, sst: {}, b-tree: {}, bloom filter: {}, sidecars: {}",
+                usage.total_bytes(),
+                usage.sst_bytes,
+                usage.btree_bytes,
+                usage.bloom_bytes,
+                usage.sidecar_bytes
+            )?;
+        }
+        Ok(())
+    }
+}
+
+///Bytes of the file at <path> if it exists. Optional files (a B-tree of a single page run, bloom filters turned off,
+/// sidecars of runs without expiries) take no space when they're missing
+fn size(storage: &Storage, path: &Path, accounted: &mut HashSet<PathBuf>) -> Size {
+    let len = storage.file_len(path).unwrap_or(0) as Size;
+    accounted.insert(path.to_path_buf());
+    len
+}
+
+///Bytes of every file under <directory>, 0 if there's no such directory
+fn directory_size(storage: &Storage, directory: &Path, accounted: &mut HashSet<PathBuf>) -> Size {
+    let Ok(paths) = storage.read_dir(directory) else {
+        return 0;
+    };
+    paths
+        .iter()
+        .map(|path| match storage.read_dir(path) {
+            Ok(_) => directory_size(storage, path, accounted),
+            Err(_) => size(storage, path, accounted),
+        })
+        .sum()
+}
+
+///Bytes of the files under <directory> that aren't in <accounted>
+fn unaccounted(storage: &Storage, directory: &Path, accounted: &HashSet<PathBuf>) -> Size {
+    let Ok(paths) = storage.read_dir(directory) else {
+        return 0;
+    };
+    paths
+        .iter()
+        .filter(|path| !accounted.contains(*path))
+        .map(|path| match storage.read_dir(path) {
+            Ok(_) => unaccounted(storage, path, accounted),
+            Err(_) => storage.file_len(path).unwrap_or(0) as Size,
+        })
+        .sum()
+}
+
+///Disk usage of the database at <db_name> whose levels hold runs of <entry_counts> entries
+pub fn measure(db_name: &DatabaseName, entry_counts: &[Vec<Size>]) -> DiskUsage {
+    let storage = db_name.storage();
+    let mut accounted = HashSet::new();
+    let levels: Vec<LevelDiskUsage> = entry_counts
+        .iter()
+        .enumerate()
+        .map(|(level, level_entry_counts)| {
+            let mut usage = LevelDiskUsage::default();
+            for (run, entries) in level_entry_counts.iter().enumerate() {
+                let run_address = (db_name, level, run);
+                usage.entries += entries;
+                usage.sst_bytes += size(storage, &run_address.path_sst(), &mut accounted);
+                usage.btree_bytes += size(storage, &run_address.path_btree(), &mut accounted);
+                usage.bloom_bytes += size(storage, &run_address.path_bloom(), &mut accounted);
+                usage.sidecar_bytes += [
+                    filename::expiry_path(&run_address),
+                    filename::fence_path(&run_address),
+                    filename::operands_path(&run_address),
+                ]
+                .iter()
+                .map(|path| size(storage, path, &mut accounted))
+                .sum::<Size>();
+            }
+            usage
+        })
+        .collect();
+    let overhead_bytes = [
+        filename::config(db_name),
+        filename::metadata(db_name),
+        filename::read_cache(db_name),
+        filename::maintenance(db_name),
+        filename::buffer_pool_warm(db_name),
+    ]
+    .iter()
+    .map(|path| size(storage, path, &mut accounted))
+    .sum::<Size>()
+        + directory_size(
+            storage,
+            &filename::read_cache_directory(db_name),
+            &mut accounted,
+        );
+    DiskUsage {
+        total_bytes: levels.iter().map(LevelDiskUsage::total_bytes).sum::<Size>() + overhead_bytes,
+        levels,
+        overhead_bytes,
+        unaccounted_bytes: unaccounted(storage, db_name, &accounted),
+    }
+}
@@ -0,0 +1,106 @@
+use std::{error::Error, fmt, io};
+
+use crate::{db::Database, util::yield_hook};
+
+///Errors surfaced to callers instead of panicking
+#[derive(Debug)]
+pub enum DbError {
+    Io(io::Error),
+    ///A database file couldn't be decoded
+    Corrupt {
+        file: &'static str,
+        reason: String,
+    },
+    ///A database file was written by a newer version of this crate
+    UnsupportedVersion {
+        found: u32,
+        supported: u32,
+    },
+    ///A line of an imported file was invalid, see Database::import_delimited
+    Import {
+        line: usize,
+        reason: String,
+    },
+    ///A setting was given a value it can't take
+    InvalidConfig {
+        setting: &'static str,
+        reason: String,
+    },
+    ///The value is Database::TOMBSTONE_VALUE, which marks deleted keys
+    ReservedValue,
+    ///The key is Database::INVALID_KEY, which scans can't handle
+    ReservedKey,
+    ///Level 0 already holds <max_runs> runs, so the memtable can't be flushed until it's compacted,
+    /// see LevelZeroOverflowBehavior::Backpressure
+    TooManyRuns {
+        max_runs: usize,
+    },
+    ///Level 0 holds <level_zero_runs> runs, at least Database::level_zero_stop_runs, so writes are refused until it's
+    /// compacted, see StallBehavior::Fail
+    WriteStalled {
+        level_zero_runs: usize,
+    },
+    ///The key of entry <index> of a batch isn't larger than the one before it, see Database::put_sorted_batch
+    UnsortedBatch {
+        index: usize,
+    },
+    ///The database's yield hook stopped the operation, see Database::set_yield_hook
+    Aborted,
+    ///The database was truncated after the snapshot or iterator was created, see Database::truncate
+    Truncated,
+    ///The database was closed (or abandoned), see Database::try_close
+    Closed,
+}
+
+impl fmt::Display for DbError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DbError::Io(why) => write!(f, "{why}"),
+            DbError::Corrupt { file, reason } => write!(f, "corrupt {file} file: {reason}"),
+            DbError::UnsupportedVersion { found, supported } => write!(
+                f,
+                "unsupported file version {found}, versions up to {supported} are supported"
+            ),
+            DbError::Import { line, reason } => write!(f, "line {line}: {reason}"),
+            DbError::InvalidConfig { setting, reason } => write!(f, "invalid {setting}: {reason}"),
+            DbError::ReservedValue => write!(f, "value {} is reserved", Database::TOMBSTONE_VALUE),
+            DbError::ReservedKey => write!(f, "key {} is reserved", Database::INVALID_KEY),
+            DbError::TooManyRuns { max_runs } => {
+                write!(f, "level 0 already holds {max_runs} runs, compact it first")
+            }
+            DbError::WriteStalled { level_zero_runs } => {
+                write!(
+                    f,
+                    "writes are stalled, level 0 holds {level_zero_runs} runs"
+                )
+            }
+            DbError::UnsortedBatch { index } => {
+                write!(
+                    f,
+                    "entry {index} of the batch isn't sorted after the one before it"
+                )
+            }
+            DbError::Aborted => write!(f, "aborted by the yield hook"),
+            DbError::Truncated => write!(f, "the database was truncated since"),
+            DbError::Closed => write!(f, "the database is closed"),
+        }
+    }
+}
+
+impl Error for DbError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            DbError::Io(why) => Some(why),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for DbError {
+    fn from(why: io::Error) -> Self {
+        match yield_hook::is_aborted(&why) {
+            true => DbError::Aborted,
+            false => DbError::Io(why),
+        }
+    }
+}
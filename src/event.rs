@@ -0,0 +1,54 @@
+//!Events reported to the database's event listener (see Database::set_event_listener), for watching what flushes
+//! and compactions cost while the database runs, when they hold back writes and when writes wait too long for a flush
+
+use std::{io, time::Duration};
+
+use crate::util::types::{Level, Run, Size};
+
+///Something the database did to its runs, or to a write while they pile up
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DbEvent {
+    ///The memtable was written to a new level 0 run (<duration> only counts writing it), reported after any moves and
+    /// compactions that made room for it
+    MemtableFlush { entries: Size, duration: Duration },
+    ///The runs of a level are about to be merged into one
+    CompactionStarted {
+        level: Level,
+        runs: Size,
+        input_entries: Size,
+    },
+    ///The merged run replaced the input runs of the level
+    CompactionFinished {
+        level: Level,
+        output_entries: Size,
+        input_pages_read: Size,     //SST pages of the input runs
+        output_pages_written: Size, //SST pages of the merged run, and of intermediate runs if merged in passes
+        duration: Duration,         //from CompactionStarted
+    },
+    ///A write slept for <delay> before going ahead, level 0 holds at least Database::level_zero_slowdown_runs runs
+    WriteSlowed {
+        level_zero_runs: Size,
+        delay: Duration,
+    },
+    ///A write was stalled, level 0 holds at least Database::level_zero_stop_runs runs. Reported before the write fails
+    /// or waits for level 0 to be compacted, see StallBehavior
+    WriteStopped { level_zero_runs: Size },
+    ///The oldest write not flushed yet is <age> old, at least Database::durability_lag_threshold. Reported by the
+    /// first write to find it that old, once for each flush's worth of writes
+    DurabilityLag {
+        age: Duration,
+        unflushed_entries: Size,
+    },
+    ///The bloom filter of <level>, <run> was missing or of the wrong size, it was rebuilt from the run's SST (see
+    /// Database::verify_filters)
+    FilterRepaired { level: Level, run: Run },
+    ///Every run of <from_level> was moved to the end of <to_level>, without being rewritten
+    RunsMoved {
+        from_level: Level,
+        to_level: Level,
+        count: Size,
+    },
+    ///The database was dropped without being closed and closing it failed with an error of <kind>, whatever wasn't
+    /// flushed or written by then is lost (see Database::try_close)
+    CloseFailed { kind: io::ErrorKind },
+}
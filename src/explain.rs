@@ -0,0 +1,152 @@
+//!Traces of single reads, see Database::explain_get. A trace records each step the get took and what it cost, it's
+//! built by the get itself so it always matches the answer get gives
+
+use std::fmt;
+
+use crate::{
+    file_io::io_stats::IoStats,
+    util::types::{Key, Level, Run, Timestamp, Value},
+};
+
+///What the memtable held for the key
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MemtableCheck {
+    Absent,
+    Live(Value),
+    Deleted,
+    ///Live, but its TTL ran out
+    Expired,
+    ///A merge operand, the runs are searched for the value under it
+    Operand(Value),
+}
+
+///What a run's bloom filter said about the key
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BloomProbe {
+    ///The level doesn't keep filters, the run was written before it did or its filter can't be read
+    NoFilter,
+    ///The run is all tombstones, its filter would only say maybe
+    Skipped,
+    Negative,
+    Positive,
+}
+
+///How a run was searched for the key
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SearchMethod {
+    ///Binary search over the run's pages (SstSearchAlgorithm::Default on an array SST)
+    Array,
+    ///Down the run's B-tree
+    Btree,
+    ///The fence index picked the page
+    Fence,
+    ///Binary search over the run's entries (SstSearchAlgorithm::BinarySearch)
+    BinarySearch,
+}
+
+///What searching a run found
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RunOutcome {
+    ///Ruled out by its bloom filter without being searched
+    Filtered,
+    NotFound,
+    ///The youngest entry for the key, a tombstone or an expired entry means the key is deleted
+    Found {
+        value: Value,
+        expiry: Option<Timestamp>,
+    },
+    ///A merge operand, older runs are searched for the value under it
+    Operand(Value),
+}
+
+///One run the get looked at
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RunTrace {
+    pub level: Level,
+    pub run: Run,
+    pub bloom: BloomProbe,
+    pub search: Option<SearchMethod>, //None if the run wasn't searched
+    pub outcome: RunOutcome,
+    pub io: IoStats, //pages read for the run's filter, indexes, entries and sidecars
+}
+
+#[allow(dead_code)]
+impl RunTrace {
+    ///Whether the filter let the get into a run that doesn't hold the key
+    pub fn is_bloom_false_positive(&self) -> bool {
+        self.bloom == BloomProbe::Positive && self.outcome == RunOutcome::NotFound
+    }
+}
+
+///Where the get's answer came from
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Resolution {
+    Memtable,
+    ReadCache,
+    Run {
+        level: Level,
+        run: Run,
+    },
+    ///No run holds the key (any operands were applied to the identity)
+    NotFound,
+}
+
+///Every step of one get, in the order they were taken
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GetTrace {
+    pub key: Key,
+    pub memtable: MemtableCheck,
+    pub read_cache_hit: bool,
+    pub runs: Vec<RunTrace>, //from the youngest run searched to the oldest
+    pub resolution: Resolution,
+    pub value: Option<Value>,
+    pub io: IoStats, //for the whole get
+}
+
+impl GetTrace {
+    pub(crate) fn new(key: Key) -> Self {
+        Self {
+            key,
+            memtable: MemtableCheck::Absent,
+            read_cache_hit: false,
+            runs: Vec::new(),
+            resolution: Resolution::NotFound,
+            value: None,
+            io: IoStats::default(),
+        }
+    }
+}
+
+impl fmt::Display for GetTrace {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "get {}", self.key)?;
+        writeln!(f, "memtable: {:?}", self.memtable)?;
+        if self.read_cache_hit {
+            writeln!(f, "read cache: hit")?;
+        }
+        for run in &self.runs {
+            write!(
+                f,
+                "level {}, run {}: bloom {:?}",
+                run.level, run.run, run.bloom
+            )?;
+            if let Some(search) = run.search {
+                write!(f, ", {search:?} search")?;
+            }
+            writeln!(
+                f,
+                ", {:?}, {} pages read, {} buffer pool hits",
+                run.outcome, run.io.pages_read, run.io.buffer_pool_hits
+            )?;
+        }
+        match self.value {
+            Some(value) => writeln!(f, "value: {value}, from {:?}", self.resolution)?,
+            None => writeln!(f, "value: none, from {:?}", self.resolution)?,
+        }
+        writeln!(
+            f,
+            "{} pages read, {} buffer pool hits, {} bloom probes",
+            self.io.pages_read, self.io.buffer_pool_hits, self.io.bloom_probes
+        )
+    }
+}
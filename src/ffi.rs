@@ -0,0 +1,399 @@
+//!C compatible interface, for callers that aren't written in Rust (declared in include/kvs.h).
+//! Every function goes through the database's fallible (try_) operations and returns an error code for what they
+//! fail with, the reason for the last KVS_ERROR (or null database) on a thread can be read with kvs_last_error.
+//! Reserved keys and values are rejected as invalid arguments. Panics would be bugs, they're still caught (and
+//! returned as KVS_ERROR) since unwinding across the FFI boundary is undefined behaviour, but the panic hook prints
+//! them first
+
+use std::{
+    any::Any,
+    cell::RefCell,
+    ffi::{c_char, CStr, CString},
+    panic::{self, AssertUnwindSafe},
+    path::PathBuf,
+    ptr,
+};
+
+use crate::{
+    db::Database,
+    error::DbError,
+    util::types::{Key, Size, Value},
+};
+
+///The call succeeded (for kvs_get: the key was found)
+pub const KVS_OK: i32 = 0;
+///kvs_get: the key isn't in the database
+pub const KVS_NOT_FOUND: i32 = 1;
+///kvs_scan: the buffer can't hold every entry in the range, nothing was written
+pub const KVS_BUFFER_TOO_SMALL: i32 = 2;
+///A null pointer, or a reserved key or value
+pub const KVS_INVALID_ARGUMENT: i32 = -1;
+///The database failed (e.g. an I/O error), see kvs_last_error for the reason
+pub const KVS_ERROR: i32 = -2;
+
+thread_local! {
+    static LAST_ERROR: RefCell<Option<CString>> = const { RefCell::new(None) };
+}
+
+///Remembers <why> for kvs_last_error on this thread
+fn set_last_error(why: impl ToString) {
+    //an interior null would end the message early, replace it rather than lose the whole message
+    let why = why.to_string().replace('\0', "\\0");
+    let why = CString::new(why).expect("interior nulls were replaced");
+    LAST_ERROR.with(|last_error| *last_error.borrow_mut() = Some(why));
+}
+
+fn panic_message(payload: &(dyn Any + Send)) -> String {
+    match (
+        payload.downcast_ref::<&str>(),
+        payload.downcast_ref::<String>(),
+    ) {
+        (Some(message), _) => format!("panicked: {message}"),
+        (_, Some(message)) => format!("panicked: {message}"),
+        _ => "panicked".to_string(),
+    }
+}
+
+///Runs <call>, turning a panic into KVS_ERROR
+fn catch_panic(call: impl FnOnce() -> i32) -> i32 {
+    panic::catch_unwind(AssertUnwindSafe(call)).unwrap_or_else(|payload| {
+        set_last_error(panic_message(payload.as_ref()));
+        KVS_ERROR
+    })
+}
+
+#[cfg(unix)]
+fn path_from_bytes(bytes: &[u8]) -> Option<PathBuf> {
+    use std::{ffi::OsStr, os::unix::ffi::OsStrExt};
+    Some(PathBuf::from(OsStr::from_bytes(bytes)))
+}
+
+#[cfg(not(unix))]
+fn path_from_bytes(bytes: &[u8]) -> Option<PathBuf> {
+    std::str::from_utf8(bytes).ok().map(PathBuf::from)
+}
+
+///Opens (or creates) the database rooted at directory <name>, a null terminated path.
+/// Returns null if <name> is null or empty, or the database can't be opened. Free it with kvs_close
+///
+/// # Safety
+/// <name> must be null or point to a null terminated string
+#[no_mangle]
+pub unsafe extern "C" fn kvs_open(name: *const c_char) -> *mut Database {
+    if name.is_null() {
+        return ptr::null_mut();
+    }
+    let bytes = CStr::from_ptr(name).to_bytes();
+    let Some(path) = path_from_bytes(bytes).filter(|path| !path.as_os_str().is_empty()) else {
+        return ptr::null_mut();
+    };
+    match panic::catch_unwind(|| Database::try_open(&path)) {
+        Ok(Ok(db)) => Box::into_raw(Box::new(db)),
+        Ok(Err(why)) => {
+            set_last_error(why);
+            ptr::null_mut()
+        }
+        Err(payload) => {
+            set_last_error(panic_message(payload.as_ref()));
+            ptr::null_mut()
+        }
+    }
+}
+
+///# Safety
+/// <db> must be null or returned by kvs_open and not closed yet
+#[no_mangle]
+pub unsafe extern "C" fn kvs_put(db: *mut Database, key: i64, value: i64) -> i32 {
+    let Some(db) = db.as_mut() else {
+        return KVS_INVALID_ARGUMENT;
+    };
+    catch_panic(|| match db.put(key as Key, value as Value) {
+        Ok(()) => KVS_OK,
+        Err(DbError::ReservedKey | DbError::ReservedValue) => KVS_INVALID_ARGUMENT,
+        Err(why) => {
+            set_last_error(why);
+            KVS_ERROR
+        }
+    })
+}
+
+///Writes the value of <key> to <out_value> and returns KVS_OK, or returns KVS_NOT_FOUND (leaving it untouched)
+///
+/// # Safety
+/// <db> must be null or returned by kvs_open and not closed yet, <out_value> must be null or valid for writes
+#[no_mangle]
+pub unsafe extern "C" fn kvs_get(db: *mut Database, key: i64, out_value: *mut i64) -> i32 {
+    let Some(db) = db.as_mut() else {
+        return KVS_INVALID_ARGUMENT;
+    };
+    if out_value.is_null() || key == Database::INVALID_KEY {
+        return KVS_INVALID_ARGUMENT;
+    }
+    catch_panic(|| match db.try_get(key as Key) {
+        Ok(Some(value)) => {
+            *out_value = value;
+            KVS_OK
+        }
+        Ok(None) => KVS_NOT_FOUND,
+        Err(why) => {
+            set_last_error(why);
+            KVS_ERROR
+        }
+    })
+}
+
+///Writes the entries with keys in [<key1>, <key2>] to <out_buf> as interleaved key, value pairs (in key order) and
+/// their number to <out_written>. <buf_len> is the capacity of <out_buf> in i64s, if it can't hold 2 per entry
+/// nothing is written to it, <out_written> gets the number of entries in the range and KVS_BUFFER_TOO_SMALL is
+/// returned, so the caller can retry with a larger buffer
+///
+/// # Safety
+/// <db> must be null or returned by kvs_open and not closed yet. <out_buf> must be valid for <buf_len> i64 writes
+/// (it may be null if <buf_len> is 0), <out_written> must be null or valid for writes
+#[no_mangle]
+pub unsafe extern "C" fn kvs_scan(
+    db: *mut Database,
+    key1: i64,
+    key2: i64,
+    out_buf: *mut i64,
+    buf_len: usize,
+    out_written: *mut usize,
+) -> i32 {
+    let Some(db) = db.as_mut() else {
+        return KVS_INVALID_ARGUMENT;
+    };
+    if out_written.is_null() || (out_buf.is_null() && buf_len > 0) {
+        return KVS_INVALID_ARGUMENT;
+    }
+    //the scan's heap can't handle the reserved key
+    let key1 = key1.max(Database::INVALID_KEY + 1);
+    catch_panic(|| {
+        let entries = match db.try_scan(key1 as Key, key2 as Key) {
+            Ok(entries) => entries,
+            Err(why) => {
+                set_last_error(why);
+                return KVS_ERROR;
+            }
+        };
+        *out_written = entries.len();
+        if entries.len() * 2 > buf_len as Size {
+            return KVS_BUFFER_TOO_SMALL;
+        }
+        for (index, (key, value)) in entries.into_iter().enumerate() {
+            *out_buf.add(2 * index) = key;
+            *out_buf.add(2 * index + 1) = value;
+        }
+        KVS_OK
+    })
+}
+
+///Flushes and closes the database, then frees it. Returns KVS_ERROR if closing failed, the database is left open
+/// (nothing unflushed is lost) and <db> stays valid: call kvs_close again, e.g. once there's space on the disk
+///
+/// # Safety
+/// <db> must be null or returned by kvs_open and not closed yet, it can't be used after this returns KVS_OK
+#[no_mangle]
+pub unsafe extern "C" fn kvs_close(db: *mut Database) -> i32 {
+    let Some(open_db) = db.as_mut() else {
+        return KVS_INVALID_ARGUMENT;
+    };
+    let status = catch_panic(|| match open_db.try_close() {
+        Ok(()) => KVS_OK,
+        Err(why) => {
+            set_last_error(why);
+            KVS_ERROR
+        }
+    });
+    if status == KVS_OK {
+        drop(Box::from_raw(db)); //closed, dropping it does nothing more
+    }
+    status
+}
+
+///The reason for the last KVS_ERROR (or null kvs_open) on the calling thread, or null if there was none yet.
+/// The string stays valid until the next call that fails on this thread, don't free it
+#[no_mangle]
+pub extern "C" fn kvs_last_error() -> *const c_char {
+    LAST_ERROR.with(|last_error| {
+        last_error
+            .borrow()
+            .as_ref()
+            .map_or(ptr::null(), |why| why.as_ptr())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::ffi::CString;
+
+    use super::*;
+    use crate::util::testing;
+
+    const HEADER: &str = include_str!("../include/kvs.h");
+
+    #[test]
+    fn test_extern_functions() {
+        let test_dir = testing::test_dir("ffi_extern_functions");
+        let _ = std::fs::remove_dir_all(test_dir);
+        let name = CString::new(test_dir.join("db").to_str().unwrap()).unwrap();
+        unsafe {
+            let db = kvs_open(name.as_ptr());
+            assert!(!db.is_null());
+            for key in 0..5 {
+                assert_eq!(kvs_put(db, key, key * 10), KVS_OK);
+            }
+
+            let mut value = -1;
+            assert_eq!(kvs_get(db, 3, &mut value), KVS_OK);
+            assert_eq!(value, 30);
+            assert_eq!(kvs_get(db, 7, &mut value), KVS_NOT_FOUND);
+            assert_eq!(value, 30);
+
+            let mut buf = [0; 6];
+            let mut written = 0;
+            assert_eq!(
+                kvs_scan(db, 1, 3, buf.as_mut_ptr(), buf.len(), &mut written),
+                KVS_OK
+            );
+            assert_eq!((buf, written), ([1, 10, 2, 20, 3, 30], 3));
+            //one slot short, the caller learns how many entries there are
+            let mut small = [0; 5];
+            assert_eq!(
+                kvs_scan(db, 1, 3, small.as_mut_ptr(), small.len(), &mut written),
+                KVS_BUFFER_TOO_SMALL
+            );
+            assert_eq!((small, written), ([0; 5], 3));
+            assert_eq!(
+                kvs_scan(db, 100, 200, ptr::null_mut(), 0, &mut written),
+                KVS_OK
+            );
+            assert_eq!(written, 0);
+            assert_eq!(
+                kvs_scan(db, Key::MIN, Key::MAX, ptr::null_mut(), 0, &mut written),
+                KVS_BUFFER_TOO_SMALL
+            );
+            assert_eq!(written, 5);
+
+            //entries survive closing and reopening
+            assert_eq!(kvs_close(db), KVS_OK);
+            let db = kvs_open(name.as_ptr());
+            assert_eq!(kvs_get(db, 4, &mut value), KVS_OK);
+            assert_eq!(value, 40);
+            assert_eq!(kvs_close(db), KVS_OK);
+
+            //a run deleted under the database fails reads that reach it with the I/O error
+            std::fs::remove_file(test_dir.join("db/0/0.sst")).unwrap();
+            let db = kvs_open(name.as_ptr());
+            assert!(kvs_last_error().is_null());
+            assert_eq!(kvs_get(db, 1, &mut value), KVS_ERROR);
+            let why = CStr::from_ptr(kvs_last_error()).to_str().unwrap();
+            assert!(why.contains("get the value of key 1"), "{why}");
+            assert_eq!(
+                kvs_scan(db, 0, 4, buf.as_mut_ptr(), buf.len(), &mut written),
+                KVS_ERROR
+            );
+            let why = CStr::from_ptr(kvs_last_error()).to_str().unwrap();
+            assert!(why.contains("scan range (0 to 4)"), "{why}");
+            assert_eq!(kvs_close(db), KVS_OK);
+        }
+        std::fs::remove_dir_all(test_dir).unwrap();
+    }
+
+    #[test]
+    fn test_close_failure() {
+        let test_dir = testing::test_dir("ffi_close_failure");
+        let _ = std::fs::remove_dir_all(test_dir);
+        let name = CString::new(test_dir.join("db").to_str().unwrap()).unwrap();
+        //a file where level 0's directory goes fails the flush on close
+        let level_zero = test_dir.join("db/0");
+        unsafe {
+            let db = kvs_open(name.as_ptr());
+            for key in 0..3 {
+                assert_eq!(kvs_put(db, key, key * 10), KVS_OK);
+            }
+            let _ = std::fs::remove_dir_all(&level_zero);
+            std::fs::write(&level_zero, []).unwrap();
+            assert_eq!(kvs_close(db), KVS_ERROR);
+            assert!(!kvs_last_error().is_null());
+
+            //the database is still open with its entries, closing can be retried
+            let mut value = -1;
+            assert_eq!(kvs_get(db, 2, &mut value), KVS_OK);
+            assert_eq!(value, 20);
+            std::fs::remove_file(&level_zero).unwrap();
+            assert_eq!(kvs_close(db), KVS_OK);
+
+            let db = kvs_open(name.as_ptr());
+            assert_eq!(kvs_get(db, 2, &mut value), KVS_OK);
+            assert_eq!(kvs_close(db), KVS_OK);
+        }
+        std::fs::remove_dir_all(test_dir).unwrap();
+    }
+
+    #[test]
+    fn test_invalid_arguments() {
+        let test_dir = testing::test_dir("ffi_invalid_arguments");
+        let _ = std::fs::remove_dir_all(test_dir);
+        let name = CString::new(test_dir.join("db").to_str().unwrap()).unwrap();
+        unsafe {
+            assert!(kvs_open(ptr::null()).is_null());
+            assert!(kvs_open(c"".as_ptr()).is_null());
+            let mut value = 0;
+            let mut written = 0;
+            assert_eq!(kvs_put(ptr::null_mut(), 1, 1), KVS_INVALID_ARGUMENT);
+            assert_eq!(
+                kvs_get(ptr::null_mut(), 1, &mut value),
+                KVS_INVALID_ARGUMENT
+            );
+            assert_eq!(
+                kvs_scan(ptr::null_mut(), 0, 1, ptr::null_mut(), 0, &mut written),
+                KVS_INVALID_ARGUMENT
+            );
+            assert_eq!(kvs_close(ptr::null_mut()), KVS_INVALID_ARGUMENT);
+
+            let db = kvs_open(name.as_ptr());
+            assert_eq!(
+                kvs_put(db, 1, Database::TOMBSTONE_VALUE),
+                KVS_INVALID_ARGUMENT
+            );
+            assert_eq!(kvs_put(db, Database::INVALID_KEY, 1), KVS_INVALID_ARGUMENT);
+            assert_eq!(kvs_get(db, 1, ptr::null_mut()), KVS_INVALID_ARGUMENT);
+            assert_eq!(
+                kvs_scan(db, 0, 1, ptr::null_mut(), 2, &mut written),
+                KVS_INVALID_ARGUMENT
+            );
+            assert_eq!(kvs_get(db, 1, &mut value), KVS_NOT_FOUND);
+            assert_eq!(kvs_close(db), KVS_OK);
+        }
+        std::fs::remove_dir_all(test_dir).unwrap();
+    }
+
+    #[test]
+    fn test_header_matches() {
+        //the header is written to match what `cbindgen --config cbindgen.toml` generates, check it declares every
+        // function and status code with the signatures used here
+        for declaration in [
+            "Database *kvs_open(const char *name);",
+            "int32_t kvs_put(Database *db, int64_t key, int64_t value);",
+            "int32_t kvs_get(Database *db, int64_t key, int64_t *out_value);",
+            "int32_t kvs_scan(Database *db,\n                 int64_t key1,\n                 int64_t key2,\n                 int64_t *out_buf,\n                 uintptr_t buf_len,\n                 uintptr_t *out_written);",
+            "int32_t kvs_close(Database *db);",
+            "const char *kvs_last_error(void);",
+            "typedef struct Database Database;",
+        ] {
+            assert!(HEADER.contains(declaration), "missing: {declaration}");
+        }
+        for (name, code) in [
+            ("KVS_OK", KVS_OK),
+            ("KVS_NOT_FOUND", KVS_NOT_FOUND),
+            ("KVS_BUFFER_TOO_SMALL", KVS_BUFFER_TOO_SMALL),
+            ("KVS_INVALID_ARGUMENT", KVS_INVALID_ARGUMENT),
+            ("KVS_ERROR", KVS_ERROR),
+        ] {
+            let define = format!("#define {name} {code}\n");
+            assert!(HEADER.contains(&define), "missing: {define}");
+        }
+        let functions = HEADER.matches(" kvs_").count() + HEADER.matches("*kvs_").count();
+        assert_eq!(functions, 6, "header declares functions that don't exist");
+    }
+}
@@ -1,82 +1,478 @@
 use std::{
-    fs::{File, OpenOptions},
-    io,
-    path::Path,
+    fs::{self, File, OpenOptions},
+    io::{self, Read, Write},
+    path::{Path, PathBuf},
 };
 
-#[cfg(unix)]
+#[cfg(all(unix, not(target_os = "macos"), feature = "direct-io"))]
 use std::os::unix::fs::OpenOptionsExt;
 
-#[cfg(windows)]
+#[cfg(all(windows, feature = "direct-io"))]
 use std::os::windows::fs::OpenOptionsExt;
 
-use crate::util::{system_info, types::Page};
+use crate::util::{
+    system_info,
+    types::{IoMode, Page},
+};
+
+use super::{
+    io_stats,
+    page_buffers::PageBuffer,
+    storage::{Access, DbFile, FileSystem},
+};
 
-#[cfg(unix)]
-fn direct_io_flags() -> i32 {
+#[cfg(all(unix, not(target_os = "macos"), feature = "direct-io"))]
+fn open_direct(mut options: OpenOptions, path: &Path) -> io::Result<File> {
     // Unix-specific code
     extern crate libc;
-    //NOTE: O_DIRECT is not available on apple silicon, seems like they can't do direct I/O (need to confirm)
-    libc::O_DIRECT
+    #[cfg(test)]
+    tests::check_simulated_support(path)?;
+    options.custom_flags(libc::O_DIRECT).open(path)
 }
 
-#[cfg(windows)]
-fn direct_io_flags() -> u32 {
+///O_DIRECT doesn't exist on macOS, F_NOCACHE is the closest thing (no alignment rules, pages aren't kept cached)
+#[cfg(all(target_os = "macos", feature = "direct-io"))]
+fn open_direct(options: OpenOptions, path: &Path) -> io::Result<File> {
+    use std::os::fd::AsRawFd;
+    extern crate libc;
+    #[cfg(test)]
+    tests::check_simulated_support(path)?;
+    let file = options.open(path)?;
+    if unsafe { libc::fcntl(file.as_raw_fd(), libc::F_NOCACHE, 1) } == -1 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(file)
+}
+
+#[cfg(all(windows, feature = "direct-io"))]
+fn open_direct(mut options: OpenOptions, path: &Path) -> io::Result<File> {
     // Windows-specific code
     extern crate winapi;
     use winapi::um::winbase::{FILE_FLAG_NO_BUFFERING, FILE_FLAG_WRITE_THROUGH};
-    FILE_FLAG_NO_BUFFERING | FILE_FLAG_WRITE_THROUGH //direct io flags for windows
+    #[cfg(test)]
+    tests::check_simulated_support(path)?;
+    options
+        .custom_flags(FILE_FLAG_NO_BUFFERING | FILE_FLAG_WRITE_THROUGH) //direct io flags for windows
+        .open(path)
 }
 
-fn open_options() -> OpenOptions {
-    OpenOptions::new()
-        .custom_flags(direct_io_flags())
-        .to_owned()
+///Without direct-io files go through the OS page cache, buffers are still page aligned so nothing else changes
+#[cfg(not(feature = "direct-io"))]
+fn open_direct(_: OpenOptions, _: &Path) -> io::Result<File> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "built without the direct-io feature",
+    ))
 }
 
-///Opens (creates if doesn't exist) file with read and write permissions using direct I/O
-pub fn create(path: &str) -> io::Result<File> {
-    open_options()
-        .read(true)
-        .write(true)
-        .create(true)
-        .open(path)
+///Whether opening with direct I/O failed because the filesystem (or OS) doesn't do it, rather than for the file
+fn is_unsupported(why: &io::Error) -> bool {
+    //EINVAL on linux (e.g. tmpfs), ERROR_INVALID_PARAMETER on windows
+    matches!(
+        why.kind(),
+        io::ErrorKind::InvalidInput | io::ErrorKind::Unsupported
+    )
+}
+
+fn unsupported_error(directory: &Path, why: io::Error) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::Unsupported,
+        format!(
+            "direct I/O isn't supported for {} ({why}), use IoMode::Auto or IoMode::Buffered",
+            directory.display()
+        ),
+    )
+}
+
+///Reserve <bytes> bytes of storage for <file> (being written from its start) up front, so the filesystem can lay it out
+/// in a few large extents instead of growing it an append at a time. The file is <bytes> long afterwards, the writer
+/// truncates it to what it wrote (set_len) once it's done. fallocate where it's available, a single set_len elsewhere.
+/// In-memory files aren't preallocated.
+/// NOTE: on failure (e.g. ENOSPC) the file can be written the same as if it was never preallocated
+pub fn preallocate(file: &DbFile, bytes: u64) -> io::Result<()> {
+    match file {
+        DbFile::Disk(file) => allocate(file, bytes),
+        DbFile::Memory(_) => Ok(()),
+    }
+}
+
+#[cfg(all(target_os = "linux", feature = "direct-io"))]
+fn allocate(file: &File, bytes: u64) -> io::Result<()> {
+    use std::os::fd::AsRawFd;
+    extern crate libc;
+    let len = libc::off_t::try_from(bytes)
+        .map_err(|why| io::Error::new(io::ErrorKind::InvalidInput, why))?;
+    if unsafe { libc::fallocate(file.as_raw_fd(), 0, 0, len) } == -1 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+#[cfg(not(all(target_os = "linux", feature = "direct-io")))]
+fn allocate(file: &File, bytes: u64) -> io::Result<()> {
+    file.set_len(bytes)
 }
 
-///Opens file with read and write permissions using direct I/O
-pub fn open(path: &str) -> io::Result<File> {
-    open_options().read(true).write(true).open(path)
+///Open <path> with <options>, with or without direct I/O depending on <io_mode>.
+/// In IoMode::Auto files are opened for direct I/O, and opened again without it if the filesystem doesn't support it
+fn open_with(options: &mut OpenOptions, path: &Path, io_mode: IoMode) -> io::Result<File> {
+    match io_mode {
+        IoMode::Buffered => options.open(path),
+        IoMode::Direct => open_direct(options.clone(), path),
+        IoMode::Auto => {
+            open_direct(options.clone(), path).or_else(|why| match is_unsupported(&why) {
+                true => options.open(path),
+                false => Err(why),
+            })
+        }
+    }
 }
 
-///Opens file with read only permissions
-pub fn open_read(path: &str) -> io::Result<File> {
-    open_options().read(true).open(path)
+///Whether writes to <file> have to be a multiple of the minimum write size (see serde_util::write_len)
+pub fn is_direct(file: &DbFile) -> bool {
+    match file {
+        DbFile::Disk(file) => is_direct_file(file),
+        DbFile::Memory(_) => false,
+    }
 }
 
-///Opens file write only permissions using direct I/O
-pub fn open_write(path: &str) -> io::Result<File> {
-    open_options().write(true).open(path)
+#[cfg(all(unix, not(target_os = "macos"), feature = "direct-io"))]
+fn is_direct_file(file: &File) -> bool {
+    use std::os::fd::AsRawFd;
+    let flags = unsafe { libc::fcntl(file.as_raw_fd(), libc::F_GETFL) };
+    flags != -1 && flags & libc::O_DIRECT != 0
+}
+
+///F_NOCACHE doesn't need aligned writes
+#[cfg(all(target_os = "macos", feature = "direct-io"))]
+fn is_direct_file(_: &File) -> bool {
+    false
+}
+
+///Windows can't tell how a file was opened, padding a buffered write is harmless
+#[cfg(all(windows, feature = "direct-io"))]
+fn is_direct_file(_: &File) -> bool {
+    true
+}
+
+#[cfg(not(feature = "direct-io"))]
+fn is_direct_file(_: &File) -> bool {
+    false
+}
+
+///Files on disk, see Storage::disk
+#[derive(Debug)]
+pub struct DiskFs;
+
+impl FileSystem for DiskFs {
+    fn is_in_memory(&self) -> bool {
+        false
+    }
+    fn resolve_io_mode(
+        &self,
+        directory: &Path,
+        probe_path: &Path,
+        io_mode: IoMode,
+    ) -> io::Result<IoMode> {
+        match io_mode {
+            IoMode::Buffered => Ok(IoMode::Buffered),
+            IoMode::Auto | IoMode::Direct => {
+                match open_direct(OpenOptions::new().read(true).to_owned(), probe_path) {
+                    Ok(_) => Ok(IoMode::Direct),
+                    Err(why) if is_unsupported(&why) && io_mode == IoMode::Auto => {
+                        Ok(IoMode::Buffered)
+                    }
+                    Err(why) if is_unsupported(&why) => Err(unsupported_error(directory, why)),
+                    Err(why) => Err(why),
+                }
+            }
+        }
+    }
+    fn open(&self, path: &Path, access: Access, io_mode: IoMode) -> io::Result<DbFile> {
+        let mut options = OpenOptions::new();
+        let file = match access {
+            Access::Create => open_with(options.read(true).write(true).create(true), path, io_mode),
+            Access::ReadWrite => open_with(options.read(true).write(true), path, io_mode),
+            Access::Read => open_with(options.read(true), path, io_mode),
+            Access::Write => open_with(options.write(true), path, io_mode),
+            Access::Append => open_with(options.append(true), path, io_mode),
+            Access::CreateBuffered => File::create(path),
+            Access::ReadBuffered => File::open(path),
+        };
+        file.map(DbFile::Disk)
+    }
+    fn exists(&self, path: &Path) -> bool {
+        path.exists()
+    }
+    fn remove_file(&self, path: &Path) -> io::Result<()> {
+        fs::remove_file(path)
+    }
+    fn rename(&self, old_path: &Path, new_path: &Path) -> io::Result<()> {
+        fs::rename(old_path, new_path)
+    }
+    fn hard_link(&self, original: &Path, link: &Path) -> io::Result<()> {
+        fs::hard_link(original, link)
+    }
+    fn file_len(&self, path: &Path) -> io::Result<u64> {
+        Ok(fs::metadata(path)?.len())
+    }
+    fn read(&self, path: &Path) -> io::Result<Vec<u8>> {
+        fs::read(path)
+    }
+    fn write(&self, path: &Path, bytes: &[u8], sync: bool) -> io::Result<()> {
+        let mut file = File::create(path)?;
+        file.write_all(bytes)?;
+        match sync {
+            true => DbFile::Disk(file).sync_all(),
+            false => Ok(()),
+        }
+    }
+    ///Directories can only be opened as files (to sync them) on unix
+    fn sync_directory(&self, directory: &Path) -> io::Result<()> {
+        match cfg!(unix) {
+            true => File::open(directory)?.sync_all(),
+            false => Ok(()),
+        }
+    }
+    fn create_dir_all(&self, path: &Path) -> io::Result<()> {
+        fs::create_dir_all(path)
+    }
+    fn remove_dir_all(&self, path: &Path) -> io::Result<()> {
+        fs::remove_dir_all(path)
+    }
+    fn read_dir(&self, path: &Path) -> io::Result<Vec<PathBuf>> {
+        fs::read_dir(path)?
+            .map(|entry| entry.map(|entry| entry.path()))
+            .collect()
+    }
 }
 
-///Opens file append only permissions  using direct I/O
-pub fn open_append(path: &str) -> io::Result<File> {
-    open_options().append(true).open(path)
+///Write all bytes through a page aligned buffer, direct I/O rejects (EINVAL) transfers from unaligned memory.
+/// NOTE: bytes that don't already start at a page aligned address (heap Vecs mostly don't) are copied once, into a
+/// buffer from the free list (see page_buffers). That's a memcpy of a few pages, far cheaper than the write itself,
+/// which bypasses the page cache, so the serializers keep building plain Vecs
+pub fn write_all(writer: &mut (impl Write + ?Sized), bytes: &[u8]) -> io::Result<()> {
+    match bytes.as_ptr().align_offset(system_info::page_size()) {
+        0 => writer.write_all(bytes)?,
+        _ => {
+            let mut aligned = PageBuffer::take(bytes.len());
+            aligned.copy_from_slice(bytes);
+            writer.write_all(&aligned)?;
+        }
+    }
+    io_stats::record_write(bytes.len());
+    Ok(())
 }
 
-pub fn path_exists(path: &str) -> bool {
-    Path::new(path).exists()
+///Read up to <len> bytes through a page aligned buffer, returns the bytes that were read
+pub fn read(reader: &mut impl Read, len: usize) -> io::Result<PageBuffer> {
+    let mut aligned = PageBuffer::take(len);
+    let bytes_read = reader.read(&mut aligned)?;
+    io_stats::record_read(bytes_read);
+    aligned.retain(0..bytes_read);
+    Ok(aligned)
 }
 
 ///deserialize with a custom buffer size
 pub fn read_page(
     reader: &mut (impl std::io::Read + std::io::Seek),
     page_index: Page,
-) -> io::Result<Vec<u8>> {
-    let mut buffer: Vec<u8> = vec![0; system_info::page_size()];
+) -> io::Result<PageBuffer> {
+    //widened first, files can be larger than a usize on 32-bit targets
     reader.seek(io::SeekFrom::Start(
-        (page_index * system_info::page_size()) as u64,
+        page_index as u64 * system_info::page_size() as u64,
     ))?;
-    let bytes_read = reader.read(&mut buffer)?;
-    buffer.truncate(bytes_read);
-    Ok(buffer)
+    read(reader, system_info::page_size())
+}
+
+///Read the <len> bytes at <byte_offset> through the smallest run of minimum write size blocks that holds them (direct
+/// I/O needs aligned offsets and lengths), instead of the whole page. Returns fewer bytes past the end of the file
+pub fn read_aligned(
+    reader: &mut (impl std::io::Read + std::io::Seek),
+    byte_offset: u64,
+    len: usize,
+) -> io::Result<PageBuffer> {
+    let block_size = system_info::mimimum_write_size() as u64;
+    let start = byte_offset / block_size * block_size;
+    let end = (byte_offset + len as u64).div_ceil(block_size) * block_size;
+    reader.seek(io::SeekFrom::Start(start))?;
+    let mut blocks = read(reader, (end - start) as usize)?;
+    let from = (byte_offset - start) as usize;
+    blocks.retain(from..from + len);
+    Ok(blocks)
+}
+
+#[cfg(test)]
+pub mod tests {
+    use std::sync::Mutex;
+
+    use super::*;
+    use crate::{
+        file_io::{page_buffers, storage::Storage},
+        util::testing,
+    };
+
+    ///Directories whose filesystem pretends not to support direct I/O
+    static UNSUPPORTED: Mutex<Vec<PathBuf>> = Mutex::new(Vec::new());
+
+    ///Fail direct I/O opens of files under <directory> like a filesystem without O_DIRECT (EINVAL)
+    pub fn simulate_no_direct_io(directory: &Path) {
+        UNSUPPORTED.lock().unwrap().push(directory.to_path_buf());
+    }
+
+    ///Where copies of a directory are made before each change to the files in it, see record_crash_points
+    struct CrashRecorder {
+        directory: PathBuf,
+        snapshots: PathBuf,
+        num_snapshots: usize,
+    }
+
+    static CRASH_RECORDERS: Mutex<Vec<CrashRecorder>> = Mutex::new(Vec::new());
+
+    ///From now on copy <directory> (on disk) into a new numbered directory under <snapshots> right before any file in
+    /// it is created, replaced, renamed or removed, i.e. everything a crash at that point would leave behind
+    pub fn record_crash_points(directory: &Path, snapshots: &Path) {
+        fs::create_dir_all(snapshots).unwrap();
+        CRASH_RECORDERS.lock().unwrap().push(CrashRecorder {
+            directory: directory.to_path_buf(),
+            snapshots: snapshots.to_path_buf(),
+            num_snapshots: 0,
+        });
+    }
+
+    ///Stop copying <directory>, returns the copies made in the order they were made
+    pub fn stop_recording_crash_points(directory: &Path) -> Vec<PathBuf> {
+        let mut recorders = CRASH_RECORDERS.lock().unwrap();
+        let index = recorders
+            .iter()
+            .position(|recorder| recorder.directory == directory)
+            .expect("crash points of the directory weren't being recorded");
+        let recorder = recorders.remove(index);
+        (0..recorder.num_snapshots)
+            .map(|snapshot| recorder.snapshots.join(snapshot.to_string()))
+            .collect()
+    }
+
+    pub fn crash_point(path: &Path) {
+        let mut recorders = CRASH_RECORDERS.lock().unwrap();
+        for recorder in recorders.iter_mut() {
+            if path.starts_with(&recorder.directory) {
+                let snapshot = recorder.snapshots.join(recorder.num_snapshots.to_string());
+                copy_directory(&recorder.directory, &snapshot).unwrap();
+                recorder.num_snapshots += 1;
+            }
+        }
+    }
+
+    fn copy_directory(from: &Path, to: &Path) -> io::Result<()> {
+        fs::create_dir_all(to)?;
+        for entry in fs::read_dir(from)? {
+            let path = entry?.path();
+            let copy = to.join(path.file_name().unwrap());
+            match path.is_dir() {
+                true => copy_directory(&path, &copy)?,
+                false => drop(fs::copy(&path, &copy)?),
+            }
+        }
+        Ok(())
+    }
+
+    #[cfg_attr(not(feature = "direct-io"), allow(dead_code))]
+    pub fn check_simulated_support(path: &Path) -> io::Result<()> {
+        let unsupported = UNSUPPORTED.lock().unwrap();
+        if path
+            .ancestors()
+            .any(|directory| unsupported.iter().any(|dir| dir == directory))
+        {
+            return Err(io::Error::from(io::ErrorKind::InvalidInput));
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_preallocate() {
+        let directory = testing::test_dir("direct_io_preallocate");
+        let _ = fs::remove_dir_all(directory);
+        fs::create_dir_all(directory).unwrap();
+        let page_size = system_info::page_size();
+        let path = directory.join("file.bin");
+        let mut file = Storage::disk().create(&path).unwrap();
+        preallocate(&file, 4 * page_size as u64).unwrap();
+        assert_eq!(file.len_bytes().unwrap(), 4 * page_size as u64);
+
+        //written from the start as if it was empty, then truncated to what was written
+        write_all(&mut file, &vec![7; page_size]).unwrap();
+        file.set_len(page_size as u64 - 3).unwrap();
+        assert_eq!(fs::read(&path).unwrap(), vec![7; page_size - 3]);
+
+        //in-memory files are left as they are
+        let memory = Storage::in_memory();
+        memory.create_dir_all(directory).unwrap();
+        let file = memory.create(&path).unwrap();
+        preallocate(&file, page_size as u64).unwrap();
+        assert_eq!(file.len_bytes().unwrap(), 0);
+        fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[test]
+    fn test_write_all_copies_unaligned_only() {
+        let directory = testing::test_dir("direct_io_write_all_copies_unaligned_only");
+        let _ = fs::remove_dir_all(directory);
+        fs::create_dir_all(directory).unwrap();
+        let page_size = system_info::page_size();
+        let path = directory.join("file.bin");
+        let mut file = Storage::disk().create(&path).unwrap();
+
+        //already aligned bytes are written as they are
+        let mut aligned = PageBuffer::take(page_size);
+        aligned.fill(3);
+        let baseline = page_buffers::stats();
+        write_all(&mut file, &aligned).unwrap();
+        assert_eq!(page_buffers::stats().since(&baseline), Default::default());
+
+        //unaligned ones go through a buffer
+        let unaligned = vec![4; page_size + 1];
+        write_all(&mut file, &unaligned[1..]).unwrap();
+        let copies = page_buffers::stats().since(&baseline);
+        assert_eq!(copies.allocations + copies.reuses, 1);
+        drop(file);
+
+        let mut expected = vec![3; page_size];
+        expected.extend(vec![4; page_size]);
+        assert_eq!(fs::read(&path).unwrap(), expected);
+        fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[test]
+    #[cfg(feature = "direct-io")]
+    fn test_auto_falls_back() {
+        let directory = testing::test_dir("direct_io_auto_falls_back");
+        std::fs::create_dir_all(directory).unwrap();
+        simulate_no_direct_io(directory);
+        let path = directory.join("file.bin");
+
+        //files are opened in Auto mode until it's resolved
+        let mut storage = Storage::disk();
+        let mut file = storage.create(&path).unwrap();
+        assert!(!is_direct(&file));
+        write_all(&mut file, &[1, 2, 3]).unwrap(); //no padding needed
+        assert_eq!(std::fs::read(&path).unwrap(), vec![1, 2, 3]);
+
+        assert_eq!(
+            storage
+                .register_io_mode(directory, &path, IoMode::Auto)
+                .unwrap(),
+            IoMode::Buffered
+        );
+        assert_eq!(storage.io_mode(), IoMode::Buffered);
+        let why = storage
+            .register_io_mode(directory, &path, IoMode::Direct)
+            .unwrap_err();
+        assert_eq!(why.kind(), io::ErrorKind::Unsupported);
+        assert!(
+            why.to_string().contains("direct I/O isn't supported"),
+            "{why}"
+        );
+        std::fs::remove_dir_all(directory).unwrap();
+    }
 }
@@ -1,48 +1,160 @@
-use std::io;
+use std::{io, path::Path};
 
 use crate::{buffer_pool::BufferPool, util::types::Page};
+#[cfg(feature = "btree")]
+use crate::{
+    ceil_div,
+    util::system_info::{self, PageLayout},
+};
+#[cfg(feature = "btree")]
+use std::ops::Range;
 
-use super::direct_io;
+use super::{direct_io, generation, io_stats, page_buffers::PageBuffer, storage::Storage};
 
 //This file is responsible for getting pages through the buffer pool if enabled
 // and interacting with files in a way that keeps the buffer pool in sync with
 // the state of the file system
 
 pub fn get_page(
-    path: &str,
+    storage: &Storage,
+    path: &Path,
     page_index: Page,
     buffer_pool: Option<&mut BufferPool>,
-) -> io::Result<Vec<u8>> {
+) -> io::Result<PageBuffer> {
+    get_page_with(path, page_index, buffer_pool, |_| {
+        let mut file = storage.open_read(path)?;
+        direct_io::read_page(&mut file, page_index)
+    })
+}
+
+///get_page for pages that aren't stored as they're read, e.g. compressed ones (see sst::compression): the buffer pool
+/// caches what <read_page> returns for page <page_index> of <path>. <read_page> gets the buffer pool, if there is one,
+/// to look up what it needs to read the page
+pub fn get_page_with(
+    path: &Path,
+    page_index: Page,
+    buffer_pool: Option<&mut BufferPool>,
+    read_page: impl FnOnce(Option<&mut BufferPool>) -> io::Result<PageBuffer>,
+) -> io::Result<PageBuffer> {
     if let Some(pool) = buffer_pool {
-        //NOTE: watch out for bugs from this, not super sure how things work out here
-        if let Some(page) = pool.get(path, page_index) {
-            Ok(page)
+        let generation = generation::current(path);
+        let cached = pool
+            .get_with_generation(path, page_index)
+            .filter(|(_, cached_generation)| {
+                let is_stale = *cached_generation != generation;
+                if is_stale {
+                    generation::record_stale_read(path, *cached_generation, generation);
+                }
+                !is_stale
+            });
+        io_stats::record_buffer_pool_lookup(cached.is_some());
+        if let Some((page, _)) = cached {
+            Ok(PageBuffer::from(page))
         } else {
-            let mut file = direct_io::open_read(path)?;
-            let page_bytes = direct_io::read_page(&mut file, page_index)?;
-            pool.insert(path, page_index, &page_bytes);
+            let page_bytes = read_page(Some(&mut *pool))?;
+            //we return what we read rather than the cached frame, a pool too small to keep it can evict it right away
+            pool.insert_with_generation(path, page_index, &page_bytes, generation);
             Ok(page_bytes)
         }
     } else {
-        let mut file = direct_io::open_read(path)?;
-        direct_io::read_page(&mut file, page_index)
+        read_page(None)
     }
 }
 
-pub fn remove_file(path: &str, buffer_pool: Option<&mut BufferPool>) -> io::Result<()> {
+///Page <page_index> of a file written with <layout>. Pages of another size are stitched together from (or cut out of)
+/// the host sized pages that hold their bytes, so the buffer pool only ever caches host sized pages
+#[cfg(feature = "btree")]
+pub fn get_layout_page(
+    storage: &Storage,
+    path: &Path,
+    page_index: Page,
+    layout: PageLayout,
+    mut buffer_pool: Option<&mut BufferPool>,
+) -> io::Result<PageBuffer> {
+    let host_page_size = system_info::page_size();
+    if layout.page_size() == host_page_size {
+        return get_page(storage, path, page_index, buffer_pool);
+    }
+    let start = page_index * layout.page_size();
+    let end = start + layout.page_size();
+    let mut bytes = Vec::with_capacity(layout.page_size());
+    for host_page_index in layout_host_pages(page_index, layout) {
+        let host_page = get_page(storage, path, host_page_index, buffer_pool.as_deref_mut())?;
+        let host_page_start = host_page_index * host_page_size;
+        let from = start.saturating_sub(host_page_start).min(host_page.len());
+        let to = (end - host_page_start).min(host_page.len());
+        bytes.extend_from_slice(&host_page[from..to]);
+        if host_page.len() < host_page_size {
+            break; //end of file
+        }
+    }
+    Ok(PageBuffer::from(bytes))
+}
+
+///Host sized pages that hold the bytes of page <page_index> of a file written with <layout>
+#[cfg(feature = "btree")]
+pub fn layout_host_pages(page_index: Page, layout: PageLayout) -> Range<Page> {
+    let host_page_size = system_info::page_size();
+    let start = page_index * layout.page_size();
+    start / host_page_size..ceil_div!(start + layout.page_size(), host_page_size)
+}
+
+pub fn remove_file(
+    storage: &Storage,
+    path: &Path,
+    buffer_pool: Option<&mut BufferPool>,
+) -> io::Result<()> {
     if let Some(pool) = buffer_pool {
         pool.remove(path)
     }
-    std::fs::remove_file(path)
+    storage.remove_file(path)
 }
 
 pub fn rename_file(
-    old_path: &str,
-    new_path: &str,
+    storage: &Storage,
+    old_path: &Path,
+    new_path: &Path,
     buffer_pool: Option<&mut BufferPool>,
 ) -> io::Result<()> {
     if let Some(pool) = buffer_pool {
         pool.rename(old_path, new_path)
     }
-    std::fs::rename(old_path, new_path)
+    storage.rename(old_path, new_path)
+}
+
+#[cfg(all(test, feature = "buffer-pool"))]
+mod tests {
+    use super::*;
+    use crate::util::{system_info, testing};
+
+    fn write_file(storage: &Storage, path: &Path, byte: u8) {
+        let mut file = storage.create(path).unwrap();
+        direct_io::write_all(&mut file, &page(byte)).unwrap();
+    }
+
+    fn page(byte: u8) -> Vec<u8> {
+        vec![byte; system_info::page_size()]
+    }
+
+    #[test]
+    fn test_stale_page_detected() {
+        let test_dir = testing::test_dir("file_interface_stale_page");
+        let storage = Storage::in_memory();
+        storage.create_dir_all(test_dir).unwrap();
+        let path = test_dir.join("0.sst");
+        let mut pool = BufferPool::new(1, 4 * system_info::page_size());
+
+        write_file(&storage, &path, 1);
+        let get = |pool: &mut BufferPool| get_page(&storage, &path, 0, Some(pool)).unwrap();
+        assert_eq!(get(&mut pool), page(1));
+        //replaced through file_interface, the pool forgets the old pages
+        remove_file(&storage, &path, Some(&mut pool)).unwrap();
+        write_file(&storage, &path, 2);
+        assert_eq!(get(&mut pool), page(2));
+
+        //replaced behind the pool's back, the cached page is from an older generation
+        write_file(&storage, &path, 3);
+        let stale = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| get(&mut pool)));
+        assert!(stale.is_err(), "stale page was served");
+    }
 }
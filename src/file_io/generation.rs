@@ -0,0 +1,97 @@
+//!Modification generations of the database's files. Every time a file is created, opened for writing, renamed or
+//! removed through direct_io it gets a new generation, and buffer pool frames remember the generation of the file
+//! they were read from. A frame whose generation doesn't match its file's is stale: the file was replaced without
+//! the buffer pool being told, it's read again instead of served.
+//! NOTE: stale reads are bugs in cache invalidation, test builds panic on them so they can't go unnoticed
+
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex, MutexGuard,
+    },
+};
+
+pub type Generation = u64;
+
+///Generation of files that were never modified through direct_io (e.g. written by an older process)
+pub const INITIAL_GENERATION: Generation = 0;
+
+static NEXT_GENERATION: AtomicU64 = AtomicU64::new(INITIAL_GENERATION + 1);
+static GENERATIONS: Mutex<Option<HashMap<PathBuf, Generation>>> = Mutex::new(None);
+static STALE_READS: AtomicU64 = AtomicU64::new(0);
+
+fn generations() -> MutexGuard<'static, Option<HashMap<PathBuf, Generation>>> {
+    //a panic while holding the lock can't leave the map half updated, so a poisoned lock is still usable
+    GENERATIONS
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+///Current generation of the file at <path>
+pub fn current(path: &Path) -> Generation {
+    generations()
+        .as_ref()
+        .and_then(|generations| generations.get(path).copied())
+        .unwrap_or(INITIAL_GENERATION)
+}
+
+///The file at <path> changed (or is gone), anything read from it before is stale
+pub fn bump(path: &Path) {
+    let generation = NEXT_GENERATION.fetch_add(1, Ordering::Relaxed);
+    generations()
+        .get_or_insert_with(HashMap::new)
+        .insert(path.to_path_buf(), generation);
+}
+
+///The file at <old_path> was moved to <new_path>, its contents (and pages read from it) are unchanged
+pub fn rename(old_path: &Path, new_path: &Path) {
+    let generation = NEXT_GENERATION.fetch_add(1, Ordering::Relaxed);
+    let mut guard = generations();
+    let generations = guard.get_or_insert_with(HashMap::new);
+    let moved = generations
+        .insert(old_path.to_path_buf(), generation)
+        .unwrap_or(INITIAL_GENERATION);
+    generations.insert(new_path.to_path_buf(), moved);
+}
+
+///Count a page of generation <cached> served for the file at <path>, which is at generation <current>
+pub fn record_stale_read(path: &Path, cached: Generation, current: Generation) {
+    STALE_READS.fetch_add(1, Ordering::Relaxed);
+    if cfg!(test) {
+        panic!(
+            "stale read: cached page of {} is from generation {cached}, the file is at generation {current}",
+            path.display()
+        );
+    }
+}
+
+///Number of stale pages caught (and read again) since the process started
+#[allow(dead_code)]
+pub fn stale_reads() -> u64 {
+    STALE_READS.load(Ordering::Relaxed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_generations() {
+        let path = Path::new("generation_test/0/0.sst");
+        let other = Path::new("generation_test/1/0.sst");
+        let before = current(path);
+        bump(path);
+        let written = current(path);
+        assert_ne!(written, before);
+        assert_eq!(current(path), written);
+
+        //a renamed file keeps its generation, the old path gets a new one
+        rename(path, other);
+        assert_eq!(current(other), written);
+        assert_ne!(current(path), written);
+        bump(other);
+        assert_ne!(current(other), written);
+    }
+}
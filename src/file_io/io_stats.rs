@@ -0,0 +1,133 @@
+//!Counters for the page I/O done on the current thread. Every page read or write goes through direct_io (and every
+//! cached read through file_interface), so counting there covers all of the database's files.
+//! NOTE: counters are per thread, work done by the background compactor is not counted on the database's thread
+
+use std::cell::Cell;
+
+use crate::{ceil_div, util::system_info, util::types::Size};
+
+///Snapshot of I/O counters, see Database::io_stats
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct IoStats {
+    pub pages_read: Size, //pages read from disk, buffer pool hits don't count
+    pub pages_written: Size,
+    pub bloom_probes: Size,    //bloom filter queries
+    pub bloom_negatives: Size, //queries that ruled a run out without searching it
+    pub buffer_pool_hits: Size,
+    pub buffer_pool_misses: Size,
+    pub syncs: Size, //file and directory syncs, see SyncPolicy. In-memory files count although there's nothing to sync
+}
+
+impl IoStats {
+    ///Counts since <baseline> was taken
+    pub fn since(&self, baseline: &IoStats) -> IoStats {
+        IoStats {
+            pages_read: self.pages_read - baseline.pages_read,
+            pages_written: self.pages_written - baseline.pages_written,
+            bloom_probes: self.bloom_probes - baseline.bloom_probes,
+            bloom_negatives: self.bloom_negatives - baseline.bloom_negatives,
+            buffer_pool_hits: self.buffer_pool_hits - baseline.buffer_pool_hits,
+            buffer_pool_misses: self.buffer_pool_misses - baseline.buffer_pool_misses,
+            syncs: self.syncs - baseline.syncs,
+        }
+    }
+    ///Pages read and written
+    #[allow(dead_code)]
+    pub fn pages(&self) -> Size {
+        self.pages_read + self.pages_written
+    }
+}
+
+thread_local! {
+    static COUNTERS: Cell<IoStats> = Cell::new(IoStats::default());
+}
+
+///Counters of the current thread since it started
+pub fn snapshot() -> IoStats {
+    COUNTERS.with(Cell::get)
+}
+
+fn record(update: impl FnOnce(&mut IoStats)) {
+    COUNTERS.with(|counters| {
+        let mut stats = counters.get();
+        update(&mut stats);
+        counters.set(stats);
+    });
+}
+
+///Runs <f> without counting its I/O, for checks that aren't part of the work being measured
+#[cfg_attr(not(any(test, feature = "paranoid-reads")), allow(dead_code))]
+pub fn uncounted<T>(f: impl FnOnce() -> T) -> T {
+    let counters = snapshot();
+    let result = f();
+    COUNTERS.with(|current| current.set(counters));
+    result
+}
+
+///<num_bytes> were read from disk, a partial page counts as a whole one
+pub fn record_read(num_bytes: Size) {
+    record(|stats| stats.pages_read += ceil_div!(num_bytes, system_info::page_size()));
+}
+
+pub fn record_write(num_bytes: Size) {
+    record(|stats| stats.pages_written += ceil_div!(num_bytes, system_info::page_size()));
+}
+
+#[cfg_attr(not(feature = "bloom"), allow(dead_code))]
+pub fn record_bloom_probe(is_negative: bool) {
+    record(|stats| {
+        stats.bloom_probes += 1;
+        stats.bloom_negatives += Size::from(is_negative);
+    });
+}
+
+pub fn record_sync() {
+    record(|stats| stats.syncs += 1);
+}
+
+pub fn record_buffer_pool_lookup(is_hit: bool) {
+    record(|stats| {
+        if is_hit {
+            stats.buffer_pool_hits += 1;
+        } else {
+            stats.buffer_pool_misses += 1;
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_counters() {
+        let baseline = snapshot();
+        let page_size = system_info::page_size();
+        record_read(page_size + 1);
+        record_read(0);
+        record_write(page_size);
+        record_bloom_probe(true);
+        record_bloom_probe(false);
+        record_buffer_pool_lookup(false);
+        record_sync();
+
+        let stats = snapshot().since(&baseline);
+        assert_eq!(
+            stats,
+            IoStats {
+                pages_read: 2,
+                pages_written: 1,
+                bloom_probes: 2,
+                bloom_negatives: 1,
+                buffer_pool_hits: 0,
+                buffer_pool_misses: 1,
+                syncs: 1,
+            }
+        );
+        assert_eq!(stats.pages(), 3);
+
+        //other threads have their own counters
+        std::thread::spawn(|| record_read(1)).join().unwrap();
+        assert_eq!(snapshot().since(&baseline).pages_read, 2);
+    }
+}
@@ -0,0 +1,382 @@
+//!Files of databases kept in memory (see Storage::in_memory), as byte blobs keyed by their path instead of on disk,
+//! with the same semantics as the filesystem (e.g. files can only be created in existing directories, renames replace
+//! their target, open handles keep the contents of a removed file).
+//! NOTE: nothing is durable, files last as long as the MemoryFs holding them. A database closed and opened again with
+//! the same Storage finds its files where it left them
+
+use std::{
+    collections::{HashMap, HashSet},
+    fmt,
+    io::{self, Read, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex, MutexGuard},
+};
+
+use crate::util::types::IoMode;
+
+use super::storage::{Access, DbFile, FileSystem};
+
+type Blob = Arc<Mutex<Vec<u8>>>;
+
+#[derive(Default)]
+struct Files {
+    directories: HashSet<PathBuf>,
+    files: HashMap<PathBuf, Blob>,
+}
+
+impl Files {
+    ///Whether files can be created in <path>'s directory, the current directory (or root) always exists
+    fn parent_exists(&self, path: &Path) -> bool {
+        path.parent().is_some_and(|parent| {
+            parent.as_os_str().is_empty() || self.directories.contains(parent)
+        })
+    }
+    fn open(&mut self, path: &Path, mode: OpenMode) -> io::Result<MemoryFile> {
+        let blob = match (self.files.get(path), mode) {
+            (Some(blob), _) => blob.clone(),
+            (None, OpenMode::Create) if self.parent_exists(path) => {
+                let blob = Blob::default();
+                self.files.insert(path.to_path_buf(), blob.clone());
+                blob
+            }
+            (None, _) => return Err(not_found(path)),
+        };
+        Ok(MemoryFile {
+            blob,
+            position: 0,
+            append: mode == OpenMode::Append,
+        })
+    }
+}
+
+///Files kept in memory, see Storage::in_memory
+#[derive(Default)]
+pub struct MemoryFs {
+    files: Mutex<Files>,
+}
+
+impl fmt::Debug for MemoryFs {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("MemoryFs").finish_non_exhaustive()
+    }
+}
+
+fn lock(blob: &Blob) -> MutexGuard<'_, Vec<u8>> {
+    blob.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+fn not_found(path: &Path) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::NotFound,
+        format!("{} doesn't exist in memory", path.display()),
+    )
+}
+
+///How a file is opened, see Files::open
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum OpenMode {
+    Create,   //created empty if it doesn't exist, its contents are kept otherwise
+    Existing, //fails with NotFound if it doesn't exist
+    Append,   //like Existing, every write goes to the end of the file
+}
+
+impl MemoryFs {
+    fn files(&self) -> MutexGuard<'_, Files> {
+        //every operation leaves the maps consistent before anything that can panic, so a poisoned lock is still usable
+        self.files
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+impl FileSystem for MemoryFs {
+    fn is_in_memory(&self) -> bool {
+        true
+    }
+    fn resolve_io_mode(
+        &self,
+        directory: &Path,
+        _probe_path: &Path,
+        io_mode: IoMode,
+    ) -> io::Result<IoMode> {
+        match io_mode {
+            IoMode::Direct => Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                format!(
+                    "direct I/O isn't supported for {} (files are kept in memory), use IoMode::Auto or \
+                     IoMode::Buffered",
+                    directory.display()
+                ),
+            )),
+            IoMode::Auto | IoMode::Buffered => Ok(IoMode::Buffered),
+        }
+    }
+    fn open(&self, path: &Path, access: Access, _: IoMode) -> io::Result<DbFile> {
+        let mode = match access {
+            Access::Create | Access::CreateBuffered => OpenMode::Create,
+            Access::Append => OpenMode::Append,
+            Access::ReadWrite | Access::Read | Access::Write | Access::ReadBuffered => {
+                OpenMode::Existing
+            }
+        };
+        let file = self.files().open(path, mode)?;
+        if access == Access::CreateBuffered {
+            file.set_len(0)?;
+        }
+        Ok(DbFile::Memory(file))
+    }
+    fn exists(&self, path: &Path) -> bool {
+        let files = self.files();
+        files.files.contains_key(path) || files.directories.contains(path)
+    }
+    fn remove_file(&self, path: &Path) -> io::Result<()> {
+        self.files()
+            .files
+            .remove(path)
+            .map(drop)
+            .ok_or_else(|| not_found(path))
+    }
+    fn rename(&self, old_path: &Path, new_path: &Path) -> io::Result<()> {
+        let mut files = self.files();
+        if !files.parent_exists(new_path) {
+            return Err(not_found(new_path));
+        }
+        let blob = files
+            .files
+            .remove(old_path)
+            .ok_or_else(|| not_found(old_path))?;
+        files.files.insert(new_path.to_path_buf(), blob);
+        Ok(())
+    }
+    ///Like a hard link the contents are shared and outlive the removal (or replacement) of either name
+    fn hard_link(&self, original: &Path, link: &Path) -> io::Result<()> {
+        let mut files = self.files();
+        if !files.parent_exists(link) {
+            return Err(not_found(link));
+        }
+        if files.files.contains_key(link) {
+            return Err(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                format!("{} already exists", link.display()),
+            ));
+        }
+        let blob = files
+            .files
+            .get(original)
+            .ok_or_else(|| not_found(original))?;
+        let blob = Arc::clone(blob);
+        files.files.insert(link.to_path_buf(), blob);
+        Ok(())
+    }
+    fn file_len(&self, path: &Path) -> io::Result<u64> {
+        Ok(self.files().open(path, OpenMode::Existing)?.len_bytes())
+    }
+    fn read(&self, path: &Path) -> io::Result<Vec<u8>> {
+        let mut file = self.files().open(path, OpenMode::Existing)?;
+        let mut bytes = Vec::new();
+        file.read_to_end(&mut bytes)?;
+        Ok(bytes)
+    }
+    ///Nothing to flush, the sync is counted like DbFile::sync_all
+    fn write(&self, path: &Path, bytes: &[u8], sync: bool) -> io::Result<()> {
+        let file = self.files().open(path, OpenMode::Create)?;
+        *lock(&file.blob) = bytes.to_vec();
+        match sync {
+            true => DbFile::Memory(file).sync_all(),
+            false => Ok(()),
+        }
+    }
+    fn sync_directory(&self, _directory: &Path) -> io::Result<()> {
+        Ok(())
+    }
+    fn create_dir_all(&self, path: &Path) -> io::Result<()> {
+        let mut files = self.files();
+        for directory in path.ancestors() {
+            if directory.as_os_str().is_empty() {
+                continue;
+            }
+            if files.files.contains_key(directory) {
+                return Err(io::Error::new(
+                    io::ErrorKind::AlreadyExists,
+                    format!("{} is a file", directory.display()),
+                ));
+            }
+            files.directories.insert(directory.to_path_buf());
+        }
+        Ok(())
+    }
+    fn remove_dir_all(&self, path: &Path) -> io::Result<()> {
+        let mut files = self.files();
+        if !files.directories.contains(path) {
+            return Err(not_found(path));
+        }
+        files
+            .files
+            .retain(|file_path, _| !file_path.starts_with(path));
+        files
+            .directories
+            .retain(|directory| !directory.starts_with(path));
+        Ok(())
+    }
+    fn read_dir(&self, path: &Path) -> io::Result<Vec<PathBuf>> {
+        let files = self.files();
+        if !files.directories.contains(path) {
+            return Err(not_found(path));
+        }
+        let is_child = |child: &&PathBuf| child.parent() == Some(path);
+        Ok(files
+            .files
+            .keys()
+            .filter(is_child)
+            .chain(files.directories.iter().filter(is_child))
+            .cloned()
+            .collect())
+    }
+}
+
+///Open handle to an in-memory file, with its own position like a file descriptor
+#[derive(Debug)]
+pub struct MemoryFile {
+    blob: Blob,
+    position: usize,
+    append: bool,
+}
+
+impl MemoryFile {
+    ///Truncate or zero extend the file to <len> bytes, the position doesn't move
+    pub fn set_len(&self, len: u64) -> io::Result<()> {
+        lock(&self.blob).resize(len as usize, 0);
+        Ok(())
+    }
+    pub fn len_bytes(&self) -> u64 {
+        lock(&self.blob).len() as u64
+    }
+}
+
+impl Read for MemoryFile {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let bytes = lock(&self.blob);
+        let start = self.position.min(bytes.len());
+        let len = buf.len().min(bytes.len() - start);
+        buf[..len].copy_from_slice(&bytes[start..start + len]);
+        self.position += len;
+        Ok(len)
+    }
+}
+
+impl Write for MemoryFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut bytes = lock(&self.blob);
+        if self.append {
+            self.position = bytes.len();
+        }
+        let end = self.position + buf.len();
+        if bytes.len() < end {
+            bytes.resize(end, 0); //writing past the end leaves a zeroed gap, like a sparse file
+        }
+        bytes[self.position..end].copy_from_slice(buf);
+        self.position = end;
+        Ok(buf.len())
+    }
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Seek for MemoryFile {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let (base, offset) = match pos {
+            SeekFrom::Start(offset) => (0, offset as i64),
+            SeekFrom::End(offset) => (self.len_bytes() as i64, offset),
+            SeekFrom::Current(offset) => (self.position as i64, offset),
+        };
+        let position = base.checked_add(offset).filter(|position| *position >= 0);
+        let Some(position) = position else {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "seek to a negative position",
+            ));
+        };
+        self.position = position as usize;
+        Ok(position as u64)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn read(fs: &MemoryFs, path: &Path) -> Vec<u8> {
+        fs.read(path).unwrap()
+    }
+
+    #[test]
+    fn test_files() {
+        let fs = MemoryFs::default();
+        let root = Path::new("memory_fs_test_files");
+        let level = root.join("0");
+        let path = level.join("0.sst");
+        assert_eq!(
+            fs.open(&path, Access::Create, IoMode::Auto)
+                .err()
+                .unwrap()
+                .kind(),
+            io::ErrorKind::NotFound
+        );
+
+        fs.create_dir_all(&level).unwrap();
+        assert!(fs.exists(root) && fs.exists(&level) && !fs.exists(&path));
+        let mut file = fs.open(&path, Access::Create, IoMode::Auto).unwrap();
+        file.write_all(b"hello world").unwrap();
+        file.set_len(5).unwrap();
+        assert_eq!(read(&fs, &path), b"hello");
+
+        //handles have their own position and see each other's writes
+        let mut reader = fs.open(&path, Access::Read, IoMode::Auto).unwrap();
+        reader.seek(SeekFrom::Start(1)).unwrap();
+        file.seek(SeekFrom::End(0)).unwrap();
+        file.write_all(b"!").unwrap();
+        let mut bytes = Vec::new();
+        reader.read_to_end(&mut bytes).unwrap();
+        assert_eq!(bytes, b"ello!");
+
+        let mut appender = fs.open(&path, Access::Append, IoMode::Auto).unwrap();
+        appender.write_all(b"?").unwrap();
+        assert_eq!(read(&fs, &path), b"hello!?");
+
+        let new_path = level.join("1.sst");
+        fs.write(&new_path, b"old", false).unwrap();
+        fs.rename(&path, &new_path).unwrap();
+        assert!(!fs.exists(&path));
+        assert_eq!(read(&fs, &new_path), b"hello!?");
+        assert_eq!(fs.read_dir(root).unwrap(), vec![level.clone()]);
+        assert_eq!(fs.read_dir(&level).unwrap(), vec![new_path.clone()]);
+
+        //a link outlives the name it was made from, and keeps its contents when that name is replaced
+        let link = root.join("link.sst");
+        fs.hard_link(&new_path, &link).unwrap();
+        assert_eq!(
+            fs.hard_link(&new_path, &link).unwrap_err().kind(),
+            io::ErrorKind::AlreadyExists
+        );
+        fs.write(&level.join("2.sst"), b"new", false).unwrap();
+        fs.rename(&level.join("2.sst"), &new_path).unwrap();
+        assert_eq!(read(&fs, &link), b"hello!?");
+        fs.remove_file(&link).unwrap();
+
+        fs.remove_file(&new_path).unwrap();
+        assert_eq!(
+            fs.remove_file(&new_path).unwrap_err().kind(),
+            io::ErrorKind::NotFound
+        );
+        //an open handle keeps the contents of a removed file
+        assert_eq!(reader.len_bytes().unwrap(), 7);
+
+        fs.remove_dir_all(root).unwrap();
+        assert!(!fs.exists(root));
+        //another file system doesn't see the files of this one
+        fs.create_dir_all(&level).unwrap();
+        fs.write(&path, b"mine", false).unwrap();
+        assert!(!MemoryFs::default().exists(&path));
+    }
+}
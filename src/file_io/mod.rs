@@ -3,10 +3,24 @@ pub mod direct_io;
 
 pub mod file_interface;
 
+pub mod generation;
+
+pub mod io_stats;
+
+pub mod memory_fs;
+
+pub mod page_buffers;
+
 #[allow(dead_code)]
 pub mod serde_entry;
 
+#[cfg(feature = "btree")]
 #[allow(dead_code)]
 pub mod serde_btree;
 
+#[allow(dead_code)]
+pub mod serde_state;
+
+pub mod storage;
+
 pub mod serde_util;
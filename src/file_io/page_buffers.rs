@@ -0,0 +1,188 @@
+//!Free list of the page aligned buffers direct_io reads and writes through. Every uncached page read (and every page a
+//! compaction reads or writes) needs one for a moment, taking it from the free list instead of the allocator saves an
+//! allocation and a free per page. Buffers go back to the list when their PageBuffer is dropped.
+//! NOTE: the list is per thread, like io_stats, so taking and returning buffers never waits on a lock
+
+use std::{
+    cell::{Cell, RefCell},
+    ops::{Deref, DerefMut, Range},
+};
+
+use crate::util::{system_info, types::Size};
+
+///Most buffers kept on a thread's free list, the rest are freed
+const MAX_FREE_BUFFERS: usize = 8;
+///Largest buffer (in pages) kept on the free list, bigger ones (e.g. whole bloom filters) are freed
+const MAX_FREE_BUFFER_PAGES: usize = 16;
+
+///Counters of the current thread's free list, see stats
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct PageBufferStats {
+    pub allocations: Size, //buffers taken from the allocator
+    pub reuses: Size,      //buffers taken from the free list
+}
+
+impl PageBufferStats {
+    ///Counts since <baseline> was taken
+    pub fn since(&self, baseline: &PageBufferStats) -> PageBufferStats {
+        PageBufferStats {
+            allocations: self.allocations - baseline.allocations,
+            reuses: self.reuses - baseline.reuses,
+        }
+    }
+}
+
+thread_local! {
+    static FREE_BUFFERS: RefCell<Vec<Vec<u8>>> = const { RefCell::new(Vec::new()) };
+    static STATS: Cell<PageBufferStats> = Cell::new(PageBufferStats::default());
+}
+
+///Counters of the current thread since it started
+pub fn stats() -> PageBufferStats {
+    STATS.with(Cell::get)
+}
+
+fn record(update: impl FnOnce(&mut PageBufferStats)) {
+    STATS.with(|counters| {
+        let mut stats = counters.get();
+        update(&mut stats);
+        counters.set(stats);
+    });
+}
+
+///Bytes of a page (or of several), starting at a page aligned address when taken from the free list. Derefs to the
+/// bytes, its allocation goes back to the free list when it's dropped
+pub struct PageBuffer {
+    buffer: Vec<u8>,
+    offset: usize, //index of the first aligned byte
+    len: usize,
+}
+
+impl PageBuffer {
+    ///Buffer with room for <len> bytes, from the free list if one there is large enough. Its bytes are whatever the
+    /// buffer held last (zeroes if it's new), readers and writers overwrite the ones they use
+    pub fn take(len: usize) -> Self {
+        let alignment = system_info::page_size();
+        let reused = FREE_BUFFERS.with(|free_buffers| {
+            let mut free_buffers = free_buffers.borrow_mut();
+            let index = free_buffers
+                .iter()
+                .position(|buffer| buffer.len() >= len + alignment)?;
+            Some(free_buffers.swap_remove(index))
+        });
+        record(|stats| match reused.is_some() {
+            true => stats.reuses += 1,
+            false => stats.allocations += 1,
+        });
+        let buffer = reused.unwrap_or_else(|| vec![0; len + alignment]);
+        let offset = buffer.as_ptr().align_offset(alignment);
+        Self {
+            buffer,
+            offset,
+            len,
+        }
+    }
+    ///Keep only the bytes in <range> (cut short at the end of the buffer), e.g. the ones a read filled
+    pub fn retain(&mut self, range: Range<usize>) {
+        let start = range.start.min(self.len);
+        self.offset += start;
+        self.len = range.end.clamp(start, self.len) - start;
+    }
+}
+
+///Bytes that are already in memory (e.g. copied out of the buffer pool) as they are, not aligned
+impl From<Vec<u8>> for PageBuffer {
+    fn from(buffer: Vec<u8>) -> Self {
+        let len = buffer.len();
+        Self {
+            buffer,
+            offset: 0,
+            len,
+        }
+    }
+}
+
+impl Deref for PageBuffer {
+    type Target = [u8];
+    fn deref(&self) -> &[u8] {
+        &self.buffer[self.offset..self.offset + self.len]
+    }
+}
+
+impl DerefMut for PageBuffer {
+    fn deref_mut(&mut self) -> &mut [u8] {
+        &mut self.buffer[self.offset..self.offset + self.len]
+    }
+}
+
+impl Drop for PageBuffer {
+    fn drop(&mut self) {
+        let buffer = std::mem::take(&mut self.buffer);
+        let page_size = system_info::page_size();
+        //a buffer without room to align a page in it could never be taken again
+        if buffer.len() <= page_size || buffer.len() > (MAX_FREE_BUFFER_PAGES + 1) * page_size {
+            return;
+        }
+        //the thread's free list is gone if it's exiting, the buffer is just freed then
+        let _ = FREE_BUFFERS.try_with(|free_buffers| {
+            let mut free_buffers = free_buffers.borrow_mut();
+            if free_buffers.len() < MAX_FREE_BUFFERS {
+                free_buffers.push(buffer);
+            }
+        });
+    }
+}
+
+impl std::fmt::Debug for PageBuffer {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        (**self).fmt(f)
+    }
+}
+
+impl PartialEq<Vec<u8>> for PageBuffer {
+    fn eq(&self, other: &Vec<u8>) -> bool {
+        **self == other[..]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_reuse() {
+        let page_size = system_info::page_size();
+        let baseline = stats();
+        let mut buffer = PageBuffer::take(page_size);
+        assert_eq!(buffer.as_ptr() as usize % page_size, 0);
+        assert_eq!(buffer.len(), page_size);
+        buffer[0] = 7;
+        buffer[1] = 8;
+        buffer.retain(1..3);
+        assert_eq!(*buffer, [8, 0]);
+        buffer.retain(1..page_size);
+        assert_eq!(*buffer, [0]);
+        drop(buffer);
+
+        //the same allocation comes back, for a smaller length too
+        let buffer = PageBuffer::take(page_size / 2);
+        assert_eq!(buffer.as_ptr() as usize % page_size, 0);
+        assert_eq!(buffer[0], 7);
+        let second = PageBuffer::take(page_size);
+        assert_eq!(
+            stats().since(&baseline),
+            PageBufferStats {
+                allocations: 2,
+                reuses: 1,
+            }
+        );
+        drop((buffer, second));
+
+        //buffers too small or too large to keep aren't reused
+        drop(PageBuffer::from(vec![0; page_size]));
+        drop(PageBuffer::take((MAX_FREE_BUFFER_PAGES + 1) * page_size));
+        let baseline = stats();
+        drop(PageBuffer::take((MAX_FREE_BUFFER_PAGES + 1) * page_size));
+        assert_eq!(stats().since(&baseline).allocations, 1);
+    }
+}
@@ -1,24 +1,24 @@
 use std::io;
 
-use crate::util::{
-    btree_info::{fanout, node_size, NODE_ELEMENT_SIZE},
-    types::Key,
-};
-
-///Returns a little endian buffer representation of B-tree inner node. NOTE: this function writes min(fanout, length) - 1 elements. Ie, this function assumes that the last element is not actually meant to be included in this node
-/// The extra space (where last element would be) is used for metadata (# elements in node)
-pub fn serialize(node_elements: &[Key]) -> Vec<u8> {
-    let mut buffer: Vec<u8> = Vec::with_capacity(node_size()); //capacity to prevent reallcation on push
-    let node_element_chunk = node_elements.iter().take(fanout()); //Elements needed for B-tree inner node
+use crate::util::{btree_info::NODE_ELEMENT_SIZE, system_info::PageLayout, types::Key};
+
+use super::{direct_io, serde_entry::KeyCodec};
+
+///Returns a buffer representation of B-tree inner node (delimiters encoded with <codec>). NOTE: this function writes min(fanout, length) - 1 elements. Ie, this function assumes that the last element is not actually meant to be included in this node
+/// The extra space (where last element would be) is used for metadata (# elements in node). Nodes are sized by <layout>
+pub fn serialize(node_elements: &[Key], layout: PageLayout, codec: KeyCodec) -> Vec<u8> {
+    let node_size = layout.node_size();
+    let mut buffer: Vec<u8> = Vec::with_capacity(node_size); //capacity to prevent reallcation on push
+    let node_element_chunk = node_elements.iter().take(layout.fanout()); //Elements needed for B-tree inner node
     let node_len = node_element_chunk.len() - 1;
     for key in node_element_chunk.take(node_len) {
-        let key_bytes = key.to_le_bytes();
+        let key_bytes = codec.encode(*key);
         buffer.extend_from_slice(&key_bytes);
     }
 
     //fill up remaining space with zeros
     let metadata_size = std::mem::size_of_val(&node_len);
-    buffer.resize(node_size() - metadata_size, 0);
+    buffer.resize(node_size - metadata_size, 0);
 
     //write metadata at end of buffer
     let node_len_bytes = node_len.to_le_bytes();
@@ -27,19 +27,27 @@ pub fn serialize(node_elements: &[Key]) -> Vec<u8> {
     buffer
 }
 
-pub fn serialize_into(writer: &mut dyn std::io::Write, node_elements: &[Key]) -> io::Result<()> {
-    let buffer = serialize(node_elements);
+pub fn serialize_into(
+    writer: &mut dyn std::io::Write,
+    node_elements: &[Key],
+    layout: PageLayout,
+    codec: KeyCodec,
+) -> io::Result<()> {
+    let buffer = serialize(node_elements, layout, codec);
     //Direct IO requires that we write some multiple of a minimum write size
     //buffer should always be the size of a page (node_size), so this should be okay as is
-    writer.write_all(&buffer)?;
+    direct_io::write_all(writer, &buffer)?;
     Ok(())
 }
 
 ///Deserializes initial bytes of buffer into key-node_index pair.
 /// NOTE: the buffer must be large enough to fit a key and node index type. Following bytes afterwards are ignored
-pub fn deserialize_element(buffer: &[u8]) -> Result<Key, std::array::TryFromSliceError> {
+pub fn deserialize_element(
+    buffer: &[u8],
+    codec: KeyCodec,
+) -> Result<Key, std::array::TryFromSliceError> {
     let key_bytes: [u8; std::mem::size_of::<Key>()] = buffer.try_into()?;
-    let key = Key::from_le_bytes(key_bytes);
+    let key = codec.decode(key_bytes);
 
     Ok(key)
 }
@@ -50,9 +58,9 @@ pub fn deserialize_node_metadata(buffer: &[u8]) -> Result<usize, std::array::Try
     Ok(usize::from_le_bytes(num_elements_bytes))
 }
 
-///Deserializes entire buffer
-pub fn deserialize(buffer: &[u8]) -> Result<Vec<Key>, String> {
-    let node_size = node_size();
+///Deserializes entire buffer, a node of a run written with <layout>
+pub fn deserialize(buffer: &[u8], layout: PageLayout, codec: KeyCodec) -> Result<Vec<Key>, String> {
+    let node_size = layout.node_size();
 
     //make sure buffer has correct number of bytes to deserialize, should be a multiple of entry_size/byte_size
     if node_size != buffer.len() {
@@ -70,7 +78,7 @@ pub fn deserialize(buffer: &[u8]) -> Result<Vec<Key>, String> {
     //loop over groupings of bytes (size of an node element), convert them to key and node_index tuple, add to our vec
     //NOTE: we only loop over the amount of grouping specified in the metadata, otherwise we'll read in data that is not valid
     for byte_chunk in buffer.chunks(NODE_ELEMENT_SIZE).take(num_entries) {
-        let entry = deserialize_element(byte_chunk).unwrap(); //NOTE: can unwrap because of the check at the start of the fn
+        let entry = deserialize_element(byte_chunk, codec).unwrap(); //NOTE: can unwrap because of the check at the start of the fn
         node_elements.push(entry);
     }
     Ok(node_elements)
@@ -79,19 +87,36 @@ pub fn deserialize(buffer: &[u8]) -> Result<Vec<Key>, String> {
 #[test]
 fn test_serde() {
     let entries: [Key; 3] = [2, -23, 12353242346];
-    let buffer = serialize(&entries);
-    assert_eq!(buffer.len(), node_size());
-    let entries_deserialized = deserialize(&buffer).unwrap();
+    for codec in [KeyCodec::LittleEndian, KeyCodec::OrderPreserving] {
+        let buffer = serialize(&entries, PageLayout::host(), codec);
+        assert_eq!(buffer.len(), PageLayout::host().node_size());
+        let entries_deserialized = deserialize(&buffer, PageLayout::host(), codec).unwrap();
 
-    assert_eq!(entries[..2].to_vec(), entries_deserialized);
+        assert_eq!(entries[..2].to_vec(), entries_deserialized);
+    }
 }
 
 #[test]
 fn test_serde_large() {
     let entries: Vec<Key> = (-1000..1000).collect();
-    let buffer = serialize(&entries);
-    assert_eq!(buffer.len(), node_size());
-    let entries_deserialized = deserialize(&buffer).unwrap();
-
-    assert_eq!(entries[..fanout() - 1].to_vec(), entries_deserialized);
+    let layout = PageLayout::host();
+    let buffer = serialize(&entries, layout, KeyCodec::default());
+    assert_eq!(buffer.len(), layout.node_size());
+    let entries_deserialized = deserialize(&buffer, layout, KeyCodec::default()).unwrap();
+
+    assert_eq!(
+        entries[..layout.fanout() - 1].to_vec(),
+        entries_deserialized
+    );
+
+    //nodes of a run written with a smaller page size hold fewer delimiters
+    let small_layout = PageLayout::new(layout.page_size() / 2);
+    let buffer = serialize(&entries, small_layout, KeyCodec::default());
+    assert_eq!(buffer.len(), small_layout.node_size());
+    assert!(deserialize(&buffer, layout, KeyCodec::default()).is_err());
+    let entries_deserialized = deserialize(&buffer, small_layout, KeyCodec::default()).unwrap();
+    assert_eq!(
+        entries[..small_layout.fanout() - 1].to_vec(),
+        entries_deserialized
+    );
 }
@@ -1,18 +1,54 @@
 use std::io;
-use std::{fs::File, io::Write};
+
+#[cfg(feature = "serde-config")]
+use serde::{Deserialize, Serialize};
 
 use crate::util::system_info::ENTRY_SIZE;
 use crate::util::types::{Entry, Key, Value};
 
-use super::direct_io::read_page;
-use super::serde_util::nearest_min_write_size_multiple;
+use super::serde_util::{nearest_min_write_size_multiple, write_len};
+use super::{
+    direct_io::{self, read_page},
+    storage::DbFile,
+};
+
+pub const KEY_SIZE: usize = std::mem::size_of::<Key>();
+
+///Byte representation of keys on disk (SST entries and B-tree delimiters), also the bytes that bloom filters hash
+#[cfg_attr(feature = "serde-config", derive(Serialize, Deserialize))]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum KeyCodec {
+    ///Little endian two's complement, the original format
+    #[default]
+    LittleEndian,
+    ///Big endian with the sign bit flipped, so comparing encoded keys byte by byte gives the same order as comparing keys
+    OrderPreserving,
+}
+
+impl KeyCodec {
+    const SIGN_BIT: u64 = 1 << 63;
+
+    pub fn encode(&self, key: Key) -> [u8; KEY_SIZE] {
+        match self {
+            KeyCodec::LittleEndian => key.to_le_bytes(),
+            KeyCodec::OrderPreserving => ((key as u64) ^ Self::SIGN_BIT).to_be_bytes(),
+        }
+    }
 
-///Returns a little endian buffer representation of entry array
-pub fn serialize(entries: &[Entry]) -> Vec<u8> {
+    pub fn decode(&self, bytes: [u8; KEY_SIZE]) -> Key {
+        match self {
+            KeyCodec::LittleEndian => Key::from_le_bytes(bytes),
+            KeyCodec::OrderPreserving => (u64::from_be_bytes(bytes) ^ Self::SIGN_BIT) as Key,
+        }
+    }
+}
+
+///Returns a buffer representation of entry array (keys encoded with <codec>, values little endian)
+pub fn serialize(entries: &[Entry], codec: KeyCodec) -> Vec<u8> {
     let buffer_size = entries.len() * ENTRY_SIZE;
     let mut buffer: Vec<u8> = Vec::with_capacity(buffer_size); //capacity to prevent reallcation on push
     for (key, value) in entries {
-        let key_bytes = key.to_le_bytes();
+        let key_bytes = codec.encode(*key);
         let value_bytes = value.to_le_bytes();
         buffer.extend_from_slice(&key_bytes);
         buffer.extend_from_slice(&value_bytes);
@@ -20,38 +56,45 @@ pub fn serialize(entries: &[Entry]) -> Vec<u8> {
     buffer
 }
 
-pub fn serialize_into_no_resize(writer: &mut File, entries: &[Entry]) -> io::Result<usize> {
-    let mut buffer = serialize(entries);
+pub fn serialize_into_no_resize(
+    writer: &mut DbFile,
+    entries: &[Entry],
+    codec: KeyCodec,
+) -> io::Result<usize> {
+    let mut buffer = serialize(entries, codec);
     let buffer_len = buffer.len();
     //Direct IO requires that we write some multiple of a minimum write size
     //we will use the page size (mimimum write size is smaller for some machines, mine is 512 bytes for example),
     //and then resize the file to be the actual number of bytes written
-    buffer.resize(nearest_min_write_size_multiple(buffer_len), 0);
-    writer.write_all(&buffer)?;
+    buffer.resize(write_len(writer, buffer_len), 0);
+    direct_io::write_all(writer, &buffer)?;
     Ok(buffer_len)
 }
 
-pub fn serialize_into(writer: &mut File, entries: &[Entry]) -> io::Result<()> {
-    let buffer_len = serialize_into_no_resize(writer, entries)?;
+pub fn serialize_into(writer: &mut DbFile, entries: &[Entry], codec: KeyCodec) -> io::Result<()> {
+    let buffer_len = serialize_into_no_resize(writer, entries, codec)?;
     writer.set_len(buffer_len as u64)
 }
 
 ///Deserializes initial bytes of buffer into key-value pair.
 /// NOTE: the buffer must be large enough to fit a key and value type. Following bytes afterwards are ignored
-pub fn deserialize_entry(buffer: &[u8]) -> Result<Entry, std::array::TryFromSliceError> {
-    let (key_slice, value_slice) = buffer.split_at(std::mem::size_of::<Key>());
+pub fn deserialize_entry(
+    buffer: &[u8],
+    codec: KeyCodec,
+) -> Result<Entry, std::array::TryFromSliceError> {
+    let (key_slice, value_slice) = buffer.split_at(KEY_SIZE);
 
-    let key_bytes: [u8; std::mem::size_of::<Key>()] = key_slice.try_into()?;
+    let key_bytes: [u8; KEY_SIZE] = key_slice.try_into()?;
     let value_bytes: [u8; std::mem::size_of::<Value>()] = value_slice.try_into()?;
 
-    let key = Key::from_le_bytes(key_bytes);
+    let key = codec.decode(key_bytes);
     let value = Value::from_le_bytes(value_bytes);
 
     Ok((key, value))
 }
 
 ///Deserializes entire buffer
-pub fn deserialize(buffer: &[u8]) -> Result<Vec<Entry>, String> {
+pub fn deserialize(buffer: &[u8], codec: KeyCodec) -> Result<Vec<Entry>, String> {
     let num_entries = buffer.len() / ENTRY_SIZE;
 
     //make sure buffer has correct number of bytes to deserialize, should be a multiple of entry_size/byte_size
@@ -67,7 +110,7 @@ pub fn deserialize(buffer: &[u8]) -> Result<Vec<Entry>, String> {
 
     //loop over groupings of bytes (size of an entry), convert them to key and value tuple, add to our vec
     for byte_chunk in buffer.chunks(ENTRY_SIZE) {
-        let entry = deserialize_entry(byte_chunk).unwrap(); //NOTE: can unwrap because of the check at the start of the fn
+        let entry = deserialize_entry(byte_chunk, codec).unwrap(); //NOTE: can unwrap because of the check at the start of the fn
         entries.push(entry);
     }
     Ok(entries)
@@ -77,20 +120,21 @@ pub fn deserialize(buffer: &[u8]) -> Result<Vec<Entry>, String> {
 pub fn buffered_deserialize_from(
     reader: &mut impl std::io::Read,
     buffer_size: usize,
+    codec: KeyCodec,
 ) -> io::Result<Vec<Entry>> {
     if buffer_size == 0 {
         return Ok(vec![]);
     }
-    let mut buffer: Vec<u8> = vec![0; buffer_size];
-    let bytes_read = reader.read(&mut buffer)?;
-    Ok(deserialize(&buffer[..bytes_read]).unwrap()) //NOTE: can unwrap because buffer size determines if we get an error, which we control
+    let buffer = direct_io::read(reader, buffer_size)?;
+    Ok(deserialize(&buffer, codec).unwrap()) //NOTE: can unwrap because buffer size determines if we get an error, which we control
 }
 
 ///deserializes entire file
-pub fn deserialize_from(reader: &mut File) -> io::Result<Vec<Entry>> {
+pub fn deserialize_from(reader: &mut DbFile, codec: KeyCodec) -> io::Result<Vec<Entry>> {
     buffered_deserialize_from(
         reader,
-        nearest_min_write_size_multiple(reader.metadata()?.len() as usize),
+        nearest_min_write_size_multiple(reader.len_bytes()? as usize),
+        codec,
     )
 }
 
@@ -98,33 +142,222 @@ pub fn deserialize_from(reader: &mut File) -> io::Result<Vec<Entry>> {
 pub fn deserialize_page(
     reader: &mut (impl std::io::Read + std::io::Seek),
     page_index: usize,
+    codec: KeyCodec,
 ) -> io::Result<Vec<Entry>> {
     let buffer = read_page(reader, page_index)?;
-    deserialize(&buffer)
+    deserialize(&buffer, codec)
         .map_err(|why| panic!("Failed to deserialize page {page_index}, reason: {why}"))
 }
 
-///deserialize a single entry within a page. NOTE: entry index is equal to the index it would have if it were in a (key,value) tuple array
+///deserialize a single entry within a page. NOTE: entry index is equal to the index it would have if it were in a (key,value) tuple array.
+/// Fails with InvalidData if the page is too short to hold the entry, e.g. the last page of a run whose entry count
+/// is larger than its file
 pub fn deserialize_entry_within_page(
     buffer: &[u8],
     entry_index: usize,
-) -> Result<Entry, std::array::TryFromSliceError> {
-    if buffer.len() < (entry_index + 1) * ENTRY_SIZE {
-        panic!(
-            "Entry is outside of buffer, buffer size: {}, entry offset: {}",
-            buffer.len(),
-            entry_index * ENTRY_SIZE
-        );
+    codec: KeyCodec,
+) -> io::Result<Entry> {
+    let bytes = entry_bytes_within_page(buffer, entry_index)?;
+    deserialize_entry(bytes, codec).map_err(|why| io::Error::new(io::ErrorKind::InvalidData, why))
+}
+
+///deserialize_key_within_page, or InvalidData if the page is too short to hold the entry (see
+/// deserialize_entry_within_page)
+pub fn try_deserialize_key_within_page(
+    buffer: &[u8],
+    entry_index: usize,
+    codec: KeyCodec,
+) -> io::Result<Key> {
+    let bytes = entry_bytes_within_page(buffer, entry_index)?;
+    let key_bytes: [u8; KEY_SIZE] = bytes[..KEY_SIZE].try_into().unwrap();
+    Ok(codec.decode(key_bytes))
+}
+
+fn entry_bytes_within_page(buffer: &[u8], entry_index: usize) -> io::Result<&[u8]> {
+    let index = entry_index * ENTRY_SIZE; //index within buffer
+    buffer.get(index..index + ENTRY_SIZE).ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!(
+                "entry {entry_index} is outside of the page, which holds {} bytes",
+                buffer.len()
+            ),
+        )
+    })
+}
+
+///Read and deserialize entry <entry_index> of a file of entries, reading only the block that holds it (see
+/// direct_io::read_aligned). Fails with InvalidData if the file ends before the entry does
+pub fn deserialize_entry_at(
+    reader: &mut (impl std::io::Read + std::io::Seek),
+    entry_index: usize,
+    codec: KeyCodec,
+) -> io::Result<Entry> {
+    let bytes = direct_io::read_aligned(reader, (entry_index * ENTRY_SIZE) as u64, ENTRY_SIZE)?;
+    if bytes.len() < ENTRY_SIZE {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("entry {entry_index} is past the end of the file"),
+        ));
     }
+    deserialize_entry(&bytes, codec).map_err(|why| io::Error::new(io::ErrorKind::InvalidData, why))
+}
+
+///Key of a single entry within a page, its value isn't decoded (see deserialize_entry_within_page)
+pub fn deserialize_key_within_page(buffer: &[u8], entry_index: usize, codec: KeyCodec) -> Key {
     let index = entry_index * ENTRY_SIZE; //index within buffer
-    deserialize_entry(&buffer[index..index + ENTRY_SIZE])
+    let key_bytes: [u8; KEY_SIZE] = buffer[index..index + KEY_SIZE]
+        .try_into()
+        .expect("Entry is outside of buffer");
+    codec.decode(key_bytes)
+}
+
+///Keys of the entries in <buffer> (a whole number of entries), skipping the entries whose value is <tombstone>. The
+/// bytes of each value are only compared with the tombstone's, no value is decoded
+pub fn deserialize_keys(buffer: &[u8], codec: KeyCodec, tombstone: Value) -> Vec<Key> {
+    let tombstone = tombstone.to_le_bytes();
+    buffer
+        .chunks_exact(ENTRY_SIZE)
+        .filter(|entry| entry[KEY_SIZE..] != tombstone)
+        .map(|entry| {
+            codec.decode(
+                entry[..KEY_SIZE]
+                    .try_into()
+                    .expect("Entries start with a key"),
+            )
+        })
+        .collect()
+}
+
+///Value of a single entry within a page, its key isn't decoded (see deserialize_entry_within_page)
+pub fn deserialize_value_within_page(buffer: &[u8], entry_index: usize) -> Value {
+    let index = entry_index * ENTRY_SIZE + KEY_SIZE; //index within buffer
+    let value_bytes: [u8; std::mem::size_of::<Value>()] = buffer
+        [index..index + ENTRY_SIZE - KEY_SIZE]
+        .try_into()
+        .expect("Entry is outside of buffer");
+    Value::from_le_bytes(value_bytes)
 }
 
 #[test]
 fn test_serde() {
     let entries: [Entry; 3] = [(2, 1), (-23, 323), (12353242346, -21312345434)];
-    let buffer = serialize(&entries);
-    let entries_deserialized = deserialize(&buffer).unwrap();
+    for codec in [KeyCodec::LittleEndian, KeyCodec::OrderPreserving] {
+        let buffer = serialize(&entries, codec);
+        let entries_deserialized = deserialize(&buffer, codec).unwrap();
+
+        assert_eq!(Vec::from(entries), entries_deserialized);
+        for (index, (key, value)) in entries.iter().enumerate() {
+            assert_eq!(deserialize_key_within_page(&buffer, index, codec), *key);
+            assert_eq!(deserialize_value_within_page(&buffer, index), *value);
+            assert_eq!(
+                try_deserialize_key_within_page(&buffer, index, codec).unwrap(),
+                *key
+            );
+        }
+        let why = try_deserialize_key_within_page(&buffer, entries.len(), codec).unwrap_err();
+        assert_eq!(why.kind(), io::ErrorKind::InvalidData);
+    }
+}
+
+#[test]
+fn test_deserialize_keys() {
+    let tombstone = Value::MIN;
+    let entries: [Entry; 5] = [
+        (-7, 1),
+        (2, tombstone),
+        (3, tombstone + 1),
+        (9, 0),
+        (Key::MAX, tombstone),
+    ];
+    for codec in [KeyCodec::LittleEndian, KeyCodec::OrderPreserving] {
+        let buffer = serialize(&entries, codec);
+        assert_eq!(deserialize_keys(&buffer, codec, tombstone), vec![-7, 3, 9]);
+        assert_eq!(
+            deserialize_keys(&buffer[ENTRY_SIZE..3 * ENTRY_SIZE], codec, tombstone),
+            vec![3]
+        );
+        assert_eq!(deserialize_keys(&[], codec, tombstone), vec![]);
+    }
+}
+
+#[test]
+fn test_deserialize_entry_at() {
+    use crate::util::system_info::{mimimum_write_size, num_entries_per_page};
+
+    //the last page is partial and its last entries are in a partial block
+    let num_entries = 2 * num_entries_per_page() + mimimum_write_size() / ENTRY_SIZE + 5;
+    let entries: Vec<Entry> = (0..num_entries as Key).map(|key| (key * 3, -key)).collect();
+    for codec in [KeyCodec::LittleEndian, KeyCodec::OrderPreserving] {
+        let buffer = serialize(&entries, codec);
+        let mut reader = io::Cursor::new(&buffer);
+        for (index, entry) in entries.iter().enumerate() {
+            assert_eq!(
+                deserialize_entry_at(&mut reader, index, codec).unwrap(),
+                *entry
+            );
+            //the same entry as a whole page read gives
+            let page = read_page(&mut reader, index / num_entries_per_page()).unwrap();
+            let within_page = index % num_entries_per_page();
+            assert_eq!(
+                deserialize_entry_within_page(&page, within_page, codec).unwrap(),
+                *entry
+            );
+        }
+        let why = deserialize_entry_at(&mut reader, num_entries, codec).unwrap_err();
+        assert_eq!(why.kind(), io::ErrorKind::InvalidData);
+
+        //ranges that aren't aligned, or run past the end of the file
+        let bytes = direct_io::read_aligned(&mut reader, 3, 2 * mimimum_write_size()).unwrap();
+        assert_eq!(*bytes, buffer[3..3 + 2 * mimimum_write_size()]);
+        let last = buffer.len() as u64 - 5;
+        let bytes = direct_io::read_aligned(&mut reader, last, ENTRY_SIZE).unwrap();
+        assert_eq!(*bytes, buffer[buffer.len() - 5..]);
+        let past_end = direct_io::read_aligned(&mut reader, buffer.len() as u64 + 7, 4).unwrap();
+        assert!(past_end.is_empty());
+    }
+}
+
+#[test]
+fn test_key_codec_round_trip() {
+    let keys: [Key; 7] = [Key::MIN, Key::MIN + 1, -1, 0, 1, Key::MAX - 1, Key::MAX];
+    for codec in [KeyCodec::LittleEndian, KeyCodec::OrderPreserving] {
+        for key in keys {
+            assert_eq!(codec.decode(codec.encode(key)), key, "codec: {codec:?}");
+        }
+    }
+    //original on disk format must not change
+    assert_eq!(KeyCodec::LittleEndian.encode(-2), (-2 as Key).to_le_bytes());
+}
+
+#[test]
+fn test_key_codec_order_preserving() {
+    let codec = KeyCodec::OrderPreserving;
+    let keys: Vec<Key> = vec![
+        Key::MIN,
+        Key::MIN + 1,
+        -1000,
+        -256,
+        -1,
+        0,
+        1,
+        255,
+        256,
+        Key::MAX,
+    ];
+    let encoded: Vec<[u8; KEY_SIZE]> = keys.iter().map(|key| codec.encode(*key)).collect();
+
+    //byte-wise order of encoded keys should match key order
+    for i in 1..encoded.len() {
+        assert!(
+            encoded[i - 1] < encoded[i],
+            "{} and {} are out of order when encoded",
+            keys[i - 1],
+            keys[i]
+        );
+    }
 
-    assert_eq!(Vec::from(entries), entries_deserialized);
+    //little endian does not have this property
+    let little_endian = KeyCodec::LittleEndian;
+    assert!(little_endian.encode(-1) > little_endian.encode(0));
 }
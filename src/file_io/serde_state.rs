@@ -0,0 +1,481 @@
+//!Encoding of the config, metadata and read cache files. With the serde-config feature these go through bincode,
+//! otherwise they're encoded by hand into the same bytes (bincode's default fixed width little endian layout),
+//! so files can be shared between both kinds of builds
+
+use crate::util::types::{
+    BloomLevels, CompactionPolicy, Compression, IoMode, KeyCodec, LevelZeroOverflowBehavior,
+    MergeOperator, SstImplementation, SstSearchAlgorithm, StallBehavior, SyncPolicy, VersionMerge,
+    WarmUp,
+};
+
+#[cfg(feature = "serde-config")]
+pub fn serialize<T: serde::Serialize + ?Sized>(value: &T) -> Vec<u8> {
+    bincode::serialize(value).expect("Unable to serialize database state")
+}
+
+#[cfg(feature = "serde-config")]
+pub fn deserialize<T: serde::de::DeserializeOwned>(bytes: &[u8]) -> Result<T, String> {
+    bincode::deserialize(bytes).map_err(|why| why.to_string())
+}
+
+#[cfg(not(feature = "serde-config"))]
+pub fn serialize<T: Encode + ?Sized>(value: &T) -> Vec<u8> {
+    encode(value)
+}
+
+#[cfg(not(feature = "serde-config"))]
+pub fn deserialize<T: Decode>(bytes: &[u8]) -> Result<T, String> {
+    decode(bytes)
+}
+
+pub fn encode<T: Encode + ?Sized>(value: &T) -> Vec<u8> {
+    let mut encoder = Encoder::default();
+    value.encode(&mut encoder);
+    encoder.bytes
+}
+
+///Trailing bytes are ignored, like bincode does
+pub fn decode<T: Decode>(bytes: &[u8]) -> Result<T, String> {
+    T::decode(&mut Decoder { bytes })
+}
+
+///Splits a config, metadata or read cache file into its version and contents, None if it was written before files were
+/// versioned. Their layouts start with a u64 count (memtable capacity, number of levels, cache capacity) that is
+/// nonzero and fits in 32 bits, so bytes 4..8 are zero in unversioned files while in versioned files they hold the low
+/// half of that count. The capacities are capped to keep it so, see Database::MAX_MEMTABLE_CAPACITY and
+/// ReadCache::MAX_CAPACITY
+pub fn split_version(bytes: &[u8]) -> Option<(u32, &[u8])> {
+    let (version, contents) = bytes.split_first_chunk::<4>()?;
+    if contents
+        .get(..4)
+        .is_none_or(|count_low_half| count_low_half == [0; 4])
+    {
+        return None;
+    }
+    Some((u32::from_le_bytes(*version), contents))
+}
+
+#[derive(Default)]
+pub struct Encoder {
+    bytes: Vec<u8>,
+}
+
+impl Encoder {
+    pub fn u32(&mut self, value: u32) {
+        self.bytes.extend(value.to_le_bytes());
+    }
+    pub fn u64(&mut self, value: u64) {
+        self.bytes.extend(value.to_le_bytes());
+    }
+    pub fn i64(&mut self, value: i64) {
+        self.bytes.extend(value.to_le_bytes());
+    }
+    pub fn bool(&mut self, value: bool) {
+        self.bytes.push(value as u8);
+    }
+    pub fn str(&mut self, value: &str) {
+        self.u64(value.len() as u64);
+        self.bytes.extend(value.as_bytes());
+    }
+}
+
+pub struct Decoder<'a> {
+    bytes: &'a [u8],
+}
+
+impl Decoder<'_> {
+    fn take<const N: usize>(&mut self) -> Result<[u8; N], String> {
+        let (taken, rest) = self
+            .bytes
+            .split_first_chunk::<N>()
+            .ok_or_else(|| String::from("unexpected end of file"))?;
+        self.bytes = rest;
+        Ok(*taken)
+    }
+    pub fn u32(&mut self) -> Result<u32, String> {
+        self.take().map(u32::from_le_bytes)
+    }
+    pub fn u64(&mut self) -> Result<u64, String> {
+        self.take().map(u64::from_le_bytes)
+    }
+    pub fn i64(&mut self) -> Result<i64, String> {
+        self.take().map(i64::from_le_bytes)
+    }
+    pub fn bool(&mut self) -> Result<bool, String> {
+        match self.take::<1>()? {
+            [0] => Ok(false),
+            [1] => Ok(true),
+            [byte] => Err(format!("invalid bool {byte}")),
+        }
+    }
+    pub fn string(&mut self) -> Result<String, String> {
+        let len = usize::decode(self)?;
+        if len > self.bytes.len() {
+            return Err(String::from("unexpected end of file"));
+        }
+        let (taken, rest) = self.bytes.split_at(len);
+        self.bytes = rest;
+        String::from_utf8(taken.to_vec()).map_err(|why| why.to_string())
+    }
+    fn variant(&mut self, num_variants: u32) -> Result<u32, String> {
+        let variant = self.u32()?;
+        if variant >= num_variants {
+            return Err(format!("invalid enum variant {variant}"));
+        }
+        Ok(variant)
+    }
+}
+
+pub trait Encode {
+    fn encode(&self, encoder: &mut Encoder);
+}
+
+pub trait Decode: Sized {
+    fn decode(decoder: &mut Decoder) -> Result<Self, String>;
+}
+
+impl Encode for u32 {
+    fn encode(&self, encoder: &mut Encoder) {
+        encoder.u32(*self);
+    }
+}
+
+impl Decode for u32 {
+    fn decode(decoder: &mut Decoder) -> Result<Self, String> {
+        decoder.u32()
+    }
+}
+
+impl Encode for usize {
+    fn encode(&self, encoder: &mut Encoder) {
+        encoder.u64(*self as u64);
+    }
+}
+
+impl Decode for usize {
+    fn decode(decoder: &mut Decoder) -> Result<Self, String> {
+        let value = decoder.u64()?;
+        usize::try_from(value).map_err(|_| format!("{value} does not fit in usize"))
+    }
+}
+
+impl Encode for u64 {
+    fn encode(&self, encoder: &mut Encoder) {
+        encoder.u64(*self);
+    }
+}
+
+impl Decode for u64 {
+    fn decode(decoder: &mut Decoder) -> Result<Self, String> {
+        decoder.u64()
+    }
+}
+
+impl Encode for i64 {
+    fn encode(&self, encoder: &mut Encoder) {
+        encoder.i64(*self);
+    }
+}
+
+impl Decode for i64 {
+    fn decode(decoder: &mut Decoder) -> Result<Self, String> {
+        decoder.i64()
+    }
+}
+
+///Floats are their IEEE 754 bits
+impl Encode for f64 {
+    fn encode(&self, encoder: &mut Encoder) {
+        encoder.u64(self.to_bits());
+    }
+}
+
+impl Decode for f64 {
+    fn decode(decoder: &mut Decoder) -> Result<Self, String> {
+        decoder.u64().map(f64::from_bits)
+    }
+}
+
+impl Encode for bool {
+    fn encode(&self, encoder: &mut Encoder) {
+        encoder.bool(*self);
+    }
+}
+
+impl Decode for bool {
+    fn decode(decoder: &mut Decoder) -> Result<Self, String> {
+        decoder.bool()
+    }
+}
+
+///Strings are their length in bytes followed by their UTF-8 bytes
+impl Encode for str {
+    fn encode(&self, encoder: &mut Encoder) {
+        encoder.str(self);
+    }
+}
+
+impl Encode for String {
+    fn encode(&self, encoder: &mut Encoder) {
+        encoder.str(self);
+    }
+}
+
+impl Decode for String {
+    fn decode(decoder: &mut Decoder) -> Result<Self, String> {
+        decoder.string()
+    }
+}
+
+impl<T: Encode + ?Sized> Encode for &T {
+    fn encode(&self, encoder: &mut Encoder) {
+        (**self).encode(encoder);
+    }
+}
+
+///Sequences are their length followed by their elements
+impl<T: Encode> Encode for [T] {
+    fn encode(&self, encoder: &mut Encoder) {
+        self.len().encode(encoder);
+        for element in self {
+            element.encode(encoder);
+        }
+    }
+}
+
+impl<T: Encode> Encode for Vec<T> {
+    fn encode(&self, encoder: &mut Encoder) {
+        self.as_slice().encode(encoder);
+    }
+}
+
+impl<T: Decode> Decode for Vec<T> {
+    fn decode(decoder: &mut Decoder) -> Result<Self, String> {
+        let len = usize::decode(decoder)?;
+        //don't trust the length for preallocation, a corrupt file could claim anything
+        let mut elements = Vec::with_capacity(len.min(decoder.bytes.len()));
+        for _ in 0..len {
+            elements.push(T::decode(decoder)?);
+        }
+        Ok(elements)
+    }
+}
+
+///Options are a bool telling whether a value follows
+impl<T: Encode> Encode for Option<T> {
+    fn encode(&self, encoder: &mut Encoder) {
+        encoder.bool(self.is_some());
+        if let Some(value) = self {
+            value.encode(encoder);
+        }
+    }
+}
+
+impl<T: Decode> Decode for Option<T> {
+    fn decode(decoder: &mut Decoder) -> Result<Self, String> {
+        match decoder.bool()? {
+            true => T::decode(decoder).map(Some),
+            false => Ok(None),
+        }
+    }
+}
+
+impl<A: Encode, B: Encode> Encode for (A, B) {
+    fn encode(&self, encoder: &mut Encoder) {
+        self.0.encode(encoder);
+        self.1.encode(encoder);
+    }
+}
+
+impl<A: Decode, B: Decode> Decode for (A, B) {
+    fn decode(decoder: &mut Decoder) -> Result<Self, String> {
+        Ok((A::decode(decoder)?, B::decode(decoder)?))
+    }
+}
+
+///Unit enums are encoded as their variant index, variants must be listed in declaration order
+macro_rules! impl_unit_enum_codec {
+    ($enum_type:ty, [$($variant:path),+ $(,)?]) => {
+        impl Encode for $enum_type {
+            fn encode(&self, encoder: &mut Encoder) {
+                encoder.u32(*self as u32);
+            }
+        }
+
+        impl Decode for $enum_type {
+            fn decode(decoder: &mut Decoder) -> Result<Self, String> {
+                let variants = [$($variant),+];
+                let index = decoder.variant(variants.len() as u32)?;
+                Ok(variants[index as usize])
+            }
+        }
+    };
+}
+
+///Structs are their fields one after another, fields must be listed in declaration order
+macro_rules! impl_struct_codec {
+    ($struct_type:ident { $($field:ident),+ $(,)? }) => {
+        impl $crate::file_io::serde_state::Encode for $struct_type {
+            fn encode(&self, encoder: &mut $crate::file_io::serde_state::Encoder) {
+                $($crate::file_io::serde_state::Encode::encode(&self.$field, encoder);)+
+            }
+        }
+
+        impl $crate::file_io::serde_state::Decode for $struct_type {
+            fn decode(
+                decoder: &mut $crate::file_io::serde_state::Decoder,
+            ) -> Result<Self, String> {
+                Ok(Self {
+                    $($field: $crate::file_io::serde_state::Decode::decode(decoder)?),+
+                })
+            }
+        }
+    };
+}
+pub(crate) use impl_struct_codec;
+
+impl_unit_enum_codec!(
+    CompactionPolicy,
+    [
+        CompactionPolicy::None,
+        CompactionPolicy::Leveled,
+        CompactionPolicy::Tiered,
+        CompactionPolicy::Dostoevsky,
+        CompactionPolicy::Lazy,
+    ]
+);
+impl_unit_enum_codec!(
+    SstImplementation,
+    [SstImplementation::Array, SstImplementation::Btree]
+);
+impl_unit_enum_codec!(
+    SstSearchAlgorithm,
+    [
+        SstSearchAlgorithm::Default,
+        SstSearchAlgorithm::BinarySearch
+    ]
+);
+impl_unit_enum_codec!(IoMode, [IoMode::Auto, IoMode::Direct, IoMode::Buffered]);
+impl_unit_enum_codec!(
+    MergeOperator,
+    [MergeOperator::Add, MergeOperator::Max, MergeOperator::Min]
+);
+impl_unit_enum_codec!(
+    LevelZeroOverflowBehavior,
+    [
+        LevelZeroOverflowBehavior::Auto,
+        LevelZeroOverflowBehavior::Backpressure
+    ]
+);
+impl_unit_enum_codec!(WarmUp, [WarmUp::Eager, WarmUp::Manual]);
+impl_unit_enum_codec!(
+    VersionMerge,
+    [
+        VersionMerge::KeepNewest,
+        VersionMerge::KeepOldest,
+        VersionMerge::SumValues
+    ]
+);
+impl_unit_enum_codec!(Compression, [Compression::DeltaVarint]);
+impl_unit_enum_codec!(
+    SyncPolicy,
+    [SyncPolicy::Never, SyncPolicy::OnFlush, SyncPolicy::Always]
+);
+impl_unit_enum_codec!(StallBehavior, [StallBehavior::Fail, StallBehavior::Block]);
+impl_unit_enum_codec!(
+    KeyCodec,
+    [KeyCodec::LittleEndian, KeyCodec::OrderPreserving]
+);
+
+///Variant index followed by the variant's fields, like bincode does
+impl Encode for BloomLevels {
+    fn encode(&self, encoder: &mut Encoder) {
+        match self {
+            BloomLevels::All => encoder.u32(0),
+            BloomLevels::UpTo(num_levels) => {
+                encoder.u32(1);
+                num_levels.encode(encoder);
+            }
+            BloomLevels::None => encoder.u32(2),
+        }
+    }
+}
+
+impl Decode for BloomLevels {
+    fn decode(decoder: &mut Decoder) -> Result<Self, String> {
+        match decoder.variant(3)? {
+            0 => Ok(BloomLevels::All),
+            1 => Ok(BloomLevels::UpTo(usize::decode(decoder)?)),
+            _ => Ok(BloomLevels::None),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_round_trip() {
+        let value: (u32, Vec<(Vec<usize>, bool)>) = (2, vec![(vec![1, 2], true), (vec![], false)]);
+        let bytes = encode(&value);
+        assert_eq!(decode::<(u32, Vec<(Vec<usize>, bool)>)>(&bytes), Ok(value));
+
+        let entries: Vec<((i64, i64), usize)> = vec![((i64::MIN, -1), 3), ((0, i64::MAX), 0)];
+        assert_eq!(decode(&encode(&entries)), Ok(entries));
+
+        let policies = vec![
+            CompactionPolicy::None,
+            CompactionPolicy::Dostoevsky,
+            CompactionPolicy::Lazy,
+        ];
+        assert_eq!(decode(&encode(&policies)), Ok(policies));
+
+        let bloom_levels = vec![BloomLevels::UpTo(2), BloomLevels::None, BloomLevels::All];
+        assert_eq!(decode(&encode(&bloom_levels)), Ok(bloom_levels));
+
+        let pages = vec![("0/1.sst".to_string(), 3_usize), (String::new(), 0)];
+        assert_eq!(decode(&encode(&pages)), Ok(pages));
+
+        let ratios = vec![Some(0.25_f64), None, Some(f64::MAX)];
+        assert_eq!(decode(&encode(&ratios)), Ok(ratios));
+    }
+
+    #[test]
+    fn test_invalid_input() {
+        assert!(decode::<usize>(&[1, 0, 0]).is_err());
+        assert!(decode::<bool>(&[2]).is_err());
+        assert!(decode::<SstImplementation>(&encode(&2_u32)).is_err());
+        assert!(decode::<BloomLevels>(&encode(&3_u32)).is_err());
+        assert!(decode::<BloomLevels>(&encode(&1_u32)).is_err()); //missing its level count
+
+        //claims more elements than there are bytes for
+        assert!(decode::<Vec<usize>>(&encode(&(usize::MAX, 1_usize))).is_err());
+        assert!(decode::<String>(&encode(&(2_usize, 1_u32))[..9]).is_err()); //cut short
+        assert!(decode::<String>(&encode(&(1_usize, 0xff_u32))[..9]).is_err()); //not UTF-8
+    }
+
+    #[cfg(feature = "serde-config")]
+    #[test]
+    fn test_matches_bincode() {
+        let value: (u32, Vec<Vec<usize>>, KeyCodec, bool) =
+            (7, vec![vec![1, 2], vec![]], KeyCodec::OrderPreserving, true);
+        assert_eq!(
+            encode(&(value.0, (&value.1, (value.2, value.3)))),
+            bincode::serialize(&value).unwrap()
+        );
+        let policy = CompactionPolicy::Tiered;
+        assert_eq!(encode(&policy), bincode::serialize(&policy).unwrap());
+        let entry: ((i64, i64), usize) = ((-5, 6), 7);
+        assert_eq!(encode(&entry), bincode::serialize(&entry).unwrap());
+        for ratio in [Some(0.5_f64), None] {
+            assert_eq!(encode(&ratio), bincode::serialize(&ratio).unwrap());
+        }
+        for bloom_levels in [BloomLevels::All, BloomLevels::UpTo(3), BloomLevels::None] {
+            assert_eq!(
+                encode(&bloom_levels),
+                bincode::serialize(&bloom_levels).unwrap()
+            );
+        }
+    }
+}
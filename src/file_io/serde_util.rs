@@ -1,7 +1,18 @@
 use crate::{ceil_div, util::system_info};
 
+use super::{direct_io, storage::DbFile};
+
 pub fn nearest_min_write_size_multiple(size: usize) -> usize {
     let min_write_size = system_info::mimimum_write_size();
     let multiplier = ceil_div!(size, min_write_size);
     min_write_size * multiplier
 }
+
+///Bytes to write to <file> for <len> bytes of data: direct I/O needs a multiple of the minimum write size (the file
+/// is resized to <len> afterwards), buffered files are written exactly
+pub fn write_len(file: &DbFile, len: usize) -> usize {
+    match direct_io::is_direct(file) {
+        true => nearest_min_write_size_multiple(len),
+        false => len,
+    }
+}
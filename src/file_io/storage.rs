@@ -0,0 +1,432 @@
+//!Where a database's files are kept. Every file operation of a database goes through the Storage it was opened with
+//! (see DatabaseOptions), which hands it to a FileSystem: the disk (see direct_io) or memory (see memory_fs). Clones
+//! of a Storage share its files, so a database closed and opened again with the same Storage finds them in place.
+//! Each copy also holds the settings of the database using it (io mode, sync policy, compression), so files are
+//! written the way their database says without any table keyed by path.
+//! NOTE: generations (see generation) and crash points are still tracked by path, whatever the storage
+
+use std::{
+    fmt,
+    io::{self, Read, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use crate::util::{
+    filename,
+    types::{Compression, IoMode, SyncPolicy},
+};
+
+use super::{
+    direct_io::DiskFs,
+    generation, io_stats,
+    memory_fs::{MemoryFile, MemoryFs},
+};
+
+///A database file, on disk or in memory depending on the storage it was opened from
+pub enum DbFile {
+    Disk(std::fs::File),
+    Memory(MemoryFile),
+}
+
+impl DbFile {
+    ///Truncate or zero extend the file to <len> bytes
+    pub fn set_len(&self, len: u64) -> io::Result<()> {
+        match self {
+            DbFile::Disk(file) => file.set_len(len),
+            DbFile::Memory(file) => file.set_len(len),
+        }
+    }
+    ///Size of the file in bytes
+    pub fn len_bytes(&self) -> io::Result<u64> {
+        match self {
+            DbFile::Disk(file) => Ok(file.metadata()?.len()),
+            DbFile::Memory(file) => Ok(file.len_bytes()),
+        }
+    }
+    ///Flush the file's contents to storage, in-memory files have nowhere to flush them to (the sync is still counted,
+    /// see IoStats::syncs)
+    pub fn sync_all(&self) -> io::Result<()> {
+        io_stats::record_sync();
+        match self {
+            DbFile::Disk(file) => file.sync_all(),
+            DbFile::Memory(_) => Ok(()),
+        }
+    }
+}
+
+impl Read for DbFile {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            DbFile::Disk(file) => file.read(buf),
+            DbFile::Memory(file) => file.read(buf),
+        }
+    }
+}
+
+impl Write for DbFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            DbFile::Disk(file) => file.write(buf),
+            DbFile::Memory(file) => file.write(buf),
+        }
+    }
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            DbFile::Disk(file) => file.flush(),
+            DbFile::Memory(file) => file.flush(),
+        }
+    }
+}
+
+impl Seek for DbFile {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        match self {
+            DbFile::Disk(file) => file.seek(pos),
+            DbFile::Memory(file) => file.seek(pos),
+        }
+    }
+}
+
+///How a file is opened, see FileSystem::open
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Access {
+    Create, //read and write, created empty if it doesn't exist (its contents are kept otherwise)
+    ReadWrite, //read and write, fails with NotFound if it doesn't exist (so do the ones below)
+    Read,   //read only
+    Write,  //write only
+    Append, //write only, every write goes to the end of the file
+    CreateBuffered, //like Create but truncated, and through the OS page cache whatever the io mode
+    ReadBuffered, //like Read, through the OS page cache whatever the io mode
+}
+
+///Operations of a place files can be kept in, with the semantics of a filesystem (e.g. files can only be created in
+/// existing directories, renames replace their target, open handles keep the contents of a removed file)
+pub trait FileSystem: fmt::Debug + Send + Sync {
+    fn is_in_memory(&self) -> bool;
+    ///Resolve <io_mode> for the files under <directory>, see Storage::register_io_mode
+    fn resolve_io_mode(
+        &self,
+        directory: &Path,
+        probe_path: &Path,
+        io_mode: IoMode,
+    ) -> io::Result<IoMode>;
+    ///Open <path> for <access>, with or without direct I/O depending on <io_mode> (see Storage::register_io_mode)
+    fn open(&self, path: &Path, access: Access, io_mode: IoMode) -> io::Result<DbFile>;
+    fn exists(&self, path: &Path) -> bool;
+    fn remove_file(&self, path: &Path) -> io::Result<()>;
+    ///Move the file at <old_path> to <new_path>, replacing whatever file was there
+    fn rename(&self, old_path: &Path, new_path: &Path) -> io::Result<()>;
+    ///Make <link> another name for the file at <original>, the contents outlive the removal of either name
+    fn hard_link(&self, original: &Path, link: &Path) -> io::Result<()>;
+    ///Size in bytes of the file at <path>, without opening it
+    fn file_len(&self, path: &Path) -> io::Result<u64>;
+    ///Whole contents of the file at <path>, read without direct I/O
+    fn read(&self, path: &Path) -> io::Result<Vec<u8>>;
+    ///Replace the contents of the file at <path> (creating it if needed) with <bytes>, written without direct I/O and
+    /// flushed to storage if <sync>
+    fn write(&self, path: &Path, bytes: &[u8], sync: bool) -> io::Result<()>;
+    ///Flush the directory entries of <directory> (e.g. a rename into it) to storage
+    fn sync_directory(&self, directory: &Path) -> io::Result<()>;
+    ///Create <path> and every missing directory above it
+    fn create_dir_all(&self, path: &Path) -> io::Result<()>;
+    ///Remove <path> and everything under it
+    fn remove_dir_all(&self, path: &Path) -> io::Result<()>;
+    ///Paths of the files and directories directly under <path>, in no particular order
+    fn read_dir(&self, path: &Path) -> io::Result<Vec<PathBuf>>;
+}
+
+///Handle to where a database's files are kept, see DatabaseOptions::storage. Clones share the same files, each has its
+/// own copy of the settings files are written with
+#[derive(Debug, Clone)]
+pub struct Storage {
+    file_system: Arc<dyn FileSystem>,
+    io_mode: IoMode,                  //resolved by register_io_mode, Auto until then
+    sync_policy: SyncPolicy,          //Never unless set
+    compression: Option<Compression>, //of the runs written from now on, None unless set
+}
+
+impl Default for Storage {
+    fn default() -> Self {
+        Self::disk()
+    }
+}
+
+#[allow(dead_code)]
+impl Storage {
+    ///Files on disk, under the paths they're opened with
+    pub fn disk() -> Self {
+        Self::new(Arc::new(DiskFs))
+    }
+    ///A new, empty set of files kept in memory rather than on disk. Nothing is durable: the files are freed once the
+    /// last clone of the storage (e.g. held by a database) is dropped
+    pub fn in_memory() -> Self {
+        Self::new(Arc::new(MemoryFs::default()))
+    }
+    fn new(file_system: Arc<dyn FileSystem>) -> Self {
+        Self {
+            file_system,
+            io_mode: IoMode::Auto,
+            sync_policy: SyncPolicy::Never,
+            compression: None,
+        }
+    }
+    pub fn is_in_memory(&self) -> bool {
+        self.file_system.is_in_memory()
+    }
+
+    ///Resolve <io_mode> for the database files under <directory> and open files with it from now on. Auto becomes
+    /// Direct if <probe_path> (an existing file in <directory>) can be opened for direct I/O and Buffered otherwise,
+    /// Direct fails with ErrorKind::Unsupported if it can't (the mode is left as it was). Returns the mode files are
+    /// opened with
+    pub(crate) fn register_io_mode(
+        &mut self,
+        directory: &Path,
+        probe_path: &Path,
+        io_mode: IoMode,
+    ) -> io::Result<IoMode> {
+        self.io_mode = self
+            .file_system
+            .resolve_io_mode(directory, probe_path, io_mode)?;
+        Ok(self.io_mode)
+    }
+    ///Mode files are opened with, Auto if register_io_mode wasn't called
+    pub(crate) fn io_mode(&self) -> IoMode {
+        self.io_mode
+    }
+
+    ///Sync the files written from now on as <sync_policy> says
+    pub(crate) fn set_sync_policy(&mut self, sync_policy: SyncPolicy) {
+        self.sync_policy = sync_policy;
+    }
+    pub(crate) fn sync_policy(&self) -> SyncPolicy {
+        self.sync_policy
+    }
+
+    ///Compress the runs written from now on with <compression>, None to write them uncompressed. Runs that are
+    /// already written keep their format
+    pub(crate) fn set_compression(&mut self, compression: Option<Compression>) {
+        self.compression = compression;
+    }
+    pub(crate) fn compression(&self) -> Option<Compression> {
+        self.compression
+    }
+
+    ///Whether run files (SSTs and their sidecars) are synced once they're written, along with the directory they're
+    /// renamed into
+    fn syncs_runs(&self) -> bool {
+        self.sync_policy != SyncPolicy::Never
+    }
+
+    ///Flush <file> (a run file being written) to storage, unless the sync policy is Never
+    pub(crate) fn sync_run_file(&self, file: &DbFile) -> io::Result<()> {
+        match self.syncs_runs() {
+            true => file.sync_all(),
+            false => Ok(()),
+        }
+    }
+
+    ///Opens (creates if doesn't exist) file with read and write permissions using direct I/O
+    pub(crate) fn create(&self, path: &Path) -> io::Result<DbFile> {
+        #[cfg(test)]
+        super::direct_io::tests::crash_point(path);
+        generation::bump(path);
+        self.file_system.open(path, Access::Create, self.io_mode)
+    }
+
+    ///Opens file with read and write permissions using direct I/O
+    pub(crate) fn open(&self, path: &Path) -> io::Result<DbFile> {
+        generation::bump(path);
+        self.file_system.open(path, Access::ReadWrite, self.io_mode)
+    }
+
+    ///Opens file with read only permissions
+    pub(crate) fn open_read(&self, path: &Path) -> io::Result<DbFile> {
+        self.file_system.open(path, Access::Read, self.io_mode)
+    }
+
+    ///Opens file write only permissions using direct I/O
+    pub(crate) fn open_write(&self, path: &Path) -> io::Result<DbFile> {
+        generation::bump(path);
+        self.file_system.open(path, Access::Write, self.io_mode)
+    }
+
+    ///Opens file append only permissions  using direct I/O
+    pub(crate) fn open_append(&self, path: &Path) -> io::Result<DbFile> {
+        generation::bump(path);
+        self.file_system.open(path, Access::Append, self.io_mode)
+    }
+
+    ///Creates (truncating if it exists) a scratch file that goes through the OS page cache whatever the io mode of its
+    /// directory, so it can be written in pieces of any size (e.g. through a BufWriter)
+    pub(crate) fn create_buffered(&self, path: &Path) -> io::Result<DbFile> {
+        generation::bump(path);
+        self.file_system
+            .open(path, Access::CreateBuffered, self.io_mode)
+    }
+
+    ///Opens a file written with create_buffered for reading
+    pub(crate) fn open_read_buffered(&self, path: &Path) -> io::Result<DbFile> {
+        self.file_system
+            .open(path, Access::ReadBuffered, self.io_mode)
+    }
+
+    pub(crate) fn path_exists(&self, path: &Path) -> bool {
+        self.file_system.exists(path)
+    }
+
+    ///Removes a file, pages cached from it become stale (see generation)
+    pub(crate) fn remove_file(&self, path: &Path) -> io::Result<()> {
+        #[cfg(test)]
+        super::direct_io::tests::crash_point(path);
+        generation::bump(path);
+        self.file_system.remove_file(path)
+    }
+
+    ///Renames a file, pages cached from it stay valid under <new_path> (see generation). The rename is synced (see
+    /// sync_parent_directory) unless the sync policy is Never
+    pub(crate) fn rename(&self, old_path: &Path, new_path: &Path) -> io::Result<()> {
+        self.rename_unsynced(old_path, new_path)?;
+        match self.syncs_runs() {
+            true => self.sync_parent_directory(new_path),
+            false => Ok(()),
+        }
+    }
+
+    fn rename_unsynced(&self, old_path: &Path, new_path: &Path) -> io::Result<()> {
+        #[cfg(test)]
+        super::direct_io::tests::crash_point(new_path);
+        self.file_system.rename(old_path, new_path)?;
+        generation::rename(old_path, new_path);
+        Ok(())
+    }
+
+    ///Makes <link> another name for the file at <original>. The file's contents stay readable through <link> after
+    /// <original> is removed or replaced (run files are never written in place, see publish)
+    pub(crate) fn hard_link(&self, original: &Path, link: &Path) -> io::Result<()> {
+        self.file_system.hard_link(original, link)
+    }
+
+    ///Size in bytes of the file at <path>, from its metadata (it isn't opened)
+    pub(crate) fn file_len(&self, path: &Path) -> io::Result<u64> {
+        self.file_system.file_len(path)
+    }
+
+    ///Whole contents of a small file (e.g. the config) read without direct I/O
+    pub(crate) fn read_file(&self, path: &Path) -> io::Result<Vec<u8>> {
+        self.file_system.read(path)
+    }
+
+    ///Replace the contents of a small file (e.g. the config) with <bytes>, written without direct I/O. Like publish,
+    /// the bytes go to a temporary file first, so <path> holds either its old or its new contents whenever the process
+    /// stops. The file and the rename are only synced under SyncPolicy::Always, a power failure can undo them otherwise
+    pub(crate) fn write_file(&self, path: &Path, bytes: &[u8]) -> io::Result<()> {
+        let tmp_path = filename::tmp_path(path);
+        #[cfg(test)]
+        super::direct_io::tests::crash_point(&tmp_path);
+        generation::bump(&tmp_path);
+        let sync = self.sync_policy == SyncPolicy::Always;
+        self.file_system.write(&tmp_path, bytes, sync)?;
+        self.rename_unsynced(&tmp_path, path)?;
+        match sync {
+            true => self.sync_parent_directory(path),
+            false => Ok(()),
+        }
+    }
+
+    ///Creates (truncating if it exists) the temporary file the contents of <path> are written to, publish moves it to
+    /// <path> once it's complete. A file created this way is never seen half written at <path>, whenever the process
+    /// stops
+    pub(crate) fn create_tmp(&self, path: &Path) -> io::Result<DbFile> {
+        let file = self.create(&filename::tmp_path(path))?;
+        file.set_len(0)?;
+        Ok(file)
+    }
+
+    ///Flush <file> (created by create_tmp(<path>)) to storage and move it to <path>, replacing any file there. Neither
+    /// is synced under SyncPolicy::Never
+    pub(crate) fn publish(&self, file: DbFile, path: &Path) -> io::Result<()> {
+        self.sync_run_file(&file)?;
+        drop(file);
+        self.rename(&filename::tmp_path(path), path)
+    }
+
+    ///Flush the directory entries of <path>'s directory (e.g. a rename into it) to storage. Counted in IoStats::syncs
+    /// whether the file system has anything to flush or not
+    fn sync_parent_directory(&self, path: &Path) -> io::Result<()> {
+        io_stats::record_sync();
+        let directory = match path.parent() {
+            Some(parent) if !parent.as_os_str().is_empty() => parent,
+            _ => Path::new("."),
+        };
+        self.file_system.sync_directory(directory)
+    }
+
+    pub(crate) fn create_dir_all(&self, path: &Path) -> io::Result<()> {
+        self.file_system.create_dir_all(path)
+    }
+
+    ///Removes a directory and everything in it
+    pub(crate) fn remove_dir_all(&self, path: &Path) -> io::Result<()> {
+        self.file_system.remove_dir_all(path)
+    }
+
+    ///Paths of the files and directories directly in <path>
+    pub(crate) fn read_dir(&self, path: &Path) -> io::Result<Vec<PathBuf>> {
+        self.file_system.read_dir(path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+    use crate::{file_io::direct_io, util::testing};
+
+    #[test]
+    fn test_publish() {
+        let directory = testing::test_dir("storage_publish");
+        fs::create_dir_all(directory).unwrap();
+        direct_io::tests::simulate_no_direct_io(directory); //the writes aren't page sized
+        let path = directory.join("file.bin");
+        for storage in [Storage::disk(), Storage::in_memory()] {
+            storage.create_dir_all(directory).unwrap();
+            storage.write_file(&path, &[1, 2, 3]).unwrap();
+            assert_eq!(storage.read_file(&path).unwrap(), vec![1, 2, 3]);
+
+            //the old contents stay in place until the new ones are published
+            let mut file = storage.create_tmp(&path).unwrap();
+            file.write_all(&[4, 5]).unwrap();
+            assert_eq!(storage.read_file(&path).unwrap(), vec![1, 2, 3]);
+            assert!(storage.path_exists(&filename::tmp_path(&path)));
+            storage.publish(file, &path).unwrap();
+            assert_eq!(storage.read_file(&path).unwrap(), vec![4, 5]);
+            assert!(!storage.path_exists(&filename::tmp_path(&path)));
+
+            //a leftover temporary file is truncated rather than written over
+            let mut file = storage.create(&filename::tmp_path(&path)).unwrap();
+            file.write_all(&[9; 8]).unwrap();
+            drop(file);
+            let mut file = storage.create_tmp(&path).unwrap();
+            file.write_all(&[6]).unwrap();
+            storage.publish(file, &path).unwrap();
+            assert_eq!(storage.read_file(&path).unwrap(), vec![6]);
+            assert_eq!(storage.read_dir(directory).unwrap(), vec![path.clone()]);
+        }
+        fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[test]
+    fn test_clones_share_files() {
+        let path = testing::test_dir("storage_clones_share_files");
+        let storage = Storage::in_memory();
+        storage.create_dir_all(path).unwrap();
+        storage.write_file(&path.join("file.bin"), &[1]).unwrap();
+        let clone = storage.clone();
+        assert_eq!(clone.read_file(&path.join("file.bin")).unwrap(), vec![1]);
+        //nothing is written to disk, nor seen by another storage in memory
+        assert!(!path.exists());
+        assert!(!Storage::in_memory().path_exists(path));
+    }
+}
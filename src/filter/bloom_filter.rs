@@ -2,7 +2,7 @@ use crate::{
     filter::bloom_util::{bitmap_len, BYTE_SIZE},
     util::{
         hash::BloomHasher,
-        types::{Entry, Key},
+        types::{Entry, Key, KeyCodec},
     },
 };
 
@@ -11,21 +11,20 @@ use super::bloom_util::{bit_index, num_hash_functions};
 pub struct BloomFilter {
     pub bitmap: Vec<u8>,
     num_hash_functions: usize,
+    key_codec: KeyCodec,
 }
 
 #[allow(dead_code)]
 impl BloomFilter {
-    pub fn new(num_entries: usize, bits_per_entry: usize) -> Self {
+    pub fn new(num_entries: usize, bits_per_entry: usize, key_codec: KeyCodec) -> Self {
         Self {
             num_hash_functions: num_hash_functions(bits_per_entry),
             bitmap: Self::create_bitmap(num_entries, bits_per_entry),
+            key_codec,
         }
     }
-    pub fn from_entries(entries: &Vec<Entry>, bits_per_entry: usize) -> Self {
-        let mut filter = Self {
-            num_hash_functions: num_hash_functions(bits_per_entry),
-            bitmap: Self::create_bitmap(entries.len(), bits_per_entry),
-        };
+    pub fn from_entries(entries: &[Entry], bits_per_entry: usize, key_codec: KeyCodec) -> Self {
+        let mut filter = Self::new(entries.len(), bits_per_entry, key_codec);
         filter.insert_entries(entries);
         filter
     }
@@ -38,7 +37,7 @@ impl BloomFilter {
         }
     }
 
-    pub fn insert_entries(&mut self, entries: &Vec<Entry>) {
+    pub fn insert_entries(&mut self, entries: &[Entry]) {
         for (key, ..) in entries {
             self.insert(*key);
         }
@@ -66,7 +65,12 @@ impl BloomFilter {
     }
 
     fn hash_to_index(&self, key: Key, seed: u64) -> (usize, usize) {
-        let index = BloomHasher::hash_key_to_index(key, seed, self.bitmap.len() * BYTE_SIZE);
+        let index = BloomHasher::hash_key_to_index(
+            key,
+            seed,
+            self.bitmap.len() * BYTE_SIZE,
+            self.key_codec,
+        );
         bit_index(index)
     }
 }
@@ -78,7 +82,7 @@ mod tests {
     #[test]
     fn test_bloom_filter_simple() {
         // Create a new Bloom filter
-        let mut bloom_filter = BloomFilter::new(10, 5);
+        let mut bloom_filter = BloomFilter::new(10, 5, KeyCodec::default());
 
         // Insert some elements into the Bloom filter
         bloom_filter.insert(1);
@@ -97,7 +101,7 @@ mod tests {
     #[test]
     fn test_bloom_filter_false_positive() {
         // Create a new Bloom filter
-        let mut bloom_filter = BloomFilter::new(1, 1);
+        let mut bloom_filter = BloomFilter::new(1, 1, KeyCodec::default());
         // Assuming no collisions here, maybe noy true
         bloom_filter.insert(1);
         bloom_filter.insert(2);
@@ -115,7 +119,7 @@ mod tests {
     #[test]
     fn test_bloom_filter_reset() {
         // Create a new Bloom filter
-        let mut bloom_filter = BloomFilter::new(10, 5);
+        let mut bloom_filter = BloomFilter::new(10, 5, KeyCodec::default());
 
         bloom_filter.insert(1);
         bloom_filter.reset();
@@ -127,7 +131,7 @@ mod tests {
     #[test]
     fn test_from_entries() {
         let entries = vec![(0, 0), (1, 1), (32, 32)];
-        let bloom_filter = BloomFilter::from_entries(&entries, 5);
+        let bloom_filter = BloomFilter::from_entries(&entries, 5, KeyCodec::default());
 
         assert!(bloom_filter.contains(0));
         assert!(bloom_filter.contains(32));
@@ -1,13 +1,19 @@
-use std::io::{self, Read, Write};
+use std::{io, path::Path};
 
 use crate::{
     buffer_pool::BufferPool,
-    file_io::{direct_io, serde_entry, serde_util::nearest_min_write_size_multiple},
+    file_io::{
+        direct_io, io_stats,
+        page_buffers::PageBuffer,
+        serde_entry,
+        serde_util::{nearest_min_write_size_multiple, write_len},
+        storage::Storage,
+    },
     sst::sst_util::{get_sst_page, num_pages},
     util::{
-        filename,
+        filename::RunPaths,
         hash::BloomHasher,
-        types::{Key, Page, RunAddress, Size},
+        types::{Entry, Key, KeyCodec, Page, RunAddress, Size},
     },
 };
 
@@ -23,31 +29,50 @@ pub struct BloomFilterIO {}
 impl BloomFilterIO {
     ///Write bloom filter bitmap to storage
     pub fn write(run_address: &RunAddress, bitmap: &[u8]) -> io::Result<()> {
-        let path = filename::bloom_filter_path(run_address);
-        let mut file = direct_io::create(&path)?;
+        Self::write_to(run_address.0.storage(), &run_address.path_bloom(), bitmap)
+    }
+    fn write_to(storage: &Storage, path: &Path, bitmap: &[u8]) -> io::Result<()> {
+        let mut file = storage.create_tmp(path)?;
 
         let mut buffer = bitmap.to_vec();
-        buffer.resize(nearest_min_write_size_multiple(buffer.len()), 0);
-        file.write_all(&buffer)?;
+        buffer.resize(write_len(&file, buffer.len()), 0);
+        direct_io::write_all(&mut file, &buffer)?;
         file.set_len(bitmap.len() as u64)?;
-        Ok(())
+        storage.publish(file, path)
+    }
+    ///Whether the run's filter file is there and holds exactly the bitmap of <num_entries> entries. A filter removed or
+    /// cut short behind our back would make keys the run holds look absent
+    pub fn is_intact(
+        run_address: &RunAddress,
+        bits_per_entry: Size,
+        num_entries: Size,
+    ) -> io::Result<bool> {
+        match run_address
+            .0
+            .storage()
+            .open_read_buffered(&run_address.path_bloom())
+        {
+            Ok(file) => Ok(file.len_bytes()? == bitmap_len(num_entries, bits_per_entry) as u64),
+            Err(why) if why.kind() == io::ErrorKind::NotFound => Ok(false),
+            Err(why) => Err(why),
+        }
     }
     ///Deserialize an entire bloom filter file to bloom filter struct, useful for testing
     pub fn read(
         run_address: &RunAddress,
         bits_per_entry: Size,
         num_entries: Size,
+        key_codec: KeyCodec,
     ) -> io::Result<BloomFilter> {
-        let path = filename::bloom_filter_path(run_address);
-        let mut file = direct_io::open_read(&path)?;
+        let path = run_address.path_bloom();
+        let mut file = run_address.0.storage().open_read(&path)?;
 
         let bitmap_size = bitmap_len(num_entries, bits_per_entry);
-        let mut buffer: Vec<u8> = vec![0; nearest_min_write_size_multiple(bitmap_size)];
-        let bytes_read = file.read(&mut buffer)?;
+        let buffer = direct_io::read(&mut file, nearest_min_write_size_multiple(bitmap_size))?;
 
-        let mut filter = BloomFilter::new(num_entries, bits_per_entry);
+        let mut filter = BloomFilter::new(num_entries, bits_per_entry, key_codec);
 
-        assert_eq!(bitmap_size, bytes_read, "Incorrect bitmap size");
+        assert_eq!(bitmap_size, buffer.len(), "Incorrect bitmap size");
         filter.bitmap = buffer[0..bitmap_size].to_vec();
         Ok(filter)
     }
@@ -56,57 +81,234 @@ impl BloomFilterIO {
         run_address: &RunAddress,
         bits_per_entry: Size,
         num_entries: Size,
+        key_codec: KeyCodec,
+    ) -> io::Result<()> {
+        let path = run_address.path_bloom();
+        Self::write_from_sst_to(&path, run_address, bits_per_entry, num_entries, key_codec)
+    }
+    ///Same as write_from_sst, but the filter is written to <path> instead of the run's bloom filter file
+    pub fn write_from_sst_to(
+        path: &Path,
+        run_address: &RunAddress,
+        bits_per_entry: Size,
+        num_entries: Size,
+        key_codec: KeyCodec,
     ) -> io::Result<()> {
         let (db_name, level, run) = run_address;
-        let mut filter = BloomFilter::new(num_entries, bits_per_entry);
+        let mut filter = BloomFilter::new(num_entries, bits_per_entry, key_codec);
         for page_index in 0..num_pages(num_entries) {
             let page = get_sst_page(run_address, page_index, None)?;
-            let entries = serde_entry::deserialize(&page).unwrap_or_else(|why| panic!("Failed to deserialize entries during bloom filter creation, db_name: {db_name}, level: {level} run: {run}, page_index: {page_index}, reason: {why}"));
+            let entries = serde_entry::deserialize(&page, key_codec).unwrap_or_else(|why| panic!("Failed to deserialize entries during bloom filter creation, db_name: {}, level: {level} run: {run}, page_index: {page_index}, reason: {why}", db_name.display()));
             filter.insert_entries(&entries);
         }
 
+        Self::write_to(db_name.storage(), path, &filter.bitmap)
+    }
+    ///Write the filter of a run whose keys (every one of them) are already in memory, e.g. collected during compaction
+    pub fn write_from_keys(
+        run_address: &RunAddress,
+        keys: &[Key],
+        bits_per_entry: Size,
+        key_codec: KeyCodec,
+    ) -> io::Result<()> {
+        let mut filter = BloomFilter::new(keys.len(), bits_per_entry, key_codec);
+        for key in keys {
+            filter.insert(*key);
+        }
         Self::write(run_address, &filter.bitmap)
     }
+    ///Bits (page index, byte index within the page, bit index) the <num_hash_functions> hashes of <key> set in a bitmap
+    /// of <num_bits> bits, sorted by page so each page only has to be fetched once
+    fn probes(
+        key: Key,
+        num_hash_functions: usize,
+        num_bits: Size,
+        key_codec: KeyCodec,
+    ) -> Vec<(Page, usize, usize)> {
+        let key_bytes = key_codec.encode(key);
+        let mut probes: Vec<(Page, usize, usize)> = (0..num_hash_functions)
+            .map(|seed| {
+                page_bit_index(BloomHasher::hash_to_index(key_bytes, seed as u64, num_bits))
+            })
+            .collect();
+        probes.sort_unstable();
+        probes
+    }
+    ///contains, with pages of the filter fetched through <get_page>. Each page a hash of <key> lands on is fetched
+    /// once, in page order, stopping at the first page with a 0 bit
+    fn contains_with(
+        key: Key,
+        bits_per_entry: Size,
+        num_entries: Size,
+        key_codec: KeyCodec,
+        get_page: &mut dyn FnMut(Page) -> io::Result<PageBuffer>,
+    ) -> io::Result<bool> {
+        let num_bits = bitmap_num_bits(num_entries, bits_per_entry);
+        let probes = Self::probes(key, num_hash_functions(bits_per_entry), num_bits, key_codec);
+        for page_probes in probes.chunk_by(|a, b| a.0 == b.0) {
+            let page = get_page(page_probes[0].0)?;
+            let is_unset = |(_, byte_index, bit_index): &(Page, usize, usize)| {
+                page[*byte_index] & (1 << bit_index) == 0
+            };
+            if page_probes.iter().any(is_unset) {
+                io_stats::record_bloom_probe(true);
+                return Ok(false);
+            }
+        }
+        io_stats::record_bloom_probe(false);
+        Ok(true)
+    }
     ///Check if bloom filter file contains an element. Returns false on first 0 found, otherwise true.
     pub fn contains(
         run_address: &RunAddress,
         key: Key,
         bits_per_entry: Size,
         num_entries: Size,
+        key_codec: KeyCodec,
         mut buffer_pool: Option<&mut BufferPool>,
     ) -> io::Result<bool> {
+        Self::contains_with(
+            key,
+            bits_per_entry,
+            num_entries,
+            key_codec,
+            &mut |page_index| get_bloom_page(run_address, page_index, buffer_pool.as_deref_mut()),
+        )
+    }
+    ///contains_many, with pages of the filter fetched through <get_page>
+    fn contains_many_with(
+        keys: &[Key],
+        bits_per_entry: Size,
+        num_entries: Size,
+        key_codec: KeyCodec,
+        get_page: &mut dyn FnMut(Page) -> io::Result<PageBuffer>,
+    ) -> io::Result<Vec<bool>> {
+        let num_bits = bitmap_num_bits(num_entries, bits_per_entry);
         let num_hash_functions = num_hash_functions(bits_per_entry);
+        //(page index, byte index, bit index, index of the key in <keys>) of every key's probes
+        let mut probes: Vec<(Page, usize, usize, usize)> = keys
+            .iter()
+            .enumerate()
+            .flat_map(|(key_index, key)| {
+                Self::probes(*key, num_hash_functions, num_bits, key_codec)
+                    .into_iter()
+                    .map(move |(page_index, byte_index, bit_index)| {
+                        (page_index, byte_index, bit_index, key_index)
+                    })
+            })
+            .collect();
+        probes.sort_unstable();
 
-        //for page caching, just in case bufferpool is disabled
-        let mut curr_page_index = Page::MAX;
-        let mut curr_page = Vec::<u8>::new();
+        let mut results = vec![true; keys.len()];
+        for page_probes in probes.chunk_by(|a, b| a.0 == b.0) {
+            if page_probes.iter().all(|probe| !results[probe.3]) {
+                continue; //every key probing this page is already known to be absent
+            }
+            let page = get_page(page_probes[0].0)?;
+            for (_, byte_index, bit_index, key_index) in page_probes {
+                if page[*byte_index] & (1 << bit_index) == 0 {
+                    results[*key_index] = false;
+                }
+            }
+        }
+        for result in &results {
+            io_stats::record_bloom_probe(!result);
+        }
+        Ok(results)
+    }
+    ///contains for each of <keys>, sharing page fetches between them: every page of the filter that any of their hashes
+    /// land on is fetched at most once, in page order, and pages only probed by keys already found absent are skipped
+    pub fn contains_many(
+        run_address: &RunAddress,
+        keys: &[Key],
+        bits_per_entry: Size,
+        num_entries: Size,
+        key_codec: KeyCodec,
+        mut buffer_pool: Option<&mut BufferPool>,
+    ) -> io::Result<Vec<bool>> {
+        Self::contains_many_with(
+            keys,
+            bits_per_entry,
+            num_entries,
+            key_codec,
+            &mut |page_index| get_bloom_page(run_address, page_index, buffer_pool.as_deref_mut()),
+        )
+    }
+}
 
-        for seed in 0..num_hash_functions {
-            let bitmap_index = BloomHasher::hash_to_index(
-                key.to_le_bytes(),
-                seed as u64,
-                bitmap_num_bits(num_entries, bits_per_entry),
-            );
-            let (page_index, byte_index, bit_index) = page_bit_index(bitmap_index as usize);
+///Filter of a run that's being written. Its keys are collected as its pages are written, and the filter is only sized
+/// when the run is sealed, from the entries the run ended up with. So a merge that drops entries (e.g. discarded
+/// tombstones), or a write split over several runs, gets a filter per run sized for what that run holds
+pub struct BloomFilterBuilder {
+    keys: Option<Vec<Key>>, //None once the run has more than max_buffered_keys keys, it's read back when sealed
+    max_buffered_keys: Size,
+    bits_per_entry: Size,
+    key_codec: KeyCodec,
+}
 
-            if page_index != curr_page_index {
-                curr_page = get_bloom_page(run_address, page_index, buffer_pool.as_deref_mut())?;
-                curr_page_index = page_index;
-            }
+#[allow(dead_code)]
+impl BloomFilterBuilder {
+    ///Most keys held in memory (8MiB), the filters of larger runs are built by reading the run back
+    pub const DEFAULT_MAX_BUFFERED_KEYS: Size = 2_usize.pow(20);
 
-            if (curr_page[byte_index] & (1 << bit_index)) == 0 {
-                return Ok(false);
+    pub fn new(bits_per_entry: Size, key_codec: KeyCodec) -> Self {
+        Self {
+            keys: Some(Vec::new()),
+            max_buffered_keys: Self::DEFAULT_MAX_BUFFERED_KEYS,
+            bits_per_entry,
+            key_codec,
+        }
+    }
+    pub fn set_max_buffered_keys(mut self, max_buffered_keys: Size) -> Self {
+        self.max_buffered_keys = max_buffered_keys;
+        self
+    }
+    ///Add a page of the run being written
+    pub fn insert_entries(&mut self, entries: &[Entry]) {
+        if self
+            .keys
+            .as_ref()
+            .is_some_and(|keys| keys.len() + entries.len() > self.max_buffered_keys)
+        {
+            self.keys = None; //too large, fall back to reading the run back
+        }
+        if let Some(keys) = self.keys.as_mut() {
+            keys.extend(entries.iter().map(|(key, _)| *key));
+        }
+    }
+    ///Write the filter of the run at <run_address>, which holds the <num_entries> entries inserted since the last seal,
+    /// then start over for the next run
+    pub fn seal(&mut self, run_address: &RunAddress, num_entries: Size) -> io::Result<()> {
+        let keys = self.keys.replace(Vec::new());
+        match keys {
+            Some(keys) => {
+                assert_eq!(keys.len(), num_entries, "Miscounted the keys of the run");
+                BloomFilterIO::write_from_keys(
+                    run_address,
+                    &keys,
+                    self.bits_per_entry,
+                    self.key_codec,
+                )
             }
+            None => BloomFilterIO::write_from_sst(
+                run_address,
+                self.bits_per_entry,
+                num_entries,
+                self.key_codec,
+            ),
         }
-        Ok(true)
     }
 }
 
 #[cfg(test)]
 mod tests {
     use crate::{
+        filter::bloom_util::BYTE_SIZE,
         sst::{array_sst, SortedStringTable},
-        util::testing::setup_and_test_and_cleaup,
+        util::{
+            system_info::page_size,
+            testing::{self, setup_and_test_and_cleaup},
+        },
     };
 
     use super::*;
@@ -116,7 +318,7 @@ mod tests {
 
     #[test]
     fn test() {
-        let db_name = "test_bloom_io";
+        let db_name = &testing::test_db_name("test_bloom_io");
         const LEVEL: Level = 0;
         let mut test = || {
             let level = LEVEL;
@@ -125,26 +327,177 @@ mod tests {
             let bits_per_entry = 5;
             let entries = vec![(0, 0), (1001, 1001)];
             let num_entries = entries.len();
+            let key_codec = KeyCodec::default();
 
-            array_sst::Sst.write(run_address, &entries).unwrap();
-            BloomFilterIO::write_from_sst(run_address, bits_per_entry, num_entries).unwrap();
+            array_sst::Sst::default()
+                .write(run_address, &entries)
+                .unwrap();
+            BloomFilterIO::write_from_sst(run_address, bits_per_entry, num_entries, key_codec)
+                .unwrap();
 
             let read_filter =
-                BloomFilterIO::read(run_address, bits_per_entry, num_entries).unwrap();
+                BloomFilterIO::read(run_address, bits_per_entry, num_entries, key_codec).unwrap();
 
             assert!(read_filter.contains(0));
             assert!(read_filter.contains(1001));
             assert!(!read_filter.contains(1002));
 
             let contains = |key| {
-                BloomFilterIO::contains(run_address, key, bits_per_entry, entries.len(), None)
-                    .unwrap()
+                BloomFilterIO::contains(
+                    run_address,
+                    key,
+                    bits_per_entry,
+                    entries.len(),
+                    key_codec,
+                    None,
+                )
+                .unwrap()
             };
 
             assert!(contains(0));
             assert!(contains(1001));
             assert!(!contains(1002));
+            assert_eq!(
+                BloomFilterIO::contains_many(
+                    run_address,
+                    &[1002, 0, 1001],
+                    bits_per_entry,
+                    num_entries,
+                    key_codec,
+                    None
+                )
+                .unwrap(),
+                vec![false, true, true]
+            );
         };
         setup_and_test_and_cleaup(&(db_name, LEVEL), &mut test);
     }
+
+    #[test]
+    fn test_builder_sizes_each_run() {
+        let db_name = &testing::test_db_name("test_bloom_io_builder_sizes_each_run");
+        const LEVEL: Level = 0;
+        let mut test = || {
+            let bits_per_entry = 5;
+            let key_codec = KeyCodec::default();
+            let sst = array_sst::Sst::default();
+            //one stream of entries split over 3 runs of different sizes, through the buffered keys and read back
+            let entries: Vec<Entry> = (0..3000).map(|key| (key * 7, key)).collect();
+            let outputs = [&entries[..100], &entries[100..2900], &entries[2900..]];
+            for max_buffered_keys in [BloomFilterBuilder::DEFAULT_MAX_BUFFERED_KEYS, 500] {
+                let mut builder = BloomFilterBuilder::new(bits_per_entry, key_codec)
+                    .set_max_buffered_keys(max_buffered_keys);
+                for (run, output) in outputs.iter().enumerate() {
+                    let run_address = &(db_name, LEVEL, run);
+                    sst.write(run_address, output).unwrap();
+                    output
+                        .chunks(64)
+                        .for_each(|page| builder.insert_entries(page));
+                    builder.seal(run_address, output.len()).unwrap();
+
+                    let bitmap = db_name
+                        .storage()
+                        .read_file(&run_address.path_bloom())
+                        .unwrap();
+                    assert_eq!(bitmap.len(), bitmap_len(output.len(), bits_per_entry));
+                    let expected = BloomFilter::from_entries(output, bits_per_entry, key_codec);
+                    assert_eq!(bitmap, expected.bitmap);
+
+                    let contains = |key| {
+                        BloomFilterIO::contains(
+                            run_address,
+                            key,
+                            bits_per_entry,
+                            output.len(),
+                            key_codec,
+                            None,
+                        )
+                        .unwrap()
+                    };
+                    assert!(output.iter().all(|(key, _)| contains(*key)));
+                    let false_positives = (0..1000).filter(|key| contains(key * 7 + 3)).count();
+                    assert!(false_positives < 300, "{false_positives} false positives");
+                }
+            }
+        };
+        setup_and_test_and_cleaup(&(db_name, LEVEL), &mut test);
+    }
+
+    #[test]
+    fn test_probes_fetch_each_page_once() {
+        use rand::{Rng, SeedableRng};
+        use std::collections::BTreeSet;
+
+        let mut rng = rand::rngs::StdRng::seed_from_u64(0);
+        let key_codec = KeyCodec::default();
+        for bits_per_entry in [1, 5, 10] {
+            //a filter spread over several pages
+            let num_entries = 8 * page_size() * BYTE_SIZE / bits_per_entry;
+            let keys: Vec<Key> = (0..num_entries).map(|_| rng.gen()).collect();
+            let mut filter = BloomFilter::new(num_entries, bits_per_entry, key_codec);
+            for key in &keys {
+                filter.insert(*key);
+            }
+            let pages: Vec<&[u8]> = filter.bitmap.chunks(page_size()).collect();
+            assert!(pages.len() > 1);
+            let num_bits = bitmap_num_bits(num_entries, bits_per_entry);
+            let num_hash_functions = num_hash_functions(bits_per_entry);
+
+            let absent: Vec<Key> = (0..200).map(|_| rng.gen()).collect();
+            for key in keys.iter().take(200).chain(&absent) {
+                let mut fetched = Vec::new();
+                let contains = BloomFilterIO::contains_with(
+                    *key,
+                    bits_per_entry,
+                    num_entries,
+                    key_codec,
+                    &mut |page_index| {
+                        fetched.push(page_index);
+                        Ok(PageBuffer::from(pages[page_index].to_vec()))
+                    },
+                )
+                .unwrap();
+                assert_eq!(contains, filter.contains(*key), "key {key}");
+
+                //pages are fetched once each in order, all of them unless one of them has a 0 bit
+                let probes = BloomFilterIO::probes(*key, num_hash_functions, num_bits, key_codec);
+                let distinct_pages: Vec<Page> = probes
+                    .iter()
+                    .map(|(page_index, ..)| *page_index)
+                    .collect::<BTreeSet<Page>>()
+                    .into_iter()
+                    .collect();
+                match contains {
+                    true => assert_eq!(fetched, distinct_pages),
+                    false => assert!(distinct_pages.starts_with(&fetched), "{fetched:?}"),
+                }
+            }
+
+            let queries: Vec<Key> = keys.iter().take(300).chain(&absent).copied().collect();
+            let mut fetched = Vec::new();
+            let results = BloomFilterIO::contains_many_with(
+                &queries,
+                bits_per_entry,
+                num_entries,
+                key_codec,
+                &mut |page_index| {
+                    fetched.push(page_index);
+                    Ok(PageBuffer::from(pages[page_index].to_vec()))
+                },
+            )
+            .unwrap();
+            let expected: Vec<bool> = queries.iter().map(|key| filter.contains(*key)).collect();
+            assert_eq!(results, expected);
+            assert!(results.iter().take(300).all(|result| *result));
+            let mut distinct = fetched.clone();
+            distinct.dedup();
+            assert_eq!(fetched, distinct, "a page was fetched twice");
+            assert!(fetched.windows(2).all(|pair| pair[0] < pair[1]));
+        }
+        assert_eq!(
+            BloomFilterIO::contains_many_with(&[], 5, 10, key_codec, &mut |_| panic!("no fetch"))
+                .unwrap(),
+            Vec::<bool>::new()
+        );
+    }
 }
@@ -1,9 +1,9 @@
 use crate::{
     buffer_pool::BufferPool,
     ceil_div,
-    file_io::file_interface,
+    file_io::{file_interface, page_buffers::PageBuffer},
     util::{
-        filename,
+        filename::RunPaths,
         system_info::page_size,
         types::{Page, RunAddress, Size},
     },
@@ -11,12 +11,24 @@ use crate::{
 
 pub const BYTE_SIZE: usize = 8;
 
+///Number of bits asked for by <num_entries> entries with <bits_per_entry> bits each, panics if it doesn't fit in a Size
+/// (a bitmap that large couldn't be allocated anyway)
+fn requested_bits(num_entries: Size, bits_per_entry: Size) -> Size {
+    num_entries.checked_mul(bits_per_entry).unwrap_or_else(|| {
+        panic!("Bloom filter of {num_entries} entries with {bits_per_entry} bits per entry is too large")
+    })
+}
+
 pub fn bitmap_len(num_entries: Size, bits_per_entry: Size) -> Size {
-    ceil_div!(num_entries * bits_per_entry, BYTE_SIZE)
+    ceil_div!(requested_bits(num_entries, bits_per_entry), BYTE_SIZE)
 }
 
 pub fn bitmap_num_bits(num_entries: Size, bits_per_entry: Size) -> Size {
-    ceil_div!(num_entries * bits_per_entry, BYTE_SIZE) * BYTE_SIZE
+    bitmap_len(num_entries, bits_per_entry)
+        .checked_mul(BYTE_SIZE)
+        .unwrap_or_else(|| {
+            panic!("Bloom filter of {num_entries} entries with {bits_per_entry} bits per entry is too large")
+        })
 }
 
 ///Convert bitmap index to to (byte, bit) index
@@ -30,8 +42,9 @@ pub fn bit_index(bitmap_index: usize) -> (usize, usize) {
 pub fn page_bit_index(bitmap_index: usize) -> (Page, usize, usize) {
     let (byte_index, bit_index) = bit_index(bitmap_index);
 
-    let page_index = byte_index / page_size();
-    let byte_within_page_index = byte_index % page_size();
+    let page_size = page_size();
+    let page_index = byte_index / page_size;
+    let byte_within_page_index = byte_index % page_size;
 
     (page_index, byte_within_page_index, bit_index)
 }
@@ -45,9 +58,9 @@ pub fn get_bloom_page(
     run_address: &RunAddress,
     page_index: Page,
     buffer_pool: Option<&mut BufferPool>,
-) -> std::io::Result<Vec<u8>> {
-    let path = filename::bloom_filter_path(run_address);
-    file_interface::get_page(&path, page_index, buffer_pool)
+) -> std::io::Result<PageBuffer> {
+    let path = run_address.path_bloom();
+    file_interface::get_page(run_address.0.storage(), &path, page_index, buffer_pool)
 }
 
 #[test]
@@ -59,3 +72,19 @@ fn test_bitmap_indexing_conversion() {
         bitmap_index
     )
 }
+
+#[test]
+fn test_bitmap_size_overflow() {
+    assert_eq!(bitmap_len(Size::MAX / 8, 8), Size::MAX / 8);
+    assert_eq!(bitmap_num_bits(Size::MAX / 8, 8), Size::MAX / 8 * 8);
+    assert_eq!(bitmap_len(Size::MAX, 1), Size::MAX / 8 + 1);
+    for (num_entries, bits_per_entry) in [(Size::MAX / 2 + 1, 2), (2, Size::MAX), (Size::MAX, 1)] {
+        let too_large = std::panic::catch_unwind(|| bitmap_num_bits(num_entries, bits_per_entry));
+        assert!(
+            too_large.is_err(),
+            "{num_entries} entries, {bits_per_entry} bits"
+        );
+    }
+    let too_large = std::panic::catch_unwind(|| bitmap_len(Size::MAX / 2 + 1, 2));
+    assert!(too_large.is_err());
+}
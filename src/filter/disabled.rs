@@ -0,0 +1,75 @@
+//!Stand ins for builds without the bloom feature. Nothing is written and every key might be in every run,
+//! the database also keeps bloom filters switched off in these builds so these are never reached in practice
+
+pub mod bloom_io {
+    use std::{io, path::Path};
+
+    use crate::{
+        buffer_pool::BufferPool,
+        util::types::{Entry, Key, KeyCodec, RunAddress, Size},
+    };
+
+    pub struct BloomFilterBuilder {}
+
+    impl BloomFilterBuilder {
+        pub fn new(_bits_per_entry: Size, _key_codec: KeyCodec) -> Self {
+            Self {}
+        }
+        pub fn insert_entries(&mut self, _entries: &[Entry]) {}
+        pub fn seal(&mut self, _run_address: &RunAddress, _num_entries: Size) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    pub struct BloomFilterIO {}
+
+    impl BloomFilterIO {
+        ///No filter is ever needed
+        pub fn is_intact(
+            _run_address: &RunAddress,
+            _bits_per_entry: Size,
+            _num_entries: Size,
+        ) -> io::Result<bool> {
+            Ok(true)
+        }
+        pub fn write_from_sst(
+            _run_address: &RunAddress,
+            _bits_per_entry: Size,
+            _num_entries: Size,
+            _key_codec: KeyCodec,
+        ) -> io::Result<()> {
+            Ok(())
+        }
+        pub fn write_from_sst_to(
+            _path: &Path,
+            _run_address: &RunAddress,
+            _bits_per_entry: Size,
+            _num_entries: Size,
+            _key_codec: KeyCodec,
+        ) -> io::Result<()> {
+            Ok(())
+        }
+        ///Without a filter, any key might be in the run
+        pub fn contains(
+            _run_address: &RunAddress,
+            _key: Key,
+            _bits_per_entry: Size,
+            _num_entries: Size,
+            _key_codec: KeyCodec,
+            _buffer_pool: Option<&mut BufferPool>,
+        ) -> io::Result<bool> {
+            Ok(true)
+        }
+        #[allow(dead_code)]
+        pub fn contains_many(
+            _run_address: &RunAddress,
+            keys: &[Key],
+            _bits_per_entry: Size,
+            _num_entries: Size,
+            _key_codec: KeyCodec,
+            _buffer_pool: Option<&mut BufferPool>,
+        ) -> io::Result<Vec<bool>> {
+            Ok(vec![true; keys.len()])
+        }
+    }
+}
@@ -1,3 +1,11 @@
+#[cfg(feature = "bloom")]
 pub mod bloom_filter;
+#[cfg(feature = "bloom")]
 pub mod bloom_io;
+#[cfg(feature = "bloom")]
 pub mod bloom_util;
+
+#[cfg(not(feature = "bloom"))]
+mod disabled;
+#[cfg(not(feature = "bloom"))]
+pub use disabled::bloom_io;
@@ -0,0 +1,126 @@
+//!Audits of memtable flushes, see Database::set_flush_audit_dir. Each flush writes the entries it flushed to a file
+//! of its own, in the same format (and key encoding) as the run it wrote, so audits can be checked against the runs
+//! byte for byte. The flush's run, the largest sequence number it held and when it happened are in the file's name.
+//! NOTE: audits are kept on disk whatever the storage of the database (see Storage), they're meant to outlive it
+
+use std::{
+    fmt, io,
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    file_io::storage::Storage,
+    util::types::{Level, Run, Sequence, Timestamp},
+};
+
+const AUDIT_FILE_EXTENSION: &str = "audit";
+
+///A flush recorded in an audit directory
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FlushAudit {
+    pub level: Level,
+    pub run: Run,
+    pub max_sequence: Sequence, //largest sequence number flushed, the run keeps it until it's compacted
+    pub timestamp: Timestamp,   //database clock time of the flush
+    pub path: PathBuf,
+}
+
+impl FlushAudit {
+    ///Name of the audit file of a flush, <max sequence>-<level>-<run>-<timestamp>.audit
+    fn file_name(level: Level, run: Run, max_sequence: Sequence, timestamp: Timestamp) -> String {
+        format!("{max_sequence}-{level}-{run}-{timestamp}.{AUDIT_FILE_EXTENSION}")
+    }
+    fn parse(path: &Path) -> Option<Self> {
+        if path.extension()? != AUDIT_FILE_EXTENSION {
+            return None;
+        }
+        let stem = path.file_stem()?.to_str()?;
+        let [max_sequence, level, run, timestamp] = stem.split('-').collect::<Vec<_>>()[..] else {
+            return None;
+        };
+        Some(Self {
+            level: level.parse().ok()?,
+            run: run.parse().ok()?,
+            max_sequence: max_sequence.parse().ok()?,
+            timestamp: timestamp.parse().ok()?,
+            path: path.to_path_buf(),
+        })
+    }
+    ///The flushed entries, serialized like the run's SST file
+    pub fn read(&self) -> io::Result<Vec<u8>> {
+        Storage::disk().read_file(&self.path)
+    }
+}
+
+///What checking an audit against the database's runs found
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AuditCheck {
+    ///The run at <level>, <run> (where the flush put it, or wherever it was moved since) holds the audited bytes
+    Matches { level: Level, run: Run },
+    ///The run holding the flush's entries doesn't hold the audited bytes
+    Mismatch { level: Level, run: Run },
+    ///The flushed run was compacted into others since, there's nothing left to check it against
+    Superseded,
+}
+
+impl fmt::Display for AuditCheck {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AuditCheck::Matches { level, run } => write!(f, "matches level {level}, run {run}"),
+            AuditCheck::Mismatch { level, run } => {
+                write!(f, "doesn't match level {level}, run {run}")
+            }
+            AuditCheck::Superseded => write!(f, "superseded by a compaction"),
+        }
+    }
+}
+
+///Write the audit of a flush of <level>, <run>, returns the audit file's path
+pub(crate) fn write(
+    directory: &Path,
+    level: Level,
+    run: Run,
+    max_sequence: Sequence,
+    timestamp: Timestamp,
+    entry_bytes: &[u8],
+) -> io::Result<PathBuf> {
+    Storage::disk().create_dir_all(directory)?;
+    let path = directory.join(FlushAudit::file_name(level, run, max_sequence, timestamp));
+    Storage::disk().write_file(&path, entry_bytes)?;
+    Ok(path)
+}
+
+///Every audit in <directory>, oldest flush first
+pub fn list(directory: &Path) -> io::Result<Vec<FlushAudit>> {
+    let mut audits: Vec<FlushAudit> = Storage::disk()
+        .read_dir(directory)?
+        .iter()
+        .filter_map(|path| FlushAudit::parse(path))
+        .collect();
+    audits.sort_unstable_by_key(|audit| (audit.max_sequence, audit.timestamp));
+    Ok(audits)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_file_name_round_trip() {
+        let name = FlushAudit::file_name(0, 3, 120, 99);
+        assert_eq!(name, "120-0-3-99.audit");
+        let path = Path::new("audits").join(name);
+        assert_eq!(
+            FlushAudit::parse(&path),
+            Some(FlushAudit {
+                level: 0,
+                run: 3,
+                max_sequence: 120,
+                timestamp: 99,
+                path: path.clone(),
+            })
+        );
+        assert_eq!(FlushAudit::parse(Path::new("audits/1-2-3.audit")), None);
+        assert_eq!(FlushAudit::parse(Path::new("audits/1-2-3-4.sst")), None);
+    }
+}
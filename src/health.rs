@@ -0,0 +1,71 @@
+//!Cheap readiness checks for services embedding a database, see Database::health_check. Every check only looks at
+//! small files and in-memory state, apart from the optional canary read of one page
+
+use std::fmt;
+
+///What Database::health_check looks at, in the order it checks them
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum HealthCheck {
+    ///The handle still has a database open (it wasn't closed or cleared)
+    Open,
+    ///The database's root directory exists
+    Directory,
+    ///The config and metadata files exist and can be decoded
+    Metadata,
+    ///The buffer pool holds no more bytes than its capacity
+    BufferPool,
+    ///No compaction (foreground or background) has failed since the last one that succeeded
+    LatchedError,
+    ///The first page of the newest run can be read and holds the entries the metadata says it does
+    Canary,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CheckStatus {
+    Pass,
+    Fail(String),
+    ///Not run, e.g. the canary wasn't asked for or there is no run to read
+    Skipped(String),
+}
+
+///Status of each check, in HealthCheck order
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HealthReport {
+    pub checks: Vec<(HealthCheck, CheckStatus)>,
+}
+
+#[allow(dead_code)]
+impl HealthReport {
+    ///Whether no check failed, skipped checks don't count against it
+    pub fn is_healthy(&self) -> bool {
+        self.failures().next().is_none()
+    }
+    ///Checks that failed, with why
+    pub fn failures(&self) -> impl Iterator<Item = (HealthCheck, &str)> {
+        self.checks
+            .iter()
+            .filter_map(|(check, status)| match status {
+                CheckStatus::Fail(why) => Some((*check, why.as_str())),
+                _ => None,
+            })
+    }
+    pub fn status(&self, check: HealthCheck) -> Option<&CheckStatus> {
+        self.checks
+            .iter()
+            .find(|(other, _)| *other == check)
+            .map(|(_, status)| status)
+    }
+}
+
+impl fmt::Display for HealthReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (check, status) in &self.checks {
+            match status {
+                CheckStatus::Pass => writeln!(f, "{check:?}: ok")?,
+                CheckStatus::Fail(why) => writeln!(f, "{check:?}: failed, {why}")?,
+                CheckStatus::Skipped(why) => writeln!(f, "{check:?}: skipped, {why}")?,
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,374 @@
+//!Importing flat files of "key<delimiter>value" integer lines, see Database::import_delimited. Input that doesn't fit
+//! in the memory budget is sorted in chunks that are spilled to temporary files and merged back in key order
+
+use std::{
+    cmp::Reverse,
+    collections::BinaryHeap,
+    io::{self, BufRead, BufReader, BufWriter, Read, Write},
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    db::Database,
+    error::DbError,
+    file_io::storage::{DbFile, Storage},
+    util::types::{Key, Size, Value},
+};
+
+///Separator between the key and value of each line
+#[allow(dead_code)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Delimiter {
+    Tab,
+    Comma,
+}
+
+impl Delimiter {
+    fn as_char(self) -> char {
+        match self {
+            Delimiter::Tab => '\t',
+            Delimiter::Comma => ',',
+        }
+    }
+}
+
+///What to do with a key that's on more than one line
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DuplicateKeys {
+    LastWins, //the value on the last line is imported
+    Error,
+}
+
+///Options for Database::import_delimited_with_options
+#[derive(Debug, Clone, Copy)]
+pub struct ImportOptions {
+    pub delimiter: Delimiter,
+    pub has_header: bool, //skip the first line
+    pub duplicate_keys: DuplicateKeys,
+    pub memory_budget: Size, //bytes of parsed lines held in memory before they're sorted and spilled to disk
+}
+
+impl ImportOptions {
+    pub const DEFAULT_MEMORY_BUDGET: Size = 64 * 2_usize.pow(20); //64MB
+
+    pub fn new(delimiter: Delimiter, has_header: bool) -> Self {
+        Self {
+            delimiter,
+            has_header,
+            duplicate_keys: DuplicateKeys::LastWins,
+            memory_budget: Self::DEFAULT_MEMORY_BUDGET,
+        }
+    }
+}
+
+impl Default for ImportOptions {
+    fn default() -> Self {
+        Self::new(Delimiter::Tab, false)
+    }
+}
+
+///A parsed line: key, value and (1 based) line number, the line number orders duplicate keys
+type Line = (Key, Value, Size);
+const LINE_SIZE: Size =
+    std::mem::size_of::<Key>() + std::mem::size_of::<Value>() + std::mem::size_of::<u64>();
+
+fn import_error(line: Size, reason: String) -> DbError {
+    DbError::Import { line, reason }
+}
+
+///Parse one line, None if it's blank
+fn parse_line(text: &str, line: Size, delimiter: Delimiter) -> Result<Option<Line>, DbError> {
+    if text.trim().is_empty() {
+        return Ok(None);
+    }
+    let fields: Vec<&str> = text.split(delimiter.as_char()).map(str::trim).collect();
+    let [key, value] = fields[..] else {
+        return Err(import_error(
+            line,
+            format!("expected a key and a value separated by {delimiter:?}, found \"{text}\""),
+        ));
+    };
+    let key = key
+        .parse::<Key>()
+        .map_err(|why| import_error(line, format!("invalid key \"{key}\", {why}")))?;
+    let value = value
+        .parse::<Value>()
+        .map_err(|why| import_error(line, format!("invalid value \"{value}\", {why}")))?;
+    if key == Database::INVALID_KEY {
+        return Err(import_error(line, format!("key {key} is reserved")));
+    }
+    if value == Database::TOMBSTONE_VALUE {
+        return Err(import_error(line, format!("value {value} is reserved")));
+    }
+    Ok(Some((key, value, line)))
+}
+
+fn duplicate_key_error(key: Key, first_line: Size, line: Size) -> DbError {
+    import_error(
+        line,
+        format!("duplicate key {key}, first seen on line {first_line}"),
+    )
+}
+
+///Sort a chunk by key and drop all but the last line of each key (or fail on the first duplicate)
+fn sort_chunk(chunk: &mut Vec<Line>, duplicate_keys: DuplicateKeys) -> Result<(), DbError> {
+    chunk.sort_unstable_by_key(|&(key, _, line)| (key, line));
+    if duplicate_keys == DuplicateKeys::Error {
+        if let Some(pair) = chunk.windows(2).find(|pair| pair[0].0 == pair[1].0) {
+            return Err(duplicate_key_error(pair[0].0, pair[0].2, pair[1].2));
+        }
+    }
+    //dedup_by keeps the first of each run of equal keys, reversing makes that the last line
+    chunk.reverse();
+    chunk.dedup_by_key(|(key, _, _)| *key);
+    chunk.reverse();
+    Ok(())
+}
+
+fn chunk_path(temp_dir: &Path, chunk: Size) -> PathBuf {
+    temp_dir.join(format!("{chunk}.chunk"))
+}
+
+fn spill_chunk(storage: &Storage, chunk: &[Line], path: &Path) -> io::Result<()> {
+    let mut writer = BufWriter::new(storage.create_buffered(path)?);
+    for (key, value, line) in chunk {
+        writer.write_all(&key.to_le_bytes())?;
+        writer.write_all(&value.to_le_bytes())?;
+        writer.write_all(&(*line as u64).to_le_bytes())?;
+    }
+    writer.flush()
+}
+
+///Reads back the lines of a spilled chunk in order
+struct ChunkReader(BufReader<DbFile>);
+
+impl ChunkReader {
+    fn open(storage: &Storage, path: &Path) -> io::Result<Self> {
+        Ok(Self(BufReader::new(storage.open_read_buffered(path)?)))
+    }
+    fn next(&mut self) -> io::Result<Option<Line>> {
+        let mut bytes = [0; LINE_SIZE];
+        match self.0.read_exact(&mut bytes) {
+            Ok(()) => {}
+            Err(why) if why.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+            Err(why) => return Err(why),
+        }
+        let key = Key::from_le_bytes(bytes[0..8].try_into().unwrap());
+        let value = Value::from_le_bytes(bytes[8..16].try_into().unwrap());
+        let line = u64::from_le_bytes(bytes[16..24].try_into().unwrap()) as Size;
+        Ok(Some((key, value, line)))
+    }
+}
+
+///K-way merge of the spilled chunks, calls <ingest> once per key in key order with the value of its last line.
+/// Stops at the first error <ingest> returns
+fn merge_chunks(
+    storage: &Storage,
+    temp_dir: &Path,
+    num_chunks: Size,
+    duplicate_keys: DuplicateKeys,
+    ingest: &mut dyn FnMut(Key, Value) -> Result<(), DbError>,
+) -> Result<Size, DbError> {
+    let mut readers = (0..num_chunks)
+        .map(|chunk| ChunkReader::open(storage, &chunk_path(temp_dir, chunk)))
+        .collect::<io::Result<Vec<_>>>()?;
+    let mut heap = BinaryHeap::new();
+    for (chunk, reader) in readers.iter_mut().enumerate() {
+        if let Some((key, value, line)) = reader.next()? {
+            heap.push(Reverse((key, line, value, chunk)));
+        }
+    }
+
+    let mut num_keys = 0;
+    let mut previous: Option<Line> = None; //last line seen of the key being merged
+    while let Some(Reverse((key, line, value, chunk))) = heap.pop() {
+        if let Some(next) = readers[chunk].next()? {
+            let (next_key, next_value, next_line) = next;
+            heap.push(Reverse((next_key, next_line, next_value, chunk)));
+        }
+        if let Some((previous_key, previous_value, previous_line)) = previous {
+            if previous_key != key {
+                ingest(previous_key, previous_value)?;
+                num_keys += 1;
+            } else if duplicate_keys == DuplicateKeys::Error {
+                return Err(duplicate_key_error(key, previous_line, line));
+            }
+        }
+        previous = Some((key, value, line)); //the heap orders equal keys by line, so this is the latest line so far
+    }
+    if let Some((key, value, _)) = previous {
+        ingest(key, value)?;
+        num_keys += 1;
+    }
+    Ok(num_keys)
+}
+
+///Parse, validate and sort every line of <reader> before calling <ingest> once per key in key order.
+/// Returns the number of keys ingested. Chunks past the memory budget are spilled to <temp_dir> in <storage>, which
+/// is removed when this returns
+pub fn import_sorted(
+    reader: impl BufRead,
+    options: &ImportOptions,
+    storage: &Storage,
+    temp_dir: &Path,
+    ingest: &mut dyn FnMut(Key, Value) -> Result<(), DbError>,
+) -> Result<Size, DbError> {
+    let result = sort_and_ingest(reader, options, storage, temp_dir, ingest);
+    if storage.path_exists(temp_dir) {
+        storage.remove_dir_all(temp_dir)?;
+    }
+    result
+}
+
+fn sort_and_ingest(
+    reader: impl BufRead,
+    options: &ImportOptions,
+    storage: &Storage,
+    temp_dir: &Path,
+    ingest: &mut dyn FnMut(Key, Value) -> Result<(), DbError>,
+) -> Result<Size, DbError> {
+    let chunk_capacity = (options.memory_budget / LINE_SIZE).max(1);
+    let mut chunk = Vec::new();
+    let mut num_chunks = 0;
+    for (index, text) in reader.lines().enumerate() {
+        let line = index + 1;
+        let text = text.map_err(|why| import_error(line, format!("unable to read line, {why}")))?;
+        if line == 1 && options.has_header {
+            continue;
+        }
+        let Some(parsed) = parse_line(&text, line, options.delimiter)? else {
+            continue;
+        };
+        chunk.push(parsed);
+        if chunk.len() >= chunk_capacity {
+            sort_chunk(&mut chunk, options.duplicate_keys)?;
+            if num_chunks == 0 {
+                storage.create_dir_all(temp_dir)?;
+            }
+            spill_chunk(storage, &chunk, &chunk_path(temp_dir, num_chunks))?;
+            num_chunks += 1;
+            chunk.clear();
+        }
+    }
+    sort_chunk(&mut chunk, options.duplicate_keys)?;
+
+    if num_chunks == 0 {
+        for (key, value, _) in &chunk {
+            ingest(*key, *value)?;
+        }
+        return Ok(chunk.len());
+    }
+    if !chunk.is_empty() {
+        spill_chunk(storage, &chunk, &chunk_path(temp_dir, num_chunks))?;
+        num_chunks += 1;
+    }
+    if options.duplicate_keys == DuplicateKeys::Error {
+        //find duplicates across chunks before anything is ingested
+        merge_chunks(
+            storage,
+            temp_dir,
+            num_chunks,
+            DuplicateKeys::Error,
+            &mut |_, _| Ok(()),
+        )?;
+    }
+    merge_chunks(
+        storage,
+        temp_dir,
+        num_chunks,
+        options.duplicate_keys,
+        ingest,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::util::testing;
+
+    fn import(
+        text: &str,
+        options: &ImportOptions,
+        storage: &Storage,
+        temp_dir: &Path,
+    ) -> Result<Vec<(Key, Value)>, DbError> {
+        let mut entries = Vec::new();
+        import_sorted(
+            text.as_bytes(),
+            options,
+            storage,
+            temp_dir,
+            &mut |key, value| {
+                entries.push((key, value));
+                Ok(())
+            },
+        )?;
+        Ok(entries)
+    }
+
+    fn error_line(result: Result<Vec<(Key, Value)>, DbError>) -> Size {
+        match result {
+            Err(DbError::Import { line, .. }) => line,
+            other => panic!("expected an import error, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn test_parse_line() {
+        assert_eq!(
+            parse_line(" 3\t-4 \r", 7, Delimiter::Tab).unwrap(),
+            Some((3, -4, 7))
+        );
+        assert_eq!(
+            parse_line("5,6", 1, Delimiter::Comma).unwrap(),
+            Some((5, 6, 1))
+        );
+        assert_eq!(parse_line("  ", 1, Delimiter::Tab).unwrap(), None);
+        for bad in ["5,6", "1\t2\t3", "a\t1", "1\t2.5", "1\t"] {
+            assert!(parse_line(bad, 1, Delimiter::Tab).is_err(), "{bad}");
+        }
+        let reserved_value = format!("1\t{}", Database::TOMBSTONE_VALUE);
+        assert!(parse_line(&reserved_value, 1, Delimiter::Tab).is_err());
+        let reserved_key = format!("{}\t1", Database::INVALID_KEY);
+        assert!(parse_line(&reserved_key, 1, Delimiter::Tab).is_err());
+    }
+
+    #[test]
+    fn test_import_sorted() {
+        let temp_dir = testing::test_dir("import_sorted_test");
+        let storage = &Storage::in_memory();
+        let text = "key,value\n3,30\n1,10\n\n2,20\n1,11\n";
+        for memory_budget in [Size::MAX, LINE_SIZE] {
+            //in memory, and every line spilled to its own chunk
+            let options = ImportOptions {
+                memory_budget,
+                ..ImportOptions::new(Delimiter::Comma, true)
+            };
+            assert_eq!(
+                import(text, &options, storage, temp_dir).unwrap(),
+                vec![(1, 11), (2, 20), (3, 30)]
+            );
+            assert!(!storage.path_exists(temp_dir));
+
+            let strict = ImportOptions {
+                duplicate_keys: DuplicateKeys::Error,
+                ..options
+            };
+            let mut ingested = 0;
+            let result = import_sorted(text.as_bytes(), &strict, storage, temp_dir, &mut |_, _| {
+                ingested += 1;
+                Ok(())
+            });
+            assert!(matches!(result, Err(DbError::Import { line: 6, .. })));
+            assert_eq!(ingested, 0);
+            assert!(!storage.path_exists(temp_dir));
+        }
+
+        //without a header the first line has to parse
+        let options = ImportOptions::new(Delimiter::Comma, false);
+        assert_eq!(error_line(import(text, &options, storage, temp_dir)), 1);
+        let bad_line = import("1,1\n2;2\n", &options, storage, temp_dir);
+        assert_eq!(error_line(bad_line), 2);
+        assert_eq!(import("", &options, storage, temp_dir).unwrap(), vec![]);
+    }
+}
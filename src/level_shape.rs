@@ -0,0 +1,144 @@
+use crate::util::types::{Level, Size};
+
+///Number of runs and entries in each level along with their totals, a summary of the metadata's entry counts that is
+/// rebuilt on open and kept up to date as runs are flushed, moved and merged, so flush and compaction decisions
+/// don't have to walk every level
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct LevelShape {
+    runs_per_level: Vec<Size>,
+    entries_per_level: Vec<Size>,
+    total_runs: Size,
+    total_entries: Size,
+}
+
+impl LevelShape {
+    pub fn from_entry_counts(entry_counts: &[Vec<Size>]) -> Self {
+        let runs_per_level: Vec<Size> = entry_counts.iter().map(Vec::len).collect();
+        let entries_per_level: Vec<Size> = entry_counts
+            .iter()
+            .map(|level_entry_counts| level_entry_counts.iter().sum())
+            .collect();
+        Self {
+            total_runs: runs_per_level.iter().sum(),
+            total_entries: entries_per_level.iter().sum(),
+            runs_per_level,
+            entries_per_level,
+        }
+    }
+    pub fn num_levels(&self) -> Size {
+        self.runs_per_level.len()
+    }
+    ///Number of runs in level, NOTE: a value of 0 can mean that there is no level
+    pub fn run_count(&self, level: Level) -> Size {
+        self.runs_per_level.get(level).copied().unwrap_or(0)
+    }
+    ///Number of entries in every run of a level, tombstones and shadowed entries included
+    #[allow(dead_code)]
+    pub fn entry_count(&self, level: Level) -> Size {
+        self.entries_per_level.get(level).copied().unwrap_or(0)
+    }
+    pub fn total_runs(&self) -> Size {
+        self.total_runs
+    }
+    #[allow(dead_code)]
+    pub fn total_entries(&self) -> Size {
+        self.total_entries
+    }
+    fn add_levels_up_to(&mut self, level: Level) {
+        if level >= self.num_levels() {
+            self.runs_per_level.resize(level + 1, 0);
+            self.entries_per_level.resize(level + 1, 0);
+        }
+    }
+    ///A new run was written to <level>
+    pub fn push_run(&mut self, level: Level, num_entries: Size) {
+        self.add_levels_up_to(level);
+        self.runs_per_level[level] += 1;
+        self.entries_per_level[level] += num_entries;
+        self.total_runs += 1;
+        self.total_entries += num_entries;
+    }
+    ///Every run of <level> was moved to the next level
+    pub fn move_runs(&mut self, level: Level) {
+        self.add_levels_up_to(level + 1);
+        self.runs_per_level[level + 1] += std::mem::take(&mut self.runs_per_level[level]);
+        self.entries_per_level[level + 1] += std::mem::take(&mut self.entries_per_level[level]);
+    }
+    ///Every run of <level> was merged into a single run of <entries_written> entries, no run is left if it's 0
+    pub fn merge_runs(&mut self, level: Level, entries_written: Size) {
+        self.add_levels_up_to(level);
+        let runs_written = Size::from(entries_written > 0);
+        self.total_runs = self.total_runs - self.runs_per_level[level] + runs_written;
+        self.total_entries = self.total_entries - self.entries_per_level[level] + entries_written;
+        self.runs_per_level[level] = runs_written;
+        self.entries_per_level[level] = entries_written;
+    }
+    ///The runs of <level> were replaced by <num_runs> runs holding <num_entries> entries in all
+    pub fn replace_runs(&mut self, level: Level, num_runs: Size, num_entries: Size) {
+        self.add_levels_up_to(level);
+        self.total_runs = self.total_runs - self.runs_per_level[level] + num_runs;
+        self.total_entries = self.total_entries - self.entries_per_level[level] + num_entries;
+        self.runs_per_level[level] = num_runs;
+        self.entries_per_level[level] = num_entries;
+    }
+    ///A run of <level> was rewritten without <entries_dropped> of its entries
+    pub fn shrink_run(&mut self, level: Level, entries_dropped: Size) {
+        self.entries_per_level[level] -= entries_dropped;
+        self.total_entries -= entries_dropped;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_from_entry_counts() {
+        let shape = LevelShape::from_entry_counts(&[vec![3, 4], vec![], vec![10]]);
+        assert_eq!(shape.num_levels(), 3);
+        assert_eq!(shape.run_count(0), 2);
+        assert_eq!(shape.entry_count(0), 7);
+        assert_eq!(shape.run_count(1), 0);
+        assert_eq!(shape.run_count(5), 0);
+        assert_eq!(shape.entry_count(5), 0);
+        assert_eq!(shape.total_runs(), 3);
+        assert_eq!(shape.total_entries(), 17);
+    }
+
+    #[test]
+    fn test_updates_match_rebuild() {
+        let mut entry_counts = vec![vec![]];
+        let mut shape = LevelShape::from_entry_counts(&entry_counts);
+
+        shape.push_run(0, 4);
+        shape.push_run(0, 2);
+        entry_counts[0] = vec![4, 2];
+        assert_eq!(shape, LevelShape::from_entry_counts(&entry_counts));
+
+        shape.move_runs(0);
+        entry_counts = vec![vec![], vec![4, 2]];
+        assert_eq!(shape, LevelShape::from_entry_counts(&entry_counts));
+
+        shape.merge_runs(1, 5);
+        entry_counts[1] = vec![5];
+        assert_eq!(shape, LevelShape::from_entry_counts(&entry_counts));
+
+        shape.shrink_run(1, 2);
+        entry_counts[1] = vec![3];
+        assert_eq!(shape, LevelShape::from_entry_counts(&entry_counts));
+
+        shape.replace_runs(1, 3, 9);
+        entry_counts[1] = vec![2, 3, 4];
+        assert_eq!(shape, LevelShape::from_entry_counts(&entry_counts));
+
+        shape.replace_runs(1, 1, 3);
+        entry_counts[1] = vec![3];
+        assert_eq!(shape, LevelShape::from_entry_counts(&entry_counts));
+
+        //everything was deleted
+        shape.merge_runs(1, 0);
+        entry_counts[1] = vec![];
+        assert_eq!(shape, LevelShape::from_entry_counts(&entry_counts));
+        assert_eq!(shape.total_runs(), 0);
+    }
+}
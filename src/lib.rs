@@ -1,8 +1,33 @@
+#[cfg(not(any(feature = "direct-io", feature = "minimal-io")))]
+compile_error!("an I/O backend is required, enable either the direct-io or minimal-io feature");
+
 mod buffer_pool;
+mod compaction;
 mod data_structures;
 pub mod db;
+pub mod disk_usage;
+pub mod error;
+pub mod event;
+pub mod explain;
+#[cfg(feature = "ffi")]
+pub mod ffi;
 mod file_io;
 mod filter;
+pub mod flush_audit;
+pub mod health;
+pub mod import;
+pub mod level_shape;
+pub mod maintenance;
+pub mod manager;
 mod memtable;
+mod read_cache;
+pub mod registry;
+pub mod salvage;
+pub mod snapshot;
 mod sst;
+pub mod stats;
 pub mod util;
+
+pub use compaction::size_ratio;
+pub use file_io::storage::Storage;
+pub use sst::export;
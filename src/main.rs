@@ -1,12 +1,100 @@
-mod buffer_pool;
-mod data_structures;
-mod db;
-mod file_io;
-mod filter;
-mod memtable;
-mod sst;
-mod util;
+//!Demo shell for a database: opens (or creates) the database in the given directory, then runs one command per line
+//! from stdin until the input ends or quit.
+//! Usage: cargo run -- <database directory>
+//! Commands: put <key> <value>, get <key>, delete <key>, scan <low key> <high key>, quit
+
+use std::{
+    io::{self, BufRead, Write},
+    path::Path,
+    process,
+};
+
+use key_value::{
+    db::Database,
+    util::{
+        system_info,
+        types::{Key, Value},
+    },
+};
+
+const USAGE: &str = "usage: key_value <database directory>";
+const COMMANDS: &str =
+    "commands: put <key> <value>, get <key>, delete <key>, scan <low key> <high key>, quit";
+
+fn parse_number(name: &str, arg: &str) -> Result<i64, String> {
+    arg.parse::<i64>()
+        .map_err(|why| format!("invalid {name} \"{arg}\", {why}"))
+}
+
+///Runs one line of input, returns the lines to print
+fn run_command(db: &mut Database, words: &[&str]) -> Result<Vec<String>, String> {
+    match words {
+        ["put", key, value] => {
+            let key: Key = parse_number("key", key)?;
+            let value: Value = parse_number("value", value)?;
+            db.put(key, value).map_err(|why| why.to_string())?;
+            Ok(vec![])
+        }
+        ["get", key] => {
+            let key: Key = parse_number("key", key)?;
+            match db.get(key) {
+                Some(value) => Ok(vec![value.to_string()]),
+                None => Ok(vec!["not found".to_string()]),
+            }
+        }
+        ["delete", key] => {
+            let key: Key = parse_number("key", key)?;
+            db.delete(key).map_err(|why| why.to_string())?;
+            Ok(vec![])
+        }
+        ["scan", low, high] => {
+            let low: Key = parse_number("low key", low)?;
+            let high: Key = parse_number("high key", high)?;
+            Ok(db
+                .scan(low, high)
+                .into_iter()
+                .map(|(key, value)| format!("{key},{value}"))
+                .collect())
+        }
+        _ => Err(COMMANDS.to_string()),
+    }
+}
 
 fn main() {
-    println!("Hello, world!");
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let [db_name] = args.as_slice() else {
+        eprintln!("{USAGE}");
+        process::exit(2);
+    };
+    let mut db = match Database::try_open(Path::new(db_name)) {
+        Ok(db) => db,
+        Err(why) => {
+            eprintln!("unable to open {db_name}, {why}");
+            process::exit(2);
+        }
+    };
+    if system_info::page_size_was_defaulted() {
+        eprintln!(
+            "WARNING: the system reported an unusable page size, using {} bytes instead",
+            system_info::DEFAULT_PAGE_SIZE
+        );
+    }
+    let mut stdout = io::stdout().lock();
+    for line in io::stdin().lock().lines() {
+        let Ok(line) = line else {
+            break;
+        };
+        let words: Vec<&str> = line.split_whitespace().collect();
+        match words.as_slice() {
+            [] => continue,
+            ["quit"] => break,
+            words => match run_command(&mut db, words) {
+                Ok(output) => output.iter().for_each(|line| {
+                    let _ = writeln!(stdout, "{line}");
+                }),
+                Err(why) => eprintln!("{why}"),
+            },
+        }
+    }
+    db.close();
 }
@@ -0,0 +1,222 @@
+use std::collections::{HashMap, HashSet};
+
+#[cfg(feature = "serde-config")]
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    file_io::serde_state::{self, impl_struct_codec},
+    util::types::{Level, Run, Sequence, Size},
+};
+
+///One unit of maintenance work, see Database::maintain. Variants are declared in the order they're picked in
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum MaintenanceTask {
+    ///Merge the runs of a level that holds more than it should, e.g. level 0 at its limit under
+    /// CompactionPolicy::None or a level whose compaction failed
+    Compact { level: Level },
+    ///Rewrite a run older than a run holding tombstones without the entries younger sources hide, see
+    /// Database::rewrite_run
+    CollectTombstones { level: Level, run: Run },
+    ///Write the bloom filter of a run in a level that has them, e.g. one written before bloom_levels changed
+    BackfillFilter { level: Level, run: Run },
+    ///Read a run in full and check its keys are sorted and as many as the metadata says
+    Verify { level: Level, run: Run },
+}
+
+///Per run metadata the scheduler decides from (same layout as the database's metadata)
+#[derive(Clone, Copy)]
+pub struct RunStats<'a> {
+    pub entry_counts: &'a [Vec<Size>],
+    pub tombstone_counts: &'a [Vec<Size>],
+    pub max_sequences: &'a [Vec<Sequence>],
+}
+
+///A run as its metadata describes it, a run that is moved or rewritten gets a new one
+type RunId = ((Level, Run), (Size, Sequence));
+
+impl RunStats<'_> {
+    fn run_id(&self, level: Level, run: Run) -> RunId {
+        let entries = self.entry_counts[level][run];
+        ((level, run), (entries, self.max_sequences[level][run]))
+    }
+    ///Every run, youngest first
+    fn runs(&self) -> impl Iterator<Item = (Level, Run)> + '_ {
+        (self.entry_counts.iter().enumerate())
+            .flat_map(|(level, runs)| (0..runs.len()).rev().map(move |run| (level, run)))
+    }
+    ///Newest write of the youngest run holding tombstones that's younger than each run, youngest first
+    fn tombstones_above(&self) -> impl Iterator<Item = ((Level, Run), Option<Sequence>)> + '_ {
+        let mut newest: Option<Sequence> = None;
+        self.runs().map(move |(level, run)| {
+            let above = newest;
+            if self.tombstone_counts[level][run] > 0 {
+                newest = newest.max(Some(self.max_sequences[level][run]));
+            }
+            ((level, run), above)
+        })
+    }
+}
+
+///Picks the next maintenance task from what's pending, in MaintenanceTask order. Which runs were already collected
+/// and verified is remembered across opens (see serialize), so bounded calls to Database::maintain pick up where the
+/// last one stopped
+#[derive(Debug, Default)]
+pub struct MaintenanceScheduler {
+    collected: HashMap<RunId, Sequence>, //runs rewritten without what's hidden by tombstones up to this sequence
+    verified: HashSet<RunId>,
+}
+
+///On disk layout of the scheduler's progress, written on close and read back on open
+#[cfg_attr(feature = "serde-config", derive(Serialize, Deserialize))]
+struct MaintenanceFile {
+    collected: Vec<(RunId, Sequence)>,
+    verified: Vec<RunId>,
+}
+
+impl_struct_codec!(MaintenanceFile {
+    collected,
+    verified
+});
+
+impl MaintenanceScheduler {
+    ///Highest priority task, given the levels with more runs than they should hold and the runs missing a bloom
+    /// filter. None once there's nothing left to do
+    pub fn next_task(
+        &self,
+        stats: &RunStats,
+        debt_levels: &[Level],
+        missing_filters: &[(Level, Run)],
+    ) -> Option<MaintenanceTask> {
+        if let Some(&level) = debt_levels.iter().min() {
+            return Some(MaintenanceTask::Compact { level });
+        }
+        let uncollected = stats.tombstones_above().find(|((level, run), above)| {
+            let collected = self.collected.get(&stats.run_id(*level, *run));
+            above.is_some_and(|above| collected.is_none_or(|collected| *collected < above))
+        });
+        if let Some(((level, run), _)) = uncollected {
+            return Some(MaintenanceTask::CollectTombstones { level, run });
+        }
+        if let Some(&(level, run)) = missing_filters.iter().min() {
+            return Some(MaintenanceTask::BackfillFilter { level, run });
+        }
+        stats
+            .runs()
+            .find(|(level, run)| !self.verified.contains(&stats.run_id(*level, *run)))
+            .map(|(level, run)| MaintenanceTask::Verify { level, run })
+    }
+    ///Call once run <run> of <level> has been rewritten by a CollectTombstones task, <stats> are as of after
+    pub fn record_collected(&mut self, stats: &RunStats, level: Level, run: Run) {
+        let above = stats
+            .tombstones_above()
+            .find_map(|(address, above)| (address == (level, run)).then_some(above))
+            .flatten();
+        if let Some(above) = above {
+            self.collected.insert(stats.run_id(level, run), above);
+        }
+    }
+    pub fn record_verified(&mut self, stats: &RunStats, level: Level, run: Run) {
+        self.verified.insert(stats.run_id(level, run));
+    }
+    ///Forget runs that don't exist anymore (compacted, moved or rewritten)
+    pub fn retain_runs(&mut self, stats: &RunStats) {
+        let run_ids: HashSet<RunId> = stats
+            .runs()
+            .map(|(level, run)| stats.run_id(level, run))
+            .collect();
+        self.collected.retain(|run_id, _| run_ids.contains(run_id));
+        self.verified.retain(|run_id| run_ids.contains(run_id));
+    }
+    pub fn is_empty(&self) -> bool {
+        self.collected.is_empty() && self.verified.is_empty()
+    }
+
+    pub fn serialize(&self) -> Vec<u8> {
+        let mut collected: Vec<(RunId, Sequence)> =
+            self.collected.iter().map(|(id, seq)| (*id, *seq)).collect();
+        let mut verified: Vec<RunId> = self.verified.iter().copied().collect();
+        collected.sort_unstable();
+        verified.sort_unstable();
+        serde_state::serialize(&MaintenanceFile {
+            collected,
+            verified,
+        })
+    }
+    pub fn deserialize(bytes: &[u8]) -> Result<Self, String> {
+        let file: MaintenanceFile = serde_state::deserialize(bytes)?;
+        Ok(Self {
+            collected: file.collected.into_iter().collect(),
+            verified: file.verified.into_iter().collect(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_priority_order() {
+        //level 0 run 1 holds tombstones, so run 0 and the level 1 run under it have entries to collect
+        let entry_counts = vec![vec![4, 4], vec![8]];
+        let tombstone_counts = vec![vec![0, 2], vec![0]];
+        let max_sequences = vec![vec![12, 16], vec![8]];
+        let stats = RunStats {
+            entry_counts: &entry_counts,
+            tombstone_counts: &tombstone_counts,
+            max_sequences: &max_sequences,
+        };
+        let mut scheduler = MaintenanceScheduler::default();
+        assert_eq!(
+            scheduler.next_task(&stats, &[1, 0], &[(1, 0)]),
+            Some(MaintenanceTask::Compact { level: 0 })
+        );
+        let mut tasks = Vec::new();
+        let mut missing_filters = vec![(1, 0)];
+        while let Some(task) = scheduler.next_task(&stats, &[], &missing_filters) {
+            match task {
+                MaintenanceTask::Compact { .. } => unreachable!("no level has debt"),
+                MaintenanceTask::CollectTombstones { level, run } => {
+                    scheduler.record_collected(&stats, level, run)
+                }
+                MaintenanceTask::BackfillFilter { .. } => missing_filters.clear(),
+                MaintenanceTask::Verify { level, run } => {
+                    scheduler.record_verified(&stats, level, run)
+                }
+            }
+            tasks.push(task);
+        }
+        assert_eq!(
+            tasks,
+            vec![
+                MaintenanceTask::CollectTombstones { level: 0, run: 0 },
+                MaintenanceTask::CollectTombstones { level: 1, run: 0 },
+                MaintenanceTask::BackfillFilter { level: 1, run: 0 },
+                MaintenanceTask::Verify { level: 0, run: 1 },
+                MaintenanceTask::Verify { level: 0, run: 0 },
+                MaintenanceTask::Verify { level: 1, run: 0 },
+            ]
+        );
+
+        //newer tombstones above a collected run make it pending again, progress survives a round trip
+        let max_sequences = vec![vec![12, 20], vec![8]];
+        let newer = RunStats {
+            max_sequences: &max_sequences,
+            ..stats
+        };
+        let scheduler = MaintenanceScheduler::deserialize(&scheduler.serialize()).unwrap();
+        assert_eq!(scheduler.next_task(&stats, &[], &[]), None);
+        assert_eq!(
+            scheduler.next_task(&newer, &[], &[]),
+            Some(MaintenanceTask::CollectTombstones { level: 0, run: 0 })
+        );
+
+        let mut scheduler = scheduler;
+        scheduler.retain_runs(&RunStats {
+            entry_counts: &[vec![9]],
+            tombstone_counts: &[vec![0]],
+            max_sequences: &[vec![20]],
+        });
+        assert!(scheduler.is_empty());
+    }
+}
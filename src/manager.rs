@@ -0,0 +1,178 @@
+use std::{cell::RefCell, path::Path, rc::Rc};
+
+use crate::{
+    buffer_pool::{BufferPool, BufferPoolStats},
+    db::Database,
+    util::types::Size,
+};
+
+///Opens several databases that share a single buffer pool, so their cached pages fit in one memory budget.
+/// Page keys hold the file path, so databases never see each other's pages
+#[derive(Debug)]
+pub struct DatabaseManager {
+    buffer_pool: Rc<RefCell<BufferPool>>,
+    databases: Vec<Database>, //NOTE: may hold databases closed by their owner, they're dropped on the next lookup
+}
+
+impl DatabaseManager {
+    ///Manager whose databases share a buffer pool of <buffer_pool_capacity> bytes
+    pub fn new(buffer_pool_capacity: Size) -> Self {
+        assert!(
+            buffer_pool_capacity > 0,
+            "Buffer pool capacity must be over zero"
+        );
+        Self {
+            buffer_pool: Rc::new(RefCell::new(BufferPool::new(
+                Database::DEFAULT_BUFFER_POOL_INITIAL_SIZE,
+                buffer_pool_capacity,
+            ))),
+            databases: Vec::new(),
+        }
+    }
+    ///Opens the database rooted at <name> (see Database::open) with the shared buffer pool, or returns it if it's
+    /// already open
+    pub fn open(&mut self, name: impl AsRef<Path>) -> &mut Database {
+        self.open_with(name, |db| db)
+    }
+    ///Like open, <alterations> applies settings (e.g. set_memtable_capacity) to a database that wasn't already open
+    pub fn open_with(
+        &mut self,
+        name: impl AsRef<Path>,
+        alterations: impl FnOnce(Database) -> Database,
+    ) -> &mut Database {
+        let name = name.as_ref();
+        self.unregister_closed();
+        let index = match self.databases.iter().position(|db| db.path() == name) {
+            Some(index) => index,
+            None => {
+                let db =
+                    alterations(Database::open(name)).share_buffer_pool(self.buffer_pool.clone());
+                self.databases.push(db);
+                self.databases.len() - 1
+            }
+        };
+        &mut self.databases[index]
+    }
+    ///The open database rooted at <name>
+    pub fn get(&mut self, name: impl AsRef<Path>) -> Option<&mut Database> {
+        self.unregister_closed();
+        let name = name.as_ref();
+        self.databases.iter_mut().find(|db| db.path() == name)
+    }
+    ///Number of open databases
+    pub fn len(&mut self) -> Size {
+        self.unregister_closed();
+        self.databases.len()
+    }
+    pub fn is_empty(&mut self) -> bool {
+        self.len() == 0
+    }
+    ///Capacity of the shared buffer pool in bytes
+    pub fn buffer_pool_capacity(&self) -> Size {
+        self.buffer_pool.borrow().capacity()
+    }
+    ///Set the capacity of the shared buffer pool in bytes. Shrinking it evicts pages of any open database, the
+    /// pool's clock decides which, so the databases that are read the most keep the most pages
+    pub fn set_buffer_pool_capacity(&mut self, buffer_pool_capacity: Size) {
+        assert!(
+            buffer_pool_capacity > 0,
+            "Buffer pool capacity must be over zero"
+        );
+        self.buffer_pool
+            .borrow_mut()
+            .set_capacity(buffer_pool_capacity);
+    }
+    pub fn buffer_pool_stats(&self) -> BufferPoolStats {
+        self.buffer_pool.borrow().stats()
+    }
+    ///Closes every open database
+    pub fn close_all(&mut self) {
+        for db in self.databases.iter_mut() {
+            db.close();
+        }
+        self.databases.clear();
+    }
+    ///Forget databases that were closed through Database::close, they already freed their pages
+    fn unregister_closed(&mut self) {
+        self.databases.retain(|db| !db.is_closed());
+    }
+}
+
+//without the buffer-pool feature there are no pages to share
+#[cfg(all(test, feature = "buffer-pool"))]
+mod tests {
+    use crate::util::{
+        system_info, testing,
+        types::{CompactionPolicy, Key, SstImplementation},
+    };
+
+    use super::*;
+
+    #[test]
+    fn shared_buffer_pool_test() {
+        let test_dir = testing::test_dir("shared_buffer_pool_test");
+        if test_dir.exists() {
+            std::fs::remove_dir_all(test_dir).unwrap();
+        }
+        let names = [test_dir.join("tenant_a"), test_dir.join("tenant_b")];
+        let capacity = 4 * system_info::page_size();
+        let num_keys = (8 * system_info::num_entries_per_page()) as Key;
+        let value = |tenant: usize, key: Key| key * 10 + tenant as Key;
+
+        let mut manager = DatabaseManager::new(capacity);
+        for (tenant, name) in names.iter().enumerate() {
+            let db = manager.open_with(name, |db| {
+                db.set_enable_buffer_pool(true)
+                    .set_sst_implementation(SstImplementation::Array)
+                    .set_compaction_policy(CompactionPolicy::Leveled)
+                    .set_memtable_capacity(system_info::num_entries_per_page())
+                    .set_buffer_pool_capacity(capacity * 100) //only saved, the manager's capacity is used
+            });
+            for key in 0..num_keys {
+                db.put(key, value(tenant, key)).unwrap();
+            }
+        }
+        manager.open(&names[0]); //already open
+        assert_eq!(manager.len(), 2);
+
+        //interleaved reads of both databases, the pool never grows past the shared capacity
+        let check_reads = |manager: &mut DatabaseManager, tenants: &[usize]| {
+            for key in (0..num_keys).step_by(37) {
+                for &tenant in tenants {
+                    let db = manager.get(&names[tenant]).unwrap();
+                    assert_eq!(db.get(key), Some(value(tenant, key)));
+                    let stats = db.buffer_pool_stats(); //the whole shared pool
+                    assert_eq!(stats, manager.buffer_pool_stats());
+                    assert!(
+                        stats.bytes <= stats.capacity_bytes,
+                        "{} bytes cached, capacity is {}",
+                        stats.bytes,
+                        stats.capacity_bytes
+                    );
+                }
+            }
+        };
+        check_reads(&mut manager, &[0, 1]);
+        assert!(manager.buffer_pool_stats().frames > 0);
+
+        //shrinking the shared pool evicts across both databases right away
+        let capacity = 2 * system_info::page_size();
+        manager.set_buffer_pool_capacity(capacity);
+        assert!(manager.buffer_pool_stats().bytes <= capacity);
+        check_reads(&mut manager, &[0, 1]);
+
+        //closing one database frees its pages, the other's stay cached and correct
+        manager.get(&names[0]).unwrap().close();
+        assert_eq!(manager.len(), 1);
+        assert!(manager.get(&names[0]).is_none());
+        assert!(manager.buffer_pool_stats().frames > 0);
+        check_reads(&mut manager, &[1]);
+
+        manager.close_all();
+        assert!(manager.is_empty());
+        let db = manager.open(&names[0]);
+        assert_eq!(db.get(1), Some(value(0, 1)));
+        manager.close_all();
+        std::fs::remove_dir_all(test_dir).unwrap();
+    }
+}
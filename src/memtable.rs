@@ -1,32 +1,325 @@
+use std::{
+    cmp::Ordering,
+    collections::{HashMap, HashSet},
+    rc::Rc,
+};
+
 use crate::{
     data_structures::avl::AvlTree,
-    util::types::{Entry, Key, Size, Value},
+    db::Database,
+    util::types::{Entry, Key, Sequence, Size, Timestamp, Value},
 };
 
-#[derive(Debug)]
+///What the memtable says about a key. NOTE: expiries and merge operands aren't taken into account, a live entry
+/// may have expired or be an operand waiting for the value under it (see Memtable::expiry and Memtable::is_operand)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MemtableHit {
+    Live(Value),
+    Deleted, //hides every older value of the key
+    Absent,  //older runs decide
+}
+
+impl MemtableHit {
+    fn from_value(value: Value) -> Self {
+        match value {
+            Database::TOMBSTONE_VALUE => MemtableHit::Deleted,
+            value => MemtableHit::Live(value),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
 pub struct Memtable {
     tree: AvlTree<Key, Value>,
+    expiries: HashMap<Key, Timestamp>, //only for entries put with a TTL
+    sequences: HashMap<Key, Sequence>,
+    operands: HashSet<Key>, //entries holding a merge operand instead of a value
+    num_tombstones: Size,
+    frozen: Option<Rc<Memtable>>, //entries frozen for a flush that isn't done yet, see freeze_view
+    first_write: Option<Timestamp>, //when the first entry was written since it started out empty, see record_write
 }
 
 impl Memtable {
+    ///put_sorted rebuilds the tree for batches of at least 1 / SORTED_REBUILD_FRACTION of the memtable's entries,
+    /// smaller ones cost less inserted one at a time
+    const SORTED_REBUILD_FRACTION: Size = 8;
+
     ///Initializes an empty Memtable with a given capacity
     pub fn new() -> Self {
         Memtable {
             tree: AvlTree::new(),
+            expiries: HashMap::new(),
+            sequences: HashMap::new(),
+            operands: HashSet::new(),
+            num_tombstones: 0,
+            frozen: None,
+            first_write: None,
         }
     }
+    ///Number of entries, tombstones included. NOTE: entries of the frozen view aren't counted, only the ones written
+    /// since it was frozen (see freeze_view)
     pub fn len(&self) -> Size {
         self.tree.len()
     }
-    ///Insert value into memtable, returns None if fails to insert (when it's full)
-    pub fn put(&mut self, key: Key, value: Value) {
+    ///Number of entries not flushed yet, the frozen view's included. NOTE: a key written again since the freeze is
+    /// counted twice
+    pub fn len_unflushed(&self) -> Size {
+        self.len() + self.frozen.as_ref().map_or(0, |frozen| frozen.len())
+    }
+    ///Number of entries that aren't tombstones, the frozen view's aren't counted either
+    pub fn len_live(&self) -> Size {
+        self.len() - self.num_tombstones
+    }
+    ///Freeze the entries of <memtable> into an immutable view for a flush to write out, <memtable> starts over empty
+    /// and answers for both (its own entries first) until release_frozen. The view shares the entries (with any
+    /// snapshot sharing the memtable too), nothing is copied
+    pub fn freeze_view(memtable: &mut Rc<Memtable>) -> Rc<Memtable> {
+        assert!(
+            memtable.frozen.is_none(),
+            "The memtable already has a frozen view"
+        );
+        let frozen = Rc::clone(memtable);
+        *memtable = Rc::new(Memtable {
+            frozen: Some(Rc::clone(&frozen)),
+            ..Memtable::new()
+        });
+        frozen
+    }
+    ///Undo freeze_view, for a flush that failed before anything was written to <memtable> since
+    pub fn thaw(memtable: &mut Rc<Memtable>) {
+        assert_eq!(
+            memtable.len(),
+            0,
+            "The memtable was written to since it was frozen"
+        );
+        if let Some(frozen) = memtable.frozen.clone() {
+            *memtable = frozen;
+        }
+    }
+    ///The frozen view without the entries written again since the freeze, which is what its flush writes out. Those
+    /// answer for their keys from now on, a merge operand written since was combined with the frozen entry under it.
+    /// Shares the view if none were written again
+    pub fn frozen_to_flush(&self) -> Option<Rc<Memtable>> {
+        let frozen = self.frozen.as_ref()?;
+        if self
+            .tree
+            .iter()
+            .all(|(key, _)| frozen.tree.search(*key).is_none())
+        {
+            return Some(Rc::clone(frozen));
+        }
+        let mut unshadowed = Memtable::new();
+        for (key, value) in frozen
+            .iter()
+            .filter(|(key, _)| self.tree.search(*key).is_none())
+        {
+            let sequence = frozen.sequence(key).unwrap_or(0);
+            match frozen.expiry(key) {
+                _ if frozen.is_operand(key) => unshadowed.put_operand(key, value, sequence),
+                Some(expiry) => unshadowed.put_with_expiry(key, value, expiry, sequence),
+                None => unshadowed.put(key, value, sequence),
+            }
+        }
+        Some(Rc::new(unshadowed))
+    }
+    ///Remember <now> as the time of the first write, unless one was written since the memtable started out empty
+    pub fn record_write(&mut self, now: Timestamp) {
+        self.first_write.get_or_insert(now);
+    }
+    ///When the first entry was written since the memtable started out empty, not counting the frozen view's
+    pub fn first_write(&self) -> Option<Timestamp> {
+        self.first_write
+    }
+    ///When the oldest entry that isn't flushed yet was written, the frozen view's first if there is one
+    pub fn oldest_unflushed_write(&self) -> Option<Timestamp> {
+        self.frozen
+            .as_ref()
+            .and_then(|frozen| frozen.first_write)
+            .or(self.first_write)
+    }
+    ///Drop the frozen view once its entries were flushed
+    pub fn release_frozen(&mut self) {
+        self.frozen = None;
+    }
+    ///The memtable holding the entry that answers for <key>, the frozen view only if there's none written since
+    fn source(&self, key: Key) -> &Memtable {
+        match &self.frozen {
+            Some(frozen) if self.tree.search(key).is_none() => frozen,
+            _ => self,
+        }
+    }
+    fn insert(&mut self, key: Key, value: Value) {
+        //only entries written since the freeze are counted, the frozen view's tombstones are its own
+        if self.tree.search(key) == Some(Database::TOMBSTONE_VALUE) {
+            self.num_tombstones -= 1;
+        }
+        if value == Database::TOMBSTONE_VALUE {
+            self.num_tombstones += 1;
+        }
         self.tree.insert(key, value);
     }
+    ///Insert value into memtable, returns None if fails to insert (when it's full)
+    pub fn put(&mut self, key: Key, value: Value, sequence: Sequence) {
+        self.insert(key, value);
+        self.expiries.remove(&key);
+        self.sequences.insert(key, sequence);
+        self.operands.remove(&key);
+    }
+    pub fn put_with_expiry(
+        &mut self,
+        key: Key,
+        value: Value,
+        expiry: Timestamp,
+        sequence: Sequence,
+    ) {
+        self.insert(key, value);
+        self.expiries.insert(key, expiry);
+        self.sequences.insert(key, sequence);
+        self.operands.remove(&key);
+    }
+    ///Put <entries> (strictly increasing keys, no tombstones) with sequences counting up from <first_sequence>.
+    /// A batch that's large next to the memtable is merged with the tree's entries into a new tree instead of being
+    /// inserted a key at a time
+    pub fn put_sorted(&mut self, entries: &[Entry], first_sequence: Sequence) {
+        let sequences = first_sequence..;
+        if entries.len() * Self::SORTED_REBUILD_FRACTION < self.len() {
+            for (sequence, &(key, value)) in sequences.zip(entries) {
+                self.put(key, value, sequence);
+            }
+            return;
+        }
+        self.sequences.reserve(entries.len());
+        for (sequence, &(key, _)) in sequences.zip(entries) {
+            self.sequences.insert(key, sequence);
+        }
+        if !self.expiries.is_empty() || !self.operands.is_empty() {
+            for (key, _) in entries {
+                self.expiries.remove(key);
+                self.operands.remove(key);
+            }
+        }
+        let mut merged = Vec::with_capacity(self.len() + entries.len());
+        let (mut old, mut new) = (
+            self.tree
+                .iter()
+                .map(|(key, value)| (*key, *value))
+                .peekable(),
+            entries.iter().copied().peekable(),
+        );
+        while let (Some(&(old_key, _)), Some(&(new_key, _))) = (old.peek(), new.peek()) {
+            match old_key.cmp(&new_key) {
+                Ordering::Less => merged.extend(old.next()),
+                Ordering::Equal => {
+                    old.next(); //overwritten
+                    merged.extend(new.next());
+                }
+                Ordering::Greater => merged.extend(new.next()),
+            }
+        }
+        merged.extend(old.chain(new));
+        self.num_tombstones = merged
+            .iter()
+            .filter(|(_, value)| *value == Database::TOMBSTONE_VALUE)
+            .count();
+        self.tree = AvlTree::from_sorted(merged);
+    }
+    ///Insert a merge operand that's still waiting for the value under it (see Database::merge)
+    pub fn put_operand(&mut self, key: Key, operand: Value, sequence: Sequence) {
+        self.put(key, operand, sequence);
+        self.operands.insert(key);
+    }
+    pub fn is_operand(&self, key: Key) -> bool {
+        self.source(key).operands.contains(&key)
+    }
+    ///Keys of every merge operand, sorted
+    pub fn operands(&self) -> Vec<Key> {
+        let mut operands: Vec<Key> = self.operands.iter().copied().collect();
+        if let Some(frozen) = &self.frozen {
+            operands.extend(
+                frozen
+                    .operands
+                    .iter()
+                    .filter(|key| self.tree.search(**key).is_none()),
+            );
+        }
+        operands.sort_unstable();
+        operands
+    }
+    pub fn expiry(&self, key: Key) -> Option<Timestamp> {
+        self.source(key).expiries.get(&key).copied()
+    }
+    ///Expiries of every entry put with a TTL, sorted by key
+    pub fn expiries(&self) -> Vec<(Key, Timestamp)> {
+        let mut expiries: Vec<(Key, Timestamp)> =
+            self.expiries.iter().map(|(k, e)| (*k, *e)).collect();
+        if let Some(frozen) = &self.frozen {
+            expiries.extend(
+                frozen
+                    .expiries
+                    .iter()
+                    .filter(|(key, _)| self.tree.search(**key).is_none())
+                    .map(|(k, e)| (*k, *e)),
+            );
+        }
+        expiries.sort_unstable();
+        expiries
+    }
+    ///Sequence of the write that put <key>'s entry
+    pub fn sequence(&self, key: Key) -> Option<Sequence> {
+        self.source(key).sequences.get(&key).copied()
+    }
+    ///Sequence of the newest write, 0 if the memtable is empty
+    pub fn max_sequence(&self) -> Sequence {
+        let frozen_max = self
+            .frozen
+            .as_ref()
+            .map_or(0, |frozen| frozen.max_sequence());
+        self.sequences
+            .values()
+            .max()
+            .copied()
+            .unwrap_or(0)
+            .max(frozen_max)
+    }
     pub fn get(&self, key: Key) -> Option<Value> {
-        self.tree.search(key)
+        self.tree
+            .search(key)
+            .or_else(|| self.frozen.as_ref()?.get(key))
+    }
+    pub fn get_entry(&self, key: Key) -> MemtableHit {
+        self.get(key)
+            .map_or(MemtableHit::Absent, MemtableHit::from_value)
+    }
+    ///Whether <key> was deleted since the last flush, its older values in runs don't need to be searched
+    #[allow(dead_code)]
+    pub fn contains_tombstone(&self, key: Key) -> bool {
+        self.get_entry(key) == MemtableHit::Deleted
+    }
+    ///Smallest entry with a key >= <key>
+    pub fn ceiling(&self, key: Key) -> Option<Entry> {
+        let live = self.tree.ceiling(key);
+        match self.frozen.as_ref().and_then(|frozen| frozen.ceiling(key)) {
+            //a frozen key smaller than the live ceiling wasn't written since the freeze
+            Some(frozen) if live.is_none_or(|live| frozen.0 < live.0) => Some(frozen),
+            _ => live,
+        }
+    }
+    ///Largest entry with a key <= <key>
+    pub fn floor(&self, key: Key) -> Option<Entry> {
+        let live = self.tree.floor(key);
+        match self.frozen.as_ref().and_then(|frozen| frozen.floor(key)) {
+            Some(frozen) if live.is_none_or(|live| frozen.0 > live.0) => Some(frozen),
+            _ => live,
+        }
     }
     pub fn clear(&mut self) {
-        self.tree = AvlTree::new()
+        self.tree = AvlTree::new();
+        self.expiries.clear();
+        self.sequences.clear();
+        self.operands.clear();
+        self.num_tombstones = 0;
+        self.frozen = None;
+        self.first_write = None;
     }
     // Performs inorder traversal of the tree and returns a vector of all the key-value pairs
     // with key between key1 and key2
@@ -34,14 +327,99 @@ impl Memtable {
         let mut result = Vec::new();
         self.tree
             .for_each_in_range(&key1, &key2, &mut |key, value| result.push((*key, *value)));
-        result
+        match &self.frozen {
+            Some(frozen) => merge_entries(result, frozen.scan(key1, key2), Ordering::Less),
+            None => result,
+        }
     }
-    pub fn as_vec(&self) -> Vec<Entry> {
+    ///Number of entries with keys between key1 and key2 that aren't tombstones, counted without copying them
+    pub fn count_live(&self, key1: Key, key2: Key) -> Size {
+        if self.frozen.is_some() {
+            return self
+                .scan(key1, key2)
+                .iter()
+                .filter(|(_, value)| *value != Database::TOMBSTONE_VALUE)
+                .count();
+        }
+        let mut count = 0;
+        self.tree.for_each_in_range(&key1, &key2, &mut |_, value| {
+            if *value != Database::TOMBSTONE_VALUE {
+                count += 1;
+            }
+        });
+        count
+    }
+    ///Same as scan, but in descending key order
+    pub fn scan_rev(&self, key1: Key, key2: Key) -> Vec<Entry> {
         let mut result = Vec::new();
         self.tree
-            .for_each(&mut |key, value| result.push((*key, *value)));
-        result
+            .for_each_in_range_rev(&key1, &key2, &mut |key, value| result.push((*key, *value)));
+        match &self.frozen {
+            Some(frozen) => merge_entries(result, frozen.scan_rev(key1, key2), Ordering::Greater),
+            None => result,
+        }
+    }
+    ///Same as scan, with deleted keys reported as MemtableHit::Deleted (never Absent)
+    pub fn scan_entries(&self, key1: Key, key2: Key) -> Vec<(Key, MemtableHit)> {
+        self.scan(key1, key2)
+            .into_iter()
+            .map(|(key, value)| (key, MemtableHit::from_value(value)))
+            .collect()
+    }
+    ///Same as scan_entries, but in descending key order
+    pub fn scan_entries_rev(&self, key1: Key, key2: Key) -> Vec<(Key, MemtableHit)> {
+        self.scan_rev(key1, key2)
+            .into_iter()
+            .map(|(key, value)| (key, MemtableHit::from_value(value)))
+            .collect()
+    }
+    ///Every entry in key order, one at a time instead of copied into a Vec like scan
+    pub fn iter(&self) -> impl Iterator<Item = Entry> + '_ {
+        let mut live = self
+            .tree
+            .iter()
+            .map(|(key, value)| (*key, *value))
+            .peekable();
+        let mut frozen = self
+            .frozen
+            .iter()
+            .flat_map(|frozen| frozen.tree.iter().map(|(key, value)| (*key, *value)))
+            .peekable();
+        std::iter::from_fn(move || match (live.peek(), frozen.peek()) {
+            (Some(live_entry), Some(frozen_entry)) => match live_entry.0.cmp(&frozen_entry.0) {
+                Ordering::Less => live.next(),
+                Ordering::Equal => {
+                    frozen.next(); //written again since the freeze
+                    live.next()
+                }
+                Ordering::Greater => frozen.next(),
+            },
+            (Some(_), None) => live.next(),
+            (None, _) => frozen.next(),
+        })
+    }
+}
+
+///Merge the entries of the memtable (<live>) with the ones of its frozen view, both sorted so that consecutive keys
+/// compare as <order>. A key in both is the live entry
+fn merge_entries(live: Vec<Entry>, frozen: Vec<Entry>, order: Ordering) -> Vec<Entry> {
+    if frozen.is_empty() {
+        return live;
     }
+    let mut merged = Vec::with_capacity(live.len() + frozen.len());
+    let (mut live, mut frozen) = (live.into_iter().peekable(), frozen.into_iter().peekable());
+    while let (Some(&(live_key, _)), Some(&(frozen_key, _))) = (live.peek(), frozen.peek()) {
+        match live_key.cmp(&frozen_key) {
+            Ordering::Equal => {
+                frozen.next();
+                merged.extend(live.next());
+            }
+            ordering if ordering == order => merged.extend(live.next()),
+            _ => merged.extend(frozen.next()),
+        }
+    }
+    merged.extend(live.chain(frozen));
+    merged
 }
 
 // Sanity tests, need to check for edge cases
@@ -59,7 +437,7 @@ mod tests {
     #[test]
     fn test_scan_single() {
         let mut memtable: Memtable = Memtable::new();
-        memtable.put(1, 1);
+        memtable.put(1, 1, 0);
         let result = memtable.scan(1, 1);
         assert_eq!(result, vec![(1, 1)]);
     }
@@ -67,8 +445,8 @@ mod tests {
     #[test]
     fn test_scan_multiple() {
         let mut memtable: Memtable = Memtable::new();
-        memtable.put(1, 11);
-        memtable.put(3, 33);
+        memtable.put(1, 11, 0);
+        memtable.put(3, 33, 0);
         let result = memtable.scan(1, 3);
         assert_eq!(result, vec![(1, 11), (3, 33)]);
     }
@@ -76,9 +454,9 @@ mod tests {
     #[test]
     fn test_scan_order() {
         let mut memtable: Memtable = Memtable::new();
-        memtable.put(1, 1);
-        memtable.put(2, 3);
-        memtable.put(3, 5);
+        memtable.put(1, 1, 0);
+        memtable.put(2, 3, 0);
+        memtable.put(3, 5, 0);
         let result = memtable.scan(1, 3);
         assert_eq!(result, vec![(1, 1), (2, 3), (3, 5)]);
     }
@@ -86,32 +464,222 @@ mod tests {
     #[test]
     fn test_scan_invalid_range() {
         let mut memtable: Memtable = Memtable::new();
-        memtable.put(1, 1);
-        memtable.put(2, 3);
-        memtable.put(3, 5);
+        memtable.put(1, 1, 0);
+        memtable.put(2, 3, 0);
+        memtable.put(3, 5, 0);
         assert_eq!(memtable.get(1), Some(1));
 
         let result = memtable.scan(4, 11);
         assert_eq!(result, vec![]);
     }
 
+    #[test]
+    fn test_scan_rev() {
+        let mut memtable: Memtable = Memtable::new();
+        for key in [5, 1, 9, 3, 7, 2, 8] {
+            memtable.put(key, key * 11, 0);
+        }
+        let mut forward = memtable.scan(2, 8);
+        forward.reverse();
+        assert_eq!(memtable.scan_rev(2, 8), forward);
+        assert_eq!(memtable.scan_rev(10, 20), vec![]);
+        assert_eq!(memtable.scan_rev(9, 9), vec![(9, 99)]);
+    }
+
+    #[test]
+    fn test_expiries() {
+        let mut memtable: Memtable = Memtable::new();
+        memtable.put_with_expiry(3, 30, 100, 0);
+        memtable.put_with_expiry(1, 10, 50, 0);
+        memtable.put(2, 20, 0);
+        assert_eq!(memtable.expiry(3), Some(100));
+        assert_eq!(memtable.expiry(2), None);
+        assert_eq!(memtable.expiries(), vec![(1, 50), (3, 100)]);
+
+        //a plain put replaces the entry along with its expiry
+        memtable.put(3, 31, 0);
+        assert_eq!(memtable.expiry(3), None);
+        memtable.clear();
+        assert_eq!(memtable.expiries(), vec![]);
+    }
+
+    #[test]
+    fn test_operands() {
+        let mut memtable: Memtable = Memtable::new();
+        memtable.put_operand(3, 1, 0);
+        memtable.put_operand(1, 1, 0);
+        memtable.put(2, 20, 0);
+        assert!(memtable.is_operand(3));
+        assert!(!memtable.is_operand(2));
+        assert_eq!(memtable.operands(), vec![1, 3]);
+
+        //a put or delete replaces the operand with a value
+        memtable.put(3, 30, 0);
+        assert!(!memtable.is_operand(3));
+        memtable.put_with_expiry(1, 10, 50, 0);
+        assert_eq!(memtable.operands(), vec![]);
+    }
+
+    #[test]
+    fn test_tombstones() {
+        let mut memtable: Memtable = Memtable::new();
+        let tombstone = Database::TOMBSTONE_VALUE;
+        assert_eq!(memtable.get_entry(1), MemtableHit::Absent);
+        memtable.put(1, 10, 0);
+        memtable.put(2, 20, 1);
+        memtable.put(1, tombstone, 2);
+        assert_eq!(memtable.get_entry(1), MemtableHit::Deleted);
+        assert!(memtable.contains_tombstone(1));
+        assert!(!memtable.contains_tombstone(3));
+        assert_eq!((memtable.len(), memtable.len_live()), (2, 1));
+
+        //deleting twice counts once, putting a value back makes the key live again
+        memtable.put(1, tombstone, 3);
+        assert_eq!((memtable.len(), memtable.len_live()), (2, 1));
+        memtable.put_with_expiry(1, 11, 100, 4);
+        assert_eq!(memtable.get_entry(1), MemtableHit::Live(11));
+        assert_eq!((memtable.len(), memtable.len_live()), (2, 2));
+        memtable.put(2, tombstone, 5);
+        memtable.put_operand(3, 1, 6);
+        assert_eq!(
+            memtable.scan_entries(0, 5),
+            vec![
+                (1, MemtableHit::Live(11)),
+                (2, MemtableHit::Deleted),
+                (3, MemtableHit::Live(1))
+            ]
+        );
+        assert_eq!(
+            memtable.scan_entries_rev(2, 3),
+            vec![(3, MemtableHit::Live(1)), (2, MemtableHit::Deleted)]
+        );
+        assert_eq!((memtable.len(), memtable.len_live()), (3, 2));
+        memtable.clear();
+        assert_eq!((memtable.len(), memtable.len_live()), (0, 0));
+    }
+
+    #[test]
+    fn test_frozen_view() {
+        let tombstone = Database::TOMBSTONE_VALUE;
+        let mut memtable = Rc::new(Memtable::new());
+        let live = Rc::make_mut(&mut memtable);
+        for key in (0..10).step_by(2) {
+            live.put(key, key * 10, key as Sequence);
+        }
+        live.put_with_expiry(4, 40, 100, 4);
+        live.put_operand(6, 1, 6);
+        live.put(8, tombstone, 8);
+        let frozen = Memtable::freeze_view(&mut memtable);
+        assert_eq!((memtable.len(), frozen.len()), (0, 5));
+        assert_eq!(memtable.iter().collect::<Vec<_>>(), frozen.scan(0, 9));
+
+        //written since the freeze: a new key, an overwrite, a delete and a put over a tombstone
+        let live = Rc::make_mut(&mut memtable);
+        live.put(3, 30, 10);
+        live.put(2, 21, 11);
+        live.put(4, tombstone, 12);
+        live.put(8, 81, 13);
+        assert_eq!((memtable.len(), memtable.len_live()), (4, 3));
+        let expected = vec![(0, 0), (2, 21), (3, 30), (4, tombstone), (6, 1), (8, 81)];
+        assert_eq!(memtable.scan(0, 9), expected);
+        assert_eq!(memtable.iter().collect::<Vec<_>>(), expected);
+        let mut reversed = expected.clone();
+        reversed.reverse();
+        assert_eq!(memtable.scan_rev(0, 9), reversed);
+        assert_eq!(memtable.get(0), Some(0));
+        assert_eq!(memtable.get_entry(4), MemtableHit::Deleted);
+        assert_eq!(memtable.get_entry(8), MemtableHit::Live(81));
+        assert_eq!(
+            (memtable.ceiling(1), memtable.ceiling(5)),
+            (Some((2, 21)), Some((6, 1)))
+        );
+        assert_eq!(
+            (memtable.floor(1), memtable.floor(5)),
+            (Some((0, 0)), Some((4, tombstone)))
+        );
+        assert_eq!((memtable.expiry(4), memtable.expiries()), (None, vec![]));
+        assert_eq!(memtable.operands(), vec![6]);
+        assert_eq!(
+            (memtable.sequence(0), memtable.sequence(2)),
+            (Some(0), Some(11))
+        );
+        assert_eq!(memtable.max_sequence(), 13);
+
+        //the frozen view didn't change, only what wasn't written since is flushed
+        assert_eq!(frozen.get(2), Some(20));
+        let to_flush = memtable.frozen_to_flush().unwrap();
+        assert_eq!(to_flush.iter().collect::<Vec<_>>(), vec![(0, 0), (6, 1)]);
+        assert!(to_flush.is_operand(6));
+        Rc::make_mut(&mut memtable).release_frozen();
+        assert_eq!(
+            memtable.scan(0, 9),
+            vec![(2, 21), (3, 30), (4, tombstone), (8, 81)]
+        );
+
+        //thawing a view nothing was written over gives the memtable back
+        let frozen = Memtable::freeze_view(&mut memtable);
+        assert!(Rc::ptr_eq(&memtable.frozen_to_flush().unwrap(), &frozen));
+        Memtable::thaw(&mut memtable);
+        assert!(Rc::ptr_eq(&memtable, &frozen));
+    }
+
     #[test]
     fn test_sst_read() {
         // Create a new memtable with capacity 2
         let mut memtable = Memtable::new();
 
         // Insert three key-value pairs
-        memtable.put(1, 11);
-        memtable.put(2, 22);
-        memtable.put(3, 33);
-        memtable.put(4, 44);
-        memtable.put(5, 55);
-        memtable.put(6, 66);
-        memtable.put(7, 77);
-        memtable.put(8, 88);
-        memtable.put(9, 99);
+        memtable.put(1, 11, 0);
+        memtable.put(2, 22, 0);
+        memtable.put(3, 33, 0);
+        memtable.put(4, 44, 0);
+        memtable.put(5, 55, 0);
+        memtable.put(6, 66, 0);
+        memtable.put(7, 77, 0);
+        memtable.put(8, 88, 0);
+        memtable.put(9, 99, 0);
 
         assert_eq!(memtable.get(1), Some(11));
         assert_eq!(memtable.get(9), Some(99));
     }
+
+    #[test]
+    fn test_put_sorted() {
+        //a tombstone, an expiring entry and an operand, each overwritten by the batch
+        let setup = || {
+            let mut memtable = Memtable::new();
+            for key in (0..64).step_by(2) {
+                memtable.put(key, key, 1);
+            }
+            memtable.put(10, Database::TOMBSTONE_VALUE, 2);
+            memtable.put(12, Database::TOMBSTONE_VALUE, 2);
+            memtable.put_with_expiry(20, 200, 1000, 2);
+            memtable.put_operand(30, 3, 2);
+            memtable
+        };
+        let batch: Vec<Entry> = [10, 11, 20, 30, 63, 70]
+            .into_iter()
+            .map(|key| (key, -key))
+            .collect();
+        let mut expected = setup();
+        for (sequence, &(key, value)) in (3..).zip(&batch) {
+            expected.put(key, value, sequence);
+        }
+
+        //rebuilt, and inserted a key at a time (the batch is small next to the memtable)
+        let mut small_memtable = setup();
+        for _ in 0..batch.len() * Memtable::SORTED_REBUILD_FRACTION {
+            small_memtable.put(-(small_memtable.len() as Key) - 1, 1, 0);
+        }
+        for mut memtable in [setup(), small_memtable] {
+            memtable.put_sorted(&batch, 3);
+            let entries: Vec<Entry> = memtable.iter().filter(|(key, _)| *key >= 0).collect();
+            assert_eq!(entries, expected.iter().collect::<Vec<Entry>>());
+            assert_eq!(memtable.num_tombstones, 1); //12 is still deleted
+            assert_eq!(memtable.expiry(20), None);
+            assert!(!memtable.is_operand(30));
+            assert_eq!(memtable.sequence(70), Some(8));
+            assert_eq!(memtable.max_sequence(), expected.max_sequence());
+        }
+    }
 }
@@ -0,0 +1,525 @@
+use std::{collections::HashMap, io, mem};
+
+#[cfg(feature = "serde-config")]
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    buffer_pool::BufferPool,
+    file_io::{
+        file_interface,
+        serde_state::{self, impl_struct_codec},
+    },
+    filter::bloom_io::{BloomFilterBuilder, BloomFilterIO},
+    sst::{array_sst, SortedStringTable},
+    util::{
+        filename::{self, RunPaths},
+        types::{DatabaseName, Entry, Key, KeyCodec, Level, Run, Sequence, Size, Value},
+    },
+};
+
+///Small cache of values found in SSTs, consulted right after the memtable so hot keys don't pay for a
+/// full level search every time their pages are evicted from the buffer pool.
+/// Admitted values are copied into a cache run: a small bloom-filtered run of its own, under the database's
+/// read_cache directory, that acts like a level older than every real one. Its values are what the levels held as of
+/// its sequence, so like any older run it only answers a get while no run written since might hold the key (see
+/// Database::cached_value), and the memtable is checked first. Writes never have to touch the cache.
+/// Keys admitted since the run was written wait in memory, the run is rewritten with them once there are enough of
+/// them, truncated to the most read keys
+#[derive(Debug, Default)]
+pub struct ReadCache {
+    capacity: Size,        //max number of cached entries, 0 disables the cache
+    run: Option<CacheRun>, //None until entries are first written to a cache run
+    pending: HashMap<Key, (Value, Sequence)>, //admitted since the run was written, with the last sequence of the database when they were read
+    read_counts: HashMap<Key, Size>, //reads of keys found in SSTs or the cache, used for admission and truncation
+}
+
+///The cache run on storage, each rewrite writes the next generation (its run number) before the old one is removed
+#[cfg_attr(feature = "serde-config", derive(Serialize, Deserialize))]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct CacheRun {
+    generation: Run,
+    num_entries: Size,
+    sequence: Sequence, //newest write in any run when it was written, its values are the levels' as of then
+    key_codec: KeyCodec,
+}
+
+impl_struct_codec!(CacheRun {
+    generation,
+    num_entries,
+    sequence,
+    key_codec
+});
+
+///On disk layout of the cache's descriptor, written on close and read back on open
+#[cfg_attr(feature = "serde-config", derive(Serialize, Deserialize))]
+struct ReadCacheFile {
+    capacity: Size,
+    run: Option<CacheRun>,
+}
+
+impl_struct_codec!(ReadCacheFile { capacity, run });
+
+impl ReadCache {
+    ///Layout version written to the descriptor, files of other versions are dropped when read
+    const VERSION: u32 = 2;
+    ///Number of reads from SSTs before a key is copied into the cache
+    pub const ADMISSION_THRESHOLD: Size = 3;
+    ///Read counters are aged once we track this many keys per cache slot
+    const READ_COUNT_LIMIT_RATIO: Size = 8;
+    ///The run is rewritten once capacity / REWRITE_RATIO keys (at least one) were admitted since it was written
+    const REWRITE_RATIO: Size = 4;
+    ///Most entries the cache holds. Its descriptor starts with the capacity, which has to fit in 32 bits for its
+    /// version to be found (see serde_state::split_version)
+    pub const MAX_CAPACITY: Size = u32::MAX as Size;
+    ///Size of the cache run's bloom filter, it doesn't follow the database's setting
+    const BITS_PER_ENTRY: Size = 10;
+    ///The cache run is the only run of its directory's only level
+    const LEVEL: Level = 0;
+
+    pub fn new(capacity: Size) -> Self {
+        Self {
+            capacity,
+            ..Default::default()
+        }
+    }
+    pub fn capacity(&self) -> Size {
+        self.capacity
+    }
+    ///<capacity> is capped at ReadCache::MAX_CAPACITY. The cache run is truncated to it when it's next written
+    pub fn set_capacity(&mut self, capacity: Size) {
+        self.capacity = capacity.min(Self::MAX_CAPACITY);
+    }
+    ///Entries in the cache run and waiting to be written to it, a key whose entry in the run is stale can be in both
+    #[allow(dead_code)]
+    pub fn len(&self) -> Size {
+        self.run.map_or(0, |run| run.num_entries) + self.pending.len()
+    }
+    ///Where the cache run of the database at <db_name> is written, laid out like a database's levels
+    fn run_name(db_name: &DatabaseName) -> DatabaseName {
+        DatabaseName::new(
+            filename::read_cache_directory(db_name),
+            db_name.storage().clone(),
+        )
+    }
+    ///Cached value of <key> along with the sequence it's the levels' value as of, the caller checks that no run
+    /// written since holds the key. Keys waiting to be written are newer than the run's. Fails if the cache run
+    /// can't be read
+    pub fn get(
+        &self,
+        db_name: &DatabaseName,
+        key: Key,
+        mut buffer_pool: Option<&mut BufferPool>,
+    ) -> io::Result<Option<(Value, Sequence)>> {
+        if self.capacity == 0 {
+            return Ok(None);
+        }
+        if let Some(cached) = self.pending.get(&key) {
+            return Ok(Some(*cached));
+        }
+        let Some(run) = self.run else {
+            return Ok(None);
+        };
+        let name = Self::run_name(db_name);
+        let run_address = &(&name, Self::LEVEL, run.generation);
+        if !BloomFilterIO::contains(
+            run_address,
+            key,
+            Self::BITS_PER_ENTRY,
+            run.num_entries,
+            run.key_codec,
+            buffer_pool.as_deref_mut(),
+        )? {
+            return Ok(None);
+        }
+        let value = array_sst::Sst::new(run.key_codec).get(
+            run_address,
+            key,
+            run.num_entries,
+            buffer_pool,
+        )?;
+        Ok(value.map(|value| (value, run.sequence)))
+    }
+    ///Record a get answered by the cache, so popular keys keep their spot
+    pub fn record_hit(&mut self, key: Key) {
+        *self.read_counts.entry(key).or_insert(0) += 1;
+    }
+    ///Record a read of <key> that had to search SSTs and found <value>, the newest write to it as of
+    /// <last_sequence> (the memtable didn't hold the key). Admits the key once it's read often enough.
+    /// Returns whether enough keys were admitted for the run to be rewritten, see rewrite
+    pub fn record_read(&mut self, key: Key, value: Value, last_sequence: Sequence) -> bool {
+        if self.capacity == 0 {
+            return false;
+        }
+        let read_count = self.read_counts.entry(key).or_insert(0);
+        *read_count += 1;
+        if *read_count >= Self::ADMISSION_THRESHOLD {
+            self.pending.insert(key, (value, last_sequence));
+        }
+        if self.read_counts.len() > self.capacity * Self::READ_COUNT_LIMIT_RATIO {
+            self.age_read_counts();
+        }
+        self.pending.len() >= (self.capacity / Self::REWRITE_RATIO).max(1)
+    }
+    ///Halve every read count and forget keys that aren't waiting to be written and reach zero, so old popularity
+    /// fades
+    fn age_read_counts(&mut self) {
+        let pending = &self.pending;
+        self.read_counts.retain(|key, read_count| {
+            *read_count /= 2;
+            *read_count > 0 || pending.contains_key(key)
+        });
+    }
+    ///Write the keys waiting to be written, along with the run's entries that are still current, to the next
+    /// generation of the cache run, keeping the <capacity> most read. An entry cached as of a sequence is current if
+    /// <is_current> says no run written since might hold its key, the new run's values are then the levels' as of
+    /// <sequence> (the newest write in any run). The old generation's files are removed once the new one is written,
+    /// no run is left if nothing is current
+    pub fn rewrite(
+        &mut self,
+        db_name: &DatabaseName,
+        sequence: Sequence,
+        key_codec: KeyCodec,
+        is_current: &mut dyn FnMut(Key, Sequence, Option<&mut BufferPool>) -> bool,
+        mut buffer_pool: Option<&mut BufferPool>,
+    ) -> io::Result<()> {
+        let name = Self::run_name(db_name);
+        let mut entries = HashMap::new();
+        let old_run = self.run.take();
+        if let Some(run) = old_run.filter(|_| self.capacity > 0) {
+            let run_address = &(&name, Self::LEVEL, run.generation);
+            for (key, value) in array_sst::Sst::new(run.key_codec).read(run_address)? {
+                if is_current(key, run.sequence, buffer_pool.as_deref_mut()) {
+                    entries.insert(key, value);
+                }
+            }
+        }
+        for (key, (value, read_sequence)) in mem::take(&mut self.pending) {
+            if self.capacity > 0 && is_current(key, read_sequence, buffer_pool.as_deref_mut()) {
+                entries.insert(key, value); //newer than what the run held
+            }
+        }
+        let entries = self.most_read(entries);
+        if !entries.is_empty() {
+            let generation = old_run.map_or(0, |run| run.generation + 1);
+            let run_address = &(&name, Self::LEVEL, generation);
+            let mut filter = BloomFilterBuilder::new(Self::BITS_PER_ENTRY, key_codec);
+            let num_entries = array_sst::Sst::new(key_codec).write_from_iter(
+                run_address,
+                &mut entries.into_iter(),
+                &mut |page| {
+                    filter.insert_entries(page);
+                    Ok(())
+                },
+            )?;
+            filter.seal(run_address, num_entries)?;
+            self.run = Some(CacheRun {
+                generation,
+                num_entries,
+                sequence,
+                key_codec,
+            });
+        }
+        if let Some(old_run) = old_run {
+            let run_address = &(&name, Self::LEVEL, old_run.generation);
+            for path in [run_address.path_sst(), run_address.path_bloom()] {
+                if name.storage().path_exists(&path) {
+                    file_interface::remove_file(name.storage(), &path, buffer_pool.as_deref_mut())?;
+                }
+            }
+        }
+        Ok(())
+    }
+    ///The <capacity> most read of <entries> in key order, their read counts are kept so the ones left out can win
+    /// their spot back
+    fn most_read(&self, entries: HashMap<Key, Value>) -> Vec<Entry> {
+        let mut by_reads: Vec<(Size, Entry)> = entries
+            .into_iter()
+            .map(|(key, value)| {
+                let read_count = self.read_counts.get(&key).copied().unwrap_or(0);
+                (read_count, (key, value))
+            })
+            .collect();
+        by_reads.sort_unstable_by_key(|(read_count, (key, _))| (*read_count, *key));
+        let num_to_remove = by_reads.len().saturating_sub(self.capacity);
+        let mut entries: Vec<Entry> = (by_reads.into_iter().skip(num_to_remove))
+            .map(|(_, entry)| entry)
+            .collect();
+        entries.sort_unstable_by_key(|(key, _)| *key);
+        entries
+    }
+    ///Drop every cached entry and remove the cache run's files, e.g. once the run couldn't be read or written.
+    /// Read counts are kept, reads fill the cache again. NOTE: a directory that can't be removed is removed by the
+    /// next open, no descriptor refers to it
+    pub fn discard(&mut self, db_name: &DatabaseName, buffer_pool: Option<&mut BufferPool>) {
+        self.run = None;
+        self.pending.clear();
+        let directory = filename::read_cache_directory(db_name);
+        if let Some(pool) = buffer_pool {
+            pool.remove_directory(&directory);
+        }
+        if db_name.storage().path_exists(&directory) {
+            let _ = db_name.storage().remove_dir_all(&directory);
+        }
+    }
+    ///The cache the last close of the database at <db_name> left. Its descriptor is removed, so it's only ever
+    /// trusted by the open that follows that close (a session that crashes may have written runs its metadata doesn't
+    /// list). Without a descriptor that can be read, or if the cache run isn't intact, the cache starts empty (with the
+    /// default capacity if the descriptor is gone) and the cache run's files are removed along with any a crash left
+    pub fn open(db_name: &DatabaseName) -> io::Result<Self> {
+        let storage = db_name.storage();
+        let path = filename::read_cache(db_name);
+        let mut cache = match storage.read_file(&path) {
+            Ok(bytes) => Self::deserialize(&bytes).unwrap_or_default(),
+            Err(_) => Self::default(),
+        };
+        if storage.path_exists(&path) {
+            storage.remove_file(&path)?;
+        }
+        let name = Self::run_name(db_name);
+        cache.run = cache.run.filter(|run| {
+            let run_address = &(&name, Self::LEVEL, run.generation);
+            let sst = array_sst::Sst::new(run.key_codec);
+            sst.len(run_address).is_ok_and(|len| len == run.num_entries)
+                && BloomFilterIO::is_intact(run_address, Self::BITS_PER_ENTRY, run.num_entries)
+                    .unwrap_or(false)
+        });
+        let directory = filename::read_cache_directory(db_name);
+        match cache.run {
+            None if storage.path_exists(&directory) => storage.remove_dir_all(&directory)?,
+            None => {}
+            Some(run) => {
+                let run_address = &(&name, Self::LEVEL, run.generation);
+                let run_files = [run_address.path_sst(), run_address.path_bloom()];
+                let level_directory = filename::lsm_level_directory(&(&name, Self::LEVEL));
+                for path in storage.read_dir(&level_directory)? {
+                    if !run_files.contains(&path) {
+                        storage.remove_file(&path)?;
+                    }
+                }
+            }
+        }
+        Ok(cache)
+    }
+
+    pub fn serialize(&self) -> Vec<u8> {
+        let file = ReadCacheFile {
+            capacity: self.capacity,
+            run: self.run,
+        };
+        serde_state::serialize(&(Self::VERSION, &file))
+    }
+    pub fn deserialize(bytes: &[u8]) -> Result<Self, String> {
+        let file: ReadCacheFile = match serde_state::split_version(bytes) {
+            Some((Self::VERSION, contents)) => serde_state::deserialize(contents)?,
+            Some((version, _)) => return Err(format!("unsupported read cache version {version}")),
+            None => return Err("read cache file without a version".to_string()),
+        };
+        Ok(Self {
+            run: file.run,
+            ..Self::new(file.capacity.min(Self::MAX_CAPACITY))
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::file_io::storage::Storage;
+
+    fn test_db_name() -> DatabaseName {
+        let name = DatabaseName::new("read_cache_unit_test", Storage::in_memory());
+        name.storage().create_dir_all(&name).unwrap();
+        name
+    }
+
+    ///Rewrite the run of <cache>, with every entry current unless its key is in <stale>
+    fn rewrite(cache: &mut ReadCache, name: &DatabaseName, sequence: Sequence, stale: &[Key]) {
+        cache
+            .rewrite(
+                name,
+                sequence,
+                KeyCodec::LittleEndian,
+                &mut |key, _, _| !stale.contains(&key),
+                None,
+            )
+            .unwrap();
+    }
+
+    fn get(cache: &ReadCache, name: &DatabaseName, key: Key) -> Option<Value> {
+        let cached = cache.get(name, key, None).unwrap();
+        cached.map(|(value, _)| value)
+    }
+
+    #[test]
+    fn test_admission() {
+        let name = &test_db_name();
+        let mut cache = ReadCache::new(4);
+        for _ in 0..ReadCache::ADMISSION_THRESHOLD - 1 {
+            assert!(!cache.record_read(1, 10, 0));
+        }
+        assert_eq!(get(&cache, name, 1), None);
+        assert!(cache.record_read(1, 10, 5));
+        assert_eq!(cache.get(name, 1, None).unwrap(), Some((10, 5)));
+
+        //written to the run, which holds the levels' values as of the sequence it was written at
+        rewrite(&mut cache, name, 7, &[]);
+        assert_eq!(cache.pending.len(), 0);
+        assert_eq!(cache.get(name, 1, None).unwrap(), Some((10, 7)));
+        assert_eq!(get(&cache, name, 2), None);
+    }
+
+    #[test]
+    fn test_stale_entries_are_dropped() {
+        let name = &test_db_name();
+        let mut cache = ReadCache::new(4);
+        for key in 0..2 {
+            for _ in 0..ReadCache::ADMISSION_THRESHOLD {
+                cache.record_read(key, key * 10, 5);
+            }
+        }
+        rewrite(&mut cache, name, 5, &[]);
+        assert_eq!(cache.len(), 2);
+        //a run written since might hold key 1, the key keeps its read count so the next read admits it again
+        rewrite(&mut cache, name, 6, &[1]);
+        assert_eq!(cache.len(), 1);
+        assert_eq!(get(&cache, name, 1), None);
+        assert_eq!(get(&cache, name, 0), Some(0));
+        cache.record_read(1, 11, 8);
+        assert_eq!(get(&cache, name, 1), Some(11));
+        rewrite(&mut cache, name, 8, &[]);
+        assert_eq!(get(&cache, name, 1), Some(11));
+
+        //only the newest generation's files are kept, none once nothing is current
+        let run = cache.run.unwrap();
+        let level_directory =
+            filename::lsm_level_directory(&(&ReadCache::run_name(name), ReadCache::LEVEL));
+        let files = name.storage().read_dir(&level_directory).unwrap();
+        let num_files = if cfg!(feature = "bloom") { 2 } else { 1 };
+        assert_eq!(files.len(), num_files);
+        assert!(files.iter().all(|path| path
+            .file_stem()
+            .is_some_and(|stem| *stem == *run.generation.to_string())));
+        rewrite(&mut cache, name, 9, &[0, 1]);
+        assert_eq!(cache.run, None);
+        assert!(name
+            .storage()
+            .read_dir(&level_directory)
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn test_disabled() {
+        let name = &test_db_name();
+        let mut cache = ReadCache::new(0);
+        for _ in 0..ReadCache::ADMISSION_THRESHOLD * 2 {
+            assert!(!cache.record_read(1, 10, 0));
+        }
+        assert_eq!(get(&cache, name, 1), None);
+        assert_eq!(cache.len(), 0);
+    }
+
+    #[test]
+    fn test_truncation_keeps_most_read() {
+        let name = &test_db_name();
+        let mut cache = ReadCache::new(2);
+        for key in 0..5 {
+            //key 4 is read the most, key 0 the least
+            for _ in 0..ReadCache::ADMISSION_THRESHOLD + key as Size {
+                cache.record_read(key, key * 10, 0);
+            }
+        }
+        rewrite(&mut cache, name, 0, &[]);
+        assert_eq!(cache.len(), 2);
+        assert_eq!(get(&cache, name, 4), Some(40));
+        assert_eq!(get(&cache, name, 3), Some(30));
+
+        cache.set_capacity(1);
+        rewrite(&mut cache, name, 0, &[]);
+        assert_eq!(cache.len(), 1);
+        assert_eq!(get(&cache, name, 4), Some(40));
+        assert_eq!(get(&cache, name, 3), None);
+    }
+
+    #[test]
+    fn test_read_counts_are_bounded() {
+        let capacity = 4;
+        let mut cache = ReadCache::new(capacity);
+        for key in 0..10_000 {
+            cache.record_read(key, key, 0);
+        }
+        assert!(cache.read_counts.len() <= capacity * ReadCache::READ_COUNT_LIMIT_RATIO + 1);
+    }
+
+    #[test]
+    fn test_open() {
+        let name = &test_db_name();
+        let storage = name.storage();
+        let mut cache = ReadCache::new(3);
+        for key in 0..3 {
+            for _ in 0..ReadCache::ADMISSION_THRESHOLD {
+                cache.record_read(key, -key, 7);
+            }
+        }
+        rewrite(&mut cache, name, 7, &[]);
+        let descriptor = filename::read_cache(name);
+        storage.write_file(&descriptor, &cache.serialize()).unwrap();
+
+        //the descriptor is only trusted once
+        let cache = ReadCache::open(name).unwrap();
+        assert_eq!(cache.capacity(), 3);
+        for key in 0..3 {
+            assert_eq!(cache.get(name, key, None).unwrap(), Some((-key, 7)));
+        }
+        assert!(!storage.path_exists(&descriptor));
+        let cache = ReadCache::open(name).unwrap();
+        assert_eq!(cache.capacity(), 0);
+        assert_eq!(cache.len(), 0);
+        assert!(!storage.path_exists(&filename::read_cache_directory(name)));
+
+        //capped so the descriptor's version is found
+        let mut cache = ReadCache::new(1);
+        cache.set_capacity(Size::MAX);
+        let cache = ReadCache::deserialize(&cache.serialize()).unwrap();
+        assert_eq!(cache.capacity(), ReadCache::MAX_CAPACITY);
+        //descriptors without a version were never written by this layout
+        let unversioned = serde_state::encode(&(3 as Size, None::<CacheRun>));
+        assert!(ReadCache::deserialize(&unversioned).is_err());
+    }
+
+    #[test]
+    fn test_open_truncated_run() {
+        let name = &test_db_name();
+        let storage = name.storage();
+        let mut cache = ReadCache::new(3);
+        for key in 0..3 {
+            for _ in 0..ReadCache::ADMISSION_THRESHOLD {
+                cache.record_read(key, key, 7);
+            }
+        }
+        rewrite(&mut cache, name, 7, &[]);
+        storage
+            .write_file(&filename::read_cache(name), &cache.serialize())
+            .unwrap();
+        let run_address = &(
+            &ReadCache::run_name(name),
+            ReadCache::LEVEL,
+            cache.run.unwrap().generation,
+        );
+        let sst = storage.read_file(&run_address.path_sst()).unwrap();
+        storage
+            .write_file(&run_address.path_sst(), &sst[..sst.len() / 2])
+            .unwrap();
+        //a leftover of a crash, never referred to
+        storage
+            .write_file(&filename::tmp_path(&run_address.path_sst()), &sst)
+            .unwrap();
+
+        //the capacity is kept, the run isn't
+        let cache = ReadCache::open(name).unwrap();
+        assert_eq!(cache.capacity(), 3);
+        assert_eq!(cache.len(), 0);
+        assert_eq!(get(&cache, name, 1), None);
+        assert!(!storage.path_exists(&filename::read_cache_directory(name)));
+    }
+}
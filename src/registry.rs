@@ -0,0 +1,192 @@
+use std::{
+    cell::RefCell,
+    error::Error,
+    fmt,
+    panic::{self, AssertUnwindSafe},
+    path::{Path, PathBuf},
+    rc::{Rc, Weak},
+    time::Instant,
+};
+
+use crate::{db::Database, util::types::Size};
+
+///Why a database wasn't closed by DatabaseRegistry::close_all
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CloseError {
+    ///Closing failed (e.g. its files couldn't be written), the database is left open so closing can be retried
+    Failed(String),
+    ///The deadline passed before the database's turn
+    DeadlineExceeded,
+    ///The handle was borrowed while closing
+    Busy,
+}
+
+impl fmt::Display for CloseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CloseError::Failed(reason) => write!(f, "close failed: {reason}"),
+            CloseError::DeadlineExceeded => write!(f, "shutdown deadline exceeded"),
+            CloseError::Busy => write!(f, "database is in use"),
+        }
+    }
+}
+
+///Every database DatabaseRegistry::close_all couldn't close
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ShutdownError {
+    pub failures: Vec<(PathBuf, CloseError)>,
+}
+
+impl fmt::Display for ShutdownError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} databases weren't closed", self.failures.len())?;
+        for (path, why) in &self.failures {
+            write!(f, "\n{}: {why}", path.display())?;
+        }
+        Ok(())
+    }
+}
+
+impl Error for ShutdownError {}
+
+///Tracks the databases an application opened so they can all be closed at shutdown. Only weak handles are kept,
+/// dropping the last handle of a database closes it as usual and the registry forgets it
+#[derive(Debug, Default)]
+pub struct DatabaseRegistry {
+    databases: Vec<(PathBuf, Weak<RefCell<Database>>)>, //paths are kept, a closed database forgets its own
+}
+
+impl DatabaseRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    ///Opens the database rooted at <name> (see Database::open) and tracks it
+    pub fn open(&mut self, name: impl AsRef<Path>) -> Rc<RefCell<Database>> {
+        self.register(Database::open(name))
+    }
+    ///Tracks an already open database
+    pub fn register(&mut self, db: Database) -> Rc<RefCell<Database>> {
+        let path = db.path().to_path_buf();
+        let db = Rc::new(RefCell::new(db));
+        self.databases.push((path, Rc::downgrade(&db)));
+        db
+    }
+    ///Number of tracked databases that are still open, for health checks
+    pub fn open_count(&mut self) -> Size {
+        self.forget_closed();
+        self.databases.len()
+    }
+    ///Closes every tracked database in the order they were opened, databases still waiting their turn once
+    /// <deadline> has passed are left open. A database that fails to close doesn't stop the others, every failure
+    /// is reported with the database's path.
+    /// NOTE: the deadline is checked between databases, a close that already started isn't interrupted
+    pub fn close_all(&mut self, deadline: Instant) -> Result<(), ShutdownError> {
+        self.forget_closed();
+        let mut failures = Vec::new();
+        for (path, db) in &self.databases {
+            let Some(db) = db.upgrade() else {
+                continue;
+            };
+            if Instant::now() >= deadline {
+                failures.push((path.clone(), CloseError::DeadlineExceeded));
+                continue;
+            }
+            let Ok(mut db) = db.try_borrow_mut() else {
+                failures.push((path.clone(), CloseError::Busy));
+                continue;
+            };
+            //some failures past flushing are still reported by panicking
+            let reason = match panic::catch_unwind(AssertUnwindSafe(|| db.try_close())) {
+                Ok(Ok(())) => continue,
+                Ok(Err(why)) => why.to_string(),
+                Err(why) => match why.downcast_ref::<String>() {
+                    Some(reason) => reason.clone(),
+                    None => why.downcast_ref::<&str>().unwrap_or(&"unknown").to_string(),
+                },
+            };
+            failures.push((path.clone(), CloseError::Failed(reason)));
+        }
+        self.forget_closed();
+        match failures.is_empty() {
+            true => Ok(()),
+            false => Err(ShutdownError { failures }),
+        }
+    }
+    ///Forget databases that were dropped or closed
+    fn forget_closed(&mut self) {
+        self.databases.retain(|(_, db)| {
+            db.upgrade()
+                .is_some_and(|db| db.try_borrow().map_or(true, |db| !db.is_closed()))
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+    use crate::util::testing;
+
+    #[test]
+    fn close_all_test() {
+        let test_dir = testing::test_dir("database_registry_test");
+        if test_dir.exists() {
+            std::fs::remove_dir_all(test_dir).unwrap();
+        }
+        let names = ["a", "b", "c"].map(|name| test_dir.join(name));
+        let mut registry = DatabaseRegistry::new();
+        let handles: Vec<_> = names
+            .iter()
+            .map(|name| {
+                let db = registry.open(name);
+                db.borrow_mut().put(1, 10).unwrap(); //closing has to flush it
+                db
+            })
+            .collect();
+        assert_eq!(registry.open_count(), 3);
+
+        //out of time before anything closes
+        let err = registry.close_all(Instant::now()).unwrap_err();
+        assert_eq!(err.failures.len(), 3);
+        assert!(err
+            .failures
+            .iter()
+            .all(|(_, why)| *why == CloseError::DeadlineExceeded));
+        assert_eq!(registry.open_count(), 3);
+
+        //b's level 0 directory is replaced by a file, so its memtable can't be flushed
+        let level_dir = names[1].join("0");
+        if level_dir.exists() {
+            std::fs::remove_dir_all(&level_dir).unwrap();
+        }
+        std::fs::write(&level_dir, "").unwrap();
+        let deadline = Instant::now() + Duration::from_secs(60);
+        let err = registry.close_all(deadline).unwrap_err();
+        assert_eq!(err.failures.len(), 1);
+        let (path, why) = &err.failures[0];
+        assert_eq!(path, &names[1]);
+        assert!(matches!(why, CloseError::Failed(_)));
+        assert!(err.to_string().contains(&names[1].display().to_string()));
+        assert!(handles[0].borrow().is_closed());
+        assert!(!handles[1].borrow().is_closed());
+        assert!(handles[2].borrow().is_closed());
+        assert_eq!(registry.open_count(), 1);
+
+        //the failed database can be closed once the problem is fixed
+        std::fs::remove_file(&level_dir).unwrap();
+        registry.close_all(deadline).unwrap();
+        assert_eq!(registry.open_count(), 0);
+        for name in &names {
+            assert_eq!(Database::open(name).get(1), Some(10));
+        }
+
+        //dropped handles are forgotten
+        let db = registry.open(&names[0]);
+        assert_eq!(registry.open_count(), 1);
+        drop(db);
+        assert_eq!(registry.open_count(), 0);
+        drop(handles);
+        std::fs::remove_dir_all(test_dir).unwrap();
+    }
+}
@@ -0,0 +1,203 @@
+//!Best-effort recovery of the entries of a damaged database, see Database::salvage. Runs are found by listing the
+//! level directories instead of reading the metadata, and read a page at a time so a page that can't be read (or
+//! doesn't hold sorted entries) only loses that page
+
+use std::{fmt, io, path::Path};
+
+use crate::{
+    ceil_div,
+    db::Database,
+    error::DbError,
+    file_io::serde_entry,
+    sst::compression,
+    util::{
+        filename::{self, RunPaths},
+        system_info,
+        types::{DatabaseName, Entry, Key, KeyCodec, Level, Page, Run, Size},
+    },
+};
+
+///A page of a run that salvage couldn't use
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SkippedPage {
+    pub level: Level,
+    pub run: Run,
+    pub page: Page,
+    pub reason: String,
+}
+
+///What Database::salvage recovered and what it had to skip
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SalvageReport {
+    pub runs_read: Size,
+    pub entries_read: Size, //from usable pages, including ones younger runs shadow
+    pub skipped_runs: Vec<(Level, Run, String)>, //runs whose SST couldn't be opened at all
+    pub skipped_pages: Vec<SkippedPage>,
+    pub skipped_entries: Size, //entries the new database rejects, e.g. Database::INVALID_KEY
+    pub default_key_codec: bool, //the config couldn't be read, entries were decoded with KeyCodec::default
+}
+
+#[allow(dead_code)]
+impl SalvageReport {
+    ///Whether every run and page was read
+    pub fn is_complete(&self) -> bool {
+        self.skipped_runs.is_empty() && self.skipped_pages.is_empty() && self.skipped_entries == 0
+    }
+}
+
+impl fmt::Display for SalvageReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "{} entries read from {} runs",
+            self.entries_read, self.runs_read
+        )?;
+        if self.default_key_codec {
+            writeln!(f, "config unreadable, keys decoded with the default codec")?;
+        }
+        for (level, run, reason) in &self.skipped_runs {
+            writeln!(f, "level {level}, run {run}: skipped, {reason}")?;
+        }
+        for SkippedPage {
+            level,
+            run,
+            page,
+            reason,
+        } in &self.skipped_pages
+        {
+            writeln!(
+                f,
+                "level {level}, run {run}, page {page}: skipped, {reason}"
+            )?;
+        }
+        if self.skipped_entries > 0 {
+            writeln!(f, "{} entries rejected", self.skipped_entries)?;
+        }
+        Ok(())
+    }
+}
+
+///Number of the level or run a path in the database is named after, e.g. 3 for `<db>/3` or `<db>/2/3.sst`
+fn numbered(path: &Path, extension: Option<&str>) -> Option<usize> {
+    if path.extension().and_then(|ext| ext.to_str()) != extension {
+        return None;
+    }
+    path.file_stem()?.to_str()?.parse().ok()
+}
+
+///Every run with an SST file in <db_name>, oldest first: deeper levels are older, and so are lower numbered runs
+fn runs_on_disk(db_name: &DatabaseName) -> io::Result<Vec<(Level, Run)>> {
+    let storage = db_name.storage();
+    let mut levels: Vec<Level> = (storage.read_dir(db_name)?.iter())
+        .filter_map(|path| numbered(path, None))
+        .collect();
+    levels.sort_unstable_by(|a, b| b.cmp(a));
+    let mut runs = Vec::new();
+    for level in levels {
+        let directory = filename::lsm_level_directory(&(db_name, level));
+        let mut level_runs: Vec<Run> = (storage.read_dir(&directory)?.iter())
+            .filter_map(|path| numbered(path, Some(filename::SST_FILE_EXTENSION)))
+            .collect();
+        level_runs.sort_unstable();
+        runs.extend(level_runs.into_iter().map(|run| (level, run)));
+    }
+    Ok(runs)
+}
+
+///Entries of a page read from a run, or why the page can't be used. <previous_key> is the last key salvaged from the
+/// run, the page's keys must all be larger
+fn usable_entries(
+    bytes: &[u8],
+    previous_key: Option<Key>,
+    key_codec: KeyCodec,
+) -> Result<Vec<Entry>, String> {
+    if !bytes.len().is_multiple_of(system_info::ENTRY_SIZE) {
+        return Err(format!("holds {} bytes, a partial entry", bytes.len()));
+    }
+    let entries = serde_entry::deserialize(bytes, key_codec)?;
+    let mut keys = previous_key
+        .into_iter()
+        .chain(entries.iter().map(|(key, _)| *key));
+    let mut last = keys.next();
+    for key in keys {
+        if last.is_some_and(|last| last >= key) {
+            return Err(format!("key {key} isn't larger than the key before it"));
+        }
+        last = Some(key);
+    }
+    Ok(entries)
+}
+
+///Put every usable entry of every run of the database at <db_name> into <out>, oldest run first so the youngest entry
+/// of each key is the one that's kept
+pub(crate) fn salvage_into(
+    db_name: &DatabaseName,
+    out: &mut Database,
+) -> Result<SalvageReport, DbError> {
+    let mut report = SalvageReport::default();
+    let key_codec = Database::read_key_codec(db_name).unwrap_or_else(|_| {
+        report.default_key_codec = true;
+        KeyCodec::default()
+    });
+    let page_size = system_info::page_size();
+    for (level, run) in runs_on_disk(db_name)? {
+        let path = (db_name, level, run).path_sst();
+        let mut file = match db_name.storage().open_read_buffered(&path) {
+            Ok(file) => file,
+            Err(why) => {
+                report.skipped_runs.push((level, run, why.to_string()));
+                continue;
+            }
+        };
+        let index = match compression::read_index(&mut file) {
+            Ok(index) => index,
+            Err(why) => {
+                report.skipped_runs.push((level, run, why.to_string()));
+                continue;
+            }
+        };
+        let num_pages = match (&index, file.len_bytes()) {
+            (Some(index), _) => index.num_pages(),
+            (None, Ok(len)) => ceil_div!(len as Size, page_size),
+            (None, Err(why)) => {
+                report.skipped_runs.push((level, run, why.to_string()));
+                continue;
+            }
+        };
+        report.runs_read += 1;
+        let mut previous_key = None;
+        for page in 0..num_pages {
+            let entries = compression::read_page(&mut file, index.as_ref(), page)
+                .map_err(|why| why.to_string())
+                .and_then(|bytes| usable_entries(&bytes, previous_key, key_codec));
+            let entries = match entries {
+                Ok(entries) => entries,
+                Err(reason) => {
+                    report.skipped_pages.push(SkippedPage {
+                        level,
+                        run,
+                        page,
+                        reason,
+                    });
+                    continue;
+                }
+            };
+            report.entries_read += entries.len();
+            previous_key = entries.last().map(|(key, _)| *key).or(previous_key);
+            for (key, value) in entries {
+                let written = match value {
+                    Database::TOMBSTONE_VALUE => out.delete(key),
+                    _ => out.put(key, value),
+                };
+                match written {
+                    Ok(()) => {}
+                    Err(DbError::ReservedKey | DbError::ReservedValue) => {
+                        report.skipped_entries += 1
+                    }
+                    Err(why) => return Err(why),
+                }
+            }
+        }
+    }
+    Ok(report)
+}
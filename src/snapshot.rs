@@ -0,0 +1,126 @@
+//!Read-only views of a database as it was when the view was taken, see Database::snapshot_view. A snapshot holds a
+//! hard link to each file of every run it reads, so compactions and flushes of the database can replace or remove
+//! those runs without changing what the snapshot reads (run files are never written in place). Links copy no data,
+//! a snapshot costs a directory entry per file and the memtable it shares with the database
+
+use std::{
+    collections::BTreeSet,
+    io,
+    path::{Path, PathBuf},
+    sync::{Mutex, MutexGuard},
+};
+
+use crate::{
+    db::{Database, ScanOptions, TruncationWatch},
+    error::DbError,
+    util::{
+        filename,
+        types::{DatabaseName, Entry, Key, Value},
+    },
+};
+
+///Directories of the snapshots alive in this process, which remove_stale_snapshots leaves alone
+static LIVE_SNAPSHOTS: Mutex<BTreeSet<PathBuf>> = Mutex::new(BTreeSet::new());
+
+fn live_snapshots() -> MutexGuard<'static, BTreeSet<PathBuf>> {
+    LIVE_SNAPSHOTS
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+///A database as it was when Database::snapshot_view took it, later writes to the database aren't seen.
+/// Its files are removed when it's dropped. NOTE: it can't be read once the database is truncated, see try_get
+#[derive(Debug)]
+pub struct Snapshot {
+    db: Database,            //rooted at the snapshot's directory, never written to or closed
+    directory: DatabaseName, //in the storage of the database it was taken of
+    truncation: TruncationWatch, //of the database it was taken of
+}
+
+#[allow(dead_code)]
+impl Snapshot {
+    ///Create the directory of a new snapshot of the database at <db_name>, it's live until the snapshot is dropped.
+    /// <id> has to be unique in the process
+    pub(crate) fn create_directory(db_name: &DatabaseName, id: usize) -> io::Result<DatabaseName> {
+        let storage = db_name.storage();
+        let directory = filename::snapshot_directory(db_name, id);
+        if storage.path_exists(&directory) {
+            storage.remove_dir_all(&directory)?; //left by another process, whose ids started at 0 too
+        }
+        storage.create_dir_all(&directory)?;
+        live_snapshots().insert(directory.clone());
+        Ok(DatabaseName::new(directory, storage.clone()))
+    }
+    ///<db> is opened on <directory>, from create_directory
+    pub(crate) fn new(db: Database, directory: DatabaseName, truncation: TruncationWatch) -> Self {
+        Self {
+            db,
+            directory,
+            truncation,
+        }
+    }
+    ///Panics if the database was truncated since the snapshot was taken, see try_get
+    pub fn get(&mut self, key: Key) -> Option<Value> {
+        self.try_get(key).unwrap_or_else(|why| panic!("{why}"))
+    }
+    ///get, or DbError::Truncated if the database was truncated since the snapshot was taken (DbError::Io if a run
+    /// can't be read)
+    pub fn try_get(&mut self, key: Key) -> Result<Option<Value>, DbError> {
+        self.truncation.check()?;
+        self.db.try_get(key)
+    }
+    ///Panics if the database was truncated since the snapshot was taken, see try_scan
+    pub fn scan(&mut self, key1: Key, key2: Key) -> Vec<Entry> {
+        self.try_scan(key1, key2)
+            .unwrap_or_else(|why| panic!("{why}"))
+    }
+    ///scan, or DbError::Truncated if the database was truncated since the snapshot was taken (DbError::Io if a run
+    /// can't be read)
+    pub fn try_scan(&mut self, key1: Key, key2: Key) -> Result<Vec<Entry>, DbError> {
+        self.truncation.check()?;
+        self.db.try_scan(key1, key2)
+    }
+    pub fn scan_with_options(&mut self, key1: Key, key2: Key, options: ScanOptions) -> Vec<Entry> {
+        self.try_scan_with_options(key1, key2, options)
+            .unwrap_or_else(|why| panic!("{why}"))
+    }
+    ///scan_with_options, or DbError::Truncated if the database was truncated since the snapshot was taken
+    pub fn try_scan_with_options(
+        &mut self,
+        key1: Key,
+        key2: Key,
+        options: ScanOptions,
+    ) -> Result<Vec<Entry>, DbError> {
+        self.truncation.check()?;
+        self.db.try_scan_with_options(key1, key2, options)
+    }
+    ///Directory holding the snapshot's links to the runs it reads
+    pub fn path(&self) -> &Path {
+        self.directory.path()
+    }
+}
+
+impl Drop for Snapshot {
+    fn drop(&mut self) {
+        self.db.clear(); //so dropping it doesn't write a config or metadata file
+        let _ = self.directory.storage().remove_dir_all(&self.directory);
+        live_snapshots().remove(self.directory.path());
+    }
+}
+
+///Remove the directories of snapshots that were never dropped (e.g. cut short by a crash) from the database at
+/// <db_name>, their links are all that's left of runs the database has since replaced
+pub(crate) fn remove_stale_snapshots(db_name: &DatabaseName) -> io::Result<()> {
+    let storage = db_name.storage();
+    let directory = filename::snapshots_directory(db_name);
+    if !storage.path_exists(&directory) {
+        return Ok(());
+    }
+    let live_snapshots = live_snapshots();
+    for path in storage.read_dir(&directory)? {
+        if !live_snapshots.contains(&path) {
+            storage.remove_dir_all(&path)?;
+        }
+    }
+    Ok(())
+}
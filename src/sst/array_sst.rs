@@ -3,20 +3,31 @@ use crate::{
     db::Database,
     file_io::{
         direct_io, file_interface,
+        page_buffers::PageBuffer,
         serde_entry::{
-            self, deserialize, deserialize_entry_within_page, deserialize_from, serialize_into,
+            self, deserialize, deserialize_entry_within_page, try_deserialize_key_within_page,
         },
+        storage::{DbFile, Storage},
     },
-    sst::sst_util::{get_entries_at_page, num_pages},
     util::{
-        filename,
+        filename::{self, RunPaths},
         system_info::{self, num_entries_per_page, ENTRY_SIZE},
-        types::{Entry, Key, LevelAddress, Page, Run, RunAddress, Size, Value},
+        types::{Entry, Key, KeyCodec, LevelAddress, Run, RunAddress, Size, Value},
     },
 };
-use std::{collections::BinaryHeap, fs, io};
+use std::{
+    cell::Cell,
+    collections::BinaryHeap,
+    io,
+    ops::Range,
+    path::{Path, PathBuf},
+};
 
-use super::{sst_util::get_sst_page, SortedStringTable};
+use super::{
+    compression::{self, PageIndex, RunWriter},
+    sst_util::{get_pinned_sst_page, get_sst_page, keys_in_bounds},
+    MergeSidecars, MergeStats, SortedStringTable,
+};
 
 fn index_to_2d_index(row_size: usize, index: usize) -> (usize, usize) {
     let row_index = index / row_size;
@@ -24,30 +35,677 @@ fn index_to_2d_index(row_size: usize, index: usize) -> (usize, usize) {
     (row_index, index_within_row)
 }
 
-pub struct Sst;
+///Error for a merge whose input or output keys aren't strictly increasing, i.e. a corrupt run
+fn out_of_order_error(what: String, key: Key, previous_key: Key) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("{what} has key {key} after key {previous_key}, keys must be strictly increasing"),
+    )
+}
 
-impl SortedStringTable for Sst {
-    ///Writes key-value array (or vec) onto SST file in appropriate directory.
-    ///NOTE: Avoid using arrays larger than the size of the buffer. We shouldn't need to handle very large writes
-    /// since compaction will be implemented after we switch to a static btree implementation
-    fn write(&self, run_address: &RunAddress, entries: &[Entry]) -> io::Result<()> {
-        let (db_name, level, ..) = *run_address;
-        //create directory for the level if needed
-        let directory = filename::lsm_level_directory(&(db_name, level));
-        if !direct_io::path_exists(&directory) {
-            fs::create_dir(&directory)?;
+///Create the temporary file the SST file of a run is written to (see Storage::publish), along with the level's
+/// directory if needed
+fn create_sst_file(run_address: &RunAddress) -> io::Result<DbFile> {
+    let (db_name, level, ..) = *run_address;
+    let directory = filename::lsm_level_directory(&(db_name, level));
+    if !run_address.0.storage().path_exists(&directory) {
+        run_address.0.storage().create_dir_all(&directory)?;
+    }
+    run_address.0.storage().create_tmp(&run_address.path_sst())
+}
+
+///Finish a merge's output and sync it if the sync policy says so, the caller renames it into place
+fn finish_output(storage: &Storage, output: &mut RunWriter) -> io::Result<()> {
+    output.finish()?;
+    storage.sync_run_file(output.file())
+}
+
+///Bytes a merge of <num_runs> runs holds in memory when reading <window> entries of each run at a time:
+/// the input windows, a page of output, the heap's entry for each run and the page being read
+pub fn merge_memory_bytes(num_runs: Size, window: Size) -> Size {
+    let entry_size = std::mem::size_of::<Entry>();
+    let heap_entry_size = std::mem::size_of::<(Key, Run, Value)>();
+    num_runs * (window * entry_size + heap_entry_size)
+        + num_entries_per_page() * entry_size
+        + system_info::page_size()
+}
+
+///Most runs a merge reads a page of each of at a time within <memory_budget> bytes (0 for no limit), at least 2.
+/// A merge of more runs is done in passes that merge this many runs at a time (see Sst::merge_run_list)
+pub fn merge_group_size(memory_budget: Size) -> Size {
+    if memory_budget == 0 {
+        return Size::MAX;
+    }
+    let fixed_bytes = merge_memory_bytes(0, 0);
+    let run_bytes = merge_memory_bytes(1, num_entries_per_page()) - fixed_bytes;
+    (memory_budget.saturating_sub(fixed_bytes) / run_bytes).max(2)
+}
+
+///Entries of each run a merge of <num_runs> runs reads at a time to stay within <memory_budget> bytes (0 for no
+/// limit). Between one entry and a page, a budget too small for single entries is exceeded rather than failing
+pub fn merge_window(num_runs: Size, memory_budget: Size) -> Size {
+    let entries_per_page = num_entries_per_page();
+    if memory_budget == 0 || num_runs == 0 {
+        return entries_per_page;
+    }
+    let window_bytes = memory_budget.saturating_sub(merge_memory_bytes(num_runs, 0));
+    let window = window_bytes / (num_runs * std::mem::size_of::<Entry>());
+    window.clamp(1, entries_per_page)
+}
+
+#[derive(Default)]
+pub struct Sst {
+    key_codec: KeyCodec,
+    merge_memory_budget: Size, //bytes merge_runs' buffers may hold, 0 for no limit
+}
+
+impl Sst {
+    pub fn new(key_codec: KeyCodec) -> Self {
+        Self {
+            key_codec,
+            merge_memory_budget: 0,
         }
+    }
+    pub fn set_merge_memory_budget(mut self, merge_memory_budget: Size) -> Self {
+        self.merge_memory_budget = merge_memory_budget;
+        self
+    }
+    ///Binary search for the index of the first entry whose key is not <is_before> (like slice::partition_point).
+    /// Returns num_entries if every key is <is_before>
+    fn partition_point(
+        &self,
+        run_address: &RunAddress,
+        num_entries: Size,
+        mut buffer_pool: Option<&mut BufferPool>,
+        is_before: impl Fn(Key) -> bool,
+    ) -> io::Result<Size> {
+        let mut curr_page_index = usize::MAX;
+        let mut curr_page = PageBuffer::from(Vec::new());
+        let entries_per_page = num_entries_per_page();
 
-        let path = filename::sst_path(run_address);
-        let mut file = direct_io::create(&path)?;
-        serialize_into(&mut file, entries)?;
-        Ok(())
+        let (mut left, mut right) = (0, num_entries);
+        while left < right {
+            let middle_index = (left + right) / 2;
+            let (middle_page_index, entry_index) =
+                index_to_2d_index(entries_per_page, middle_index);
+            if middle_page_index != curr_page_index {
+                curr_page_index = middle_page_index;
+                curr_page =
+                    get_sst_page(run_address, middle_page_index, buffer_pool.as_deref_mut())?;
+            }
+            let middle_key =
+                try_deserialize_key_within_page(&curr_page, entry_index, self.key_codec)?;
+            if is_before(middle_key) {
+                left = middle_index + 1;
+            } else {
+                right = middle_index;
+            }
+        }
+        Ok(left)
+    }
+    fn entry_at(
+        &self,
+        run_address: &RunAddress,
+        index: Size,
+        buffer_pool: Option<&mut BufferPool>,
+    ) -> io::Result<Entry> {
+        let (page_index, entry_index) = index_to_2d_index(num_entries_per_page(), index);
+        let page = get_sst_page(run_address, page_index, buffer_pool)?;
+        deserialize_entry_within_page(&page, entry_index, self.key_codec)
+    }
+    ///Merge SST runs of a level into a single sorted run at <output_path>, the input runs are left untouched.
+    /// Expired entries are replaced by tombstones (dropped along with them when discarding tombstones), the
+    /// expiries of the entries written are recorded in <sidecars>. Merge operands are combined with the older
+    /// entries of their key until a value (or tombstone) is found under them, which becomes a plain value. Operands
+    /// with nothing under them in the level stay an operand, unless tombstones are discarded (on the last level
+    /// nothing older is left) where they're applied to the operator's identity. Returns counts of what was written
+    /// and dropped.
+    /// Fails with InvalidData if an input run (or the output) isn't sorted, instead of writing an unsorted run.
+    /// <on_page_flushed> gets the entries of each page as it's written, so indexes can be built in the same pass, an
+    /// error from it stops the merge.
+    /// Runs are read a window of entries at a time, shrunk below a page so the merge's buffers stay within the
+    /// merge memory budget (see merge_window)
+    pub fn merge_runs(
+        &self,
+        level_address: &LevelAddress,
+        entry_counts: &[Size],
+        discard_tombstones: bool,
+        output_path: &Path,
+        sidecars: &mut MergeSidecars,
+        on_page_flushed: &mut dyn FnMut(&[Entry]) -> io::Result<()>,
+    ) -> io::Result<MergeStats> {
+        let inputs: Vec<(Run, Size)> = entry_counts.iter().copied().enumerate().collect();
+        self.merge_run_list(
+            level_address,
+            &inputs,
+            discard_tombstones,
+            &|_| output_path.to_path_buf(),
+            0,
+            sidecars,
+            on_page_flushed,
+        )
+    }
+    ///Same as merge_runs for the runs of the level listed in <inputs> (run number and entry count, oldest first), the
+    /// sidecars are looked up by position in <inputs>. The merged run is split into files of at most
+    /// <pages_per_output> pages (0 for a single file), the n-th one at <output_path>(n). Files are filled in key order
+    /// so every file but the last is full, they're only created once a page is written to them (apart from the first).
+    /// More runs than the merge memory budget holds a page of each for are merged in passes (see merge_group_size)
+    #[allow(clippy::too_many_arguments)]
+    pub fn merge_run_list(
+        &self,
+        level_address: &LevelAddress,
+        inputs: &[(Run, Size)],
+        discard_tombstones: bool,
+        output_path: &dyn Fn(Size) -> PathBuf,
+        pages_per_output: Size,
+        sidecars: &mut MergeSidecars,
+        on_page_flushed: &mut dyn FnMut(&[Entry]) -> io::Result<()>,
+    ) -> io::Result<MergeStats> {
+        let (db_name, level) = *level_address;
+        let group_size = merge_group_size(self.merge_memory_budget);
+        if inputs.len() <= group_size {
+            return self.merge_pass(
+                level_address,
+                inputs,
+                &|run| (db_name, level, run).path_sst(),
+                discard_tombstones,
+                output_path,
+                pages_per_output,
+                sidecars,
+                on_page_flushed,
+            );
+        }
+        let mut intermediates = Vec::new();
+        let merged = self.merge_in_passes(
+            level_address,
+            inputs,
+            group_size,
+            discard_tombstones,
+            output_path,
+            pages_per_output,
+            sidecars,
+            on_page_flushed,
+            &mut intermediates,
+        );
+        //the runs of the last pass, or of every pass so far if the merge failed
+        for path in intermediates {
+            if level_address.0.storage().path_exists(&path) {
+                let removed = level_address.0.storage().remove_file(&path);
+                if merged.is_ok() {
+                    removed?;
+                }
+            }
+        }
+        merged
+    }
+    ///merge_run_list of more than <group_size> runs: the runs are merged <group_size> at a time into intermediate
+    /// runs, keeping tombstones and operands (as a merge of a level that isn't the last does), until they're few enough
+    /// to be merged into the output. Groups are made of consecutive inputs, so an intermediate run is younger than the
+    /// ones before it just like its inputs, and youngest still wins in the merges after it. What's written, and what's
+    /// counted as dropped, is what a single merge of every input gives. Only the output's pages are passed to
+    /// <on_page_flushed>, the paths of the intermediate runs are added to <intermediates> as they're created.
+    /// NOTE: operands are combined in groups, so a MergeOperator::Add that saturates part way can sum differently
+    #[allow(clippy::too_many_arguments)]
+    fn merge_in_passes(
+        &self,
+        level_address: &LevelAddress,
+        inputs: &[(Run, Size)],
+        group_size: Size,
+        discard_tombstones: bool,
+        output_path: &dyn Fn(Size) -> PathBuf,
+        pages_per_output: Size,
+        sidecars: &mut MergeSidecars,
+        on_page_flushed: &mut dyn FnMut(&[Entry]) -> io::Result<()>,
+        intermediates: &mut Vec<PathBuf>,
+    ) -> io::Result<MergeStats> {
+        let (db_name, level) = *level_address;
+        let mut pass_inputs = inputs.to_vec();
+        let mut pass_sidecars = std::mem::take(sidecars);
+        let mut totals = MergeStats::default(); //of the intermediate runs
+        for pass in 0.. {
+            //inputs of later passes are the groups of the pass before
+            let input_path = |run: Run| match pass {
+                0 => (db_name, level, run).path_sst(),
+                _ => filename::sst_compaction_group_path(level_address, pass - 1, run),
+            };
+            if pass_inputs.len() <= group_size {
+                let stats = self.merge_pass(
+                    level_address,
+                    &pass_inputs,
+                    &input_path,
+                    discard_tombstones,
+                    output_path,
+                    pages_per_output,
+                    &mut pass_sidecars,
+                    on_page_flushed,
+                )?;
+                *sidecars = pass_sidecars; //what was recorded for the output
+                return Ok(MergeStats {
+                    entries_dropped: totals.entries_dropped + stats.entries_dropped,
+                    memory_bytes: totals.memory_bytes.max(stats.memory_bytes),
+                    pages_read: totals.pages_read + stats.pages_read,
+                    pages_written: totals.pages_written + stats.pages_written,
+                    ..stats
+                });
+            }
+            let mut groups = Vec::new();
+            let mut group_sidecars = Vec::new();
+            for (group, start) in (0..pass_inputs.len()).step_by(group_size).enumerate() {
+                let runs = start..(start + group_size).min(pass_inputs.len());
+                let path = filename::sst_compaction_group_path(level_address, pass, group);
+                intermediates.push(path.clone());
+                let mut sidecars = pass_sidecars.take_inputs(runs.clone());
+                let stats = self.merge_pass(
+                    level_address,
+                    &pass_inputs[runs],
+                    &input_path,
+                    false,
+                    &|_| path.clone(),
+                    0,
+                    &mut sidecars,
+                    &mut |_| Ok(()),
+                )?;
+                totals.entries_dropped += stats.entries_dropped;
+                totals.memory_bytes = totals.memory_bytes.max(stats.memory_bytes);
+                totals.pages_read += stats.pages_read;
+                totals.pages_written += stats.pages_written;
+                groups.push((group, stats.entries_written));
+                group_sidecars.push(sidecars);
+            }
+            if pass > 0 {
+                for (run, _) in &pass_inputs {
+                    level_address.0.storage().remove_file(&input_path(*run))?; //merged into this pass's runs
+                }
+            }
+            pass_inputs = groups;
+            pass_sidecars = pass_sidecars.of_outputs(group_sidecars);
+        }
+        unreachable!("every pass merges groups of at least 2 runs")
+    }
+    ///Merge the runs listed in <inputs> (number and entry count, oldest first), run <run>'s SST is at <input_path>(run)
+    #[allow(clippy::too_many_arguments)]
+    fn merge_pass(
+        &self,
+        level_address: &LevelAddress,
+        inputs: &[(Run, Size)],
+        input_path: &dyn Fn(Run) -> PathBuf,
+        discard_tombstones: bool,
+        output_path: &dyn Fn(Size) -> PathBuf,
+        pages_per_output: Size,
+        sidecars: &mut MergeSidecars,
+        on_page_flushed: &mut dyn FnMut(&[Entry]) -> io::Result<()>,
+    ) -> io::Result<MergeStats> {
+        let level = level_address.1;
+        let storage = level_address.0.storage();
+        let MergeSidecars { expiries, operands } = sidecars;
+        let operator = operands.operator();
+        let num_runs = inputs.len(); //Number of SST runs
+
+        let entries_per_page = num_entries_per_page();
+        let window = merge_window(num_runs, self.merge_memory_budget);
+
+        //Read up to <window> entries of a run starting at <start>, never past the end of <start>'s page
+        let pages_read = Cell::new(0);
+        let read_window = |input: usize, start: Size| -> io::Result<Vec<Entry>> {
+            let (page_index, index_within_page) = index_to_2d_index(entries_per_page, start);
+            let page =
+                compression::get_page(storage, &input_path(inputs[input].0), page_index, None)?;
+            pages_read.set(pages_read.get() + 1);
+            let end = (index_within_page + window).min(page.len() / ENTRY_SIZE);
+            deserialize(
+                &page[index_within_page * ENTRY_SIZE..end * ENTRY_SIZE],
+                self.key_codec,
+            )
+            .map_err(|reason| io::Error::new(io::ErrorKind::InvalidData, reason))
+        };
+
+        //Input buffer along with metadata for each run, index within buffer, index in the run of the buffer's next
+        // entry, last key pulled from the run. Buffers are filled on the first pull
+        type InputBufferData = (Vec<Entry>, usize, Size, Option<Key>);
+        let mut input_buffers: Vec<InputBufferData> =
+            (0..num_runs).map(|_| (Vec::new(), 0, 0, None)).collect();
+
+        //Pull entry from buffer, if at end, then fill buffer with the next window of entries
+        //Return None if no more entires to pull in run's SST
+        let pull_entry =
+            |input_buffers: &mut Vec<InputBufferData>, run: Run| -> io::Result<Option<Entry>> {
+                let (entries, curr_index, run_index, last_key) = &mut input_buffers[run];
+
+                if *curr_index >= entries.len() {
+                    //check if we are at the end of this input buffer
+                    if *run_index < inputs[run].1 {
+                        //if there are more entries to pull into our buffer, do it
+                        *entries = read_window(run, *run_index)?;
+                        if entries.is_empty() {
+                            return Ok(None); //run is shorter than its entry count
+                        }
+                        *curr_index = 0;
+                    } else {
+                        //no more entries in the SST to pull into buffer
+                        return Ok(None);
+                    }
+                }
+
+                let entry = entries[*curr_index];
+                *curr_index += 1;
+                *run_index += 1;
+
+                //a run that isn't sorted would make the merged run unsorted too
+                let key = entry.0;
+                if let Some(previous_key) = last_key.filter(|previous_key| *previous_key >= key) {
+                    let curr_page = (*run_index - 1) / entries_per_page;
+                    let what = format!("run {} of level {level} (page {curr_page})", inputs[run].0);
+                    return Err(out_of_order_error(what, key, previous_key));
+                }
+                *last_key = Some(key);
+
+                Ok(Some(entry))
+            };
+
+        type BufferHeap = BinaryHeap<(Key, Run, Value)>;
+        let mut heap = BufferHeap::with_capacity(num_runs); //to ensure we write the smallest value in our buffers
+
+        let mut output_buffer: Vec<Entry> = Vec::with_capacity(entries_per_page);
+        //outputs are preallocated to hold every input entry that's left for them, finish truncates them to what the
+        // merge wrote. Unfragmented outputs are faster to scan
+        let input_bytes: Size = inputs.iter().map(|(_, entries)| entries * ENTRY_SIZE).sum();
+        let output_bytes = |output: Size| -> Size {
+            let max_bytes = pages_per_output * entries_per_page * ENTRY_SIZE;
+            match pages_per_output {
+                0 => input_bytes,
+                _ => input_bytes
+                    .saturating_sub(output * max_bytes)
+                    .min(max_bytes),
+            }
+        };
+        let new_output = |output: Size| -> io::Result<RunWriter> {
+            let path = output_path(output);
+            let file = storage.create(&path)?;
+            //without the space reserved the output is written the way it would be without preallocation
+            let _ = direct_io::preallocate(&file, output_bytes(output) as u64);
+            Ok(RunWriter::new(file, storage.compression(), self.key_codec))
+        };
+        let mut output = new_output(0)?;
+        let mut num_outputs: Size = 1;
+        let mut output_pages: Size = 0;
+        let mut entries_written: Size = 0;
+        let mut tombstones_written: Size = 0;
+        let mut entries_dropped: Size = 0;
+        let mut pages_written: Size = 0;
+
+        let heap_insert = |heap: &mut BufferHeap, key: Key, value, run| {
+            //NOTE: tuple elements are sorted lexicographically in the heap by default, this fact is very
+            //      important for the implementation. In case of a tie, the youngest (highest number) SST
+            //      run will have its value used
+            heap.push((-key, run, value)); //Negative key to use as min_heap instead
+        };
+        let heap_extract = |heap: &mut BufferHeap| {
+            if let Some((negative_key, run, value_option)) = heap.pop() {
+                let key = -negative_key;
+                return Some(((key, value_option), run));
+            }
+            None
+        };
+
+        //take item from heap and replace it with another element in its run (if there is any)
+        let heap_swap_extract = |heap: &mut BufferHeap,
+                                 input_buffers: &mut Vec<InputBufferData>|
+         -> io::Result<Option<(Entry, Run)>> {
+            if let Some((entry, run)) = heap_extract(heap) {
+                if let Some(replacement_entry) = pull_entry(input_buffers, run)? {
+                    let (key, value) = replacement_entry;
+                    heap_insert(heap, key, value, run);
+                }
+                return Ok(Some((entry, run)));
+            }
+            Ok(None)
+        };
+        let mut flush_output_buffer = |output_buffer: &mut Vec<Entry>| -> io::Result<()> {
+            if output_buffer.is_empty() {
+                return Ok(());
+            }
+            if pages_per_output > 0 && output_pages == pages_per_output {
+                finish_output(storage, &mut output)?;
+                output = new_output(num_outputs)?;
+                num_outputs += 1;
+                output_pages = 0;
+            }
+            output.write_page(output_buffer)?;
+            on_page_flushed(output_buffer)?;
+            entries_written += output_buffer.len();
+            pages_written += 1;
+            output_pages += 1;
+            output_buffer.clear();
+            Ok(())
+        };
+        let mut last_output_key: Option<Key> = None;
+        let mut output_buffer_insert = |output_buffer: &mut Vec<Entry>,
+                                        entry: Entry|
+         -> io::Result<()> {
+            let key = entry.0;
+            if let Some(previous_key) = last_output_key.filter(|previous_key| *previous_key >= key)
+            {
+                let what = format!("merged run of level {level}");
+                return Err(out_of_order_error(what, key, previous_key));
+            }
+            last_output_key = Some(key);
+            output_buffer.push(entry);
+            //if we filled up our buffer, flush buffer to compaction file
+            if output_buffer.len() >= entries_per_page {
+                flush_output_buffer(output_buffer)?;
+            }
+            Ok(())
+        };
+
+        //put one entry from each buffer, NOTE: higher run number is younger
+        for run in (0..num_runs).rev() {
+            let entry_option = pull_entry(&mut input_buffers, run)?;
+            if entry_option.is_none() {
+                continue;
+            }
+            let (key, value) = entry_option.unwrap(); //NOTE: can unwrap safely because of earlier check
+            heap_insert(&mut heap, key, value, run);
+        }
+
+        let mut recent_key: Option<Key> = None;
+        //operands of the recent key combined so far (youngest first), waiting for the entry under them
+        let mut pending_operand: Option<Entry> = None;
+
+        //put entries into output buffer until there are no more entries to pull from any buffer
+        loop {
+            let entry_option = heap_swap_extract(&mut heap, &mut input_buffers)?;
+            let next_key = entry_option.map(|((key, _), _)| key);
+
+            //nothing in the level is under the pending operands, only the last level can apply them to the identity
+            if let Some((key, operand)) = pending_operand.filter(|(key, _)| Some(*key) != next_key)
+            {
+                pending_operand = None;
+                if discard_tombstones {
+                    output_buffer_insert(
+                        &mut output_buffer,
+                        (key, operator.apply(operator.identity(), operand)),
+                    )?;
+                } else {
+                    output_buffer_insert(&mut output_buffer, (key, operand))?;
+                    operands.record_output(key);
+                }
+            }
+
+            let Some(((key, mut value), run)) = entry_option else {
+                break; //nothing in heap, should be done
+            };
+            let is_operand = operands.is_operand(run, key);
+            if recent_key.is_some_and(|recent| recent == key) {
+                entries_dropped += 1; //shadowed by a younger entry, or combined with the operands above it
+                let Some((_, operand)) = pending_operand else {
+                    continue; //we already have inserted the value (or it we discarded its tombstone already)
+                };
+                if is_operand {
+                    pending_operand = Some((key, operator.apply(value, operand)));
+                    continue;
+                }
+                //the value under the operands, a deleted or expired one counts as the identity
+                pending_operand = None;
+                let mut expiry = expiries.expiry(run, key);
+                if value == Database::TOMBSTONE_VALUE || expiries.is_expired(expiry) {
+                    value = operator.identity();
+                    expiry = None;
+                }
+                output_buffer_insert(&mut output_buffer, (key, operator.apply(value, operand)))?;
+                if let Some(expiry) = expiry {
+                    expiries.record_output(key, expiry);
+                }
+                continue;
+            }
+            recent_key = Some(key);
+            if is_operand {
+                pending_operand = Some((key, value));
+                continue;
+            }
+            let mut expiry = expiries.expiry(run, key);
+            if expiries.is_expired(expiry) {
+                //an expired entry still hides older values of its key, same as a delete
+                value = Database::TOMBSTONE_VALUE;
+                expiry = None;
+            }
+            let is_tombstone = value == Database::TOMBSTONE_VALUE;
+            if !discard_tombstones || !is_tombstone {
+                output_buffer_insert(&mut output_buffer, (key, value))?;
+                tombstones_written += is_tombstone as Size;
+                if let Some(expiry) = expiry {
+                    expiries.record_output(key, expiry);
+                }
+            } else {
+                entries_dropped += 1;
+            }
+        }
+        //flush remaining elements
+        flush_output_buffer(&mut output_buffer)?;
+        finish_output(storage, &mut output)?;
+
+        Ok(MergeStats {
+            entries_written,
+            tombstones_written,
+            entries_dropped,
+            expiries_written: expiries.output().len(),
+            operands_written: operands.output().len(),
+            memory_bytes: merge_memory_bytes(num_runs, window),
+            pages_read: pages_read.get(),
+            pages_written,
+        })
+    }
+    ///Same as SortedStringTable::compact, <on_page_flushed> is called with the entries of every page of the new run
+    /// as it's written (see merge_runs)
+    pub fn compact_with_page_callback(
+        &self,
+        level_address: &LevelAddress,
+        entry_counts: &mut Vec<Size>,
+        discard_tombstones: bool,
+        mut buffer_pool: Option<&mut BufferPool>,
+        on_page_flushed: &mut dyn FnMut(&[Entry]) -> io::Result<()>,
+    ) -> io::Result<Option<MergeStats>> {
+        let (db_name, level) = *level_address;
+        let num_runs = entry_counts.len(); //Number of SST runs
+
+        if num_runs < 2 {
+            return Ok(None); //nothing to compact
+        }
+
+        let temp_file_name = filename::sst_compaction_path(level_address);
+        let stats = self
+            .merge_runs(
+                level_address,
+                entry_counts,
+                discard_tombstones,
+                &temp_file_name,
+                &mut MergeSidecars::default(),
+                on_page_flushed,
+            )
+            .inspect_err(|_| {
+                let _ = db_name.storage().remove_file(&temp_file_name); //input runs are left in place
+            })?;
+
+        //delete other runs
+        for path in db_name
+            .storage()
+            .read_dir(&filename::lsm_level_directory(level_address))?
+        {
+            if let Some(file_extension) = path.extension() {
+                if file_extension == filename::SST_FILE_EXTENSION {
+                    // fs::remove_file(path)?;
+                    file_interface::remove_file(
+                        db_name.storage(),
+                        &path,
+                        buffer_pool.as_deref_mut(),
+                    )?
+                }
+            }
+        }
+
+        //if we write no entries, then we should delete the compaction file instead and set entry counts to be empty (to represent the fact there are no more SST runs on this level)
+        if stats.entries_written == 0 {
+            entry_counts.clear();
+            db_name.storage().remove_file(&temp_file_name)?;
+            return Ok(Some(stats));
+        }
+
+        //By this point we know our compaction file has entries, so we rename it to an actual SST file name
+
+        db_name
+            .storage()
+            .rename(&temp_file_name, &(db_name, level, 0).path_sst())?;
+        *entry_counts = vec![stats.entries_written];
+
+        Ok(Some(stats))
+    }
+}
+
+impl SortedStringTable for Sst {
+    ///Writes entries onto the SST file in the level's directory, one page at a time
+    fn write_from_iter(
+        &self,
+        run_address: &RunAddress,
+        entries: &mut dyn Iterator<Item = Entry>,
+        on_page_written: &mut dyn FnMut(&[Entry]) -> io::Result<()>,
+    ) -> io::Result<Size> {
+        let (_, level, run) = *run_address;
+        let path = run_address.path_sst();
+        let compression = run_address.0.storage().compression();
+        let mut output = RunWriter::new(create_sst_file(run_address)?, compression, self.key_codec);
+        let mut page: Vec<Entry> = Vec::with_capacity(num_entries_per_page());
+        let mut flush_page = |page: &mut Vec<Entry>| -> io::Result<()> {
+            output.write_page(page)?;
+            on_page_written(page)?;
+            page.clear();
+            Ok(())
+        };
+        let mut last_key: Option<Key> = None;
+        for entry in entries {
+            let key = entry.0;
+            if let Some(previous_key) = last_key.filter(|previous_key| *previous_key >= key) {
+                let what = format!("run {run} of level {level}");
+                return Err(out_of_order_error(what, key, previous_key));
+            }
+            last_key = Some(key);
+            page.push(entry);
+            if page.len() == num_entries_per_page() {
+                flush_page(&mut page)?;
+            }
+        }
+        if !page.is_empty() {
+            flush_page(&mut page)?;
+        }
+        output.finish()?;
+        let num_entries = output.num_entries();
+        run_address.0.storage().publish(output.into_file(), &path)?;
+        Ok(num_entries)
     }
 
     ///Deserializes entire SST to entry vec
     fn read(&self, run_address: &RunAddress) -> io::Result<Vec<Entry>> {
-        let mut file = direct_io::open_read(&filename::sst_path(run_address))?;
-        deserialize_from(&mut file)
+        let bytes = compression::read_run(run_address.0.storage(), &run_address.path_sst())?;
+        deserialize(&bytes, self.key_codec)
+            .map_err(|why| io::Error::new(io::ErrorKind::InvalidData, why))
     }
     fn binary_search_get(
         &self,
@@ -65,34 +723,53 @@ impl SortedStringTable for Sst {
         num_entries: Size,
         mut buffer_pool: Option<&mut BufferPool>,
     ) -> io::Result<Option<Value>> {
-        let mut curr_page_index = usize::MAX;
-        let mut curr_page = Vec::<u8>::new();
-
-        let mut get_middle = |left: i64, right: i64| -> io::Result<(Entry, i64)> {
-            let middle_index = (left + right) / 2;
-            let (middle_page_index, entry_index) =
-                index_to_2d_index(system_info::num_entries_per_page(), middle_index as usize);
-            //check if we need to read in a new page
-            if middle_page_index != curr_page_index {
-                curr_page_index = middle_page_index;
-                let bp = buffer_pool.as_deref_mut(); //NOTE: watch out for this, not quite sure if it will cause bugs, shouldn't though
-                curr_page = get_sst_page(run_address, middle_page_index, bp)?;
-            };
-            let middle_entry = deserialize_entry_within_page(&curr_page, entry_index)
-                .expect("Invalid number of bytes in page");
-            Ok((middle_entry, middle_index))
+        //the buffer pool caches whole pages, without one only the block (minimum write size) holding each entry the
+        // search looks at is read. Pages of a compressed run can only be read whole
+        let mut file: Option<(DbFile, Option<PageIndex>)> = None;
+        if buffer_pool.is_none() && num_entries > 0 {
+            let mut opened = run_address.0.storage().open_read(&run_address.path_sst())?;
+            let index = compression::read_index(&mut opened)?;
+            file = Some((opened, index));
+        }
+        let block_size = match (&buffer_pool, &file) {
+            (None, Some((_, None))) => system_info::mimimum_write_size(),
+            _ => system_info::page_size(),
         };
+        let entries_per_block = block_size / ENTRY_SIZE;
+        let mut curr_block_index = usize::MAX;
+        let mut curr_block = PageBuffer::from(Vec::new());
 
         //https://en.wikipedia.org/wiki/Binary_search_algorithm#Procedure
         //implemented "non alternate" version to optimize for I/O operations
         let (mut left, mut right): (i64, i64) = (0, num_entries as i64 - 1);
         while left <= right {
-            let ((middle_key, middle_value), middle_index) = get_middle(left, right)?;
+            let middle_index = (left + right) / 2;
+            let (middle_block_index, entry_index) =
+                index_to_2d_index(entries_per_block, middle_index as usize);
+            //check if we need to read in a new block
+            if middle_block_index != curr_block_index {
+                curr_block_index = middle_block_index;
+                curr_block = match (buffer_pool.as_deref_mut(), file.as_mut()) {
+                    (None, Some((file, None))) => {
+                        let block_offset = (middle_block_index * block_size) as u64;
+                        direct_io::read_aligned(file, block_offset, block_size)?
+                    }
+                    (None, Some((file, Some(index)))) => {
+                        compression::read_page(file, Some(index), middle_block_index)?
+                    }
+                    (pool, _) => get_sst_page(run_address, middle_block_index, pool)?,
+                };
+            };
+            //only the key is decoded until it's the one we're looking for
+            let middle_key =
+                try_deserialize_key_within_page(&curr_block, entry_index, self.key_codec)?;
             match middle_key.cmp(&key) {
                 std::cmp::Ordering::Less => left = middle_index + 1,
                 std::cmp::Ordering::Greater => right = middle_index - 1,
                 std::cmp::Ordering::Equal => {
-                    return Ok(Some(middle_value));
+                    let value =
+                        serde_entry::deserialize_value_within_page(&curr_block, entry_index);
+                    return Ok(Some(value));
                 }
             };
         }
@@ -121,20 +798,24 @@ impl SortedStringTable for Sst {
 
         //hold onto the current page we're looking at to avoid some repeated deserialization
         let mut curr_page_index = usize::MAX;
-        let mut curr_page = Vec::<u8>::new();
+        let mut curr_page = PageBuffer::from(Vec::new());
+        let entries_per_page = system_info::num_entries_per_page();
+        //both binary searches start with the same probes and end on the bound pages, pinned so the probes in between
+        // can't evict them from a small buffer pool. Pages between the bounds are only read once and aren't pinned
+        let mut pins = Vec::new();
 
         let mut get_middle = |left: i64, right: i64| -> io::Result<(Key, i64)> {
             let middle_index = (left + right) / 2;
             let (middle_page_index, entry_index) =
-                index_to_2d_index(system_info::num_entries_per_page(), middle_index as usize);
+                index_to_2d_index(entries_per_page, middle_index as usize);
             //check if we need to read in a new page
             if middle_page_index != curr_page_index {
                 curr_page_index = middle_page_index;
                 let bp = buffer_pool.as_deref_mut(); //NOTE: watch out for this (.as_deref_mut), not quite sure if it will cause bugs, shouldn't though
-                curr_page = get_sst_page(run_address, middle_page_index, bp)?;
+                curr_page = get_pinned_sst_page(run_address, middle_page_index, bp, &mut pins)?;
             };
-            let (middle_key, _) = deserialize_entry_within_page(&curr_page, entry_index)
-                .expect("Invalid number of bytes in page");
+            let middle_key =
+                try_deserialize_key_within_page(&curr_page, entry_index, self.key_codec)?;
             Ok((middle_key, middle_index))
         };
 
@@ -153,10 +834,8 @@ impl SortedStringTable for Sst {
         if lowerbound_index >= num_entries as i64 {
             return Ok(vec![]);
         }
-        let (lowerbound_page_index, lowerbound_within_page_index) = index_to_2d_index(
-            system_info::num_entries_per_page(),
-            lowerbound_index as usize,
-        );
+        let (lowerbound_page_index, lowerbound_within_page_index) =
+            index_to_2d_index(entries_per_page, lowerbound_index as usize);
 
         //step 2: find position of inclusive upperbound
         //https://en.wikipedia.org/wiki/Binary_search_algorithm#Procedure_for_finding_the_rightmost_element
@@ -173,41 +852,41 @@ impl SortedStringTable for Sst {
         if upperbound_index < 0 {
             return Ok(vec![]);
         }
-        let (upperbound_page_index, upperbound_within_page_index) = index_to_2d_index(
-            system_info::num_entries_per_page(),
-            upperbound_index as usize,
-        );
+        let (upperbound_page_index, upperbound_within_page_index) =
+            index_to_2d_index(entries_per_page, upperbound_index as usize);
 
         //EDGE CASE: lowerbound and upperbound are in the same page
         //NOTE: this case means the work we did to get the lowerbound_entries and upperbound_entries array slices is wasted, hopefully compiler optimization can handle that
         if lowerbound_page_index == upperbound_page_index {
             //NOTE: curr_page_entries should contain all the values within our bounds
-            results = deserialize(&curr_page).unwrap_or_else(|_| panic!("Unable to deserialize lowerbound page during scan, level: {level}, run: {run} page_index: {lowerbound_page_index}"))[lowerbound_within_page_index..upperbound_within_page_index + 1].to_vec();
+            results = deserialize(&curr_page, self.key_codec).unwrap_or_else(|_| panic!("Unable to deserialize lowerbound page during scan, level: {level}, run: {run} page_index: {lowerbound_page_index}"))[lowerbound_within_page_index..upperbound_within_page_index + 1].to_vec();
             return Ok(results);
         }
 
         //NOTE: we set upperbound page first because there's a higher change that the if condition is true and we don't need to go back for a page
         let upperbound_bound_page = if curr_page_index == lowerbound_page_index {
-            curr_page.to_owned()
+            PageBuffer::from(curr_page.to_vec())
         } else {
-            get_sst_page(
+            get_pinned_sst_page(
                 run_address,
                 upperbound_page_index,
                 buffer_pool.as_deref_mut(),
+                &mut pins,
             )?
         };
-        let upperbound_entries = &deserialize(&upperbound_bound_page).unwrap_or_else(|_| panic!("Unable to deserialize upperbound page during scan, level: {level}, run: {run} page_index: {upperbound_page_index}"))[..upperbound_within_page_index + 1]; //NOTE: curr_page_entries should be the same page that we found our upperbound in
+        let upperbound_entries = &deserialize(&upperbound_bound_page, self.key_codec).unwrap_or_else(|_| panic!("Unable to deserialize upperbound page during scan, level: {level}, run: {run} page_index: {upperbound_page_index}"))[..upperbound_within_page_index + 1]; //NOTE: curr_page_entries should be the same page that we found our upperbound in
 
         let lower_bound_page = if curr_page_index == lowerbound_page_index {
             curr_page
         } else {
-            get_sst_page(
+            get_pinned_sst_page(
                 run_address,
                 lowerbound_page_index,
                 buffer_pool.as_deref_mut(),
+                &mut pins,
             )?
         };
-        let lowerbound_entries = &deserialize(&lower_bound_page).unwrap_or_else(|_| panic!("Unable to deserialize lowerbound page during scan, level: {level}, run: {run} page_index: {lowerbound_page_index}"))[lowerbound_within_page_index..]; //NOTE: curr_page_entries should be the same page that we found our lowerbound in
+        let lowerbound_entries = &deserialize(&lower_bound_page, self.key_codec).unwrap_or_else(|_| panic!("Unable to deserialize lowerbound page during scan, level: {level}, run: {run} page_index: {lowerbound_page_index}"))[lowerbound_within_page_index..]; //NOTE: curr_page_entries should be the same page that we found our lowerbound in
 
         //step 3: get all entries between the pages that contain our lowerbound and upperbound values
         //NOTE: by this point we have all the values in the pages that contain our bounds
@@ -215,7 +894,7 @@ impl SortedStringTable for Sst {
 
         for i in (lowerbound_page_index + 1)..upperbound_page_index {
             let page = get_sst_page(run_address, i, buffer_pool.as_deref_mut())?;
-            let page_entries = &deserialize(&page).unwrap_or_else(|_| panic!("Unable to deserialize page during scan, level: {level}, run: {run} page_index: {i}"));
+            let page_entries = &deserialize(&page, self.key_codec).unwrap_or_else(|_| panic!("Unable to deserialize page during scan, level: {level}, run: {run} page_index: {i}"));
 
             results.extend(page_entries);
         }
@@ -223,12 +902,89 @@ impl SortedStringTable for Sst {
 
         Ok(results)
     }
+    ///Only the keys of the entries in range are decoded, a page at a time
+    fn scan_keys(
+        &self,
+        run_address: &RunAddress,
+        key_range: (Key, Key),
+        num_entries: Size,
+        mut buffer_pool: Option<&mut BufferPool>,
+    ) -> io::Result<Vec<Key>> {
+        let bounds = self.scan_bounds(
+            run_address,
+            key_range,
+            num_entries,
+            buffer_pool.as_deref_mut(),
+        )?;
+        keys_in_bounds(run_address, bounds, self.key_codec, buffer_pool)
+    }
+    fn binary_search_scan_bounds(
+        &self,
+        run_address: &RunAddress,
+        key_range: (Key, Key),
+        num_entries: Size,
+        buffer_pool: Option<&mut BufferPool>,
+    ) -> io::Result<Range<Size>> {
+        self.scan_bounds(run_address, key_range, num_entries, buffer_pool) //Default scan function uses binary search
+    }
+    fn scan_bounds(
+        &self,
+        run_address: &RunAddress,
+        key_range: (Key, Key),
+        num_entries: Size,
+        mut buffer_pool: Option<&mut BufferPool>,
+    ) -> io::Result<Range<Size>> {
+        let (key1, key2) = key_range;
+        let start = self.partition_point(
+            run_address,
+            num_entries,
+            buffer_pool.as_deref_mut(),
+            |middle_key| middle_key < key1,
+        )?;
+        let end = self.partition_point(run_address, num_entries, buffer_pool, |middle_key| {
+            middle_key <= key2
+        })?;
+        Ok(start..end.max(start)) //empty when key1 > key2
+    }
+    fn ceiling(
+        &self,
+        run_address: &RunAddress,
+        key: Key,
+        num_entries: Size,
+        mut buffer_pool: Option<&mut BufferPool>,
+    ) -> io::Result<Option<Entry>> {
+        let index = self.partition_point(
+            run_address,
+            num_entries,
+            buffer_pool.as_deref_mut(),
+            |middle_key| middle_key < key,
+        )?;
+        if index >= num_entries {
+            return Ok(None); //every key is smaller
+        }
+        self.entry_at(run_address, index, buffer_pool).map(Some)
+    }
+    fn floor(
+        &self,
+        run_address: &RunAddress,
+        key: Key,
+        num_entries: Size,
+        mut buffer_pool: Option<&mut BufferPool>,
+    ) -> io::Result<Option<Entry>> {
+        let index = self.partition_point(
+            run_address,
+            num_entries,
+            buffer_pool.as_deref_mut(),
+            |middle_key| middle_key <= key,
+        )?;
+        if index == 0 {
+            return Ok(None); //every key is larger
+        }
+        self.entry_at(run_address, index - 1, buffer_pool).map(Some)
+    }
     ///Gets the number of entries in an sst
     fn len(&self, run_address: &RunAddress) -> io::Result<Size> {
-        let byte_count = direct_io::open_read(&filename::sst_path(run_address))?
-            .metadata()?
-            .len();
-        Ok(byte_count as Size / ENTRY_SIZE)
+        compression::num_entries(run_address.0.storage(), &run_address.path_sst())
     }
 
     ///Compact all SST runs in a level into a single SST run and update entry_counts to reflect that
@@ -237,190 +993,204 @@ impl SortedStringTable for Sst {
         level_address: &LevelAddress,
         entry_counts: &mut Vec<Size>,
         discard_tombstones: bool,
-        mut buffer_pool: Option<&mut BufferPool>,
-    ) -> io::Result<()> {
-        let (db_name, level) = *level_address;
-        let num_runs = entry_counts.len(); //Number of SST runs
-
-        if num_runs < 2 {
-            return Ok(()); //nothing to compact
-        }
-
-        let page_counts: Vec<Size> = entry_counts
-            .iter()
-            .map(|num_entries| num_pages(*num_entries))
-            .collect(); //Number of pages in each SST run
-
-        let get_entries =
-            |run, page_index| get_entries_at_page(&(db_name, level, run), page_index, None);
-
-        //Input buffer along with metadata for each run, index within buffer, page_index of buffer entries, boolean to indicate whether or not any more values exist in SST
-        type InputBufferData = (Vec<Entry>, usize, Page);
-        let mut input_buffers: Vec<InputBufferData> = (0..num_runs)
-            .map(|run| {
-                let entries = match get_entries(run, 0) {
-                    Err(_) => vec![],
-                    Ok(entries) => entries,
-                };
-                (entries, 0, 0)
-            })
-            .collect();
-
-        //Pull entry from buffer, if at end, then fill buffer with next page of entries
-        //Return None if no more entires to pull in run's SST
-        let pull_entry =
-            |input_buffers: &mut Vec<InputBufferData>, run: Run| -> io::Result<Option<Entry>> {
-                let (entries, curr_index, curr_page) = &mut input_buffers[run];
+        buffer_pool: Option<&mut BufferPool>,
+    ) -> io::Result<Option<MergeStats>> {
+        self.compact_with_page_callback(
+            level_address,
+            entry_counts,
+            discard_tombstones,
+            buffer_pool,
+            &mut |_| Ok(()),
+        )
+    }
+}
 
-                if *curr_index >= entries.len() {
-                    //check if we are at the end of this input buffer
-                    if *curr_page + 1 < page_counts[run] {
-                        //if there is another page of entries to pull into our buffer, do it
-                        *curr_page += 1;
-                        *entries = get_entries(run, *curr_page)?;
+mod tests {
+    #[allow(unused_imports)]
+    use super::*;
+    #[allow(unused_imports)]
+    use crate::file_io::serde_entry::serialize_into;
+    #[allow(unused_imports)]
+    use crate::util::testing::{self, setup_and_test_and_cleaup};
+    #[allow(unused_imports)]
+    use crate::util::types::{Level, Run};
 
-                        *curr_index = 0;
-                    } else {
-                        //no more entries in the SST to pull into buffer
-                        return Ok(None);
-                    }
+    #[test]
+    fn test_block_reads_match_page_reads() {
+        //gets without a buffer pool read single blocks, with one they read whole pages
+        let db_name = &testing::test_db_name("array_sst_block_reads_match_page_reads");
+        const LEVEL: Level = 0;
+        let mut test = || {
+            let run_address = &(db_name, LEVEL, 0);
+            let entries_per_block = system_info::mimimum_write_size() / ENTRY_SIZE;
+            let sst = Sst::default();
+            //the last page is partial, and so is its last block
+            for num_entries in [1, entries_per_block + 1, 3 * num_entries_per_page() + 7] {
+                let entries: Vec<Entry> =
+                    (0..num_entries as Key).map(|key| (key * 2, key)).collect();
+                sst.write(run_address, &entries).unwrap();
+                let mut buffer_pool = BufferPool::new(1, 64);
+                for key in -1..=num_entries as Key * 2 {
+                    let from_blocks = sst.get(run_address, key, num_entries, None).unwrap();
+                    let from_pages = sst
+                        .get(run_address, key, num_entries, Some(&mut buffer_pool))
+                        .unwrap();
+                    assert_eq!(from_blocks, from_pages, "key {key} of {num_entries}");
+                    let expected = (key >= 0 && key % 2 == 0 && key < num_entries as Key * 2)
+                        .then_some(key / 2);
+                    assert_eq!(from_blocks, expected, "key {key} of {num_entries}");
                 }
-
-                let entry = entries[*curr_index];
-                *curr_index += 1;
-
-                Ok(Some(entry))
-            };
-
-        type BufferHeap = BinaryHeap<(Key, Run, Value)>;
-        let mut heap = BufferHeap::new(); //to ensure we write the smallest value in our buffers
-
-        let mut output_buffer: Vec<Entry> = Vec::with_capacity(num_entries_per_page());
-        let temp_file_name = filename::sst_compaction_path(db_name, level);
-        let mut output = direct_io::create(&temp_file_name)?;
-        let mut entries_written: Size = 0;
-
-        let heap_insert = |heap: &mut BufferHeap, key: Key, value, run| {
-            //NOTE: tuple elements are sorted lexicographically in the heap by default, this fact is very
-            //      important for the implementation. In case of a tie, the youngest (highest number) SST
-            //      run will have its value used
-            heap.push((-key, run, value)); //Negative key to use as min_heap instead
-        };
-        let heap_extract = |heap: &mut BufferHeap| {
-            if let Some((negative_key, run, value_option)) = heap.pop() {
-                let key = -negative_key;
-                return Some(((key, value_option), run));
             }
-            None
         };
+        setup_and_test_and_cleaup(&(db_name, LEVEL), &mut test);
+    }
 
-        //take item from heap and replace it with another element in its run (if there is any)
-        let heap_swap_extract = |heap: &mut BufferHeap,
-                                 input_buffers: &mut Vec<InputBufferData>|
-         -> io::Result<Option<Entry>> {
-            if let Some((entry, run)) = heap_extract(heap) {
-                if let Some(replacement_entry) = pull_entry(input_buffers, run)? {
-                    let (key, value) = replacement_entry;
-                    heap_insert(heap, key, value, run);
-                }
-                return Ok(Some(entry));
-            }
-            Ok(None)
-        };
-        let mut flush_output_buffer = |output_buffer: &mut Vec<Entry>| -> io::Result<()> {
-            if output_buffer.is_empty() {
-                return Ok(());
-            }
-            serde_entry::serialize_into_no_resize(&mut output, output_buffer)?;
-            entries_written += output_buffer.len();
-            output_buffer.clear();
-            Ok(())
-        };
-        let mut output_buffer_insert = |output_buffer: &mut Vec<Entry>, entry| -> io::Result<()> {
-            output_buffer.push(entry);
-            //if we filled up our buffer, flush buffer to compaction file
-            if output_buffer.len() >= num_entries_per_page() {
-                flush_output_buffer(output_buffer)?;
-            }
-            Ok(())
+    #[test]
+    #[cfg(feature = "buffer-pool")]
+    fn test_scan_pins_pages() {
+        use crate::file_io::io_stats;
+        //the binary searches of a scan come back to pages they probed, a pool with room for 2 pages keeps them pinned
+        // rather than reading them again
+        let db_name = &testing::test_db_name("array_sst_scan_pins_pages");
+        const LEVEL: Level = 0;
+        let mut test = || {
+            let run_address = &(db_name, LEVEL, 0);
+            let num_entries = 16 * num_entries_per_page();
+            let entries: Vec<Entry> = (0..num_entries as Key).map(|key| (key, key)).collect();
+            let sst = Sst::default();
+            sst.write(run_address, &entries).unwrap();
+            let key_range = (
+                5 * num_entries_per_page() as Key + 3,
+                9 * num_entries_per_page() as Key,
+            );
+
+            let pages_read = |buffer_pool: &mut BufferPool| {
+                let before = io_stats::snapshot();
+                let scanned = sst
+                    .scan(run_address, key_range, num_entries, Some(buffer_pool))
+                    .unwrap();
+                assert_eq!(
+                    scanned,
+                    entries[key_range.0 as usize..=key_range.1 as usize]
+                );
+                io_stats::snapshot().since(&before).pages_read
+            };
+            let mut large_pool = BufferPool::new(1, 64 * system_info::page_size());
+            let mut small_pool = BufferPool::new(1, 2 * system_info::page_size());
+            let distinct_pages = pages_read(&mut large_pool);
+            assert_eq!(pages_read(&mut small_pool), distinct_pages);
+            assert!(small_pool.len() > 2); //went over capacity while pages were pinned
+
+            //the pins are gone with the scan, the next insertion brings the pool back under capacity
+            small_pool.insert(&run_address.path_sst(), num_entries, &[0; 8]);
+            assert!(small_pool.size_bytes() <= small_pool.capacity());
         };
+        setup_and_test_and_cleaup(&(db_name, LEVEL), &mut test);
+    }
 
-        //put one entry from each buffer, NOTE: higher run number is younger
-        for run in (0..entry_counts.len()).rev() {
-            let entry_option = pull_entry(&mut input_buffers, run)?;
-            if entry_option.is_none() {
-                continue;
-            }
-            let (key, value) = entry_option.unwrap(); //NOTE: can unwrap safely because of earlier check
-            heap_insert(&mut heap, key, value, run);
-        }
-
-        let mut recent_key: Option<Key> = None;
-
-        //put entries into output buffer until there are no more entries to pull from any buffer
-        loop {
-            let entry_option = heap_swap_extract(&mut heap, &mut input_buffers)?;
-
-            if let Some((key, value)) = entry_option {
-                if recent_key.is_some_and(|recent| recent == key) {
-                    continue; //we already have inserted the value (or it we discarded its tombstone already)
-                }
-                if !discard_tombstones || value != Database::TOMBSTONE_VALUE {
-                    output_buffer_insert(&mut output_buffer, (key, value))?;
+    #[test]
+    fn test_scan_keys() {
+        //every third entry is a tombstone, ranges start and end within pages and span several
+        let db_name = &testing::test_db_name("array_sst_scan_keys");
+        const LEVEL: Level = 0;
+        let mut test = || {
+            let run_address = &(db_name, LEVEL, 0);
+            let num_entries = 3 * num_entries_per_page() + 7;
+            let entries: Vec<Entry> = (0..num_entries as Key)
+                .map(|key| match key % 3 {
+                    0 => (key * 2, Database::TOMBSTONE_VALUE),
+                    _ => (key * 2, key),
+                })
+                .collect();
+            #[cfg_attr(not(feature = "btree"), allow(unused_mut))]
+            let mut ssts: Vec<Box<dyn SortedStringTable>> = vec![Box::new(Sst::default())];
+            #[cfg(feature = "btree")]
+            ssts.push(Box::new(crate::sst::btree_sst::Sst::default()));
+            for sst in ssts {
+                sst.write(run_address, &entries).unwrap();
+                let last_key = entries.last().unwrap().0;
+                for key_range in [
+                    (Key::MIN, Key::MAX),
+                    (3, 517),
+                    (600, last_key),
+                    (-5, 0),
+                    (7, 7),
+                ] {
+                    let expected: Vec<Key> = sst
+                        .scan(run_address, key_range, num_entries, None)
+                        .unwrap()
+                        .into_iter()
+                        .filter(|(_, value)| *value != Database::TOMBSTONE_VALUE)
+                        .map(|(key, _)| key)
+                        .collect();
+                    let mut buffer_pool = BufferPool::new(1, 64);
+                    assert_eq!(
+                        sst.scan_keys(run_address, key_range, num_entries, None)
+                            .unwrap(),
+                        expected,
+                        "{key_range:?}"
+                    );
+                    assert_eq!(
+                        sst.scan_keys(run_address, key_range, num_entries, Some(&mut buffer_pool))
+                            .unwrap(),
+                        expected,
+                        "{key_range:?}"
+                    );
                 }
-                recent_key = Some(key);
-            } else {
-                //nothing in heap, should be done
-                break;
             }
-        }
-        //flush remaining elements
-        flush_output_buffer(&mut output_buffer)?;
-        output.set_len((entries_written * ENTRY_SIZE) as u64)?; //set correct file size
+        };
+        setup_and_test_and_cleaup(&(db_name, LEVEL), &mut test);
+    }
 
-        //delete other runs
-        for path_result in fs::read_dir(filename::lsm_level_directory(level_address))? {
-            let path = path_result?.path();
-            if let Some(file_extension) = path.extension() {
-                if file_extension == filename::SST_FILE_EXTENSION {
-                    // fs::remove_file(path)?;
-                    file_interface::remove_file(
-                        path.as_os_str().to_str().unwrap(),
-                        buffer_pool.as_deref_mut(),
-                    )? //TODO: change function to use path directly
+    #[test]
+    fn test_entry_count_past_end_of_file() {
+        //metadata that counts one more entry than the run's file holds (e.g. after a truncated write)
+        let db_name = &testing::test_db_name("array_sst_entry_count_past_end_of_file");
+        const LEVEL: Level = 0;
+        let mut test = || {
+            let run_address = &(db_name, LEVEL, 0);
+            let entries_per_page = num_entries_per_page();
+            for num_entries in [entries_per_page + 3, 2 * entries_per_page] {
+                let entries: Vec<Entry> = (0..num_entries as Key).map(|key| (key, key)).collect();
+                let sst = Sst::default();
+                sst.write(run_address, &entries).unwrap();
+                let claimed = num_entries + 1;
+                let last_key = num_entries as Key - 1;
+
+                //a search that reaches the missing entry fails instead of panicking
+                let why = sst
+                    .get(run_address, last_key + 1, claimed, None)
+                    .unwrap_err();
+                assert_eq!(why.kind(), io::ErrorKind::InvalidData, "{why}");
+                let why = sst
+                    .scan(run_address, (last_key + 1, Key::MAX), claimed, None)
+                    .unwrap_err();
+                assert_eq!(why.kind(), io::ErrorKind::InvalidData, "{why}");
+                //one that doesn't still finds what the file holds
+                assert_eq!(sst.get(run_address, 0, claimed, None).unwrap(), Some(0));
+
+                #[cfg(feature = "btree")]
+                {
+                    let btree_sst = crate::sst::btree_sst::Sst::default();
+                    btree_sst.write(run_address, &entries).unwrap();
+                    let found = btree_sst.get(run_address, last_key + 1, claimed, None);
+                    assert!(!matches!(found, Ok(Some(_))), "{found:?}");
+                    assert_eq!(
+                        btree_sst.get(run_address, last_key, claimed, None).unwrap(),
+                        Some(last_key)
+                    );
                 }
             }
-        }
-
-        //if we write no entries, then we should delete the compaction file instead and set entry counts to be empty (to represent the fact there are no more SST runs on this level)
-        if entries_written == 0 {
-            entry_counts.clear();
-            return fs::remove_file(&temp_file_name);
-        }
-
-        //By this point we know our compaction file has entries, so we rename it to an actual SST file name
-
-        fs::rename(&temp_file_name, filename::sst_path(&(db_name, level, 0)))?;
-        *entry_counts = vec![entries_written];
-
-        Ok(())
+        };
+        setup_and_test_and_cleaup(&(db_name, LEVEL), &mut test);
     }
-}
-
-mod tests {
-    #[allow(unused_imports)]
-    use super::*;
-    #[allow(unused_imports)]
-    use crate::util::testing::setup_and_test_and_cleaup;
-    #[allow(unused_imports)]
-    use crate::util::types::{Level, Run};
 
     #[test]
     fn test_small_compaction() {
-        let db_name = "array_sst_compaction_small";
+        let db_name = &testing::test_db_name("array_sst_compaction_small");
         const LEVEL: Level = 0;
         let mut test = || {
-            let sst = Sst {};
+            let sst = Sst::default();
             // let iter = 0..num_entries_per_page() as Key;
             let entries0: Vec<Entry> = vec![(0, 0), (1, 0)];
             let entries1: Vec<Entry> = vec![(0, 1), (1, 1)];
@@ -433,6 +1203,11 @@ mod tests {
                 .unwrap();
 
             assert_eq!(entry_counts, vec![expected_result.len()]);
+            //preallocated for the entries of both inputs, then truncated to the ones written
+            assert_eq!(
+                sst.len(&(db_name, LEVEL, 0)).unwrap(),
+                expected_result.len()
+            );
 
             let compaction_entries = sst.read(&(db_name, LEVEL, 0)).unwrap();
             assert_eq!(compaction_entries, expected_result);
@@ -442,10 +1217,10 @@ mod tests {
 
     #[test]
     fn test_small_compaction2() {
-        let db_name = "array_sst_compaction_small2";
+        let db_name = &testing::test_db_name("array_sst_compaction_small2");
         const LEVEL: Level = 0;
         let mut test = || {
-            let sst = Sst {};
+            let sst = Sst::default();
             // let iter = 0..num_entries_per_page() as Key;
             let entries0: Vec<Entry> = vec![(0, 0), (2, 0)];
             let entries1: Vec<Entry> = vec![(1, 1), (2, 1)];
@@ -467,17 +1242,13 @@ mod tests {
 
     #[test]
     fn test_interspersed_compaction() {
-        let db_name = "array_sst_interspersed_compaction";
+        let db_name = &testing::test_db_name("array_sst_interspersed_compaction");
         const LEVEL: Level = 0;
         let mut test = || {
-            let sst = Sst {};
+            let sst = Sst::default();
             let iter = 0..num_entries_per_page() as Key;
-            let mut entries0: Vec<Entry> = iter
-                .to_owned()
-                .skip(0)
-                .step_by(2)
-                .map(|key| (key, -key))
-                .collect();
+            let mut entries0: Vec<Entry> =
+                iter.to_owned().step_by(2).map(|key| (key, -key)).collect();
             let mut entries1: Vec<Entry> = iter
                 .to_owned()
                 .skip(1)
@@ -542,17 +1313,12 @@ mod tests {
 
     #[test]
     fn test_compaction_edge_cases() {
-        let db_name = "array_sst_compaction_edge_cases";
+        let db_name = &testing::test_db_name("array_sst_compaction_edge_cases");
         const LEVEL: Level = 0;
         let mut test = || {
-            let sst = Sst {};
+            let sst = Sst::default();
             let iter = 0..num_entries_per_page() as Key;
-            let entries0: Vec<Entry> = iter
-                .to_owned()
-                .skip(0)
-                .step_by(2)
-                .map(|key| (key, -key))
-                .collect();
+            let entries0: Vec<Entry> = iter.to_owned().step_by(2).map(|key| (key, -key)).collect();
 
             //EDGE case tests
 
@@ -590,7 +1356,6 @@ mod tests {
             //EDGE CASE TEST 3: compacting 1 SST filled with tombstones
             let entries0: Vec<Entry> = iter
                 .to_owned()
-                .skip(0)
                 .step_by(2)
                 .map(|key| (key, Database::TOMBSTONE_VALUE))
                 .collect();
@@ -627,12 +1392,235 @@ mod tests {
         setup_and_test_and_cleaup(&(db_name, LEVEL), &mut test);
     }
 
+    #[test]
+    fn test_unsorted_compaction() {
+        let db_name = &testing::test_db_name("array_sst_unsorted_compaction");
+        const LEVEL: Level = 0;
+        let mut test = || {
+            let sst = Sst::default();
+            let entries_per_page = num_entries_per_page() as Key;
+            let entries0: Vec<Entry> = (0..entries_per_page).map(|key| (key * 2, 0)).collect();
+            //keys go backwards on the second page of run 1
+            let mut entries1: Vec<Entry> =
+                (0..entries_per_page).map(|key| (key * 2 + 1, 1)).collect();
+            entries1.push((0, 1));
+            sst.write(&(db_name, LEVEL, 0), &entries0).unwrap();
+            let mut file = db_name
+                .storage()
+                .create(&(db_name, LEVEL, 1).path_sst())
+                .unwrap();
+            serialize_into(&mut file, &entries1, KeyCodec::default()).unwrap();
+
+            let mut entry_counts = vec![entries0.len(), entries1.len()];
+            let why = sst
+                .compact(&(db_name, LEVEL), &mut entry_counts, false, None)
+                .unwrap_err();
+            assert_eq!(why.kind(), io::ErrorKind::InvalidData);
+            let message = why.to_string();
+            assert!(message.contains("run 1 of level 0 (page 1)"), "{message}");
+
+            //input runs are untouched and nothing is left behind
+            assert_eq!(entry_counts, vec![entries0.len(), entries1.len()]);
+            assert_eq!(sst.read(&(db_name, LEVEL, 0)).unwrap(), entries0);
+            assert_eq!(sst.read(&(db_name, LEVEL, 1)).unwrap(), entries1);
+            assert!(!db_name
+                .storage()
+                .path_exists(&filename::sst_compaction_path(&(db_name, LEVEL))));
+        };
+        setup_and_test_and_cleaup(&(db_name, LEVEL), &mut test);
+    }
+
+    ///Write <num_runs> overlapping runs with tombstones to <level_address>, younger runs shadow older ones.
+    /// Returns their entry counts
+    #[cfg(test)]
+    fn write_overlapping_runs(level_address: &LevelAddress, num_runs: Size) -> Vec<Size> {
+        let (db_name, level) = *level_address;
+        (0..num_runs)
+            .map(|run| {
+                let entries: Vec<Entry> = (0..40)
+                    .map(|i| match (i + run) % 9 {
+                        0 => ((i * 5 + run % 5) as Key, Database::TOMBSTONE_VALUE),
+                        _ => ((i * 5 + run % 5) as Key, (run * 1000 + i) as Value),
+                    })
+                    .collect();
+                Sst::default()
+                    .write(&(db_name, level, run), &entries)
+                    .unwrap();
+                entries.len()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_compaction_in_passes() {
+        //a budget that holds a page of 8 runs merges 64 runs in two passes, into the same run a single merge writes
+        let db_name = &testing::test_db_name("array_sst_compaction_in_passes");
+        const LEVEL: Level = 0;
+        const NUM_RUNS: Size = 64;
+        let mut test = || {
+            let level_address = &(db_name, LEVEL);
+            let budget = merge_memory_bytes(8, num_entries_per_page());
+            assert_eq!(merge_group_size(budget), 8);
+            assert_eq!(merge_group_size(0), Size::MAX);
+            for discard_tombstones in [false, true] {
+                let mut expected_counts = write_overlapping_runs(level_address, NUM_RUNS);
+                let expected_stats = Sst::default()
+                    .compact(
+                        level_address,
+                        &mut expected_counts,
+                        discard_tombstones,
+                        None,
+                    )
+                    .unwrap()
+                    .unwrap();
+                let expected = db_name
+                    .storage()
+                    .read_file(&(db_name, LEVEL, 0).path_sst())
+                    .unwrap();
+
+                let mut entry_counts = write_overlapping_runs(level_address, NUM_RUNS);
+                let stats = Sst::default()
+                    .set_merge_memory_budget(budget)
+                    .compact(level_address, &mut entry_counts, discard_tombstones, None)
+                    .unwrap()
+                    .unwrap();
+                assert_eq!(
+                    db_name
+                        .storage()
+                        .read_file(&(db_name, LEVEL, 0).path_sst())
+                        .unwrap(),
+                    expected
+                );
+                assert_eq!(entry_counts, expected_counts);
+                assert_eq!(
+                    (
+                        stats.entries_written,
+                        stats.tombstones_written,
+                        stats.entries_dropped
+                    ),
+                    (
+                        expected_stats.entries_written,
+                        expected_stats.tombstones_written,
+                        expected_stats.entries_dropped
+                    )
+                );
+                assert!(stats.memory_bytes <= budget, "{} bytes", stats.memory_bytes);
+                //each of the 8 intermediate runs fits in a page
+                assert_eq!(stats.pages_written, expected_stats.pages_written + 8);
+                assert_eq!(
+                    db_name
+                        .storage()
+                        .read_dir(&filename::lsm_level_directory(level_address))
+                        .unwrap(),
+                    vec![(db_name, LEVEL, 0).path_sst()]
+                );
+            }
+        };
+        setup_and_test_and_cleaup(&(db_name, LEVEL), &mut test);
+    }
+
+    #[test]
+    fn test_failed_compaction_in_passes() {
+        //the intermediate runs written before an unsorted input is found are removed along with the output
+        let db_name = &testing::test_db_name("array_sst_failed_compaction_in_passes");
+        const LEVEL: Level = 0;
+        const NUM_RUNS: Size = 64;
+        let mut test = || {
+            let level_address = &(db_name, LEVEL);
+            let mut entry_counts = write_overlapping_runs(level_address, NUM_RUNS);
+            let mut file = db_name
+                .storage()
+                .create(&(db_name, LEVEL, 44).path_sst())
+                .unwrap();
+            serialize_into(&mut file, &[(1, 0), (0, 0)], KeyCodec::default()).unwrap();
+            entry_counts[44] = 2;
+
+            let why = Sst::default()
+                .set_merge_memory_budget(merge_memory_bytes(8, num_entries_per_page()))
+                .compact(level_address, &mut entry_counts, false, None)
+                .unwrap_err();
+            assert_eq!(why.kind(), io::ErrorKind::InvalidData);
+            assert!(why.to_string().contains("run 44 of level 0"), "{why}");
+            let mut files = db_name
+                .storage()
+                .read_dir(&filename::lsm_level_directory(level_address))
+                .unwrap();
+            files.sort();
+            let mut inputs: Vec<PathBuf> = (0..NUM_RUNS)
+                .map(|run| (db_name, LEVEL, run).path_sst())
+                .collect();
+            inputs.sort();
+            assert_eq!(files, inputs);
+        };
+        setup_and_test_and_cleaup(&(db_name, LEVEL), &mut test);
+    }
+
+    #[test]
+    fn test_write_from_iter() {
+        //writing a page at a time gives the same file as serializing every entry at once
+        let db_name = &testing::test_db_name("array_sst_write_from_iter");
+        const LEVEL: Level = 0;
+        let mut test = || {
+            let sst = Sst::default();
+            let entries_per_page = num_entries_per_page();
+            let entries: Vec<Entry> = (0..2 * entries_per_page as Key + 3)
+                .map(|key| (key * 3, key))
+                .collect();
+            let mut page_lens = Vec::new();
+            let written = sst
+                .write_from_iter(
+                    &(db_name, LEVEL, 0),
+                    &mut entries.iter().copied(),
+                    &mut |page| {
+                        page_lens.push(page.len());
+                        Ok(())
+                    },
+                )
+                .unwrap();
+            assert_eq!(written, entries.len());
+            assert_eq!(page_lens, vec![entries_per_page, entries_per_page, 3]);
+            let mut file = db_name
+                .storage()
+                .create(&(db_name, LEVEL, 1).path_sst())
+                .unwrap();
+            serialize_into(&mut file, &entries, KeyCodec::default()).unwrap();
+            assert_eq!(
+                db_name
+                    .storage()
+                    .read_file(&(db_name, LEVEL, 0).path_sst())
+                    .unwrap(),
+                db_name
+                    .storage()
+                    .read_file(&(db_name, LEVEL, 1).path_sst())
+                    .unwrap()
+            );
+
+            let run_address = &(db_name, LEVEL, 2);
+            let empty = &mut std::iter::empty();
+            assert_eq!(
+                sst.write_from_iter(run_address, empty, &mut |_| Ok(()))
+                    .unwrap(),
+                0
+            );
+            assert_eq!(sst.len(run_address).unwrap(), 0);
+
+            let why = sst
+                .write_from_iter(run_address, &mut [(1, 0), (1, 1)].into_iter(), &mut |_| {
+                    Ok(())
+                })
+                .unwrap_err();
+            assert_eq!(why.kind(), io::ErrorKind::InvalidData);
+            assert!(why.to_string().contains("run 2 of level 0"), "{why}");
+        };
+        setup_and_test_and_cleaup(&(db_name, LEVEL), &mut test);
+    }
+
     #[test]
     fn test_multi_compaction() {
-        let db_name = "array_sst_compaction";
+        let db_name = &testing::test_db_name("array_sst_compaction");
         const LEVEL: Level = 0;
         let mut test = || {
-            let sst = Sst {};
+            let sst = Sst::default();
             let entries0: Vec<Entry> = vec![(0, 0), (1, 0), (32, 0), (64, 0)];
             let entries1: Vec<Entry> = vec![(0, 1), (1, Database::TOMBSTONE_VALUE)];
             let entries2: Vec<Entry> = vec![(1, 2), (16, Database::TOMBSTONE_VALUE), (32, 2)];
@@ -650,10 +1638,24 @@ mod tests {
             sst.write(&(db_name, LEVEL, 1), &entries1).unwrap();
             sst.write(&(db_name, LEVEL, 2), &entries2).unwrap();
 
-            sst.compact(&(db_name, LEVEL), &mut entry_counts, false, None)
+            let stats = sst
+                .compact(&(db_name, LEVEL), &mut entry_counts, false, None)
                 .unwrap();
 
             assert_eq!(entry_counts, vec![expected_result.len()]);
+            assert_eq!(
+                stats,
+                Some(MergeStats {
+                    entries_written: 5,
+                    tombstones_written: 1,
+                    entries_dropped: 4, //keys 0, 1 (twice) and 32 are shadowed
+                    expiries_written: 0,
+                    operands_written: 0,
+                    memory_bytes: merge_memory_bytes(3, num_entries_per_page()),
+                    pages_read: 3,
+                    pages_written: 1,
+                })
+            );
 
             let compaction_entries = sst.read(&(db_name, LEVEL, 0)).unwrap();
             assert_eq!(compaction_entries, expected_result);
@@ -666,10 +1668,24 @@ mod tests {
             sst.write(&(db_name, LEVEL, 1), &entries1).unwrap();
             sst.write(&(db_name, LEVEL, 2), &entries2).unwrap();
 
-            sst.compact(&(db_name, LEVEL), &mut entry_counts, true, None)
+            let stats = sst
+                .compact(&(db_name, LEVEL), &mut entry_counts, true, None)
                 .unwrap();
 
             assert_eq!(entry_counts, vec![expected_result.len()]);
+            assert_eq!(
+                stats,
+                Some(MergeStats {
+                    entries_written: 4,
+                    tombstones_written: 0,
+                    entries_dropped: 5, //tombstone of key 16 is discarded as well
+                    expiries_written: 0,
+                    operands_written: 0,
+                    memory_bytes: merge_memory_bytes(3, num_entries_per_page()),
+                    pages_read: 3,
+                    pages_written: 1,
+                })
+            );
 
             let compaction_entries = sst.read(&(db_name, LEVEL, 0)).unwrap();
             assert_eq!(compaction_entries, expected_result);
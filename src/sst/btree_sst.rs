@@ -1,4 +1,4 @@
-use std::{fs, io};
+use std::{io, ops::Range};
 
 use crate::file_io::{file_interface, serde_entry};
 use crate::sst::btree_util::num_leaves;
@@ -7,80 +7,230 @@ use crate::util::algorithm::{
 };
 use crate::{
     buffer_pool::BufferPool,
-    file_io::{direct_io, serde_btree},
+    file_io::{serde_btree, storage::DbFile},
     sst::btree_util::num_nodes,
     util::{
-        btree_info::fanout,
-        filename,
-        system_info::num_entries_per_page,
-        types::{Depth, Entry, Key, LevelAddress, Node, RunAddress, Size, Value},
+        filename::{self, RunPaths},
+        system_info::{num_entries_per_page, PageLayout},
+        types::{Depth, Entry, Key, KeyCodec, LevelAddress, Node, Page, RunAddress, Size, Value},
     },
 };
 
-use super::btree_util::{
-    btree_navigate, get_last_in_each_chunk, has_inner_nodes, seek_node, tree_depth,
-};
-use super::sst_util::{get_entries_at_page, get_sst_page, num_pages};
-use super::{array_sst, SortedStringTable};
+use super::btree_util::{btree_navigate, has_inner_nodes, seek_node, tree_depth};
+use super::sst_util::{get_entries_at_leaf, keys_in_bounds};
+use super::{array_sst, compression, MergeStats, SortedStringTable};
 
 type DelimiterBuffer = Vec<(Vec<Key>, Node)>; //Type alias for datastructure used to recursively build inner B-tree nodes from an SST
 
-pub struct Sst;
+///Collects the delimiter (last key) of every leaf of a run while its entries are written,
+/// so its B-tree can be built without reading the run back (see Sst::write_inner_nodes_from_delimiters)
+pub struct LeafDelimiters {
+    entries_per_leaf: Size,
+    num_entries: Size,
+    last_key: Option<Key>,
+    delimiters: Vec<Key>,
+}
 
-impl SortedStringTable for Sst {
-    ///Writes key-value array (or vec) onto SST file in appropriate directory.
-    ///NOTE: Avoid using arrays larger than the size of the buffer. We shouldn't need to handle very large writes
-    /// since compaction will be implemented after we switch to a static btree implementation
-    fn write(
-        &self,
-        run_address: &RunAddress,
-        entries: &[Entry], //assumes this is sorted properly
-    ) -> io::Result<()> {
-        let (_db_name, level, _run) = run_address;
-        //step 1: create directory for level if needed and write sorted entries into SST file
-        array_sst::Sst.write(run_address, entries)?;
+impl LeafDelimiters {
+    pub fn new(layout: PageLayout) -> Self {
+        Self {
+            entries_per_leaf: layout.entries_per_page(),
+            num_entries: 0,
+            last_key: None,
+            delimiters: Vec::new(),
+        }
+    }
+    ///Entries must be pushed in the order they're written, in chunks of any size
+    pub fn push(&mut self, entries: &[Entry]) {
+        for (key, _) in entries {
+            self.num_entries += 1;
+            if self.num_entries.is_multiple_of(self.entries_per_leaf) {
+                self.delimiters.push(*key);
+            }
+        }
+        if let Some((key, _)) = entries.last() {
+            self.last_key = Some(*key);
+        }
+    }
+    ///Delimiters of every leaf, including the last one if it isn't full
+    pub fn finish(mut self) -> Vec<Key> {
+        if !self.num_entries.is_multiple_of(self.entries_per_leaf) {
+            self.delimiters.extend(self.last_key);
+        }
+        self.delimiters
+    }
+}
 
-        let num_entries = entries.len();
+#[derive(Default)]
+pub struct Sst {
+    key_codec: KeyCodec,
+    layout: PageLayout, //page size the B-tree files are written (or were written) with
+}
 
-        //step 2: write file for inner nodes, if needed
-        if has_inner_nodes(num_entries) {
-            return Ok(()); //we only have enough entries for 1 node, that means it is the "root"
+impl Sst {
+    pub fn new(key_codec: KeyCodec) -> Self {
+        Self {
+            key_codec,
+            layout: PageLayout::host(),
+        }
+    }
+    ///Read (or write) runs with another machine's page size instead of this one's
+    pub fn set_layout(mut self, layout: PageLayout) -> Self {
+        self.layout = layout;
+        self
+    }
+    ///Array SST with the same key codec, used for operations that don't need inner nodes
+    fn array_sst(&self) -> array_sst::Sst {
+        array_sst::Sst::new(self.key_codec)
+    }
+    ///Index of the first entry whose key is not <is_before>, navigating to the page that may hold <key>.
+    /// NOTE: the partition point can be right past the end of the page navigation lands on, which is fine since
+    /// every page but the last one is full
+    fn partition_point(
+        &self,
+        run_address: &RunAddress,
+        key: Key,
+        num_entries: Size,
+        mut buffer_pool: Option<&mut BufferPool>,
+        is_before: impl Fn(Key) -> bool,
+    ) -> io::Result<Size> {
+        let page_index = btree_navigate(
+            run_address,
+            key,
+            num_entries,
+            self.layout,
+            self.key_codec,
+            buffer_pool.as_deref_mut(),
+        )?;
+        let entries = self.get_leaf(run_address, page_index, buffer_pool)?;
+        let index_within_page = entries.partition_point(|(entry_key, _)| is_before(*entry_key));
+        Ok(page_index * self.layout.entries_per_page() + index_within_page)
+    }
+    fn get_leaf(
+        &self,
+        run_address: &RunAddress,
+        page_index: Page,
+        buffer_pool: Option<&mut BufferPool>,
+    ) -> io::Result<Vec<Entry>> {
+        get_entries_at_leaf(
+            run_address,
+            page_index,
+            self.layout,
+            self.key_codec,
+            buffer_pool,
+        )
+    }
+    ///Build the B-tree file for an existing SST run, reading the last key of each of its leaves.
+    /// Does nothing if the run fits in one page
+    #[allow(dead_code)] //compaction builds B-trees as it writes, this is for runs that are already written
+    pub fn write_inner_nodes(&self, run_address: &RunAddress, num_entries: Size) -> io::Result<()> {
+        let layout = self.layout;
+        let num_pages = num_leaves(num_entries, layout);
+        if num_pages < 2 {
+            return Ok(()); //a single page is its own root, no inner nodes needed
         }
 
-        let path = filename::sst_btree_path(run_address);
-        let mut file = direct_io::create(&path)?; //NOTE: the directory should exist by this point, so no checks needed (created in array_sst::write)
+        //only the block holding each delimiter is read, not its whole page (unless the run is compressed)
+        let mut file = run_address.0.storage().open_read(&run_address.path_sst())?;
+        let footer = compression::read_index(&mut file)?;
+        let mut get_key = |index: Size| -> io::Result<Key> {
+            let Some(footer) = &footer else {
+                let (key, ..) =
+                    serde_entry::deserialize_entry_at(&mut file, index, self.key_codec)?;
+                return Ok(key);
+            };
+            let entries_per_page = num_entries_per_page();
+            let page = compression::read_page(&mut file, Some(footer), index / entries_per_page)?;
+            serde_entry::try_deserialize_key_within_page(
+                &page,
+                index % entries_per_page,
+                self.key_codec,
+            )
+        };
 
-        //get largest entry in each SST page (last value in each)
-        let entry_keys: Vec<Key> = entries.iter().map(|(key, _)| *key).collect();
-        let mut delimiters: Vec<Key> = get_last_in_each_chunk(&entry_keys, num_entries_per_page());
+        let mut delimiters = Vec::with_capacity(num_pages);
+        for page_index in 0..num_pages {
+            //need to handle last page differently
+            let is_last_page = page_index == num_pages - 1;
+            let last_element_index = if is_last_page {
+                num_entries - 1
+            } else {
+                (page_index + 1) * layout.entries_per_page() - 1
+            };
+            delimiters.push(get_key(last_element_index)?);
+        }
+        self.write_inner_nodes_from_delimiters(run_address, num_entries, &delimiters)
+    }
+    ///Build the B-tree file of a run from the delimiter (last key) of each of its leaves, see LeafDelimiters.
+    /// Does nothing if the run fits in one page
+    pub fn write_inner_nodes_from_delimiters(
+        &self,
+        run_address: &RunAddress,
+        num_entries: Size,
+        delimiters: &[Key],
+    ) -> io::Result<()> {
+        let layout = self.layout;
+        let num_pages = num_leaves(num_entries, layout);
+        if num_pages < 2 {
+            return Ok(()); //a single page is its own root, no inner nodes needed
+        }
         assert_eq!(
-            num_leaves(num_entries),
+            num_pages,
             delimiters.len(),
-            "Miscalculated number of leaves"
+            "Expected one delimiter per leaf"
         );
-        let node_chunk_size = fanout();
 
-        //build parent nodes all the way up to root
-        for depth in (0..tree_depth(num_entries)).rev() {
-            let num_nodes = num_nodes(depth, num_entries);
-            let delimiters_per_node = delimiters.chunks(node_chunk_size); //each chunk corresponds to the values in each node on this level
-            assert_eq!(delimiters_per_node.len(), num_nodes, "Calculated number of nodes on level {level} differs from number of delimiter chunks allocated to this level, chunk sizes: {:?}", delimiters_per_node.map(|delimiter_chunk| delimiter_chunk.len()).collect::<Vec<usize>>()); //if this breaks one of these is wrong
+        let mut delimiter_buffer: DelimiterBuffer = (0..tree_depth(num_entries, layout))
+            .map(|_depth| (Vec::with_capacity(layout.fanout()), 0))
+            .collect();
 
-            for (node, node_elements) in delimiters_per_node.enumerate() {
-                seek_node(&mut file, depth, node, num_entries)?;
-                serde_btree::serialize_into(&mut file, node_elements)?;
-            }
+        let (storage, path) = (run_address.0.storage(), run_address.path_btree());
+        let mut file = storage.create_tmp(&path)?;
 
-            //get largest delimiter in subtrees
-            delimiters = get_last_in_each_chunk(&delimiters, fanout());
+        for (page_index, delimiter) in delimiters.iter().enumerate() {
+            let is_last_page = page_index == num_pages - 1;
+            delimiter_buffer_insert(
+                &mut file,
+                &mut delimiter_buffer,
+                tree_depth(num_entries, layout) - 1,
+                num_entries,
+                *delimiter,
+                is_last_page,
+                self,
+            )?;
         }
 
-        Ok(())
+        storage.publish(file, &path)
+    }
+}
+
+impl SortedStringTable for Sst {
+    ///Writes entries onto the SST file one page at a time, then the inner nodes above them if there's more than one
+    fn write_from_iter(
+        &self,
+        run_address: &RunAddress,
+        entries: &mut dyn Iterator<Item = Entry>,
+        on_page_written: &mut dyn FnMut(&[Entry]) -> io::Result<()>,
+    ) -> io::Result<Size> {
+        //delimiters are collected from the pages as they're written, instead of reading the run back
+        let mut leaf_delimiters = LeafDelimiters::new(self.layout);
+        let num_entries =
+            self.array_sst()
+                .write_from_iter(run_address, entries, &mut |page: &[Entry]| {
+                    leaf_delimiters.push(page);
+                    on_page_written(page)
+                })?;
+        self.write_inner_nodes_from_delimiters(
+            run_address,
+            num_entries,
+            &leaf_delimiters.finish(),
+        )?;
+        Ok(num_entries)
     }
 
     ///Deserializes entire SST to entry vec
     fn read(&self, run_address: &RunAddress) -> io::Result<Vec<Entry>> {
-        array_sst::Sst.read(run_address)
+        self.array_sst().read(run_address)
     }
 
     fn binary_search_get(
@@ -90,7 +240,8 @@ impl SortedStringTable for Sst {
         num_entries: Size,
         buffer_pool: Option<&mut BufferPool>,
     ) -> io::Result<Option<Value>> {
-        array_sst::Sst.binary_search_get(run_address, key, num_entries, buffer_pool)
+        self.array_sst()
+            .binary_search_get(run_address, key, num_entries, buffer_pool)
     }
 
     fn get(
@@ -100,19 +251,152 @@ impl SortedStringTable for Sst {
         num_entries: Size,
         mut buffer_pool: Option<&mut BufferPool>,
     ) -> io::Result<Option<Value>> {
-        if has_inner_nodes(num_entries) {
+        if has_inner_nodes(num_entries, self.layout) {
             //there is no btree file, only entries
-            return array_sst::Sst.get(run_address, key, num_entries, buffer_pool);
+            return self
+                .array_sst()
+                .get(run_address, key, num_entries, buffer_pool);
         }
 
         //get SST page that should contain the entry we want, using inner node navigation
-        let page_index = btree_navigate(run_address, key, num_entries, buffer_pool.as_deref_mut())?; //next_node;
+        let page_index = btree_navigate(
+            run_address,
+            key,
+            num_entries,
+            self.layout,
+            self.key_codec,
+            buffer_pool.as_deref_mut(),
+        )?; //next_node;
 
-        let entries = get_entries_at_page(run_address, page_index, buffer_pool)?;
+        let entries = self.get_leaf(run_address, page_index, buffer_pool)?;
 
         Ok(binary_search_entries(&entries, key))
     }
 
+    ///Only the keys of the entries in range are decoded, a page at a time
+    fn scan_keys(
+        &self,
+        run_address: &RunAddress,
+        key_range: (Key, Key),
+        num_entries: Size,
+        mut buffer_pool: Option<&mut BufferPool>,
+    ) -> io::Result<Vec<Key>> {
+        let bounds = self.scan_bounds(
+            run_address,
+            key_range,
+            num_entries,
+            buffer_pool.as_deref_mut(),
+        )?;
+        keys_in_bounds(run_address, bounds, self.key_codec, buffer_pool)
+    }
+    fn binary_search_scan_bounds(
+        &self,
+        run_address: &RunAddress,
+        key_range: (Key, Key),
+        num_entries: Size,
+        buffer_pool: Option<&mut BufferPool>,
+    ) -> io::Result<Range<Size>> {
+        self.array_sst()
+            .binary_search_scan_bounds(run_address, key_range, num_entries, buffer_pool)
+    }
+    fn scan_bounds(
+        &self,
+        run_address: &RunAddress,
+        key_range: (Key, Key),
+        num_entries: Size,
+        mut buffer_pool: Option<&mut BufferPool>,
+    ) -> io::Result<Range<Size>> {
+        if has_inner_nodes(num_entries, self.layout) {
+            //there is no btree file, only entries
+            return self
+                .array_sst()
+                .scan_bounds(run_address, key_range, num_entries, buffer_pool);
+        }
+
+        let (key1, key2) = key_range;
+        let start = self.partition_point(
+            run_address,
+            key1,
+            num_entries,
+            buffer_pool.as_deref_mut(),
+            |entry_key| entry_key < key1,
+        )?;
+        let end =
+            self.partition_point(run_address, key2, num_entries, buffer_pool, |entry_key| {
+                entry_key <= key2
+            })?;
+        Ok(start..end.max(start)) //empty when key1 > key2
+    }
+    fn ceiling(
+        &self,
+        run_address: &RunAddress,
+        key: Key,
+        num_entries: Size,
+        mut buffer_pool: Option<&mut BufferPool>,
+    ) -> io::Result<Option<Entry>> {
+        if has_inner_nodes(num_entries, self.layout) {
+            //there is no btree file, only entries
+            return self
+                .array_sst()
+                .ceiling(run_address, key, num_entries, buffer_pool);
+        }
+
+        let page_index = btree_navigate(
+            run_address,
+            key,
+            num_entries,
+            self.layout,
+            self.key_codec,
+            buffer_pool.as_deref_mut(),
+        )?;
+        let entries = self.get_leaf(run_address, page_index, buffer_pool.as_deref_mut())?;
+        let index = entries.partition_point(|(entry_key, _)| *entry_key < key);
+        if let Some(entry) = entries.get(index) {
+            return Ok(Some(*entry));
+        }
+        //every key in the page is smaller, the ceiling is the first entry of the next page (if any)
+        if page_index + 1 >= num_leaves(num_entries, self.layout) {
+            return Ok(None);
+        }
+        let entries = self.get_leaf(run_address, page_index + 1, buffer_pool)?;
+        Ok(entries.first().copied())
+    }
+
+    fn floor(
+        &self,
+        run_address: &RunAddress,
+        key: Key,
+        num_entries: Size,
+        mut buffer_pool: Option<&mut BufferPool>,
+    ) -> io::Result<Option<Entry>> {
+        if has_inner_nodes(num_entries, self.layout) {
+            //there is no btree file, only entries
+            return self
+                .array_sst()
+                .floor(run_address, key, num_entries, buffer_pool);
+        }
+
+        let page_index = btree_navigate(
+            run_address,
+            key,
+            num_entries,
+            self.layout,
+            self.key_codec,
+            buffer_pool.as_deref_mut(),
+        )?;
+        let entries = self.get_leaf(run_address, page_index, buffer_pool.as_deref_mut())?;
+        let index = entries.partition_point(|(entry_key, _)| *entry_key <= key);
+        if index > 0 {
+            return Ok(Some(entries[index - 1]));
+        }
+        //every key in the page is larger, the floor is the last entry of the previous page (if any)
+        if page_index == 0 {
+            return Ok(None);
+        }
+        let entries = self.get_leaf(run_address, page_index - 1, buffer_pool)?;
+        Ok(entries.last().copied())
+    }
+
     fn binary_search_scan(
         &self,
         run_address: &RunAddress,
@@ -120,7 +404,8 @@ impl SortedStringTable for Sst {
         num_entries: Size,
         buffer_pool: Option<&mut BufferPool>,
     ) -> io::Result<Vec<Entry>> {
-        array_sst::Sst.binary_search_scan(run_address, key_range, num_entries, buffer_pool)
+        self.array_sst()
+            .binary_search_scan(run_address, key_range, num_entries, buffer_pool)
     }
     ///Perform binary search to find the starting and end positions for our scan, then append all values within those bounds
     fn scan(
@@ -130,28 +415,44 @@ impl SortedStringTable for Sst {
         num_entries: Size,
         mut buffer_pool: Option<&mut BufferPool>,
     ) -> io::Result<Vec<Entry>> {
-        if num_entries <= fanout() {
+        if num_entries <= self.layout.fanout() {
             //there is no btree file, only entries
-            return array_sst::Sst.scan(run_address, key_range, num_entries, buffer_pool);
+            return self
+                .array_sst()
+                .scan(run_address, key_range, num_entries, buffer_pool);
         }
 
         let (key1, key2) = key_range;
 
-        let lowerbound_page_index =
-            btree_navigate(run_address, key1, num_entries, buffer_pool.as_deref_mut())?;
+        let lowerbound_page_index = btree_navigate(
+            run_address,
+            key1,
+            num_entries,
+            self.layout,
+            self.key_codec,
+            buffer_pool.as_deref_mut(),
+        )?;
 
-        let upperbound_page_index =
-            btree_navigate(run_address, key2, num_entries, buffer_pool.as_deref_mut())?;
+        let upperbound_page_index = btree_navigate(
+            run_address,
+            key2,
+            num_entries,
+            self.layout,
+            self.key_codec,
+            buffer_pool.as_deref_mut(),
+        )?;
 
         let mut get_entries =
-            |page_index| get_entries_at_page(run_address, page_index, buffer_pool.as_deref_mut()); //for readability: reduce duplicate args
+            |page_index| self.get_leaf(run_address, page_index, buffer_pool.as_deref_mut()); //for readability: reduce duplicate args
 
         let lowerbound_page_entries = get_entries(lowerbound_page_index)?;
         let lowerbound_keys: Vec<Key> = lowerbound_page_entries
             .iter()
             .map(|(key, _)| *key)
             .collect(); //TODO: write a specific binary search for entries instead of creating mapped array
-        let lowerbound_within_page_index = binary_search_leftmost(&lowerbound_keys, key1);
+                        //past the end of the page if every key on it is smaller
+        let lowerbound_within_page_index =
+            binary_search_leftmost(&lowerbound_keys, key1).unwrap_or(lowerbound_keys.len());
 
         let (upperbound_keys, upperbound_page_entries) =
             if upperbound_page_index == lowerbound_page_index {
@@ -169,12 +470,12 @@ impl SortedStringTable for Sst {
         //EDGE CASE: lowerbound and upperbound are in the same page
         //then, we should already have the entries on this page
         if lowerbound_page_index == upperbound_page_index {
-            if lowerbound_within_page_index as i64 > upperbound_within_page_index {
-                return Ok(vec![]);
-            }
-            let scan_result = upperbound_page_entries
-                [lowerbound_within_page_index..upperbound_within_page_index as usize + 1]
-                .to_vec();
+            let scan_result = match upperbound_within_page_index {
+                Some(upperbound) if lowerbound_within_page_index <= upperbound => {
+                    upperbound_page_entries[lowerbound_within_page_index..=upperbound].to_vec()
+                }
+                _ => vec![],
+            };
             return Ok(scan_result);
         }
 
@@ -193,114 +494,84 @@ impl SortedStringTable for Sst {
             results.extend(page_entries);
         }
 
-        //Add upperbound entries if there are any (upperbound_within_page_index is inside its entries array)
-        //EDGE CASE: upperbound index is "before" first element, that means our upperbound entry is contained
+        //Add upperbound entries if there are any
+        //EDGE CASE: upperbound index is "before" first element (None), that means our upperbound entry is contained
         //           in the last index of the page prior to this one (if it is there)
-        if upperbound_within_page_index >= 0 {
-            results.extend_from_slice(
-                &upperbound_page_entries[..upperbound_within_page_index as usize + 1],
-            );
+        if let Some(upperbound) = upperbound_within_page_index {
+            results.extend_from_slice(&upperbound_page_entries[..=upperbound]);
         }
 
         Ok(results)
     }
     ///Gets the number of entries in an sst
     fn len(&self, run_address: &RunAddress) -> io::Result<Size> {
-        array_sst::Sst.len(run_address)
+        self.array_sst().len(run_address)
     }
 
-    ///Compact all SST runs in a level into a single SST run, build B-tree nodes (if applicable),
-    /// and update entry_counts to reflect that
+    ///Compact all SST runs in a level into a single SST run, build B-tree nodes (if applicable) from the leaves
+    /// as they're written, and update entry_counts to reflect that
     fn compact(
         &self,
         level_address: &LevelAddress,
         entry_counts: &mut Vec<Size>,
         discard_tombstones: bool,
         mut buffer_pool: Option<&mut BufferPool>,
-    ) -> io::Result<()> {
+    ) -> io::Result<Option<MergeStats>> {
         if entry_counts.len() < 2 {
-            return Ok(()); //Nothing to compact
+            return Ok(None); //Nothing to compact
         }
 
-        //Step 1: compact file containing entries
-        array_sst::Sst.compact(
+        //Step 1: compact file containing entries, keeping the last key of each leaf
+        let mut leaf_delimiters = LeafDelimiters::new(self.layout);
+        let stats = self.array_sst().compact_with_page_callback(
             level_address,
             entry_counts,
             discard_tombstones,
             buffer_pool.as_deref_mut(),
+            &mut |entries| {
+                leaf_delimiters.push(entries);
+                Ok(())
+            },
         )?;
 
         //remove existing B-tree files
-        for path_result in fs::read_dir(filename::lsm_level_directory(level_address))? {
-            let path = path_result?.path();
+        let storage = level_address.0.storage();
+        for path in storage.read_dir(&filename::lsm_level_directory(level_address))? {
             if let Some(file_extension) = path.extension() {
                 if file_extension == filename::BTREE_FILE_EXTENSION {
                     // fs::remove_file(path)?;
-                    file_interface::remove_file(
-                        path.as_os_str().to_str().unwrap(),
-                        buffer_pool.as_deref_mut(),
-                    )? //TODO: change function to use path directly
+                    file_interface::remove_file(storage, &path, buffer_pool.as_deref_mut())?
                 }
             }
         }
 
         let run = 0;
         let (db_name, level) = *level_address;
-        let run_address = &(db_name, level, run);
         //Step 2: if our new file takes up more than a page, build inner B-tree nodes
-        if entry_counts.is_empty() || num_pages(entry_counts[run]) < 2 {
-            return Ok(());
+        if entry_counts.is_empty() {
+            return Ok(stats);
         }
-
-        let num_entries = entry_counts[run];
-        let num_pages = num_pages(num_entries);
-
-        let get_key = |page_index, index_within_page| -> io::Result<Key> {
-            let page = get_sst_page(run_address, page_index, None)?;
-            let (key, ..) = serde_entry::deserialize_entry_within_page(&page, index_within_page).unwrap_or_else(|why| panic!("Failed to deserialize key at page: {page_index} index: {index_within_page}, reason: {why}"));
-            Ok(key)
-        };
-
-        let mut delimiter_buffer: DelimiterBuffer = (0..tree_depth(num_entries))
-            .map(|_depth| (Vec::with_capacity(fanout()), 0))
-            .collect();
-
-        let path = filename::sst_btree_path(run_address);
-        let mut file = direct_io::create(&path)?;
-
-        for page_index in 0..num_pages {
-            //need to handle last page differently
-            let is_last_page = page_index == num_pages - 1;
-            let last_element_index = if is_last_page {
-                (num_entries - 1) % num_entries_per_page()
-            } else {
-                num_entries_per_page() - 1
-            };
-            let delimiter = get_key(page_index, last_element_index)?;
-            delimiter_buffer_insert(
-                &mut file,
-                &mut delimiter_buffer,
-                tree_depth(num_entries) - 1,
-                num_entries,
-                delimiter,
-                is_last_page,
-            )?;
-        }
-
-        Ok(())
+        self.write_inner_nodes_from_delimiters(
+            &(db_name, level, run),
+            entry_counts[run],
+            &leaf_delimiters.finish(),
+        )?;
+        Ok(stats)
     }
 }
 
 ///Recursively build inner B-tree nodes in a scalable way (only needs <tree depth> * fanout memory).
 /// Requires inserting last value of every page in an SST (one scan)
 fn delimiter_buffer_insert(
-    mut file: &mut std::fs::File,
+    mut file: &mut DbFile,
     buffer: &mut DelimiterBuffer,
     depth: Depth,
     num_entries: Size,
     key: Key,
     force_flush: bool,
+    sst: &Sst, //layout and key codec the nodes are written with
 ) -> io::Result<()> {
+    let layout = sst.layout;
     let (delimiters, curr_node) = &mut buffer[depth];
 
     delimiters.push(key);
@@ -308,16 +579,16 @@ fn delimiter_buffer_insert(
     //when we reach enough delimiters to write a node (or if we want to force a write),
     // write all but the last (handled by serialize_into) and move the last value into the upper level,
     // where it will be used to write nodes at that level (when that level fills up)
-    if delimiters.len() >= fanout() || force_flush {
-        seek_node(file, depth, *curr_node, num_entries)?;
-        serde_btree::serialize_into(&mut file, delimiters)?;
+    if delimiters.len() >= layout.fanout() || force_flush {
+        seek_node(file, depth, *curr_node, num_entries, layout)?;
+        serde_btree::serialize_into(&mut file, delimiters, layout, sst.key_codec)?;
 
         //largest key is moved to a higher level node, where it is used as a delimiter there
         let largest_key = delimiters.last().unwrap().to_owned(); //NOTE: should be able to unwrap because of the length check earlier
 
         delimiters.clear(); //we no longer need these delimiters in our buffer
         *curr_node += 1;
-        assert!(*curr_node <= num_nodes(depth, num_entries));
+        assert!(*curr_node <= num_nodes(depth, num_entries, layout));
 
         if depth > 0 {
             delimiter_buffer_insert(
@@ -327,6 +598,7 @@ fn delimiter_buffer_insert(
                 num_entries,
                 largest_key,
                 force_flush,
+                sst,
             )?;
         }
     }
@@ -334,10 +606,21 @@ fn delimiter_buffer_insert(
 }
 
 mod tests {
+    #[allow(unused_imports)]
+    use std::path::Path;
+
     #[allow(unused_imports)]
     use super::*;
     #[allow(unused_imports)]
-    use crate::util::testing::setup_and_test_and_cleaup;
+    use crate::db::Database;
+    #[allow(unused_imports)]
+    use crate::sst::RunCursor;
+    #[allow(unused_imports)]
+    use crate::util::btree_info::fanout;
+    #[allow(unused_imports)]
+    use crate::util::system_info::num_entries_per_page;
+    #[allow(unused_imports)]
+    use crate::util::testing::{self, setup_and_test_and_cleaup};
     #[allow(unused_imports)]
     use crate::util::types::{Level, Run};
 
@@ -345,10 +628,10 @@ mod tests {
     fn test_simple_compaction_btree_nodes() {
         //test if we properly build the inner nodes when compacting
         //we don't need to test if the entries are compacted properly since it's handled by array_sst
-        let db_name = "btree_simple_sst_compaction";
+        let db_name = &testing::test_db_name("btree_simple_sst_compaction");
         const LEVEL: Level = 0;
         let mut test = || {
-            let btree_sst = Sst {};
+            let btree_sst = Sst::default();
             let num_entries_per_sst = fanout() * num_entries_per_page();
             let iter = 0..num_entries_per_sst as Key; //needs #fanout nodes + 1 root
             let entries0: Vec<Entry> = iter.to_owned().map(|key| (key, 0)).collect();
@@ -377,14 +660,368 @@ mod tests {
         setup_and_test_and_cleaup(&(db_name, LEVEL), &mut test);
     }
 
+    #[test]
+    fn test_single_pass_compaction_btree_nodes() {
+        //B-trees built while compacting must match the ones built by reading the compacted run back
+        let db_name = &testing::test_db_name("btree_single_pass_compaction");
+        const LEVEL: Level = 0;
+        let mut test = || {
+            let layout = PageLayout::new(512); //smallest direct I/O write, keeps a tree with 3 inner levels small
+            let btree_sst = Sst::default().set_layout(layout);
+            let max_key = 210_000;
+            let entries0: Vec<Entry> = (0..max_key).step_by(2).map(|key| (key, 0)).collect();
+            let entries1: Vec<Entry> = (0..max_key).step_by(3).map(|key| (key, 1)).collect();
+            let entries2: Vec<Entry> = (1000..1500)
+                .map(|key| (key, Database::TOMBSTONE_VALUE))
+                .collect();
+            for (run, entries) in [&entries0, &entries1, &entries2].into_iter().enumerate() {
+                btree_sst.write(&(db_name, LEVEL, run), entries).unwrap();
+            }
+
+            let mut entry_counts = vec![entries0.len(), entries1.len(), entries2.len()];
+            btree_sst
+                .compact(&(db_name, LEVEL), &mut entry_counts, true, None)
+                .unwrap();
+            let num_entries = entry_counts[0];
+            assert!(tree_depth(num_entries, layout) >= 3);
+
+            let run_address = &(db_name, LEVEL, 0);
+            let btree_path = run_address.path_btree();
+            let single_pass = db_name.storage().read_file(&btree_path).unwrap();
+            btree_sst
+                .write_inner_nodes(run_address, num_entries)
+                .unwrap();
+            assert_eq!(
+                single_pass,
+                db_name.storage().read_file(&btree_path).unwrap()
+            );
+
+            for key in (-1..max_key + 1).step_by(7) {
+                let expected = match key {
+                    1000..=1499 => None,
+                    _ if key % 3 == 0 => Some(1),
+                    _ if key % 2 == 0 => Some(0),
+                    _ => None,
+                };
+                assert_eq!(
+                    btree_sst.get(run_address, key, num_entries, None).unwrap(),
+                    expected,
+                    "key: {key}"
+                );
+            }
+        };
+        setup_and_test_and_cleaup(&(db_name, LEVEL), &mut test);
+    }
+
+    #[test]
+    fn test_streamed_write_btree_nodes() {
+        //B-trees built while the run is written must match the ones built by reading the run back
+        let db_name = &testing::test_db_name("btree_streamed_write");
+        const LEVEL: Level = 0;
+        let mut test = || {
+            let layout = PageLayout::new(512);
+            let btree_sst = Sst::default().set_layout(layout);
+            let run_address = &(db_name, LEVEL, 0);
+            let num_entries = 200_003;
+            let entries = (0..num_entries as Key).map(|key| (key * 2, key));
+            let written = btree_sst
+                .write_from_iter(run_address, &mut entries.clone(), &mut |_| Ok(()))
+                .unwrap();
+            assert_eq!(written, num_entries);
+            assert!(tree_depth(num_entries, layout) >= 3);
+
+            let btree_path = run_address.path_btree();
+            let streamed = db_name.storage().read_file(&btree_path).unwrap();
+            btree_sst
+                .write_inner_nodes(run_address, num_entries)
+                .unwrap();
+            assert_eq!(streamed, db_name.storage().read_file(&btree_path).unwrap());
+            assert_eq!(
+                btree_sst.read(run_address).unwrap(),
+                entries.collect::<Vec<_>>()
+            );
+        };
+        setup_and_test_and_cleaup(&(db_name, LEVEL), &mut test);
+    }
+
+    #[test]
+    fn test_leaf_delimiters() {
+        let layout = PageLayout::new(64); //4 entries per leaf
+        let entries: Vec<Entry> = (0..10).map(|key| (key, key)).collect();
+        for chunk_size in [1, 3, 4, 10] {
+            let mut leaf_delimiters = LeafDelimiters::new(layout);
+            for chunk in entries.chunks(chunk_size) {
+                leaf_delimiters.push(chunk);
+            }
+            assert_eq!(leaf_delimiters.finish(), vec![3, 7, 9]);
+        }
+        let mut full_leaves = LeafDelimiters::new(layout);
+        full_leaves.push(&entries[..8]);
+        assert_eq!(full_leaves.finish(), vec![3, 7]);
+        assert_eq!(LeafDelimiters::new(layout).finish(), vec![]);
+    }
+
+    #[test]
+    fn test_scan_bounds_matches_scan() {
+        let db_name = &testing::test_db_name("btree_scan_bounds");
+        const LEVEL: Level = 0;
+        let mut test = || {
+            let btree_sst = Sst::default();
+            let run_address = &(db_name, LEVEL, 0);
+            let num_entries = fanout() * num_entries_per_page() + 3; //needs a second level of inner nodes
+            let entries: Vec<Entry> = (0..num_entries as Key).map(|i| (i * 2, i)).collect(); //only even keys
+            btree_sst.write(run_address, &entries).unwrap();
+
+            let page_end = num_entries_per_page() as Key * 2; //first key of the second page
+            for key_range in [
+                (-10, 10),
+                (page_end - 1, page_end + 1), //both bounds between keys across a page boundary
+                (page_end - 2, page_end - 2),
+                (1, 1),
+                (0, Key::MAX),
+            ] {
+                let bounds = btree_sst
+                    .scan_bounds(run_address, key_range, num_entries, None)
+                    .unwrap();
+                let expected = btree_sst
+                    .scan(run_address, key_range, num_entries, None)
+                    .unwrap();
+                assert_eq!(entries[bounds.clone()], expected, "range {key_range:?}");
+
+                let mut cursor = RunCursor::new(*run_address, KeyCodec::default(), bounds, true);
+                let mut reversed = Vec::new();
+                while let Some(entry) = cursor.next(None).unwrap() {
+                    reversed.push(entry);
+                }
+                reversed.reverse();
+                assert_eq!(reversed, expected, "reverse cursor over {key_range:?}");
+            }
+        };
+        setup_and_test_and_cleaup(&(db_name, LEVEL), &mut test);
+    }
+
+    #[test]
+    fn test_foreign_page_size_runs() {
+        let db_name = &testing::test_db_name("btree_foreign_page_size");
+        const LEVEL: Level = 0;
+        let mut test = || {
+            let host = PageLayout::host();
+            //runs written on machines with half and double this machine's page size
+            for (run, layout) in [
+                PageLayout::new(host.page_size() / 2),
+                PageLayout::new(host.page_size() * 2),
+            ]
+            .into_iter()
+            .enumerate()
+            {
+                let run_address = &(db_name, LEVEL, run);
+                let num_entries = layout.entries_per_page() * 300 + 5; //more leaves than the smaller layout's fanout
+                let entries: Vec<Entry> = (0..num_entries as Key).map(|i| (i * 2, i)).collect(); //only even keys
+                let writer = Sst::default().set_layout(layout);
+                writer.write(run_address, &entries).unwrap();
+
+                //the B-tree file is made of the other machine's nodes, not this one's
+                let btree_len = db_name
+                    .storage()
+                    .file_len(&run_address.path_btree())
+                    .unwrap();
+                assert_eq!(btree_len as usize % layout.node_size(), 0);
+
+                let reader = Sst::default().set_layout(layout);
+                let last_key = entries.last().unwrap().0;
+                for (key, value) in entries.iter().step_by(97).chain(entries.last()) {
+                    assert_eq!(
+                        reader.get(run_address, *key, num_entries, None).unwrap(),
+                        Some(*value)
+                    );
+                    assert_eq!(
+                        reader.get(run_address, key + 1, num_entries, None).unwrap(),
+                        None
+                    );
+                    assert_eq!(
+                        reader
+                            .ceiling(run_address, key - 1, num_entries, None)
+                            .unwrap(),
+                        Some((*key, *value))
+                    );
+                    assert_eq!(
+                        reader
+                            .floor(run_address, key + 1, num_entries, None)
+                            .unwrap(),
+                        Some((*key, *value))
+                    );
+                }
+                assert_eq!(
+                    reader
+                        .ceiling(run_address, last_key + 1, num_entries, None)
+                        .unwrap(),
+                    None
+                );
+
+                let page_end = layout.entries_per_page() as Key * 2; //first key of the other machine's second leaf
+                for key_range in [(page_end - 1, page_end + 1), (-10, 10), (0, Key::MAX)] {
+                    let bounds = reader
+                        .scan_bounds(run_address, key_range, num_entries, None)
+                        .unwrap();
+                    let expected: Vec<Entry> = entries
+                        .iter()
+                        .filter(|(key, _)| key_range.0 <= *key && *key <= key_range.1)
+                        .copied()
+                        .collect();
+                    assert_eq!(entries[bounds], expected, "range {key_range:?}");
+                }
+
+                //rebuilding the inner nodes gives the same file
+                let btree = db_name
+                    .storage()
+                    .read_file(&run_address.path_btree())
+                    .unwrap();
+                db_name
+                    .storage()
+                    .remove_file(&run_address.path_btree())
+                    .unwrap();
+                writer.write_inner_nodes(run_address, num_entries).unwrap();
+                assert_eq!(
+                    db_name
+                        .storage()
+                        .read_file(&run_address.path_btree())
+                        .unwrap(),
+                    btree
+                );
+            }
+        };
+        setup_and_test_and_cleaup(&(db_name, LEVEL), &mut test);
+    }
+
+    #[test]
+    #[cfg(feature = "buffer-pool")]
+    fn test_navigation_pins_pages() {
+        //a tree of depth 3 whose root and first inner level share a host page, the deepest node and the leaf are on
+        // pages of their own
+        use crate::{file_io::io_stats, util::system_info};
+        let db_name = &testing::test_db_name("btree_navigation_pins_pages");
+        const LEVEL: Level = 0;
+        let mut test = || {
+            let layout = PageLayout::new(512);
+            let btree_sst = Sst::default().set_layout(layout);
+            let run_address = &(db_name, LEVEL, 0);
+            let num_entries = (layout.fanout().pow(2) + 2) * layout.entries_per_page();
+            assert_eq!(tree_depth(num_entries, layout), 3);
+            let entries: Vec<Entry> = (0..num_entries as Key).map(|key| (key, key)).collect();
+            btree_sst.write(run_address, &entries).unwrap();
+            let last_key = num_entries as Key - 1;
+            let host_page_size = system_info::page_size();
+
+            let pages_read = |read: &mut dyn FnMut()| {
+                let before = io_stats::snapshot();
+                read();
+                io_stats::snapshot().since(&before).pages_read
+            };
+            //with room for 2 pages, a get that needs 3 reads each of them once
+            let mut buffer_pool = BufferPool::new(1, 2 * host_page_size);
+            let mut get = || {
+                let value = btree_sst
+                    .get(run_address, last_key, num_entries, Some(&mut buffer_pool))
+                    .unwrap();
+                assert_eq!(value, Some(last_key));
+            };
+            assert_eq!(pages_read(&mut get), 3);
+            assert!(buffer_pool.size_bytes() <= buffer_pool.capacity());
+
+            //with room for 1 page, the root's page stays pinned while the deepest node's page is fetched
+            let mut buffer_pool = BufferPool::new(1, host_page_size);
+            let mut navigate = || {
+                let leaf = btree_navigate(
+                    run_address,
+                    last_key,
+                    num_entries,
+                    layout,
+                    btree_sst.key_codec,
+                    Some(&mut buffer_pool),
+                )
+                .unwrap();
+                assert_eq!(leaf, num_entries / layout.entries_per_page() - 1);
+            };
+            assert_eq!(pages_read(&mut navigate), 2);
+            assert_eq!(buffer_pool.len(), 2);
+
+            //unpinned once the navigation is done, the next insertion evicts back down to capacity
+            buffer_pool.insert(&run_address.path_sst(), 0, &vec![0; host_page_size]);
+            assert_eq!(buffer_pool.len(), 1);
+        };
+        setup_and_test_and_cleaup(&(db_name, LEVEL), &mut test);
+    }
+
+    #[test]
+    fn test_navigate_rightmost_path() {
+        //trees of depth 1, 2 and 3 whose last subtree at every depth is only partially filled
+        let db_name = &testing::test_db_name("btree_navigate_rightmost_path");
+        const LEVEL: Level = 0;
+        let mut test = || {
+            let layout = PageLayout::new(512);
+            let btree_sst = Sst::default().set_layout(layout);
+            let entries_per_page = layout.entries_per_page();
+            let fanout = layout.fanout();
+            for (run, num_leaves) in [fanout - 3, fanout * 3 + 5, fanout.pow(2) + fanout + 2]
+                .into_iter()
+                .enumerate()
+            {
+                let run_address = &(db_name, LEVEL, run);
+                let num_entries = (num_leaves - 1) * entries_per_page + 7; //last leaf is partially filled too
+                assert_eq!(tree_depth(num_entries, layout), run + 1);
+                let entries: Vec<Entry> = (0..num_entries as Key).map(|i| (i * 2, i)).collect(); //only even keys
+                btree_sst.write(run_address, &entries).unwrap();
+
+                let navigate = |key| {
+                    btree_navigate(
+                        run_address,
+                        key,
+                        num_entries,
+                        layout,
+                        btree_sst.key_codec,
+                        None,
+                    )
+                    .unwrap()
+                };
+                for (leaf, chunk) in entries.chunks(entries_per_page).enumerate() {
+                    assert_eq!(navigate(chunk.first().unwrap().0), leaf);
+                    assert_eq!(navigate(chunk.last().unwrap().0), leaf);
+                }
+
+                //keys past the largest key land on the last leaf, which doesn't have them
+                let last_key = entries.last().unwrap().0;
+                for key in [last_key + 1, last_key + 1000, Key::MAX] {
+                    assert_eq!(navigate(key), num_leaves - 1, "key: {key}");
+                    assert_eq!(
+                        btree_sst.get(run_address, key, num_entries, None).unwrap(),
+                        None
+                    );
+                }
+                for (key, value) in &entries[(num_leaves - 1) * entries_per_page..] {
+                    assert_eq!(
+                        btree_sst.get(run_address, *key, num_entries, None).unwrap(),
+                        Some(*value)
+                    );
+                    assert_eq!(
+                        btree_sst
+                            .get(run_address, key + 1, num_entries, None)
+                            .unwrap(),
+                        None
+                    );
+                }
+            }
+        };
+        setup_and_test_and_cleaup(&(db_name, LEVEL), &mut test);
+    }
+
     #[test]
     fn test_multi_compaction_btree_nodes() {
         //test if we properly build the inner nodes when compacting
         //we don't need to test if the entries are compacted properly since it's handled by array_sst
-        let db_name = "btree_multi_sst_compaction";
+        let db_name = &testing::test_db_name("btree_multi_sst_compaction");
         const LEVEL: Level = 0;
         let mut test = || {
-            let btree_sst = Sst {};
+            let btree_sst = Sst::default();
             let num_entries_per_sst = fanout() * num_entries_per_page();
             let num_runs: Run = 5;
 
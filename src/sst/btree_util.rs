@@ -2,74 +2,112 @@ use std::io::Seek;
 
 use crate::buffer_pool::BufferPool;
 use crate::ceil_div;
-use crate::file_io::serde_btree;
+use crate::file_io::{serde_btree, storage::DbFile};
 use crate::util::algorithm::binary_search_leftmost;
-use crate::util::btree_info::{fanout, node_size, ROOT_PAGE_OFFSET};
-use crate::util::system_info::num_entries_per_page;
+use crate::util::btree_info::ROOT_PAGE_OFFSET;
+use crate::util::system_info::PageLayout;
 use crate::util::types::{Depth, Node, RunAddress};
-use crate::util::types::{Key, Page, Size};
+use crate::util::types::{Key, KeyCodec, Page, Size};
 
-use super::sst_util::{get_btree_page, num_pages};
+use super::sst_util::get_pinned_btree_page;
 
-pub fn has_inner_nodes(num_entries: Size) -> bool {
-    num_entries <= num_entries_per_page()
+//NOTE: every function here takes the layout the run was written with, runs copied from a machine with a different
+// page size have a different fanout and number of entries per leaf
+
+pub fn has_inner_nodes(num_entries: Size, layout: PageLayout) -> bool {
+    num_entries <= layout.entries_per_page()
 }
 
-pub fn num_leaves(num_entries: Size) -> Size {
-    num_pages(num_entries)
+pub fn num_leaves(num_entries: Size, layout: PageLayout) -> Size {
+    ceil_div!(num_entries, layout.entries_per_page())
 }
 
-///Depth of B-tree, same as number of inner node levels
-pub fn tree_depth(num_entries: Size) -> Size {
-    (num_leaves(num_entries) as f64).log(fanout() as f64).ceil() as Size
+///Fanout of runs written with <layout>, a node with less than 2 children would never shrink the level above it
+fn checked_fanout(layout: PageLayout) -> Size {
+    let fanout = layout.fanout();
+    assert!(
+        fanout >= 2,
+        "B-tree fanout must be at least 2, got {fanout}"
+    );
+    fanout
 }
 
-pub fn subtree_height(depth: Depth, num_entries: Size) -> Size {
-    tree_depth(num_entries) - depth
+///Depth of B-tree, same as number of inner node levels (the smallest depth with fanout^depth >= number of leaves).
+/// NOTE: integer math only, float logs are off by one level for counts past 2^53 and undefined for a fanout of 1
+pub fn tree_depth(num_entries: Size, layout: PageLayout) -> Size {
+    let fanout = checked_fanout(layout);
+    //each inner level has ceil(nodes below / fanout) nodes, the root is the level with 1 node
+    let mut level_nodes = num_leaves(num_entries, layout);
+    let mut depth = 0;
+    while level_nodes > 1 {
+        level_nodes = ceil_div!(level_nodes, fanout);
+        depth += 1;
+    }
+    depth
+}
+
+pub fn subtree_height(depth: Depth, num_entries: Size, layout: PageLayout) -> Size {
+    tree_depth(num_entries, layout) - depth
 }
 
 ///Number of nodes at a given depth
-pub fn num_nodes(depth: Depth, num_entries: Size) -> Size {
+pub fn num_nodes(depth: Depth, num_entries: Size, layout: PageLayout) -> Size {
     ceil_div!(
-        num_leaves(num_entries),
-        fanout().pow(subtree_height(depth, num_entries) as u32)
+        num_leaves(num_entries, layout),
+        leaves_in_subtree(depth, num_entries, layout)
     )
 }
 
-///Number of leaves that are skipped when moving a long each node at a given depth
-pub fn leaves_in_subtree(depth: Depth, num_entries: Size) -> Size {
-    fanout().pow(subtree_height(depth, num_entries) as u32)
+///Number of leaves that are skipped when moving a long each node at a given depth.
+/// NOTE: only the last node at a depth can have a partially filled subtree, and nothing is ever skipped past it
+pub fn leaves_in_subtree(depth: Depth, num_entries: Size, layout: PageLayout) -> Size {
+    //saturates instead of overflowing, a subtree can't have more leaves than Size::MAX anyway
+    checked_fanout(layout).saturating_pow(subtree_height(depth, num_entries, layout) as u32)
+}
+
+///Number of children of a node, every node but the last at its depth has #fanout children,
+/// the last one only has what's left of the level below it
+pub fn num_children(depth: Depth, node: Node, num_entries: Size, layout: PageLayout) -> Size {
+    let nodes_below = num_nodes(depth + 1, num_entries, layout);
+    nodes_below
+        .saturating_sub(node * layout.fanout())
+        .min(layout.fanout())
 }
 
 ///get page index of first node at a depth
-pub fn depth_page_index(depth: Depth, num_entries: Size) -> Page {
+pub fn depth_page_index(depth: Depth, num_entries: Size, layout: PageLayout) -> Page {
     //NOTE: there is probably room for optimization here, might be able to change this into a closed form expression (instead of summation)
-    (0..depth).fold(ROOT_PAGE_OFFSET, |acc, d| acc + num_nodes(d, num_entries))
+    (0..depth).fold(ROOT_PAGE_OFFSET, |acc, d| {
+        acc + num_nodes(d, num_entries, layout)
+    })
 }
 
 ///get page index of a node
-pub fn node_page_index(depth: Depth, node: Node, num_entries: Size) -> Page {
-    depth_page_index(depth, num_entries) + node
+pub fn node_page_index(depth: Depth, node: Node, num_entries: Size, layout: PageLayout) -> Page {
+    depth_page_index(depth, num_entries, layout) + node
 }
 
-///get byte index of first byte in a node
-pub fn node_byte_index(depth: Depth, node: Node, num_entries: Size) -> u64 {
-    (node_page_index(depth, node, num_entries) * node_size()) as u64
+///get byte index of first byte in a node. NOTE: widened before multiplying, a B-tree file can be larger than a usize
+/// on 32-bit targets
+pub fn node_byte_index(depth: Depth, node: Node, num_entries: Size, layout: PageLayout) -> u64 {
+    node_page_index(depth, node, num_entries, layout) as u64 * layout.node_size() as u64
 }
 
 pub fn seek_node(
-    file: &mut std::fs::File,
+    file: &mut DbFile,
     depth: Depth,
     node: Node,
     num_entries: Size,
+    layout: PageLayout,
 ) -> std::io::Result<u64> {
-    let seek_offset = node_byte_index(depth, node, num_entries);
+    let seek_offset = node_byte_index(depth, node, num_entries, layout);
     file.seek(std::io::SeekFrom::Start(seek_offset))?;
     Ok(seek_offset)
 }
 
 ///Gets the largest values in each chunk of an array of keys. Useful for building inner nodes of B-tree
 /// NOTE: assumes array is sorted
+#[allow(dead_code)]
 pub fn get_last_in_each_chunk(elements: &[Key], chunk_size: usize) -> Vec<Key> {
     elements
         .chunks(chunk_size)
@@ -83,33 +121,51 @@ pub fn get_last_in_each_chunk(elements: &[Key], chunk_size: usize) -> Vec<Key> {
         .collect()
 }
 
-///Navigate inner nodes of B-tree starting from root, returns page index of where key may be
+///Navigate inner nodes of B-tree starting from root, returns page index (in <layout> sized pages) of where key may be
 pub fn btree_navigate(
     run_address: &RunAddress,
     key: Key,
     num_entries: Size,
+    layout: PageLayout,
+    key_codec: KeyCodec,
     mut buffer_pool: Option<&mut BufferPool>,
 ) -> std::io::Result<Page> {
     let (db_name, level, run) = run_address;
-    let num_inner_levels = tree_depth(num_entries);
+    let num_inner_levels = tree_depth(num_entries, layout);
 
     let mut curr_leaf_page_index: Page = 0;
     let mut next_node: Node = 0;
+    //nodes of different depths can share host pages (runs written with a smaller page size), pinned so fetching a
+    // deeper node can't evict a page we'd read again
+    let mut pins = Vec::with_capacity(num_inner_levels);
     for depth in 0..num_inner_levels {
-        let node_page_index = node_page_index(depth, next_node, num_entries);
-        let node_page = get_btree_page(run_address, node_page_index, buffer_pool.as_deref_mut())?; //NOTE: watch out for the deref_mut, we don't want to accdientally copy the buffer pool, TODO: verify this doesn't break it
-
-        let node_delimiters = serde_btree::deserialize(&node_page).unwrap_or_else(|_| panic!("Failed to deserialize B-tree node during B-tree navigation while searching for key: {key}, name: {db_name}, level: {level}, run: {run}, page_index: {node_page_index} num_entries: {num_entries}"));
-
-        next_node = binary_search_leftmost(&node_delimiters, key);
-        curr_leaf_page_index += next_node * leaves_in_subtree(depth + 1, num_entries);
+        let node_page_index = node_page_index(depth, next_node, num_entries, layout);
+        let node_page = get_pinned_btree_page(
+            run_address,
+            node_page_index,
+            layout,
+            buffer_pool.as_deref_mut(),
+            &mut pins,
+        )?; //NOTE: watch out for the deref_mut, we don't want to accdientally copy the buffer pool, TODO: verify this doesn't break it
+
+        let node_delimiters = serde_btree::deserialize(&node_page, layout, key_codec).unwrap_or_else(|_| panic!("Failed to deserialize B-tree node during B-tree navigation while searching for key: {key}, name: {}, level: {level}, run: {run}, page_index: {node_page_index} num_entries: {num_entries}", db_name.display()));
+
+        //a key past the last delimiter belongs to the last child, clamped to the children the node actually has
+        // so a node with more delimiters than children (a damaged node) can't send us past the last leaf
+        let last_child = num_children(depth, next_node, num_entries, layout).saturating_sub(1);
+        let child = binary_search_leftmost(&node_delimiters, key)
+            .unwrap_or(node_delimiters.len())
+            .min(last_child);
+        curr_leaf_page_index += child * leaves_in_subtree(depth + 1, num_entries, layout);
+        //nodes at the next depth are numbered across the whole level, not within their parent
+        next_node = next_node * layout.fanout() + child;
     }
 
     assert!(
-        curr_leaf_page_index < num_leaves(num_entries),
+        curr_leaf_page_index < num_leaves(num_entries, layout),
         "Btree navigated to leaf page index that does not exist, page index: {}, num_leaves {}",
         curr_leaf_page_index,
-        num_leaves(num_entries)
+        num_leaves(num_entries, layout)
     );
 
     // Ok(next_node as Page)
@@ -123,115 +179,248 @@ mod tests {
 
     #[test]
     fn test_num_leaves() {
-        let entries_per_page = num_entries_per_page();
-        assert_eq!(num_leaves(0), 0);
-        assert_eq!(num_leaves(1), 1);
-
-        assert_eq!(num_leaves(entries_per_page), 1);
-        assert_eq!(num_leaves(entries_per_page + 1), 2);
-        assert_eq!(num_leaves(entries_per_page - 1), 1);
-
-        assert_eq!(num_leaves(entries_per_page * 99), 99);
-        assert_eq!(num_leaves(entries_per_page * 99 + 1), 100);
-        assert_eq!(num_leaves(entries_per_page * 99 - 1), 99);
+        let layout = PageLayout::host();
+        let entries_per_page = layout.entries_per_page();
+        assert_eq!(num_leaves(0, layout), 0);
+        assert_eq!(num_leaves(1, layout), 1);
+
+        assert_eq!(num_leaves(entries_per_page, layout), 1);
+        assert_eq!(num_leaves(entries_per_page + 1, layout), 2);
+        assert_eq!(num_leaves(entries_per_page - 1, layout), 1);
+
+        assert_eq!(num_leaves(entries_per_page * 99, layout), 99);
+        assert_eq!(num_leaves(entries_per_page * 99 + 1, layout), 100);
+        assert_eq!(num_leaves(entries_per_page * 99 - 1, layout), 99);
     }
 
     #[test]
     fn test_tree_depth() {
-        let entries_per_page = num_entries_per_page();
-        let fanout = fanout();
+        let layout = PageLayout::host();
+        let entries_per_page = layout.entries_per_page();
+        let fanout = layout.fanout();
 
-        assert_eq!(tree_depth(0), 0);
-        assert_eq!(tree_depth(1), 0); //no internal nodes needed for 1 entry
+        assert_eq!(tree_depth(0, layout), 0);
+        assert_eq!(tree_depth(1, layout), 0); //no internal nodes needed for 1 entry
 
-        assert_eq!(tree_depth(entries_per_page), 0); //no internal nodes needed for 1 page worth of entries
-        assert_eq!(tree_depth(entries_per_page + 1), 1); //2 pages worth of entries, need 1 node to manage it
-        assert_eq!(tree_depth(entries_per_page - 1), 0); //no internal nodes needed for less than 1 page worth of entries
+        assert_eq!(tree_depth(entries_per_page, layout), 0); //no internal nodes needed for 1 page worth of entries
+        assert_eq!(tree_depth(entries_per_page + 1, layout), 1); //2 pages worth of entries, need 1 node to manage it
+        assert_eq!(tree_depth(entries_per_page - 1, layout), 0); //no internal nodes needed for less than 1 page worth of entries
 
         //#fanout should be the max number of leaves a single node can handle
-        assert_eq!(tree_depth(entries_per_page * fanout), 1);
-        assert_eq!(tree_depth(entries_per_page * fanout + 1), 2); //now we need 2 nodes to handle the leaves, and a root to handle those 2 nodes
-        assert_eq!(tree_depth(entries_per_page * fanout - 1), 1);
+        assert_eq!(tree_depth(entries_per_page * fanout, layout), 1);
+        assert_eq!(tree_depth(entries_per_page * fanout + 1, layout), 2); //now we need 2 nodes to handle the leaves, and a root to handle those 2 nodes
+        assert_eq!(tree_depth(entries_per_page * fanout - 1, layout), 1);
 
         //#fanout^2 leaves should be handled by #fanout nodes and a root to handle those nodes
         //this amount should be the limit, so anything more needs another level of nodes
-        assert_eq!(tree_depth(entries_per_page * fanout.pow(2)), 2);
-        assert_eq!(tree_depth(entries_per_page * fanout.pow(2) + 1), 3);
-        assert_eq!(tree_depth(entries_per_page * fanout.pow(2) - 1), 2);
+        assert_eq!(tree_depth(entries_per_page * fanout.pow(2), layout), 2);
+        assert_eq!(tree_depth(entries_per_page * fanout.pow(2) + 1, layout), 3);
+        assert_eq!(tree_depth(entries_per_page * fanout.pow(2) - 1, layout), 2);
     }
 
     #[test]
     fn test_num_nodes() {
-        let entries_per_page = num_entries_per_page();
-        let fanout = fanout();
+        let layout = PageLayout::host();
+        let entries_per_page = layout.entries_per_page();
+        let fanout = layout.fanout();
 
-        assert_eq!(num_nodes(0, 0), 0); //no nodes needed for 1 entry
-        assert_eq!(num_nodes(0, 1), 1); //1 leaf node needed for 1 entry
+        assert_eq!(num_nodes(0, 0, layout), 0); //no nodes needed for 1 entry
+        assert_eq!(num_nodes(0, 1, layout), 1); //1 leaf node needed for 1 entry
 
         //#fanout should be the max number of leaves a single node can handle
         let num_entries = entries_per_page * fanout;
-        assert_eq!(num_nodes(1, num_entries), num_leaves(num_entries)); //confirm above comment
-        assert_eq!(num_nodes(0, num_entries), 1); //confirm above comment
+        assert_eq!(
+            num_nodes(1, num_entries, layout),
+            num_leaves(num_entries, layout)
+        ); //confirm above comment
+        assert_eq!(num_nodes(0, num_entries, layout), 1); //confirm above comment
 
         //#fanout should be the max number of leaves a single node can handle
         let num_entries = entries_per_page * fanout + 1;
-        assert_eq!(num_nodes(2, num_entries), num_leaves(num_entries)); //confirm above comment
-        assert_eq!(num_nodes(1, num_entries), 2); //confirm above comment
-        assert_eq!(num_nodes(0, num_entries), 1); //confirm above comment
+        assert_eq!(
+            num_nodes(2, num_entries, layout),
+            num_leaves(num_entries, layout)
+        ); //confirm above comment
+        assert_eq!(num_nodes(1, num_entries, layout), 2); //confirm above comment
+        assert_eq!(num_nodes(0, num_entries, layout), 1); //confirm above comment
 
         //#fanout^2 leaves should be handled by #fanout nodes and a root to handle those nodes
         let num_entries = entries_per_page * fanout.pow(2);
-        assert_eq!(num_nodes(2, num_entries), num_leaves(num_entries)); //confirm above comment
-        assert_eq!(num_nodes(1, num_entries), fanout); //confirm above comment
-        assert_eq!(num_nodes(0, num_entries), 1); //confirm above comment
+        assert_eq!(
+            num_nodes(2, num_entries, layout),
+            num_leaves(num_entries, layout)
+        ); //confirm above comment
+        assert_eq!(num_nodes(1, num_entries, layout), fanout); //confirm above comment
+        assert_eq!(num_nodes(0, num_entries, layout), 1); //confirm above comment
 
         //#fanout^2 + 1 leaves should be handled by #fanout + 1 nodes, 2 nodes above those, and a root to handle those nodes
         let num_entries = entries_per_page * fanout.pow(2) + 1;
-        assert_eq!(num_nodes(3, num_entries), num_leaves(num_entries)); //confirm above comment
-        assert_eq!(num_nodes(2, num_entries), fanout + 1); //confirm above comment
-        assert_eq!(num_nodes(1, num_entries), 2); //confirm above comment
-        assert_eq!(num_nodes(0, num_entries), 1); //confirm above comment
+        assert_eq!(
+            num_nodes(3, num_entries, layout),
+            num_leaves(num_entries, layout)
+        ); //confirm above comment
+        assert_eq!(num_nodes(2, num_entries, layout), fanout + 1); //confirm above comment
+        assert_eq!(num_nodes(1, num_entries, layout), 2); //confirm above comment
+        assert_eq!(num_nodes(0, num_entries, layout), 1); //confirm above comment
     }
 
     #[test]
     fn test_indexing() {
-        let entries_per_page = num_entries_per_page();
-        let fanout = fanout();
+        let layout = PageLayout::host();
+        let entries_per_page = layout.entries_per_page();
+        let fanout = layout.fanout();
 
         //#fanout^2 leaves should be handled by #fanout nodes and a root to handle those nodes
         //this amount should be the limit, so anything more needs another level of nodes
         let num_entries = entries_per_page * fanout.pow(2);
-        assert_eq!(node_page_index(0, 0, num_entries), 0);
-        assert_eq!(node_page_index(1, 0, num_entries), 1);
-        assert_eq!(node_page_index(2, 0, num_entries), 1 + fanout);
+        assert_eq!(node_page_index(0, 0, num_entries, layout), 0);
+        assert_eq!(node_page_index(1, 0, num_entries, layout), 1);
+        assert_eq!(node_page_index(2, 0, num_entries, layout), 1 + fanout);
         assert_eq!(
-            node_page_index(3, 0, num_entries),
+            node_page_index(3, 0, num_entries, layout),
             1 + fanout + fanout.pow(2)
         );
 
         //has no inner nodes, NOTE: should not be a real use case
-        assert_eq!(node_page_index(0, 0, 0), 0);
-        // assert_eq!(node_page_index(1, 2, 0), 0);
+        assert_eq!(node_page_index(0, 0, 0, layout), 0);
+        // assert_eq!(node_page_index(1, 2, 0, layout), 0);
     }
 
     #[test]
     fn test_leaves_in_subtree() {
-        let num_entries = num_entries_per_page() * fanout().pow(3) - 73;
-        let depths: Vec<Depth> = (0..tree_depth(num_entries)).rev().collect();
+        let layout = PageLayout::host();
+        let num_entries = layout.entries_per_page() * layout.fanout().pow(3) - 73;
+        let depths: Vec<Depth> = (0..tree_depth(num_entries, layout)).rev().collect();
 
         let deepest_inner_node_depth = depths[0]; //0 index since we reversed the iter
         assert_eq!(
-            leaves_in_subtree(deepest_inner_node_depth, num_entries),
-            fanout()
+            leaves_in_subtree(deepest_inner_node_depth, num_entries, layout),
+            layout.fanout()
         ); //when you skip an inner node at lowest level, you skip all its children, there are fanout children
 
-        assert_eq!(leaves_in_subtree(depths[1], num_entries), fanout().pow(2)); //skipping a node with fanout children, each who have fanout children (leaves)
-        assert_eq!(leaves_in_subtree(depths[2], num_entries), fanout().pow(3));
+        assert_eq!(
+            leaves_in_subtree(depths[1], num_entries, layout),
+            layout.fanout().pow(2)
+        ); //skipping a node with fanout children, each who have fanout children (leaves)
+        assert_eq!(
+            leaves_in_subtree(depths[2], num_entries, layout),
+            layout.fanout().pow(3)
+        );
+    }
+
+    #[test]
+    fn test_num_children() {
+        let layout = PageLayout::host();
+        let entries_per_page = layout.entries_per_page();
+        let fanout = layout.fanout();
+
+        //#fanout + 3 leaves: 2 nodes above them (the second with 3 children) and a root
+        let num_entries = entries_per_page * (fanout + 3) - 1;
+        assert_eq!(num_children(0, 0, num_entries, layout), 2);
+        assert_eq!(num_children(1, 0, num_entries, layout), fanout);
+        assert_eq!(num_children(1, 1, num_entries, layout), 3);
+
+        //#fanout^2 + 1 leaves, the rightmost path has a single child at every depth
+        let num_entries = entries_per_page * fanout.pow(2) + 1;
+        assert_eq!(num_children(0, 0, num_entries, layout), 2);
+        assert_eq!(num_children(1, 0, num_entries, layout), fanout);
+        assert_eq!(num_children(1, 1, num_entries, layout), 1);
+        assert_eq!(num_children(2, fanout, num_entries, layout), 1);
+        assert_eq!(num_children(2, fanout - 1, num_entries, layout), fanout);
+
+        //a full tree
+        let num_entries = entries_per_page * fanout.pow(2);
+        assert_eq!(num_children(0, 0, num_entries, layout), fanout);
+        assert_eq!(num_children(1, fanout - 1, num_entries, layout), fanout);
+    }
+
+    //smallest depth with fanout^depth >= num_leaves, in u128 so it can't overflow
+    #[allow(dead_code)]
+    fn expected_depth(num_leaves: Size, fanout: Size) -> Size {
+        let mut depth = 0;
+        while (fanout as u128).pow(depth as u32) < num_leaves as u128 {
+            depth += 1;
+        }
+        depth
+    }
+
+    #[test]
+    fn test_shape_at_fanout_boundaries() {
+        //smallest layout (fanout 2, 1 entry per leaf), a small one and the host's
+        for layout in [PageLayout::new(16), PageLayout::new(64), PageLayout::host()] {
+            let entries_per_page = layout.entries_per_page();
+            let fanout = layout.fanout();
+            let max_depth = match fanout {
+                2 => 40,
+                _ => 4,
+            };
+            for depth in 1..=max_depth {
+                let full_leaves = fanout.pow(depth as u32);
+                for num_leaves in [full_leaves - 1, full_leaves, full_leaves + 1] {
+                    for num_entries in [
+                        num_leaves * entries_per_page,
+                        (num_leaves - 1) * entries_per_page + 1,
+                    ] {
+                        let tree_depth = tree_depth(num_entries, layout);
+                        assert_eq!(
+                            tree_depth,
+                            expected_depth(num_leaves, fanout),
+                            "page size {}, {num_entries} entries",
+                            layout.page_size()
+                        );
+                        //one root, the leaves at the bottom, and each level covers every node below it
+                        assert_eq!(num_nodes(0, num_entries, layout), 1);
+                        assert_eq!(num_nodes(tree_depth, num_entries, layout), num_leaves);
+                        for d in 0..tree_depth {
+                            assert_eq!(
+                                num_nodes(d, num_entries, layout),
+                                ceil_div!(num_nodes(d + 1, num_entries, layout), fanout)
+                            );
+                            assert_eq!(
+                                leaves_in_subtree(d, num_entries, layout),
+                                fanout * leaves_in_subtree(d + 1, num_entries, layout)
+                            );
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_shape_past_float_precision() {
+        //2^53 + 1 leaves round down to 2^53 as a float, whose log is exactly 53 with a fanout of 2 (a level short)
+        let layout = PageLayout::new(16);
+        for num_entries in [(1 << 53) - 1, 1 << 53, (1 << 53) + 1, Size::MAX / 2] {
+            assert_eq!(
+                tree_depth(num_entries, layout),
+                expected_depth(num_leaves(num_entries, layout), 2),
+                "{num_entries} entries"
+            );
+        }
+        assert_eq!(tree_depth((1 << 53) + 1, layout), 54);
+        assert_eq!(num_nodes(1, (1 << 53) + 1, layout), 2);
+        assert_eq!(leaves_in_subtree(1, (1 << 53) + 1, layout), 1 << 53);
+
+        //leaves just past a power of the fanout, near 2^53 entries
+        let layout = PageLayout::host();
+        let fanout = layout.fanout();
+        let depth = (0..).find(|d| fanout.pow(*d) >= 1 << 40).unwrap();
+        let num_entries = fanout.pow(depth) * layout.entries_per_page() + 1;
+        assert_eq!(tree_depth(num_entries, layout), depth as Size + 1);
+        assert_eq!(num_nodes(1, num_entries, layout), 2);
+        assert_eq!(
+            depth_page_index(depth as Depth + 1, num_entries, layout),
+            (0..=depth as Depth)
+                .map(|d| num_nodes(d, num_entries, layout))
+                .sum::<Size>()
+        );
     }
 
     #[test]
     fn get_each_node_largest_entry_test() {
-        let b = fanout();
+        let layout = PageLayout::host();
+        let b = layout.fanout();
         let entries: Vec<Key> = (0..b * 3 - 100).map(|value| value as Key).collect(); //3 nodes worth of entries
         let largest_in_each_node = get_last_in_each_chunk(&entries, b);
 
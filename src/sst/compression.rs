@@ -0,0 +1,455 @@
+//!Compressed runs, see Database::set_compression. Each page of entries is compressed on its own and written right
+//! after the previous one, so pages take a varying number of bytes on disk. A footer after the last page holds the
+//! byte offset of every page, reading page <n> of a compressed run still gives the entries of logical page <n>: binary
+//! searches and B-tree navigation work on page indices the same way they do for uncompressed runs.
+//! Layout of a compressed run: the pages, zero padding, then the footer of little endian u64s: the offset of every
+//! page and of the end of the last one, the number of entries, the key codec, the number of pages and FOOTER_MAGIC.
+//! The padding makes the length of the file FOOTER_REMAINDER more than a multiple of ENTRY_SIZE while uncompressed
+//! runs are always a multiple of it, so the length (then the magic number) tells the two formats apart.
+//! NOTE: the footer is cached in the buffer pool under INDEX_PAGE, without a buffer pool it's read with every page
+
+use std::{io, path::Path};
+
+use crate::{
+    buffer_pool::BufferPool,
+    file_io::{
+        direct_io, file_interface, generation,
+        page_buffers::PageBuffer,
+        serde_entry::{self, KEY_SIZE},
+        serde_util::write_len,
+        storage::{DbFile, Storage},
+    },
+    util::{
+        system_info::{self, ENTRY_SIZE},
+        types::{Compression, Entry, KeyCodec, Page, Size},
+    },
+};
+
+///Last bytes of a compressed run
+const FOOTER_MAGIC: u64 = 0x6b76_7a70_6167_6573; //"kvzpages"
+const U64_SIZE: usize = std::mem::size_of::<u64>();
+///Bytes of the footer after the page offsets: number of entries, key codec, number of pages and magic number
+const TRAILER_SIZE: usize = 4 * U64_SIZE;
+///Length of a compressed run modulo ENTRY_SIZE
+const FOOTER_REMAINDER: usize = U64_SIZE;
+///Page a compressed run's footer is cached under in the buffer pool, no run has that many pages
+pub const INDEX_PAGE: Page = Page::MAX;
+
+//First byte of a page in a compressed run, how the rest of it is encoded
+const RAW_PAGE: u8 = 0; //entries as they are in an uncompressed run
+const DELTA_VARINT_PAGE: u8 = 1; //entry count, then the zigzag varint of each key and value's difference from the last
+
+fn invalid_data(why: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, why)
+}
+
+fn u64_at(bytes: &[u8], index: usize) -> u64 {
+    let start = index * U64_SIZE;
+    u64::from_le_bytes(bytes[start..start + U64_SIZE].try_into().unwrap())
+}
+
+fn key_codec_id(key_codec: KeyCodec) -> u64 {
+    match key_codec {
+        KeyCodec::LittleEndian => 0,
+        KeyCodec::OrderPreserving => 1,
+    }
+}
+
+///Footer of a compressed run
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PageIndex {
+    offsets: Vec<u64>, //byte offset of every page, then of the end of the last one
+    num_entries: Size,
+    key_codec: KeyCodec,
+}
+
+impl PageIndex {
+    pub fn num_pages(&self) -> Size {
+        self.offsets.len() - 1
+    }
+    ///Footer as it's written after the pages
+    fn to_bytes(&self) -> Vec<u8> {
+        let trailer = [
+            self.num_entries as u64,
+            key_codec_id(self.key_codec),
+            self.num_pages() as u64,
+            FOOTER_MAGIC,
+        ];
+        self.offsets
+            .iter()
+            .chain(trailer.iter())
+            .flat_map(|word| word.to_le_bytes())
+            .collect()
+    }
+    ///Parse a footer written by to_bytes
+    fn from_bytes(footer: &[u8]) -> io::Result<Self> {
+        let num_words = footer.len() / U64_SIZE;
+        if !footer.len().is_multiple_of(U64_SIZE) || footer.len() < TRAILER_SIZE + U64_SIZE {
+            return Err(invalid_data(format!(
+                "{} bytes can't be the footer of a compressed run",
+                footer.len()
+            )));
+        }
+        let trailer = num_words - TRAILER_SIZE / U64_SIZE;
+        let num_pages = u64_at(footer, trailer + 2);
+        if u64_at(footer, trailer + 3) != FOOTER_MAGIC || num_pages != trailer as u64 - 1 {
+            return Err(invalid_data(format!(
+                "footer of a compressed run with {num_pages} pages holds {trailer} offsets"
+            )));
+        }
+        let key_codec = match u64_at(footer, trailer + 1) {
+            0 => KeyCodec::LittleEndian,
+            1 => KeyCodec::OrderPreserving,
+            id => return Err(invalid_data(format!("unknown key codec {id}"))),
+        };
+        Ok(Self {
+            offsets: (0..trailer).map(|index| u64_at(footer, index)).collect(),
+            num_entries: u64_at(footer, trailer) as Size,
+            key_codec,
+        })
+    }
+}
+
+///Whether a run of <len> bytes can be compressed, uncompressed runs are a multiple of ENTRY_SIZE
+fn has_footer_len(len: u64) -> bool {
+    len % ENTRY_SIZE as u64 == FOOTER_REMAINDER as u64 && len >= (TRAILER_SIZE + U64_SIZE) as u64
+}
+
+///Footer of the run <file>, None if it isn't compressed
+pub fn read_index(file: &mut DbFile) -> io::Result<Option<PageIndex>> {
+    let len = file.len_bytes()?;
+    if !has_footer_len(len) {
+        return Ok(None);
+    }
+    let trailer = direct_io::read_aligned(file, len - TRAILER_SIZE as u64, TRAILER_SIZE)?;
+    if trailer.len() < TRAILER_SIZE || u64_at(&trailer, 3) != FOOTER_MAGIC {
+        return Ok(None); //a damaged uncompressed run
+    }
+    let num_pages = u64_at(&trailer, 2);
+    let footer_len = num_pages
+        .checked_add(1)
+        .and_then(|num_offsets| num_offsets.checked_mul(U64_SIZE as u64))
+        .and_then(|offsets_len| offsets_len.checked_add(TRAILER_SIZE as u64))
+        .filter(|footer_len| *footer_len <= len)
+        .ok_or_else(|| {
+            invalid_data(format!(
+                "the footer of {num_pages} pages doesn't fit in the run's {len} bytes"
+            ))
+        })?;
+    let footer = direct_io::read_aligned(file, len - footer_len, footer_len as usize)?;
+    PageIndex::from_bytes(&footer).map(Some)
+}
+
+///read_index, cached in the buffer pool if there is one
+fn get_index(
+    path: &Path,
+    file: &mut DbFile,
+    buffer_pool: Option<&mut BufferPool>,
+) -> io::Result<Option<PageIndex>> {
+    //uncompressed runs never touch the buffer pool, so they're cached exactly like they always were
+    let len = file.len_bytes()?;
+    let Some(pool) = buffer_pool.filter(|_| has_footer_len(len)) else {
+        return read_index(file);
+    };
+    let generation = generation::current(path);
+    let cached = pool
+        .get_with_generation(path, INDEX_PAGE)
+        .filter(|(_, cached_generation)| *cached_generation == generation);
+    if let Some((footer, _)) = cached {
+        return PageIndex::from_bytes(&footer).map(Some);
+    }
+    let index = read_index(file)?;
+    if let Some(index) = &index {
+        pool.insert_with_generation(path, INDEX_PAGE, &index.to_bytes(), generation);
+    }
+    Ok(index)
+}
+
+///Page <page_index> of the run <file> whose footer is <index> (None if it isn't compressed), as an uncompressed run
+/// holds it. Empty past the last page
+pub fn read_page(
+    file: &mut DbFile,
+    index: Option<&PageIndex>,
+    page_index: Page,
+) -> io::Result<PageBuffer> {
+    let Some(index) = index else {
+        return direct_io::read_page(file, page_index);
+    };
+    if page_index >= index.num_pages() {
+        return Ok(PageBuffer::from(Vec::new()));
+    }
+    let (start, end) = (index.offsets[page_index], index.offsets[page_index + 1]);
+    let bytes = match end.checked_sub(start) {
+        Some(len) => direct_io::read_aligned(file, start, len as usize)?,
+        None => PageBuffer::from(Vec::new()),
+    };
+    decode_page(&bytes, index.key_codec).map_err(|why| {
+        invalid_data(format!(
+            "page {page_index} of a compressed run can't be decompressed, {why}"
+        ))
+    })
+}
+
+///Page <page_index> of the run at <path> through the buffer pool (see file_interface::get_page), decompressed if the
+/// run is compressed. The buffer pool caches pages decompressed
+pub fn get_page(
+    storage: &Storage,
+    path: &Path,
+    page_index: Page,
+    buffer_pool: Option<&mut BufferPool>,
+) -> io::Result<PageBuffer> {
+    file_interface::get_page_with(path, page_index, buffer_pool, |buffer_pool| {
+        let mut file = storage.open_read(path)?;
+        let index = get_index(path, &mut file, buffer_pool)?;
+        read_page(&mut file, index.as_ref(), page_index)
+    })
+}
+
+///Bytes of the run at <path> as an uncompressed run holds them
+pub fn read_run(storage: &Storage, path: &Path) -> io::Result<Vec<u8>> {
+    let mut file = storage.open_read(path)?;
+    let Some(index) = read_index(&mut file)? else {
+        let len = file.len_bytes()?;
+        return Ok(direct_io::read_aligned(&mut file, 0, len as usize)?.to_vec());
+    };
+    let mut bytes = Vec::with_capacity(index.num_entries * ENTRY_SIZE);
+    for page_index in 0..index.num_pages() {
+        bytes.extend_from_slice(&read_page(&mut file, Some(&index), page_index)?);
+    }
+    Ok(bytes)
+}
+
+///Number of entries in the run at <path>
+pub fn num_entries(storage: &Storage, path: &Path) -> io::Result<Size> {
+    let mut file = storage.open_read(path)?;
+    match read_index(&mut file)? {
+        Some(index) => Ok(index.num_entries),
+        None => Ok(file.len_bytes()? as Size / ENTRY_SIZE),
+    }
+}
+
+fn zigzag(delta: i64) -> u64 {
+    ((delta << 1) ^ (delta >> 63)) as u64
+}
+
+fn unzigzag(bits: u64) -> i64 {
+    (bits >> 1) as i64 ^ -((bits & 1) as i64)
+}
+
+fn push_varint(bytes: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        bytes.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    bytes.push(value as u8);
+}
+
+fn read_varint(bytes: &[u8], position: &mut usize) -> Result<u64, String> {
+    let mut value = 0;
+    for shift in (0..64).step_by(7) {
+        let byte = *bytes
+            .get(*position)
+            .ok_or_else(|| "the page ends in the middle of an entry".to_string())?;
+        *position += 1;
+        value |= ((byte & 0x7f) as u64) << shift;
+        if byte & 0x80 == 0 {
+            return Ok(value);
+        }
+    }
+    Err(format!("varint ending at byte {position} is too long"))
+}
+
+///<entries> of a page encoded with <compression>, or as they are if that doesn't make them smaller
+fn encode_page(entries: &[Entry], compression: Compression, key_codec: KeyCodec) -> Vec<u8> {
+    let raw_len = entries.len() * ENTRY_SIZE;
+    let mut bytes = match compression {
+        Compression::DeltaVarint => {
+            let mut bytes = vec![DELTA_VARINT_PAGE];
+            push_varint(&mut bytes, entries.len() as u64);
+            let (mut previous_key, mut previous_value) = (0, 0);
+            for (key, value) in entries {
+                push_varint(&mut bytes, zigzag(key.wrapping_sub(previous_key)));
+                push_varint(&mut bytes, zigzag(value.wrapping_sub(previous_value)));
+                (previous_key, previous_value) = (*key, *value);
+            }
+            bytes
+        }
+    };
+    if bytes.len() > raw_len {
+        //e.g. random keys and values
+        bytes.clear();
+        bytes.push(RAW_PAGE);
+        bytes.extend(serde_entry::serialize(entries, key_codec));
+    }
+    bytes
+}
+
+///Page encoded by encode_page, as an uncompressed run holds it
+fn decode_page(bytes: &[u8], key_codec: KeyCodec) -> Result<PageBuffer, String> {
+    let (tag, encoded) = bytes
+        .split_first()
+        .ok_or_else(|| "the page is empty".to_string())?;
+    match *tag {
+        RAW_PAGE if encoded.len().is_multiple_of(ENTRY_SIZE) => {
+            let mut page = PageBuffer::take(encoded.len());
+            page.copy_from_slice(encoded);
+            Ok(page)
+        }
+        RAW_PAGE => Err(format!(
+            "{} bytes aren't a whole number of entries",
+            encoded.len()
+        )),
+        DELTA_VARINT_PAGE => {
+            let mut position = 0;
+            let num_entries = read_varint(encoded, &mut position)? as usize;
+            //every entry takes at least a byte for its key and one for its value
+            if num_entries > encoded.len() / 2 {
+                return Err(format!(
+                    "{num_entries} entries can't fit in {} bytes",
+                    encoded.len()
+                ));
+            }
+            let mut page = PageBuffer::take(num_entries * ENTRY_SIZE);
+            let (mut key, mut value) = (0i64, 0i64);
+            for entry in page.chunks_exact_mut(ENTRY_SIZE) {
+                key = key.wrapping_add(unzigzag(read_varint(encoded, &mut position)?));
+                value = value.wrapping_add(unzigzag(read_varint(encoded, &mut position)?));
+                entry[..KEY_SIZE].copy_from_slice(&key_codec.encode(key));
+                entry[KEY_SIZE..].copy_from_slice(&value.to_le_bytes());
+            }
+            Ok(page)
+        }
+        tag => Err(format!("unknown page encoding {tag}")),
+    }
+}
+
+///Writes the pages of a run to its file, compressed if its database's runs are (see Storage::compression)
+pub struct RunWriter {
+    file: DbFile,
+    compression: Option<Compression>,
+    key_codec: KeyCodec,
+    num_entries: Size,
+    pending: Vec<u8>, //compressed pages not written yet, less than a page of them between writes
+    written: u64,     //bytes of compressed pages written to the file
+    offsets: Vec<u64>,
+}
+
+impl RunWriter {
+    ///Writer of <file>, with its pages compressed by <compression> (None writes them as they are)
+    pub fn new(file: DbFile, compression: Option<Compression>, key_codec: KeyCodec) -> Self {
+        Self {
+            file,
+            compression,
+            key_codec,
+            num_entries: 0,
+            pending: Vec::new(),
+            written: 0,
+            offsets: Vec::new(),
+        }
+    }
+    ///Append a page of <entries>, every page but the last has to be full
+    pub fn write_page(&mut self, entries: &[Entry]) -> io::Result<()> {
+        self.num_entries += entries.len();
+        let Some(compression) = self.compression else {
+            //only the last page is padded, finish trims it
+            serde_entry::serialize_into_no_resize(&mut self.file, entries, self.key_codec)?;
+            return Ok(());
+        };
+        self.offsets.push(self.written + self.pending.len() as u64);
+        self.pending
+            .extend(encode_page(entries, compression, self.key_codec));
+        //whole pages only, so every write to a direct I/O file is aligned
+        let page_size = system_info::page_size();
+        let full_len = self.pending.len() / page_size * page_size;
+        if full_len > 0 {
+            direct_io::write_all(&mut self.file, &self.pending[..full_len])?;
+            self.pending.drain(..full_len);
+            self.written += full_len as u64;
+        }
+        Ok(())
+    }
+    pub fn num_entries(&self) -> Size {
+        self.num_entries
+    }
+    ///Write what's left of the run (the footer of a compressed one) and give the file its exact length.
+    /// NOTE: nothing can be written after
+    pub fn finish(&mut self) -> io::Result<()> {
+        if self.compression.is_none() {
+            return self
+                .file
+                .set_len(self.num_entries as u64 * ENTRY_SIZE as u64);
+        }
+        let end = self.written + self.pending.len() as u64;
+        let mut offsets = std::mem::take(&mut self.offsets);
+        offsets.push(end);
+        let footer = PageIndex {
+            offsets,
+            num_entries: self.num_entries,
+            key_codec: self.key_codec,
+        }
+        .to_bytes();
+        let unpadded_len = end as usize + footer.len();
+        let padding = (ENTRY_SIZE + FOOTER_REMAINDER - unpadded_len % ENTRY_SIZE) % ENTRY_SIZE;
+        self.pending.resize(self.pending.len() + padding, 0);
+        self.pending.extend(footer);
+        let len = self.written + self.pending.len() as u64;
+        self.pending
+            .resize(write_len(&self.file, self.pending.len()), 0);
+        direct_io::write_all(&mut self.file, &self.pending)?;
+        self.pending.clear();
+        self.file.set_len(len)
+    }
+    pub fn file(&self) -> &DbFile {
+        &self.file
+    }
+    pub fn into_file(self) -> DbFile {
+        self.file
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_page_encoding() {
+        let entries_per_page = system_info::num_entries_per_page();
+        let sequential: Vec<Entry> = (0..entries_per_page as i64).map(|key| (key, 7)).collect();
+        let random: Vec<Entry> = (0..entries_per_page as i64)
+            .map(|key| {
+                let bits = key.wrapping_mul(0x5851_f42d_4c95_7f2d);
+                (bits.rotate_left(17), bits)
+            })
+            .collect();
+        let extremes = [(i64::MIN, i64::MAX), (-1, 0), (i64::MAX, i64::MIN)];
+        for key_codec in [KeyCodec::LittleEndian, KeyCodec::OrderPreserving] {
+            for entries in [&sequential[..], &random, &extremes, &[]] {
+                let bytes = encode_page(entries, Compression::DeltaVarint, key_codec);
+                assert!(bytes.len() <= entries.len() * ENTRY_SIZE + 1);
+                let page = decode_page(&bytes, key_codec).unwrap();
+                assert_eq!(*page, serde_entry::serialize(entries, key_codec)[..]);
+            }
+        }
+        let compressed = encode_page(&sequential, Compression::DeltaVarint, KeyCodec::default());
+        assert_eq!(compressed[0], DELTA_VARINT_PAGE);
+        assert!(compressed.len() * 4 < sequential.len() * ENTRY_SIZE);
+        let random = encode_page(&random, Compression::DeltaVarint, KeyCodec::default());
+        assert_eq!(random[0], RAW_PAGE);
+
+        assert!(decode_page(&compressed[..compressed.len() - 1], KeyCodec::default()).is_err());
+        assert!(decode_page(&[], KeyCodec::default()).is_err());
+        assert!(decode_page(&[9, 0], KeyCodec::default()).is_err());
+    }
+
+    #[test]
+    fn test_footer() {
+        let index = PageIndex {
+            offsets: vec![0, 40, 100],
+            num_entries: 300,
+            key_codec: KeyCodec::OrderPreserving,
+        };
+        assert_eq!(PageIndex::from_bytes(&index.to_bytes()).unwrap(), index);
+        assert!(PageIndex::from_bytes(&index.to_bytes()[8..]).is_err());
+        assert!(PageIndex::from_bytes(&[0; TRAILER_SIZE]).is_err());
+    }
+}
@@ -0,0 +1,134 @@
+//!Expiry sidecar of an SST run: the key and expiry time of every entry that was written with a TTL, sorted by key
+//! and stored in the same fixed size entry format as the run itself. Runs without any TTL entries have no sidecar
+
+use std::{collections::HashMap, io, mem, ops::Range};
+
+use crate::{
+    buffer_pool::BufferPool,
+    file_io::serde_entry::{deserialize_from, serialize_into},
+    util::{
+        filename,
+        types::{Key, KeyCodec, LevelAddress, Run, RunAddress, Size, Timestamp, Value},
+    },
+};
+
+use super::sst_util::search_sidecar;
+
+///Whether an entry with <expiry> should be treated as absent at time <now>
+pub fn is_expired(expiry: Option<Timestamp>, now: Timestamp) -> bool {
+    expiry.is_some_and(|expiry| expiry <= now)
+}
+
+pub fn write(
+    run_address: &RunAddress,
+    expiries: &[(Key, Timestamp)],
+    key_codec: KeyCodec,
+) -> io::Result<()> {
+    let entries: Vec<(Key, Value)> = expiries
+        .iter()
+        .map(|(key, expiry)| (*key, *expiry as Value))
+        .collect();
+    let (storage, path) = (run_address.0.storage(), filename::expiry_path(run_address));
+    let mut file = storage.create_tmp(&path)?;
+    serialize_into(&mut file, &entries, key_codec)?;
+    storage.publish(file, &path)
+}
+
+///Deserializes an entire sidecar
+pub fn read(run_address: &RunAddress, key_codec: KeyCodec) -> io::Result<Vec<(Key, Timestamp)>> {
+    let mut file = run_address
+        .0
+        .storage()
+        .open_read(&filename::expiry_path(run_address))?;
+    let entries = deserialize_from(&mut file, key_codec)?;
+    Ok(entries
+        .into_iter()
+        .map(|(key, expiry)| (key, expiry as Timestamp))
+        .collect())
+}
+
+///Binary search the sidecar (<num_expiries> entries long) for the expiry of <key>
+pub fn get(
+    run_address: &RunAddress,
+    key: Key,
+    num_expiries: Size,
+    key_codec: KeyCodec,
+    buffer_pool: Option<&mut BufferPool>,
+) -> io::Result<Option<Timestamp>> {
+    let path = filename::expiry_path(run_address);
+    let storage = run_address.0.storage();
+    let expiry = search_sidecar(storage, &path, key, num_expiries, key_codec, buffer_pool)?;
+    Ok(expiry.map(|expiry| expiry as Timestamp))
+}
+
+///Expiries of the input runs of a merge, and of the entries it wrote
+#[derive(Debug, Default)]
+pub struct MergeExpiries {
+    now: Timestamp, //entries that expire at or before this are merged away
+    inputs: Vec<HashMap<Key, Timestamp>>, //indexed by run, runs without a sidecar have no expiries
+    output: Vec<(Key, Timestamp)>,
+}
+
+impl MergeExpiries {
+    ///Read the sidecar of each of <runs> that has one, they're looked up by their position in <runs>.
+    /// NOTE: sidecars are held in memory for the whole merge, they only list entries written with a TTL
+    pub fn read_inputs(
+        level_address: &LevelAddress,
+        runs: impl IntoIterator<Item = Run>,
+        key_codec: KeyCodec,
+        now: Timestamp,
+    ) -> io::Result<Self> {
+        let (db_name, level) = *level_address;
+        let mut inputs = Vec::new();
+        for run in runs {
+            let run_address = &(db_name, level, run);
+            if db_name
+                .storage()
+                .path_exists(&filename::expiry_path(run_address))
+            {
+                inputs.push(read(run_address, key_codec)?.into_iter().collect());
+            } else {
+                inputs.push(HashMap::new());
+            }
+        }
+        Ok(Self {
+            now,
+            inputs,
+            output: Vec::new(),
+        })
+    }
+    ///Take the expiries of the inputs at positions <runs>, for a merge of just those runs
+    pub fn take_inputs(&mut self, runs: Range<usize>) -> Self {
+        Self {
+            now: self.now,
+            inputs: runs
+                .map(|run| self.inputs.get_mut(run).map(mem::take).unwrap_or_default())
+                .collect(),
+            output: Vec::new(),
+        }
+    }
+    ///Expiries of the runs written by <merged>, as the inputs of a merge of those runs (in the same order)
+    pub fn of_outputs(&self, merged: Vec<Self>) -> Self {
+        Self {
+            now: self.now,
+            inputs: merged
+                .into_iter()
+                .map(|merged| merged.output.into_iter().collect())
+                .collect(),
+            output: Vec::new(),
+        }
+    }
+    pub fn expiry(&self, run: Run, key: Key) -> Option<Timestamp> {
+        self.inputs.get(run)?.get(&key).copied()
+    }
+    pub fn is_expired(&self, expiry: Option<Timestamp>) -> bool {
+        is_expired(expiry, self.now)
+    }
+    ///Entries must be recorded in key order
+    pub fn record_output(&mut self, key: Key, expiry: Timestamp) {
+        self.output.push((key, expiry));
+    }
+    pub fn output(&self) -> &[(Key, Timestamp)] {
+        &self.output
+    }
+}
@@ -0,0 +1,160 @@
+//!Reading an SST run's entries straight from its file, for offline tooling that doesn't open the database (nothing
+//! but the run's path and the key codec it was written with is needed). Runs are read a page at a time, in key order
+
+use std::io::{self, Write};
+
+use crate::{
+    file_io::serde_entry::{self, deserialize},
+    sst::compression,
+    util::{
+        filename::RunPaths,
+        types::{Entry, KeyCodec, RunAddress, Size},
+    },
+};
+
+///How export_run_to_writer writes entries
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExportFormat {
+    ///A "key,value" line per entry, the format Database::import_delimited reads with Delimiter::Comma
+    Csv,
+    ///Little endian key then little endian value per entry, whatever key codec the run was written with
+    Raw,
+}
+
+///Call <f> with the entries of each page of the run at <run_address>, in key order
+fn for_each_page(
+    run_address: &RunAddress,
+    key_codec: KeyCodec,
+    f: &mut dyn FnMut(&[Entry]) -> io::Result<()>,
+) -> io::Result<()> {
+    //not opened for direct I/O, the database's io mode isn't registered and a partial last page is read as is
+    let mut file = run_address
+        .0
+        .storage()
+        .open_read_buffered(&run_address.path_sst())?;
+    let index = compression::read_index(&mut file)?;
+    for page_index in 0.. {
+        let buffer = compression::read_page(&mut file, index.as_ref(), page_index)?;
+        if buffer.is_empty() {
+            break;
+        }
+        let entries = deserialize(&buffer, key_codec).map_err(|why| {
+            let (_, level, run) = *run_address;
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("page {page_index} of run {run} of level {level} is cut short, {why}"),
+            )
+        })?;
+        f(&entries)?;
+    }
+    Ok(())
+}
+
+///Every entry of the run at <run_address> (tombstones included), in key order
+pub fn export_run(run_address: &RunAddress, key_codec: KeyCodec) -> io::Result<Vec<Entry>> {
+    let mut entries = Vec::new();
+    for_each_page(run_address, key_codec, &mut |page| {
+        entries.extend_from_slice(page);
+        Ok(())
+    })?;
+    Ok(entries)
+}
+
+///Write every entry of the run at <run_address> (tombstones included) to <writer> in key order, a page at a time.
+/// Returns the number of entries written
+pub fn export_run_to_writer(
+    run_address: &RunAddress,
+    key_codec: KeyCodec,
+    format: ExportFormat,
+    writer: &mut dyn Write,
+) -> io::Result<Size> {
+    let mut num_entries = 0;
+    for_each_page(run_address, key_codec, &mut |page| {
+        match format {
+            ExportFormat::Csv => {
+                for (key, value) in page {
+                    writeln!(writer, "{key},{value}")?;
+                }
+            }
+            ExportFormat::Raw => {
+                writer.write_all(&serde_entry::serialize(page, KeyCodec::LittleEndian))?;
+            }
+        }
+        num_entries += page.len();
+        Ok(())
+    })?;
+    writer.flush()?;
+    Ok(num_entries)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        sst::{array_sst, SortedStringTable},
+        util::{
+            system_info::{num_entries_per_page, ENTRY_SIZE},
+            testing::{self, setup_and_test_and_cleaup},
+            types::{Key, Level},
+        },
+    };
+
+    #[test]
+    fn test_export_run() {
+        let db_name = &testing::test_db_name("export_run_test");
+        const LEVEL: Level = 0;
+        let mut test = || {
+            for key_codec in [KeyCodec::LittleEndian, KeyCodec::OrderPreserving] {
+                let sst = array_sst::Sst::new(key_codec);
+                //empty, exactly one page, and a partial last page
+                let entries_per_page = num_entries_per_page() as Key;
+                for (run, num_entries) in [0, entries_per_page, 2 * entries_per_page + 5]
+                    .into_iter()
+                    .enumerate()
+                {
+                    let run_address = &(db_name, LEVEL, run);
+                    let entries: Vec<Entry> =
+                        (0..num_entries).map(|key| (key * 2 - 7, -key)).collect();
+                    sst.write(run_address, &entries).unwrap();
+                    let expected = sst.read(run_address).unwrap();
+                    assert_eq!(expected, entries);
+                    assert_eq!(export_run(run_address, key_codec).unwrap(), expected);
+
+                    let mut raw = Vec::new();
+                    let written =
+                        export_run_to_writer(run_address, key_codec, ExportFormat::Raw, &mut raw)
+                            .unwrap();
+                    assert_eq!(written, expected.len());
+                    assert_eq!(raw.len(), expected.len() * ENTRY_SIZE);
+                    assert_eq!(deserialize(&raw, KeyCodec::LittleEndian).unwrap(), expected);
+
+                    let mut csv = Vec::new();
+                    export_run_to_writer(run_address, key_codec, ExportFormat::Csv, &mut csv)
+                        .unwrap();
+                    let lines: Vec<Entry> = String::from_utf8(csv)
+                        .unwrap()
+                        .lines()
+                        .map(|line| {
+                            let (key, value) = line.split_once(',').unwrap();
+                            (key.parse().unwrap(), value.parse().unwrap())
+                        })
+                        .collect();
+                    assert_eq!(lines, expected);
+                }
+            }
+
+            //a file that isn't a whole number of entries long
+            let run_address = &(db_name, LEVEL, 3);
+            db_name
+                .storage()
+                .write_file(&run_address.path_sst(), &[0; ENTRY_SIZE + 1])
+                .unwrap();
+            let why = export_run(run_address, KeyCodec::default()).unwrap_err();
+            assert_eq!(why.kind(), io::ErrorKind::InvalidData);
+            assert!(why.to_string().contains("run 3 of level 0"), "{why}");
+            let missing = export_run(&(db_name, LEVEL, 4), KeyCodec::default()).unwrap_err();
+            assert_eq!(missing.kind(), io::ErrorKind::NotFound);
+        };
+        setup_and_test_and_cleaup(&(db_name, LEVEL), &mut test);
+    }
+}
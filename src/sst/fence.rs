@@ -0,0 +1,324 @@
+//!Fence index sidecar of an SST run: the first key of every page of the run, in page order. It finds the page a key
+//! belongs in with a search over keys that are a few pages at most, instead of binary searching the run itself.
+//! Fences are written for the host page size, runs written with another page size are searched without them
+
+use std::{io, ops::Range, path::Path};
+
+use crate::{
+    buffer_pool::BufferPool,
+    file_io::{
+        direct_io, file_interface, page_buffers::PageBuffer, serde_entry::KEY_SIZE,
+        serde_util::write_len, storage::Storage,
+    },
+    util::{
+        filename,
+        system_info::{num_entries_per_page, page_size},
+        types::{Entry, Key, KeyCodec, Page, RunAddress, Size, Value},
+    },
+};
+
+use super::sst_util::{get_entries_at_page, num_entries_at_page, num_pages};
+
+///First key of every page of a run holding <entries>
+#[allow(dead_code)]
+pub fn first_keys(entries: &[Entry]) -> Vec<Key> {
+    entries
+        .chunks(num_entries_per_page())
+        .map(|page| page[0].0)
+        .collect()
+}
+
+pub fn write(run_address: &RunAddress, first_keys: &[Key], key_codec: KeyCodec) -> io::Result<()> {
+    let mut buffer: Vec<u8> = first_keys
+        .iter()
+        .flat_map(|key| key_codec.encode(*key))
+        .collect();
+    let len = buffer.len();
+    let (storage, path) = (run_address.0.storage(), filename::fence_path(run_address));
+    let mut file = storage.create_tmp(&path)?;
+    buffer.resize(write_len(&file, len), 0);
+    direct_io::write_all(&mut file, &buffer)?;
+    file.set_len(len as u64)?;
+    storage.publish(file, &path)
+}
+
+///First key of page <page> of the run, out of the page of the fence index at <path> holding it
+fn decode_fence(
+    path: &Path,
+    fence_page: &[u8],
+    page: Page,
+    key_codec: KeyCodec,
+) -> io::Result<Key> {
+    let start = page % (page_size() / KEY_SIZE) * KEY_SIZE;
+    fence_page
+        .get(start..start + KEY_SIZE)
+        .and_then(|bytes| bytes.try_into().ok())
+        .map(|bytes| key_codec.decode(bytes))
+        .ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("{} has no fence for page {page}", path.display()),
+            )
+        })
+}
+
+///First key of page <page> of the run, read from its fence index
+fn first_key_of(
+    storage: &Storage,
+    path: &Path,
+    page: Page,
+    key_codec: KeyCodec,
+    buffer_pool: Option<&mut BufferPool>,
+) -> io::Result<Key> {
+    let fence_page_index = page / (page_size() / KEY_SIZE);
+    let fence_page = file_interface::get_page(storage, path, fence_page_index, buffer_pool)?;
+    decode_fence(path, &fence_page, page, key_codec)
+}
+
+///Index of the last page whose first key is <= <key>, page 0 if <key> is before every page
+fn page_of(
+    run_address: &RunAddress,
+    key: Key,
+    num_entries: Size,
+    key_codec: KeyCodec,
+    mut buffer_pool: Option<&mut BufferPool>,
+) -> io::Result<Page> {
+    let path = filename::fence_path(run_address);
+    let keys_per_page = page_size() / KEY_SIZE;
+    let mut curr_page_index = Page::MAX;
+    let mut curr_page = PageBuffer::from(Vec::new());
+
+    //partition point of the pages whose first key is <= key
+    let (mut left, mut right) = (0, num_pages(num_entries));
+    while left < right {
+        let middle = (left + right) / 2;
+        let fence_page_index = middle / keys_per_page;
+        if fence_page_index != curr_page_index {
+            curr_page_index = fence_page_index;
+            curr_page = file_interface::get_page(
+                run_address.0.storage(),
+                &path,
+                fence_page_index,
+                buffer_pool.as_deref_mut(),
+            )?;
+        }
+        let first_key = decode_fence(&path, &curr_page, middle, key_codec)?;
+        if first_key <= key {
+            left = middle + 1;
+        } else {
+            right = middle;
+        }
+    }
+    Ok(left.saturating_sub(1))
+}
+
+///SortedStringTable::scan_bounds, reading the (at most 2) pages the bounds are in instead of binary searching
+pub fn scan_bounds(
+    run_address: &RunAddress,
+    key_range: (Key, Key),
+    num_entries: Size,
+    key_codec: KeyCodec,
+    mut buffer_pool: Option<&mut BufferPool>,
+) -> io::Result<Range<Size>> {
+    let (key1, key2) = key_range;
+    if num_entries == 0 {
+        return Ok(0..0);
+    }
+    let entries_per_page = num_entries_per_page();
+    let start_page = page_of(
+        run_address,
+        key1,
+        num_entries,
+        key_codec,
+        buffer_pool.as_deref_mut(),
+    )?;
+    let end_page = page_of(
+        run_address,
+        key2,
+        num_entries,
+        key_codec,
+        buffer_pool.as_deref_mut(),
+    )?;
+    let start_entries = get_entries_at_page(
+        run_address,
+        start_page,
+        num_entries,
+        key_codec,
+        buffer_pool.as_deref_mut(),
+    )?;
+    let start =
+        start_page * entries_per_page + start_entries.partition_point(|(key, _)| *key < key1);
+    let end_entries = if end_page == start_page {
+        start_entries
+    } else {
+        get_entries_at_page(run_address, end_page, num_entries, key_codec, buffer_pool)?
+    };
+    let end = end_page * entries_per_page + end_entries.partition_point(|(key, _)| *key <= key2);
+    Ok(start..end.max(start))
+}
+
+///Estimated number of entries of the run before <key> (or up to it if <inclusive>), from the fence index alone. Keys
+/// are taken to be spread evenly from the first key of <key>'s page to the first key of the next page
+fn estimate_position(
+    run_address: &RunAddress,
+    key: Key,
+    inclusive: bool,
+    num_entries: Size,
+    key_codec: KeyCodec,
+    mut buffer_pool: Option<&mut BufferPool>,
+) -> io::Result<f64> {
+    let (storage, path) = (run_address.0.storage(), filename::fence_path(run_address));
+    let entries_per_page = num_entries_per_page();
+    let page = page_of(
+        run_address,
+        key,
+        num_entries,
+        key_codec,
+        buffer_pool.as_deref_mut(),
+    )?;
+    let first_key = first_key_of(storage, &path, page, key_codec, buffer_pool.as_deref_mut())?;
+    let page_entries = num_entries_at_page(num_entries, page);
+    let key_span = if page + 1 < num_pages(num_entries) {
+        first_key_of(storage, &path, page + 1, key_codec, buffer_pool)? as f64 - first_key as f64
+    } else if page > 0 {
+        //the last page has no next one, its keys are taken to be as dense as the page before it
+        let previous_key = first_key_of(storage, &path, page - 1, key_codec, buffer_pool)?;
+        (first_key as f64 - previous_key as f64) * page_entries as f64 / entries_per_page as f64
+    } else {
+        page_entries as f64 //a single page, its keys are taken to be consecutive
+    };
+    let offset = key as f64 - first_key as f64 + if inclusive { 1.0 } else { 0.0 };
+    let fraction = (offset / key_span.max(1.0)).clamp(0.0, 1.0);
+    Ok((page * entries_per_page) as f64 + fraction * page_entries as f64)
+}
+
+///Estimated number of entries of the run in <key_range> (inclusive), reading its fence index but none of its pages
+pub fn estimate_count(
+    run_address: &RunAddress,
+    key_range: (Key, Key),
+    num_entries: Size,
+    key_codec: KeyCodec,
+    mut buffer_pool: Option<&mut BufferPool>,
+) -> io::Result<Size> {
+    let (key1, key2) = key_range;
+    if num_entries == 0 || key1 > key2 {
+        return Ok(0);
+    }
+    let start = estimate_position(
+        run_address,
+        key1,
+        false,
+        num_entries,
+        key_codec,
+        buffer_pool.as_deref_mut(),
+    )?;
+    let end = estimate_position(run_address, key2, true, num_entries, key_codec, buffer_pool)?;
+    Ok((end - start).max(0.0).round() as Size)
+}
+
+///SortedStringTable::get, reading the one page <key> can be in
+pub fn get(
+    run_address: &RunAddress,
+    key: Key,
+    num_entries: Size,
+    key_codec: KeyCodec,
+    mut buffer_pool: Option<&mut BufferPool>,
+) -> io::Result<Option<Value>> {
+    if num_entries == 0 {
+        return Ok(None);
+    }
+    let page_index = page_of(
+        run_address,
+        key,
+        num_entries,
+        key_codec,
+        buffer_pool.as_deref_mut(),
+    )?;
+    let entries =
+        get_entries_at_page(run_address, page_index, num_entries, key_codec, buffer_pool)?;
+    Ok(entries
+        .binary_search_by_key(&key, |(entry_key, _)| *entry_key)
+        .ok()
+        .map(|index| entries[index].1))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        file_io::io_stats,
+        sst::{array_sst, SortedStringTable},
+        util::{
+            testing::{self, setup_and_test_and_cleaup},
+            types::Level,
+        },
+    };
+
+    #[test]
+    fn test_matches_binary_search() {
+        let db_name = &testing::test_db_name("fence_matches_binary_search");
+        const LEVEL: Level = 0;
+        let mut test = || {
+            let run_address = &(db_name, LEVEL, 0);
+            let entries_per_page = num_entries_per_page();
+            let num_entries = 3 * entries_per_page + 5;
+            let entries: Vec<Entry> = (0..num_entries as Key).map(|i| (i * 2, i)).collect();
+            let sst = array_sst::Sst::default();
+            sst.write(run_address, &entries).unwrap();
+            write(run_address, &first_keys(&entries), KeyCodec::default()).unwrap();
+
+            let boundary = (entries_per_page * 2) as Key; //first key of page 1
+            let last = entries.last().unwrap().0;
+            for key_range in [
+                (3, 9),                       //within a page
+                (boundary - 4, boundary + 4), //across a page boundary
+                (boundary, boundary),
+                (boundary - 1, boundary - 1), //between keys
+                (-100, last + 100),           //the whole run
+                (-100, -1),
+                (last + 1, last + 100),
+                (9, 3),
+            ] {
+                let expected = sst
+                    .scan_bounds(run_address, key_range, num_entries, None)
+                    .unwrap();
+                let bounds = scan_bounds(
+                    run_address,
+                    key_range,
+                    num_entries,
+                    KeyCodec::default(),
+                    None,
+                )
+                .unwrap();
+                assert_eq!(bounds, expected, "{key_range:?}");
+            }
+            for key in [
+                -1,
+                0,
+                1,
+                boundary - 2,
+                boundary,
+                boundary + 1,
+                last,
+                last + 1,
+            ] {
+                let expected = sst.get(run_address, key, num_entries, None).unwrap();
+                let value = get(run_address, key, num_entries, KeyCodec::default(), None).unwrap();
+                assert_eq!(value, expected, "key {key}");
+            }
+
+            //a get only reads the fence page and the page the key is in
+            let baseline = io_stats::snapshot();
+            get(
+                run_address,
+                boundary,
+                num_entries,
+                KeyCodec::default(),
+                None,
+            )
+            .unwrap();
+            assert_eq!(io_stats::snapshot().since(&baseline).pages_read, 2);
+        };
+        setup_and_test_and_cleaup(&(db_name, LEVEL), &mut test);
+    }
+}
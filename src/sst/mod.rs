@@ -1,22 +1,97 @@
 pub mod array_sst;
+#[cfg(feature = "btree")]
 pub mod btree_sst;
+#[cfg(feature = "btree")]
 mod btree_util;
+pub mod compression;
+pub mod expiry;
+#[allow(dead_code)]
+pub mod export;
+pub mod fence;
+pub mod operands;
+mod run_cursor;
 pub mod sst_util;
 
-use std::io;
+#[cfg(test)]
+pub use run_cursor::values_decoded;
+pub use run_cursor::RunCursor;
+
+use std::{io, ops::Range};
 
 use crate::{
     buffer_pool::BufferPool,
+    db::Database,
     util::types::{Entry, Key, LevelAddress, RunAddress, Size, Value},
 };
 
+use self::{expiry::MergeExpiries, operands::MergeOperands};
+
+///Counts from merging SST runs during compaction
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct MergeStats {
+    pub entries_written: Size,
+    pub tombstones_written: Size,
+    pub entries_dropped: Size, //input entries shadowed by younger entries, or tombstones discarded on the last level
+    pub expiries_written: Size, //entries written with an expiry, listed in the run's expiry sidecar
+    pub operands_written: Size, //merge operands with no value under them yet, listed in the run's operand sidecar
+    pub memory_bytes: Size, //held by the merge's input windows, output page and heap (see array_sst::merge_memory_bytes)
+    pub pages_read: Size, //input (and intermediate) SST pages read, a page read in several windows counts once per window
+    pub pages_written: Size, //pages of the merged SST, and of any intermediate runs (see array_sst::merge_group_size)
+}
+
+///Sidecars of the input runs of a merge, and what's recorded for the merged run's sidecars (see
+/// array_sst::Sst::merge_runs)
+#[derive(Debug, Default)]
+pub struct MergeSidecars {
+    pub expiries: MergeExpiries,
+    pub operands: MergeOperands,
+}
+
+impl MergeSidecars {
+    ///Take the sidecars of the inputs at positions <runs>, for a merge of just those runs (a pass of a merge done in
+    /// passes, see array_sst::merge_group_size)
+    pub fn take_inputs(&mut self, runs: Range<usize>) -> Self {
+        Self {
+            expiries: self.expiries.take_inputs(runs.clone()),
+            operands: self.operands.take_inputs(runs),
+        }
+    }
+    ///Sidecars of the runs written by the merges of <merged>, as the inputs of a merge of those runs
+    pub fn of_outputs(&self, merged: Vec<Self>) -> Self {
+        let (expiries, operands) = merged
+            .into_iter()
+            .map(|merged| (merged.expiries, merged.operands))
+            .unzip();
+        Self {
+            expiries: self.expiries.of_outputs(expiries),
+            operands: self.operands.of_outputs(operands),
+        }
+    }
+}
+
 ///Common traits needed for for any sst implementation.
 /// NOTE: this trait is only responsible for a single file, not the entire level or database's files
 pub trait SortedStringTable {
     ///Write entire SST
-    fn write(&self, run_address: &RunAddress, entries: &[Entry]) -> io::Result<()>;
+    #[allow(dead_code)]
+    fn write(&self, run_address: &RunAddress, entries: &[Entry]) -> io::Result<()> {
+        self.write_from_iter(run_address, &mut entries.iter().copied(), &mut |_| Ok(()))?;
+        Ok(())
+    }
+
+    ///Write an SST from entries in key order one page at a time, instead of serializing them all at once like write.
+    /// <on_page_written> gets the entries of each page once it's written, to build other indexes in the same pass,
+    /// an error from it stops the write before the run is published.
+    /// Returns the number of entries written
+    fn write_from_iter(
+        &self,
+        run_address: &RunAddress,
+        entries: &mut dyn Iterator<Item = Entry>,
+        on_page_written: &mut dyn FnMut(&[Entry]) -> io::Result<()>,
+    ) -> io::Result<Size>;
 
     ///Deserializes entire SST
+    #[allow(dead_code)]
     fn read(&self, run_address: &RunAddress) -> io::Result<Vec<Entry>>;
 
     ///Search for specific key
@@ -38,6 +113,7 @@ pub trait SortedStringTable {
     ) -> io::Result<Option<Value>>;
 
     ///Range scan operation. NOTE: key range is inclusive
+    #[allow(dead_code)]
     fn scan(
         &self,
         run_address: &RunAddress,
@@ -47,6 +123,7 @@ pub trait SortedStringTable {
     ) -> io::Result<Vec<Entry>>;
 
     ///Range scan operation using binary search explicitly. NOTE: key range is inclusive
+    #[allow(dead_code)]
     fn binary_search_scan(
         &self,
         run_address: &RunAddress,
@@ -55,15 +132,71 @@ pub trait SortedStringTable {
         buffer_pool: Option<&mut BufferPool>,
     ) -> io::Result<Vec<Entry>>;
 
+    ///Keys of the entries within the key range, tombstones are skipped. NOTE: key range is inclusive
+    fn scan_keys(
+        &self,
+        run_address: &RunAddress,
+        key_range: (Key, Key),
+        num_entries: Size,
+        buffer_pool: Option<&mut BufferPool>,
+    ) -> io::Result<Vec<Key>> {
+        let entries = self.scan(run_address, key_range, num_entries, buffer_pool)?;
+        Ok(entries
+            .into_iter()
+            .filter(|(_, value)| *value != Database::TOMBSTONE_VALUE)
+            .map(|(key, _)| key)
+            .collect())
+    }
+
+    ///Indices of the entries within the key range, for scanning the run one page at a time with a RunCursor.
+    /// NOTE: key range is inclusive
+    fn scan_bounds(
+        &self,
+        run_address: &RunAddress,
+        key_range: (Key, Key),
+        num_entries: Size,
+        buffer_pool: Option<&mut BufferPool>,
+    ) -> io::Result<Range<Size>>;
+
+    ///Indices of the entries within the key range using binary search explicitly. NOTE: key range is inclusive
+    fn binary_search_scan_bounds(
+        &self,
+        run_address: &RunAddress,
+        key_range: (Key, Key),
+        num_entries: Size,
+        buffer_pool: Option<&mut BufferPool>,
+    ) -> io::Result<Range<Size>>;
+
+    ///Entry with the smallest key >= <key>
+    fn ceiling(
+        &self,
+        run_address: &RunAddress,
+        key: Key,
+        num_entries: Size,
+        buffer_pool: Option<&mut BufferPool>,
+    ) -> io::Result<Option<Entry>>;
+
+    ///Entry with the largest key <= <key>
+    fn floor(
+        &self,
+        run_address: &RunAddress,
+        key: Key,
+        num_entries: Size,
+        buffer_pool: Option<&mut BufferPool>,
+    ) -> io::Result<Option<Entry>>;
+
     //Number of entries in SST
+    #[allow(dead_code)]
     fn len(&self, run_address: &RunAddress) -> io::Result<Size>;
 
-    ///Compact all SST runs in a level into a single SST run and update entry_counts to reflect that
+    ///Compact all SST runs in a level into a single SST run and update entry_counts to reflect that.
+    /// Returns None if there was nothing to compact
+    #[allow(dead_code)]
     fn compact(
         &self,
         level_address: &LevelAddress,
         entry_counts: &mut Vec<Size>,
         discard_tombstones: bool,
         buffer_pool: Option<&mut BufferPool>,
-    ) -> io::Result<()>;
+    ) -> io::Result<Option<MergeStats>>;
 }
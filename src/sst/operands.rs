@@ -0,0 +1,154 @@
+//!Merge operand sidecar of an SST run: the keys of entries written by Database::merge whose value is an operand still
+//! waiting for the value under it, rather than a value. Each key is stored with its record type in the same fixed size
+//! entry format as the run itself, sorted by key. Runs without any operands have no sidecar
+
+use std::{
+    collections::HashSet,
+    io::{self, ErrorKind},
+    mem,
+    ops::Range,
+};
+
+use crate::{
+    buffer_pool::BufferPool,
+    file_io::serde_entry::{deserialize_from, serialize_into},
+    util::{
+        filename,
+        types::{Entry, Key, KeyCodec, LevelAddress, MergeOperator, Run, RunAddress, Size, Value},
+    },
+};
+
+use super::sst_util::search_sidecar;
+
+///Kind of an SST entry listed in a sidecar, entries that aren't listed hold a value (or a tombstone)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum RecordType {
+    MergeOperand = 1,
+}
+
+impl RecordType {
+    fn from_value(value: Value) -> io::Result<Self> {
+        match value {
+            1 => Ok(RecordType::MergeOperand),
+            _ => Err(io::Error::new(
+                ErrorKind::InvalidData,
+                format!("{value} is not a known record type"),
+            )),
+        }
+    }
+}
+
+pub fn write(run_address: &RunAddress, keys: &[Key], key_codec: KeyCodec) -> io::Result<()> {
+    let entries: Vec<Entry> = keys
+        .iter()
+        .map(|key| (*key, RecordType::MergeOperand as Value))
+        .collect();
+    let (storage, path) = (
+        run_address.0.storage(),
+        filename::operands_path(run_address),
+    );
+    let mut file = storage.create_tmp(&path)?;
+    serialize_into(&mut file, &entries, key_codec)?;
+    storage.publish(file, &path)
+}
+
+///Keys of every operand in the run, fails with InvalidData on a record type this version doesn't know
+pub fn read(run_address: &RunAddress, key_codec: KeyCodec) -> io::Result<Vec<Key>> {
+    let mut file = run_address
+        .0
+        .storage()
+        .open_read(&filename::operands_path(run_address))?;
+    deserialize_from(&mut file, key_codec)?
+        .into_iter()
+        .map(|(key, record_type)| RecordType::from_value(record_type).map(|_| key))
+        .collect()
+}
+
+///Binary search the sidecar (<num_operands> entries long) for <key>
+pub fn contains(
+    run_address: &RunAddress,
+    key: Key,
+    num_operands: Size,
+    key_codec: KeyCodec,
+    buffer_pool: Option<&mut BufferPool>,
+) -> io::Result<bool> {
+    let path = filename::operands_path(run_address);
+    let storage = run_address.0.storage();
+    match search_sidecar(storage, &path, key, num_operands, key_codec, buffer_pool)? {
+        Some(record_type) => Ok(RecordType::from_value(record_type)? == RecordType::MergeOperand),
+        None => Ok(false),
+    }
+}
+
+///Operands of the input runs of a merge, and the keys of the operands it wrote
+#[derive(Debug, Default)]
+pub struct MergeOperands {
+    operator: MergeOperator,
+    inputs: Vec<HashSet<Key>>, //indexed by run, runs without a sidecar have no operands
+    output: Vec<Key>,
+}
+
+impl MergeOperands {
+    ///Read the sidecar of each of <runs> that has one, they're looked up by their position in <runs>.
+    /// NOTE: sidecars are held in memory for the whole merge, like expiry sidecars
+    pub fn read_inputs(
+        level_address: &LevelAddress,
+        runs: impl IntoIterator<Item = Run>,
+        key_codec: KeyCodec,
+        operator: MergeOperator,
+    ) -> io::Result<Self> {
+        let (db_name, level) = *level_address;
+        let mut inputs = Vec::new();
+        for run in runs {
+            let run_address = &(db_name, level, run);
+            if db_name
+                .storage()
+                .path_exists(&filename::operands_path(run_address))
+            {
+                inputs.push(read(run_address, key_codec)?.into_iter().collect());
+            } else {
+                inputs.push(HashSet::new());
+            }
+        }
+        Ok(Self {
+            operator,
+            inputs,
+            output: Vec::new(),
+        })
+    }
+    ///Take the operands of the inputs at positions <runs>, for a merge of just those runs
+    pub fn take_inputs(&mut self, runs: Range<usize>) -> Self {
+        Self {
+            operator: self.operator,
+            inputs: runs
+                .map(|run| self.inputs.get_mut(run).map(mem::take).unwrap_or_default())
+                .collect(),
+            output: Vec::new(),
+        }
+    }
+    ///Operands of the runs written by <merged>, as the inputs of a merge of those runs (in the same order)
+    pub fn of_outputs(&self, merged: Vec<Self>) -> Self {
+        Self {
+            operator: self.operator,
+            inputs: merged
+                .into_iter()
+                .map(|merged| merged.output.into_iter().collect())
+                .collect(),
+            output: Vec::new(),
+        }
+    }
+    pub fn operator(&self) -> MergeOperator {
+        self.operator
+    }
+    pub fn is_operand(&self, run: Run, key: Key) -> bool {
+        self.inputs.get(run).is_some_and(|keys| keys.contains(&key))
+    }
+    ///Operands must be recorded in key order
+    pub fn record_output(&mut self, key: Key) {
+        self.output.push(key);
+    }
+    pub fn output(&self) -> &[Key] {
+        &self.output
+    }
+}
@@ -0,0 +1,132 @@
+use std::{cell::Cell, io, ops::Range};
+
+use crate::{
+    buffer_pool::BufferPool,
+    db::Database,
+    file_io::{page_buffers::PageBuffer, serde_entry},
+    util::{
+        system_info::num_entries_per_page,
+        types::{Entry, Key, KeyCodec, Page, RunAddress, Size, Timestamp, Value},
+    },
+};
+
+use super::{
+    expiry,
+    sst_util::{check_page_len, get_sst_page},
+};
+
+thread_local! {
+    static VALUES_DECODED: Cell<Size> = const { Cell::new(0) };
+}
+
+///Number of values cursors on this thread have decoded, entries whose key is all that's looked at don't count
+#[cfg(test)]
+pub fn values_decoded() -> Size {
+    VALUES_DECODED.with(Cell::get)
+}
+
+///Lazily walks a range of entries in an SST run (see SortedStringTable::scan_bounds) in either direction.
+/// Pages are only read once the cursor reaches them, so a scan that stops early never reads the rest of the range.
+/// Keys are decoded as the cursor moves (next_key) and the value of the entry it's on only when asked for (value),
+/// so entries hidden by a younger run cost a key decode.
+/// NOTE: the buffer pool is passed on every call instead of being held, so many cursors can share it
+pub struct RunCursor<'a> {
+    run_address: RunAddress<'a>,
+    key_codec: KeyCodec,
+    remaining: Range<Size>, //indices of entries not returned yet
+    reverse: bool,
+    page_index: Option<Page>,
+    page: PageBuffer,                //encoded entries of page <page_index>
+    current: Option<(Size, Key)>, //index within the page and key of the entry last returned by next_key
+    expiries: Vec<(Key, Timestamp)>, //sorted by key, entries that expired by <now> come out as tombstones
+    now: Timestamp,
+    operands: Vec<Key>, //sorted keys of the entries that are merge operands
+}
+
+impl<'a> RunCursor<'a> {
+    pub fn new(
+        run_address: RunAddress<'a>,
+        key_codec: KeyCodec,
+        bounds: Range<Size>,
+        reverse: bool,
+    ) -> Self {
+        Self {
+            run_address,
+            key_codec,
+            remaining: bounds,
+            reverse,
+            page_index: None,
+            page: PageBuffer::from(Vec::new()),
+            current: None,
+            expiries: Vec::new(),
+            now: 0,
+            operands: Vec::new(),
+        }
+    }
+    ///Entries listed in <expiries> (sorted by key) that expired by <now> are returned as tombstones,
+    /// so they still hide older values of their key
+    pub fn with_expiries(mut self, expiries: Vec<(Key, Timestamp)>, now: Timestamp) -> Self {
+        self.expiries = expiries;
+        self.now = now;
+        self
+    }
+    ///Keys (sorted) of the run's merge operands, see is_operand
+    pub fn with_operands(mut self, operands: Vec<Key>) -> Self {
+        self.operands = operands;
+        self
+    }
+    ///Whether the run's entry for <key> is a merge operand rather than a value
+    pub fn is_operand(&self, key: Key) -> bool {
+        self.operands.binary_search(&key).is_ok()
+    }
+    pub fn run_address(&self) -> &RunAddress<'a> {
+        &self.run_address
+    }
+    ///Next entry in ascending key order (descending if reversed), None once the range is exhausted
+    pub fn next(&mut self, buffer_pool: Option<&mut BufferPool>) -> io::Result<Option<Entry>> {
+        Ok(self.next_key(buffer_pool)?.map(|key| (key, self.value())))
+    }
+    ///Move to the next entry like next, but only decode its key, see value
+    pub fn next_key(&mut self, buffer_pool: Option<&mut BufferPool>) -> io::Result<Option<Key>> {
+        let index = if self.reverse {
+            self.remaining.next_back()
+        } else {
+            self.remaining.next()
+        };
+        let Some(index) = index else {
+            self.current = None;
+            return Ok(None);
+        };
+        let entries_per_page = num_entries_per_page();
+        let (page_index, index_within_page) = (index / entries_per_page, index % entries_per_page);
+        if self.page_index != Some(page_index) {
+            self.page = get_sst_page(&self.run_address, page_index, buffer_pool)?;
+            self.page_index = Some(page_index);
+        }
+        check_page_len(
+            &self.run_address,
+            page_index,
+            &self.page,
+            index_within_page + 1,
+        )?;
+        let key =
+            serde_entry::deserialize_key_within_page(&self.page, index_within_page, self.key_codec);
+        self.current = Some((index_within_page, key));
+        Ok(Some(key))
+    }
+    ///Value of the entry last returned by next_key, a tombstone if it expired.
+    /// Panics if next_key hasn't returned an entry
+    pub fn value(&self) -> Value {
+        let (index_within_page, key) = self.current.expect("Cursor isn't on an entry");
+        let expiry = self
+            .expiries
+            .binary_search_by_key(&key, |(expiring_key, _)| *expiring_key)
+            .ok()
+            .map(|index| self.expiries[index].1);
+        if expiry::is_expired(expiry, self.now) {
+            return Database::TOMBSTONE_VALUE;
+        }
+        VALUES_DECODED.with(|count| count.set(count.get() + 1));
+        serde_entry::deserialize_value_within_page(&self.page, index_within_page)
+    }
+}
@@ -1,50 +1,333 @@
+#[cfg(feature = "btree")]
+use crate::util::system_info::{self, PageLayout};
 use crate::{
-    buffer_pool::BufferPool,
+    buffer_pool::{BufferPool, FramePin},
     ceil_div,
-    file_io::{file_interface, serde_entry},
+    db::Database,
+    file_io::{file_interface, page_buffers::PageBuffer, serde_entry, storage::Storage},
     util::{
-        filename,
-        system_info::num_entries_per_page,
-        types::{Entry, Page, RunAddress, Size},
+        filename::RunPaths,
+        system_info::{num_entries_per_page, ENTRY_SIZE},
+        types::{Entry, Key, KeyCodec, Page, RunAddress, Size, Value},
     },
 };
-use std::io;
+use std::{io, ops::Range, path::Path};
+
+use super::compression;
 
 pub fn num_pages(num_entries: Size) -> Size {
     ceil_div!(num_entries, num_entries_per_page())
 }
 
+///Number of entries page <page_index> of a run of <num_entries> entries holds, only the last page can be partial
+pub fn num_entries_at_page(num_entries: Size, page_index: Page) -> Size {
+    let entries_per_page = num_entries_per_page();
+    num_entries
+        .saturating_sub(page_index * entries_per_page)
+        .min(entries_per_page)
+}
+
+///Make sure <page> (page <page_index> of the run at <run_address>) holds at least <min_entries> entries. A page cached
+/// before its file was replaced behind the buffer pool's back can be shorter than the run now is, reading it must fail
+/// rather than silently return fewer entries
+pub fn check_page_len(
+    run_address: &RunAddress,
+    page_index: Page,
+    page: &[u8],
+    min_entries: Size,
+) -> io::Result<()> {
+    if page.len() >= min_entries * ENTRY_SIZE {
+        return Ok(());
+    }
+    let (db_name, level, run) = run_address;
+    Err(io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!(
+            "page {page_index} of db {} level {level} run {run} holds {} bytes, {min_entries} entries need {}",
+            db_name.display(),
+            page.len(),
+            min_entries * ENTRY_SIZE
+        ),
+    ))
+}
+
 pub fn get_sst_page(
     run_address: &RunAddress,
     page_index: Page,
     buffer_pool: Option<&mut BufferPool>,
-) -> io::Result<Vec<u8>> {
-    let path = filename::sst_path(run_address);
-    file_interface::get_page(&path, page_index, buffer_pool)
+) -> io::Result<PageBuffer> {
+    let path = run_address.path_sst();
+    compression::get_page(run_address.0.storage(), &path, page_index, buffer_pool)
+}
+
+///get_sst_page that keeps the page's frame in the buffer pool until <pins> is dropped, for operations that come back
+/// to pages they fetched earlier
+pub fn get_pinned_sst_page(
+    run_address: &RunAddress,
+    page_index: Page,
+    mut buffer_pool: Option<&mut BufferPool>,
+    pins: &mut Vec<FramePin>,
+) -> io::Result<PageBuffer> {
+    let page = get_sst_page(run_address, page_index, buffer_pool.as_deref_mut())?;
+    if let Some(pool) = buffer_pool {
+        pins.extend(pool.pin(&run_address.path_sst(), page_index));
+    }
+    Ok(page)
+}
+
+///Keys of the entries with indices in <bounds> (see SortedStringTable::scan_bounds), tombstones skipped. Pages are
+/// read one at a time and only the keys of the entries in range are decoded
+pub fn keys_in_bounds(
+    run_address: &RunAddress,
+    bounds: Range<Size>,
+    key_codec: KeyCodec,
+    mut buffer_pool: Option<&mut BufferPool>,
+) -> io::Result<Vec<Key>> {
+    let entries_per_page = num_entries_per_page();
+    let mut keys = Vec::new();
+    let mut start = bounds.start;
+    while start < bounds.end {
+        let page_index = start / entries_per_page;
+        let page_start = page_index * entries_per_page;
+        let end = (page_start + entries_per_page).min(bounds.end);
+        let page = get_sst_page(run_address, page_index, buffer_pool.as_deref_mut())?;
+        check_page_len(run_address, page_index, &page, end - page_start)?;
+        let bytes = &page[(start - page_start) * ENTRY_SIZE..(end - page_start) * ENTRY_SIZE];
+        keys.extend(serde_entry::deserialize_keys(
+            bytes,
+            key_codec,
+            Database::TOMBSTONE_VALUE,
+        ));
+        start = end;
+    }
+    Ok(keys)
+}
+
+///Smallest and largest key of a run of <num_entries> entries, read from its first and last entries. None if it's empty
+pub fn key_range(
+    run_address: &RunAddress,
+    num_entries: Size,
+    key_codec: KeyCodec,
+    mut buffer_pool: Option<&mut BufferPool>,
+) -> io::Result<Option<(Key, Key)>> {
+    let Some(last_index) = num_entries.checked_sub(1) else {
+        return Ok(None);
+    };
+    let entries_per_page = num_entries_per_page();
+    let first_page = get_sst_page(run_address, 0, buffer_pool.as_deref_mut())?;
+    let low = serde_entry::try_deserialize_key_within_page(&first_page, 0, key_codec)?;
+    let last_page = match last_index / entries_per_page {
+        0 => first_page,
+        page_index => get_sst_page(run_address, page_index, buffer_pool)?,
+    };
+    let high = serde_entry::try_deserialize_key_within_page(
+        &last_page,
+        last_index % entries_per_page,
+        key_codec,
+    )?;
+    Ok(Some((low, high)))
+}
+
+///Binary search a sidecar file of <num_entries> entries sorted by key (e.g. a run's expiries) for the value of <key>
+pub fn search_sidecar(
+    storage: &Storage,
+    path: &Path,
+    key: Key,
+    num_entries: Size,
+    key_codec: KeyCodec,
+    mut buffer_pool: Option<&mut BufferPool>,
+) -> io::Result<Option<Value>> {
+    let entries_per_page = num_entries_per_page();
+    let mut curr_page_index = usize::MAX;
+    let mut curr_page = PageBuffer::from(Vec::new());
+
+    let (mut left, mut right) = (0, num_entries);
+    while left < right {
+        let middle_index = (left + right) / 2;
+        let page_index = middle_index / entries_per_page;
+        if page_index != curr_page_index {
+            curr_page_index = page_index;
+            curr_page =
+                file_interface::get_page(storage, path, page_index, buffer_pool.as_deref_mut())?;
+        }
+        let (middle_key, value) = serde_entry::deserialize_entry_within_page(
+            &curr_page,
+            middle_index % entries_per_page,
+            key_codec,
+        )?;
+        match middle_key.cmp(&key) {
+            std::cmp::Ordering::Less => left = middle_index + 1,
+            std::cmp::Ordering::Greater => right = middle_index,
+            std::cmp::Ordering::Equal => return Ok(Some(value)),
+        }
+    }
+    Ok(None)
 }
 
-///Get page from bufferpool or through I/O and return the entries in that page
+///Get page from bufferpool or through I/O and return the entries in that page. Fails with InvalidData if the page
+/// doesn't hold as many entries as a run of <num_entries> entries should have there
 pub fn get_entries_at_page(
     run_address: &RunAddress,
     page_index: Page,
+    num_entries: Size,
+    key_codec: KeyCodec,
     buffer_pool: Option<&mut BufferPool>,
 ) -> io::Result<Vec<Entry>> {
     let (db_name, level, run) = run_address;
     let page = get_sst_page(run_address, page_index, buffer_pool)?;
-    let entries = serde_entry::deserialize(&page).unwrap_or_else(|_| {
-        panic!(
-            "Failed to deserialize page {} from db {db_name} level {level} run {run}",
-            page_index
+    let entries = serde_entry::deserialize(&page, key_codec).map_err(|why| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!(
+                "page {page_index} of db {} level {level} run {run} can't be read, {why}",
+                db_name.display()
+            ),
         )
-    });
+    })?;
+    let expected = num_entries_at_page(num_entries, page_index);
+    if entries.len() != expected {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!(
+                "page {page_index} of db {} level {level} run {run} holds {} entries instead of {expected}",
+                db_name.display(),
+                entries.len()
+            ),
+        ));
+    }
     Ok(entries)
 }
 
+///Entries in leaf <page_index> of a B-tree run written with <layout>
+#[cfg(feature = "btree")]
+pub fn get_entries_at_leaf(
+    run_address: &RunAddress,
+    page_index: Page,
+    layout: PageLayout,
+    key_codec: KeyCodec,
+    buffer_pool: Option<&mut BufferPool>,
+) -> io::Result<Vec<Entry>> {
+    let (db_name, level, run) = run_address;
+    //compressed runs are only written with the host's page size
+    let page = match layout.page_size() == system_info::page_size() {
+        true => get_sst_page(run_address, page_index, buffer_pool)?,
+        false => file_interface::get_layout_page(
+            run_address.0.storage(),
+            &run_address.path_sst(),
+            page_index,
+            layout,
+            buffer_pool,
+        )?,
+    };
+    serde_entry::deserialize(&page, key_codec).map_err(|why| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!(
+                "leaf {page_index} of db {} level {level} run {run} can't be read, {why}",
+                db_name.display()
+            ),
+        )
+    })
+}
+
+#[cfg(feature = "btree")]
 pub fn get_btree_page(
     run_address: &RunAddress,
     page_index: Page,
+    layout: PageLayout,
     buffer_pool: Option<&mut BufferPool>,
-) -> io::Result<Vec<u8>> {
-    let path = filename::sst_btree_path(run_address);
-    file_interface::get_page(&path, page_index, buffer_pool)
+) -> io::Result<PageBuffer> {
+    let path = run_address.path_btree();
+    file_interface::get_layout_page(
+        run_address.0.storage(),
+        &path,
+        page_index,
+        layout,
+        buffer_pool,
+    )
+}
+
+///get_btree_page that keeps the frames of the host pages the page is read from in the buffer pool until <pins> is
+/// dropped
+#[cfg(feature = "btree")]
+pub fn get_pinned_btree_page(
+    run_address: &RunAddress,
+    page_index: Page,
+    layout: PageLayout,
+    mut buffer_pool: Option<&mut BufferPool>,
+    pins: &mut Vec<FramePin>,
+) -> io::Result<PageBuffer> {
+    let page = get_btree_page(run_address, page_index, layout, buffer_pool.as_deref_mut())?;
+    if let Some(pool) = buffer_pool {
+        let path = run_address.path_btree();
+        for host_page_index in file_interface::layout_host_pages(page_index, layout) {
+            pins.extend(pool.pin(&path, host_page_index));
+        }
+    }
+    Ok(page)
+}
+
+#[cfg(all(test, feature = "buffer-pool"))]
+mod tests {
+    use std::fs;
+
+    use super::*;
+    use crate::{
+        sst::{array_sst, RunCursor, SortedStringTable},
+        util::{
+            system_info,
+            testing::{self, setup_and_test_and_cleaup},
+            types::{DatabaseName, Level},
+        },
+    };
+
+    #[test]
+    fn test_partial_page_replaced_behind_buffer_pool() {
+        //on disk, the run is rewritten the way another process would
+        let db_name = &DatabaseName::new(
+            testing::test_dir("sst_util_partial_page_replaced"),
+            Storage::disk(),
+        );
+        const LEVEL: Level = 0;
+        let mut test = || {
+            let run_address = &(db_name, LEVEL, 0);
+            let entries_per_page = num_entries_per_page();
+            let key_codec = KeyCodec::default();
+            let mut pool = BufferPool::new(1, 4 * system_info::page_size());
+
+            //cache the partial last page of a run
+            let num_entries = entries_per_page + 3;
+            let entries: Vec<Entry> = (0..num_entries as Key).map(|i| (i, i)).collect();
+            array_sst::Sst::default()
+                .write(run_address, &entries)
+                .unwrap();
+            let last_page =
+                get_entries_at_page(run_address, 1, num_entries, key_codec, Some(&mut pool))
+                    .unwrap();
+            assert_eq!(last_page, entries[entries_per_page..]);
+
+            //the run is rewritten larger without the buffer pool knowing
+            let num_entries = 2 * entries_per_page;
+            let entries: Vec<Entry> = (0..num_entries as Key).map(|i| (i, i)).collect();
+            fs::write(
+                run_address.path_sst(),
+                serde_entry::serialize(&entries, key_codec),
+            )
+            .unwrap();
+
+            let why = get_entries_at_page(run_address, 1, num_entries, key_codec, Some(&mut pool))
+                .unwrap_err();
+            assert_eq!(why.kind(), io::ErrorKind::InvalidData);
+            let mut cursor = RunCursor::new(*run_address, key_codec, 0..num_entries, false);
+            let why = (0..num_entries)
+                .find_map(|_| cursor.next(Some(&mut pool)).err())
+                .expect("scan returned entries the cached page doesn't have");
+            assert_eq!(why.kind(), io::ErrorKind::InvalidData);
+
+            //without the stale page it reads fine
+            let page = get_entries_at_page(run_address, 1, num_entries, key_codec, None).unwrap();
+            assert_eq!(page, entries[entries_per_page..]);
+        };
+        setup_and_test_and_cleaup(&(db_name, LEVEL), &mut test);
+    }
 }
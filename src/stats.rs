@@ -0,0 +1,474 @@
+//!Stats of a database as stable, versioned records for tooling that reads them outside of the crate. Every record type
+//! has its own schema version, bumped whenever its fields change, and is written as CSV (after a "#schema_version"
+//! comment line) or as a flat JSON object with a "schema_version" field. Readers check the version before reading the
+//! columns, see parse_csv
+//!NOTE: the writers are hand-rolled, serde is only a dependency with the serde-config feature
+
+use std::{fmt, time::Duration};
+
+use crate::{
+    buffer_pool::BufferPoolStats, disk_usage::DiskUsage, event::DbEvent,
+    file_io::io_stats::IoStats, util::types::Size,
+};
+
+///Starts the first line of a CSV file, followed by the schema version of its records
+pub const SCHEMA_VERSION_PREFIX: &str = "#schema_version ";
+const CSV_SEPARATOR: &str = ", ";
+
+///Value of a field of a record
+#[derive(Debug, Clone, PartialEq)]
+pub enum FieldValue {
+    Count(Size),
+    Number(f64),
+    Text(String),
+}
+
+impl fmt::Display for FieldValue {
+    ///CSV cell, text with a separator or a quote in it is quoted
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Count(count) => write!(f, "{count}"),
+            Self::Number(number) => write!(f, "{number}"),
+            Self::Text(text) if text.contains([',', '"']) => {
+                write!(f, "\"{}\"", text.replace('"', "\"\""))
+            }
+            Self::Text(text) => write!(f, "{text}"),
+        }
+    }
+}
+
+impl FieldValue {
+    fn to_json(&self) -> String {
+        match self {
+            Self::Count(count) => count.to_string(),
+            Self::Number(number) if number.is_finite() => number.to_string(),
+            Self::Number(_) => "null".to_string(), //JSON has no NaN or infinity
+            Self::Text(text) => json_string(text),
+        }
+    }
+}
+
+fn json_string(text: &str) -> String {
+    let mut quoted = String::from("\"");
+    for c in text.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            c if c.is_control() => quoted.push_str(&format!("\\u{:04x}", c as u32)),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+///A type written with a versioned schema
+pub trait Record {
+    ///Bumped whenever FIELDS changes, so readers can tell files of the old and new schema apart
+    const SCHEMA_VERSION: u32;
+    ///Names of the fields in the order values returns them
+    const FIELDS: &'static [&'static str];
+    fn values(&self) -> Vec<FieldValue>;
+}
+
+///Version line, header and one line per record
+pub fn to_csv<R: Record>(records: &[R]) -> String {
+    let mut output = format!(
+        "{SCHEMA_VERSION_PREFIX}{}\n{}\n",
+        R::SCHEMA_VERSION,
+        R::FIELDS.join(CSV_SEPARATOR)
+    );
+    for record in records {
+        let values: Vec<String> = record.values().iter().map(FieldValue::to_string).collect();
+        output.push_str(&values.join(CSV_SEPARATOR));
+        output.push('\n');
+    }
+    output
+}
+
+///Flat object of the fields, after "schema_version"
+pub fn to_json<R: Record>(record: &R) -> String {
+    let mut output = format!("{{\"schema_version\": {}", R::SCHEMA_VERSION);
+    for (name, value) in R::FIELDS.iter().zip(record.values()) {
+        output.push_str(&format!(", {}: {}", json_string(name), value.to_json()));
+    }
+    output.push('}');
+    output
+}
+
+///A CSV file written by to_csv
+#[derive(Debug, Clone, PartialEq)]
+pub struct CsvTable {
+    pub schema_version: u32,
+    pub columns: Vec<String>,
+    pub rows: Vec<Vec<String>>, //every row has a value for each column, blank lines are skipped
+}
+
+impl CsvTable {
+    ///Index of the column named <name>
+    pub fn column(&self, name: &str) -> Result<usize, String> {
+        self.columns
+            .iter()
+            .position(|column| column == name)
+            .ok_or_else(|| format!("missing column \"{name}\""))
+    }
+}
+
+///Cells of a line, trimmed and unquoted
+fn split_csv_line(line: &str) -> Result<Vec<String>, String> {
+    let mut cells = Vec::new();
+    let mut chars = line.chars().peekable();
+    loop {
+        while chars.next_if(|c| *c == ' ').is_some() {}
+        let mut cell = String::new();
+        if chars.next_if_eq(&'"').is_some() {
+            loop {
+                match chars.next() {
+                    Some('"') if chars.next_if_eq(&'"').is_some() => cell.push('"'),
+                    Some('"') => break,
+                    Some(c) => cell.push(c),
+                    None => return Err("unterminated quote".to_string()),
+                }
+            }
+            while chars.next_if(|c| *c == ' ').is_some() {}
+            if !matches!(chars.peek(), None | Some(',')) {
+                return Err("text after a quoted value".to_string());
+            }
+        } else {
+            while let Some(c) = chars.next_if(|c| *c != ',') {
+                cell.push(c);
+            }
+            cell.truncate(cell.trim_end().len());
+        }
+        cells.push(cell);
+        if chars.next().is_none() {
+            return Ok(cells);
+        }
+    }
+}
+
+///Reads the version line, header and rows of a CSV file of any schema version, the caller checks the version
+pub fn parse_csv(text: &str) -> Result<CsvTable, String> {
+    let mut lines = text.lines();
+    let version_line = lines.next().unwrap_or_default();
+    let schema_version = version_line
+        .strip_prefix(SCHEMA_VERSION_PREFIX)
+        .and_then(|version| version.trim().parse::<u32>().ok())
+        .ok_or_else(|| format!("missing schema version line, found \"{version_line}\""))?;
+    let header = lines.next().ok_or("missing header")?;
+    let columns = split_csv_line(header).map_err(|why| format!("line 2: {why}"))?;
+
+    let mut rows = Vec::new();
+    for (line_index, line) in lines.enumerate() {
+        if line.trim().is_empty() {
+            continue;
+        }
+        let line_number = line_index + 3;
+        let row = split_csv_line(line).map_err(|why| format!("line {line_number}: {why}"))?;
+        if row.len() != columns.len() {
+            return Err(format!(
+                "line {line_number}: expected {} fields, found \"{line}\"",
+                columns.len()
+            ));
+        }
+        rows.push(row);
+    }
+    Ok(CsvTable {
+        schema_version,
+        columns,
+        rows,
+    })
+}
+
+impl Record for IoStats {
+    const SCHEMA_VERSION: u32 = 1;
+    const FIELDS: &'static [&'static str] = &[
+        "pages_read",
+        "pages_written",
+        "bloom_probes",
+        "bloom_negatives",
+        "buffer_pool_hits",
+        "buffer_pool_misses",
+        "syncs",
+    ];
+    fn values(&self) -> Vec<FieldValue> {
+        [
+            self.pages_read,
+            self.pages_written,
+            self.bloom_probes,
+            self.bloom_negatives,
+            self.buffer_pool_hits,
+            self.buffer_pool_misses,
+            self.syncs,
+        ]
+        .map(FieldValue::Count)
+        .to_vec()
+    }
+}
+
+///What flushes and compactions cost, summed from a database's events (see Database::set_event_listener). Counts the
+/// background compactor's work too, unlike IoStats
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CompactionStats {
+    pub flushes: Size,
+    pub flush_time: Duration,
+    pub compactions: Size,
+    pub compaction_time: Duration,
+    pub input_entries: Size,
+    pub output_entries: Size,
+    pub pages_read: Size,    //SST pages of the input runs
+    pub pages_written: Size, //SST pages of the merged runs
+    pub runs_moved: Size,
+}
+
+impl CompactionStats {
+    pub fn record(&mut self, event: DbEvent) {
+        match event {
+            DbEvent::MemtableFlush { duration, .. } => {
+                self.flushes += 1;
+                self.flush_time += duration;
+            }
+            DbEvent::CompactionStarted { input_entries, .. } => {
+                self.input_entries += input_entries;
+            }
+            DbEvent::CompactionFinished {
+                output_entries,
+                input_pages_read,
+                output_pages_written,
+                duration,
+                ..
+            } => {
+                self.compactions += 1;
+                self.output_entries += output_entries;
+                self.pages_read += input_pages_read;
+                self.pages_written += output_pages_written;
+                self.compaction_time += duration;
+            }
+            DbEvent::RunsMoved { count, .. } => self.runs_moved += count,
+            DbEvent::WriteSlowed { .. }
+            | DbEvent::WriteStopped { .. }
+            | DbEvent::DurabilityLag { .. }
+            | DbEvent::FilterRepaired { .. }
+            | DbEvent::CloseFailed { .. } => {}
+        }
+    }
+}
+
+impl Record for CompactionStats {
+    const SCHEMA_VERSION: u32 = 1;
+    const FIELDS: &'static [&'static str] = &[
+        "flushes",
+        "flush_ms",
+        "compactions",
+        "compaction_ms",
+        "input_entries",
+        "output_entries",
+        "pages_read",
+        "pages_written",
+        "runs_moved",
+    ];
+    fn values(&self) -> Vec<FieldValue> {
+        let ms = |duration: Duration| FieldValue::Number(duration.as_secs_f64() * 1000.0);
+        vec![
+            FieldValue::Count(self.flushes),
+            ms(self.flush_time),
+            FieldValue::Count(self.compactions),
+            ms(self.compaction_time),
+            FieldValue::Count(self.input_entries),
+            FieldValue::Count(self.output_entries),
+            FieldValue::Count(self.pages_read),
+            FieldValue::Count(self.pages_written),
+            FieldValue::Count(self.runs_moved),
+        ]
+    }
+}
+
+///Snapshot of a database's stats, see Database::stats
+#[derive(Debug, Clone, PartialEq)]
+pub struct DbStats {
+    pub io: IoStats,
+    pub buffer_pool: BufferPoolStats,
+    pub disk: DiskUsage,
+}
+
+impl Record for DbStats {
+    const SCHEMA_VERSION: u32 = 1;
+    const FIELDS: &'static [&'static str] = &[
+        "io_pages_read",
+        "io_pages_written",
+        "io_bloom_probes",
+        "io_bloom_negatives",
+        "io_buffer_pool_hits",
+        "io_buffer_pool_misses",
+        "io_syncs",
+        "buffer_pool_frames",
+        "buffer_pool_bytes",
+        "buffer_pool_capacity_bytes",
+        "disk_bytes",
+        "disk_logical_bytes",
+        "disk_overhead_bytes",
+        "disk_unaccounted_bytes",
+        "space_amplification",
+    ];
+    fn values(&self) -> Vec<FieldValue> {
+        let mut values = self.io.values();
+        values.extend(
+            [
+                self.buffer_pool.frames,
+                self.buffer_pool.bytes,
+                self.buffer_pool.capacity_bytes,
+                self.disk.total_bytes,
+                self.disk.logical_bytes(),
+                self.disk.overhead_bytes,
+                self.disk.unaccounted_bytes,
+            ]
+            .map(FieldValue::Count),
+        );
+        values.push(FieldValue::Number(self.disk.space_amplification()));
+        values
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    ///Name, schema version and fields of every record type. If this fails, the fields of a record changed: bump its
+    /// SCHEMA_VERSION (readers of the old version have to keep working) and then update the entry here
+    const PINNED_SCHEMAS: &[(&str, u32, &[&str])] = &[
+        (
+            "IoStats",
+            1,
+            &[
+                "pages_read",
+                "pages_written",
+                "bloom_probes",
+                "bloom_negatives",
+                "buffer_pool_hits",
+                "buffer_pool_misses",
+                "syncs",
+            ],
+        ),
+        (
+            "CompactionStats",
+            1,
+            &[
+                "flushes",
+                "flush_ms",
+                "compactions",
+                "compaction_ms",
+                "input_entries",
+                "output_entries",
+                "pages_read",
+                "pages_written",
+                "runs_moved",
+            ],
+        ),
+        (
+            "DbStats",
+            1,
+            &[
+                "io_pages_read",
+                "io_pages_written",
+                "io_bloom_probes",
+                "io_bloom_negatives",
+                "io_buffer_pool_hits",
+                "io_buffer_pool_misses",
+                "io_syncs",
+                "buffer_pool_frames",
+                "buffer_pool_bytes",
+                "buffer_pool_capacity_bytes",
+                "disk_bytes",
+                "disk_logical_bytes",
+                "disk_overhead_bytes",
+                "disk_unaccounted_bytes",
+                "space_amplification",
+            ],
+        ),
+    ];
+
+    fn schema<R: Record>(name: &'static str, record: R) -> (&'static str, u32, Vec<&'static str>) {
+        assert_eq!(record.values().len(), R::FIELDS.len(), "{name}");
+        (name, R::SCHEMA_VERSION, R::FIELDS.to_vec())
+    }
+
+    #[test]
+    fn test_schemas_are_pinned() {
+        let db_stats = DbStats {
+            io: IoStats::default(),
+            buffer_pool: BufferPoolStats {
+                frames: 0,
+                bytes: 0,
+                capacity_bytes: 0,
+            },
+            disk: DiskUsage::default(),
+        };
+        let schemas = [
+            schema("IoStats", IoStats::default()),
+            schema("CompactionStats", CompactionStats::default()),
+            schema("DbStats", db_stats),
+        ];
+        for ((name, version, fields), pinned) in schemas.iter().zip(PINNED_SCHEMAS) {
+            assert_eq!(
+                (*name, *version, fields.as_slice()),
+                (pinned.0, pinned.1, pinned.2),
+                "the fields of {name} changed, bump its SCHEMA_VERSION"
+            );
+        }
+        assert_eq!(schemas.len(), PINNED_SCHEMAS.len());
+    }
+
+    #[test]
+    fn test_csv_and_json() {
+        let stats = CompactionStats {
+            flushes: 2,
+            flush_time: Duration::from_micros(1500),
+            compactions: 1,
+            runs_moved: 3,
+            ..Default::default()
+        };
+        let csv = to_csv(&[stats, CompactionStats::default()]);
+        assert_eq!(
+            csv,
+            "#schema_version 1\n\
+             flushes, flush_ms, compactions, compaction_ms, input_entries, output_entries, pages_read, pages_written, \
+             runs_moved\n\
+             2, 1.5, 1, 0, 0, 0, 0, 0, 3\n\
+             0, 0, 0, 0, 0, 0, 0, 0, 0\n"
+        );
+        let table = parse_csv(&csv).unwrap();
+        assert_eq!(table.schema_version, 1);
+        assert_eq!(table.columns, CompactionStats::FIELDS);
+        assert_eq!(table.rows.len(), 2);
+        assert_eq!(table.rows[0][table.column("runs_moved").unwrap()], "3");
+
+        assert_eq!(
+            to_json(&stats),
+            "{\"schema_version\": 1, \"flushes\": 2, \"flush_ms\": 1.5, \"compactions\": 1, \"compaction_ms\": 0, \
+             \"input_entries\": 0, \"output_entries\": 0, \"pages_read\": 0, \"pages_written\": 0, \"runs_moved\": 3}"
+        );
+    }
+
+    #[test]
+    fn test_text_fields() {
+        let text = FieldValue::Text("a, \"b\"".to_string());
+        assert_eq!(text.to_string(), "\"a, \"\"b\"\"\"");
+        assert_eq!(text.to_json(), "\"a, \\\"b\\\"\"");
+        assert_eq!(FieldValue::Number(f64::NAN).to_json(), "null");
+
+        let csv = format!("{SCHEMA_VERSION_PREFIX}3\nname, value\n{text}, 1\n\nplain , 2\n");
+        let table = parse_csv(&csv).unwrap();
+        assert_eq!(table.schema_version, 3);
+        assert_eq!(table.rows, vec![vec!["a, \"b\"", "1"], vec!["plain", "2"]]);
+        assert!(table.column("missing").is_err());
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        assert!(parse_csv("name, value\n").is_err()); //no version
+        assert!(parse_csv("#schema_version 1\n").is_err()); //no header
+        let short_row = parse_csv("#schema_version 1\nname, value\nget\n").unwrap_err();
+        assert!(short_row.contains("line 3"));
+        assert!(parse_csv("#schema_version 1\nname\n\"get\n").is_err());
+    }
+}
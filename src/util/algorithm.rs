@@ -1,28 +1,16 @@
-use crate::ceil_div;
-
-///Binary search: from https://en.wikipedia.org/wiki/Binary_search_algorithm#Alternative_procedure
-pub fn binary_search_entries<K: std::cmp::PartialOrd, V: Clone>(
-    array: &[(K, V)],
-    key: K,
-) -> Option<V> {
-    let (mut left, mut right) = (0, array.len() - 1);
-
-    while left != right {
-        let middle = ceil_div!((left + right), 2);
-        if array[middle].0 > key {
-            right = middle - 1;
-        } else {
-            left = middle;
-        }
-    }
-    if array[left].0 == key {
-        return Some(array[left].1.clone());
-    }
-    None
+///Value of the entry with <key> in <entries> (sorted by key), None if there's no such entry (e.g. <entries> is empty)
+pub fn binary_search_entries<K: Ord, V: Copy>(entries: &[(K, V)], key: K) -> Option<V> {
+    entries
+        .binary_search_by(|(entry_key, _)| entry_key.cmp(&key))
+        .ok()
+        .map(|index| entries[index].1)
 }
 
-///Gets rank of value (number of elements less than value in the array), from https://en.wikipedia.org/wiki/Binary_search_algorithm#Procedure_for_finding_the_leftmost_element
-pub fn binary_search_leftmost<T: std::cmp::PartialOrd>(array: &[T], value: T) -> usize {
+///Index of the first element of <array> (sorted) that isn't less than <value>, None if every element is less (e.g.
+/// <array> is empty). The index is also the rank of <value>, the number of elements less than it, which is
+/// array.len() when None is returned.
+/// From https://en.wikipedia.org/wiki/Binary_search_algorithm#Procedure_for_finding_the_leftmost_element
+pub fn binary_search_leftmost<T: PartialOrd>(array: &[T], value: T) -> Option<usize> {
     let (mut left, mut right) = (0, array.len());
 
     while left < right {
@@ -33,45 +21,84 @@ pub fn binary_search_leftmost<T: std::cmp::PartialOrd>(array: &[T], value: T) ->
             right = middle;
         }
     }
-    left
+    (left < array.len()).then_some(left)
 }
 
-///From: https://en.wikipedia.org/wiki/Binary_search_algorithm#Procedure_for_finding_the_rightmost_element
-pub fn binary_search_rightmost<T: std::cmp::PartialOrd>(array: &[T], value: T) -> i64 {
-    let (mut left, mut right) = (0, array.len() as i64);
+///Index of the last element of <array> (sorted) that isn't greater than <value>, None if every element is greater
+/// (e.g. <array> is empty).
+/// From: https://en.wikipedia.org/wiki/Binary_search_algorithm#Procedure_for_finding_the_rightmost_element
+pub fn binary_search_rightmost<T: PartialOrd>(array: &[T], value: T) -> Option<usize> {
+    let (mut left, mut right) = (0, array.len());
 
     while left < right {
         let middle = (left + right) / 2;
-        if array[middle as usize] > value {
+        if array[middle] > value {
             right = middle;
         } else {
             left = middle + 1;
         }
     }
-    right - 1
+    right.checked_sub(1)
+}
+
+#[test]
+fn test_entries_search() {
+    let entries = [(1, 10), (3, 30), (5, 50), (7, 70), (9, 90)];
+    assert_eq!(binary_search_entries(&entries, 5), Some(50));
+    assert_eq!(binary_search_entries(&entries, 1), Some(10));
+    assert_eq!(binary_search_entries(&entries, 9), Some(90));
+    assert_eq!(binary_search_entries(&entries, 4), None);
+    assert_eq!(binary_search_entries(&entries, 0), None);
+    assert_eq!(binary_search_entries(&entries, 10), None);
+
+    assert_eq!(binary_search_entries::<i64, i64>(&[], 5), None);
+    assert_eq!(binary_search_entries(&[(5, 50)], 5), Some(50));
+    assert_eq!(binary_search_entries(&[(5, 50)], 4), None);
+    assert_eq!(binary_search_entries(&[(5, 50)], 6), None);
+    //a page holds a key once, but a run with duplicates still finds one of them
+    assert_eq!(binary_search_entries(&[(5, 50); 4], 5), Some(50));
+    assert_eq!(binary_search_entries(&[(5, 50); 4], 6), None);
 }
 
 #[test]
 fn test_leftmost_search() {
     let array = [1, 3, 5, 7, 9];
-    assert_eq!(binary_search_leftmost(&array, 5), 2);
-    assert_eq!(binary_search_leftmost(&array, 4), 2);
-    assert_eq!(binary_search_leftmost(&array, 6), 3);
+    assert_eq!(binary_search_leftmost(&array, 5), Some(2));
+    assert_eq!(binary_search_leftmost(&array, 4), Some(2));
+    assert_eq!(binary_search_leftmost(&array, 6), Some(3));
+
+    assert_eq!(binary_search_leftmost(&array, 0), Some(0));
+    assert_eq!(binary_search_leftmost(&array, 9), Some(4));
+    assert_eq!(binary_search_leftmost(&array, 10), None);
 
-    assert_eq!(binary_search_leftmost(&array, 0), 0);
-    assert_eq!(binary_search_leftmost(&array, 10), array.len());
+    assert_eq!(binary_search_leftmost(&[], 5), None);
+    assert_eq!(binary_search_leftmost(&[5], 4), Some(0));
+    assert_eq!(binary_search_leftmost(&[5], 5), Some(0));
+    assert_eq!(binary_search_leftmost(&[5], 6), None);
+    assert_eq!(binary_search_leftmost(&[5; 4], 5), Some(0));
+    assert_eq!(binary_search_leftmost(&[5; 4], 4), Some(0));
+    assert_eq!(binary_search_leftmost(&[5; 4], 6), None);
 }
 
 #[test]
 fn test_rightmost_search() {
     let array = [1, 3, 5, 7, 9];
-    assert_eq!(binary_search_rightmost(&array, 5), 2);
-    assert_eq!(binary_search_rightmost(&array, 4), 1);
-    assert_eq!(binary_search_rightmost(&array, 6), 2);
+    assert_eq!(binary_search_rightmost(&array, 5), Some(2));
+    assert_eq!(binary_search_rightmost(&array, 4), Some(1));
+    assert_eq!(binary_search_rightmost(&array, 6), Some(2));
+
+    assert_eq!(binary_search_rightmost(&array, 0), None);
+    assert_eq!(binary_search_rightmost(&array, -20), None);
+    assert_eq!(binary_search_rightmost(&array, 1), Some(0));
 
-    assert_eq!(binary_search_rightmost(&array, 0), -1);
-    assert_eq!(binary_search_rightmost(&array, -20), -1);
+    assert_eq!(binary_search_rightmost(&array, 10), Some(array.len() - 1));
+    assert_eq!(binary_search_rightmost(&array, 20), Some(array.len() - 1));
 
-    assert_eq!(binary_search_rightmost(&array, 10), array.len() as i64 - 1);
-    assert_eq!(binary_search_rightmost(&array, 20), array.len() as i64 - 1);
+    assert_eq!(binary_search_rightmost(&[], 5), None);
+    assert_eq!(binary_search_rightmost(&[5], 4), None);
+    assert_eq!(binary_search_rightmost(&[5], 5), Some(0));
+    assert_eq!(binary_search_rightmost(&[5], 6), Some(0));
+    assert_eq!(binary_search_rightmost(&[5; 4], 5), Some(3));
+    assert_eq!(binary_search_rightmost(&[5; 4], 4), None);
+    assert_eq!(binary_search_rightmost(&[5; 4], 6), Some(3));
 }
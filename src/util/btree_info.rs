@@ -1,5 +1,5 @@
 use super::{
-    system_info::page_size,
+    system_info::PageLayout,
     types::{Key, Page},
 };
 
@@ -8,10 +8,17 @@ pub const NODE_ELEMENT_SIZE: usize = std::mem::size_of::<Key>();
 
 pub const ROOT_PAGE_OFFSET: Page = 0;
 
-pub fn node_size() -> usize {
-    page_size()
+impl PageLayout {
+    ///Size of a B-tree node in a run written with this layout
+    pub fn node_size(&self) -> usize {
+        self.page_size()
+    }
+    pub fn fanout(&self) -> usize {
+        self.node_size() / NODE_ELEMENT_SIZE
+    }
 }
 
+#[allow(dead_code)]
 pub fn fanout() -> usize {
-    node_size() / NODE_ELEMENT_SIZE
+    PageLayout::host().fanout()
 }
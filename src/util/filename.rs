@@ -1,16 +1,54 @@
-use crate::util::types::{Level, LevelAddress, Run, RunAddress};
+use std::path::{Path, PathBuf};
+
+use crate::util::types::{LevelAddress, Run, RunAddress, Size};
 
-const FILE_SEPARATOR: char = '/';
 pub const SST_FILE_EXTENSION: &str = "sst";
 pub const BTREE_FILE_EXTENSION: &str = "btree";
 pub const BLOOM_FILTER_FILE_EXTENSION: &str = "bloom";
+pub const EXPIRY_FILE_EXTENSION: &str = "ttl";
+pub const FENCE_FILE_EXTENSION: &str = "fence";
+pub const OPERANDS_FILE_EXTENSION: &str = "operands";
+pub const TMP_FILE_EXTENSION: &str = "tmp";
 
-//Responsible for all filename conversions
-pub fn config(db_name: &str) -> String {
-    String::from(db_name) + "/config.bin"
+//Responsible for all filename conversions. Paths are joined with the platform's separator, the database's root is
+// used as given (it doesn't have to be valid UTF-8)
+pub fn config(db_name: &Path) -> PathBuf {
+    db_name.join("config.bin")
+}
+pub fn metadata(db_name: &Path) -> PathBuf {
+    db_name.join("meta.bin")
+}
+///Descriptor of the read cache, see ReadCache::open
+pub fn read_cache(db_name: &Path) -> PathBuf {
+    db_name.join("read_cache.bin")
+}
+///Holds the read cache's run, laid out like a database's levels
+pub fn read_cache_directory(db_name: &Path) -> PathBuf {
+    db_name.join("read_cache")
+}
+pub fn maintenance(db_name: &Path) -> PathBuf {
+    db_name.join("maintenance.bin")
+}
+pub fn buffer_pool_warm(db_name: &Path) -> PathBuf {
+    db_name.join("bufferpool.warm")
+}
+///Exists while a truncate is removing the runs, see Database::truncate
+pub fn truncate_marker(db_name: &Path) -> PathBuf {
+    db_name.join("truncating")
+}
+///Exists while the files of a rewritten run are swapped in, see Database::rewrite_run
+pub fn rewrite_marker(db_name: &Path) -> PathBuf {
+    db_name.join("rewriting")
 }
-pub fn metadata(db_name: &str) -> String {
-    String::from(db_name) + "/meta.bin"
+pub fn import_directory(db_name: &Path) -> PathBuf {
+    db_name.join("import")
+}
+///Holds a directory per live snapshot, each laid out like a database's root, see Database::snapshot_view
+pub fn snapshots_directory(db_name: &Path) -> PathBuf {
+    db_name.join("snapshots")
+}
+pub fn snapshot_directory(db_name: &Path, id: usize) -> PathBuf {
+    snapshots_directory(db_name).join(id.to_string())
 }
 pub fn sst(run: Run) -> String {
     run.to_string() + "." + SST_FILE_EXTENSION
@@ -21,34 +59,108 @@ pub fn sst_btree(run: Run) -> String {
 pub fn bloom_filter(run: Run) -> String {
     run.to_string() + "." + BLOOM_FILTER_FILE_EXTENSION
 }
+pub fn expiry(run: Run) -> String {
+    run.to_string() + "." + EXPIRY_FILE_EXTENSION
+}
+pub fn fence(run: Run) -> String {
+    run.to_string() + "." + FENCE_FILE_EXTENSION
+}
+pub fn operands(run: Run) -> String {
+    run.to_string() + "." + OPERANDS_FILE_EXTENSION
+}
 pub fn sst_compaction() -> String {
     "compaction.bin".to_string()
 }
 
-pub fn lsm_level_directory(level_address: &LevelAddress) -> String {
+pub fn lsm_level_directory(level_address: &LevelAddress) -> PathBuf {
     let (db_name, level) = level_address;
-    format!("{db_name}{0}{level}{0}", FILE_SEPARATOR)
-}
-pub fn sst_path(run_address: &RunAddress) -> String {
-    let (db_name, level, run) = run_address;
-    format!("{db_name}{0}{level}{0}{1}", FILE_SEPARATOR, sst(*run))
-}
-pub fn sst_btree_path(run_address: &RunAddress) -> String {
-    let (db_name, level, run) = run_address;
-    format!("{db_name}{0}{level}{0}{1}", FILE_SEPARATOR, sst_btree(*run))
-}
-pub fn bloom_filter_path(run_address: &RunAddress) -> String {
-    let (db_name, level, run) = run_address;
-    format!(
-        "{db_name}{0}{level}{0}{1}",
-        FILE_SEPARATOR,
-        bloom_filter(*run)
-    )
-}
-pub fn sst_compaction_path(db_name: &str, level: Level) -> String {
-    format!(
-        "{db_name}{0}{level}{0}{1}",
-        FILE_SEPARATOR,
-        sst_compaction()
-    )
+    db_name.join(level.to_string())
+}
+fn run_file_path(run_address: &RunAddress, filename: String) -> PathBuf {
+    let (db_name, level, _) = run_address;
+    lsm_level_directory(&(db_name, *level)).join(filename)
+}
+///Where a file is written before it's published at <path>, e.g. `3.sst.tmp` for `3.sst` (see Storage::publish)
+pub fn tmp_path(path: &Path) -> PathBuf {
+    let mut tmp_path = path.as_os_str().to_os_string();
+    tmp_path.push(".");
+    tmp_path.push(TMP_FILE_EXTENSION);
+    PathBuf::from(tmp_path)
+}
+pub fn is_tmp_path(path: &Path) -> bool {
+    path.extension()
+        .is_some_and(|extension| extension == TMP_FILE_EXTENSION)
+}
+///Where a run's new bloom filter is built before it replaces the old one
+pub fn bloom_filter_rebuild_path(run_address: &RunAddress) -> PathBuf {
+    run_file_path(run_address, bloom_filter(run_address.2) + ".rebuild")
+}
+pub fn expiry_path(run_address: &RunAddress) -> PathBuf {
+    run_file_path(run_address, expiry(run_address.2))
+}
+pub fn fence_path(run_address: &RunAddress) -> PathBuf {
+    run_file_path(run_address, fence(run_address.2))
+}
+pub fn operands_path(run_address: &RunAddress) -> PathBuf {
+    run_file_path(run_address, operands(run_address.2))
+}
+pub fn sst_compaction_path(level_address: &LevelAddress) -> PathBuf {
+    lsm_level_directory(level_address).join(sst_compaction())
+}
+///Run written by group <group> of pass <pass> of a merge done in passes, a temporary file so one a crash leaves behind
+/// is removed by the next open
+pub fn sst_compaction_group_path(level_address: &LevelAddress, pass: Size, group: Size) -> PathBuf {
+    let path =
+        lsm_level_directory(level_address).join(format!("{}.{pass}.{group}", sst_compaction()));
+    tmp_path(&path)
+}
+
+///Paths of a run's main files, e.g. `(db_name, level, run).path_sst()`
+pub trait RunPaths {
+    fn path_sst(&self) -> PathBuf;
+    fn path_btree(&self) -> PathBuf;
+    fn path_bloom(&self) -> PathBuf;
+}
+
+impl RunPaths for RunAddress<'_> {
+    fn path_sst(&self) -> PathBuf {
+        run_file_path(self, sst(self.2))
+    }
+    fn path_btree(&self) -> PathBuf {
+        run_file_path(self, sst_btree(self.2))
+    }
+    fn path_bloom(&self) -> PathBuf {
+        run_file_path(self, bloom_filter(self.2))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use super::*;
+    use crate::{file_io::storage::Storage, util::types::DatabaseName};
+
+    #[test]
+    fn test_run_paths() {
+        let db_name = &DatabaseName::new("db", Storage::in_memory());
+        let run_address = (db_name, 2, 3);
+        let level_directory = lsm_level_directory(&(db_name, 2));
+        assert_eq!(level_directory, Path::new("db").join("2"));
+        assert_eq!(run_address.path_sst(), level_directory.join("3.sst"));
+        assert_eq!(run_address.path_btree(), level_directory.join("3.btree"));
+        assert_eq!(run_address.path_bloom(), level_directory.join("3.bloom"));
+        assert_eq!(
+            sst_compaction_path(&(db_name, 2)),
+            level_directory.join("compaction.bin")
+        );
+        let group_path = sst_compaction_group_path(&(db_name, 2), 1, 4);
+        assert_eq!(group_path, level_directory.join("compaction.bin.1.4.tmp"));
+        assert!(is_tmp_path(&group_path));
+        let tmp_sst_path = tmp_path(&run_address.path_sst());
+        assert_eq!(tmp_sst_path, level_directory.join("3.sst.tmp"));
+        assert!(is_tmp_path(&tmp_sst_path));
+        assert!(!is_tmp_path(&run_address.path_sst()));
+        assert!(!is_tmp_path(Path::new("db").join("tmp").as_path()));
+    }
 }
@@ -1,10 +1,14 @@
-use super::types::Key;
+#[cfg(feature = "bloom")]
+use super::types::{Key, KeyCodec};
 
+#[cfg(feature = "buffer-pool")]
 pub type FastHasher = twox_hash::XxHash;
 
+#[cfg(feature = "bloom")]
 #[derive(Debug)]
 pub struct BloomHasher {}
 
+#[cfg(feature = "bloom")]
 impl BloomHasher {
     pub fn hash(bytes: &[u8], seed: u64) -> u64 {
         //twox_hash::xxh3::hash64_with_seed(bytes, seed)//NOTE: this function won't have a consistent hash across sessions, so we have to use another lib to do this
@@ -13,10 +17,11 @@ impl BloomHasher {
     pub fn hash_to_index<T: AsRef<[u8]>>(item: T, seed: u64, arr_len: usize) -> usize {
         Self::hash(item.as_ref(), seed) as usize % arr_len
     }
-    pub fn hash_key(key: Key, seed: u64) -> u64 {
-        Self::hash(&key.to_le_bytes(), seed)
+    ///Hashes the on disk representation of a key
+    pub fn hash_key(key: Key, seed: u64, codec: KeyCodec) -> u64 {
+        Self::hash(&codec.encode(key), seed)
     }
-    pub fn hash_key_to_index(key: Key, seed: u64, arr_len: usize) -> usize {
-        Self::hash_key(key, seed) as usize % arr_len
+    pub fn hash_key_to_index(key: Key, seed: u64, arr_len: usize, codec: KeyCodec) -> usize {
+        Self::hash_key(key, seed, codec) as usize % arr_len
     }
 }
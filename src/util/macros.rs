@@ -2,6 +2,6 @@
 ///Cheap integer ceil division: ceil(a/b)
 macro_rules! ceil_div {
     ($numerator:expr, $denominator:expr) => {
-        ($numerator + $denominator - 1) / $denominator
+        ($numerator).div_ceil($denominator)
     };
 }
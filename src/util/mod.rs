@@ -1,8 +1,12 @@
+#[cfg(feature = "btree")]
 pub mod algorithm;
+#[cfg(feature = "btree")]
 pub mod btree_info;
+pub mod clock;
 pub mod filename;
 pub mod hash;
 pub mod macros;
 pub mod system_info;
 pub mod testing;
 pub mod types;
+pub mod yield_hook;
@@ -1,16 +1,125 @@
+use std::sync::OnceLock;
+
 use super::types::{Key, Value};
 
 pub const ENTRY_SIZE: usize = std::mem::size_of::<Key>() + std::mem::size_of::<Value>();
 
+///Page size used when the system reports something we can't work with
+pub const DEFAULT_PAGE_SIZE: usize = 4096;
+const MIN_PAGE_SIZE: usize = 512;
+const MAX_PAGE_SIZE: usize = 64 * 1024;
+
+///Page size in use and whether it's DEFAULT_PAGE_SIZE because the system reported an unusable one
+static PAGE_SIZE: OnceLock<(usize, bool)> = OnceLock::new();
+
+fn page_size_info() -> (usize, bool) {
+    *PAGE_SIZE.get_or_init(|| match validate_page_size(system_page_size()) {
+        Some(page_size) => (page_size, false),
+        None => (DEFAULT_PAGE_SIZE, true),
+    })
+}
+
+///System page size, queried once and cached (hot paths call this a lot)
 pub fn page_size() -> usize {
+    page_size_info().0
+}
+
+///Whether the system reported a page size that isn't a power of two within [512 B, 64 KiB], so page_size fell back
+/// to DEFAULT_PAGE_SIZE (callers that care can warn about it, this module doesn't print anything)
+pub fn page_size_was_defaulted() -> bool {
+    page_size_info().1
+}
+
+#[cfg(feature = "direct-io")]
+fn system_page_size() -> usize {
     //NOTE: using a libary for this might be overkill, maybe just fix value to 4k isntead (consider this later)
     page_size::get()
 }
 
+///Direct I/O is what ties us to the system page size, without it any reasonable size works
+#[cfg(not(feature = "direct-io"))]
+fn system_page_size() -> usize {
+    DEFAULT_PAGE_SIZE
+}
+
+///None if the reported page size is not a power of two within [512 B, 64 KiB]
+fn validate_page_size(reported: usize) -> Option<usize> {
+    (reported.is_power_of_two() && (MIN_PAGE_SIZE..=MAX_PAGE_SIZE).contains(&reported))
+        .then_some(reported)
+}
+
 pub fn num_entries_per_page() -> usize {
     page_size() / ENTRY_SIZE
 }
 
+///Page size a run was written with. Entry, bloom filter and expiry files are flat arrays that read the same under any
+/// page size, but B-tree files are made of page sized nodes over one leaf per page of entries, so a run copied from a
+/// machine with a different page size has to be navigated with the layout it was written with
+#[cfg_attr(not(feature = "btree"), allow(dead_code))]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PageLayout {
+    page_size: usize,
+}
+
+#[cfg_attr(not(feature = "btree"), allow(dead_code))]
+impl PageLayout {
+    ///Layout of runs written on this machine
+    pub fn host() -> Self {
+        Self::new(page_size())
+    }
+    pub fn new(page_size: usize) -> Self {
+        assert!(
+            page_size.is_power_of_two() && page_size >= ENTRY_SIZE,
+            "Invalid page size for a run layout: {page_size}"
+        );
+        Self { page_size }
+    }
+    pub fn page_size(&self) -> usize {
+        self.page_size
+    }
+    pub fn entries_per_page(&self) -> usize {
+        self.page_size / ENTRY_SIZE
+    }
+}
+
+impl Default for PageLayout {
+    fn default() -> Self {
+        Self::host()
+    }
+}
+
 pub fn mimimum_write_size() -> usize {
     page_size() / 8 //TODO: test on various systems, may need to change based on OS or system
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_validate_page_size() {
+        assert_eq!(validate_page_size(4096), Some(4096));
+        assert_eq!(validate_page_size(MIN_PAGE_SIZE), Some(MIN_PAGE_SIZE));
+        assert_eq!(validate_page_size(MAX_PAGE_SIZE), Some(MAX_PAGE_SIZE));
+        assert_eq!(validate_page_size(16384), Some(16384));
+
+        //fallbacks
+        assert_eq!(validate_page_size(0), None);
+        assert_eq!(validate_page_size(3000), None); //not a power of two
+        assert_eq!(validate_page_size(MIN_PAGE_SIZE / 2), None);
+        assert_eq!(validate_page_size(MAX_PAGE_SIZE * 2), None);
+        assert_eq!(validate_page_size(usize::MAX), None);
+    }
+
+    #[test]
+    fn test_page_size_is_cached() {
+        let page_size = page_size();
+        assert!(page_size.is_power_of_two());
+        assert!((MIN_PAGE_SIZE..=MAX_PAGE_SIZE).contains(&page_size));
+        assert_eq!(
+            PAGE_SIZE.get(),
+            Some(&(page_size, page_size_was_defaulted()))
+        );
+        assert_eq!(num_entries_per_page() * ENTRY_SIZE, page_size);
+    }
+}
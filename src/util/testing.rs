@@ -1,21 +1,41 @@
-use crate::db::Database;
+use std::path::Path;
+
+use crate::{db::Database, file_io::storage::Storage};
 
 use super::{
     filename,
-    types::{CompactionPolicy, LevelAddress, SstImplementation, SstSearchAlgorithm},
+    types::{CompactionPolicy, DatabaseName, LevelAddress, SstImplementation, SstSearchAlgorithm},
 };
 
+///Directory for a test's files under the system's temp directory, so tests never write into the working directory.
+/// Each test process gets its own, so runs of the tests at the same time don't share files
+#[allow(dead_code)]
+pub fn test_dir(name: impl AsRef<Path>) -> &'static Path {
+    let process_dir = format!("key_value_tests_{}", std::process::id());
+    let path = std::env::temp_dir().join(process_dir).join(name);
+    Box::leak(path.into_boxed_path())
+}
+
+///Root of a test's database with its files kept in memory (freed when it's dropped), named like test_dir
+#[allow(dead_code)]
+pub fn test_db_name(name: impl AsRef<Path>) -> DatabaseName {
+    DatabaseName::new(test_dir(name), Storage::in_memory())
+}
+
 #[allow(dead_code)]
 pub fn setup_and_test_and_cleaup(level_address: &LevelAddress, test: &mut dyn FnMut()) {
-    let dir = &filename::lsm_level_directory(level_address);
-    if std::path::Path::new(dir).exists() {
-        std::fs::remove_dir_all(dir).unwrap(); //remove previous directory if panicked during tests and didn't clean up
+    let (storage, dir) = (
+        level_address.0.storage(),
+        &filename::lsm_level_directory(level_address),
+    );
+    if storage.path_exists(dir) {
+        storage.remove_dir_all(dir).unwrap(); //remove previous directory if panicked during tests and didn't clean up
     }
-    std::fs::create_dir_all(dir).unwrap();
+    storage.create_dir_all(dir).unwrap();
 
     test();
 
-    std::fs::remove_dir_all(level_address.0).unwrap();
+    storage.remove_dir_all(level_address.0).unwrap();
 }
 
 #[allow(dead_code)]
@@ -25,7 +45,7 @@ pub fn part1_db_alterations(db: Database) -> Database {
         .set_sst_implementation(SstImplementation::Array)
         .set_sst_search_algorithm(SstSearchAlgorithm::Default)
         .set_enable_buffer_pool(false)
-        .set_buffer_pool_capacity(1)
+        .set_buffer_pool_capacity_pages(1)
         .set_buffer_pool_initial_size(1)
         .set_enable_bloom_filter(false)
         .set_bloom_filter_bits_per_entry(1)
@@ -38,7 +58,7 @@ pub fn part2_db_alterations(db: Database) -> Database {
         .set_sst_implementation(SstImplementation::Btree)
         .set_sst_search_algorithm(SstSearchAlgorithm::Default)
         .set_enable_buffer_pool(true)
-        .set_buffer_pool_capacity(10)
+        .set_buffer_pool_capacity_pages(10)
         .set_buffer_pool_initial_size(4)
         .set_enable_bloom_filter(false)
         .set_bloom_filter_bits_per_entry(1)
@@ -51,7 +71,7 @@ pub fn part3_db_alterations(db: Database) -> Database {
         .set_sst_implementation(SstImplementation::Btree)
         .set_sst_search_algorithm(SstSearchAlgorithm::Default)
         .set_enable_buffer_pool(true)
-        .set_buffer_pool_capacity(10)
+        .set_buffer_pool_capacity_pages(10)
         .set_buffer_pool_initial_size(4)
         .set_enable_bloom_filter(true)
         .set_bloom_filter_bits_per_entry(5)
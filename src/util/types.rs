@@ -1,5 +1,14 @@
+use std::{
+    ops::Deref,
+    path::{Path, PathBuf},
+};
+
+#[cfg(feature = "serde-config")]
 use serde::{Deserialize, Serialize};
 
+pub use crate::file_io::serde_entry::KeyCodec;
+use crate::file_io::storage::Storage;
+
 pub type Key = i64;
 pub type Value = i64;
 pub type Entry = (Key, Value);
@@ -9,36 +18,202 @@ pub type Size = usize; //for lengths and capacities
 pub type Level = usize;
 ///Run number within LSM level
 pub type Run = usize;
-///Name of database
-pub type DatabaseName = str;
+///Root directory of a database, along with the storage its files are kept in (dereferences to the directory)
+#[derive(Debug, Clone)]
+pub struct DatabaseName {
+    path: PathBuf,
+    storage: Storage,
+}
+
+impl DatabaseName {
+    pub fn new(path: impl Into<PathBuf>, storage: Storage) -> Self {
+        Self {
+            path: path.into(),
+            storage,
+        }
+    }
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+    pub fn storage(&self) -> &Storage {
+        &self.storage
+    }
+    pub(crate) fn storage_mut(&mut self) -> &mut Storage {
+        &mut self.storage
+    }
+}
+
+impl Deref for DatabaseName {
+    type Target = Path;
+    fn deref(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl AsRef<Path> for DatabaseName {
+    fn as_ref(&self) -> &Path {
+        &self.path
+    }
+}
 ///Used to identify an LSM level within a specific database
 pub type LevelAddress<'a> = (&'a DatabaseName, Level);
 ///Used to identify an SST run within a specific database
 pub type RunAddress<'a> = (&'a DatabaseName, Level, Run);
+///Seconds since the unix epoch
+pub type Timestamp = u64;
+///Order of a write, each put or delete gets the next one (starting at 1)
+pub type Sequence = u64;
 ///Page index (assumes consistent page sizes)
 pub type Page = usize;
 
 ///Depth in a B-tree
+#[cfg(feature = "btree")]
 pub type Depth = usize;
 ///Node index in a B-tree
+#[cfg(feature = "btree")]
 pub type Node = usize;
 
-#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde-config", derive(Serialize, Deserialize))]
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum CompactionPolicy {
     None,
     Leveled,
     Tiered,
     Dostoevsky,
+    Lazy, //lazy leveling, see compaction::strategy::LazyLeveled
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
+#[cfg_attr(feature = "serde-config", derive(Serialize, Deserialize))]
+#[derive(Debug, Clone, Copy)]
 pub enum SstImplementation {
     Array,
     Btree,
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
+#[cfg_attr(feature = "serde-config", derive(Serialize, Deserialize))]
+#[derive(Debug, Clone, Copy)]
 pub enum SstSearchAlgorithm {
     Default,
     BinarySearch,
 }
+
+///How database files are opened. Direct I/O bypasses the OS page cache (see the buffer pool), not every filesystem
+/// supports it (e.g. tmpfs on older kernels)
+#[cfg_attr(feature = "serde-config", derive(Serialize, Deserialize))]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IoMode {
+    Auto,     //direct I/O where the filesystem supports it, buffered I/O elsewhere
+    Direct,   //direct I/O only, opening a database on a filesystem without it fails
+    Buffered, //through the OS page cache
+}
+
+///How the pages of a run are compressed on disk, see Database::set_compression. Pages are compressed one at a time so
+/// any page can still be read on its own
+#[cfg_attr(feature = "serde-config", derive(Serialize, Deserialize))]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Compression {
+    ///Each key and value as a varint of its difference from the previous entry's, pages this doesn't shrink (e.g.
+    /// random keys and values) are written as they are
+    DeltaVarint,
+}
+
+///Which writes are flushed to storage (fsync) before they're relied on, see Database::set_sync_policy. Files that
+/// aren't synced can come back empty or missing after a power failure, even though the process saw them written
+#[cfg_attr(feature = "serde-config", derive(Serialize, Deserialize))]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SyncPolicy {
+    #[default]
+    Never, //leave it to the OS, like the database always did. Fine for data that can be rebuilt
+    OnFlush, //sync the files flushes and compactions write, and their level directories once they're renamed into place
+    Always,  //also sync the config, metadata and other state files
+}
+
+///What a flush does when level 0 already holds Database::max_level_zero_runs runs under CompactionPolicy::None,
+/// the other policies move level 0 runs up on every flush
+#[cfg_attr(feature = "serde-config", derive(Serialize, Deserialize))]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum LevelZeroOverflowBehavior {
+    #[default]
+    Auto, //merge every level 0 run into one before writing the new run
+    Backpressure, //fail the write that would flush with DbError::TooManyRuns, see Database::compact_level_zero
+}
+
+///What a write does once level 0 holds Database::level_zero_stop_runs runs, see Database::set_level_zero_stop_runs
+#[cfg_attr(feature = "serde-config", derive(Serialize, Deserialize))]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum StallBehavior {
+    #[default]
+    Fail, //fail the write with DbError::WriteStalled, see Database::compact_level_zero
+    Block, //merge every level 0 run into one before writing, the write waits for the compaction
+}
+
+///When the pages saved in a database's warm-start file (see Database::set_persist_buffer_pool) are read back into the
+/// buffer pool. NOTE: the buffer pool isn't shared between threads, so they can't be read in the background
+#[cfg_attr(feature = "serde-config", derive(Serialize, Deserialize))]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum WarmUp {
+    #[default]
+    Eager, //before open returns
+    Manual, //when Database::warm_up is called, e.g. once the first requests have been served
+}
+
+///Combines a merge operand (see Database::merge) with the value under it. Operators are associative, so operands
+/// stacked on a key can be combined with each other before the value under them is found
+#[cfg_attr(feature = "serde-config", derive(Serialize, Deserialize))]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum MergeOperator {
+    #[default]
+    Add, //saturating, sums never reach the reserved tombstone value
+    Max,
+    Min,
+}
+
+impl MergeOperator {
+    ///Value operands are applied to when their key is absent or deleted
+    pub fn identity(&self) -> Value {
+        match self {
+            MergeOperator::Add => 0,
+            MergeOperator::Max => Value::MIN,
+            MergeOperator::Min => Value::MAX,
+        }
+    }
+    ///<operand> applied to <value>, <operand> is the younger of the two
+    pub fn apply(&self, value: Value, operand: Value) -> Value {
+        match self {
+            MergeOperator::Add => value.saturating_add(operand).max(Value::MIN + 1),
+            MergeOperator::Max => value.max(operand),
+            MergeOperator::Min => value.min(operand),
+        }
+    }
+}
+
+///What a put does with the version of its key under it, see Database::set_version_merge. One of a fixed set so it
+/// can be saved with the config. A delete always wins: nothing under a tombstone is combined with what's put after it
+#[cfg_attr(feature = "serde-config", derive(Serialize, Deserialize))]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum VersionMerge {
+    #[default]
+    KeepNewest, //the put replaces the value under it
+    KeepOldest, //the put does nothing if its key has a live value
+    SumValues,  //the put adds to the value under it, written as a MergeOperator::Add operand
+}
+
+///Levels whose runs get a bloom filter. The deepest level holds most of the data and is searched last (usually
+/// finding the key), so its filters cost the most space for the least benefit
+#[cfg_attr(feature = "serde-config", derive(Serialize, Deserialize))]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BloomLevels {
+    All,
+    UpTo(Level), //levels 0 to n - 1
+    None,
+}
+
+impl BloomLevels {
+    pub fn includes(&self, level: Level) -> bool {
+        match self {
+            BloomLevels::All => true,
+            BloomLevels::UpTo(num_levels) => level < *num_levels,
+            BloomLevels::None => false,
+        }
+    }
+}
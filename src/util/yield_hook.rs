@@ -0,0 +1,50 @@
+use std::{error::Error, fmt, io, ops::ControlFlow};
+
+///Called by long operations between pages (see Database::set_yield_hook), ControlFlow::Break aborts the operation
+pub struct YieldHook(Box<dyn FnMut() -> ControlFlow<()>>);
+
+impl YieldHook {
+    pub fn new(hook: impl FnMut() -> ControlFlow<()> + 'static) -> Self {
+        Self(Box::new(hook))
+    }
+    ///Give the host its turn, an error if it asked for the operation to stop
+    pub fn call(&mut self) -> io::Result<()> {
+        match (self.0)() {
+            ControlFlow::Continue(()) => Ok(()),
+            ControlFlow::Break(()) => Err(aborted_error()),
+        }
+    }
+}
+
+///Never yields or aborts
+impl Default for YieldHook {
+    fn default() -> Self {
+        Self::new(|| ControlFlow::Continue(()))
+    }
+}
+
+impl fmt::Debug for YieldHook {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("YieldHook")
+    }
+}
+
+#[derive(Debug)]
+struct Aborted;
+
+impl fmt::Display for Aborted {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("aborted by the yield hook")
+    }
+}
+
+impl Error for Aborted {}
+
+///Error of an operation its yield hook aborted, see is_aborted
+pub fn aborted_error() -> io::Error {
+    io::Error::new(io::ErrorKind::Interrupted, Aborted)
+}
+
+pub fn is_aborted(why: &io::Error) -> bool {
+    why.get_ref().is_some_and(|inner| inner.is::<Aborted>())
+}